@@ -1,21 +1,34 @@
 #include <linux/atomic.h>
+#include <linux/cc_platform.h>
 #include <linux/cdev.h>
 #include <linux/delay.h> // For msleep/udelay
 #include <linux/device.h>
+#include <linux/dma-mapping.h> // For DMA_MAPPING_ERROR
 #include <linux/errno.h>
 #include <linux/fs.h>
+#include <linux/irqdesc.h>
+#include <linux/kprobes.h>
 #include <linux/kernel.h>
+#include <linux/log2.h> // For is_power_of_2/ilog2
 // #include <linux/ktime.h>  // For ktime_get_ns()
 #include <linux/math64.h> // For div64_u64
 #include <linux/mm.h>
 #include <linux/module.h>
+#include <linux/mutex.h>
+#include <linux/netdevice.h>
+#include <linux/nodemask.h>
+#include <linux/percpu.h>
+#include <linux/random.h> // For get_random_u32
+#include <linux/rcupdate.h> // Kernel producers vs. ring reallocation
 #include <linux/sched.h>   // For smp_processor_id()
-#include <linux/slab.h>    // For kcalloc/kfree
+#include <linux/slab.h>    // For kvcalloc/kvfree
 #include <linux/smp.h>     // For memory barriers smp_wmb/rmb
 #include <linux/stddef.h>  // For offsetof if needed
 #include <linux/uaccess.h> // For copy_to_user etc (if using ioctl)
 #include <linux/version.h>
+#include <linux/virtio.h>
 #include <linux/vmalloc.h> // For vmalloc/vfree if using that
+#include <asm/tsc.h>       // For tsc_khz
 
 #include "../shared/common.h"
 #include "../shared/ops.h"
@@ -24,11 +37,62 @@
 #define CLASS_NAME "hireslogger"
 
 // --- Module Parameters ---
-// Use the default from the header unless overridden
+// Use the default from the header unless overridden. Follows
+// HIRES_IOCTL_SET_RB_CAPACITY, so it always reads as the current ring's size.
 static int rb_size_log2 = RING_BUFFER_LOG2_SIZE;
 module_param(rb_size_log2, int, S_IRUGO);
 MODULE_PARM_DESC(rb_size_log2, "Log2 of the ring buffer size in entries");
 
+static int entry_layout = HIRES_ENTRY_LAYOUT_STANDARD;
+module_param(entry_layout, int, S_IRUGO);
+MODULE_PARM_DESC(entry_layout,
+                 "Ring entry layout: 0 = standard (40 bytes), 1 = compact (32 "
+                 "bytes), 2 = extended with TID and seqno (64 bytes)");
+// entry_layout as written to the ring header, filled at init.
+static hires_entry_layout_t rb_layout;
+
+static bool secure_tsc = true;
+module_param(secure_tsc, bool, S_IRUGO);
+MODULE_PARM_DESC(secure_tsc,
+                 "Use the SEV-SNP SecureTSC frequency instead of the timing "
+                 "loop when available (default: on)");
+
+static bool virtio_probes = false;
+module_param(virtio_probes, bool, S_IRUGO);
+MODULE_PARM_DESC(virtio_probes,
+                 "Log virtio-net datapath milestones (HIRES_EV_VNET_*) via "
+                 "kretprobes (default: off)");
+
+static bool irq_probes = false;
+module_param(irq_probes, bool, S_IRUGO);
+MODULE_PARM_DESC(irq_probes,
+                 "Log every hardware interrupt (HIRES_EV_IRQ) via a kretprobe, "
+                 "for interrupt-to-wakeup latency (default: off)");
+
+static int numa_node = NUMA_NO_NODE;
+module_param(numa_node, int, S_IRUGO);
+MODULE_PARM_DESC(numa_node,
+                 "NUMA node to allocate the ring buffer on; put it next to "
+                 "the consumer (default: -1, wherever the module loads)");
+
+static bool swiotlb_probes = false;
+module_param(swiotlb_probes, bool, S_IRUGO);
+MODULE_PARM_DESC(swiotlb_probes,
+                 "Log swiotlb bounce-buffer map/unmap (HIRES_EV_SWIOTLB_*) via "
+                 "kretprobes (default: off)");
+
+static bool exit_probes = false;
+module_param(exit_probes, bool, S_IRUGO);
+MODULE_PARM_DESC(exit_probes,
+                 "Log every TDX #VE / SEV-ES #VC the guest handles "
+                 "(HIRES_EV_VMEXIT) via kretprobes (default: off)");
+
+static bool tls_probes = false;
+module_param(tls_probes, bool, S_IRUGO);
+MODULE_PARM_DESC(tls_probes,
+                 "Log kTLS record encryption/decryption (HIRES_EV_TLS) via "
+                 "kretprobes; load the tls module first (default: off)");
+
 // --- Global Variables ---
 static dev_t dev_num;
 static struct cdev hires_cdev;
@@ -39,6 +103,12 @@ static unsigned long buffer_total_size = 0;
 static unsigned long buffer_num_pages = 0;
 // Array holding buffer pages
 static struct page **buffer_pages = NULL;
+// Serializes replacing the ring (HIRES_IOCTL_SET_RB_CAPACITY) with mmap()
+// and the other ioctls. hires_log() reads shared_buffer under RCU instead.
+static DEFINE_MUTEX(rb_mutex);
+// VMAs mapping the ring. The ring is only replaced while there are none, so
+// the fault handler never sees the page array change under it.
+static atomic_t rb_mappings = ATOMIC_INIT(0);
 
 // --- Forward Declarations ---
 static int hireslogger_dev_open(struct inode *, struct file *);
@@ -53,6 +123,22 @@ u64 hires_rdtscp(u32 *auxp);
 
 // --- TSC Calibration ---
 static u64 cycles_per_us PROF_CACHE_LINE_ALIGNED = 0;
+// Unrounded rate for converting cycles to time without the truncation of
+// cycles_per_us (up to 1 cycle/us, i.e. ~0.05% at 2 GHz).
+static u64 tsc_hz = 0;
+static hires_tsc_info_t tsc_info;
+
+// Disagreement between the timing loop and the guest-visible frequency above
+// which a warning is logged (parts per million).
+#define HIRES_TSC_MISMATCH_PPM 1000
+
+static bool hires_secure_tsc_active(void) {
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(6, 14, 0)
+  return cc_platform_has(CC_ATTR_GUEST_SNP_SECURE_TSC);
+#else
+  return false;
+#endif
+}
 
 // Helper function to calibrate TSC frequency (cycles per microsecond)
 static u64 hires_calibrate_tsc(void) {
@@ -85,6 +171,33 @@ static u64 hires_calibrate_tsc(void) {
 
   // Calculate cycles per microsecond: (cycles * 1,000) / ns
   cycles_per_us = div64_u64(elapsed_tsc * 1000, elapsed_ns);
+  tsc_hz = mul_u64_u64_div_u64(elapsed_tsc, NSEC_PER_SEC, elapsed_ns);
+  tsc_info.tsc_hz = tsc_hz;
+  tsc_info.calibrated_hz = tsc_hz;
+  tsc_info.source = HIRES_TSC_SRC_CALIBRATED;
+
+  // SecureTSC guests get their TSC frequency from the SNP secrets page and
+  // GUEST_TSC_FREQ MSR (already applied to tsc_khz by the kernel), which the
+  // hypervisor cannot tamper with. A timing loop against a clock that may
+  // itself be derived from the TSC can only be worse.
+  if (hires_secure_tsc_active()) {
+    u64 reported = (u64)tsc_khz * 1000;
+    u64 diff = reported > tsc_hz ? reported - tsc_hz : tsc_hz - reported;
+    u64 ppm = div64_u64(diff * 1000000, reported ? reported : 1);
+
+    tsc_info.reported_hz = reported;
+    if (ppm > HIRES_TSC_MISMATCH_PPM) {
+      pr_warn("kHiResLogger: SecureTSC frequency %llu Hz disagrees with "
+              "calibration %llu Hz by %llu ppm\n",
+              reported, tsc_hz, ppm);
+    }
+    if (secure_tsc && reported) {
+      tsc_hz = reported;
+      cycles_per_us = div64_u64(reported, 1000000);
+      tsc_info.tsc_hz = reported;
+      tsc_info.source = HIRES_TSC_SRC_SECURE_TSC;
+    }
+  }
   return cycles_per_us;
 }
 
@@ -118,7 +231,18 @@ static vm_fault_t hireslogger_vma_fault(struct vm_fault *vmf) {
   return 0;
 }
 
+// A VMA copied on fork() or split by a partial munmap().
+static void hireslogger_vma_open(struct vm_area_struct *vma) {
+  atomic_inc(&rb_mappings);
+}
+
+static void hireslogger_vma_close(struct vm_area_struct *vma) {
+  atomic_dec(&rb_mappings);
+}
+
 static const struct vm_operations_struct hireslogger_vm_ops = {
+    .open = hireslogger_vma_open,
+    .close = hireslogger_vma_close,
     .fault = hireslogger_vma_fault,
 };
 
@@ -138,23 +262,160 @@ static int hireslogger_dev_mmap(struct file *filp, struct vm_area_struct *vma) {
   // unsigned long physical_pfn; // Needed only for remap_pfn_range
   // int ret; // Needed only for remap_pfn_range
 
+  mutex_lock(&rb_mutex);
   pr_info("kHiResLogger: mmap called. Requested size: %lu, Buffer size: %lu\n",
-          requested_size, READ_ONCE(buffer_total_size));
+          requested_size, buffer_total_size);
 
-  if (requested_size > READ_ONCE(buffer_total_size) || vma->vm_pgoff != 0) {
+  if (requested_size > buffer_total_size || vma->vm_pgoff != 0) {
     pr_err("kHiResLogger: Invalid mmap request. ReqSize=%lu > BufSize=%lu or "
            "PageOffset=%lu != 0\n",
-           requested_size, READ_ONCE(buffer_total_size), vma->vm_pgoff);
+           requested_size, buffer_total_size, vma->vm_pgoff);
+    mutex_unlock(&rb_mutex);
     return -EINVAL;
   }
 
   vm_flags_set(vma, VM_DONTEXPAND | VM_DONTDUMP);
   vma->vm_ops = &hireslogger_vm_ops;
+  atomic_inc(&rb_mappings);
+  mutex_unlock(&rb_mutex);
 
   pr_info("kHiResLogger: mmap successful using page fault handler.\n");
   return 0;
 }
 
+// --- Ring Allocation ---
+static void hires_rb_free(shared_ring_buffer_t *buf, struct page **pages,
+                          unsigned long num_pages) {
+  unsigned long i;
+
+  if (buf) {
+    vunmap(buf);
+  }
+  if (pages) {
+    for (i = 0; i < num_pages; ++i) {
+      if (pages[i]) {
+        __free_page(pages[i]);
+      }
+    }
+    kvfree(pages);
+  }
+}
+
+// Allocates and seals a ring of 2^log2 entries and makes it the current
+// one. The caller frees the ring it replaces, once no kernel producer can
+// still be writing to it. On failure the current ring is left as it was.
+static int hires_rb_create(unsigned int log2) {
+  unsigned long entries = 1UL << log2;
+  unsigned long ctrl_size = SHARED_RING_BUFFER_CTRL_SIZE;
+  unsigned long total_size_unaligned = ctrl_size + entries * rb_layout.entry_size;
+  unsigned long total_size = PAGE_ALIGN(total_size_unaligned);
+  unsigned long num_pages = total_size / PAGE_SIZE;
+  struct page **pages;
+  shared_ring_buffer_t *buf;
+  unsigned long i;
+
+  pr_info("kHiResLogger: Requested log2_size=%u, Ring buffer entries=%lu, "
+          "Entry size=%u (layout %d), Ctrl size=%lu, Total size "
+          "unaligned=%lu, Total size aligned=%lu (%lu pages)\n",
+          log2, entries, rb_layout.entry_size, entry_layout, ctrl_size,
+          total_size_unaligned, total_size, num_pages);
+
+  // A multi-gigabyte ring needs megabytes of page pointers, more than
+  // kmalloc can hand out in one piece.
+  pages = kvcalloc(num_pages, sizeof(struct page *), GFP_KERNEL);
+  if (!pages) {
+    pr_err("kHiResLogger: Failed to allocate page pointer array\n");
+    return -ENOMEM;
+  }
+
+  for (i = 0; i < num_pages; ++i) {
+    // Allocate pages with GFP_KERNEL | __GFP_ZERO to get zeroed memory
+    // With an explicit node, fail rather than silently fall back to another.
+    pages[i] =
+        numa_node == NUMA_NO_NODE
+            ? alloc_page(GFP_KERNEL | __GFP_ZERO)
+            : alloc_pages_node(numa_node,
+                               GFP_KERNEL | __GFP_ZERO | __GFP_THISNODE, 0);
+    if (!pages[i]) {
+      pr_err("kHiResLogger: Failed to allocate page %lu\n", i);
+      hires_rb_free(NULL, pages, num_pages);
+      return -ENOMEM;
+    }
+    cond_resched();
+  }
+
+  // We need a contiguous kernel virtual mapping of potentially non-contiguous
+  buf = vmap(pages, num_pages, VM_MAP, PAGE_KERNEL);
+  if (!buf) {
+    pr_err("kHiResLogger: Failed to vmap page array\n");
+    hires_rb_free(NULL, pages, num_pages);
+    return -ENOMEM;
+  }
+
+  pr_info("kHiResLogger: Initializing shared buffer header at %px\n", buf);
+  buf->capacity = entries;
+  buf->idx_mask = entries - 1;
+  buf->shm_size_bytes_unaligned = total_size_unaligned;
+  buf->shm_size_bytes_aligned = total_size;
+
+  atomic64_set((atomic64_t *)&buf->head, 0);
+  atomic64_set((atomic64_t *)&buf->tail, 0);
+  atomic64_set((atomic64_t *)&buf->dropped_count, 0);
+  buf->entry_layout = rb_layout;
+
+  // Seal the header last: userspace only trusts the fields above once it
+  // sees the magic. A random generation tells a reloaded module's ring from
+  // the previous one.
+  buf->generation = get_random_u32();
+  buf->layout_checksum =
+      hires_layout_checksum(rb_layout.entry_size, buf->capacity,
+                            buf->idx_mask, buf->shm_size_bytes_unaligned);
+  smp_wmb();
+  WRITE_ONCE(buf->magic, HIRES_SHM_MAGIC);
+
+  buffer_pages = pages;
+  buffer_num_pages = num_pages;
+  buffer_total_size = total_size;
+  rb_size_log2 = log2;
+  rcu_assign_pointer(shared_buffer, buf);
+  return 0;
+}
+
+// HIRES_IOCTL_SET_RB_CAPACITY, with rb_mutex held.
+static int hires_rb_resize(prof_size_t capacity) {
+  shared_ring_buffer_t *old_buf = shared_buffer;
+  struct page **old_pages = buffer_pages;
+  unsigned long old_num_pages = buffer_num_pages;
+  int ret;
+
+  if (capacity < 2 || !is_power_of_2(capacity) ||
+      capacity > (1ULL << RING_BUFFER_LOG2_MAX)) {
+    pr_err("kHiResLogger: Ring capacity %llu is not a power of two in "
+           "2..2^%d\n",
+           capacity, RING_BUFFER_LOG2_MAX);
+    return -EINVAL;
+  }
+  if (capacity == old_buf->capacity) {
+    return 0;
+  }
+  // A mapped ring stays where its consumers and producers expect it.
+  if (atomic_read(&rb_mappings) > 0) {
+    pr_err("kHiResLogger: Cannot resize the ring to %llu entries while it "
+           "is mapped (%llu entries)\n",
+           capacity, old_buf->capacity);
+    return -EBUSY;
+  }
+
+  ret = hires_rb_create(ilog2(capacity));
+  if (ret) {
+    return ret;
+  }
+  // Kernel producers that read the old pointer finish with it first.
+  synchronize_rcu();
+  hires_rb_free(old_buf, old_pages, old_num_pages);
+  return 0;
+}
+
 // --- IOCTL Handler ---
 static long hireslogger_dev_ioctl(struct file *filp, unsigned int cmd,
                                   unsigned long arg) {
@@ -168,10 +429,16 @@ static long hireslogger_dev_ioctl(struct file *filp, unsigned int cmd,
     return -EIO;
   }
 
+  mutex_lock(&rb_mutex);
   switch (cmd) {
   case HIRES_IOCTL_RESET_RB:
     pr_info("kHiResLogger: IOCTL: Resetting buffer.\n");
 
+    // A consumer that sees its generation change knows the indexes it was
+    // tracking are gone.
+    WRITE_ONCE(shared_buffer->generation,
+               READ_ONCE(shared_buffer->generation) + 1);
+
     // Atomically reset head, tail, and dropped count
     atomic64_set((atomic64_t *)&shared_buffer->head, 0);
     atomic64_set((atomic64_t *)&shared_buffer->tail, 0);
@@ -184,11 +451,14 @@ static long hireslogger_dev_ioctl(struct file *filp, unsigned int cmd,
     // A more complex scheme could involve a generation count.
     for (i = 0; i < shared_buffer->capacity; ++i) {
       uint16_t old_flags, new_flags;
-      log_entry_t *entry = &shared_buffer->buffer[i];
+      uint16_t *flags =
+          (uint16_t *)((char *)hires_ring_entry(shared_buffer, i,
+                                                rb_layout.entry_size) +
+                       rb_layout.field_offset[HIRES_FIELD_FLAGS]);
       do {
-        old_flags = READ_ONCE(entry->flags);
+        old_flags = READ_ONCE(*flags);
         new_flags = old_flags & ~LOG_FLAG_VALID;
-      } while (cmpxchg(&entry->flags, old_flags, new_flags) != old_flags);
+      } while (cmpxchg(flags, old_flags, new_flags) != old_flags);
     }
     smp_wmb();
     ret = 0;
@@ -230,11 +500,60 @@ static long hireslogger_dev_ioctl(struct file *filp, unsigned int cmd,
     break;
   }
 
+  case HIRES_IOCTL_GET_TSC_HZ: {
+    pr_info("kHiResLogger: IOCTL: Get calibrated TSC freq (Hz).\n");
+    if (tsc_hz == 0) {
+      pr_err("kHiResLogger: TSC freq not calibrated yet or error happened.\n");
+      ret = -EFAULT;
+      break;
+    }
+
+    if (put_user(tsc_hz, (u64 __user *)user_ptr)) {
+      pr_err("kHiResLogger: IOCTL: Failed to copy TSC freq to user.\n");
+      ret = -EFAULT;
+    } else {
+      ret = 0;
+    }
+    break;
+  }
+
+  case HIRES_IOCTL_GET_TSC_INFO: {
+    pr_info("kHiResLogger: IOCTL: Get TSC calibration info.\n");
+    if (tsc_info.tsc_hz == 0) {
+      pr_err("kHiResLogger: TSC freq not calibrated yet or error happened.\n");
+      ret = -EFAULT;
+      break;
+    }
+
+    if (copy_to_user((hires_tsc_info_t __user *)user_ptr, &tsc_info,
+                     sizeof(tsc_info))) {
+      pr_err("kHiResLogger: IOCTL: Failed to copy TSC info to user.\n");
+      ret = -EFAULT;
+    } else {
+      ret = 0;
+    }
+    break;
+  }
+
+  case HIRES_IOCTL_SET_RB_CAPACITY: {
+    prof_size_t capacity;
+
+    if (get_user(capacity, (prof_size_t __user *)user_ptr)) {
+      pr_err("kHiResLogger: IOCTL: Failed to copy capacity from user.\n");
+      ret = -EFAULT;
+      break;
+    }
+    pr_info("kHiResLogger: IOCTL: Set buffer capacity to %llu.\n", capacity);
+    ret = hires_rb_resize(capacity);
+    break;
+  }
+
   default:
     pr_warn("kHiResLogger: IOCTL: Unknown command %u.\n", cmd);
     ret = -ENOTTY;
     break;
   }
+  mutex_unlock(&rb_mutex);
 
   return ret;
 }
@@ -251,12 +570,18 @@ static long hireslogger_dev_ioctl(struct file *filp, unsigned int cmd,
  */
 int hires_log(u32 event_id, u64 data1, u64 data2) {
   prof_size_t head_val, tail_val, next_head_val, current_idx;
-  log_entry_t *entry;
+  shared_ring_buffer_t *rb;
+  void *entry;
+  uint16_t *flags;
   uint16_t old_flags, new_flags;
 
-  // Use READ_ONCE for shared_buffer check for robustness
-  if (unlikely(!READ_ONCE(shared_buffer))) {
+  // The ring is read under RCU: HIRES_IOCTL_SET_RB_CAPACITY frees the one
+  // it replaces only after every producer that saw it is done.
+  rcu_read_lock();
+  rb = rcu_dereference(shared_buffer);
+  if (unlikely(!rb)) {
     // Module not initialized or buffer allocation failed
+    rcu_read_unlock();
     return -EIO;
   }
 
@@ -265,21 +590,22 @@ int hires_log(u32 event_id, u64 data1, u64 data2) {
   //    We fetch-and-add, then calculate the index from the *previous* head
   //    value. Using atomic64_fetch_add_acquire on the *address* of the plain
   //    u64 head field.
-  head_val = atomic64_fetch_add_acquire(1, (atomic64_t *)&shared_buffer->head);
-  current_idx = head_val & shared_buffer->idx_mask; // Use mask from header
+  head_val = atomic64_fetch_add_acquire(1, (atomic64_t *)&rb->head);
+  current_idx = head_val & rb->idx_mask; // Use mask from header
 
   // 2. Check if buffer is full (using Acquire semantics for tail read)
   //    We compare the *next* potential head position against the current tail.
   //    Read tail atomically using atomic64_read_acquire on its address.
-  tail_val = atomic64_read_acquire((atomic64_t *)&shared_buffer->tail);
+  tail_val = atomic64_read_acquire((atomic64_t *)&rb->tail);
 
   // If the slot we're about to write (current_idx) is the same as the tail,
   // and head has already wrapped around past tail, the buffer is full.
   if (unlikely(current_idx == tail_val &&
-               (head_val - tail_val) >= shared_buffer->capacity)) {
+               (head_val - tail_val) >= rb->capacity)) {
     // Buffer is full. Increment dropped count atomically.
     // No need to roll back head with fetch_add.
-    atomic64_inc((atomic64_t *)&shared_buffer->dropped_count);
+    atomic64_inc((atomic64_t *)&rb->dropped_count);
+    rcu_read_unlock();
     return -ENOMEM;
   }
 
@@ -294,15 +620,46 @@ int hires_log(u32 event_id, u64 data1, u64 data2) {
   //    *** This needs careful handling depending on allocation strategy ***
   //    Let's assume shared_buffer IS the correct kernel virtual address for the
   //    whole region.
-  entry = &shared_buffer->buffer[current_idx];
+  entry = hires_ring_entry(rb, current_idx, rb_layout.entry_size);
 
   // 4. Fill in the data (flags field handled atomically later)
   //    Direct writes to plain struct members.
-  entry->timestamp = __rdtscp(&entry->cpu_id);
-  entry->event_id = event_id;
-
-  entry->data1 = data1;
-  entry->data2 = data2;
+  switch (rb_layout.id) {
+  case HIRES_ENTRY_LAYOUT_COMPACT: {
+    log_entry_compact_t *e = entry;
+    u32 cpu;
+
+    e->timestamp = __rdtscp(&cpu);
+    e->cpu_id = cpu;
+    e->event_id = event_id;
+    e->data1 = data1;
+    e->data2 = data2;
+    flags = &e->flags;
+    break;
+  }
+  case HIRES_ENTRY_LAYOUT_EXTENDED: {
+    log_entry_ext_t *e = entry;
+
+    e->timestamp = __rdtscp(&e->cpu_id);
+    e->event_id = event_id;
+    e->tid = in_task() ? task_pid_nr(current) : 0;
+    e->data1 = data1;
+    e->data2 = data2;
+    e->seqno = head_val;
+    flags = &e->flags;
+    break;
+  }
+  default: {
+    log_entry_t *e = entry;
+
+    e->timestamp = __rdtscp(&e->cpu_id);
+    e->event_id = event_id;
+    e->data1 = data1;
+    e->data2 = data2;
+    flags = &e->flags;
+    break;
+  }
+  }
 
   // 5. Write Memory Barrier: Ensure all prior writes to the entry data payload
   //    are globally visible before the atomic update to the 'flags' field.
@@ -313,12 +670,13 @@ int hires_log(u32 event_id, u64 data1, u64 data2) {
   //    This provides release semantics implicitly on success on most
   //    architectures, making the written data visible to the consumer.
   do {
-    old_flags = READ_ONCE(entry->flags);
+    old_flags = READ_ONCE(*flags);
     new_flags =
         (old_flags & ~LOG_FLAG_VALID) | LOG_FLAG_VALID | LOG_FLAG_KERNEL;
-  } while (cmpxchg(&entry->flags, old_flags, new_flags) != old_flags);
+  } while (cmpxchg(flags, old_flags, new_flags) != old_flags);
   // --- Entry is now visible to consumer ---
 
+  rcu_read_unlock();
   return 0;
 }
 
@@ -331,13 +689,282 @@ EXPORT_SYMBOL(hires_rdtsc);
 __always_inline u64 hires_rdtscp(u32 *auxp) { return __rdtscp(auxp); }
 EXPORT_SYMBOL(hires_rdtscp);
 
+// --- Datapath Probes ---
+// Each probe times its function with a kretprobe and logs the matching
+// reserved event on return, with data2 captured at entry (arguments may be
+// freed by then, e.g. a delivered skb).
+struct hires_probe_data {
+  u64 start_tsc;
+  u64 data2;
+};
+
+#define HIRES_RET_HANDLER(name, event)                                         \
+  static int name(struct kretprobe_instance *ri, struct pt_regs *regs) {       \
+    struct hires_probe_data *d = (struct hires_probe_data *)ri->data;          \
+    hires_log(event, __rdtscp(NULL) - d->start_tsc, d->data2);                 \
+    return 0;                                                                  \
+  }
+
+#define HIRES_KRETPROBE(sym, entry, ret)                                       \
+  {                                                                            \
+    .kp.symbol_name = sym, .entry_handler = entry, .handler = ret,             \
+    .data_size = sizeof(struct hires_probe_data), .maxactive = 64,             \
+  }
+
+// A missing symbol (e.g. virtio_net not loaded) only disables that probe.
+static void hires_register_probes(struct kretprobe *probes, bool *registered,
+                                  size_t n) {
+  size_t i;
+  int ret;
+
+  for (i = 0; i < n; ++i) {
+    ret = register_kretprobe(&probes[i]);
+    registered[i] = ret == 0;
+    if (ret < 0) {
+      pr_warn("kHiResLogger: Failed to probe %s: %d\n",
+              probes[i].kp.symbol_name, ret);
+    } else {
+      pr_info("kHiResLogger: Probing %s.\n", probes[i].kp.symbol_name);
+    }
+  }
+}
+
+static void hires_unregister_probes(struct kretprobe *probes, bool *registered,
+                                    size_t n) {
+  size_t i;
+
+  for (i = 0; i < n; ++i) {
+    if (registered[i]) {
+      unregister_kretprobe(&probes[i]);
+      registered[i] = false;
+    }
+  }
+}
+
+// --- virtio-net Datapath Probes ---
+
+// Only GRO receives from virtnet_poll count as virtio-net deliveries.
+static DEFINE_PER_CPU(int, in_virtnet_poll);
+
+static int vnet_kick_entry(struct kretprobe_instance *ri, struct pt_regs *regs) {
+  struct hires_probe_data *d = (struct hires_probe_data *)ri->data;
+  struct virtqueue *vq = (struct virtqueue *)regs_get_kernel_argument(regs, 0);
+
+  d->data2 = vq->index;
+  d->start_tsc = __rdtsc();
+  return 0;
+}
+
+static int vnet_interrupt_entry(struct kretprobe_instance *ri,
+                                struct pt_regs *regs) {
+  struct hires_probe_data *d = (struct hires_probe_data *)ri->data;
+  struct virtqueue *vq = (struct virtqueue *)regs_get_kernel_argument(regs, 1);
+
+  d->data2 = vq->index;
+  d->start_tsc = __rdtsc();
+  return 0;
+}
+
+static int vnet_poll_entry(struct kretprobe_instance *ri, struct pt_regs *regs) {
+  struct hires_probe_data *d = (struct hires_probe_data *)ri->data;
+  struct napi_struct *napi =
+      (struct napi_struct *)regs_get_kernel_argument(regs, 0);
+
+  d->data2 = (u64)napi->napi_id << 32;
+  this_cpu_inc(in_virtnet_poll);
+  d->start_tsc = __rdtsc();
+  return 0;
+}
+
+static int vnet_deliver_entry(struct kretprobe_instance *ri,
+                              struct pt_regs *regs) {
+  struct hires_probe_data *d = (struct hires_probe_data *)ri->data;
+  struct sk_buff *skb = (struct sk_buff *)regs_get_kernel_argument(regs, 1);
+
+  if (!this_cpu_read(in_virtnet_poll))
+    return 1; // Not virtio-net: skip the return handler
+  d->data2 = skb->len;
+  d->start_tsc = __rdtsc();
+  return 0;
+}
+
+static int vnet_poll_ret(struct kretprobe_instance *ri, struct pt_regs *regs) {
+  struct hires_probe_data *d = (struct hires_probe_data *)ri->data;
+  u64 elapsed = __rdtscp(NULL) - d->start_tsc;
+
+  this_cpu_dec(in_virtnet_poll);
+  hires_log(HIRES_EV_VNET_NAPI_POLL, elapsed,
+            d->data2 | (u32)regs_return_value(regs));
+  return 0;
+}
+
+HIRES_RET_HANDLER(vnet_kick_ret, HIRES_EV_VNET_KICK)
+HIRES_RET_HANDLER(vnet_interrupt_ret, HIRES_EV_VNET_INTERRUPT)
+HIRES_RET_HANDLER(vnet_deliver_ret, HIRES_EV_VNET_SKB_DELIVER)
+
+static struct kretprobe vnet_probes[] = {
+    HIRES_KRETPROBE("virtqueue_notify", vnet_kick_entry, vnet_kick_ret),
+    HIRES_KRETPROBE("vring_interrupt", vnet_interrupt_entry,
+                    vnet_interrupt_ret),
+    HIRES_KRETPROBE("virtnet_poll", vnet_poll_entry, vnet_poll_ret),
+// napi_gro_receive became an inline wrapper around gro_receive_skb in 6.15.
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(6, 15, 0)
+    HIRES_KRETPROBE("gro_receive_skb", vnet_deliver_entry, vnet_deliver_ret),
+#else
+    HIRES_KRETPROBE("napi_gro_receive", vnet_deliver_entry, vnet_deliver_ret),
+#endif
+};
+static bool vnet_probe_registered[ARRAY_SIZE(vnet_probes)];
+
+// --- Interrupt Probe ---
+static int irq_entry(struct kretprobe_instance *ri, struct pt_regs *regs) {
+  struct hires_probe_data *d = (struct hires_probe_data *)ri->data;
+  struct irq_desc *desc = (struct irq_desc *)regs_get_kernel_argument(regs, 0);
+
+  d->data2 = desc->irq_data.irq;
+  d->start_tsc = __rdtsc();
+  return 0;
+}
+
+HIRES_RET_HANDLER(irq_ret, HIRES_EV_IRQ)
+
+static struct kretprobe irq_kprobes[] = {
+    HIRES_KRETPROBE("handle_irq_event", irq_entry, irq_ret),
+};
+static bool irq_probe_registered[ARRAY_SIZE(irq_kprobes)];
+
+// --- swiotlb Bounce-Buffer Probes ---
+// Argument positions of the DMA direction: 6.9 dropped alloc_size from
+// swiotlb_tbl_map_single, and 6.10 moved unmap behind an inline wrapper.
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(6, 9, 0)
+#define SWIOTLB_MAP_DIR_ARG 4
+#else
+#define SWIOTLB_MAP_DIR_ARG 5
+#endif
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(6, 10, 0)
+#define SWIOTLB_UNMAP_SYMBOL "__swiotlb_tbl_unmap_single"
+#else
+#define SWIOTLB_UNMAP_SYMBOL "swiotlb_tbl_unmap_single"
+#endif
+
+static int swiotlb_map_entry(struct kretprobe_instance *ri,
+                             struct pt_regs *regs) {
+  struct hires_probe_data *d = (struct hires_probe_data *)ri->data;
+
+  d->data2 = HIRES_SWIOTLB_DATA2(
+      regs_get_kernel_argument(regs, SWIOTLB_MAP_DIR_ARG),
+      regs_get_kernel_argument(regs, 2));
+  d->start_tsc = __rdtsc();
+  return 0;
+}
+
+static int swiotlb_map_ret(struct kretprobe_instance *ri,
+                           struct pt_regs *regs) {
+  struct hires_probe_data *d = (struct hires_probe_data *)ri->data;
+  u64 elapsed = __rdtscp(NULL) - d->start_tsc;
+
+  if ((phys_addr_t)regs_return_value(regs) == (phys_addr_t)DMA_MAPPING_ERROR) {
+    d->data2 |= HIRES_SWIOTLB_FAILED;
+  }
+  hires_log(HIRES_EV_SWIOTLB_MAP, elapsed, d->data2);
+  return 0;
+}
+
+static int swiotlb_unmap_entry(struct kretprobe_instance *ri,
+                               struct pt_regs *regs) {
+  struct hires_probe_data *d = (struct hires_probe_data *)ri->data;
+
+  d->data2 = HIRES_SWIOTLB_DATA2(regs_get_kernel_argument(regs, 3),
+                                 regs_get_kernel_argument(regs, 2));
+  d->start_tsc = __rdtsc();
+  return 0;
+}
+
+HIRES_RET_HANDLER(swiotlb_unmap_ret, HIRES_EV_SWIOTLB_UNMAP)
+
+static struct kretprobe swiotlb_kprobes[] = {
+    HIRES_KRETPROBE("swiotlb_tbl_map_single", swiotlb_map_entry,
+                    swiotlb_map_ret),
+    HIRES_KRETPROBE(SWIOTLB_UNMAP_SYMBOL, swiotlb_unmap_entry,
+                    swiotlb_unmap_ret),
+};
+static bool swiotlb_probe_registered[ARRAY_SIZE(swiotlb_kprobes)];
+
+// --- VM-Exit Probes ---
+// Only the handler matching the running guest type will find its symbol; the
+// other one just fails to register.
+static int tdx_ve_entry(struct kretprobe_instance *ri, struct pt_regs *regs) {
+  struct hires_probe_data *d = (struct hires_probe_data *)ri->data;
+  // exit_reason is the first member of struct ve_info.
+  u64 *ve = (u64 *)regs_get_kernel_argument(regs, 1);
+
+  d->data2 = HIRES_VMEXIT_DATA2(HIRES_VMEXIT_TDX_VE, *ve);
+  d->start_tsc = __rdtsc();
+  return 0;
+}
+
+static int snp_vc_entry(struct kretprobe_instance *ri, struct pt_regs *regs) {
+  struct hires_probe_data *d = (struct hires_probe_data *)ri->data;
+
+  d->data2 = HIRES_VMEXIT_DATA2(HIRES_VMEXIT_SNP_VC,
+                                regs_get_kernel_argument(regs, 2));
+  d->start_tsc = __rdtsc();
+  return 0;
+}
+
+HIRES_RET_HANDLER(vmexit_ret, HIRES_EV_VMEXIT)
+
+static struct kretprobe exit_kprobes[] = {
+    HIRES_KRETPROBE("tdx_handle_virt_exception", tdx_ve_entry, vmexit_ret),
+    HIRES_KRETPROBE("vc_handle_exitcode", snp_vc_entry, vmexit_ret),
+};
+static bool exit_probe_registered[ARRAY_SIZE(exit_kprobes)];
+
+// --- kTLS Record Crypto Probes ---
+// Both are static functions of the tls module taking the record length as
+// their fifth argument. With an async crypto engine the probes only time the
+// submission (-EINPROGRESS), which is not counted as a failure.
+static int tls_encrypt_entry(struct kretprobe_instance *ri,
+                             struct pt_regs *regs) {
+  struct hires_probe_data *d = (struct hires_probe_data *)ri->data;
+
+  d->data2 =
+      HIRES_TLS_DATA2(HIRES_TLS_ENCRYPT, regs_get_kernel_argument(regs, 4));
+  d->start_tsc = __rdtsc();
+  return 0;
+}
+
+static int tls_decrypt_entry(struct kretprobe_instance *ri,
+                             struct pt_regs *regs) {
+  struct hires_probe_data *d = (struct hires_probe_data *)ri->data;
+
+  d->data2 =
+      HIRES_TLS_DATA2(HIRES_TLS_DECRYPT, regs_get_kernel_argument(regs, 4));
+  d->start_tsc = __rdtsc();
+  return 0;
+}
+
+static int tls_crypto_ret(struct kretprobe_instance *ri, struct pt_regs *regs) {
+  struct hires_probe_data *d = (struct hires_probe_data *)ri->data;
+  u64 elapsed = __rdtscp(NULL) - d->start_tsc;
+  int ret = (int)regs_return_value(regs);
+
+  if (ret < 0 && ret != -EINPROGRESS) {
+    d->data2 |= HIRES_TLS_FAILED;
+  }
+  hires_log(HIRES_EV_TLS, elapsed, d->data2);
+  return 0;
+}
+
+static struct kretprobe tls_kprobes[] = {
+    HIRES_KRETPROBE("tls_do_encryption", tls_encrypt_entry, tls_crypto_ret),
+    HIRES_KRETPROBE("tls_do_decryption", tls_decrypt_entry, tls_crypto_ret),
+};
+static bool tls_probe_registered[ARRAY_SIZE(tls_kprobes)];
+
 // --- Module Initialization and Exit ---
 static int __init hireslogger_km_init(void) {
   int ret = 0;
-  size_t i;
-  unsigned long calculated_ring_buffer_entries;
-  unsigned long calculated_buffer_ctrl_size = SHARED_RING_BUFFER_CTRL_SIZE;
-  unsigned long calculated_buffer_total_size_unaligned;
 
   pr_info("kHiResLogger: Initializing module...\n");
 
@@ -346,63 +973,37 @@ static int __init hireslogger_km_init(void) {
     pr_err("kHiResLogger: TSC calibration failed.\n");
     return -EIO;
   }
-  pr_info("kHiResLogger: TSC cycles per us: %llu\n", tsc_cycle);
-
-  calculated_ring_buffer_entries = (1UL << rb_size_log2);
-  calculated_buffer_total_size_unaligned =
-      calculated_buffer_ctrl_size +
-      (calculated_ring_buffer_entries * sizeof(log_entry_t));
-
-  buffer_total_size = PAGE_ALIGN(calculated_buffer_total_size_unaligned);
-  buffer_num_pages = buffer_total_size / PAGE_SIZE;
-
-  pr_info("kHiResLogger: Requested log2_size=%d, Ring buffer entries=%lu, "
-          "Ctrl size=%lu, Total size unaligned=%lu, Total size aligned=%lu "
-          "(%lu pages)\n",
-          rb_size_log2, calculated_ring_buffer_entries,
-          calculated_buffer_ctrl_size, calculated_buffer_total_size_unaligned,
-          buffer_total_size, buffer_num_pages);
-
-  buffer_pages = kcalloc(buffer_num_pages, sizeof(struct page *), GFP_KERNEL);
-  if (!buffer_pages) {
-    pr_err("kHiResLogger: Failed to allocate page pointer array\n");
-    return -ENOMEM;
+  pr_info("kHiResLogger: TSC cycles per us: %llu (%llu Hz, %s)\n", tsc_cycle,
+          tsc_hz,
+          tsc_info.source == HIRES_TSC_SRC_SECURE_TSC ? "SecureTSC"
+                                                      : "calibrated");
+
+  if (rb_size_log2 < 1 || rb_size_log2 > RING_BUFFER_LOG2_MAX) {
+    pr_err("kHiResLogger: rb_size_log2=%d is outside 1..%d\n", rb_size_log2,
+           RING_BUFFER_LOG2_MAX);
+    return -EINVAL;
   }
-
-  for (i = 0; i < buffer_num_pages; ++i) {
-    // Allocate pages with GFP_KERNEL | __GFP_ZERO to get zeroed memory
-    buffer_pages[i] = alloc_page(GFP_KERNEL | __GFP_ZERO);
-    if (!buffer_pages[i]) {
-      pr_err("kHiResLogger: Failed to allocate page %zu\n", i);
-      ret = -ENOMEM;
-      goto fail_alloc_pages;
-    }
+  if (!hires_entry_layout_init(&rb_layout, entry_layout)) {
+    pr_err("kHiResLogger: entry_layout=%d is not a known layout\n",
+           entry_layout);
+    return -EINVAL;
   }
 
-  // We need a contiguous kernel virtual mapping of potentially non-contiguous
-  shared_buffer = vmap(buffer_pages, buffer_num_pages, VM_MAP, PAGE_KERNEL);
-  if (!shared_buffer) {
-    pr_err("kHiResLogger: Failed to vmap page array\n");
-    ret = -ENOMEM;
-    goto fail_alloc_pages;
+  if (numa_node != NUMA_NO_NODE &&
+      (numa_node < 0 || numa_node >= MAX_NUMNODES || !node_online(numa_node))) {
+    pr_err("kHiResLogger: numa_node=%d is not an online node\n", numa_node);
+    return -EINVAL;
   }
 
-  pr_info("kHiResLogger: Initializing shared buffer header at %px\n",
-          shared_buffer);
-  shared_buffer->capacity = calculated_ring_buffer_entries;
-  shared_buffer->idx_mask = calculated_ring_buffer_entries - 1;
-  shared_buffer->shm_size_bytes_unaligned =
-      calculated_buffer_total_size_unaligned;
-  shared_buffer->shm_size_bytes_aligned = buffer_total_size;
-
-  atomic64_set((atomic64_t *)&shared_buffer->head, 0);
-  atomic64_set((atomic64_t *)&shared_buffer->tail, 0);
-  atomic64_set((atomic64_t *)&shared_buffer->dropped_count, 0);
+  ret = hires_rb_create(rb_size_log2);
+  if (ret) {
+    goto fail_alloc;
+  }
 
   ret = alloc_chrdev_region(&dev_num, 0, 1, DEVICE_NAME);
   if (ret < 0) {
     pr_err("kHiResLogger: Failed to allocate major number: %d\n", ret);
-    goto fail_vmap;
+    goto fail_ring;
   }
 
   hireslogger_class = class_create(CLASS_NAME);
@@ -423,6 +1024,26 @@ static int __init hireslogger_km_init(void) {
 
   device_create(hireslogger_class, NULL, dev_num, NULL, DEVICE_NAME);
   pr_info("kHiResLogger: Device node /dev/%s created.\n", DEVICE_NAME);
+  if (virtio_probes) {
+    hires_register_probes(vnet_probes, vnet_probe_registered,
+                          ARRAY_SIZE(vnet_probes));
+  }
+  if (irq_probes) {
+    hires_register_probes(irq_kprobes, irq_probe_registered,
+                          ARRAY_SIZE(irq_kprobes));
+  }
+  if (swiotlb_probes) {
+    hires_register_probes(swiotlb_kprobes, swiotlb_probe_registered,
+                          ARRAY_SIZE(swiotlb_kprobes));
+  }
+  if (exit_probes) {
+    hires_register_probes(exit_kprobes, exit_probe_registered,
+                          ARRAY_SIZE(exit_kprobes));
+  }
+  if (tls_probes) {
+    hires_register_probes(tls_kprobes, tls_probe_registered,
+                          ARRAY_SIZE(tls_kprobes));
+  }
   pr_info("kHiResLogger: Module loaded successfully.\n");
   return 0;
 
@@ -430,31 +1051,27 @@ fail_class_create:
   class_destroy(hireslogger_class);
 fail_chrdev_region:
   unregister_chrdev_region(dev_num, 1);
-fail_vmap:
-  if (shared_buffer) {
-    vunmap(shared_buffer);
-    shared_buffer = NULL;
-  }
-fail_alloc_pages:
-  if (buffer_pages) {
-    for (i = 0; i < buffer_num_pages; ++i) {
-      if (buffer_pages[i]) {
-        __free_page(buffer_pages[i]);
-      }
-    }
-    kfree(buffer_pages);
-    buffer_pages = NULL;
-  }
-  if (!shared_buffer) {
-    shared_buffer = NULL;
-  }
+fail_ring:
+  hires_rb_free(shared_buffer, buffer_pages, buffer_num_pages);
+  shared_buffer = NULL;
+  buffer_pages = NULL;
+fail_alloc:
   pr_err("kHiResLogger: Module initialization failed with error %d.\n", ret);
   return ret;
 }
 
 static void __exit hireslogger_km_exit(void) {
-  size_t i;
   pr_info("kHiResLogger: Exiting module...\n");
+  hires_unregister_probes(vnet_probes, vnet_probe_registered,
+                          ARRAY_SIZE(vnet_probes));
+  hires_unregister_probes(irq_kprobes, irq_probe_registered,
+                          ARRAY_SIZE(irq_kprobes));
+  hires_unregister_probes(swiotlb_kprobes, swiotlb_probe_registered,
+                          ARRAY_SIZE(swiotlb_kprobes));
+  hires_unregister_probes(exit_kprobes, exit_probe_registered,
+                          ARRAY_SIZE(exit_kprobes));
+  hires_unregister_probes(tls_kprobes, tls_probe_registered,
+                          ARRAY_SIZE(tls_kprobes));
 
   device_destroy(hireslogger_class, dev_num);
   cdev_del(&hires_cdev);
@@ -462,18 +1079,14 @@ static void __exit hireslogger_km_exit(void) {
   unregister_chrdev_region(dev_num, 1);
 
   if (shared_buffer) {
-    vunmap(shared_buffer);
-    shared_buffer = NULL;
-  }
-  if (buffer_pages) {
-    for (i = 0; i < buffer_num_pages; ++i) {
-      if (buffer_pages[i]) {
-        __free_page(buffer_pages[i]);
-      }
-    }
-    kfree(buffer_pages);
-    buffer_pages = NULL;
+    // Mappings that outlive the module keep the pages; break the seal so
+    // their consumers stop instead of waiting on a ring nobody writes.
+    WRITE_ONCE(shared_buffer->magic, 0);
+    smp_wmb();
   }
+  hires_rb_free(shared_buffer, buffer_pages, buffer_num_pages);
+  shared_buffer = NULL;
+  buffer_pages = NULL;
 
   pr_info("kHiResLogger: Module unloaded.\n");
 }
@@ -2,8 +2,38 @@ use std::path::PathBuf;
 use std::env;
 
 fn main() {
-    let libhires_path = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("../build/rt/");
-    println!("cargo:rustc-link-search=native={}", libhires_path.display());
+    // With the `static` feature, and on musl, rt_ffi links libhires_rt.a
+    // instead; with `dynamic-load` it is opened at runtime, and `stub` and
+    // `native` don't use it at all.
+    if env::var("CARGO_FEATURE_STATIC").is_ok()
+        || env::var("CARGO_FEATURE_DYNAMIC_LOAD").is_ok()
+        || env::var("CARGO_FEATURE_STUB").is_ok()
+        || env::var("CARGO_FEATURE_NATIVE").is_ok()
+        || env::var("CARGO_CFG_TARGET_ENV").is_ok_and(|e| e == "musl")
+    {
+        return;
+    }
+    // Same lookup as rt_ffi: HIRES_RT_LIB_DIR overrides the in-tree build,
+    // unless HIRES_VENDORED=1 builds it there, and an install found through
+    // pkg-config comes next.
+    println!("cargo:rerun-if-env-changed=HIRES_RT_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=HIRES_VENDORED");
+    let vendored = env::var("HIRES_VENDORED").is_ok_and(|v| v == "1");
+    let in_tree = || vec![PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("../build/rt/")];
+    let lib_dirs = match env::var_os("HIRES_RT_LIB_DIR") {
+        _ if vendored => in_tree(),
+        Some(dir) => vec![PathBuf::from(dir)],
+        // System library directories are left out of link_paths and need no
+        // rpath.
+        None => pkg_config::Config::new()
+            .cargo_metadata(false)
+            .env_metadata(true)
+            .probe("hires_rt")
+            .map_or_else(|_| in_tree(), |lib| lib.link_paths),
+    };
+    for libhires_path in &lib_dirs {
+        println!("cargo:rustc-link-search=native={}", libhires_path.display());
+        println!("cargo:rustc-link-arg=-Wl,-rpath,{}", libhires_path.display());
+    }
     println!("cargo:rustc-link-lib=dylib=hires_rt");
-    println!("cargo:rustc-link-arg=-Wl,-rpath,{}", libhires_path.display());
 }
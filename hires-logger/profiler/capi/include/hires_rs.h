@@ -0,0 +1,126 @@
+#ifndef HIRES_RS_H
+#define HIRES_RS_H
+
+// C interface to the Rust wrapper (profiler/rt), linked as libhires_rs.a.
+//
+// Over libhires_rt's rt_c.h this adds the perf/user_events fallback when
+// khires is not loaded, batched draining, and span and stack logging in the
+// formats the profiler decodes (--spans, stacks).
+//
+// Build:   cargo build --release -p hires-rs [--features static]
+// Link:    -lhires_rs -lhires_rt, then the system libraries Rust needs as
+//          listed by `cargo rustc -p hires-rs --release -- --print
+//          native-static-libs` (typically -lgcc_s -lpthread -ldl -lm).
+//          With --features static libhires_rt is bundled, and with --features
+//          native it is not used: drop -lhires_rt.
+//
+// Functions are safe to call from several threads on one connection, like
+// rt_c.h's; a connection must not be used after hires_rs_disconnect().
+
+#include <stddef.h>
+#include <stdint.h>
+#include <stdbool.h>
+
+#if defined(__has_include)
+#if __has_include("../../../shared/common.h")
+#include "../../../shared/common.h"
+#else
+#include "common.h"
+#endif
+#else
+#include "../../../shared/common.h"
+#endif
+
+typedef struct hires_rs_conn hires_rs_conn_t;
+
+// hires_rs_backend() results.
+#define HIRES_RS_BACKEND_KHIRES 0
+#define HIRES_RS_BACKEND_PERF 1
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/**
+ * @brief Connects to khires, or to the perf transport if the device does
+ * not exist.
+ * @param device_path Path to the device. If NULL, uses /dev/khires.
+ * @return A connection, or NULL on failure; see hires_rs_last_error().
+ */
+hires_rs_conn_t* hires_rs_connect(const char* device_path);
+
+/**
+ * @brief Closes a connection. If NULL, does nothing.
+ */
+void hires_rs_disconnect(hires_rs_conn_t* conn);
+
+/**
+ * @brief The calling thread's last hires_rs_connect() error, or NULL. Valid
+ * until the next failing call on this thread.
+ */
+const char* hires_rs_last_error(void);
+
+/**
+ * @brief Which transport the connection uses, one of HIRES_RS_BACKEND_*.
+ */
+uint32_t hires_rs_backend(const hires_rs_conn_t* conn);
+
+/**
+ * @brief Logs an event.
+ * @return False if the ring was full and the entry was dropped.
+ */
+bool hires_rs_log(const hires_rs_conn_t* conn, uint32_t event_id, uint64_t data1, uint64_t data2);
+
+/**
+ * @brief Pops the oldest entry into entry.
+ * @return False if the ring was empty or the entry wasn't ready.
+ */
+bool hires_rs_pop(const hires_rs_conn_t* conn, log_entry_t* entry);
+
+/**
+ * @brief Pops up to max entries into entries, stopping early where
+ * hires_rs_pop() would fail.
+ * @return The number of entries written.
+ */
+size_t hires_rs_pop_batch(const hires_rs_conn_t* conn, log_entry_t* entries, size_t max);
+
+/**
+ * @brief Takes a snapshot of the ring's indexes and counters.
+ */
+void hires_rs_get_stats(const hires_rs_conn_t* conn, hires_rb_stats_t* out);
+
+/**
+ * @brief TSC frequency in Hz used to convert entry timestamps.
+ */
+uint64_t hires_rs_get_tsc_hz(const hires_rs_conn_t* conn);
+
+/**
+ * @brief The data2 value carrying a span's ID and its parent's (0 for a
+ * root), as decoded by the profiler's --spans.
+ */
+uint64_t hires_rs_pack_span_ids(uint32_t span_id, uint32_t parent_id);
+
+/**
+ * @brief Logs one finished span: its duration in data1 and the IDs from
+ * hires_rs_pack_span_ids() in data2.
+ */
+bool hires_rs_log_span(const hires_rs_conn_t* conn, uint32_t event_id, uint64_t duration,
+                       uint32_t span_id, uint32_t parent_id);
+
+/**
+ * @brief Logs count return addresses as one stack trace for event_id.
+ * @return False if any frame was dropped.
+ */
+bool hires_rs_log_stack(const hires_rs_conn_t* conn, uint32_t event_id, const uint64_t* frames,
+                        size_t count);
+
+/**
+ * @brief Captures the calling thread's stack and logs it for event_id.
+ */
+bool hires_rs_log_backtrace(const hires_rs_conn_t* conn, uint32_t event_id);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // HIRES_RS_H
@@ -0,0 +1,175 @@
+//! C ABI over `rt`, built as libhires_rs.a. The declarations and their
+//! documentation are in include/hires_rs.h; keep the two in sync.
+//!
+//! Functions are prefixed `hires_rs_` so they never collide with
+//! libhires_rt's `hires_*` when both end up in one program.
+
+use rt::ring::Consumer;
+use rt::{Backend, HiResConn, hires_rb_stats_t, log_entry_t};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char};
+use std::path::Path;
+use std::ptr;
+
+/// `hires_rs_conn_t`.
+pub struct Conn(HiResConn<'static>);
+
+pub const HIRES_RS_BACKEND_KHIRES: u32 = 0;
+pub const HIRES_RS_BACKEND_PERF: u32 = 1;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(message: String) {
+    let message = CString::new(message).unwrap_or_else(|_| c"invalid error message".into());
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// # Safety
+/// `device_path` is NULL or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hires_rs_connect(device_path: *const c_char) -> *mut Conn {
+    let path = if device_path.is_null() {
+        None
+    } else {
+        match unsafe { CStr::from_ptr(device_path) }.to_str() {
+            Ok(path) => Some(path),
+            Err(e) => {
+                set_error(format!("Invalid device path: {}", e));
+                return ptr::null_mut();
+            }
+        }
+    };
+    match HiResConn::connect(path.map(Path::new)) {
+        Ok(conn) => Box::into_raw(Box::new(Conn(conn))),
+        Err(e) => {
+            set_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+/// `conn` is NULL or came from `hires_rs_connect()` and is not used again.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hires_rs_disconnect(conn: *mut Conn) {
+    if !conn.is_null() {
+        drop(unsafe { Box::from_raw(conn) });
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn hires_rs_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+/// # Safety
+/// `conn` came from `hires_rs_connect()`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hires_rs_backend(conn: *const Conn) -> u32 {
+    match unsafe { &(*conn).0 }.backend() {
+        Backend::Khires => HIRES_RS_BACKEND_KHIRES,
+        Backend::Perf => HIRES_RS_BACKEND_PERF,
+    }
+}
+
+/// # Safety
+/// `conn` came from `hires_rs_connect()`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hires_rs_log(
+    conn: *const Conn,
+    event_id: u32,
+    data1: u64,
+    data2: u64,
+) -> bool {
+    unsafe { &(*conn).0 }.log(event_id, data1, data2)
+}
+
+/// # Safety
+/// `conn` came from `hires_rs_connect()` and `entry` is writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hires_rs_pop(conn: *const Conn, entry: *mut log_entry_t) -> bool {
+    match unsafe { &(*conn).0 }.pop() {
+        Some(e) => {
+            unsafe { entry.write(e) };
+            true
+        }
+        None => false,
+    }
+}
+
+/// # Safety
+/// `conn` came from `hires_rs_connect()` and `entries` has room for `max`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hires_rs_pop_batch(
+    conn: *const Conn,
+    entries: *mut log_entry_t,
+    max: usize,
+) -> usize {
+    let conn = unsafe { &(*conn).0 };
+    let mut batch = Vec::with_capacity(max);
+    let n = conn.pop_batch(&mut batch, max);
+    unsafe { ptr::copy_nonoverlapping(batch.as_ptr(), entries, n) };
+    n
+}
+
+/// # Safety
+/// `conn` came from `hires_rs_connect()` and `out` is writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hires_rs_get_stats(conn: *const Conn, out: *mut hires_rb_stats_t) {
+    unsafe { out.write((*conn).0.get_stats()) };
+}
+
+/// # Safety
+/// `conn` came from `hires_rs_connect()`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hires_rs_get_tsc_hz(conn: *const Conn) -> u64 {
+    unsafe { &(*conn).0 }.get_tsc_hz()
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn hires_rs_pack_span_ids(span_id: u32, parent_id: u32) -> u64 {
+    rt::span::pack_span_ids(span_id, parent_id)
+}
+
+/// # Safety
+/// `conn` came from `hires_rs_connect()`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hires_rs_log_span(
+    conn: *const Conn,
+    event_id: u32,
+    duration: u64,
+    span_id: u32,
+    parent_id: u32,
+) -> bool {
+    unsafe { &(*conn).0 }.log(
+        event_id,
+        duration,
+        rt::span::pack_span_ids(span_id, parent_id),
+    )
+}
+
+/// # Safety
+/// `conn` came from `hires_rs_connect()` and `frames` holds `count` values.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hires_rs_log_stack(
+    conn: *const Conn,
+    event_id: u32,
+    frames: *const u64,
+    count: usize,
+) -> bool {
+    let frames = if count == 0 {
+        &[][..]
+    } else {
+        unsafe { std::slice::from_raw_parts(frames, count) }
+    };
+    unsafe { &(*conn).0 }.log_stack(event_id, frames)
+}
+
+/// # Safety
+/// `conn` came from `hires_rs_connect()`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hires_rs_log_backtrace(conn: *const Conn, event_id: u32) -> bool {
+    unsafe { &(*conn).0 }.log_backtrace(event_id)
+}
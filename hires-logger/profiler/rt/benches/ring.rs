@@ -0,0 +1,241 @@
+//! Producer and consumer costs of the wrapper.
+//!
+//! Runs against whatever `connect()` finds: khires if it is loaded, else
+//! the perf fallback, or the in-memory ring with `--features stub`
+//! (`cargo bench -p rt --features stub` needs neither the module nor
+//! libhires_rt). Benchmarks that cannot connect print why and are skipped.
+//!
+//! - `log/threads/N`: latency of one `log()` with N producers logging at
+//!   once;
+//! - `drain/pop`, `drain/pop_batch`: consumer throughput on a full ring;
+//! - `drop_onset/producers/N`: how long one consumer holds off N producers
+//!   logging flat out, i.e. the time from an empty ring to `ONSET_FILL` of
+//!   it pending, capped at `ONSET_CAP` for a consumer that keeps up.
+//!
+//! None of them lets the ring overflow. A dropped `log()` has already
+//! claimed its sequence number, and the consumer stops at that slot for
+//! good, which would leave every later benchmark (and, on khires, every
+//! other user of the ring) measuring a dead ring. So producers log at most
+//! a ring's worth between drains, and drop onset stops short of full.
+
+use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
+use rt::HiResConn;
+use rt::ring::Consumer;
+use std::sync::Barrier;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const EVENT_ID: u32 = 1;
+const THREADS: [usize; 7] = [1, 2, 4, 8, 16, 32, 64];
+const ONSET_PRODUCERS: [usize; 4] = [1, 4, 16, 64];
+/// Fraction of the ring pending that counts as the onset of drops; the rest
+/// absorbs what producers log before they see the stop flag.
+const ONSET_FILL: (u64, u64) = (7, 8);
+const ONSET_CAP: Duration = Duration::from_millis(200);
+
+fn connect() -> Option<HiResConn<'static>> {
+    match HiResConn::connect(None) {
+        Ok(conn) => Some(conn),
+        Err(e) => {
+            eprintln!("skipping: cannot connect: {}", e);
+            None
+        }
+    }
+}
+
+fn drain(conn: &HiResConn) {
+    while conn.pop().is_some() {}
+}
+
+/// Runs `f` while another thread pops everything that is logged.
+fn with_consumer<R>(f: impl FnOnce() -> R) -> R {
+    let running = AtomicBool::new(true);
+    thread::scope(|s| {
+        s.spawn(|| {
+            let Some(conn) = connect() else { return };
+            while running.load(Ordering::Relaxed) {
+                if conn.pop().is_none() {
+                    std::hint::spin_loop();
+                }
+            }
+            drain(&conn);
+        });
+        let result = f();
+        running.store(false, Ordering::Relaxed);
+        result
+    })
+}
+
+/// Each of `threads` producers logs `iters` entries, in rounds that fit in
+/// the ring with a drain between them, and the slowest one's logging time
+/// is returned: the per-call latency at this contention.
+fn time_log(conn: &HiResConn, threads: usize, iters: u64) -> Duration {
+    let per_round = (conn.get_rb_capacity() / threads as u64).max(1);
+    let rounds = iters.div_ceil(per_round);
+    let barrier = Barrier::new(threads + 1);
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                s.spawn(|| {
+                    let conn = connect().expect("connected above");
+                    let mut elapsed = Duration::ZERO;
+                    let mut left = iters;
+                    for _ in 0..rounds {
+                        let n = left.min(per_round);
+                        barrier.wait();
+                        let started = Instant::now();
+                        for i in 0..n {
+                            black_box(conn.log(EVENT_ID, i, 0));
+                        }
+                        elapsed += started.elapsed();
+                        left -= n;
+                        barrier.wait();
+                    }
+                    elapsed
+                })
+            })
+            .collect();
+        for _ in 0..rounds {
+            barrier.wait();
+            barrier.wait();
+            drain(conn);
+        }
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .max()
+            .unwrap()
+    })
+}
+
+fn bench_log(c: &mut Criterion) {
+    let Some(conn) = connect() else { return };
+    drain(&conn);
+    let mut group = c.benchmark_group("log");
+    group.throughput(Throughput::Elements(1));
+    for threads in THREADS {
+        group.bench_with_input(
+            BenchmarkId::new("threads", threads),
+            &threads,
+            |b, &threads| {
+                b.iter_custom(|iters| time_log(&conn, threads, iters));
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Times draining `iters` entries, logged up to one ring's worth at a time.
+fn time_drain(
+    conn: &HiResConn,
+    iters: u64,
+    mut pop_all: impl FnMut(&HiResConn, u64) -> u64,
+) -> Duration {
+    let capacity = conn.get_rb_capacity();
+    let mut elapsed = Duration::ZERO;
+    let mut left = iters;
+    while left > 0 {
+        let chunk = left.min(capacity);
+        for i in 0..chunk {
+            conn.log(EVENT_ID, i, 0);
+        }
+        let started = Instant::now();
+        let popped = pop_all(conn, chunk);
+        elapsed += started.elapsed();
+        assert!(popped > 0, "ring did not drain");
+        left -= popped;
+        drain(conn);
+    }
+    elapsed
+}
+
+fn bench_drain(c: &mut Criterion) {
+    let Some(conn) = connect() else { return };
+    drain(&conn);
+    const BATCH: usize = 256;
+    let mut group = c.benchmark_group("drain");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("pop", |b| {
+        b.iter_custom(|iters| {
+            time_drain(&conn, iters, |conn, n| {
+                let mut popped = 0;
+                while popped < n && black_box(conn.pop()).is_some() {
+                    popped += 1;
+                }
+                popped
+            })
+        })
+    });
+    group.bench_function("pop_batch", |b| {
+        let mut out = Vec::with_capacity(BATCH);
+        b.iter_custom(|iters| {
+            time_drain(&conn, iters, |conn, n| {
+                let mut popped = 0;
+                while popped < n {
+                    out.clear();
+                    let got = conn.pop_batch(&mut out, BATCH.min((n - popped) as usize));
+                    black_box(&out);
+                    if got == 0 {
+                        break;
+                    }
+                    popped += got as u64;
+                }
+                popped
+            })
+        })
+    });
+    group.finish();
+}
+
+/// Time until `producers` threads logging flat out against one consumer
+/// have `ONSET_FILL` of the ring pending.
+fn time_to_onset(conn: &HiResConn, producers: usize) -> Duration {
+    let capacity = conn.get_rb_capacity();
+    let threshold = capacity * ONSET_FILL.0 / ONSET_FILL.1;
+    with_consumer(|| {
+        let stop = AtomicBool::new(false);
+        let barrier = Barrier::new(producers + 1);
+        thread::scope(|s| {
+            for _ in 0..producers {
+                s.spawn(|| {
+                    let conn = connect().expect("connected above");
+                    barrier.wait();
+                    let mut i = 0;
+                    while !stop.load(Ordering::Relaxed) {
+                        conn.log(EVENT_ID, i, 0);
+                        i += 1;
+                    }
+                });
+            }
+            barrier.wait();
+            let started = Instant::now();
+            while conn.get_stats().pending < threshold && started.elapsed() < ONSET_CAP {
+                std::hint::spin_loop();
+            }
+            let elapsed = started.elapsed();
+            stop.store(true, Ordering::Relaxed);
+            elapsed
+        })
+    })
+}
+
+fn bench_drop_onset(c: &mut Criterion) {
+    let Some(conn) = connect() else { return };
+    drain(&conn);
+    let mut group = c.benchmark_group("drop_onset");
+    group.sample_size(10);
+    for producers in ONSET_PRODUCERS {
+        group.bench_with_input(
+            BenchmarkId::new("producers", producers),
+            &producers,
+            |b, &producers| {
+                b.iter_custom(|iters| (0..iters).map(|_| time_to_onset(&conn, producers)).sum())
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_log, bench_drain, bench_drop_onset);
+criterion_main!(benches);
@@ -0,0 +1,341 @@
+//! Layout of the structs shared by khires, libhires_rt and Rust.
+//!
+//! The ring is written by the kernel module and the C++ library and read
+//! through rt_ffi's bindings, and nothing in the build ties the three
+//! together. bindgen's layout tests only prove the bindings match the
+//! header they were generated from (or, for the checked-in bindings, the
+//! header at the time they were refreshed). The assertions below pin the
+//! layout itself, so changing shared/common.h fails the Rust build until
+//! they are updated; [`check_library`] and [`check_ring`] compare against
+//! what the loaded library was compiled with and what the device mapped.
+//! [`check_version`] runs first and catches a library whose C API has
+//! changed under the bindings, before any other function is called.
+
+use crate::{
+    EntryLayout, ErrorKind, HiResError, ffi, hires_entry_layout_t, log_entry_compact_t,
+    log_entry_ext_t, log_entry_t, shared_ring_buffer_t,
+};
+use static_assertions::{assert_eq_align, assert_eq_size, const_assert_eq};
+use std::mem::{align_of, offset_of, size_of, size_of_val};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+assert_eq_size!(log_entry_t, [u64; 5]);
+assert_eq_align!(log_entry_t, u64);
+const_assert_eq!(offset_of!(log_entry_t, timestamp), 0);
+const_assert_eq!(offset_of!(log_entry_t, event_id), 8);
+const_assert_eq!(offset_of!(log_entry_t, cpu_id), 12);
+const_assert_eq!(offset_of!(log_entry_t, flags), 16);
+const_assert_eq!(offset_of!(log_entry_t, data1), 24);
+const_assert_eq!(offset_of!(log_entry_t, data2), 32);
+
+assert_eq_size!(log_entry_compact_t, [u64; 4]);
+assert_eq_align!(log_entry_compact_t, u64);
+const_assert_eq!(offset_of!(log_entry_compact_t, cpu_id), 12);
+const_assert_eq!(offset_of!(log_entry_compact_t, flags), 14);
+const_assert_eq!(offset_of!(log_entry_compact_t, data1), 16);
+const_assert_eq!(offset_of!(log_entry_compact_t, data2), 24);
+
+// log_entry_t is a prefix of the extended layout.
+assert_eq_size!(log_entry_ext_t, [u64; 8]);
+assert_eq_align!(log_entry_ext_t, u64);
+const_assert_eq!(offset_of!(log_entry_ext_t, flags), offset_of!(log_entry_t, flags));
+const_assert_eq!(offset_of!(log_entry_ext_t, tid), 20);
+const_assert_eq!(offset_of!(log_entry_ext_t, data1), offset_of!(log_entry_t, data1));
+const_assert_eq!(offset_of!(log_entry_ext_t, data2), offset_of!(log_entry_t, data2));
+const_assert_eq!(offset_of!(log_entry_ext_t, seqno), 40);
+
+assert_eq_size!(hires_entry_layout_t, [u32; 7]);
+
+/// Producer and consumer indexes each own a cacheline, then one line of
+/// metadata, then the entries.
+const RING_HEADER_SIZE: usize = 256;
+
+const_assert_eq!(align_of::<shared_ring_buffer_t>(), 64);
+const_assert_eq!(offset_of!(shared_ring_buffer_t, head), 0);
+const_assert_eq!(offset_of!(shared_ring_buffer_t, tail), 64);
+const_assert_eq!(offset_of!(shared_ring_buffer_t, shm_size_bytes_unaligned), 128);
+const_assert_eq!(offset_of!(shared_ring_buffer_t, capacity), 144);
+const_assert_eq!(offset_of!(shared_ring_buffer_t, idx_mask), 152);
+const_assert_eq!(offset_of!(shared_ring_buffer_t, dropped_count), 160);
+const_assert_eq!(offset_of!(shared_ring_buffer_t, magic), 168);
+const_assert_eq!(offset_of!(shared_ring_buffer_t, layout_checksum), 176);
+const_assert_eq!(offset_of!(shared_ring_buffer_t, generation), 180);
+const_assert_eq!(offset_of!(shared_ring_buffer_t, entry_layout), 184);
+const_assert_eq!(offset_of!(shared_ring_buffer_t, buffer), RING_HEADER_SIZE);
+const_assert_eq!(
+    size_of::<shared_ring_buffer_t>(),
+    RING_HEADER_SIZE + ffi::RING_BUFFER_SIZE as usize * size_of::<log_entry_t>()
+);
+
+/// `HIRES_API_VERSION` of the loaded libhires_rt, as `(major, minor)`.
+pub fn library_version() -> (u32, u32) {
+    let v = unsafe { ffi::hires_get_api_version() };
+    (v >> 16, v & 0xffff)
+}
+
+/// Checks that the loaded libhires_rt implements the API the bindings were
+/// generated from. Minor versions only add functions, so a newer library is
+/// fine; an older one may lack some the bindings call, and a different
+/// major version has changed existing ones.
+pub fn check_version() -> Result<(), HiResError> {
+    let (major, minor) = library_version();
+    let (want_major, want_minor) = (ffi::HIRES_API_VERSION_MAJOR, ffi::HIRES_API_VERSION_MINOR);
+    if major != want_major || minor < want_minor {
+        return Err(HiResError {
+            kind: ErrorKind::IncompatibleAbi,
+            message: format!(
+                "API version mismatch: rt_ffi expects libhires_rt {}.{} or a later {}.x, \
+                 found {}.{}",
+                want_major, want_minor, want_major, major, minor
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Compares the bindings with the layout libhires_rt was compiled with.
+pub fn check_library() -> Result<(), HiResError> {
+    let checks = [
+        (
+            "log_entry_t size",
+            size_of::<log_entry_t>(),
+            unsafe { ffi::hires_sizeof_log_entry() },
+        ),
+        (
+            "shared_ring_buffer_t size",
+            size_of::<shared_ring_buffer_t>(),
+            unsafe { ffi::hires_sizeof_ring_buffer() },
+        ),
+        (
+            "shared_ring_buffer_t entries offset",
+            offset_of!(shared_ring_buffer_t, buffer),
+            unsafe { ffi::hires_offsetof_ring_entries() },
+        ),
+    ];
+    for (what, rust, c) in checks {
+        if rust != c {
+            return Err(HiResError {
+                kind: ErrorKind::IncompatibleAbi,
+                message: format!(
+                    "ABI mismatch: {} is {} bytes in rt_ffi but {} in libhires_rt; \
+                     rebuild both from the same shared/common.h",
+                    what, rust, c
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Compares the bindings with the ring khires mapped: its size is the ring
+/// header plus one entry of the ring's layout per slot.
+pub(crate) fn check_ring(
+    capacity: u64,
+    shm_size: u64,
+    layout: EntryLayout,
+) -> Result<(), HiResError> {
+    let header = offset_of!(shared_ring_buffer_t, buffer) as u64;
+    let entry = layout.entry_size() as u64;
+    let expected = header + capacity * entry;
+    if shm_size != expected {
+        return Err(HiResError {
+            kind: ErrorKind::IncompatibleAbi,
+            message: format!(
+                "Ring layout mismatch: device maps {} bytes, bindings expect {} \
+                 ({} byte header + {} x {} byte entries); rebuild rt_ffi",
+                shm_size, expected, header, capacity, entry
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// `hires_layout_checksum()` from shared/common.h: FNV-1a over the entry
+/// size, the header size and the ring geometry.
+pub fn layout_checksum(entry_size: u16, capacity: u64, idx_mask: u64, shm_size: u64) -> u32 {
+    let words = [
+        entry_size as u64,
+        offset_of!(shared_ring_buffer_t, buffer) as u64,
+        capacity,
+        idx_mask,
+        shm_size,
+    ];
+    let mut hash = 2166136261u32;
+    for byte in words.iter().flat_map(|w| w.to_le_bytes()) {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(16777619);
+    }
+    hash
+}
+
+/// The ring's current generation, read when connecting so [`check_seal`]
+/// can tell a reset ring from the one the connection started on.
+///
+/// # Safety
+/// `buf` points at a mapped ring header.
+pub(crate) unsafe fn generation(buf: *mut shared_ring_buffer_t) -> u32 {
+    unsafe { AtomicU32::from_ptr(&raw mut (*buf).generation) }.load(Ordering::Acquire)
+}
+
+/// The entry size the ring header records, read when connecting so
+/// [`check_seal`] can check the header against it before the rest of the
+/// entry layout is trusted.
+///
+/// # Safety
+/// `buf` points at a mapped ring header.
+pub(crate) unsafe fn entry_size(buf: *mut shared_ring_buffer_t) -> u16 {
+    unsafe { (*buf).entry_layout.entry_size }
+}
+
+/// Checks the seal khires writes into the ring header after initializing
+/// it: the magic, the layout checksum of the geometry the connection uses,
+/// and the generation it saw when connecting.
+///
+/// # Safety
+/// `buf` points at a mapped ring header.
+pub(crate) unsafe fn check_seal(
+    buf: *mut shared_ring_buffer_t,
+    entry_size: u16,
+    capacity: u64,
+    idx_mask: u64,
+    shm_size: u64,
+    generation: u32,
+) -> Result<(), HiResError> {
+    let broken = |kind, message: String| {
+        Err(HiResError {
+            kind,
+            message: format!("Ring buffer failed its integrity check: {}", message),
+        })
+    };
+    let magic = unsafe { AtomicU64::from_ptr(&raw mut (*buf).magic) }.load(Ordering::Acquire);
+    if magic == 0 {
+        return broken(
+            ErrorKind::Runtime,
+            "khires has not initialized the ring or has been unloaded".to_string(),
+        );
+    }
+    if magic != ffi::HIRES_SHM_MAGIC {
+        return broken(
+            ErrorKind::IncompatibleAbi,
+            format!("bad magic {:#x}", magic),
+        );
+    }
+    let expected = layout_checksum(entry_size, capacity, idx_mask, shm_size);
+    let (checksum, header_capacity, header_mask) =
+        unsafe { ((*buf).layout_checksum, (*buf).capacity, (*buf).idx_mask) };
+    if checksum != expected || header_capacity != capacity || header_mask != idx_mask {
+        return broken(
+            ErrorKind::IncompatibleAbi,
+            format!(
+                "layout checksum mismatch (header {:#x}, expected {:#x})",
+                checksum, expected
+            ),
+        );
+    }
+    let current = unsafe { self::generation(buf) };
+    if current != generation {
+        return broken(
+            ErrorKind::Runtime,
+            format!("ring was reset (generation {} -> {})", generation, current),
+        );
+    }
+    Ok(())
+}
+
+/// The fields of entry struct `$ty`, indexed by `HIRES_FIELD_*`, as
+/// `(offset, size)`; `tid_seqno` adds the two only the extended layout has.
+macro_rules! entry_fields {
+    ($ty:ty) => {{
+        let mut fields = [(0, 0); ffi::HIRES_ENTRY_FIELDS as usize];
+        fields[..6].copy_from_slice(&[
+            (offset_of!($ty, timestamp), size_of::<u64>()),
+            (offset_of!($ty, event_id), size_of::<u32>()),
+            (offset_of!($ty, cpu_id), size_of_val(&<$ty>::default().cpu_id)),
+            (offset_of!($ty, flags), size_of::<u16>()),
+            (offset_of!($ty, data1), size_of::<u64>()),
+            (offset_of!($ty, data2), size_of::<u64>()),
+        ]);
+        fields
+    }};
+    ($ty:ty, tid_seqno) => {{
+        let mut fields = entry_fields!($ty);
+        fields[ffi::HIRES_FIELD_TID as usize] = (offset_of!($ty, tid), size_of::<u32>());
+        fields[ffi::HIRES_FIELD_SEQNO as usize] = (offset_of!($ty, seqno), size_of::<u64>());
+        fields
+    }};
+}
+
+/// Entry layout `layout` as this build lays it out, in the form khires
+/// records in the ring header (`hires_entry_layout_init()` in
+/// shared/common.h).
+pub fn entry_layout(layout: EntryLayout) -> hires_entry_layout_t {
+    let fields: [(usize, usize); ffi::HIRES_ENTRY_FIELDS as usize] = match layout {
+        EntryLayout::Standard => entry_fields!(log_entry_t),
+        EntryLayout::Compact => entry_fields!(log_entry_compact_t),
+        EntryLayout::Extended => entry_fields!(log_entry_ext_t, tid_seqno),
+    };
+    hires_entry_layout_t {
+        byte_order: ffi::HIRES_BYTE_ORDER_MARK,
+        entry_size: layout.entry_size() as u16,
+        header_size: offset_of!(shared_ring_buffer_t, buffer) as u16,
+        id: layout as u16,
+        reserved: 0,
+        field_offset: fields.map(|(offset, _)| offset as u8),
+        field_size: fields.map(|(_, size)| size as u8),
+    }
+}
+
+/// Compares the entry layout recorded in the ring header with this build's
+/// version of the same layout, so a ring written with another byte order or
+/// other entry structs is refused rather than decoded field by field into
+/// garbage. Returns the ring's layout.
+///
+/// # Safety
+/// `buf` points at a mapped ring header.
+pub(crate) unsafe fn check_entry_layout(
+    buf: *mut shared_ring_buffer_t,
+) -> Result<EntryLayout, HiResError> {
+    const FIELDS: [&str; ffi::HIRES_ENTRY_FIELDS as usize] =
+        ["timestamp", "event_id", "cpu_id", "flags", "data1", "data2", "tid", "seqno"];
+    let ring = unsafe { (*buf).entry_layout };
+    let mismatch = |message: String| {
+        Err(HiResError {
+            kind: ErrorKind::IncompatibleAbi,
+            message: format!("Ring entry layout mismatch: {}", message),
+        })
+    };
+    let endianness = |big| if big { "big-endian" } else { "little-endian" };
+    if ring.byte_order == ffi::HIRES_BYTE_ORDER_MARK.swap_bytes() {
+        return mismatch(format!(
+            "the ring is {} but this build is {}",
+            endianness(cfg!(target_endian = "little")),
+            endianness(cfg!(target_endian = "big"))
+        ));
+    }
+    if ring.byte_order != ffi::HIRES_BYTE_ORDER_MARK {
+        return mismatch(format!("unknown byte-order mark {:#x}", ring.byte_order));
+    }
+    let Some(layout) = EntryLayout::from_id(ring.id as u32) else {
+        return mismatch(format!("unknown entry layout {}; rebuild rt_ffi", ring.id));
+    };
+    let ours = entry_layout(layout);
+    if (ring.entry_size, ring.header_size) != (ours.entry_size, ours.header_size) {
+        return mismatch(format!(
+            "{} byte entries after a {} byte header, bindings expect {} after {}; \
+             rebuild rt_ffi",
+            ring.entry_size, ring.header_size, ours.entry_size, ours.header_size
+        ));
+    }
+    for (i, name) in FIELDS.iter().enumerate() {
+        let theirs = (ring.field_offset[i], ring.field_size[i]);
+        let expected = (ours.field_offset[i], ours.field_size[i]);
+        if theirs != expected {
+            return mismatch(format!(
+                "{} is {} bytes at offset {} in the ring, {} at {} in the bindings' {:?} \
+                 layout; rebuild rt_ffi",
+                name, theirs.1, theirs.0, expected.1, expected.0, layout
+            ));
+        }
+    }
+    Ok(layout)
+}
@@ -0,0 +1,163 @@
+//! Named channels: several workloads on one ring without mixing event IDs.
+//!
+//! Bits 16-28 of an event ID ([`HIRES_CHANNEL_SHIFT`], [`HIRES_CHANNEL_BITS`])
+//! select a channel and the lower bits hold an application event ID. The
+//! top three bits are left to the packet hook, hardware timestamp and stack
+//! frame flags, which the profiler checks first. Channel 0 is the default,
+//! which plain [`HiResConn::log`] and khires use. A channel's ID is derived
+//! from its name ([`channel_id`], `hires_channel_id()` in shared/common.h),
+//! so two processes agree on it without coordinating and a workload keeps
+//! its channel across runs.
+//!
+//! ```text
+//! let net = conn.channel("nginx");
+//! net.register_events::<NetEvent>();
+//! net.log(5, elapsed, 0);              // event 5 of channel "nginx"
+//! net.log_event(NetEvent::Recv(n));
+//! ```
+//!
+//! [`HiResConn::channel`] logs the name as a `HIRES_EV_CHANNEL_NAME` entry
+//! (encoded like thread names, see [`crate::thread`]), and the profiler
+//! reports each channel's events apart, labelled with its name. Distinct
+//! names can hash to one ID; the profiler warns when two are registered for
+//! the same channel.
+
+use crate::event::HiresEvent;
+use crate::{
+    HIRES_CHANNEL_BITS, HIRES_CHANNEL_MASK, HIRES_CHANNEL_SHIFT, HIRES_EV_CHANNEL_NAME,
+    HIRES_EV_RESERVED_FIRST, HiResConn,
+};
+
+/// `HIRES_CHANNEL_MASK`: the bits of a channel ID.
+pub const CHANNEL_MASK: u16 = HIRES_CHANNEL_MASK as u16;
+
+/// `hires_channel_id()`: FNV-1a over `name`, folded to
+/// [`HIRES_CHANNEL_BITS`]. Never 0.
+pub fn channel_id(name: &str) -> u16 {
+    let mut hash: u32 = 2166136261;
+    for &byte in name.as_bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(16777619);
+    }
+    match ((hash >> HIRES_CHANNEL_BITS) ^ hash) as u16 & CHANNEL_MASK {
+        0 => 1,
+        id => id,
+    }
+}
+
+/// `HIRES_CHANNEL_EVENT()`: the event ID `event_id` of `channel` is logged
+/// under. `channel` is a [`channel_id`], within [`CHANNEL_MASK`].
+#[inline]
+pub const fn channel_event(channel: u16, event_id: u32) -> u32 {
+    (((channel & CHANNEL_MASK) as u32) << HIRES_CHANNEL_SHIFT) | event_id
+}
+
+/// Splits a logged event ID into its channel and the event ID within it.
+#[inline]
+pub const fn split(event_id: u32) -> (u16, u32) {
+    (
+        (event_id >> HIRES_CHANNEL_SHIFT) as u16 & CHANNEL_MASK,
+        event_id & ((1 << HIRES_CHANNEL_SHIFT) - 1),
+    )
+}
+
+/// A connection logging on one channel, from [`HiResConn::channel`].
+#[derive(Clone, Copy)]
+pub struct Channel<'c, 'a> {
+    conn: &'c HiResConn<'a>,
+    id: u16,
+}
+
+impl<'a> HiResConn<'a> {
+    /// The channel named `name`, registering the name with the profiler.
+    /// Cheap enough to call once per workload or subsystem; keep the
+    /// returned handle rather than calling it per entry.
+    pub fn channel(&self, name: &str) -> Channel<'_, 'a> {
+        let id = channel_id(name);
+        if !cfg!(feature = "disabled") {
+            self.log_name(HIRES_EV_CHANNEL_NAME, id as u32, name);
+        }
+        Channel { conn: self, id }
+    }
+}
+
+impl<'c, 'a> Channel<'c, 'a> {
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// [`HiResConn::log`] with `event_id`, an application event ID, on
+    /// this channel.
+    #[inline]
+    pub fn log(&self, event_id: u32, data1: u64, data2: u64) -> bool {
+        debug_assert!(
+            event_id < HIRES_EV_RESERVED_FIRST,
+            "event ID {} is reserved",
+            event_id
+        );
+        self.conn
+            .log(channel_event(self.id, event_id), data1, data2)
+    }
+
+    /// [`HiResConn::log_event`] on this channel.
+    #[inline]
+    pub fn log_event<E: HiresEvent>(&self, event: E) -> bool {
+        let (data1, data2) = event.encode();
+        self.log(event.event_id(), data1, data2)
+    }
+
+    /// [`HiResConn::register_events`] for this channel's IDs.
+    #[track_caller]
+    pub fn register_events<E: HiresEvent>(&self) -> bool {
+        let mut logged = true;
+        for &(event_id, name) in E::EVENTS {
+            logged &= self.register_event(event_id, name);
+        }
+        logged
+    }
+
+    /// [`HiResConn::register_event`] for this channel's `event_id`. The
+    /// same ID on different channels may have different names.
+    #[track_caller]
+    pub fn register_event(&self, event_id: u32, name: &str) -> bool {
+        self.conn
+            .register_event(channel_event(self.id, event_id), name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hwts::is_hw_timestamp;
+    use crate::packet::is_packet_hook;
+    use crate::stack::is_stack_frame;
+
+    #[test]
+    fn channel_events_stay_clear_of_entry_flags() {
+        let names = ["nginx", "envoy", "redis", "memcached", "wrk2", "echo"]
+            .into_iter()
+            .map(str::to_string)
+            .chain((0..4096).map(|i| format!("workload-{}", i)));
+        for name in names {
+            let channel = channel_id(&name);
+            assert_ne!(channel, 0, "{}", name);
+            assert_eq!(channel & !CHANNEL_MASK, 0, "{}", name);
+            for event_id in [0, 1, HIRES_EV_RESERVED_FIRST - 1] {
+                let logged = channel_event(channel, event_id);
+                assert!(!is_stack_frame(logged), "{}: {:#x}", name, logged);
+                assert!(!is_hw_timestamp(logged), "{}: {:#x}", name, logged);
+                assert!(!is_packet_hook(logged), "{}: {:#x}", name, logged);
+                assert_eq!(split(logged), (channel, event_id));
+            }
+        }
+    }
+
+    #[test]
+    fn split_ignores_entry_flags() {
+        let logged = channel_event(channel_id("nginx"), 5);
+        assert_eq!(
+            split(logged | crate::stack::STACK_FRAME_FLAG),
+            split(logged)
+        );
+    }
+}
@@ -0,0 +1,38 @@
+//! Clocks for the backends that run without khires, which otherwise
+//! provides the TSC calibration and the entry timestamps.
+
+use std::time::{Duration, Instant};
+
+/// Counts TSC ticks over 10ms of wall time, as khires does at load.
+pub(crate) fn calibrate_tsc_hz() -> u64 {
+    let start = Instant::now();
+    let tsc_start = read_tsc();
+    while start.elapsed() < Duration::from_millis(10) {
+        std::hint::spin_loop();
+    }
+    let tsc_end = read_tsc();
+    let elapsed_ns = start.elapsed().as_nanos() as u64;
+    tsc_end.wrapping_sub(tsc_start) * 1_000_000_000 / elapsed_ns.max(1)
+}
+
+pub(crate) fn read_tsc() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        core::arch::x86_64::_rdtsc()
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        let cnt: u64;
+        unsafe { core::arch::asm!("isb", "mrs {}, cntvct_el0", out(reg) cnt) };
+        cnt
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC_RAW, &mut ts) };
+        ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+    }
+}
@@ -0,0 +1,144 @@
+//! Cross-checking the TSC against `CLOCK_MONOTONIC`.
+//!
+//! Every latency in a report is TSC cycles scaled by the frequency khires
+//! calibrated at load. If that calibration is off, or the hypervisor scales
+//! or offsets the guest TSC while the capture runs, the numbers are wrong
+//! without looking wrong. A [`ClockCheck`] measures intervals with both the
+//! TSC (converted at the calibrated frequency) and `std::time::Instant`, and
+//! logs each pair as a `HIRES_EV_CLOCK_CHECK` entry; the profiler reports
+//! how far they disagree.
+//!
+//! `CLOCK_MONOTONIC` is itself slewed by NTP, by at most 500 ppm, so only a
+//! larger or growing disagreement points at the TSC.
+
+use crate::{HIRES_EV_CLOCK_CHECK, HiResConn};
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// One interval measured with both clocks, as logged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClockSample {
+    /// Length per the TSC at the calibrated frequency, in ns.
+    pub tsc_ns: u64,
+    /// Length per `CLOCK_MONOTONIC`, in ns.
+    pub monotonic_ns: u64,
+}
+
+impl ClockSample {
+    /// The sample a `HIRES_EV_CLOCK_CHECK` entry carries.
+    pub fn from_entry(data1: u64, data2: u64) -> Self {
+        ClockSample {
+            tsc_ns: data1,
+            monotonic_ns: data2,
+        }
+    }
+
+    /// How much faster the TSC ran than `CLOCK_MONOTONIC`, in parts per
+    /// million (negative if slower).
+    pub fn error_ppm(&self) -> f64 {
+        if self.monotonic_ns == 0 {
+            return 0.0;
+        }
+        (self.tsc_ns as f64 - self.monotonic_ns as f64) / self.monotonic_ns as f64 * 1e6
+    }
+}
+
+/// Both clocks read together: the TSC on either side of `Instant::now()`,
+/// averaged, so neither read waits on the other.
+#[derive(Clone, Copy)]
+struct Reading {
+    tsc: u64,
+    monotonic: Instant,
+}
+
+impl Reading {
+    fn now(conn: &HiResConn) -> Self {
+        let before = conn.now_cycles();
+        let monotonic = Instant::now();
+        let after = conn.now_cycles();
+        Reading {
+            tsc: before + after.wrapping_sub(before) / 2,
+            monotonic,
+        }
+    }
+}
+
+/// Measures back-to-back intervals, each from the previous
+/// [`check`](Self::check) (or [`new`](Self::new)) to the next.
+pub struct ClockCheck {
+    start: Reading,
+}
+
+impl ClockCheck {
+    /// Starts the first interval.
+    pub fn new(conn: &HiResConn) -> Self {
+        ClockCheck {
+            start: Reading::now(conn),
+        }
+    }
+
+    /// Ends the current interval, logs it as a `HIRES_EV_CLOCK_CHECK` entry
+    /// and starts the next. `None` (nothing logged) if the connection has
+    /// no TSC calibration, or with the `disabled` feature.
+    pub fn check(&mut self, conn: &HiResConn) -> Option<ClockSample> {
+        let end = Reading::now(conn);
+        let start = std::mem::replace(&mut self.start, end);
+        let tsc_ns = conn.cycles_to_ns(end.tsc.wrapping_sub(start.tsc));
+        if conn.tsc_hz == 0 || tsc_ns == 0 {
+            return None;
+        }
+        let sample = ClockSample {
+            tsc_ns,
+            monotonic_ns: end.monotonic.duration_since(start.monotonic).as_nanos() as u64,
+        };
+        conn.log(HIRES_EV_CLOCK_CHECK, sample.tsc_ns, sample.monotonic_ns);
+        Some(sample)
+    }
+
+    /// Runs checks every `interval` on a background thread until the
+    /// returned handle is dropped.
+    pub fn spawn(
+        conn: Arc<HiResConn<'static>>,
+        interval: Duration,
+    ) -> io::Result<ClockCheckThread> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let handle = thread::Builder::new()
+            .name("hires-clockcheck".to_string())
+            .spawn(move || {
+                let mut check = ClockCheck::new(&conn);
+                loop {
+                    // Woken early by the handle's drop.
+                    thread::park_timeout(interval);
+                    if stopped.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    check.check(&conn);
+                }
+            })?;
+        Ok(ClockCheckThread {
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+/// The thread started by [`ClockCheck::spawn`]; dropping it stops the
+/// checks.
+pub struct ClockCheckThread {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for ClockCheckThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Correlation IDs for `data2`.
+//!
+//! [`next_id`] hands out 64-bit IDs without shared atomics on the hot path:
+//! each thread owns a salt in the upper [`SALT_BITS`] bits and counts in
+//! the lower [`COUNTER_BITS`]. Salts come from a process-wide slot counter
+//! scrambled with a per-process value, so IDs are unique across the
+//! threads of a process (for the first 2^24 threads) and unlikely to
+//! collide with another process's. A thread that exhausts its counter
+//! takes a new salt.
+//!
+//! IDs are never 0 and always have salt bits set, so they cannot be
+//! mistaken for the small values kernel events carry in `data2` (IRQ and
+//! queue numbers, lengths) or for "no ID".
+
+use crate::clock::read_tsc;
+use std::cell::Cell;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+pub const SALT_BITS: u32 = 24;
+pub const COUNTER_BITS: u32 = 64 - SALT_BITS;
+
+const COUNTER_MASK: u64 = (1 << COUNTER_BITS) - 1;
+const SALT_MASK: u32 = (1 << SALT_BITS) - 1;
+
+static NEXT_SLOT: AtomicU32 = AtomicU32::new(0);
+static PROCESS_SALT: OnceLock<u32> = OnceLock::new();
+
+thread_local! {
+    // The next ID this thread hands out; 0 until it takes a salt.
+    static NEXT: Cell<u64> = const { Cell::new(0) };
+}
+
+/// A fresh correlation ID, see the module docs.
+#[inline]
+pub fn next_id() -> u64 {
+    NEXT.with(|next| {
+        let mut id = next.get();
+        if id & COUNTER_MASK == 0 {
+            id = new_base() | 1;
+        }
+        next.set(id.wrapping_add(1));
+        id
+    })
+}
+
+/// Splits an ID from [`next_id`] into (thread salt, counter).
+#[inline]
+pub fn split_id(id: u64) -> (u32, u64) {
+    ((id >> COUNTER_BITS) as u32, id & COUNTER_MASK)
+}
+
+fn new_base() -> u64 {
+    let process = *PROCESS_SALT.get_or_init(|| {
+        // Multiplicative hash of the PID and the TSC at first use.
+        let seed = ((std::process::id() as u64) << 32) ^ read_tsc();
+        (seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 40) as u32
+    });
+    loop {
+        // XOR with a constant keeps distinct slots distinct.
+        let salt = (NEXT_SLOT.fetch_add(1, Ordering::Relaxed) ^ process) & SALT_MASK;
+        if salt != 0 {
+            return (salt as u64) << COUNTER_BITS;
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! DPDK burst events, the Rust side of `rt/include/hires_dpdk.h`.
+//!
+//! One entry per burst: `data1` is the cycles spent in the RX/TX burst
+//! call and `data2` packs the port, queue and packet count (see
+//! [`burst_data2`]). Wrap the PMD call of whichever DPDK binding is in use
+//! with [`HiResConn::log_rx_burst`] or [`HiResConn::log_tx_burst`].
+
+use crate::{HiResConn, rdtsc};
+
+/// Same layout as `HIRES_DPDK_DATA2`.
+#[inline]
+pub const fn burst_data2(port: u16, queue: u16, packets: u16) -> u64 {
+    ((port as u64) << 48) | ((queue as u64) << 32) | packets as u64
+}
+
+/// Inverse of [`burst_data2`]: `(port, queue, packets)`.
+#[inline]
+pub const fn decode_burst(data2: u64) -> (u16, u16, u16) {
+    ((data2 >> 48) as u16, (data2 >> 32) as u16, data2 as u16)
+}
+
+impl<'a> HiResConn<'a> {
+    /// Times `burst` (an `rte_eth_rx_burst` call returning the number of
+    /// packets) and logs it unless it was an empty poll.
+    #[inline]
+    pub fn log_rx_burst<F: FnOnce() -> u16>(
+        &self,
+        event_id: u32,
+        port: u16,
+        queue: u16,
+        burst: F,
+    ) -> u16 {
+        let start = rdtsc();
+        let n = burst();
+        if n > 0 {
+            self.log(event_id, rdtsc() - start, burst_data2(port, queue, n));
+        }
+        n
+    }
+
+    /// Times and logs an `rte_eth_tx_burst` call. Bursts that sent fewer
+    /// packets than offered (a full TX ring) are logged too.
+    #[inline]
+    pub fn log_tx_burst<F: FnOnce() -> u16>(
+        &self,
+        event_id: u32,
+        port: u16,
+        queue: u16,
+        burst: F,
+    ) -> u16 {
+        let start = rdtsc();
+        let n = burst();
+        self.log(event_id, rdtsc() - start, burst_data2(port, queue, n));
+        n
+    }
+}
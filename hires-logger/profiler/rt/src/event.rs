@@ -0,0 +1,168 @@
+//! Typed event definitions.
+//!
+//! An enum implementing [`HiresEvent`] is the single definition of a set of
+//! events: each variant has a stable event ID and a name, and its fields
+//! (at most two, each a [`Payload`]) are the entry's `data1` and `data2`.
+//! With the `derive` feature, `#[derive(HiresEvent)]` writes the
+//! implementation and rejects duplicate or reserved IDs at compile time:
+//!
+//! ```text
+//! #[derive(HiresEvent)]
+//! #[hires(base = 100)]
+//! enum NetEvent {
+//!     Connect,                          // 100
+//!     Send { bytes: u64, queue: u16 },  // 101
+//!     Recv(u64),                        // 102
+//!     #[hires(id = 120, name = "conn_close")]
+//!     Close,
+//! }
+//!
+//! conn.register_events::<NetEvent>();
+//! conn.log_event(NetEvent::Send { bytes: n, queue });
+//! ```
+//!
+//! Variants without `#[hires(id = ..)]` take the previous variant's ID plus
+//! one (the first takes `base`, default 0), as discriminants do, so append
+//! new variants to keep existing IDs. Names default to the variant name.
+//!
+//! [`HiResConn::register_events`] logs the names as `HIRES_EV_EVENT_NAME`
+//! entries, the same encoding as thread names (see [`crate::thread`]), and
+//! the profiler shows them next to the IDs. In debug builds registration
+//! also panics if an ID was already registered under another name, by
+//! another enum or a [`HiResConn::register_event`] call elsewhere.
+
+use crate::{HIRES_EV_EVENT_NAME, HIRES_THREAD_NAME_MAX, HiResConn};
+
+#[cfg(feature = "derive")]
+pub use rt_derive::HiresEvent;
+
+/// A set of events with stable IDs and typed payloads, see the module docs.
+pub trait HiresEvent: Sized {
+    /// `(event ID, name)` of every variant, in declaration order.
+    const EVENTS: &'static [(u32, &'static str)];
+
+    fn event_id(&self) -> u32;
+
+    fn name(&self) -> &'static str;
+
+    /// The entry's `(data1, data2)`; fields a variant does not have are 0.
+    fn encode(&self) -> (u64, u64);
+
+    /// The event an entry was logged as, or `None` if `event_id` is not one
+    /// of this type's.
+    fn decode(event_id: u32, data1: u64, data2: u64) -> Option<Self>;
+}
+
+/// A variant field type, stored in one data word.
+pub trait Payload: Copy {
+    fn to_word(self) -> u64;
+    fn from_word(word: u64) -> Self;
+}
+
+macro_rules! int_payload {
+    ($($t:ty),*) => {$(
+        impl Payload for $t {
+            #[inline]
+            fn to_word(self) -> u64 {
+                self as u64
+            }
+            #[inline]
+            fn from_word(word: u64) -> Self {
+                word as $t
+            }
+        }
+    )*};
+}
+
+int_payload!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl Payload for bool {
+    #[inline]
+    fn to_word(self) -> u64 {
+        self as u64
+    }
+    #[inline]
+    fn from_word(word: u64) -> Self {
+        word != 0
+    }
+}
+
+impl Payload for f64 {
+    #[inline]
+    fn to_word(self) -> u64 {
+        self.to_bits()
+    }
+    #[inline]
+    fn from_word(word: u64) -> Self {
+        f64::from_bits(word)
+    }
+}
+
+/// The longest name [`HiResConn::register_events`] logs whole.
+pub const MAX_NAME_LEN: usize = HIRES_THREAD_NAME_MAX as usize;
+
+impl<'a> HiResConn<'a> {
+    /// Logs `event` with its ID and encoded fields.
+    #[inline]
+    pub fn log_event<E: HiresEvent>(&self, event: E) -> bool {
+        let (data1, data2) = event.encode();
+        self.log(event.event_id(), data1, data2)
+    }
+
+    /// Logs the name of every event of `E` for the profiler's name table.
+    /// Returns `false` if any part was dropped.
+    ///
+    /// # Panics
+    /// In debug builds, if one of the IDs was registered under another name
+    /// before, see [`register_event`](Self::register_event).
+    #[track_caller]
+    pub fn register_events<E: HiresEvent>(&self) -> bool {
+        let mut logged = true;
+        for &(event_id, name) in E::EVENTS {
+            logged &= self.register_event(event_id, name);
+        }
+        logged
+    }
+
+    /// Logs `name` as the name of `event_id`, for events not defined with
+    /// [`HiresEvent`]. Returns `false` if any part was dropped.
+    ///
+    /// # Panics
+    /// In debug builds, if `event_id` was registered under another name
+    /// before, in this process and on any connection: two call sites using
+    /// one ID for different events would mix their entries in every
+    /// statistic. Registering the same name again is fine.
+    #[track_caller]
+    pub fn register_event(&self, event_id: u32, name: &str) -> bool {
+        #[cfg(debug_assertions)]
+        check_unique(event_id, name);
+        if cfg!(feature = "disabled") {
+            return true;
+        }
+        self.log_name(HIRES_EV_EVENT_NAME, event_id, name)
+    }
+}
+
+/// Debug builds: the name each event ID was first registered under.
+#[cfg(debug_assertions)]
+static REGISTERED: std::sync::Mutex<std::collections::BTreeMap<u32, String>> =
+    std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+#[cfg(debug_assertions)]
+#[track_caller]
+fn check_unique(event_id: u32, name: &str) {
+    let mut registered = REGISTERED
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let previous = registered
+        .entry(event_id)
+        .or_insert_with(|| name.to_string())
+        .clone();
+    drop(registered);
+    if previous != name {
+        panic!(
+            "event ID {} registered as `{}` and as `{}`",
+            event_id, previous, name
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Page faults on the ring.
+//!
+//! A producer that faults on a ring page between taking its timestamp and
+//! publishing the entry stalls for microseconds (a minor fault) or much
+//! longer (a major one), and the stall shows up as latency the code under
+//! test never had. [`HiResConn::lock_ring`] maps every page of the ring up
+//! front and keeps it mapped (`mlock`); [`HiResConn::ring_residency`] tells
+//! whether a run took faults on the ring anyway, as pages that became
+//! resident meanwhile. [`FaultCounts`] counts a process's or a thread's
+//! faults on any memory, which bounds those from above.
+//!
+//! ```text
+//! conn.lock_ring()?;
+//! let before = conn.ring_residency().unwrap();
+//! run_workload(&conn);
+//! let faulted_in = conn.ring_residency().unwrap().resident - before.resident;
+//! ```
+//!
+//! Locking needs `CAP_IPC_LOCK` or an `RLIMIT_MEMLOCK` (`ulimit -l`) at
+//! least the size of the ring. The lock goes with the mapping: on a khires
+//! ring it ends when the connection is dropped.
+
+use crate::{ErrorKind, HiResConn, HiResError};
+use std::io;
+use std::mem;
+
+/// Page faults taken so far on any memory, from `getrusage(2)`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FaultCounts {
+    /// Served without I/O, e.g. a page table entry filled in for a page
+    /// already in memory.
+    pub minor: u64,
+    /// Served by reading the page in.
+    pub major: u64,
+}
+
+impl FaultCounts {
+    /// Faults of every thread of this process.
+    pub fn process() -> io::Result<Self> {
+        Self::usage(libc::RUSAGE_SELF)
+    }
+
+    /// Faults of the calling thread.
+    pub fn thread() -> io::Result<Self> {
+        Self::usage(libc::RUSAGE_THREAD)
+    }
+
+    fn usage(who: libc::c_int) -> io::Result<Self> {
+        let mut usage: libc::rusage = unsafe { mem::zeroed() };
+        if unsafe { libc::getrusage(who, &mut usage) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(FaultCounts {
+            minor: usage.ru_minflt as u64,
+            major: usage.ru_majflt as u64,
+        })
+    }
+
+    /// Faults taken between `earlier` and `self`.
+    pub fn since(&self, earlier: &FaultCounts) -> FaultCounts {
+        FaultCounts {
+            minor: self.minor.saturating_sub(earlier.minor),
+            major: self.major.saturating_sub(earlier.major),
+        }
+    }
+}
+
+/// Pages of the ring mapped into this process, from `mincore(2)`. A page
+/// that is not has yet to be faulted in by whoever touches it first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Residency {
+    pub resident: u64,
+    pub pages: u64,
+}
+
+impl<'a> HiResConn<'a> {
+    /// The mapped ring as a byte range; `None` on the perf fallback, whose
+    /// buffers belong to the kernel.
+    fn ring_range(&self) -> Option<(*mut libc::c_void, usize)> {
+        let buf = unsafe { self.get_raw_buffer() };
+        let len = self.get_shm_size() as usize;
+        (!buf.is_null() && len > 0).then_some((buf.cast(), len))
+    }
+
+    /// Locks the ring's pages into this process's memory (`mlock`),
+    /// faulting in any not mapped yet, so that logging and draining through
+    /// this connection take no faults on it.
+    ///
+    /// # Errors
+    /// Fails on the perf fallback, and if the lock exceeds
+    /// `RLIMIT_MEMLOCK` without `CAP_IPC_LOCK`.
+    pub fn lock_ring(&self) -> Result<(), HiResError> {
+        let Some((buf, len)) = self.ring_range() else {
+            return Err(HiResError {
+                kind: ErrorKind::Runtime,
+                message: "the perf fallback has no shared ring to lock".to_string(),
+            });
+        };
+        if unsafe { libc::mlock(buf, len) } != 0 {
+            let err = io::Error::last_os_error();
+            return Err(HiResError {
+                kind: ErrorKind::Runtime,
+                message: format!(
+                    "Failed to lock the ring ({} bytes) in memory: {} (raise `ulimit -l` or grant CAP_IPC_LOCK)",
+                    len, err
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// How much of the ring is mapped into this process right now; `None`
+    /// on the perf fallback or if `mincore` fails.
+    pub fn ring_residency(&self) -> Option<Residency> {
+        let (buf, len) = self.ring_range()?;
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        // mincore wants a page-aligned start, which only the stub's heap
+        // ring may lack.
+        let offset = buf as usize % page;
+        let (start, len) = (buf.wrapping_byte_sub(offset), len + offset);
+        let pages = len.div_ceil(page);
+        let mut vec = vec![0u8; pages];
+        if unsafe { libc::mincore(start, len, vec.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        Some(Residency {
+            resident: vec.iter().filter(|&&v| v & 1 != 0).count() as u64,
+            pages: pages as u64,
+        })
+    }
+}
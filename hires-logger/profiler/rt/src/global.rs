@@ -0,0 +1,112 @@
+//! A process-global connection, for code that cannot be handed a
+//! `&HiResConn`.
+//!
+//! The application calls [`init`] once at startup; from then on any crate
+//! linked into the process can log through it with [`crate::log!`] without
+//! threading the connection through its call signatures:
+//!
+//! ```text
+//! rt::init(rt::InitOptions::default())?;  // in main()
+//! rt::log!(EV_SEND, bytes_sent);          // anywhere
+//! ```
+//!
+//! Before `init` (or if it failed) `log!` drops the entry and returns
+//! `false`, so libraries can instrument unconditionally and leave it to
+//! the application whether anything is recorded.
+
+use crate::{ErrorKind, HiResConn, HiResError, TimestampSource};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+static GLOBAL: OnceLock<HiResConn<'static>> = OnceLock::new();
+
+/// How [`init`] connects.
+#[derive(Clone, Debug, Default)]
+pub struct InitOptions {
+    /// Device node to connect to; `/dev/khires` if `None`.
+    pub device_path: Option<PathBuf>,
+    /// Ring capacity to ask khires for, see
+    /// [`HiResConn::connect_with_capacity`]; the ring as loaded if `None`.
+    pub ring_capacity: Option<u64>,
+    /// Lock the ring in memory after connecting, see
+    /// [`HiResConn::lock_ring`].
+    pub lock_ring: bool,
+    /// Timestamp source to select after connecting; the connection's
+    /// default (`CLOCK_MONOTONIC`) if `None`.
+    pub timestamp_source: Option<TimestampSource>,
+}
+
+/// Connects the process-global connection `log!` writes to and returns it.
+///
+/// # Errors
+/// Fails if the connection, the timestamp source or the lock fails, as
+/// [`HiResConn::connect`] (or [`HiResConn::connect_with_capacity`]),
+/// [`HiResConn::set_timestamp_source`] and [`HiResConn::lock_ring`] do, and
+/// if the global connection is already set up. A failed `init` can be
+/// retried.
+pub fn init(options: InitOptions) -> Result<&'static HiResConn<'static>, HiResError> {
+    if GLOBAL.get().is_some() {
+        return Err(already_initialized());
+    }
+    let conn = match options.ring_capacity {
+        Some(capacity) => {
+            HiResConn::connect_with_capacity(options.device_path.as_deref(), capacity)?
+        }
+        None => HiResConn::connect(options.device_path.as_deref())?,
+    };
+    if let Some(source) = options.timestamp_source {
+        conn.set_timestamp_source(source)?;
+    }
+    if options.lock_ring {
+        conn.lock_ring()?;
+    }
+    // Two racing inits both connect; the loser's connection is dropped.
+    GLOBAL.set(conn).map_err(|_| already_initialized())?;
+    Ok(GLOBAL.get().unwrap())
+}
+
+/// The global connection, once [`init`] has succeeded.
+#[inline]
+pub fn get() -> Option<&'static HiResConn<'static>> {
+    GLOBAL.get()
+}
+
+/// What `log!` expands to: `log()` on the global connection, `false`
+/// before [`init`].
+#[inline]
+pub fn log(event_id: u32, data1: u64, data2: u64) -> bool {
+    if cfg!(feature = "disabled") {
+        return true;
+    }
+    GLOBAL
+        .get()
+        .is_some_and(|conn| conn.log(event_id, data1, data2))
+}
+
+fn already_initialized() -> HiResError {
+    HiResError {
+        kind: ErrorKind::Runtime,
+        message: "The global connection is already initialized".to_string(),
+    }
+}
+
+/// Logs an event through the process-global connection set up by
+/// [`init`](crate::init): `log!(event_id, data1, data2)`, with omitted data
+/// words 0. The data words are cast to `u64` with `as`, so lengths and
+/// counters of any integer type can be passed directly.
+///
+/// Evaluates to `true` if the entry was logged, `false` if the ring was full
+/// or the global connection is not set up. Like [`HiResConn::log`], always
+/// `true` with the `disabled` feature.
+#[macro_export]
+macro_rules! log {
+    ($event_id:expr $(,)?) => {
+        $crate::global::log($event_id, 0, 0)
+    };
+    ($event_id:expr, $data1:expr $(,)?) => {
+        $crate::global::log($event_id, ($data1) as u64, 0)
+    };
+    ($event_id:expr, $data1:expr, $data2:expr $(,)?) => {
+        $crate::global::log($event_id, ($data1) as u64, ($data2) as u64)
+    };
+}
@@ -0,0 +1,197 @@
+//! NIC hardware timestamps (SO_TIMESTAMPING) on the hires timeline.
+//!
+//! Wire-level timestamps come from the NIC's PTP hardware clock (PHC), which
+//! is unrelated to the TSC. They are logged following this convention:
+//!
+//! * `event_id` is the application's event with [`HW_TIMESTAMP_FLAG`] set.
+//! * `data1` is the PHC timestamp in nanoseconds, or 0 for a TX submit marker
+//!   (logged when the packet is handed to the socket).
+//! * `data2` is the packet key with [`HW_TX_BIT`] set for the TX direction.
+//!   For TX this should be the `SOF_TIMESTAMPING_OPT_ID` counter so the
+//!   completion read from the error queue matches its submit marker.
+//!
+//! [`PHC_SYNC_EVENT`] entries carry a (TSC, PHC) pair in `data1`/`data2`,
+//! sampled by [`Phc::sample_offset`]. The consumer fits PHC against TSC over
+//! these samples, so hardware timestamps land in the same domain as kernel
+//! entries and producers using an rdtsc timestamp source.
+
+use crate::HiResConn;
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::Path;
+
+/// Set in `event_id` for entries carrying a hardware timestamp.
+pub const HW_TIMESTAMP_FLAG: u32 = 1 << 30;
+
+/// Reserved event for PHC<->TSC synchronization samples.
+pub const PHC_SYNC_EVENT: u32 = HW_TIMESTAMP_FLAG | 0x3fff_ffff;
+
+/// Set in `data2` for TX-direction entries.
+pub const HW_TX_BIT: u64 = 1 << 63;
+
+#[inline]
+pub fn is_hw_timestamp(event_id: u32) -> bool {
+    event_id & HW_TIMESTAMP_FLAG != 0
+}
+
+/// Enables raw hardware RX and TX timestamps on a socket, with an OPT_ID
+/// counter on TX completions. The NIC itself must have hardware stamping
+/// switched on (SIOCSHWTSTAMP, e.g. via `hwstamp_ctl`).
+pub fn enable_timestamping(fd: RawFd) -> io::Result<()> {
+    let flags: libc::c_uint = libc::SOF_TIMESTAMPING_RX_HARDWARE
+        | libc::SOF_TIMESTAMPING_TX_HARDWARE
+        | libc::SOF_TIMESTAMPING_RAW_HARDWARE
+        | libc::SOF_TIMESTAMPING_OPT_ID
+        | libc::SOF_TIMESTAMPING_OPT_TSONLY;
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPING,
+            &flags as *const _ as *const libc::c_void,
+            mem::size_of_val(&flags) as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Extracts the raw hardware timestamp (ns) from the control messages of a
+/// `recvmsg` call on a socket set up with [`enable_timestamping`].
+pub fn hw_timestamp(msg: &libc::msghdr) -> Option<u64> {
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(msg) };
+    while !cmsg.is_null() {
+        let hdr = unsafe { &*cmsg };
+        if hdr.cmsg_level == libc::SOL_SOCKET && hdr.cmsg_type == libc::SCM_TIMESTAMPING {
+            // struct scm_timestamping: software, (deprecated), raw hardware.
+            let ts =
+                unsafe { (libc::CMSG_DATA(cmsg) as *const [libc::timespec; 3]).read_unaligned() };
+            let raw = ts[2];
+            if raw.tv_sec == 0 && raw.tv_nsec == 0 {
+                return None;
+            }
+            return Some(raw.tv_sec as u64 * 1_000_000_000 + raw.tv_nsec as u64);
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(msg, cmsg) };
+    }
+    None
+}
+
+/// Reads one TX completion from the socket's error queue without blocking.
+///
+/// # Returns
+/// `(OPT_ID counter, hardware timestamp in ns)`, or `None` if the queue is
+/// empty.
+pub fn read_tx_timestamp(fd: RawFd) -> io::Result<Option<(u32, u64)>> {
+    let mut control = [0u64; 64];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let ret = unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT) };
+    if ret < 0 {
+        let err = io::Error::last_os_error();
+        return match err.kind() {
+            io::ErrorKind::WouldBlock => Ok(None),
+            _ => Err(err),
+        };
+    }
+
+    let mut id = None;
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let hdr = unsafe { &*cmsg };
+        let is_recverr = (hdr.cmsg_level == libc::SOL_IP && hdr.cmsg_type == libc::IP_RECVERR)
+            || (hdr.cmsg_level == libc::SOL_IPV6 && hdr.cmsg_type == libc::IPV6_RECVERR);
+        if is_recverr {
+            let err = unsafe {
+                (libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err).read_unaligned()
+            };
+            if err.ee_origin == libc::SO_EE_ORIGIN_TIMESTAMPING {
+                id = Some(err.ee_data);
+            }
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+    Ok(id.zip(hw_timestamp(&msg)))
+}
+
+/// A NIC's PTP hardware clock, e.g. `/dev/ptp0`.
+pub struct Phc {
+    file: File,
+}
+
+impl Phc {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Phc {
+            file: File::open(path)?,
+        })
+    }
+
+    fn clock_id(&self) -> libc::clockid_t {
+        // FD_TO_CLOCKID from the kernel's posix-clock interface.
+        const CLOCKFD: libc::clockid_t = 3;
+        ((!self.file.as_raw_fd()) << 3) | CLOCKFD
+    }
+
+    pub fn now_ns(&self) -> io::Result<u64> {
+        let mut ts: libc::timespec = unsafe { mem::zeroed() };
+        if unsafe { libc::clock_gettime(self.clock_id(), &mut ts) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64)
+    }
+
+    /// Reads the PHC between two TSC reads `tries` times and keeps the
+    /// tightest bracket.
+    ///
+    /// # Returns
+    /// `(TSC at the bracket midpoint, PHC time in ns)`.
+    pub fn sample_offset(&self, tries: usize) -> io::Result<(u64, u64)> {
+        let mut best: Option<(u64, u64, u64)> = None;
+        for _ in 0..tries.max(1) {
+            let before = crate::rdtsc();
+            let phc = self.now_ns()?;
+            let after = crate::rdtsc();
+            let width = after.wrapping_sub(before);
+            if best.is_none_or(|(w, _, _)| width < w) {
+                best = Some((width, before + width / 2, phc));
+            }
+        }
+        let (_, tsc, phc) = best.unwrap();
+        Ok((tsc, phc))
+    }
+}
+
+impl<'a> HiResConn<'a> {
+    /// Logs the hardware RX timestamp of a received packet.
+    #[inline]
+    pub fn log_hw_rx(&self, event_id: u32, key: u64, hw_ns: u64) -> bool {
+        self.log(event_id | HW_TIMESTAMP_FLAG, hw_ns, key & !HW_TX_BIT)
+    }
+
+    /// Marks the moment a packet is handed to the socket, to be paired with
+    /// its [`log_hw_tx`](Self::log_hw_tx) completion.
+    #[inline]
+    pub fn log_tx_submit(&self, event_id: u32, key: u64) -> bool {
+        self.log(event_id | HW_TIMESTAMP_FLAG, 0, key | HW_TX_BIT)
+    }
+
+    /// Logs the hardware TX timestamp of a sent packet.
+    #[inline]
+    pub fn log_hw_tx(&self, event_id: u32, key: u64, hw_ns: u64) -> bool {
+        self.log(event_id | HW_TIMESTAMP_FLAG, hw_ns, key | HW_TX_BIT)
+    }
+
+    /// Logs one PHC<->TSC synchronization sample. Log these periodically
+    /// (e.g. once a second) so the consumer can also correct for drift.
+    pub fn log_phc_sync(&self, phc: &Phc) -> io::Result<bool> {
+        const SYNC_TRIES: usize = 16;
+        let (tsc, phc_ns) = phc.sample_offset(SYNC_TRIES)?;
+        Ok(self.log(PHC_SYNC_EVENT, tsc, phc_ns))
+    }
+}
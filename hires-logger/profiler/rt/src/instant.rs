@@ -0,0 +1,160 @@
+//! A drop-in for `std::time::Instant` read from the TSC.
+//!
+//! [`HiresInstant`] has `Instant`'s API, so code timing itself with
+//! `Instant` can switch with an import or a type alias:
+//!
+//! ```text
+//! use rt::HiresInstant as Instant;
+//! ```
+//!
+//! Readings are TSC cycles, the clock of `rdtsc`-stamped entries and of
+//! the `data1` durations [`crate::pair`] and
+//! [`now_cycles`](crate::HiResConn::now_cycles) produce, so intervals the
+//! application measures itself agree with the ones it logs
+//! ([`HiresInstant::cycles`] gives the raw reading to log).
+//!
+//! Cycles are converted to `Duration`s at the TSC frequency khires
+//! calibrated, taken from the most recent
+//! [`connect`](crate::HiResConn::connect). Before any connection (or with
+//! the `disabled` feature) the first conversion calibrates the TSC against
+//! the monotonic clock itself, which takes 10ms.
+//!
+//! Unlike `Instant`, readings are only comparable across CPUs where the TSC
+//! is synchronized (`constant_tsc` and `nonstop_tsc`, as on any platform
+//! khires' TSC timestamps are meaningful on).
+
+use crate::clock::{calibrate_tsc_hz, read_tsc};
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// TSC frequency for conversions; 0 until a connection or calibration.
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+
+/// Makes `hz` (a connection's calibrated frequency) the one conversions use.
+pub(crate) fn adopt_tsc_hz(hz: u64) {
+    if hz != 0 {
+        TSC_HZ.store(hz, Ordering::Relaxed);
+    }
+}
+
+/// The TSC frequency conversions use, see the module docs.
+pub fn tsc_hz() -> u64 {
+    match TSC_HZ.load(Ordering::Relaxed) {
+        0 => {
+            // Racing first uses each calibrate; a connection's value wins.
+            let hz = calibrate_tsc_hz().max(1);
+            match TSC_HZ.compare_exchange(0, hz, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => hz,
+                Err(current) => current,
+            }
+        }
+        hz => hz,
+    }
+}
+
+fn cycles_to_duration(cycles: u64) -> Duration {
+    let ns = cycles as u128 * NANOS_PER_SEC / tsc_hz() as u128;
+    Duration::from_nanos(ns.min(u64::MAX as u128) as u64)
+}
+
+fn duration_to_cycles(duration: Duration) -> Option<u64> {
+    u64::try_from(duration.as_nanos() * tsc_hz() as u128 / NANOS_PER_SEC).ok()
+}
+
+/// A TSC reading, with the API of `std::time::Instant`. See the module
+/// docs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HiresInstant(u64);
+
+impl HiresInstant {
+    #[inline]
+    pub fn now() -> Self {
+        HiresInstant(read_tsc())
+    }
+
+    /// The instant a TSC reading (an entry timestamp or `data1` of a
+    /// `rdtsc`-stamped producer) was taken at.
+    #[inline]
+    pub fn from_cycles(cycles: u64) -> Self {
+        HiresInstant(cycles)
+    }
+
+    /// The raw TSC reading.
+    #[inline]
+    pub fn cycles(&self) -> u64 {
+        self.0
+    }
+
+    /// Time since `earlier`, zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: HiresInstant) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+
+    pub fn checked_duration_since(&self, earlier: HiresInstant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(cycles_to_duration)
+    }
+
+    pub fn saturating_duration_since(&self, earlier: HiresInstant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        HiresInstant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<HiresInstant> {
+        let cycles = duration_to_cycles(duration)?;
+        self.0.checked_add(cycles).map(HiresInstant)
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<HiresInstant> {
+        let cycles = duration_to_cycles(duration)?;
+        self.0.checked_sub(cycles).map(HiresInstant)
+    }
+}
+
+impl Add<Duration> for HiresInstant {
+    type Output = HiresInstant;
+
+    /// # Panics
+    /// On overflow, as `Instant` does.
+    fn add(self, duration: Duration) -> HiresInstant {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for HiresInstant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for HiresInstant {
+    type Output = HiresInstant;
+
+    /// # Panics
+    /// On overflow, as `Instant` does.
+    fn sub(self, duration: Duration) -> HiresInstant {
+        self.checked_sub(duration)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for HiresInstant {
+    fn sub_assign(&mut self, duration: Duration) {
+        *self = *self - duration;
+    }
+}
+
+impl Sub<HiresInstant> for HiresInstant {
+    type Output = Duration;
+
+    /// Saturates at zero, as `Instant` does.
+    fn sub(self, other: HiresInstant) -> Duration {
+        self.duration_since(other)
+    }
+}
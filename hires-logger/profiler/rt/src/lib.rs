@@ -1,22 +1,106 @@
 //! Safe Rust wrapper for FFI bindings.
 
+// Both replace libhires_rt with rt::native; `stub` would silently win and
+// leave a `native` build logging to an in-memory ring.
+#[cfg(all(feature = "stub", feature = "native"))]
+compile_error!("The `stub` and `native` features are mutually exclusive; enable at most one");
+
+#[cfg(not(any(feature = "stub", feature = "native")))]
 use rt_ffi as ffi;
+#[cfg(any(feature = "stub", feature = "native"))]
+use native as ffi;
 use std::ffi::{CStr, CString};
 use std::fmt;
 use std::marker::PhantomData;
 use std::ops::Deref;
 use std::path::Path;
 use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+pub mod abi;
+pub mod channel;
+mod clock;
+pub mod clockcheck;
+pub mod corr;
+pub mod dpdk;
+pub mod event;
+pub mod faults;
+pub mod global;
+pub mod hwts;
+pub mod instant;
+pub mod mock;
+#[cfg(any(feature = "stub", feature = "native"))]
+mod native;
+pub mod net;
+pub mod numa;
+pub mod pair;
+pub mod packet;
+mod perf;
+pub mod ring;
+#[cfg(feature = "quinn")]
+pub mod quic;
+mod shm;
+pub mod span;
+pub mod stack;
+#[cfg(feature = "stub")]
+mod stub;
+pub mod thread;
+pub mod tls;
+#[cfg(feature = "tower")]
+pub mod tower;
+pub mod uring;
+pub mod wakeup;
+pub mod xsk;
 
 // Re-export shared types for convenience, ensuring they match FFI defs
-pub use ffi::{LOG_FLAG_KERNEL, LOG_FLAG_VALID, log_entry_t, shared_ring_buffer_t};
+pub use ffi::{
+    HIRES_CHANNEL_BITS, HIRES_CHANNEL_MASK, HIRES_CHANNEL_SHIFT, HIRES_EV_CHANNEL_NAME,
+    HIRES_EV_CLOCK_CHECK, HIRES_EV_EVENT_NAME, HIRES_EV_IRQ, HIRES_EV_RESERVED_FIRST,
+    HIRES_EV_SWIOTLB_FIRST, HIRES_EV_SWIOTLB_LAST, HIRES_EV_SWIOTLB_MAP, HIRES_EV_SWIOTLB_UNMAP,
+    HIRES_EV_THREAD_NAME, HIRES_EV_TLS, HIRES_EV_VMEXIT, HIRES_EV_VNET_FIRST,
+    HIRES_EV_VNET_INTERRUPT, HIRES_EV_VNET_KICK, HIRES_EV_VNET_LAST, HIRES_EV_VNET_NAPI_POLL,
+    HIRES_EV_VNET_SKB_DELIVER, HIRES_EV_WAKEUP, HIRES_SWIOTLB_FAILED, HIRES_THREAD_NAME_MAX,
+    HIRES_TLS_DECRYPT, HIRES_TLS_ENCRYPT, HIRES_TLS_FAILED, HIRES_TSC_SRC_CALIBRATED,
+    HIRES_TSC_SRC_SECURE_TSC, HIRES_VMEXIT_SNP_VC, HIRES_VMEXIT_TDX_VE, LOG_FLAG_KERNEL,
+    LOG_FLAG_TSC, LOG_FLAG_VALID, hires_batch_entry_t, hires_entry_layout_t, hires_rb_stats_t,
+    hires_tsc_info_t, log_entry_compact_t, log_entry_ext_t, log_entry_t, shared_ring_buffer_t,
+};
+pub use event::HiresEvent;
+pub use global::{InitOptions, init};
+pub use instant::HiresInstant;
+
+/// This crate's version, recorded in profiler reports.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 // --- Error Handling ---
+/// What went wrong, for callers that handle some failures differently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// An error reported by libhires_rt or the device.
+    Runtime,
+    /// libhires_rt could not be opened (only with the `dynamic-load`
+    /// feature, which resolves it at runtime).
+    LibraryNotFound,
+    /// libhires_rt, the device or the bindings disagree on the API version
+    /// or struct layout; rebuilding against the same tree fixes it.
+    IncompatibleAbi,
+    /// An argument outside what khires accepts, e.g. a ring capacity.
+    InvalidArgument,
+}
+
 #[derive(Debug)]
 pub struct HiResError {
+    kind: ErrorKind,
     message: String,
 }
 
+impl HiResError {
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
 impl std::error::Error for HiResError {}
 
 impl fmt::Display for HiResError {
@@ -33,11 +117,124 @@ fn check_error() -> Result<(), HiResError> {
     } else {
         let err_cstr = unsafe { CStr::from_ptr(err_ptr) };
         Err(HiResError {
+            kind: ErrorKind::Runtime,
             message: err_cstr.to_string_lossy().into_owned(),
         })
     }
 }
 
+/// Where `log()` takes entry timestamps from.
+///
+/// Raw TSC reads are cheapest, but on some CVM configurations the TSC is
+/// intercepted or scaled and a paravirt clock gives more trustworthy
+/// intervals. TSC-stamped entries carry `LOG_FLAG_TSC`.
+///
+/// A connection's source is set with
+/// [`HiResConn::set_timestamp_source`] (or [`InitOptions`]); single entries
+/// can use another with [`HiResConn::log_with_source`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampSource {
+    /// `CLOCK_MONOTONIC` nanoseconds (default).
+    Monotonic,
+    /// `CLOCK_MONOTONIC_RAW` nanoseconds, not slewed by NTP.
+    MonotonicRaw,
+    /// Raw `rdtsc` cycles, the cheapest source. The read may execute before
+    /// earlier instructions complete, so short intervals can come out short.
+    Rdtsc,
+    /// `rdtscp` cycles, ordered after preceding instructions. The entry's
+    /// CPU is the one the TSC was read on, from the same instruction.
+    Rdtscp,
+    /// `CLOCK_MONOTONIC_RAW` via the vDSO, only while kvm-clock is the active
+    /// clocksource.
+    Kvmclock,
+    /// `lfence; rdtsc` cycles: ordered like [`Rdtscp`](Self::Rdtscp),
+    /// without reading the CPU, and usable where `rdtscp` is not exposed.
+    RdtscLfence,
+}
+
+impl TimestampSource {
+    fn raw(self) -> u32 {
+        match self {
+            TimestampSource::Monotonic => ffi::HIRES_TS_MONOTONIC,
+            TimestampSource::MonotonicRaw => ffi::HIRES_TS_MONOTONIC_RAW,
+            TimestampSource::Rdtsc => ffi::HIRES_TS_RDTSC,
+            TimestampSource::Rdtscp => ffi::HIRES_TS_RDTSCP,
+            TimestampSource::Kvmclock => ffi::HIRES_TS_KVMCLOCK,
+            TimestampSource::RdtscLfence => ffi::HIRES_TS_RDTSC_LFENCE,
+        }
+    }
+}
+
+/// Where a connection's entries go.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// The ring khires shares through /dev/khires (or, with the `stub`
+    /// feature, its in-memory stand-in), through libhires_rt or, with the
+    /// `native` feature, mapped by `rt` itself.
+    Khires,
+    /// The perf fallback used when the device is missing (see `rt::perf`):
+    /// one syscall per entry and kernel timestamps, no kernel-side events.
+    Perf,
+}
+
+/// How khires lays out the ring's entries (its `entry_layout` parameter).
+/// Every layout carries `log_entry_t`'s fields; they trade payload against
+/// how many entries fit in the ring's memory and cachelines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum EntryLayout {
+    /// `log_entry_t`, 40 bytes.
+    Standard = ffi::HIRES_ENTRY_LAYOUT_STANDARD,
+    /// `log_entry_compact_t`, 32 bytes; `cpu_id` is truncated to 16 bits.
+    Compact = ffi::HIRES_ENTRY_LAYOUT_COMPACT,
+    /// `log_entry_ext_t`, 64 bytes: adds the producer's TID and the
+    /// entry's sequence number.
+    Extended = ffi::HIRES_ENTRY_LAYOUT_EXTENDED,
+}
+
+impl EntryLayout {
+    /// The layout with `HIRES_ENTRY_LAYOUT_*` value `id`, if this build
+    /// knows it.
+    pub fn from_id(id: u32) -> Option<Self> {
+        match id {
+            ffi::HIRES_ENTRY_LAYOUT_STANDARD => Some(Self::Standard),
+            ffi::HIRES_ENTRY_LAYOUT_COMPACT => Some(Self::Compact),
+            ffi::HIRES_ENTRY_LAYOUT_EXTENDED => Some(Self::Extended),
+            _ => None,
+        }
+    }
+
+    /// Bytes one entry takes in the ring.
+    pub fn entry_size(self) -> usize {
+        match self {
+            Self::Standard => std::mem::size_of::<log_entry_t>(),
+            Self::Compact => std::mem::size_of::<log_entry_compact_t>(),
+            Self::Extended => std::mem::size_of::<log_entry_ext_t>(),
+        }
+    }
+}
+
+/// Clocks read together when a connection is made. Relates the TSC that
+/// entries are stamped with to wall-clock time, and bounds the session for
+/// rates and duty cycles.
+#[derive(Clone, Copy, Debug)]
+pub struct SessionAnchor {
+    /// TSC (on aarch64, the virtual counter) at connect.
+    pub tsc: u64,
+    pub wall: SystemTime,
+    pub monotonic: Instant,
+}
+
+impl SessionAnchor {
+    fn now() -> Self {
+        SessionAnchor {
+            tsc: clock::read_tsc(),
+            wall: SystemTime::now(),
+            monotonic: Instant::now(),
+        }
+    }
+}
+
 // --- Safe Wrapper Struct ---
 #[repr(align(64))]
 pub struct AlignedU64(pub u64);
@@ -52,7 +249,23 @@ impl Deref for AlignedU64 {
 
 pub struct HiResConn<'a> {
     handle: *mut ffi::HiResLoggerConnHandle,
+    // Only set, with a null handle, when connected through the perf fallback.
+    perf: Option<perf::PerfRing>,
     pub cycle_per_us: AlignedU64, 
+    // Calibrated TSC frequency at connect, for the elapsed_* helpers.
+    tsc_hz: u64,
+    // Ring generation and entry size at connect, see check_seal().
+    generation: u32,
+    entry_size: u16,
+    session: SessionAnchor,
+    // The mapped ring, read directly by the consumer methods. Set once the
+    // connection passes its checks; `None` on the perf fallback.
+    shm: Option<shm::Shm>,
+    // Drop count as of the last take_drops() (or connect).
+    drops_epoch: AtomicU64,
+    // Torn read checks on pop (set_torn_checks()) and entries they discarded.
+    torn_checks: AtomicBool,
+    torn: AtomicU64,
     // Use PhantomData to indicate lifetime relationship if buffer access is tied
     // to the connection's lifetime, although the buffer itself is static memory.
     // Not strictly needed here as get_buffer returns a raw pointer.
@@ -60,6 +273,24 @@ pub struct HiResConn<'a> {
 }
 
 impl<'a> HiResConn<'a> {
+    /// A connection to no ring, as used by the `disabled` feature.
+    fn detached(session: SessionAnchor) -> Self {
+        HiResConn {
+            handle: ptr::null_mut(),
+            perf: None,
+            cycle_per_us: AlignedU64(0),
+            tsc_hz: 0,
+            generation: 0,
+            entry_size: 0,
+            session,
+            shm: None,
+            drops_epoch: AtomicU64::new(0),
+            torn_checks: AtomicBool::new(false),
+            torn: AtomicU64::new(0),
+            _marker: PhantomData,
+        }
+    }
+
     /// Connects to the profiler device.
     ///
     /// # Arguments
@@ -68,30 +299,210 @@ impl<'a> HiResConn<'a> {
     ///
     /// # Errors
     /// Returns `HiResError` if connection fails.
+    ///
+    /// If the device node does not exist (no khires on this kernel), falls
+    /// back to the perf transport, see [`Backend::Perf`].
+    ///
+    /// With the `disabled` feature this opens nothing and always succeeds;
+    /// the connection behaves as closed and `log()` compiles to nothing.
     pub fn connect(device_path: Option<&Path>) -> Result<Self, HiResError> {
+        Self::open(device_path, 0)
+    }
+
+    /// [`connect`](Self::connect), first asking khires for a ring of
+    /// `capacity` entries (a power of two) instead of the one it was loaded
+    /// with, so each experiment can size the ring for its rate.
+    ///
+    /// # Errors
+    /// [`ErrorKind::InvalidArgument`] if `capacity` is not a power of two
+    /// in 2..=2^`RING_BUFFER_LOG2_MAX`, as khires requires (or, on the
+    /// `stub` backend, too large to allocate). Also fails if another
+    /// process has the ring mapped with a different capacity, or if the
+    /// module cannot allocate it. The perf fallback sizes its own per-CPU
+    /// buffers and ignores `capacity`.
+    pub fn connect_with_capacity(
+        device_path: Option<&Path>,
+        capacity: u64,
+    ) -> Result<Self, HiResError> {
+        if capacity < 2 || !capacity.is_power_of_two() || capacity > 1 << ffi::RING_BUFFER_LOG2_MAX
+        {
+            return Err(HiResError {
+                kind: ErrorKind::InvalidArgument,
+                message: format!(
+                    "Requested ring capacity {} is not a power of two in 2..2^{}",
+                    capacity,
+                    ffi::RING_BUFFER_LOG2_MAX
+                ),
+            });
+        }
+        #[cfg(feature = "stub")]
+        if stub::ring_size(capacity).is_none() {
+            return Err(HiResError {
+                kind: ErrorKind::InvalidArgument,
+                message: format!(
+                    "Requested ring capacity {} is too large for the in-memory ring",
+                    capacity
+                ),
+            });
+        }
+        Self::open(device_path, capacity)
+    }
+
+    /// `capacity` 0 keeps the ring's current capacity.
+    fn open(device_path: Option<&Path>, capacity: u64) -> Result<Self, HiResError> {
+        let session = SessionAnchor::now();
+        if cfg!(feature = "disabled") {
+            return Ok(Self::detached(session));
+        }
         let path_cstr = device_path
             .map(|p| CString::new(p.to_string_lossy().as_bytes()))
             .transpose()
             .map_err(|e| HiResError {
+                kind: ErrorKind::Runtime,
                 message: format!("Invalid device path: {}", e),
             })?;
 
+        let device = device_path.unwrap_or(Path::new("/dev/khires"));
+        if !cfg!(feature = "stub") && !device.exists() {
+            let perf = perf::PerfRing::open().map_err(|e| HiResError {
+                kind: ErrorKind::Runtime,
+                message: format!(
+                    "{} does not exist (is khires loaded?) and the perf fallback failed: {}",
+                    device.display(),
+                    e
+                ),
+            })?;
+            instant::adopt_tsc_hz(perf.tsc_hz());
+            return Ok(HiResConn {
+                handle: ptr::null_mut(),
+                cycle_per_us: AlignedU64(perf.tsc_hz() / 1_000_000),
+                tsc_hz: perf.tsc_hz(),
+                perf: Some(perf),
+                generation: 0,
+                entry_size: 0,
+                session,
+                shm: None,
+                drops_epoch: AtomicU64::new(0),
+                torn_checks: AtomicBool::new(false),
+                torn: AtomicU64::new(0),
+                _marker: PhantomData,
+            });
+        }
+
+        ffi::load().map_err(|message| HiResError {
+            kind: ErrorKind::LibraryNotFound,
+            message,
+        })?;
+        abi::check_version()?;
+
         let c_path_ptr = path_cstr.as_ref().map_or(ptr::null(), |cs| cs.as_ptr());
 
-        let handle = unsafe { ffi::hires_connect(c_path_ptr) };
+        let handle = if capacity == 0 {
+            unsafe { ffi::hires_connect(c_path_ptr) }
+        } else {
+            unsafe { ffi::hires_connect_with_capacity(c_path_ptr, capacity) }
+        };
         if handle.is_null() {
             check_error()?; // Check error if handle is null
             // If check_error didn't return Err, something unexpected happened
             Err(HiResError {
+                kind: ErrorKind::Runtime,
                 message: "profiler_connect returned null without setting error".to_string(),
             })
         } else {
             let cycle_per_us = unsafe { ffi::hires_get_cycles_per_us(handle) };
-            Ok(HiResConn {
+            let buf = unsafe { ffi::hires_get_buffer(handle) };
+            let generation = unsafe { abi::generation(buf) };
+            let mut conn = HiResConn {
                 handle,
+                perf: None,
                 cycle_per_us: AlignedU64(cycle_per_us),
+                tsc_hz: unsafe { ffi::hires_get_tsc_hz(handle) },
+                generation,
+                entry_size: unsafe { abi::entry_size(buf) },
+                session,
+                shm: None,
+                drops_epoch: AtomicU64::new(0),
+                torn_checks: AtomicBool::new(false),
+                torn: AtomicU64::new(0),
                 _marker: PhantomData,
-            })
+            };
+            conn.check_seal()?;
+            conn.check_layout()?;
+            instant::adopt_tsc_hz(conn.tsc_hz);
+            conn.shm = Some(unsafe {
+                shm::Shm::new(
+                    buf,
+                    conn.get_rb_capacity(),
+                    conn.get_rb_idx_mask(),
+                    conn.entry_layout(),
+                )
+            });
+            *conn.drops_epoch.get_mut() = conn.get_drop_num();
+            Ok(conn)
+        }
+    }
+
+    /// The clocks read when this connection was made.
+    pub fn session_anchor(&self) -> SessionAnchor {
+        self.session
+    }
+
+    /// Wall time since this connection was made, on the monotonic clock.
+    pub fn session_elapsed(&self) -> Duration {
+        self.session.monotonic.elapsed()
+    }
+
+    /// Which transport this connection uses.
+    pub fn backend(&self) -> Backend {
+        if self.perf.is_some() {
+            Backend::Perf
+        } else {
+            Backend::Khires
+        }
+    }
+
+    /// Checks the bindings' struct layout against libhires_rt and against
+    /// the ring khires mapped, including its entry layout. A mismatch means
+    /// rt_ffi (possibly the checked-in bindings) is out of date with
+    /// shared/common.h.
+    fn check_layout(&self) -> Result<(), HiResError> {
+        abi::check_library()?;
+        let layout = unsafe { abi::check_entry_layout(self.get_raw_buffer()) }?;
+        abi::check_ring(self.get_rb_capacity(), self.get_shm_size(), layout)
+    }
+
+    /// The layout of the ring's entries, chosen when khires was loaded.
+    /// Always `Standard` on the perf fallback.
+    pub fn entry_layout(&self) -> EntryLayout {
+        if self.handle.is_null() {
+            return EntryLayout::Standard;
+        }
+        // Checked at connect.
+        EntryLayout::from_id(unsafe { ffi::hires_get_entry_layout(self.handle) })
+            .unwrap_or(EntryLayout::Standard)
+    }
+
+    /// Checks the seal khires keeps in the ring header: its magic, the
+    /// layout checksum of the geometry this connection mapped, and the
+    /// generation seen at connect. `connect()` refuses a ring that fails it;
+    /// consumers call it now and then to stop cleanly when the ring is reset
+    /// or khires is unloaded under them, instead of reading garbage.
+    ///
+    /// Always `Ok` on the perf fallback, which has no shared header.
+    pub fn check_seal(&self) -> Result<(), HiResError> {
+        if self.handle.is_null() {
+            return Ok(());
+        }
+        unsafe {
+            abi::check_seal(
+                self.get_raw_buffer(),
+                self.entry_size,
+                self.get_rb_capacity(),
+                self.get_rb_idx_mask(),
+                self.get_shm_size(),
+                self.generation,
+            )
         }
     }
 
@@ -105,31 +516,195 @@ impl<'a> HiResConn<'a> {
     /// # Returns
     /// `true` if the event was logged successfully.
     /// `false` if the buffer was full and the event was dropped.
+    /// Always `true` with the `disabled` feature, which logs nothing.
     #[inline]
     pub fn log(&self, event_id: u32, data1: u64, data2: u64) -> bool {
+        if cfg!(feature = "disabled") {
+            return true;
+        }
         if self.handle.is_null() {
-            return false;
-        } // Should not happen with RAII wrapper
+            return self.perf.as_ref().is_some_and(|p| p.log(event_id, data1, data2));
+        }
         unsafe { ffi::hires_log(self.handle, event_id, data1, data2) }
         // Note: We don't check error here, as false return indicates buffer full, not API error.
     }
 
+    /// [`log`](Self::log) with the timestamp taken from `source` instead of
+    /// the connection's timestamp source, to pay for an ordered TSC read
+    /// only around the operations that need one. `Kvmclock` is not checked
+    /// against the active clocksource here.
+    ///
+    /// The perf fallback stamps entries itself and ignores `source`.
+    #[inline]
+    pub fn log_with_source(&self, source: TimestampSource, event_id: u32, data1: u64, data2: u64) -> bool {
+        if cfg!(feature = "disabled") {
+            return true;
+        }
+        if self.handle.is_null() {
+            return self.perf.as_ref().is_some_and(|p| p.log(event_id, data1, data2));
+        }
+        unsafe { ffi::hires_log_with_source(self.handle, source.raw(), event_id, data1, data2) }
+    }
+
+    /// Logs `(event_id, data1, data2)` events as one contiguous run of
+    /// entries: one CAS reserves the slots and one release fence publishes
+    /// them, instead of an atomic add and a release store per entry. The
+    /// entries share one timestamp and CPU ID.
+    ///
+    /// # Returns
+    /// `true` if all were logged, `false` if the ring had no room for the
+    /// whole batch, which is then dropped whole. On the perf fallback the
+    /// events are logged one by one, and `false` means any was dropped.
+    pub fn log_batch(&self, events: &[(u32, u64, u64)]) -> bool {
+        if cfg!(feature = "disabled") || events.is_empty() {
+            return true;
+        }
+        if self.handle.is_null() {
+            let Some(perf) = &self.perf else {
+                return false;
+            };
+            return events
+                .iter()
+                .fold(true, |ok, &(id, d1, d2)| perf.log(id, d1, d2) && ok);
+        }
+        // The C API takes its own struct; batches are small, so convert on
+        // the stack when they fit.
+        const STACK_BATCH: usize = 32;
+        let entry = |&(event_id, data1, data2): &(u32, u64, u64)| hires_batch_entry_t {
+            event_id,
+            reserved: 0,
+            data1,
+            data2,
+        };
+        let mut stack = [hires_batch_entry_t::default(); STACK_BATCH];
+        let heap: Vec<hires_batch_entry_t>;
+        let batch = if events.len() <= STACK_BATCH {
+            for (slot, e) in stack.iter_mut().zip(events) {
+                *slot = entry(e);
+            }
+            &stack[..events.len()]
+        } else {
+            heap = events.iter().map(entry).collect();
+            &heap[..]
+        };
+        unsafe { ffi::hires_log_batch(self.handle, batch.as_ptr(), batch.len()) }
+    }
+
+    /// Removes and returns the oldest entry. The ring is read here rather
+    /// than through libhires_rt, with the orderings documented in `shm`.
     #[inline]
     pub fn pop(&self) -> Option<log_entry_t> {
+        let Some(ring) = &self.shm else {
+            return self.perf.as_ref().and_then(|p| p.pop());
+        };
+        self.take(ring).map(shm::to_log_entry)
+    }
+
+    /// `pop()` returning every field of the ring's entry layout. `tid` is 0
+    /// unless the layout is [`EntryLayout::Extended`]; `seqno` is the entry's
+    /// position in the ring for every layout, and 0 on the perf fallback.
+    #[inline]
+    pub fn pop_ext(&self) -> Option<log_entry_ext_t> {
+        let Some(ring) = &self.shm else {
+            return self.perf.as_ref().and_then(|p| p.pop()).map(|e| log_entry_ext_t {
+                timestamp: e.timestamp,
+                event_id: e.event_id,
+                cpu_id: e.cpu_id,
+                flags: e.flags,
+                data1: e.data1,
+                data2: e.data2,
+                ..Default::default()
+            });
+        };
+        self.take(ring)
+    }
+
+    /// Consumes the entry at the tail, skipping torn ones when checks are on.
+    #[inline]
+    fn take(&self, ring: &shm::Shm) -> Option<log_entry_ext_t> {
+        if !self.torn_checks.load(Ordering::Relaxed) {
+            return shm::read_tail(ring, true, shm::MAX_SPINS);
+        }
+        loop {
+            match shm::pop_checked(ring, shm::MAX_SPINS)? {
+                Ok(entry) => return Some(entry),
+                Err(shm::Torn) => self.torn.fetch_add(1, Ordering::Relaxed),
+            };
+        }
+    }
+
+    /// Makes `pop()` and `pop_ext()` re-check each entry's slot after
+    /// copying it and discard the entry if a producer rewrote the slot
+    /// meanwhile, instead of returning fields of two different entries.
+    /// Only a producer breaking the protocol (an interrupted or runaway
+    /// writer lapping the consumer) causes that. Off by default; the check
+    /// costs a fence and one or two loads per entry.
+    ///
+    /// On the extended layout the entry's sequence number is checked as
+    /// well as its flags, which catches far more; on the other layouts
+    /// only a changed flag word shows. Peeks are not checked, and the perf
+    /// fallback, whose ring the kernel writes, has nothing to check.
+    pub fn set_torn_checks(&self, on: bool) {
+        self.torn_checks.store(on, Ordering::Relaxed);
+    }
+
+    /// Entries discarded as torn since connect (see
+    /// [`set_torn_checks`](Self::set_torn_checks)).
+    pub fn torn_reads(&self) -> u64 {
+        self.torn.load(Ordering::Relaxed)
+    }
+
+    /// Returns the entry `pop()` would return next, leaving it in the ring.
+    #[inline]
+    pub fn peek(&self) -> Option<log_entry_t> {
+        let Some(ring) = &self.shm else {
+            return self.perf.as_ref().and_then(|p| p.peek());
+        };
+        shm::read_tail(ring, false, shm::MAX_SPINS).map(shm::to_log_entry)
+    }
+
+    /// Appends up to `max` of the entries `pop()` would return next onto
+    /// `out`, oldest first, leaving them in the ring, and returns how many
+    /// were added. Like `peek()` it stops at an entry still being written,
+    /// so look-ahead (e.g. holding a pair's start event until its end event
+    /// is in the ring) only sees complete entries.
+    pub fn peek_n(&self, out: &mut Vec<log_entry_t>, max: usize) -> usize {
+        let Some(ring) = &self.shm else {
+            return self.perf.as_ref().map_or(0, |p| p.peek_n(out, max));
+        };
+        shm::read_ahead(ring, max, shm::MAX_SPINS, |entry| {
+            out.push(shm::to_log_entry(entry))
+        })
+    }
+
+    /// Full fence after this thread's `log()` calls: every entry logged so
+    /// far is ordered before any later memory operation, e.g. signalling a
+    /// consumer in another process to drain the ring now.
+    #[inline]
+    pub fn flush(&self) {
         if self.handle.is_null() {
-            return None;
+            return;
+        }
+        unsafe { ffi::hires_flush(self.handle) }
+    }
+
+    /// Snapshot of the ring's head/tail indexes, pending entries and drops.
+    pub fn get_stats(&self) -> hires_rb_stats_t {
+        let mut stats = hires_rb_stats_t::default();
+        if let Some(ring) = &self.shm {
+            stats = shm::stats(ring);
+        } else if let Some(perf) = &self.perf {
+            stats = perf.stats();
         }
-        let mut entry = log_entry_t::default();
-        let result = unsafe { ffi::hires_pop(self.handle, &mut entry) };
-        if result { Some(entry) } else { None }
+        stats
     }
 
     #[inline]
     pub fn get_rb_capacity(&self) -> u64 {
         if self.handle.is_null() {
-            return 0;
+            return self.perf.as_ref().map_or(0, |p| p.capacity());
         }
-        return unsafe { ffi::hires_get_rb_capacity(self.handle) as u64 };
+        return unsafe { ffi::hires_get_rb_capacity(self.handle) };
     }
 
     #[inline]
@@ -137,15 +712,31 @@ impl<'a> HiResConn<'a> {
         if self.handle.is_null() {
             return 0;
         }
-        return unsafe { ffi::hires_get_rb_idx_mask(self.handle) as u64 };
+        return unsafe { ffi::hires_get_rb_idx_mask(self.handle) };
     }
     
     #[inline]
     pub fn get_drop_num(&self) -> u64 {
-        if self.handle.is_null() {
-            return 0;
-        }
-        return unsafe { ffi::hires_get_drop_num(self.handle) as u64 };
+        let Some(ring) = &self.shm else {
+            return self.perf.as_ref().map_or(0, |p| p.drop_num());
+        };
+        shm::dropped(ring)
+    }
+
+    /// Entries dropped since the previous call, or since connect for the
+    /// first. Each drop is returned by exactly one call, also when several
+    /// threads share the connection, so interval reports need not keep
+    /// their own copy of [`get_drop_num`](Self::get_drop_num) to subtract.
+    ///
+    /// Observers needing independent intervals should each take their own
+    /// connection; on one connection the deltas are shared.
+    pub fn take_drops(&self) -> u64 {
+        let now = self.get_drop_num();
+        // The count only grows, so advancing the epoch with fetch_max hands
+        // each drop to one caller even if a slower caller read an older
+        // count: that caller gets 0 rather than moving the epoch back.
+        let last = self.drops_epoch.fetch_max(now, Ordering::AcqRel);
+        now.saturating_sub(last)
     }
 
     /// Gets a raw pointer to the underlying shared memory buffer structure.
@@ -170,22 +761,119 @@ impl<'a> HiResConn<'a> {
         if self.handle.is_null() {
             return 0;
         }
-        unsafe { ffi::hires_get_shm_size(self.handle) as u64 }
+        unsafe { ffi::hires_get_shm_size(self.handle) }
     }
     
     #[inline]
     pub fn get_cycles_per_us(&self) -> u64 {
         return *self.cycle_per_us;
     }
+
+    /// Calibrated TSC frequency in Hz, for converting cycles to time without
+    /// the rounding of `get_cycles_per_us`.
+    #[inline]
+    pub fn get_tsc_hz(&self) -> u64 {
+        if self.handle.is_null() {
+            return self.perf.as_ref().map_or(0, |p| p.tsc_hz());
+        }
+        unsafe { ffi::hires_get_tsc_hz(self.handle) }
+    }
+
+    /// The TSC now, as a start point for [`elapsed_ns`](Self::elapsed_ns).
+    /// Constant 0 with the `disabled` feature.
+    #[inline]
+    pub fn now_cycles(&self) -> u64 {
+        rdtsc()
+    }
+
+    /// `cycles` in nanoseconds at the calibrated TSC frequency; see
+    /// [`cycles_to_ns`].
+    #[inline]
+    pub fn cycles_to_ns(&self, cycles: u64) -> u64 {
+        cycles_to_ns(cycles, self.tsc_hz)
+    }
+
+    /// Nanoseconds since `start_cycles`, a TSC reading from
+    /// [`now_cycles`](Self::now_cycles) (or `Token::start`).
+    #[inline]
+    pub fn elapsed_ns(&self, start_cycles: u64) -> u64 {
+        self.cycles_to_ns(rdtsc().wrapping_sub(start_cycles))
+    }
+
+    /// Microseconds since `start_cycles`, with the fraction kept; computed
+    /// from `elapsed_ns`, not from the rounded `get_cycles_per_us`.
+    #[inline]
+    pub fn elapsed_us(&self, start_cycles: u64) -> f64 {
+        self.elapsed_ns(start_cycles) as f64 / 1e3
+    }
+
+    /// How the kernel module obtained its TSC frequency (timing loop vs
+    /// SecureTSC), or `None` with modules that do not report it.
+    pub fn get_tsc_info(&self) -> Option<hires_tsc_info_t> {
+        if self.handle.is_null() {
+            return None;
+        }
+        let mut info = hires_tsc_info_t::default();
+        let ok = unsafe { ffi::hires_get_tsc_info(self.handle, &mut info) };
+        if ok { Some(info) } else { None }
+    }
+
+    /// Selects the timestamp source for subsequent `log()` calls. Fails if
+    /// the source is not usable on this system.
+    pub fn set_timestamp_source(&self, source: TimestampSource) -> Result<(), HiResError> {
+        if cfg!(feature = "disabled") {
+            return Ok(());
+        }
+        if self.handle.is_null() {
+            // perf stamps entries with the kernel's CLOCK_MONOTONIC.
+            if self.perf.is_some() && source == TimestampSource::Monotonic {
+                return Ok(());
+            }
+            return Err(HiResError {
+                kind: ErrorKind::Runtime,
+                message: if self.perf.is_some() {
+                    format!("Timestamp source {:?} is not available with the perf backend", source)
+                } else {
+                    "Connection is closed".to_string()
+                },
+            });
+        }
+        if unsafe { ffi::hires_set_timestamp_source(self.handle, source.raw()) } {
+            Ok(())
+        } else {
+            check_error()
+        }
+    }
+}
+
+/// `cycles * 1e9 / tsc_hz` in 128-bit arithmetic, so neither the multiply
+/// overflows nor the frequency gets rounded to whole cycles per microsecond.
+/// Saturates at `u64::MAX` for rates below 1 GHz; without calibration
+/// (`tsc_hz` 0) the count is passed through unchanged.
+#[inline]
+pub fn cycles_to_ns(cycles: u64, tsc_hz: u64) -> u64 {
+    if tsc_hz == 0 {
+        return cycles;
+    }
+    let ns = (cycles as u128 * 1_000_000_000) / tsc_hz as u128;
+    ns.min(u64::MAX as u128) as u64
 }
 
+// Constant with `disabled`, so call sites timing an operation for log()
+// optimize away along with it.
 #[inline]
 fn rdtsc() -> u64 {
+    if cfg!(feature = "disabled") {
+        return 0;
+    }
     unsafe { ffi::hires_rdtsc() }
 }
 
 #[inline]
 fn rdtscp() -> (u64, u32) {
+    if cfg!(feature = "disabled") {
+        return (0, 0);
+    }
     let mut cpu_id: u32 = 0;
     let ts = unsafe { ffi::hires_rdtscp(&mut cpu_id as *mut u32) };
     return (ts, cpu_id as u32);
@@ -206,3 +894,30 @@ impl<'a> Drop for HiResConn<'a> {
 // and operations like log() are atomic w.r.t the shared buffer, it should be safe.
 unsafe impl<'a> Send for HiResConn<'a> {}
 unsafe impl<'a> Sync for HiResConn<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connect_with_capacity_checks_khires_bounds() {
+        for capacity in [0, 1, 3, 6, (1 << ffi::RING_BUFFER_LOG2_MAX) + 1, 1 << 40] {
+            let err = HiResConn::connect_with_capacity(None, capacity)
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidArgument, "{}", capacity);
+        }
+    }
+
+    #[test]
+    fn cycles_to_ns_saturates_and_passes_through_uncalibrated() {
+        let mut conn = HiResConn::detached(SessionAnchor::now());
+        assert_eq!(conn.cycles_to_ns(12345), 12345);
+        conn.tsc_hz = 3_000_000_000;
+        assert_eq!(conn.cycles_to_ns(3_000_000_000), 1_000_000_000);
+        assert_eq!(conn.cycles_to_ns(u64::MAX), u64::MAX / 3);
+        // Below 1 GHz a large count no longer fits in nanoseconds.
+        conn.tsc_hz = 1;
+        assert_eq!(conn.cycles_to_ns(u64::MAX), u64::MAX);
+    }
+}
@@ -0,0 +1,278 @@
+//! The ring protocol in plain Rust, for model checking.
+//!
+//! [`MockRing`] follows rt.cpp's producer step for step (reserve by
+//! `fetch_add` on head, publish with a release store of the VALID flag)
+//! with Rust atomics instead of `std::atomic_ref` on shared memory, and its
+//! consumer is `shm::read_tail` itself, the code `HiResConn` drains the
+//! mapped ring with. It has no FFI or raw memory, so it runs under miri,
+//! and built with `RUSTFLAGS="--cfg loom"` it uses loom's atomics and
+//! cells, so a loom model with a few producer threads and a consumer
+//! (rt/tests/loom_ring.rs) explores every interleaving the orderings allow.
+//!
+//! Entries are stamped with their reservation sequence number rather than
+//! a clock, which keeps models deterministic and makes ring order visible.
+//!
+//! The protocol is copied as is, including its behaviour when full: a
+//! dropped `log()` has already advanced head, so its sequence number is
+//! never published and the consumer stops at that slot. A model that logs
+//! past capacity will show exactly that.
+
+use crate::ring::{Consumer, Producer};
+use crate::shm::{self, RingState};
+use crate::{LOG_FLAG_VALID, log_entry_t};
+
+#[cfg(loom)]
+use loom::cell::UnsafeCell;
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+#[cfg(not(loom))]
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+
+/// `std` stand-in for loom's `UnsafeCell`, which only hands out its
+/// pointer through closures so loom can track the accesses.
+#[cfg(not(loom))]
+struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    fn new(value: T) -> Self {
+        UnsafeCell(std::cell::UnsafeCell::new(value))
+    }
+
+    fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+        f(self.0.get())
+    }
+
+    fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+}
+
+struct Slot {
+    /// Written by the producer that reserved the slot, read by the consumer
+    /// once `flags` has VALID.
+    entry: UnsafeCell<log_entry_t>,
+    flags: AtomicU16,
+    /// The head index the slot was last written for, as the extended
+    /// layout's `seqno`.
+    seqno: AtomicU64,
+}
+
+pub struct MockRing {
+    head: AtomicU64,
+    tail: AtomicU64,
+    dropped: AtomicU64,
+    slots: Box<[Slot]>,
+    /// Entries `pop_checked` discarded.
+    torn: AtomicU64,
+    /// Run on each slot position right after its entry is copied out, to
+    /// play a producer that writes the slot mid-copy.
+    #[cfg(test)]
+    during_copy: Option<fn(&MockRing, u64)>,
+}
+
+// Slots are handed between threads by the head/tail/flags protocol.
+unsafe impl Sync for MockRing {}
+
+impl MockRing {
+    /// A ring of `capacity` entries, a power of two like khires's. Small
+    /// rings (2 or 4) keep loom models tractable while still wrapping.
+    pub fn new(capacity: usize) -> Self {
+        assert!(
+            capacity.is_power_of_two(),
+            "capacity must be a power of two"
+        );
+        MockRing {
+            head: AtomicU64::new(0),
+            tail: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            slots: (0..capacity)
+                .map(|_| Slot {
+                    entry: UnsafeCell::new(log_entry_t::default()),
+                    flags: AtomicU16::new(0),
+                    seqno: AtomicU64::new(0),
+                })
+                .collect(),
+            torn: AtomicU64::new(0),
+            #[cfg(test)]
+            during_copy: None,
+        }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.slots.len() as u64
+    }
+
+    fn slot(&self, index: u64) -> &Slot {
+        &self.slots[(index & (self.capacity() - 1)) as usize]
+    }
+
+    /// `pop()` through `shm::pop_checked`, as `HiResConn` pops with torn
+    /// read checks on: torn entries are skipped and counted.
+    pub fn pop_checked(&self) -> Option<log_entry_t> {
+        loop {
+            match shm::pop_checked(self, 0)? {
+                Ok(entry) => return Some(entry),
+                Err(shm::Torn) => self.torn.fetch_add(1, Ordering::Relaxed),
+            };
+        }
+    }
+
+    /// Entries `pop_checked` discarded as torn.
+    pub fn torn_reads(&self) -> u64 {
+        self.torn.load(Ordering::Relaxed)
+    }
+}
+
+impl Producer for MockRing {
+    fn log(&self, event_id: u32, data1: u64, data2: u64) -> bool {
+        let head = self.head.fetch_add(1, Ordering::AcqRel);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head - tail >= self.capacity() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let slot = self.slot(head);
+        slot.entry.with_mut(|e| unsafe {
+            *e = log_entry_t {
+                timestamp: head,
+                event_id,
+                cpu_id: 0,
+                flags: 0,
+                data1,
+                data2,
+            };
+        });
+        slot.seqno.store(head, Ordering::Relaxed);
+        slot.flags.store(LOG_FLAG_VALID as u16, Ordering::Release);
+        true
+    }
+}
+
+impl RingState for MockRing {
+    type Entry = log_entry_t;
+
+    fn head(&self, order: Ordering) -> u64 {
+        self.head.load(order)
+    }
+
+    fn tail(&self, order: Ordering) -> u64 {
+        self.tail.load(order)
+    }
+
+    fn set_tail(&self, tail: u64, order: Ordering) {
+        self.tail.store(tail, order)
+    }
+
+    fn dropped(&self, order: Ordering) -> u64 {
+        self.dropped.load(order)
+    }
+
+    fn capacity(&self) -> u64 {
+        MockRing::capacity(self)
+    }
+
+    fn flags(&self, pos: u64, order: Ordering) -> u16 {
+        self.slot(pos).flags.load(order)
+    }
+
+    fn set_flags(&self, pos: u64, flags: u16, order: Ordering) {
+        self.slot(pos).flags.store(flags, order)
+    }
+
+    fn seqno(&self, pos: u64, order: Ordering) -> Option<u64> {
+        Some(self.slot(pos).seqno.load(order))
+    }
+
+    unsafe fn read(&self, pos: u64) -> log_entry_t {
+        let entry = self.slot(pos).entry.with(|e| unsafe { *e });
+        #[cfg(test)]
+        if let Some(write) = self.during_copy {
+            write(self, pos);
+        }
+        entry
+    }
+}
+
+/// Without the spin: an entry whose producer has not set VALID yet reads
+/// as not ready, which keeps loom models finite.
+impl Consumer for MockRing {
+    fn pop(&self) -> Option<log_entry_t> {
+        shm::read_tail(self, true, 0)
+    }
+
+    fn peek(&self) -> Option<log_entry_t> {
+        shm::read_tail(self, false, 0)
+    }
+
+    fn peek_n(&self, out: &mut Vec<log_entry_t>, max: usize) -> usize {
+        shm::read_ahead(self, max, 0, |entry| out.push(entry))
+    }
+
+    fn dropped(&self) -> u64 {
+        shm::dropped(self)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use crate::LOG_FLAG_KERNEL;
+
+    /// A ring holding entries 0..n, with `during_copy` writing slot 0.
+    fn ring(n: u64, during_copy: fn(&MockRing, u64)) -> MockRing {
+        let mut ring = MockRing::new(4);
+        for id in 0..n {
+            assert!(ring.log(1, id, !id));
+        }
+        ring.during_copy = Some(during_copy);
+        ring
+    }
+
+    #[test]
+    fn checked_pop_discards_entry_whose_seqno_changed_mid_copy() {
+        // A producer a lap ahead reserving the slot.
+        let ring = ring(2, |ring, pos| {
+            if pos == 0 {
+                ring.slot(pos).seqno.store(pos + 4, Ordering::Relaxed);
+            }
+        });
+        let entry = ring.pop_checked().unwrap();
+        assert_eq!(entry.data1, 1);
+        assert_eq!(ring.torn_reads(), 1);
+        assert!(ring.pop_checked().is_none());
+        assert_eq!(ring.torn_reads(), 1);
+    }
+
+    #[test]
+    fn checked_pop_discards_entry_whose_flags_changed_mid_copy() {
+        // A producer republishing the slot with other flags.
+        let ring = ring(1, |ring, pos| {
+            let flags = (LOG_FLAG_VALID | LOG_FLAG_KERNEL) as u16;
+            ring.slot(pos).flags.store(flags, Ordering::Release);
+        });
+        assert!(ring.pop_checked().is_none());
+        assert_eq!(ring.torn_reads(), 1);
+        // Consumed all the same.
+        assert!(ring.peek().is_none());
+    }
+
+    #[test]
+    fn unchecked_pop_returns_torn_entries() {
+        let ring = ring(1, |ring, pos| {
+            ring.slot(pos).seqno.store(pos + 4, Ordering::Relaxed);
+        });
+        assert_eq!(ring.pop().unwrap().data1, 0);
+        assert_eq!(ring.torn_reads(), 0);
+    }
+
+    #[test]
+    fn checked_pop_keeps_untouched_entries() {
+        let ring = ring(3, |_, _| {});
+        let seen: Vec<u64> = std::iter::from_fn(|| ring.pop_checked())
+            .map(|e| e.data1)
+            .collect();
+        assert_eq!(seen, [0, 1, 2]);
+        assert_eq!(ring.torn_reads(), 0);
+    }
+}
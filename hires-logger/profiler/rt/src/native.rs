@@ -0,0 +1,642 @@
+//! The `hires_*` C API implemented in Rust (the `native` and `stub`
+//! features).
+//!
+//! With `native`, connecting opens the device node, asks khires for the
+//! ring's geometry and TSC frequency with the HIRES_IOCTL_* requests and
+//! maps the ring here, so libhires_rt is neither linked nor loaded and
+//! `log()` is a handful of atomics that inline into the caller instead of a
+//! call into a shared library. With `stub` the ring is a heap allocation
+//! instead, see `stub`. `lib.rs` uses this module in place of rt_ffi;
+//! `HiResConn` and everything built on it work unchanged.
+//!
+//! The producer/consumer protocol is the one in rt.cpp, and error messages
+//! match rt_c.cpp's.
+
+// The whole C API is here, including functions HiResConn does not call yet,
+// so none of them falls through to rt_ffi's.
+#![allow(dead_code)]
+
+pub use rt_ffi::*;
+
+use crate::EntryLayout;
+use crate::clock::read_tsc;
+use crate::shm::{self, Shm};
+use crate::thread::current_tid;
+use std::cell::RefCell;
+use std::ffi::{CString, c_char};
+use std::fs;
+use std::mem::{offset_of, size_of};
+use std::os::fd::OwnedFd;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering, fence};
+
+/// A mapped ring and what khires reported about it.
+pub(crate) struct Ring {
+    pub(crate) buf: *mut shared_ring_buffer_t,
+    pub(crate) capacity: u64,
+    pub(crate) mask: u64,
+    pub(crate) shm_size: u64,
+    pub(crate) cycles_per_us: u64,
+    pub(crate) tsc_hz: u64,
+    pub(crate) tsc_info: Option<hires_tsc_info_t>,
+    /// How the ring's entries are laid out. Entries are sized and accessed
+    /// through this build's structs for it, never through the sizes in the
+    /// header.
+    pub(crate) layout: EntryLayout,
+    /// The device the ring is mapped from, unmapped and closed on drop.
+    /// `None` for the stub's ring, which lives as long as the process.
+    pub(crate) device: Option<OwnedFd>,
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        if self.device.is_some() {
+            unsafe { libc::munmap(self.buf.cast(), self.shm_size as usize) };
+        }
+    }
+}
+
+struct Conn {
+    ring: Ring,
+    shm: Shm,
+    ts_source: AtomicU32,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(msg: &str) {
+    LAST_ERROR.with(|e| {
+        *e.borrow_mut() = (!msg.is_empty()).then(|| CString::new(msg).unwrap_or_default())
+    });
+}
+
+/// Opens `path` (or /dev/khires), sizes its ring to `capacity` entries
+/// unless 0 and maps it, like rt.cpp's `HiResConn` constructor.
+#[cfg(not(feature = "stub"))]
+fn map_device(path: *const c_char, capacity: u64) -> Result<Ring, String> {
+    use std::ffi::CStr;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    let path = if path.is_null() {
+        c"/dev/khires"
+    } else {
+        unsafe { CStr::from_ptr(path) }
+    };
+    let name = path.to_string_lossy();
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(format!(
+            "Failed to open device '{}': {}",
+            name,
+            io::Error::last_os_error()
+        ));
+    }
+    let device = unsafe { OwnedFd::from_raw_fd(fd) };
+    let ioctl = |request: u32, arg: *mut libc::c_void| unsafe {
+        libc::ioctl(device.as_raw_fd(), request as _, arg) >= 0
+    };
+
+    if capacity != 0 {
+        let mut requested = capacity;
+        if !ioctl(HIRES_IOCTL_SET_RB_CAPACITY, (&raw mut requested).cast()) {
+            return Err(format!(
+                "Failed to set the ring capacity of device '{}' to {}: {}",
+                name,
+                capacity,
+                io::Error::last_os_error()
+            ));
+        }
+    }
+
+    let mut meta = hires_rb_meta_t::default();
+    if !ioctl(HIRES_IOCTL_GET_RB_META, (&raw mut meta).cast()) {
+        return Err(format!(
+            "Failed to get ring buffer metadata from device '{}'",
+            name
+        ));
+    }
+    let mut cycles_per_us = 0u64;
+    if !ioctl(
+        HIRES_IOCTL_GET_TSC_CYCLE_PER_US,
+        (&raw mut cycles_per_us).cast(),
+    ) {
+        cycles_per_us = 0;
+    }
+    let mut info = hires_tsc_info_t::default();
+    let tsc_info = ioctl(HIRES_IOCTL_GET_TSC_INFO, (&raw mut info).cast()).then_some(info);
+    let mut tsc_hz = 0u64;
+    match tsc_info {
+        Some(info) => tsc_hz = info.tsc_hz,
+        None => {
+            ioctl(HIRES_IOCTL_GET_TSC_HZ, (&raw mut tsc_hz).cast());
+        }
+    }
+    if tsc_hz == 0 {
+        // Older modules only report the rounded cycles/us rate.
+        tsc_hz = cycles_per_us * 1_000_000;
+    }
+
+    let shm_size = meta.shm_size_bytes_unaligned;
+    // The header's sizes are 64-bit; map all of the ring or nothing.
+    let Ok(map_len) = usize::try_from(shm_size) else {
+        return Err(format!(
+            "Ring buffer of device '{}' ({} bytes) does not fit in this process's address space",
+            name, shm_size
+        ));
+    };
+    let buf = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            map_len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED | libc::MAP_POPULATE,
+            device.as_raw_fd(),
+            0,
+        )
+    };
+    if buf == libc::MAP_FAILED {
+        return Err(format!(
+            "Failed to mmap device '{}': {}",
+            name,
+            io::Error::last_os_error()
+        ));
+    }
+    let buf = buf.cast::<shared_ring_buffer_t>();
+    // An unknown layout fails the connect's layout check before any entry
+    // is touched.
+    let layout = EntryLayout::from_id(unsafe { (*buf).entry_layout.id } as u32)
+        .unwrap_or(EntryLayout::Standard);
+    Ok(Ring {
+        buf,
+        capacity: meta.capacity,
+        mask: meta.idx_mask,
+        shm_size,
+        cycles_per_us,
+        tsc_hz,
+        tsc_info,
+        layout,
+        device: Some(device),
+    })
+}
+
+fn clock_ns(clock: libc::clockid_t) -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(clock, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+fn current_cpu() -> u32 {
+    let cpu = unsafe { libc::sched_getcpu() };
+    if cpu < 0 { 0xFFFF } else { cpu as u32 }
+}
+
+/// `rdtscp`: the TSC once preceding instructions have completed, and the
+/// CPU it was read on.
+fn read_tscp() -> (u64, u32) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        let mut aux = 0;
+        let tsc = unsafe { core::arch::x86_64::__rdtscp(&mut aux) };
+        // Linux sets TSC_AUX to (node << 12) | cpu.
+        (tsc, aux & 0xfff)
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        (read_tsc(), current_cpu())
+    }
+}
+
+/// `lfence; rdtsc`: the TSC once preceding instructions have completed.
+fn read_tsc_lfence() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        core::arch::x86_64::_mm_lfence();
+        core::arch::x86_64::_rdtsc()
+    }
+    // read_tsc() already waits (isb) on aarch64.
+    #[cfg(not(target_arch = "x86_64"))]
+    read_tsc()
+}
+
+/// rt.cpp's `read_timestamp()`: a timestamp from `source`, the flags that
+/// go with it and the CPU the caller runs on.
+fn stamp(source: u32) -> (u64, u16, u32) {
+    let tsc = LOG_FLAG_TSC as u16;
+    match source {
+        HIRES_TS_MONOTONIC => (clock_ns(libc::CLOCK_MONOTONIC), 0, current_cpu()),
+        HIRES_TS_RDTSC => (read_tsc(), tsc, current_cpu()),
+        HIRES_TS_RDTSCP => {
+            let (timestamp, cpu) = read_tscp();
+            (timestamp, tsc, cpu)
+        }
+        HIRES_TS_RDTSC_LFENCE => (read_tsc_lfence(), tsc, current_cpu()),
+        _ => (clock_ns(libc::CLOCK_MONOTONIC_RAW), 0, current_cpu()),
+    }
+}
+
+/// # Safety
+/// `handle` must come from `hires_connect` and not be disconnected yet.
+unsafe fn conn<'a>(handle: *mut HiResLoggerConnHandle) -> Option<&'a Conn> {
+    unsafe { (handle as *const Conn).as_ref() }
+}
+
+/// There is no library to open.
+pub fn load() -> Result<(), String> {
+    Ok(())
+}
+
+pub unsafe fn hires_get_api_version() -> u32 {
+    HIRES_API_VERSION
+}
+
+pub unsafe fn hires_connect(device_path: *const c_char) -> *mut HiResLoggerConnHandle {
+    unsafe { hires_connect_with_capacity(device_path, 0) }
+}
+
+pub unsafe fn hires_connect_with_capacity(
+    device_path: *const c_char,
+    capacity: u64,
+) -> *mut HiResLoggerConnHandle {
+    set_last_error("");
+    // khires's bounds, which the stub ring would not check.
+    let valid =
+        capacity >= 2 && capacity.is_power_of_two() && capacity <= 1 << RING_BUFFER_LOG2_MAX;
+    let ring = if capacity != 0 && !valid {
+        Err(format!(
+            "Requested ring capacity {} is not a power of two in 2..2^{}",
+            capacity, RING_BUFFER_LOG2_MAX
+        ))
+    } else {
+        #[cfg(feature = "stub")]
+        {
+            let _ = device_path;
+            crate::stub::ring(capacity)
+        }
+        #[cfg(not(feature = "stub"))]
+        map_device(device_path, capacity)
+    };
+    match ring {
+        Ok(ring) => {
+            let shm = unsafe { Shm::new(ring.buf, ring.capacity, ring.mask, ring.layout) };
+            let conn = Box::new(Conn {
+                ring,
+                shm,
+                ts_source: AtomicU32::new(HIRES_TS_MONOTONIC),
+            });
+            Box::into_raw(conn) as *mut HiResLoggerConnHandle
+        }
+        Err(e) => {
+            set_last_error(&e);
+            ptr::null_mut()
+        }
+    }
+}
+
+pub unsafe fn hires_disconnect(handle: *mut HiResLoggerConnHandle) {
+    set_last_error("");
+    if !handle.is_null() {
+        drop(unsafe { Box::from_raw(handle as *mut Conn) });
+    }
+}
+
+#[inline]
+pub unsafe fn hires_log(
+    handle: *mut HiResLoggerConnHandle,
+    event_id: u32,
+    data1: u64,
+    data2: u64,
+) -> bool {
+    let Some(conn) = (unsafe { conn(handle) }) else {
+        set_last_error("Invalid handle passed to profiler_log");
+        return false;
+    };
+    let source = conn.ts_source.load(Ordering::Relaxed);
+    conn.log(source, event_id, data1, data2)
+}
+
+pub unsafe fn hires_log_with_source(
+    handle: *mut HiResLoggerConnHandle,
+    source: u32,
+    event_id: u32,
+    data1: u64,
+    data2: u64,
+) -> bool {
+    let Some(conn) = (unsafe { conn(handle) }) else {
+        set_last_error("Invalid handle passed to hires_log_with_source");
+        return false;
+    };
+    if source > HIRES_TS_RDTSC_LFENCE {
+        set_last_error("Unknown timestamp source passed to hires_log_with_source");
+        return false;
+    }
+    conn.log(source, event_id, data1, data2)
+}
+
+/// rt.cpp's `log_batch()`: one CAS reserving the whole run (nothing is
+/// reserved if it does not fit) and one release fence before the VALID
+/// stores.
+pub unsafe fn hires_log_batch(
+    handle: *mut HiResLoggerConnHandle,
+    entries: *const hires_batch_entry_t,
+    count: usize,
+) -> bool {
+    let Some(conn) = (unsafe { conn(handle) }) else {
+        set_last_error("Invalid handle passed to hires_log_batch");
+        return false;
+    };
+    if count == 0 {
+        return true;
+    }
+    if entries.is_null() {
+        set_last_error("NULL entries pointer passed to hires_log_batch");
+        return false;
+    }
+    let entries = unsafe { std::slice::from_raw_parts(entries, count) };
+    let count = count as u64;
+    let capacity = conn.ring.capacity;
+    let head = conn.shm.head_cell();
+    let mut first = head.load(Ordering::Relaxed);
+    loop {
+        // Acquire as in hires_log(): the slots are written only after the
+        // consumer's copy out of them.
+        let used = first.wrapping_sub(conn.shm.tail_cell().load(Ordering::Acquire));
+        if used > capacity || count > capacity - used {
+            conn.shm.dropped_cell().fetch_add(count, Ordering::Relaxed);
+            return false;
+        }
+        match head.compare_exchange_weak(first, first + count, Ordering::AcqRel, Ordering::Relaxed)
+        {
+            Ok(_) => break,
+            Err(now) => first = now,
+        }
+    }
+
+    let (timestamp, flags, cpu) = stamp(conn.ts_source.load(Ordering::Relaxed));
+    for (slot, e) in (first..).zip(entries) {
+        unsafe { conn.fill(slot, timestamp, cpu, e.event_id, e.data1, e.data2) };
+    }
+    // Orders every entry above before each of the relaxed VALID stores; a
+    // consumer acquiring any one of them sees its entry whole.
+    fence(Ordering::Release);
+    for slot in first..first + count {
+        conn.shm
+            .flags_cell(slot)
+            .store(flags | LOG_FLAG_VALID as u16, Ordering::Relaxed);
+    }
+    true
+}
+
+impl Conn {
+    /// The producer half of the protocol `shm` documents.
+    fn log(&self, source: u32, event_id: u32, data1: u64, data2: u64) -> bool {
+        let slot = self.shm.head_cell().fetch_add(1, Ordering::AcqRel);
+        if slot.wrapping_sub(self.shm.tail_cell().load(Ordering::Acquire)) >= self.ring.capacity {
+            self.shm.dropped_cell().fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        let (timestamp, flags, cpu) = stamp(source);
+        unsafe { self.fill(slot, timestamp, cpu, event_id, data1, data2) };
+        self.shm
+            .flags_cell(slot)
+            .store(flags | LOG_FLAG_VALID as u16, Ordering::Release);
+        true
+    }
+
+    /// Writes every field of the entry at ring position `slot` but its
+    /// flags.
+    ///
+    /// # Safety
+    /// `slot` was reserved by the caller and not yet published.
+    unsafe fn fill(
+        &self,
+        slot: u64,
+        timestamp: u64,
+        cpu: u32,
+        event_id: u32,
+        data1: u64,
+        data2: u64,
+    ) {
+        let entry = self.shm.entry(slot);
+        unsafe {
+            match self.ring.layout {
+                EntryLayout::Standard => {
+                    let e = entry.cast::<log_entry_t>();
+                    (*e).timestamp = timestamp;
+                    (*e).event_id = event_id;
+                    (*e).cpu_id = cpu;
+                    (*e).data1 = data1;
+                    (*e).data2 = data2;
+                }
+                EntryLayout::Compact => {
+                    let e = entry.cast::<log_entry_compact_t>();
+                    (*e).timestamp = timestamp;
+                    (*e).event_id = event_id;
+                    (*e).cpu_id = cpu as u16;
+                    (*e).data1 = data1;
+                    (*e).data2 = data2;
+                }
+                EntryLayout::Extended => {
+                    let e = entry.cast::<log_entry_ext_t>();
+                    (*e).timestamp = timestamp;
+                    (*e).event_id = event_id;
+                    (*e).cpu_id = cpu;
+                    (*e).tid = current_tid();
+                    (*e).data1 = data1;
+                    (*e).data2 = data2;
+                    (*e).seqno = slot;
+                }
+            }
+        }
+    }
+}
+
+/// `shm::read_tail` behind the C API's checks, narrowing the entry to the
+/// caller's struct.
+unsafe fn read_tail<T>(
+    func: &str,
+    handle: *mut HiResLoggerConnHandle,
+    out: *mut T,
+    consume: bool,
+    narrow: fn(log_entry_ext_t) -> T,
+) -> bool {
+    set_last_error("");
+    let Some(conn) = (unsafe { conn(handle) }) else {
+        set_last_error(&format!("Invalid handle passed to {}", func));
+        return false;
+    };
+    if out.is_null() {
+        set_last_error(&format!("NULL entry pointer passed to {}", func));
+        return false;
+    }
+    match shm::read_tail(&conn.shm, consume, shm::MAX_SPINS) {
+        Some(entry) => {
+            unsafe { *out = narrow(entry) };
+            true
+        }
+        None => false,
+    }
+}
+
+#[inline]
+pub unsafe fn hires_pop(handle: *mut HiResLoggerConnHandle, entry: *mut log_entry_t) -> bool {
+    unsafe { read_tail("hires_pop", handle, entry, true, shm::to_log_entry) }
+}
+
+pub unsafe fn hires_peek(handle: *mut HiResLoggerConnHandle, entry: *mut log_entry_t) -> bool {
+    unsafe { read_tail("hires_peek", handle, entry, false, shm::to_log_entry) }
+}
+
+pub unsafe fn hires_pop_ext(
+    handle: *mut HiResLoggerConnHandle,
+    entry: *mut log_entry_ext_t,
+) -> bool {
+    unsafe { read_tail("hires_pop_ext", handle, entry, true, |e| e) }
+}
+
+pub unsafe fn hires_get_entry_layout(handle: *mut HiResLoggerConnHandle) -> u32 {
+    set_last_error("");
+    match unsafe { conn(handle) } {
+        Some(conn) => conn.ring.layout as u32,
+        None => {
+            set_last_error("Invalid handle passed to hires_get_entry_layout");
+            HIRES_ENTRY_LAYOUT_STANDARD
+        }
+    }
+}
+
+pub unsafe fn hires_flush(_handle: *mut HiResLoggerConnHandle) {
+    fence(Ordering::SeqCst);
+}
+
+pub unsafe fn hires_get_stats(
+    handle: *mut HiResLoggerConnHandle,
+    out: *mut hires_rb_stats_t,
+) -> bool {
+    set_last_error("");
+    let Some(conn) = (unsafe { conn(handle) }).filter(|_| !out.is_null()) else {
+        set_last_error("Invalid handle or NULL out pointer passed to hires_get_stats");
+        return false;
+    };
+    unsafe { *out = shm::stats(&conn.shm) };
+    true
+}
+
+pub unsafe fn hires_get_buffer(handle: *mut HiResLoggerConnHandle) -> *mut shared_ring_buffer_t {
+    unsafe { conn(handle) }.map_or(ptr::null_mut(), |c| c.ring.buf)
+}
+
+pub unsafe fn hires_get_shm_size(handle: *mut HiResLoggerConnHandle) -> u64 {
+    unsafe { conn(handle) }.map_or(0, |c| c.ring.shm_size)
+}
+
+pub unsafe fn hires_get_rb_capacity(handle: *mut HiResLoggerConnHandle) -> u64 {
+    unsafe { conn(handle) }.map_or(0, |c| c.ring.capacity)
+}
+
+pub unsafe fn hires_get_rb_idx_mask(handle: *mut HiResLoggerConnHandle) -> u64 {
+    unsafe { conn(handle) }.map_or(0, |c| c.ring.mask)
+}
+
+pub unsafe fn hires_get_cycles_per_us(handle: *mut HiResLoggerConnHandle) -> u64 {
+    unsafe { conn(handle) }.map_or(0, |c| c.ring.cycles_per_us)
+}
+
+pub unsafe fn hires_get_tsc_hz(handle: *mut HiResLoggerConnHandle) -> u64 {
+    unsafe { conn(handle) }.map_or(0, |c| c.ring.tsc_hz)
+}
+
+pub unsafe fn hires_get_tsc_info(
+    handle: *mut HiResLoggerConnHandle,
+    out: *mut hires_tsc_info_t,
+) -> bool {
+    set_last_error("");
+    match unsafe { conn(handle) }.and_then(|c| c.ring.tsc_info) {
+        Some(info) if !out.is_null() => {
+            unsafe { *out = info };
+            true
+        }
+        _ => false,
+    }
+}
+
+pub unsafe fn hires_get_drop_num(handle: *mut HiResLoggerConnHandle) -> u64 {
+    unsafe { conn(handle) }.map_or(0, |c| shm::dropped(&c.shm))
+}
+
+pub unsafe fn hires_set_timestamp_source(handle: *mut HiResLoggerConnHandle, source: u32) -> bool {
+    set_last_error("");
+    let Some(conn) = (unsafe { conn(handle) }) else {
+        set_last_error("Invalid handle passed to hires_set_timestamp_source");
+        return false;
+    };
+    match source {
+        HIRES_TS_MONOTONIC
+        | HIRES_TS_MONOTONIC_RAW
+        | HIRES_TS_RDTSC
+        | HIRES_TS_RDTSCP
+        | HIRES_TS_RDTSC_LFENCE => {}
+        // CLOCK_MONOTONIC_RAW is only backed by the paravirt clock while
+        // kvm-clock is the kernel's clocksource.
+        HIRES_TS_KVMCLOCK
+            if fs::read_to_string(
+                "/sys/devices/system/clocksource/clocksource0/current_clocksource",
+            )
+            .is_ok_and(|cs| cs.trim() == "kvm-clock") => {}
+        _ => {
+            set_last_error(&format!("Timestamp source {} is not available", source));
+            return false;
+        }
+    }
+    conn.ts_source.store(source, Ordering::Relaxed);
+    true
+}
+
+pub unsafe fn hires_get_timestamp_source(handle: *mut HiResLoggerConnHandle) -> u32 {
+    match unsafe { conn(handle) } {
+        Some(conn) => conn.ts_source.load(Ordering::Relaxed),
+        None => HIRES_TS_MONOTONIC,
+    }
+}
+
+pub unsafe fn hires_rdtsc() -> u64 {
+    read_tsc()
+}
+
+/// `rdtscp`, storing the raw TSC_AUX like rt_c.cpp, not just the CPU.
+pub unsafe fn hires_rdtscp(auxp: *mut u32) -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    let (ts, aux) = {
+        let mut aux = 0;
+        let ts = unsafe { core::arch::x86_64::__rdtscp(&mut aux) };
+        (ts, aux)
+    };
+    #[cfg(not(target_arch = "x86_64"))]
+    let (ts, aux) = read_tscp();
+    if !auxp.is_null() {
+        unsafe { *auxp = aux };
+    }
+    ts
+}
+
+pub unsafe fn hires_sizeof_log_entry() -> usize {
+    size_of::<log_entry_t>()
+}
+
+pub unsafe fn hires_sizeof_ring_buffer() -> usize {
+    size_of::<shared_ring_buffer_t>()
+}
+
+pub unsafe fn hires_offsetof_ring_entries() -> usize {
+    offset_of!(shared_ring_buffer_t, buffer)
+}
+
+pub unsafe fn hires_get_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
@@ -0,0 +1,447 @@
+//! Packet correlation keys for `data2`.
+//!
+//! [`packet_key`] parses an IPv4 or IPv6 packet and hashes its L4 ports and
+//! the start of its payload with [`packet_hash`], i.e. the same key the XDP/tc
+//! hooks and [`HiResConn::log_packet_rx`] compute. Any layer that can see the
+//! packet (a kernel probe on the skb, a tap, the application's raw socket)
+//! can therefore log events that the consumer joins with the hook entries
+//! (`--join-events`).
+//!
+//! [`flow_key`] hashes the 5-tuple instead, for events that should group by
+//! connection rather than by packet, and [`queue_data2`] tags a payload with
+//! the RX/TX queue it was handled on.
+//!
+//! [`InstrumentedTcpStream`] (and, with the `tokio` feature,
+//! [`InstrumentedTokioStream`], or with the `async-io` feature,
+//! [`InstrumentedAsyncStream`] for async-std and smol) wrap a TCP stream and
+//! log every successful connect, send and recv with its duration in TSC
+//! cycles in `data1` and the byte count in `data2`.
+//!
+//! [`HiResConn::log_packet_rx`]: crate::HiResConn::log_packet_rx
+
+use crate::{HiResConn, rdtsc};
+use hires_xdp_common::{fnv1a_step, hash_seed, packet_hash};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpStream, ToSocketAddrs};
+use std::ops::Deref;
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+const ETH_P_IP: u16 = 0x0800;
+const ETH_P_IPV6: u16 = 0x86dd;
+const ETH_P_8021Q: u16 = 0x8100;
+const ETH_P_8021AD: u16 = 0x88a8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FiveTuple {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub protocol: u8,
+}
+
+/// Parses the IP and TCP/UDP headers at the start of `packet`. Returns the
+/// 5-tuple and the L4 payload; for other protocols the ports are 0 and the
+/// payload is everything after the IP header. IPv6 extension headers are not
+/// walked.
+pub fn parse(packet: &[u8]) -> Option<(FiveTuple, &[u8])> {
+    let (src, dst, protocol, l4) = match packet.first()? >> 4 {
+        4 => {
+            let ihl = (packet[0] & 0x0f) as usize * 4;
+            if ihl < 20 || packet.len() < ihl {
+                return None;
+            }
+            let src: [u8; 4] = packet[12..16].try_into().ok()?;
+            let dst: [u8; 4] = packet[16..20].try_into().ok()?;
+            (
+                IpAddr::V4(Ipv4Addr::from(src)),
+                IpAddr::V4(Ipv4Addr::from(dst)),
+                packet[9],
+                &packet[ihl..],
+            )
+        }
+        6 => {
+            if packet.len() < 40 {
+                return None;
+            }
+            let src: [u8; 16] = packet[8..24].try_into().ok()?;
+            let dst: [u8; 16] = packet[24..40].try_into().ok()?;
+            (
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                packet[6],
+                &packet[40..],
+            )
+        }
+        _ => return None,
+    };
+    let header_len = match protocol {
+        IPPROTO_UDP => 8,
+        IPPROTO_TCP => {
+            // Data offset, in 32-bit words.
+            let offset = (*l4.get(12)? >> 4) as usize * 4;
+            if offset < 20 {
+                return None;
+            }
+            offset
+        }
+        _ => 0,
+    };
+    if l4.len() < header_len {
+        return None;
+    }
+    let (src_port, dst_port) = if header_len > 0 {
+        (
+            u16::from_be_bytes([l4[0], l4[1]]),
+            u16::from_be_bytes([l4[2], l4[3]]),
+        )
+    } else {
+        (0, 0)
+    };
+    let tuple = FiveTuple {
+        src,
+        dst,
+        src_port,
+        dst_port,
+        protocol,
+    };
+    Some((tuple, &l4[header_len..]))
+}
+
+/// Per-packet correlation key of an IP packet, equal to the [`packet_hash`]
+/// the hooks log for it. Unparseable input is hashed as a whole so the key
+/// is still stable.
+pub fn packet_key(packet: &[u8]) -> u64 {
+    match parse(packet) {
+        Some((t, payload)) => packet_hash(t.src_port, t.dst_port, payload),
+        None => packet_hash(0, 0, packet),
+    }
+}
+
+/// [`packet_key`] of an Ethernet frame (e.g. an AF_XDP or DPDK buffer),
+/// skipping the link header and up to two VLAN tags.
+pub fn frame_key(frame: &[u8]) -> u64 {
+    let mut offset = 12;
+    for _ in 0..3 {
+        let Some(ethertype) = frame.get(offset..offset + 2) else {
+            break;
+        };
+        match u16::from_be_bytes([ethertype[0], ethertype[1]]) {
+            ETH_P_8021Q | ETH_P_8021AD => offset += 4,
+            ETH_P_IP | ETH_P_IPV6 => return packet_key(&frame[offset + 2..]),
+            _ => break,
+        }
+    }
+    packet_hash(0, 0, frame)
+}
+
+/// Per-flow key: FNV-1a over protocol, addresses and ports.
+pub fn flow_key(tuple: &FiveTuple) -> u64 {
+    let mut hash = fnv1a_step(hash_seed(), tuple.protocol);
+    let mut feed = |bytes: &[u8]| {
+        for &b in bytes {
+            hash = fnv1a_step(hash, b);
+        }
+    };
+    for addr in [tuple.src, tuple.dst] {
+        match addr {
+            IpAddr::V4(a) => feed(&a.octets()),
+            IpAddr::V6(a) => feed(&a.octets()),
+        }
+    }
+    feed(&tuple.src_port.to_be_bytes());
+    feed(&tuple.dst_port.to_be_bytes());
+    hash
+}
+
+/// Puts a NIC/virtqueue queue index into bits 32-47 of `data2`, next to a
+/// 32-bit value in the low half. This is where the DPDK burst events carry
+/// their queue, and what the consumer's `--queue-events` decodes.
+#[inline]
+pub const fn queue_data2(queue: u16, value: u32) -> u64 {
+    ((queue as u64) << 32) | value as u64
+}
+
+/// Queue index of a [`queue_data2`] payload.
+#[inline]
+pub const fn queue_of(data2: u64) -> u16 {
+    (data2 >> 32) as u16
+}
+
+/// Event IDs logged by the instrumented streams.
+#[derive(Clone, Copy, Debug)]
+pub struct SocketEvents {
+    pub connect: u32,
+    pub send: u32,
+    pub recv: u32,
+}
+
+impl SocketEvents {
+    /// `base`, `base + 1` and `base + 2` for connect, send and recv.
+    pub const fn from_base(base: u32) -> Self {
+        SocketEvents {
+            connect: base,
+            send: base + 1,
+            recv: base + 2,
+        }
+    }
+}
+
+/// [`TcpStream`] that logs its syscalls. Dereferences to the inner stream
+/// for everything else (options, addresses, shutdown).
+pub struct InstrumentedTcpStream<'c, 'a> {
+    inner: TcpStream,
+    conn: &'c HiResConn<'a>,
+    events: SocketEvents,
+}
+
+impl<'c, 'a> InstrumentedTcpStream<'c, 'a> {
+    /// Connects like [`TcpStream::connect`], logging the connect latency.
+    pub fn connect<A: ToSocketAddrs>(
+        conn: &'c HiResConn<'a>,
+        events: SocketEvents,
+        addr: A,
+    ) -> io::Result<Self> {
+        let start = rdtsc();
+        let inner = TcpStream::connect(addr)?;
+        conn.log(events.connect, rdtsc() - start, 0);
+        Ok(Self::wrap(conn, events, inner))
+    }
+
+    /// Wraps an established stream, e.g. one returned by `accept`.
+    pub fn wrap(conn: &'c HiResConn<'a>, events: SocketEvents, inner: TcpStream) -> Self {
+        InstrumentedTcpStream {
+            inner,
+            conn,
+            events,
+        }
+    }
+
+    pub fn into_inner(self) -> TcpStream {
+        self.inner
+    }
+}
+
+impl Deref for InstrumentedTcpStream<'_, '_> {
+    type Target = TcpStream;
+
+    fn deref(&self) -> &TcpStream {
+        &self.inner
+    }
+}
+
+impl Read for InstrumentedTcpStream<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = rdtsc();
+        let n = self.inner.read(buf)?;
+        self.conn.log(self.events.recv, rdtsc() - start, n as u64);
+        Ok(n)
+    }
+}
+
+impl Write for InstrumentedTcpStream<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = rdtsc();
+        let n = self.inner.write(buf)?;
+        self.conn.log(self.events.send, rdtsc() - start, n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub use tokio_stream::InstrumentedTokioStream;
+
+#[cfg(feature = "tokio")]
+mod tokio_stream {
+    use super::SocketEvents;
+    use crate::{HiResConn, rdtsc};
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use tokio::net::{TcpStream, ToSocketAddrs};
+
+    /// Async counterpart of [`super::InstrumentedTcpStream`]. Only polls that
+    /// complete are logged, so durations cover the syscall, not the time
+    /// spent waiting for readiness.
+    pub struct InstrumentedTokioStream<'c, 'a> {
+        inner: TcpStream,
+        conn: &'c HiResConn<'a>,
+        events: SocketEvents,
+    }
+
+    impl<'c, 'a> InstrumentedTokioStream<'c, 'a> {
+        /// Connects like [`TcpStream::connect`]; the logged latency includes
+        /// the whole handshake.
+        pub async fn connect<A: ToSocketAddrs>(
+            conn: &'c HiResConn<'a>,
+            events: SocketEvents,
+            addr: A,
+        ) -> io::Result<Self> {
+            let start = rdtsc();
+            let inner = TcpStream::connect(addr).await?;
+            conn.log(events.connect, rdtsc() - start, 0);
+            Ok(Self::wrap(conn, events, inner))
+        }
+
+        pub fn wrap(conn: &'c HiResConn<'a>, events: SocketEvents, inner: TcpStream) -> Self {
+            InstrumentedTokioStream {
+                inner,
+                conn,
+                events,
+            }
+        }
+
+        pub fn get_ref(&self) -> &TcpStream {
+            &self.inner
+        }
+
+        pub fn into_inner(self) -> TcpStream {
+            self.inner
+        }
+    }
+
+    impl AsyncRead for InstrumentedTokioStream<'_, '_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let filled = buf.filled().len();
+            let start = rdtsc();
+            let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+            if let Poll::Ready(Ok(())) = poll {
+                let n = buf.filled().len() - filled;
+                self.conn.log(self.events.recv, rdtsc() - start, n as u64);
+            }
+            poll
+        }
+    }
+
+    impl AsyncWrite for InstrumentedTokioStream<'_, '_> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let start = rdtsc();
+            let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+            if let Poll::Ready(Ok(n)) = poll {
+                self.conn.log(self.events.send, rdtsc() - start, n as u64);
+            }
+            poll
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+}
+
+#[cfg(feature = "async-io")]
+pub use async_io_stream::InstrumentedAsyncStream;
+
+#[cfg(feature = "async-io")]
+mod async_io_stream {
+    use super::SocketEvents;
+    use crate::{HiResConn, rdtsc};
+    use async_io::Async;
+    use futures_io::{AsyncRead, AsyncWrite};
+    use std::io;
+    use std::net::{SocketAddr, TcpStream};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// [`super::InstrumentedTokioStream`] for async-std, smol and other
+    /// executors built on `async-io`'s reactor. Logs the same events, again
+    /// only for polls that complete.
+    pub struct InstrumentedAsyncStream<'c, 'a> {
+        inner: Async<TcpStream>,
+        conn: &'c HiResConn<'a>,
+        events: SocketEvents,
+    }
+
+    impl<'c, 'a> InstrumentedAsyncStream<'c, 'a> {
+        /// Connects like [`Async::<TcpStream>::connect`]; the logged latency
+        /// includes the whole handshake.
+        pub async fn connect<A: Into<SocketAddr>>(
+            conn: &'c HiResConn<'a>,
+            events: SocketEvents,
+            addr: A,
+        ) -> io::Result<Self> {
+            let start = rdtsc();
+            let inner = Async::<TcpStream>::connect(addr).await?;
+            conn.log(events.connect, rdtsc() - start, 0);
+            Ok(Self::wrap(conn, events, inner))
+        }
+
+        /// Wraps an established stream, e.g. one accepted from an
+        /// `Async<TcpListener>`.
+        pub fn wrap(
+            conn: &'c HiResConn<'a>,
+            events: SocketEvents,
+            inner: Async<TcpStream>,
+        ) -> Self {
+            InstrumentedAsyncStream {
+                inner,
+                conn,
+                events,
+            }
+        }
+
+        pub fn get_ref(&self) -> &Async<TcpStream> {
+            &self.inner
+        }
+
+        pub fn into_inner(self) -> Async<TcpStream> {
+            self.inner
+        }
+    }
+
+    impl AsyncRead for InstrumentedAsyncStream<'_, '_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let start = rdtsc();
+            let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+            if let Poll::Ready(Ok(n)) = poll {
+                self.conn.log(self.events.recv, rdtsc() - start, n as u64);
+            }
+            poll
+        }
+    }
+
+    impl AsyncWrite for InstrumentedAsyncStream<'_, '_> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let start = rdtsc();
+            let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+            if let Poll::Ready(Ok(n)) = poll {
+                self.conn.log(self.events.send, rdtsc() - start, n as u64);
+            }
+            poll
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_close(cx)
+        }
+    }
+}
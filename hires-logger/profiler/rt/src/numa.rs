@@ -0,0 +1,87 @@
+//! NUMA placement of the consumer relative to the ring.
+//!
+//! The ring's pages are allocated by khires (`numa_node=N` module
+//! parameter), so only the kernel can place them. What userspace controls is
+//! the consumer side: [`bind_current_thread`] pins the calling thread to a
+//! node's CPUs and binds its own allocations there, so draining the ring
+//! does not pull cachelines across the interconnect while producers on
+//! other nodes are being measured.
+
+use std::fs;
+use std::io;
+use std::mem;
+
+/// `MPOL_BIND` from `<linux/mempolicy.h>`.
+const MPOL_BIND: libc::c_int = 2;
+
+/// Node the ring was allocated on, from the loaded module's `numa_node`
+/// parameter; `None` if unpinned (-1) or the module is not loaded.
+pub fn ring_node() -> Option<u32> {
+    fs::read_to_string("/sys/module/khires/parameters/numa_node")
+        .ok()?
+        .trim()
+        .parse::<i32>()
+        .ok()
+        .and_then(|n| u32::try_from(n).ok())
+}
+
+/// CPUs of `node`, parsed from its sysfs cpulist (e.g. `0-3,8-11`).
+pub fn node_cpus(node: u32) -> io::Result<Vec<usize>> {
+    read_cpulist(&format!("/sys/devices/system/node/node{}/cpulist", node))
+}
+
+/// Parses a sysfs CPU list file such as `/sys/devices/system/cpu/online`.
+pub(crate) fn read_cpulist(path: &str) -> io::Result<Vec<usize>> {
+    let list = fs::read_to_string(path)?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("bad cpulist: {}", list));
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        let (lo, hi) = range.split_once('-').unwrap_or((range, range));
+        let lo: usize = lo.parse().map_err(|_| invalid())?;
+        let hi: usize = hi.parse().map_err(|_| invalid())?;
+        cpus.extend(lo..=hi);
+    }
+    Ok(cpus)
+}
+
+/// Pins the calling thread to the CPUs of `node` and binds its future
+/// memory allocations to that node.
+pub fn bind_current_thread(node: u32) -> io::Result<()> {
+    let cpus = node_cpus(node)?;
+    if cpus.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("node {} has no CPUs", node),
+        ));
+    }
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    for &cpu in &cpus {
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    if unsafe { libc::sched_setaffinity(0, mem::size_of_val(&set), &set) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut mask = [0 as libc::c_ulong; 16];
+    let bits = libc::c_ulong::BITS as usize;
+    let node = node as usize;
+    if node >= mask.len() * bits {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "node number out of range",
+        ));
+    }
+    mask[node / bits] |= 1 << (node % bits);
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_set_mempolicy,
+            MPOL_BIND,
+            mask.as_ptr(),
+            (mask.len() * bits) as libc::c_ulong,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
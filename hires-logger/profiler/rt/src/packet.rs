@@ -0,0 +1,35 @@
+//! Per-packet events from the XDP/tc hooks and the application socket.
+//!
+//! Packet observations are logged following this convention:
+//!
+//! * `event_id` is [`PACKET_HOOK_FLAG`] with one of the `HOOK_*` values.
+//! * `data1` is the CLOCK_MONOTONIC time (ns) the hook saw the packet, or 0
+//!   to use the entry's own timestamp (socket events logged inline).
+//! * `data2` is the packet's [`packet_hash`].
+//!
+//! Hook entries are forwarded by the `hires-xdp` bridge. The application logs
+//! [`HiResConn::log_packet_rx`] when it reads the packet; with the default
+//! timestamp source that lands in the same CLOCK_MONOTONIC domain as
+//! `bpf_ktime_get_ns()`.
+
+use crate::HiResConn;
+
+pub use hires_xdp_common::{HOOK_SOCKET, HOOK_TC_INGRESS, HOOK_XDP, packet_hash};
+
+/// Set in `event_id` for packet hook entries.
+pub const PACKET_HOOK_FLAG: u32 = 1 << 29;
+
+#[inline]
+pub fn is_packet_hook(event_id: u32) -> bool {
+    event_id & PACKET_HOOK_FLAG != 0
+}
+
+impl<'a> HiResConn<'a> {
+    /// Logs delivery of a received packet to the application. `src_port` is
+    /// the sender's port and `payload` the received datagram or segment.
+    #[inline]
+    pub fn log_packet_rx(&self, src_port: u16, dst_port: u16, payload: &[u8]) -> bool {
+        let hash = packet_hash(src_port, dst_port, payload);
+        self.log(PACKET_HOOK_FLAG | HOOK_SOCKET, 0, hash)
+    }
+}
@@ -0,0 +1,77 @@
+//! Begin/end pairs logged as one entry.
+//!
+//! ```text
+//! let t = conn.begin(EV_PARSE);
+//! let request = parse(buf)?;
+//! conn.end(t);
+//! ```
+//!
+//! [`HiResConn::begin`] only reads the TSC and returns a [`Token`]; nothing
+//! is logged until [`HiResConn::end`], which logs the event once with the
+//! cycles since `begin` in `data1` and the token's correlation ID in
+//! `data2`. That is half the ring traffic of separate start and end events,
+//! and the entry is already in the form the profiler's per-event latency
+//! statistics expect.
+//!
+//! Correlation IDs come from [`corr::next_id`], so other events logged
+//! against [`Token::id`] can be joined with the pair.
+
+use crate::{HiResConn, corr, rdtsc};
+
+/// An operation started with [`HiResConn::begin`]. Dropping it without
+/// [`HiResConn::end`] logs nothing.
+#[must_use = "nothing is logged until the token is passed to end()"]
+#[derive(Debug)]
+pub struct Token {
+    event_id: u32,
+    start: u64,
+    id: u64,
+}
+
+impl Token {
+    pub fn event_id(&self) -> u32 {
+        self.event_id
+    }
+
+    /// The correlation ID `end()` logs in `data2`.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// TSC at `begin()`.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+}
+
+impl<'a> HiResConn<'a> {
+    /// Starts timing `event_id` under a fresh correlation ID.
+    #[inline]
+    pub fn begin(&self, event_id: u32) -> Token {
+        let id = if cfg!(feature = "disabled") {
+            0
+        } else {
+            corr::next_id()
+        };
+        self.begin_with_id(event_id, id)
+    }
+
+    /// Starts timing `event_id` under a correlation ID the caller already
+    /// has (a request or span ID).
+    #[inline]
+    pub fn begin_with_id(&self, event_id: u32, id: u64) -> Token {
+        Token {
+            event_id,
+            start: rdtsc(),
+            id,
+        }
+    }
+
+    /// Logs the event `token` was started for, with the cycles since
+    /// `begin()` in `data1` and its correlation ID in `data2`. Returns
+    /// `false` if the entry was dropped.
+    #[inline]
+    pub fn end(&self, token: Token) -> bool {
+        self.log(token.event_id, rdtsc().wrapping_sub(token.start), token.id)
+    }
+}
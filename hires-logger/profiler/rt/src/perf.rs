@@ -0,0 +1,524 @@
+//! Fallback transport through the kernel's perf ring buffers, for hosts
+//! without khires.
+//!
+//! Producers register a `hires_log` user event (`user_events`, Linux 6.4+)
+//! and each `log()` writes one entry to it, which fires the tracepoint. The
+//! consumer opens that tracepoint on every online CPU with
+//! `perf_event_open` and reads the samples from the per-CPU perf rings,
+//! merging them by timestamp. Every entry costs a syscall and timestamps
+//! are the kernel's `CLOCK_MONOTONIC` at the tracepoint, so this is for
+//! functional runs on stock kernels, not for measurements; there are no
+//! kernel-side events and no shared ring to map.
+//!
+//! Both sides need access to tracefs (`user_events_data`) and the consumer
+//! needs `CAP_PERFMON` or a permissive `perf_event_paranoid`.
+
+use crate::clock::calibrate_tsc_hz;
+use crate::numa::read_cpulist;
+use crate::{LOG_FLAG_VALID, hires_rb_stats_t, log_entry_t};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+const EVENT_NAME: &str = "hires_log";
+/// Fields in write order, all naturally aligned after the 8 bytes of
+/// common tracepoint fields.
+const EVENT_FIELDS: &str = "u64 data1;u64 data2;u32 event_id";
+
+const TRACEFS_DIRS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+/// `DIAG_IOCSREG` and `DIAG_IOCSUNREG` from `<linux/user_events.h>`.
+const DIAG_IOCSREG: libc::c_ulong = 0xC008_2A00;
+const DIAG_IOCSUNREG: libc::c_ulong = 0x4008_2A02;
+
+#[repr(C, packed)]
+struct UserReg {
+    size: u32,
+    enable_bit: u8,
+    enable_size: u8,
+    flags: u16,
+    enable_addr: u64,
+    name_args: u64,
+    write_index: u32,
+}
+
+#[repr(C, packed)]
+struct UserUnreg {
+    size: u32,
+    disable_bit: u8,
+    reserved: u8,
+    reserved2: u16,
+    disable_addr: u64,
+}
+
+/// `struct perf_event_attr` up to `PERF_ATTR_SIZE_VER5`.
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+    sample_regs_intr: u64,
+    aux_watermark: u32,
+    sample_max_stack: u16,
+    reserved: u16,
+}
+
+const PERF_TYPE_TRACEPOINT: u32 = 2;
+const PERF_SAMPLE_TIME: u64 = 1 << 2;
+const PERF_SAMPLE_CPU: u64 = 1 << 7;
+const PERF_SAMPLE_RAW: u64 = 1 << 10;
+const PERF_ATTR_FLAG_USE_CLOCKID: u64 = 1 << 25;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+const PERF_RECORD_LOST: u32 = 2;
+const PERF_RECORD_SAMPLE: u32 = 9;
+
+/// Offsets of `data_head` and `data_tail` in `struct perf_event_mmap_page`.
+const DATA_HEAD_OFFSET: usize = 1024;
+const DATA_TAIL_OFFSET: usize = 1032;
+
+/// Data pages per CPU ring (a power of two).
+const RING_PAGES: usize = 64;
+/// Size of one sample record: header, time, cpu, and the raw tracepoint
+/// data padded to 8 bytes. Used to express ring sizes in entries.
+const RECORD_BYTES: usize = 64;
+
+fn tracefs() -> Result<PathBuf, String> {
+    TRACEFS_DIRS
+        .iter()
+        .map(PathBuf::from)
+        .find(|dir| dir.join("user_events_data").exists())
+        .ok_or_else(|| {
+            "user_events is not available (needs CONFIG_USER_EVENTS and tracefs mounted)"
+                .to_string()
+        })
+}
+
+pub(crate) struct PerfRing {
+    data: File,
+    write_index: u32,
+    /// Set by the kernel while the tracepoint has a listener. Boxed so its
+    /// address, registered with the kernel, stays put.
+    enabled: Box<AtomicU32>,
+    tracefs: PathBuf,
+    tsc_hz: u64,
+    dropped: AtomicU64,
+    consumer: OnceLock<Option<Mutex<Consumer>>>,
+}
+
+impl PerfRing {
+    pub(crate) fn open() -> Result<Self, String> {
+        let tracefs = tracefs()?;
+        let data = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(tracefs.join("user_events_data"))
+            .map_err(|e| format!("Failed to open user_events_data: {}", e))?;
+        let enabled = Box::new(AtomicU32::new(0));
+        let name_args = format!("{} {}\0", EVENT_NAME, EVENT_FIELDS);
+        let mut reg = UserReg {
+            size: size_of::<UserReg>() as u32,
+            enable_bit: 0,
+            enable_size: 4,
+            flags: 0,
+            enable_addr: enabled.as_ptr() as u64,
+            name_args: name_args.as_ptr() as u64,
+            write_index: 0,
+        };
+        if unsafe { libc::ioctl(data.as_raw_fd(), DIAG_IOCSREG, &mut reg) } < 0 {
+            return Err(format!(
+                "Failed to register the {} user event: {}",
+                EVENT_NAME,
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(PerfRing {
+            data,
+            write_index: reg.write_index,
+            enabled,
+            tracefs,
+            tsc_hz: calibrate_tsc_hz(),
+            dropped: AtomicU64::new(0),
+            consumer: OnceLock::new(),
+        })
+    }
+
+    /// Emits one entry. With nobody consuming, nothing is written and the
+    /// entry counts as dropped.
+    pub(crate) fn log(&self, event_id: u32, data1: u64, data2: u64) -> bool {
+        if self.enabled.load(Ordering::Relaxed) & 1 == 0 {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let mut buf = [0u8; 24];
+        buf[0..4].copy_from_slice(&self.write_index.to_ne_bytes());
+        buf[4..12].copy_from_slice(&data1.to_ne_bytes());
+        buf[12..20].copy_from_slice(&data2.to_ne_bytes());
+        buf[20..24].copy_from_slice(&event_id.to_ne_bytes());
+        if (&self.data).write(&buf).is_ok() {
+            true
+        } else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+
+    /// The consumer side, opened by the first `pop()`/`peek()` so that
+    /// producer-only connections do not enable the tracepoint themselves.
+    fn consumer(&self) -> Option<&Mutex<Consumer>> {
+        self.consumer
+            .get_or_init(|| match Consumer::open(&self.tracefs) {
+                Ok(consumer) => Some(Mutex::new(consumer)),
+                Err(e) => {
+                    eprintln!("WARNING: HiResLogger perf consumer unavailable: {}", e);
+                    None
+                }
+            })
+            .as_ref()
+    }
+
+    pub(crate) fn pop(&self) -> Option<log_entry_t> {
+        self.consumer()?.lock().unwrap().next(true)
+    }
+
+    pub(crate) fn peek(&self) -> Option<log_entry_t> {
+        self.consumer()?.lock().unwrap().next(false)
+    }
+
+    pub(crate) fn peek_n(&self, out: &mut Vec<log_entry_t>, max: usize) -> usize {
+        self.consumer()
+            .map_or(0, |c| c.lock().unwrap().peek_n(out, max))
+    }
+
+    /// Drops on this side plus samples the perf rings lost, once consuming.
+    pub(crate) fn drop_num(&self) -> u64 {
+        let lost = self
+            .consumer
+            .get()
+            .and_then(|c| c.as_ref())
+            .map_or(0, |c| c.lock().unwrap().lost);
+        self.dropped.load(Ordering::Relaxed) + lost
+    }
+
+    /// Entries the per-CPU rings hold together.
+    pub(crate) fn capacity(&self) -> u64 {
+        let cpus = read_cpulist("/sys/devices/system/cpu/online").map_or(1, |cpus| cpus.len());
+        (cpus * RING_PAGES * page_size() / RECORD_BYTES) as u64
+    }
+
+    /// `head`/`tail` count entries since the consumer opened; `pending` is
+    /// estimated from the unread bytes.
+    pub(crate) fn stats(&self) -> hires_rb_stats_t {
+        let mut stats = hires_rb_stats_t {
+            capacity: self.capacity(),
+            dropped: self.drop_num(),
+            ..Default::default()
+        };
+        if let Some(consumer) = self.consumer.get().and_then(|c| c.as_ref()) {
+            let consumer = consumer.lock().unwrap();
+            stats.tail = consumer.popped;
+            stats.pending = consumer.unread_bytes() / RECORD_BYTES as u64;
+            stats.head = stats.tail + stats.pending;
+        }
+        stats
+    }
+
+    pub(crate) fn tsc_hz(&self) -> u64 {
+        self.tsc_hz
+    }
+}
+
+impl Drop for PerfRing {
+    fn drop(&mut self) {
+        // The kernel keeps writing the enable bit until it is unregistered.
+        let mut unreg = UserUnreg {
+            size: size_of::<UserUnreg>() as u32,
+            disable_bit: 0,
+            reserved: 0,
+            reserved2: 0,
+            disable_addr: self.enabled.as_ptr() as u64,
+        };
+        unsafe { libc::ioctl(self.data.as_raw_fd(), DIAG_IOCSUNREG, &mut unreg) };
+    }
+}
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// Byte offsets of the event's fields in a sample's raw data, from the
+/// tracepoint's format file.
+struct Layout {
+    data1: usize,
+    data2: usize,
+    event_id: usize,
+}
+
+fn read_format(dir: &Path) -> Result<(u64, Layout), String> {
+    let event_dir = dir.join("events/user_events").join(EVENT_NAME);
+    let id = fs::read_to_string(event_dir.join("id"))
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .ok_or_else(|| format!("No tracepoint id for {}", EVENT_NAME))?;
+    let format = fs::read_to_string(event_dir.join("format"))
+        .map_err(|e| format!("Failed to read the {} format: {}", EVENT_NAME, e))?;
+    let offset = |field: &str| {
+        format
+            .lines()
+            .find(|l| l.contains(&format!(" {};", field)))
+            .and_then(|l| l.split("offset:").nth(1))
+            .and_then(|s| s.split(';').next())
+            .and_then(|s| s.parse::<usize>().ok())
+            .ok_or_else(|| format!("No {} field in the {} format", field, EVENT_NAME))
+    };
+    Ok((
+        id,
+        Layout {
+            data1: offset("data1")?,
+            data2: offset("data2")?,
+            event_id: offset("event_id")?,
+        },
+    ))
+}
+
+struct CpuRing {
+    _fd: OwnedFd,
+    base: *mut u8,
+    mmap_len: usize,
+    data_size: usize,
+}
+
+impl CpuRing {
+    fn open(attr: &PerfEventAttr, cpu: usize) -> Result<Self, String> {
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                attr as *const PerfEventAttr,
+                -1 as libc::pid_t,
+                cpu as libc::c_int,
+                -1 as libc::c_int,
+                PERF_FLAG_FD_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(format!(
+                "perf_event_open on CPU {} failed: {}",
+                cpu,
+                std::io::Error::last_os_error()
+            ));
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) };
+        let data_size = RING_PAGES * page_size();
+        let mmap_len = page_size() + data_size;
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                mmap_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(format!(
+                "Failed to mmap the perf ring of CPU {}: {}",
+                cpu,
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(CpuRing {
+            _fd: fd,
+            base: base as *mut u8,
+            mmap_len,
+            data_size,
+        })
+    }
+
+    fn head(&self) -> &AtomicU64 {
+        unsafe { AtomicU64::from_ptr(self.base.add(DATA_HEAD_OFFSET) as *mut u64) }
+    }
+
+    fn tail(&self) -> &AtomicU64 {
+        unsafe { AtomicU64::from_ptr(self.base.add(DATA_TAIL_OFFSET) as *mut u64) }
+    }
+
+    /// Copies `len` bytes at ring position `pos`, which may wrap.
+    fn read(&self, pos: u64, len: usize) -> Vec<u8> {
+        let data = unsafe { self.base.add(page_size()) };
+        (0..len)
+            .map(|i| unsafe { *data.add((pos as usize + i) & (self.data_size - 1)) })
+            .collect()
+    }
+}
+
+impl Drop for CpuRing {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.base as *mut libc::c_void, self.mmap_len) };
+    }
+}
+
+struct Consumer {
+    rings: Vec<CpuRing>,
+    layout: Layout,
+    popped: u64,
+    lost: u64,
+}
+
+// The rings are only touched behind PerfRing's mutex.
+unsafe impl Send for Consumer {}
+
+fn field<const N: usize>(raw: &[u8], offset: usize) -> [u8; N] {
+    raw.get(offset..offset + N)
+        .and_then(|b| b.try_into().ok())
+        .unwrap_or([0; N])
+}
+
+impl Consumer {
+    fn open(tracefs: &Path) -> Result<Self, String> {
+        let (id, layout) = read_format(tracefs)?;
+        let attr = PerfEventAttr {
+            type_: PERF_TYPE_TRACEPOINT,
+            size: size_of::<PerfEventAttr>() as u32,
+            config: id,
+            sample_period: 1,
+            sample_type: PERF_SAMPLE_TIME | PERF_SAMPLE_CPU | PERF_SAMPLE_RAW,
+            flags: PERF_ATTR_FLAG_USE_CLOCKID,
+            wakeup_events: 1,
+            clockid: libc::CLOCK_MONOTONIC,
+            ..Default::default()
+        };
+        let cpus = read_cpulist("/sys/devices/system/cpu/online")
+            .map_err(|e| format!("Failed to list online CPUs: {}", e))?;
+        let rings = cpus
+            .into_iter()
+            .map(|cpu| CpuRing::open(&attr, cpu))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Consumer {
+            rings,
+            layout,
+            popped: 0,
+            lost: 0,
+        })
+    }
+
+    /// Oldest entry across the CPU rings, consumed if `consume`. Records
+    /// other than samples are skipped, counting lost samples as drops.
+    fn next(&mut self, consume: bool) -> Option<log_entry_t> {
+        let mut oldest: Option<(usize, u64, log_entry_t)> = None;
+        for (i, ring) in self.rings.iter().enumerate() {
+            let head = ring.head().load(Ordering::Acquire);
+            let mut tail = ring.tail().load(Ordering::Relaxed);
+            while tail < head {
+                let header = ring.read(tail, 8);
+                let kind = u32::from_ne_bytes(field(&header, 0));
+                let size = u16::from_ne_bytes(field(&header, 6)) as u64;
+                if kind == PERF_RECORD_SAMPLE {
+                    let record = ring.read(tail, size as usize);
+                    let entry = self.parse_sample(&record);
+                    if oldest
+                        .as_ref()
+                        .is_none_or(|(_, _, e)| entry.timestamp < e.timestamp)
+                    {
+                        oldest = Some((i, tail + size, entry));
+                    }
+                    break;
+                }
+                if kind == PERF_RECORD_LOST {
+                    self.lost += u64::from_ne_bytes(field(&ring.read(tail, size as usize), 16));
+                }
+                tail += size;
+                ring.tail().store(tail, Ordering::Release);
+            }
+        }
+        let (i, next_tail, entry) = oldest?;
+        if consume {
+            self.rings[i].tail().store(next_tail, Ordering::Release);
+            self.popped += 1;
+        }
+        Some(entry)
+    }
+
+    /// Up to `max` entries in the order `next` returns them, onto `out`.
+    /// Cursors are local, so nothing is consumed and lost records are left
+    /// for `next` to count.
+    fn peek_n(&mut self, out: &mut Vec<log_entry_t>, max: usize) -> usize {
+        let heads: Vec<u64> = self
+            .rings
+            .iter()
+            .map(|r| r.head().load(Ordering::Acquire))
+            .collect();
+        let mut cursors: Vec<u64> = self
+            .rings
+            .iter()
+            .map(|r| r.tail().load(Ordering::Relaxed))
+            .collect();
+        let start = out.len();
+        while out.len() - start < max {
+            let mut oldest: Option<(usize, u64, log_entry_t)> = None;
+            for (i, ring) in self.rings.iter().enumerate() {
+                while cursors[i] < heads[i] {
+                    let header = ring.read(cursors[i], 8);
+                    let kind = u32::from_ne_bytes(field(&header, 0));
+                    let size = u16::from_ne_bytes(field(&header, 6)) as u64;
+                    if kind == PERF_RECORD_SAMPLE {
+                        let entry = self.parse_sample(&ring.read(cursors[i], size as usize));
+                        if oldest
+                            .as_ref()
+                            .is_none_or(|(_, _, e)| entry.timestamp < e.timestamp)
+                        {
+                            oldest = Some((i, cursors[i] + size, entry));
+                        }
+                        break;
+                    }
+                    cursors[i] += size;
+                }
+            }
+            let Some((i, next, entry)) = oldest else {
+                break;
+            };
+            cursors[i] = next;
+            out.push(entry);
+        }
+        out.len() - start
+    }
+
+    /// Sample layout for TIME | CPU | RAW: header, u64 time, u32 cpu and
+    /// u32 reserved, u32 raw size, raw data.
+    fn parse_sample(&self, record: &[u8]) -> log_entry_t {
+        let raw_size = u32::from_ne_bytes(field(record, 24)) as usize;
+        let raw = record.get(28..28 + raw_size).unwrap_or(&[]);
+        log_entry_t {
+            timestamp: u64::from_ne_bytes(field(record, 8)),
+            event_id: u32::from_ne_bytes(field(raw, self.layout.event_id)),
+            cpu_id: u32::from_ne_bytes(field(record, 16)),
+            flags: LOG_FLAG_VALID as u16,
+            data1: u64::from_ne_bytes(field(raw, self.layout.data1)),
+            data2: u64::from_ne_bytes(field(raw, self.layout.data2)),
+        }
+    }
+
+    fn unread_bytes(&self) -> u64 {
+        self.rings
+            .iter()
+            .map(|r| r.head().load(Ordering::Acquire) - r.tail().load(Ordering::Relaxed))
+            .sum()
+    }
+}
@@ -0,0 +1,174 @@
+//! quinn instrumentation (`quinn` feature).
+//!
+//! * [`InstrumentedUdpSocket`] wraps the endpoint's socket and logs every
+//!   datagram sent and received: `data1` is the time spent in the send or
+//!   receive call in TSC cycles (shared by all datagrams of a batched
+//!   receive), `data2` the datagram size.
+//! * [`handshake`] drives a `Connecting` to completion, logging the time
+//!   from the start of the handshake until the peer's handshake data arrived
+//!   and until the connection was established.
+//! * [`LossMonitor`] turns increases of the connection's lost-packet counter
+//!   into events, with the RTT estimate at that point in `data1` (cycles)
+//!   and the number of newly lost packets in `data2`.
+
+use crate::{HiResConn, rdtsc};
+use quinn::udp::{RecvMeta, Transmit};
+use quinn::{AsyncUdpSocket, Connecting, Connection, ConnectionError, UdpPoller};
+use std::fmt;
+use std::io::{self, IoSliceMut};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Event IDs logged by the quinn hooks.
+#[derive(Clone, Copy, Debug)]
+pub struct QuicEvents {
+    pub handshake_data: u32,
+    pub established: u32,
+    pub send: u32,
+    pub recv: u32,
+    pub loss: u32,
+}
+
+impl QuicEvents {
+    /// `base` through `base + 4`, in field order.
+    pub const fn from_base(base: u32) -> Self {
+        QuicEvents {
+            handshake_data: base,
+            established: base + 1,
+            send: base + 2,
+            recv: base + 3,
+            loss: base + 4,
+        }
+    }
+}
+
+/// Pass to `quinn::Endpoint::new_with_abstract_socket` in place of the
+/// runtime's socket, e.g. wrapping `runtime.wrap_udp_socket(socket)?`.
+pub struct InstrumentedUdpSocket {
+    inner: Arc<dyn AsyncUdpSocket>,
+    conn: Arc<HiResConn<'static>>,
+    events: QuicEvents,
+}
+
+impl InstrumentedUdpSocket {
+    pub fn new(
+        conn: Arc<HiResConn<'static>>,
+        events: QuicEvents,
+        inner: Arc<dyn AsyncUdpSocket>,
+    ) -> Self {
+        InstrumentedUdpSocket {
+            inner,
+            conn,
+            events,
+        }
+    }
+}
+
+impl fmt::Debug for InstrumentedUdpSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InstrumentedUdpSocket")
+            .field("inner", &self.inner)
+            .field("events", &self.events)
+            .finish()
+    }
+}
+
+impl AsyncUdpSocket for InstrumentedUdpSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        self.inner.clone().create_io_poller()
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        let start = rdtsc();
+        self.inner.try_send(transmit)?;
+        self.conn.log(
+            self.events.send,
+            rdtsc() - start,
+            transmit.contents.len() as u64,
+        );
+        Ok(())
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let start = rdtsc();
+        let poll = self.inner.poll_recv(cx, bufs, meta);
+        if let Poll::Ready(Ok(n)) = poll {
+            let elapsed = rdtsc() - start;
+            for m in &meta[..n] {
+                self.conn.log(self.events.recv, elapsed, m.len as u64);
+            }
+        }
+        poll
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn max_transmit_segments(&self) -> usize {
+        self.inner.max_transmit_segments()
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        self.inner.max_receive_segments()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
+
+/// Awaits a client (`Endpoint::connect`) or server (`Incoming::accept`)
+/// handshake, logging its milestones.
+pub async fn handshake(
+    conn: &HiResConn<'_>,
+    events: QuicEvents,
+    mut connecting: Connecting,
+) -> Result<Connection, ConnectionError> {
+    let start = rdtsc();
+    connecting.handshake_data().await?;
+    conn.log(events.handshake_data, rdtsc() - start, 0);
+    let connection = connecting.await?;
+    conn.log(
+        events.established,
+        rdtsc() - start,
+        connection.stable_id() as u64,
+    );
+    Ok(connection)
+}
+
+/// Polls a connection's loss counter; call [`LossMonitor::sample`]
+/// periodically (e.g. from a timer task or after each request).
+pub struct LossMonitor {
+    events: QuicEvents,
+    lost: u64,
+}
+
+impl LossMonitor {
+    pub fn new(events: QuicEvents) -> Self {
+        LossMonitor { events, lost: 0 }
+    }
+
+    /// Logs a loss event if packets were declared lost since the last call.
+    /// Returns the number of newly lost packets.
+    pub fn sample(&mut self, conn: &HiResConn<'_>, connection: &Connection) -> u64 {
+        let stats = connection.stats();
+        let lost = stats.path.lost_packets;
+        if lost <= self.lost {
+            return 0;
+        }
+        let newly_lost = lost - self.lost;
+        self.lost = lost;
+        let rtt_cycles =
+            (stats.path.rtt.as_nanos() * conn.get_tsc_hz() as u128 / 1_000_000_000) as u64;
+        conn.log(self.events.loss, rtt_cycles, newly_lost);
+        newly_lost
+    }
+}
@@ -0,0 +1,69 @@
+//! The two sides of a hires ring, as traits.
+//!
+//! Instrumented code only logs and the profiler only drains, so helpers
+//! written against [`Producer`] or [`Consumer`] work with a [`HiResConn`]
+//! (whatever its backend) and with [`crate::mock::MockRing`], which runs
+//! the same protocol in plain Rust for loom and miri.
+
+use crate::{HiResConn, log_entry_t};
+
+/// Appends entries.
+pub trait Producer {
+    /// Logs one entry; `false` if the ring was full and it was dropped.
+    fn log(&self, event_id: u32, data1: u64, data2: u64) -> bool;
+}
+
+/// Drains entries in ring order.
+pub trait Consumer {
+    /// Removes and returns the oldest entry, or `None` if the ring is empty
+    /// or that entry is still being written.
+    fn pop(&self) -> Option<log_entry_t>;
+    /// Returns what `pop()` would, leaving it in the ring.
+    fn peek(&self) -> Option<log_entry_t>;
+    /// Appends up to `max` of the entries `pop()` would return next onto
+    /// `out`, oldest first, leaving them in the ring. Stops early like
+    /// `peek()` does; returns how many were added.
+    fn peek_n(&self, out: &mut Vec<log_entry_t>, max: usize) -> usize;
+    /// Entries producers dropped because the ring was full.
+    fn dropped(&self) -> u64;
+
+    /// Pops up to `max` entries onto `out`, stopping early like `pop()`
+    /// does, and returns how many were added.
+    fn pop_batch(&self, out: &mut Vec<log_entry_t>, max: usize) -> usize {
+        let start = out.len();
+        while out.len() - start < max {
+            match self.pop() {
+                Some(entry) => out.push(entry),
+                None => break,
+            }
+        }
+        out.len() - start
+    }
+}
+
+impl<'a> Producer for HiResConn<'a> {
+    #[inline]
+    fn log(&self, event_id: u32, data1: u64, data2: u64) -> bool {
+        HiResConn::log(self, event_id, data1, data2)
+    }
+}
+
+impl<'a> Consumer for HiResConn<'a> {
+    #[inline]
+    fn pop(&self) -> Option<log_entry_t> {
+        HiResConn::pop(self)
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<log_entry_t> {
+        HiResConn::peek(self)
+    }
+
+    fn peek_n(&self, out: &mut Vec<log_entry_t>, max: usize) -> usize {
+        HiResConn::peek_n(self, out, max)
+    }
+
+    fn dropped(&self) -> u64 {
+        self.get_drop_num()
+    }
+}
@@ -0,0 +1,357 @@
+//! The consumer's side of the ring protocol, with every access to shared
+//! memory spelled out as an atomic operation and its ordering.
+//!
+//! Producers (khires, rt.cpp and `native`) reserve a slot with a
+//! `fetch_add` on head, check it against tail (acquire), fill the entry and
+//! publish it with a release store of its VALID flag. The single consumer
+//! reads the entry once it sees VALID, clears the flag and hands the slot
+//! back by storing tail (release). Each ordering below names the access it
+//! pairs with. Weakening one lets the consumer copy an entry its producer
+//! is still writing, or lets a producer overwrite a slot the consumer is
+//! still copying; either shows up only as wrong numbers.
+//!
+//! A producer that breaks the protocol, e.g. khires reserving past a full
+//! ring, can overwrite a slot while the consumer copies it. [`pop_checked`]
+//! re-checks the slot after the copy and reports such torn reads instead
+//! of returning the mix of two entries.
+//!
+//! [`read_tail`], [`read_ahead`] and [`stats`] are generic over
+//! [`RingState`], so [`crate::mock::MockRing`] runs this exact code under
+//! loom (rt/tests/loom_ring.rs) that [`Shm`] runs on the mapped ring.
+
+use crate::{
+    EntryLayout, LOG_FLAG_VALID, hires_rb_stats_t, log_entry_compact_t, log_entry_ext_t,
+    log_entry_t, shared_ring_buffer_t,
+};
+use std::mem::offset_of;
+use std::ptr;
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+
+#[cfg(loom)]
+use loom::sync::atomic::fence;
+#[cfg(not(loom))]
+use std::sync::atomic::fence;
+
+/// How many times `pop()` yields waiting for a reserved entry's VALID flag
+/// before reporting it not ready, as in rt.cpp.
+pub(crate) const MAX_SPINS: u32 = 100;
+
+/// The shared cells of one ring. Implementations perform each access with
+/// the ordering they are given and nothing more; the orderings are chosen
+/// by the functions below.
+pub(crate) trait RingState {
+    type Entry;
+
+    /// Producers' reservation index.
+    fn head(&self, order: Ordering) -> u64;
+    /// The consumer's index: everything before it has been consumed.
+    fn tail(&self, order: Ordering) -> u64;
+    fn set_tail(&self, tail: u64, order: Ordering);
+    /// Entries producers dropped because the ring was full.
+    fn dropped(&self, order: Ordering) -> u64;
+    fn capacity(&self) -> u64;
+    /// Flags of the slot ring position `pos` maps to.
+    fn flags(&self, pos: u64, order: Ordering) -> u16;
+    fn set_flags(&self, pos: u64, flags: u16, order: Ordering);
+    /// Sequence number of the slot `pos` maps to, for layouts that record
+    /// one; producers set it to the position they reserved.
+    fn seqno(&self, pos: u64, order: Ordering) -> Option<u64>;
+
+    /// Copies out the entry at ring position `pos`.
+    ///
+    /// # Safety
+    /// Only called after an acquire load of the slot's flags saw VALID and
+    /// before tail moves past `pos`, so no producer is writing it.
+    unsafe fn read(&self, pos: u64) -> Self::Entry;
+}
+
+/// rt.cpp's `pop()` (`consume`) and `peek()`: the entry at the tail once
+/// its producer has published it, yielding up to `spins` times while it is
+/// reserved but not yet published.
+pub(crate) fn read_tail<R: RingState>(ring: &R, consume: bool, spins: u32) -> Option<R::Entry> {
+    take_tail(ring, consume, spins, false).map(|(entry, _)| entry)
+}
+
+/// The entry at the tail changed while it was copied; see [`pop_checked`].
+#[derive(Debug)]
+pub(crate) struct Torn;
+
+/// `read_tail` consuming, and re-checking the slot after the copy: a slot
+/// whose flags or sequence number changed meanwhile was being overwritten,
+/// and its copy is discarded as [`Torn`]. The slot is consumed either way.
+///
+/// The check catches a producer that stored a flag or the sequence number
+/// during the copy; one that only rewrites the payload with an identical
+/// flag word on a layout without sequence numbers goes unnoticed.
+pub(crate) fn pop_checked<R: RingState>(ring: &R, spins: u32) -> Option<Result<R::Entry, Torn>> {
+    take_tail(ring, true, spins, true).map(|(entry, torn)| if torn { Err(Torn) } else { Ok(entry) })
+}
+
+/// `read_tail`, also returning whether `check` found the entry torn.
+fn take_tail<R: RingState>(
+    ring: &R,
+    consume: bool,
+    spins: u32,
+    check: bool,
+) -> Option<(R::Entry, bool)> {
+    // Relaxed: only this consumer stores tail, so it reads its own last
+    // store.
+    let tail = ring.tail(Ordering::Relaxed);
+    // Acquire, as rt.cpp does. This only tells the consumer the slot was
+    // reserved; the entry's contents are ordered by the flag load below.
+    if tail == ring.head(Ordering::Acquire) {
+        return None;
+    }
+    // Acquire: pairs with the producer's release store of VALID (the
+    // cmpxchg after smp_wmb() in khires), so the copy below sees every
+    // field the producer wrote.
+    let mut waited = 0;
+    let seen = loop {
+        let flags = ring.flags(tail, Ordering::Acquire);
+        if flags & LOG_FLAG_VALID as u16 != 0 {
+            break flags;
+        }
+        if waited == spins {
+            return None;
+        }
+        waited += 1;
+        yield_now();
+    };
+    let entry = unsafe { ring.read(tail) };
+    let torn = check && changed(ring, tail, seen);
+    if consume {
+        // Relaxed: published by the tail store below, and the producer that
+        // reuses the slot only writes it after seeing that store.
+        let flags = ring.flags(tail, Ordering::Relaxed);
+        ring.set_flags(tail, flags & !(LOG_FLAG_VALID as u16), Ordering::Relaxed);
+        // Release: pairs with the producers' acquire load of tail in their
+        // fullness check. A producer that finds this slot free is ordered
+        // after the copy and the cleared flag, so it cannot overwrite the
+        // entry mid-copy and its VALID is the only one the next lap sees.
+        ring.set_tail(tail + 1, Ordering::Release);
+    }
+    Some((entry, torn))
+}
+
+/// Whether the slot at `pos` no longer holds what was copied: its flags
+/// differ from `seen`, loaded before the copy, or its sequence number is
+/// not `pos`.
+fn changed<R: RingState>(ring: &R, pos: u64, seen: u16) -> bool {
+    // Acquire fence: keeps the copy's loads before the loads below, as in a
+    // seqlock reader, so a producer store the copy read from is visible to
+    // them too.
+    fence(Ordering::Acquire);
+    // Relaxed: ordered by the fence.
+    ring.flags(pos, Ordering::Relaxed) != seen
+        || ring.seqno(pos, Ordering::Relaxed).is_some_and(|seqno| seqno != pos)
+}
+
+/// `peek()` for several entries: calls `f` with each entry from the tail
+/// on, in ring order, until `max` entries, the head, or an entry still
+/// unpublished after `spins` yields. Consumes nothing; returns how many
+/// entries `f` was given.
+pub(crate) fn read_ahead<R: RingState>(
+    ring: &R,
+    max: usize,
+    spins: u32,
+    mut f: impl FnMut(R::Entry),
+) -> usize {
+    // Relaxed and acquire, as in read_tail. Reservations at tail + capacity
+    // or later were refused (the ring was full) and hold nothing.
+    let tail = ring.tail(Ordering::Relaxed);
+    let reserved = ring.head(Ordering::Acquire).wrapping_sub(tail);
+    let end = tail + reserved.min(ring.capacity()).min(max as u64);
+    for pos in tail..end {
+        // Acquire: pairs with the producer's release store of VALID, as in
+        // read_tail. A slot past the tail cannot hold a stale VALID: its
+        // previous lap was consumed, which cleared the flag.
+        let mut waited = 0;
+        while ring.flags(pos, Ordering::Acquire) & LOG_FLAG_VALID as u16 == 0 {
+            if waited == spins {
+                return (pos - tail) as usize;
+            }
+            waited += 1;
+            yield_now();
+        }
+        // The consumer is not moving tail, so no producer can reach this
+        // slot's next lap while it is copied.
+        f(unsafe { ring.read(pos) });
+    }
+    (end - tail) as usize
+}
+
+/// Snapshot of the ring's indexes and counters, as rt.cpp's `stats()`.
+pub(crate) fn stats<R: RingState>(ring: &R) -> hires_rb_stats_t {
+    // Acquire on both indexes for the freshest values; the snapshot is not
+    // atomic as a whole and is only used for reporting.
+    let tail = ring.tail(Ordering::Acquire);
+    let head = ring.head(Ordering::Acquire);
+    let capacity = ring.capacity();
+    hires_rb_stats_t {
+        head,
+        tail,
+        // Producers bump head before noticing the ring is full, so head -
+        // tail can exceed the capacity while drops are happening.
+        pending: head.wrapping_sub(tail).min(capacity),
+        capacity,
+        dropped: dropped(ring),
+    }
+}
+
+/// Entries producers dropped because the ring was full.
+pub(crate) fn dropped<R: RingState>(ring: &R) -> u64 {
+    // Relaxed: a counter, ordered with nothing.
+    ring.dropped(Ordering::Relaxed)
+}
+
+#[cfg(loom)]
+fn yield_now() {
+    loom::thread::yield_now();
+}
+
+#[cfg(not(loom))]
+fn yield_now() {
+    std::thread::yield_now();
+}
+
+/// The fields `log_entry_t` has.
+pub(crate) fn to_log_entry(e: log_entry_ext_t) -> log_entry_t {
+    log_entry_t {
+        timestamp: e.timestamp,
+        event_id: e.event_id,
+        cpu_id: e.cpu_id,
+        flags: e.flags,
+        data1: e.data1,
+        data2: e.data2,
+    }
+}
+
+/// A ring mapped into this process.
+#[derive(Clone, Copy)]
+pub(crate) struct Shm {
+    buf: *mut shared_ring_buffer_t,
+    capacity: u64,
+    mask: u64,
+    layout: EntryLayout,
+}
+
+impl Shm {
+    /// # Safety
+    /// `buf` points at a mapped ring of `capacity` entries laid out as
+    /// `layout`, which outlives the returned value.
+    pub(crate) unsafe fn new(
+        buf: *mut shared_ring_buffer_t,
+        capacity: u64,
+        mask: u64,
+        layout: EntryLayout,
+    ) -> Self {
+        Shm {
+            buf,
+            capacity,
+            mask,
+            layout,
+        }
+    }
+
+    /// Entry `pos` of the ring. The capacity khires chose at load time can
+    /// exceed the `buffer` array in the bindings, and the entries need not
+    /// be `log_entry_t`s, so index by pointer. Entries are sized through
+    /// this build's structs for the layout, never through the header.
+    pub(crate) fn entry(&self, pos: u64) -> *mut u8 {
+        unsafe {
+            (&raw mut (*self.buf).buffer)
+                .cast::<u8>()
+                .add((pos & self.mask) as usize * self.layout.entry_size())
+        }
+    }
+
+    pub(crate) fn head_cell(&self) -> &AtomicU64 {
+        unsafe { AtomicU64::from_ptr(&raw mut (*self.buf).head) }
+    }
+
+    pub(crate) fn tail_cell(&self) -> &AtomicU64 {
+        unsafe { AtomicU64::from_ptr(&raw mut (*self.buf).tail) }
+    }
+
+    pub(crate) fn dropped_cell(&self) -> &AtomicU64 {
+        unsafe { AtomicU64::from_ptr(&raw mut (*self.buf).dropped_count) }
+    }
+
+    pub(crate) fn flags_cell(&self, pos: u64) -> &AtomicU16 {
+        let offset = match self.layout {
+            EntryLayout::Standard => offset_of!(log_entry_t, flags),
+            EntryLayout::Compact => offset_of!(log_entry_compact_t, flags),
+            EntryLayout::Extended => offset_of!(log_entry_ext_t, flags),
+        };
+        unsafe { AtomicU16::from_ptr(self.entry(pos).add(offset).cast()) }
+    }
+
+    /// The slot's `seqno`; only the extended layout has one.
+    pub(crate) fn seqno_cell(&self, pos: u64) -> Option<&AtomicU64> {
+        let offset = offset_of!(log_entry_ext_t, seqno);
+        (self.layout == EntryLayout::Extended)
+            .then(|| unsafe { AtomicU64::from_ptr(self.entry(pos).add(offset).cast()) })
+    }
+}
+
+impl RingState for Shm {
+    /// Every layout widened to the extended one; `seqno` is the entry's
+    /// ring position, `tid` 0 unless the ring records it.
+    type Entry = log_entry_ext_t;
+
+    fn head(&self, order: Ordering) -> u64 {
+        self.head_cell().load(order)
+    }
+
+    fn tail(&self, order: Ordering) -> u64 {
+        self.tail_cell().load(order)
+    }
+
+    fn set_tail(&self, tail: u64, order: Ordering) {
+        self.tail_cell().store(tail, order)
+    }
+
+    fn dropped(&self, order: Ordering) -> u64 {
+        self.dropped_cell().load(order)
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn flags(&self, pos: u64, order: Ordering) -> u16 {
+        self.flags_cell(pos).load(order)
+    }
+
+    fn set_flags(&self, pos: u64, flags: u16, order: Ordering) {
+        self.flags_cell(pos).store(flags, order)
+    }
+
+    fn seqno(&self, pos: u64, order: Ordering) -> Option<u64> {
+        self.seqno_cell(pos).map(|cell| cell.load(order))
+    }
+
+    unsafe fn read(&self, pos: u64) -> log_entry_ext_t {
+        let entry = self.entry(pos);
+        macro_rules! widen {
+            ($ty:ty) => {{
+                let e = unsafe { ptr::read(entry.cast::<$ty>()) };
+                log_entry_ext_t {
+                    timestamp: e.timestamp,
+                    event_id: e.event_id,
+                    cpu_id: e.cpu_id as u32,
+                    flags: e.flags,
+                    data1: e.data1,
+                    data2: e.data2,
+                    seqno: pos,
+                    ..Default::default()
+                }
+            }};
+        }
+        match self.layout {
+            EntryLayout::Standard => widen!(log_entry_t),
+            EntryLayout::Compact => widen!(log_entry_compact_t),
+            EntryLayout::Extended => unsafe { ptr::read(entry.cast::<log_entry_ext_t>()) },
+        }
+    }
+}
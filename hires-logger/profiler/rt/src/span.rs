@@ -0,0 +1,18 @@
+//! Span correlation IDs carried in `data2`.
+//!
+//! An event that is one stage of a larger operation (recv, parse, process,
+//! send of one request) logs its duration in `data1` and its place in the
+//! span tree in `data2`: its own span ID in the upper 32 bits and its
+//! parent's span ID in the lower 32 bits. Root spans use a parent ID of 0,
+//! so 0 is never a valid span ID.
+
+#[inline]
+pub fn pack_span_ids(span_id: u32, parent_id: u32) -> u64 {
+    ((span_id as u64) << 32) | parent_id as u64
+}
+
+/// Splits a `data2` value into (span ID, parent span ID).
+#[inline]
+pub fn unpack_span_ids(data2: u64) -> (u32, u32) {
+    ((data2 >> 32) as u32, data2 as u32)
+}
@@ -0,0 +1,139 @@
+//! Multi-entry stack traces.
+//!
+//! A call stack does not fit in one entry, so it is logged as one entry per
+//! frame following this convention:
+//!
+//! * `event_id` is the ID of the event the stack belongs to with
+//!   [`STACK_FRAME_FLAG`] set, marking the entry as a continuation rather than
+//!   a regular sample.
+//! * `data1` is the frame's instruction pointer.
+//! * `data2` packs the stack ID (upper 32 bits), the frame index (bits 16-31)
+//!   and the total number of frames (bits 0-15), see [`pack_frame_tag`].
+//!
+//! Frames from different producers interleave in the ring, so the consumer
+//! reassembles them by (event, stack ID) rather than relying on adjacency.
+
+use crate::HiResConn;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Set in `event_id` for entries carrying one frame of a stack trace.
+pub const STACK_FRAME_FLAG: u32 = 1 << 31;
+
+/// Upper bound on frames captured by [`HiResConn::log_backtrace`].
+pub const MAX_STACK_FRAMES: usize = 64;
+
+static NEXT_STACK_ID: AtomicU32 = AtomicU32::new(1);
+
+#[inline]
+pub fn is_stack_frame(event_id: u32) -> bool {
+    event_id & STACK_FRAME_FLAG != 0
+}
+
+#[inline]
+pub fn pack_frame_tag(stack_id: u32, index: u16, total: u16) -> u64 {
+    ((stack_id as u64) << 32) | ((index as u64) << 16) | total as u64
+}
+
+/// Splits a frame's `data2` into (stack ID, frame index, total frames).
+#[inline]
+pub fn unpack_frame_tag(tag: u64) -> (u32, u16, u16) {
+    ((tag >> 32) as u32, (tag >> 16) as u16, tag as u16)
+}
+
+/// Captures the calling thread's return addresses into `frames`, innermost
+/// first, and returns how many were written.
+#[cfg(target_env = "gnu")]
+pub fn capture_backtrace(frames: &mut [u64]) -> usize {
+    let mut raw = [std::ptr::null_mut::<libc::c_void>(); MAX_STACK_FRAMES + 1];
+    let want = (frames.len() + 1).min(raw.len());
+    let n = unsafe { libc::backtrace(raw.as_mut_ptr(), want as libc::c_int) };
+    // Skip this function's own frame.
+    let captured = (n.max(0) as usize).saturating_sub(1);
+    for (dst, src) in frames.iter_mut().zip(&raw[1..=captured]) {
+        *dst = *src as u64;
+    }
+    captured
+}
+
+/// Captures the calling thread's return addresses into `frames`, innermost
+/// first, and returns how many were written.
+///
+/// musl has no `backtrace(3)`; this walks the stack with the unwinder std
+/// already links in, which is what glibc's implementation does too.
+#[cfg(not(target_env = "gnu"))]
+pub fn capture_backtrace(frames: &mut [u64]) -> usize {
+    use std::ffi::c_void;
+
+    #[repr(C)]
+    struct UnwindContext {
+        _private: [u8; 0],
+    }
+    type TraceFn = extern "C" fn(*mut UnwindContext, *mut c_void) -> libc::c_int;
+    unsafe extern "C" {
+        fn _Unwind_Backtrace(trace: TraceFn, arg: *mut c_void) -> libc::c_int;
+        fn _Unwind_GetIP(ctx: *mut UnwindContext) -> usize;
+    }
+    /// `_URC_NO_REASON` continues the walk, `_URC_NORMAL_STOP` ends it.
+    const CONTINUE: libc::c_int = 0;
+    const STOP: libc::c_int = 4;
+
+    struct Walk<'f> {
+        frames: &'f mut [u64],
+        skip: usize,
+        captured: usize,
+    }
+    extern "C" fn trace(ctx: *mut UnwindContext, arg: *mut c_void) -> libc::c_int {
+        let walk = unsafe { &mut *(arg as *mut Walk) };
+        if walk.skip > 0 {
+            walk.skip -= 1;
+            return CONTINUE;
+        }
+        let Some(slot) = walk.frames.get_mut(walk.captured) else {
+            return STOP;
+        };
+        *slot = unsafe { _Unwind_GetIP(ctx) } as u64;
+        walk.captured += 1;
+        CONTINUE
+    }
+
+    let want = frames.len().min(MAX_STACK_FRAMES);
+    let mut walk = Walk {
+        frames: &mut frames[..want],
+        // Skip this function's own frame.
+        skip: 1,
+        captured: 0,
+    };
+    unsafe { _Unwind_Backtrace(trace, &mut walk as *mut Walk as *mut c_void) };
+    walk.captured
+}
+
+impl<'a> HiResConn<'a> {
+    /// Logs `frames` as a stack trace attached to `event_id`.
+    ///
+    /// # Returns
+    /// `true` if every frame was logged, `false` if any was dropped (the
+    /// consumer then reports the stack as incomplete).
+    pub fn log_stack(&self, event_id: u32, frames: &[u64]) -> bool {
+        if cfg!(feature = "disabled") {
+            return true;
+        }
+        let total = frames.len().min(u16::MAX as usize) as u16;
+        let stack_id = NEXT_STACK_ID.fetch_add(1, Ordering::Relaxed);
+        let mut ok = true;
+        for (i, &ip) in frames.iter().take(total as usize).enumerate() {
+            let tag = pack_frame_tag(stack_id, i as u16, total);
+            ok &= self.log(event_id | STACK_FRAME_FLAG, ip, tag);
+        }
+        ok
+    }
+
+    /// Captures the current call stack and logs it for `event_id`.
+    pub fn log_backtrace(&self, event_id: u32) -> bool {
+        if cfg!(feature = "disabled") {
+            return true;
+        }
+        let mut frames = [0u64; MAX_STACK_FRAMES];
+        let n = capture_backtrace(&mut frames);
+        self.log_stack(event_id, &frames[..n])
+    }
+}
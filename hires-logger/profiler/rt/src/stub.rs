@@ -0,0 +1,116 @@
+//! In-process stand-in for libhires_rt and khires (the `stub` feature).
+//!
+//! Runs `native`'s Rust implementation of the `hires_*` C API over a ring
+//! allocated on the heap instead of mapped from /dev/khires, so code
+//! instrumented with `rt` runs, and can be tested, on machines without the
+//! kernel module or the library. Like the device, the ring is shared by
+//! every connection in the process: entries logged through one can be
+//! popped through another. It is never freed.
+
+use crate::EntryLayout;
+use crate::abi::{entry_layout, layout_checksum};
+use crate::clock::calibrate_tsc_hz;
+use crate::native::Ring;
+use rt_ffi::{HIRES_SHM_MAGIC, RING_BUFFER_SIZE, log_entry_t, shared_ring_buffer_t};
+use std::alloc::{Layout, alloc_zeroed, handle_alloc_error};
+use std::mem::{align_of, offset_of, size_of};
+use std::sync::OnceLock;
+
+struct Shared {
+    buf: *mut shared_ring_buffer_t,
+    capacity: u64,
+    tsc_hz: u64,
+}
+
+// The ring is only accessed through atomics once initialized.
+unsafe impl Send for Shared {}
+unsafe impl Sync for Shared {}
+
+static SHARED: OnceLock<Shared> = OnceLock::new();
+
+/// Bytes of a ring of `capacity` entries: the header, then the entries.
+/// `None` if that does not fit in memory.
+pub(crate) fn ring_size(capacity: u64) -> Option<usize> {
+    usize::try_from(capacity)
+        .ok()?
+        .checked_mul(size_of::<log_entry_t>())?
+        .checked_add(offset_of!(shared_ring_buffer_t, buffer))
+        .filter(|&size| size <= isize::MAX as usize)
+}
+
+/// The process-wide ring, allocated on first use with `capacity` entries
+/// (`RING_BUFFER_SIZE` if 0). Like a ring khires has mapped, it cannot be
+/// resized after that: asking for another capacity fails. Like a module
+/// that predates `HIRES_IOCTL_GET_TSC_INFO`, it reports no TSC info.
+pub(crate) fn ring(capacity: u64) -> Result<Ring, String> {
+    if capacity != 0 && ring_size(capacity).is_none() {
+        return Err(format!(
+            "Requested ring capacity {} is too large for the in-memory ring",
+            capacity
+        ));
+    }
+    let shared = SHARED.get_or_init(|| {
+        let capacity = if capacity == 0 {
+            RING_BUFFER_SIZE as u64
+        } else {
+            capacity
+        };
+        let size = ring_size(capacity).expect("default ring size overflows") as u64;
+        let layout = Layout::from_size_align(size as usize, align_of::<shared_ring_buffer_t>())
+            .expect("stub ring size overflows");
+        let buf = unsafe { alloc_zeroed(layout) } as *mut shared_ring_buffer_t;
+        if buf.is_null() {
+            handle_alloc_error(layout);
+        }
+        // What khires fills in before mapping the ring.
+        unsafe {
+            (*buf).shm_size_bytes_unaligned = size;
+            (*buf).shm_size_bytes_aligned = size;
+            (*buf).capacity = capacity;
+            (*buf).idx_mask = capacity - 1;
+            (*buf).entry_layout = entry_layout(EntryLayout::Standard);
+            (*buf).layout_checksum = layout_checksum(
+                size_of::<log_entry_t>() as u16,
+                capacity,
+                capacity - 1,
+                size,
+            );
+            (*buf).magic = HIRES_SHM_MAGIC;
+        }
+        Shared {
+            buf,
+            capacity,
+            tsc_hz: calibrate_tsc_hz(),
+        }
+    });
+    if capacity != 0 && capacity != shared.capacity {
+        return Err(format!(
+            "Failed to set the ring capacity of the in-memory ring to {}: it is in use with {} entries",
+            capacity, shared.capacity
+        ));
+    }
+    Ok(Ring {
+        buf: shared.buf,
+        capacity: shared.capacity,
+        mask: shared.capacity - 1,
+        shm_size: ring_size(shared.capacity).unwrap() as u64,
+        cycles_per_us: shared.tsc_hz / 1_000_000,
+        tsc_hz: shared.tsc_hz,
+        tsc_info: None,
+        layout: EntryLayout::Standard,
+        device: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_size_is_checked() {
+        let header = offset_of!(shared_ring_buffer_t, buffer);
+        assert_eq!(ring_size(4), Some(header + 4 * size_of::<log_entry_t>()));
+        assert_eq!(ring_size(u64::MAX), None);
+        assert_eq!(ring_size(1 << 62), None);
+    }
+}
@@ -0,0 +1,115 @@
+//! Thread identities for per-thread analysis, the userspace side of
+//! `HIRES_EV_THREAD_NAME`.
+//!
+//! [`HiResConn::log_tid`] stamps the calling thread's TID into `data2`, the
+//! field the profiler's `--tid-field data2` groups by. (Rings in the
+//! extended entry layout record the TID of every entry in `tid` already.)
+//! The first time a thread logs this way it also registers its name: one
+//! `HIRES_EV_THREAD_NAME` entry per 8 bytes of it, which the profiler
+//! collects into its TID-to-name table so reports show `worker-3` rather
+//! than a bare number.
+//!
+//! A thread's name is its `std::thread` name if it has one, else the
+//! kernel's (`/proc/thread-self/comm`), so threads started from C are
+//! named too.
+
+use crate::{HIRES_EV_THREAD_NAME, HIRES_THREAD_NAME_MAX, HiResConn};
+use std::cell::Cell;
+use std::fs;
+
+const PART_BYTES: usize = 8;
+
+/// The calling thread's kernel TID, from one `gettid()` per thread.
+#[inline]
+pub fn current_tid() -> u32 {
+    thread_local! {
+        static TID: u32 = unsafe { libc::gettid() } as u32;
+    }
+    TID.with(|tid| *tid)
+}
+
+/// Same layout as `HIRES_THREAD_NAME_DATA1`.
+#[inline]
+pub fn pack_name_tag(tid: u32, part: u16, parts: u16) -> u64 {
+    ((parts as u64) << 48) | ((part as u64) << 32) | tid as u64
+}
+
+/// Splits a name entry's `data1` into (TID, part index, part count).
+#[inline]
+pub fn unpack_name_tag(tag: u64) -> (u32, u16, u16) {
+    (tag as u32, (tag >> 32) as u16, (tag >> 48) as u16)
+}
+
+/// The name parts' `data2` values, in order; `name` is cut at
+/// `HIRES_THREAD_NAME_MAX` bytes.
+pub fn name_parts(name: &str) -> impl Iterator<Item = u64> + '_ {
+    let bytes = &name.as_bytes()[..name.len().min(HIRES_THREAD_NAME_MAX as usize)];
+    bytes.chunks(PART_BYTES).map(|chunk| {
+        let mut part = [0u8; PART_BYTES];
+        part[..chunk.len()].copy_from_slice(chunk);
+        u64::from_le_bytes(part)
+    })
+}
+
+/// Reassembles a name from its parts' `data2` values, dropping the zero
+/// padding. Invalid UTF-8 (a name cut mid-character) is replaced.
+pub fn name_from_parts(parts: &[u64]) -> String {
+    let bytes: Vec<u8> = parts.iter().flat_map(|p| p.to_le_bytes()).collect();
+    let len = bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+/// The calling thread's name, see the module docs.
+pub fn current_name() -> Option<String> {
+    if let Some(name) = std::thread::current().name() {
+        return Some(name.to_string());
+    }
+    let comm = fs::read_to_string("/proc/thread-self/comm").ok()?;
+    Some(comm.trim_end_matches('\n').to_string())
+}
+
+impl<'a> HiResConn<'a> {
+    /// Logs `name` as the name of thread `tid`. Returns `false` if any part
+    /// was dropped.
+    pub fn register_thread_name(&self, tid: u32, name: &str) -> bool {
+        self.log_name(HIRES_EV_THREAD_NAME, tid, name)
+    }
+
+    /// Logs `name` as one `event_id` entry per part, tagged with `key` (a
+    /// TID, or an event ID for `HIRES_EV_EVENT_NAME`).
+    pub(crate) fn log_name(&self, event_id: u32, key: u32, name: &str) -> bool {
+        let parts = name
+            .len()
+            .min(HIRES_THREAD_NAME_MAX as usize)
+            .div_ceil(PART_BYTES) as u16;
+        let mut logged = true;
+        for (i, part) in name_parts(name).enumerate() {
+            logged &= self.log(event_id, pack_name_tag(key, i as u16, parts), part);
+        }
+        logged
+    }
+
+    /// Logs an entry with the calling thread's TID in `data2`, registering
+    /// the thread's name on its first call.
+    ///
+    /// The name is registered once per thread, not per connection; a thread
+    /// logging to several connections should call
+    /// [`register_thread_name`](Self::register_thread_name) on the others.
+    #[inline]
+    pub fn log_tid(&self, event_id: u32, data1: u64) -> bool {
+        if cfg!(feature = "disabled") {
+            return true;
+        }
+        thread_local! {
+            static REGISTERED: Cell<bool> = const { Cell::new(false) };
+        }
+        let tid = current_tid();
+        if !REGISTERED.get() {
+            REGISTERED.set(true);
+            if let Some(name) = current_name() {
+                self.register_thread_name(tid, &name);
+            }
+        }
+        self.log(event_id, data1, tid as u64)
+    }
+}
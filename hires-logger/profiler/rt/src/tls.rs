@@ -0,0 +1,94 @@
+//! TLS record crypto events, the userspace side of `HIRES_EV_TLS`.
+//!
+//! khires (`tls_probes=1`) logs one entry per kTLS record it encrypts or
+//! decrypts; userspace TLS stacks log the same reserved event here, so the
+//! profiler can report crypto time next to (and separately from) transport
+//! latency whichever side does the work. `data1` is the time spent in TSC
+//! cycles, `data2` packs the direction and payload length (see
+//! [`tls_data2`]).
+//!
+//! With the `rustls` feature, [`write_plaintext`] and
+//! [`process_new_packets`] wrap the rustls calls that encrypt and decrypt.
+//! A rustls call may cover several records, so those entries are per call
+//! rather than per record.
+
+use crate::{
+    HIRES_EV_TLS, HIRES_TLS_DECRYPT, HIRES_TLS_ENCRYPT, HIRES_TLS_FAILED, HiResConn, rdtsc,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Encrypt,
+    Decrypt,
+}
+
+/// Same layout as `HIRES_TLS_DATA2`, plus `HIRES_TLS_FAILED`.
+#[inline]
+pub const fn tls_data2(direction: Direction, len: u32, failed: bool) -> u64 {
+    let dir = match direction {
+        Direction::Encrypt => HIRES_TLS_ENCRYPT,
+        Direction::Decrypt => HIRES_TLS_DECRYPT,
+    };
+    let data2 = ((dir as u64) << 32) | len as u64;
+    if failed {
+        data2 | HIRES_TLS_FAILED
+    } else {
+        data2
+    }
+}
+
+impl<'a> HiResConn<'a> {
+    /// Times `op`, which encrypts or decrypts `len` bytes, and logs it as a
+    /// TLS crypto entry; an `Err` is flagged as failed.
+    #[inline]
+    pub fn log_tls<T, E, F: FnOnce() -> Result<T, E>>(
+        &self,
+        direction: Direction,
+        len: usize,
+        op: F,
+    ) -> Result<T, E> {
+        let start = rdtsc();
+        let result = op();
+        let len = len.min(u32::MAX as usize) as u32;
+        self.log(
+            HIRES_EV_TLS,
+            rdtsc() - start,
+            tls_data2(direction, len, result.is_err()),
+        );
+        result
+    }
+}
+
+/// Writes `buf` through `tls.writer()`, which encrypts it into TLS records
+/// once the handshake is done.
+#[cfg(feature = "rustls")]
+pub fn write_plaintext<D>(
+    conn: &HiResConn,
+    tls: &mut rustls::ConnectionCommon<D>,
+    buf: &[u8],
+) -> std::io::Result<usize> {
+    use std::io::Write;
+    conn.log_tls(Direction::Encrypt, buf.len(), || tls.writer().write(buf))
+}
+
+/// Runs `tls.process_new_packets()`, which decrypts the records read so
+/// far. The length logged is the plaintext buffered afterwards.
+#[cfg(feature = "rustls")]
+pub fn process_new_packets<D>(
+    conn: &HiResConn,
+    tls: &mut rustls::ConnectionCommon<D>,
+) -> Result<rustls::IoState, rustls::Error> {
+    let start = rdtsc();
+    let result = tls.process_new_packets();
+    let len = result.as_ref().map_or(0, |s| s.plaintext_bytes_to_read());
+    conn.log(
+        HIRES_EV_TLS,
+        rdtsc() - start,
+        tls_data2(
+            Direction::Decrypt,
+            len.min(u32::MAX as usize) as u32,
+            result.is_err(),
+        ),
+    );
+    result
+}
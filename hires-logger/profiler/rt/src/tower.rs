@@ -0,0 +1,228 @@
+//! tower middleware for HTTP/gRPC request latency (`tower` feature).
+//!
+//! [`HiResLayer`] goes into a hyper, axum or tonic service stack and logs
+//! every request with a fresh correlation ID ([`corr::next_id`]) in `data2`:
+//!
+//! * `start` when the request reaches the service, with the request body's
+//!   size hint (its `Content-Length`, if known) in `data1`;
+//! * `head` when the inner service returns the response head, and `end`
+//!   when the response body has been streamed out (or the service failed),
+//!   both with the cycles since `start` in `data1`;
+//! * `response_bytes` next to `end`, with the body bytes sent in `data1`.
+//!
+//! The ID is also put into the request's extensions as a [`RequestId`], so
+//! handlers can log their own events against the same request.
+
+use crate::{HiResConn, corr, rdtsc};
+use bytes::Buf;
+use http::{Request, Response};
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Event IDs logged by [`HiResLayer`].
+#[derive(Clone, Copy, Debug)]
+pub struct RequestEvents {
+    pub start: u32,
+    pub head: u32,
+    pub end: u32,
+    pub response_bytes: u32,
+}
+
+impl RequestEvents {
+    /// `base` through `base + 3`, in field order.
+    pub const fn from_base(base: u32) -> Self {
+        RequestEvents {
+            start: base,
+            head: base + 1,
+            end: base + 2,
+            response_bytes: base + 3,
+        }
+    }
+}
+
+/// Correlation ID of a request, as logged in `data2`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RequestId(pub u64);
+
+#[derive(Clone)]
+pub struct HiResLayer {
+    conn: Arc<HiResConn<'static>>,
+    events: RequestEvents,
+}
+
+impl HiResLayer {
+    pub fn new(conn: Arc<HiResConn<'static>>, events: RequestEvents) -> Self {
+        HiResLayer { conn, events }
+    }
+}
+
+impl<S> Layer<S> for HiResLayer {
+    type Service = HiResService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HiResService {
+            inner,
+            conn: self.conn.clone(),
+            events: self.events,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HiResService<S> {
+    inner: S,
+    conn: Arc<HiResConn<'static>>,
+    events: RequestEvents,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for HiResService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ReqBody: Body,
+    ResBody: Body,
+{
+    type Response = Response<CountingBody<ResBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let id = corr::next_id();
+        req.extensions_mut().insert(RequestId(id));
+        self.conn
+            .log(self.events.start, req.body().size_hint().lower(), id);
+        let tracker = Tracker {
+            conn: self.conn.clone(),
+            events: self.events,
+            id,
+            start: rdtsc(),
+        };
+        ResponseFuture {
+            inner: self.inner.call(req),
+            tracker: Some(tracker),
+        }
+    }
+}
+
+struct Tracker {
+    conn: Arc<HiResConn<'static>>,
+    events: RequestEvents,
+    id: u64,
+    start: u64,
+}
+
+impl Tracker {
+    fn head(&self) {
+        self.conn
+            .log(self.events.head, rdtsc() - self.start, self.id);
+    }
+
+    fn finish(self, bytes: u64) {
+        self.conn
+            .log(self.events.end, rdtsc() - self.start, self.id);
+        self.conn.log(self.events.response_bytes, bytes, self.id);
+    }
+}
+
+pin_project! {
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        tracker: Option<Tracker>,
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Body,
+{
+    type Output = Result<Response<CountingBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = ready!(this.inner.poll(cx));
+        let tracker = this
+            .tracker
+            .take()
+            .expect("ResponseFuture polled after completion");
+        Poll::Ready(match result {
+            Ok(res) => {
+                tracker.head();
+                Ok(res.map(|body| {
+                    // Empty bodies may never be polled.
+                    let tracker = if body.is_end_stream() {
+                        tracker.finish(0);
+                        None
+                    } else {
+                        Some(tracker)
+                    };
+                    CountingBody {
+                        inner: body,
+                        bytes: 0,
+                        tracker,
+                    }
+                }))
+            }
+            Err(e) => {
+                tracker.finish(0);
+                Err(e)
+            }
+        })
+    }
+}
+
+pin_project! {
+    /// Response body that logs `end` and `response_bytes` once it has been
+    /// fully streamed. Nothing is logged if it is dropped before that, e.g.
+    /// because the client went away.
+    pub struct CountingBody<B> {
+        #[pin]
+        inner: B,
+        bytes: u64,
+        tracker: Option<Tracker>,
+    }
+}
+
+impl<B: Body> Body for CountingBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut this = self.project();
+        let frame = ready!(this.inner.as_mut().poll_frame(cx));
+        let done = match &frame {
+            Some(Ok(f)) => {
+                if let Some(data) = f.data_ref() {
+                    *this.bytes += data.remaining() as u64;
+                }
+                this.inner.is_end_stream()
+            }
+            Some(Err(_)) | None => true,
+        };
+        if done && let Some(tracker) = this.tracker.take() {
+            tracker.finish(*this.bytes);
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
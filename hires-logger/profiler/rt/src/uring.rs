@@ -0,0 +1,184 @@
+//! io_uring submission-to-completion latency.
+//!
+//! [`UringTags`] replaces each SQE's `user_data` with a tag that remembers
+//! the original value, the event to log and, once submitted, the TSC at
+//! submission. When the completion is reaped it logs the event with the
+//! submit-to-completion time in TSC cycles in `data1` and the CQE result
+//! (sign-extended) in `data2`, then hands the original `user_data` back.
+//! It works with any io_uring binding; with the `io-uring` feature,
+//! [`InstrumentedRing`] applies it to an `io_uring::IoUring` directly.
+
+use crate::{HiResConn, rdtsc};
+use std::collections::HashMap;
+
+struct Inflight {
+    user_data: u64,
+    event_id: u32,
+    /// 0 until the SQE has been submitted.
+    submitted: u64,
+}
+
+#[derive(Default)]
+pub struct UringTags {
+    inflight: HashMap<u64, Inflight>,
+    /// Tags pushed to the SQ but not yet submitted.
+    queued: Vec<u64>,
+    next_tag: u64,
+}
+
+impl UringTags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the tag to put in the SQE's `user_data` in place of
+    /// `user_data`.
+    pub fn tag(&mut self, event_id: u32, user_data: u64) -> u64 {
+        let tag = self.next_tag;
+        self.next_tag = self.next_tag.wrapping_add(1);
+        self.inflight.insert(
+            tag,
+            Inflight {
+                user_data,
+                event_id,
+                submitted: 0,
+            },
+        );
+        self.queued.push(tag);
+        tag
+    }
+
+    /// Call right after `io_uring_enter` submitted the queued SQEs.
+    pub fn submitted(&mut self) {
+        let now = rdtsc();
+        for tag in self.queued.drain(..) {
+            if let Some(i) = self.inflight.get_mut(&tag) {
+                i.submitted = now;
+            }
+        }
+    }
+
+    /// Logs the completion of `tag` and returns the original `user_data`,
+    /// or `None` for CQEs that were not tagged. With `more` set (multishot
+    /// requests, `IORING_CQE_F_MORE`) the tag stays live for later CQEs.
+    pub fn complete(
+        &mut self,
+        conn: &HiResConn<'_>,
+        tag: u64,
+        result: i32,
+        more: bool,
+    ) -> Option<u64> {
+        let now = rdtsc();
+        let inflight = self.inflight.get(&tag)?;
+        let (user_data, event_id, submitted) =
+            (inflight.user_data, inflight.event_id, inflight.submitted);
+        if !more {
+            self.inflight.remove(&tag);
+        }
+        if submitted != 0 {
+            conn.log(
+                event_id,
+                now.saturating_sub(submitted),
+                result as i64 as u64,
+            );
+        }
+        Some(user_data)
+    }
+
+    /// Requests pushed but not yet completed.
+    pub fn inflight(&self) -> usize {
+        self.inflight.len()
+    }
+}
+
+#[cfg(feature = "io-uring")]
+pub use ring::{Completion, InstrumentedRing};
+
+#[cfg(feature = "io-uring")]
+mod ring {
+    use super::UringTags;
+    use crate::HiResConn;
+    use io_uring::squeue::PushError;
+    use io_uring::{IoUring, cqueue, squeue};
+    use std::io;
+
+    /// A reaped CQE with its original `user_data` restored.
+    #[derive(Clone, Copy, Debug)]
+    pub struct Completion {
+        pub user_data: u64,
+        pub result: i32,
+        pub flags: u32,
+    }
+
+    /// `IoUring` whose SQEs are tagged and whose completions are logged.
+    pub struct InstrumentedRing<'c, 'a> {
+        ring: IoUring,
+        conn: &'c HiResConn<'a>,
+        tags: UringTags,
+    }
+
+    impl<'c, 'a> InstrumentedRing<'c, 'a> {
+        pub fn new(conn: &'c HiResConn<'a>, ring: IoUring) -> Self {
+            InstrumentedRing {
+                ring,
+                conn,
+                tags: UringTags::new(),
+            }
+        }
+
+        /// Pushes an SQE whose completion is logged as `event_id`.
+        ///
+        /// # Safety
+        ///
+        /// As for [`io_uring::SubmissionQueue::push`]: the buffers the entry
+        /// refers to must stay valid until it completes.
+        pub unsafe fn push(
+            &mut self,
+            entry: &squeue::Entry,
+            event_id: u32,
+        ) -> Result<(), PushError> {
+            let tag = self.tags.tag(event_id, entry.get_user_data());
+            let tagged = entry.clone().user_data(tag);
+            let result = unsafe { self.ring.submission().push(&tagged) };
+            if result.is_err() {
+                self.tags.inflight.remove(&tag);
+                self.tags.queued.pop();
+            }
+            result
+        }
+
+        pub fn submit(&mut self) -> io::Result<usize> {
+            let n = self.ring.submit()?;
+            self.tags.submitted();
+            Ok(n)
+        }
+
+        pub fn submit_and_wait(&mut self, want: usize) -> io::Result<usize> {
+            let n = self.ring.submit_and_wait(want)?;
+            self.tags.submitted();
+            Ok(n)
+        }
+
+        /// Reaps all available CQEs, logging each one.
+        pub fn completions(&mut self) -> Vec<Completion> {
+            let mut reaped = Vec::new();
+            for cqe in self.ring.completion() {
+                let more = cqueue::more(cqe.flags());
+                let user_data = self
+                    .tags
+                    .complete(self.conn, cqe.user_data(), cqe.result(), more)
+                    .unwrap_or(cqe.user_data());
+                reaped.push(Completion {
+                    user_data,
+                    result: cqe.result(),
+                    flags: cqe.flags(),
+                });
+            }
+            reaped
+        }
+
+        pub fn ring(&mut self) -> &mut IoUring {
+            &mut self.ring
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! Userspace side of interrupt-to-wakeup latency.
+//!
+//! Call [`HiResConn::log_wakeup`] right after a blocking call (epoll_wait,
+//! recv) returns. The entry carries the TSC at wakeup in `data1`, so it pairs
+//! with the kernel's TSC-stamped `HIRES_EV_IRQ` entries whatever the
+//! connection's timestamp source, and the pairing key in `data2`.
+
+use crate::{HIRES_EV_WAKEUP, HiResConn};
+use std::fs;
+
+impl<'a> HiResConn<'a> {
+    /// Logs a wakeup to be paired with the interrupt identified by `key`,
+    /// usually the IRQ number serving the socket's queue (see [`find_irq`]).
+    #[inline]
+    pub fn log_wakeup(&self, key: u64) -> bool {
+        self.log(HIRES_EV_WAKEUP, crate::rdtsc(), key)
+    }
+}
+
+/// Looks up the IRQ number whose /proc/interrupts action name contains
+/// `name`, e.g. `virtio0-input.0` for the first virtio-net RX queue.
+pub fn find_irq(name: &str) -> Option<u64> {
+    let interrupts = fs::read_to_string("/proc/interrupts").ok()?;
+    interrupts
+        .lines()
+        .filter(|line| {
+            line.split_whitespace()
+                .last()
+                .is_some_and(|a| a.contains(name))
+        })
+        .find_map(|line| line.split(':').next()?.trim().parse().ok())
+}
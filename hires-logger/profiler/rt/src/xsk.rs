@@ -0,0 +1,122 @@
+//! AF_XDP socket instrumentation.
+//!
+//! [`XskInstrument`] sits next to whichever xsk binding the application uses
+//! and is called around its ring operations:
+//!
+//! * [`fill`](XskInstrument::fill), [`rx`](XskInstrument::rx) and
+//!   [`tx`](XskInstrument::tx) time a batch operation on the fill, RX or TX
+//!   ring (including the wakeup syscall, if the closure makes one) and log
+//!   the cycles in `data1` and the number of descriptors in `data2`.
+//!   Batches that moved nothing are not logged.
+//! * [`rx_frame`](XskInstrument::rx_frame) and
+//!   [`tx_frame`](XskInstrument::tx_frame) log one entry per frame with its
+//!   [`frame_key`] in `data2`, the same key the XDP hooks and socket-level
+//!   events use, so AF_XDP and kernel-socket paths can be joined and
+//!   compared per packet.
+//! * [`completed`](XskInstrument::completed) is fed the UMEM addresses read
+//!   from the completion ring and logs each frame's TX-to-completion time,
+//!   with the address in `data2`.
+
+use crate::net::frame_key;
+use crate::{HiResConn, rdtsc};
+use std::collections::HashMap;
+
+/// Event IDs logged by [`XskInstrument`].
+#[derive(Clone, Copy, Debug)]
+pub struct XskEvents {
+    pub fill: u32,
+    pub rx: u32,
+    pub rx_frame: u32,
+    pub tx: u32,
+    pub tx_frame: u32,
+    pub completion: u32,
+}
+
+impl XskEvents {
+    /// `base` through `base + 5`, in field order.
+    pub const fn from_base(base: u32) -> Self {
+        XskEvents {
+            fill: base,
+            rx: base + 1,
+            rx_frame: base + 2,
+            tx: base + 3,
+            tx_frame: base + 4,
+            completion: base + 5,
+        }
+    }
+}
+
+pub struct XskInstrument<'c, 'a> {
+    conn: &'c HiResConn<'a>,
+    events: XskEvents,
+    /// UMEM address -> TSC when the frame was queued for TX.
+    tx_pending: HashMap<u64, u64>,
+}
+
+impl<'c, 'a> XskInstrument<'c, 'a> {
+    pub fn new(conn: &'c HiResConn<'a>, events: XskEvents) -> Self {
+        XskInstrument {
+            conn,
+            events,
+            tx_pending: HashMap::new(),
+        }
+    }
+
+    #[inline]
+    fn batch<F: FnOnce() -> usize>(&self, event_id: u32, op: F) -> usize {
+        let start = rdtsc();
+        let n = op();
+        if n > 0 {
+            self.conn.log(event_id, rdtsc() - start, n as u64);
+        }
+        n
+    }
+
+    /// Times producing `op()` descriptors to the fill ring.
+    #[inline]
+    pub fn fill<F: FnOnce() -> usize>(&self, op: F) -> usize {
+        self.batch(self.events.fill, op)
+    }
+
+    /// Times consuming `op()` descriptors from the RX ring.
+    #[inline]
+    pub fn rx<F: FnOnce() -> usize>(&self, op: F) -> usize {
+        self.batch(self.events.rx, op)
+    }
+
+    /// Times producing `op()` descriptors to the TX ring.
+    #[inline]
+    pub fn tx<F: FnOnce() -> usize>(&self, op: F) -> usize {
+        self.batch(self.events.tx, op)
+    }
+
+    /// Logs a received frame.
+    #[inline]
+    pub fn rx_frame(&self, frame: &[u8]) -> bool {
+        self.conn.log(self.events.rx_frame, 0, frame_key(frame))
+    }
+
+    /// Logs a frame about to be put on the TX ring at UMEM address `addr`
+    /// and starts its completion timer.
+    #[inline]
+    pub fn tx_frame(&mut self, addr: u64, frame: &[u8]) -> bool {
+        self.tx_pending.insert(addr, rdtsc());
+        self.conn.log(self.events.tx_frame, 0, frame_key(frame))
+    }
+
+    /// Logs the completion of frames read from the completion ring.
+    pub fn completed<I: IntoIterator<Item = u64>>(&mut self, addrs: I) {
+        let now = rdtsc();
+        for addr in addrs {
+            if let Some(queued) = self.tx_pending.remove(&addr) {
+                self.conn
+                    .log(self.events.completion, now.saturating_sub(queued), addr);
+            }
+        }
+    }
+
+    /// Frames queued for TX whose completion has not been seen yet.
+    pub fn tx_inflight(&self) -> usize {
+        self.tx_pending.len()
+    }
+}
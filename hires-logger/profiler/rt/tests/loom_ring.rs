@@ -0,0 +1,168 @@
+//! Loom models of the ring protocol. The consumer side is `shm::read_tail`,
+//! the code `HiResConn` drains the mapped ring with, run over
+//! `rt::mock::MockRing`; the producer side is rt.cpp's, as MockRing copies
+//! it. Built without `--cfg loom` this file is empty.
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test -p rt --features stub --release --test loom_ring
+//! ```
+//!
+//! `stub` only keeps the test binary from linking libhires_rt.
+#![cfg(loom)]
+
+use loom::sync::Arc;
+use loom::thread;
+use rt::log_entry_t;
+use rt::mock::MockRing;
+use rt::ring::{Consumer, Producer};
+
+/// Logs entry `id` with `data2 = !data1`, so a copy torn between two
+/// producers' writes shows.
+fn log(ring: &MockRing, id: u64) -> bool {
+    ring.log(1, id, !id)
+}
+
+fn drain(ring: &MockRing, out: &mut Vec<log_entry_t>) {
+    while let Some(entry) = ring.pop() {
+        out.push(entry);
+    }
+}
+
+/// Every entry whole and in reservation order (MockRing stamps each with
+/// its head index).
+fn check(seen: &[log_entry_t]) {
+    for entry in seen {
+        assert_eq!(entry.data2, !entry.data1, "torn entry {:?}", entry);
+    }
+    for pair in seen.windows(2) {
+        assert!(pair[0].timestamp < pair[1].timestamp, "out of order: {:?}", pair);
+    }
+}
+
+/// The consumer races two producers: whatever it pops is complete, and
+/// after they finish it gets the rest.
+#[test]
+fn concurrent_producers_publish_whole_entries() {
+    loom::model(|| {
+        let ring = Arc::new(MockRing::new(4));
+        let producers: Vec<_> = (0..2)
+            .map(|id| {
+                let ring = ring.clone();
+                thread::spawn(move || assert!(log(&ring, id)))
+            })
+            .collect();
+        let mut seen: Vec<_> = ring.pop().into_iter().collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        drain(&ring, &mut seen);
+        check(&seen);
+        assert_eq!(seen.len(), 2);
+        assert_eq!(ring.dropped(), 0);
+    });
+}
+
+/// A one-slot ring: the second entry can only go into the slot the
+/// consumer is copying the first one out of. The tail store (release) and
+/// the producer's tail load (acquire) keep the two apart; the entry is
+/// either written after the copy or dropped.
+#[test]
+fn slot_is_reused_only_after_the_consumer_copied_it() {
+    loom::model(|| {
+        let ring = Arc::new(MockRing::new(1));
+        assert!(log(&ring, 0));
+        let producer = {
+            let ring = ring.clone();
+            thread::spawn(move || log(&ring, 1))
+        };
+        let mut seen: Vec<_> = ring.pop().into_iter().collect();
+        let logged = producer.join().unwrap();
+        drain(&ring, &mut seen);
+        check(&seen);
+        assert_eq!(logged, ring.dropped() == 0);
+        assert_eq!(seen.len() as u64 + ring.dropped(), 2);
+    });
+}
+
+/// What `peek()` returns is what the next `pop()` removes, with a producer
+/// publishing in between.
+#[test]
+fn peek_returns_what_pop_removes() {
+    loom::model(|| {
+        let ring = Arc::new(MockRing::new(2));
+        let producer = {
+            let ring = ring.clone();
+            thread::spawn(move || assert!(log(&ring, 7)))
+        };
+        let peeked = ring.peek();
+        let popped = ring.pop();
+        producer.join().unwrap();
+        if let Some(peeked) = peeked {
+            let popped = popped.expect("peeked entry was not popped");
+            assert_eq!(
+                (peeked.timestamp, peeked.data1, peeked.data2),
+                (popped.timestamp, popped.data1, popped.data2)
+            );
+        }
+        check(&popped.into_iter().collect::<Vec<_>>());
+    });
+}
+
+/// `peek_n()` sees a prefix of what the pops then remove, whole and in
+/// order, while a producer publishes into the slot the ring just wrapped
+/// to, whose previous entry was already consumed.
+#[test]
+fn peek_n_sees_what_pops_remove() {
+    loom::model(|| {
+        let ring = Arc::new(MockRing::new(2));
+        assert!(log(&ring, 0));
+        assert!(ring.pop().is_some());
+        assert!(log(&ring, 1));
+        let producer = {
+            let ring = ring.clone();
+            thread::spawn(move || assert!(log(&ring, 2)))
+        };
+        let mut peeked = Vec::new();
+        let n = ring.peek_n(&mut peeked, 4);
+        producer.join().unwrap();
+        let mut popped = Vec::new();
+        drain(&ring, &mut popped);
+        check(&peeked);
+        check(&popped);
+        assert_eq!(n, peeked.len());
+        assert!((1..=2).contains(&n));
+        assert_eq!(popped.len(), 2);
+        for (p, q) in peeked.iter().zip(&popped) {
+            assert_eq!(
+                (p.timestamp, p.data1, p.data2),
+                (q.timestamp, q.data1, q.data2)
+            );
+        }
+    });
+}
+
+/// With producers that follow the protocol nothing is ever torn: the
+/// checked pop's re-check after the copy never fires, whatever the
+/// interleaving, and it returns what `pop()` would.
+#[test]
+fn checked_pop_reports_no_torn_reads_from_correct_producers() {
+    loom::model(|| {
+        let ring = Arc::new(MockRing::new(2));
+        let producers: Vec<_> = (0..2)
+            .map(|id| {
+                let ring = ring.clone();
+                thread::spawn(move || assert!(log(&ring, id)))
+            })
+            .collect();
+        let mut seen: Vec<_> = ring.pop_checked().into_iter().collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        while let Some(entry) = ring.pop_checked() {
+            seen.push(entry);
+        }
+        check(&seen);
+        assert_eq!(seen.len(), 2);
+        assert_eq!(ring.torn_reads(), 0);
+    });
+}
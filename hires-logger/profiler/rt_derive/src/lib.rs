@@ -0,0 +1,208 @@
+//! `#[derive(HiresEvent)]`, re-exported by `rt` with its `derive` feature.
+//! See `rt::event` for what the generated implementation does.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use std::collections::HashMap;
+use syn::{Data, DeriveInput, Error, Fields, Ident, LitInt, LitStr, parse_macro_input};
+
+/// `HIRES_EV_RESERVED_FIRST`; IDs from here up belong to the runtime and
+/// khires.
+const FIRST_RESERVED_ID: u32 = 232;
+/// `HIRES_THREAD_NAME_MAX`, which event names share.
+const NAME_MAX: usize = 32;
+/// One field per data word.
+const MAX_FIELDS: usize = 2;
+
+#[proc_macro_derive(HiresEvent, attributes(hires))]
+pub fn derive_hires_event(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+struct Variant<'a> {
+    ident: &'a Ident,
+    id: u32,
+    name: String,
+    fields: &'a Fields,
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "HiresEvent can only be derived for enums",
+        ));
+    };
+
+    let mut next_id = 0u32;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("hires")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("base") {
+                next_id = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("expected `base = <event ID>`"))
+            }
+        })?;
+    }
+
+    let mut variants = Vec::new();
+    let mut seen: HashMap<u32, &Ident> = HashMap::new();
+    for v in &data.variants {
+        let mut id = next_id;
+        let mut name = v.ident.to_string();
+        let mut id_span = v.ident.span();
+        for attr in v.attrs.iter().filter(|a| a.path().is_ident("hires")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("id") {
+                    let lit: LitInt = meta.value()?.parse()?;
+                    id = lit.base10_parse()?;
+                    id_span = lit.span();
+                    Ok(())
+                } else if meta.path.is_ident("name") {
+                    name = meta.value()?.parse::<LitStr>()?.value();
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `id = <event ID>` or `name = \"...\"`"))
+                }
+            })?;
+        }
+
+        if id >= FIRST_RESERVED_ID {
+            return Err(Error::new(
+                id_span,
+                format!(
+                    "event ID {} of `{}` is reserved; application events use 0..{}",
+                    id, v.ident, FIRST_RESERVED_ID
+                ),
+            ));
+        }
+        if let Some(other) = seen.insert(id, &v.ident) {
+            return Err(Error::new(
+                id_span,
+                format!(
+                    "event ID {} of `{}` is already used by `{}`",
+                    id, v.ident, other
+                ),
+            ));
+        }
+        if name.is_empty() || name.len() > NAME_MAX {
+            return Err(Error::new_spanned(
+                &v.ident,
+                format!("event names are 1 to {} bytes long", NAME_MAX),
+            ));
+        }
+        if v.fields.len() > MAX_FIELDS {
+            return Err(Error::new_spanned(
+                &v.fields,
+                "an event carries at most two fields, `data1` and `data2`",
+            ));
+        }
+
+        variants.push(Variant {
+            ident: &v.ident,
+            id,
+            name,
+            fields: &v.fields,
+        });
+        next_id = id + 1;
+    }
+
+    let ty = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let table = variants.iter().map(|v| {
+        let (id, name) = (v.id, &v.name);
+        quote!((#id, #name))
+    });
+    let ids = variants.iter().map(|v| {
+        let (ident, id) = (v.ident, v.id);
+        quote!(Self::#ident { .. } => #id)
+    });
+    let names = variants.iter().map(|v| {
+        let (ident, name) = (v.ident, &v.name);
+        quote!(Self::#ident { .. } => #name)
+    });
+    let encodes = variants.iter().map(encode_arm);
+    let decodes = variants.iter().map(decode_arm);
+
+    Ok(quote! {
+        impl #impl_generics ::rt::event::HiresEvent for #ty #ty_generics #where_clause {
+            const EVENTS: &'static [(u32, &'static str)] = &[#(#table),*];
+
+            #[inline]
+            fn event_id(&self) -> u32 {
+                match self {
+                    #(#ids,)*
+                }
+            }
+
+            fn name(&self) -> &'static str {
+                match self {
+                    #(#names,)*
+                }
+            }
+
+            #[inline]
+            fn encode(&self) -> (u64, u64) {
+                match self {
+                    #(#encodes,)*
+                }
+            }
+
+            fn decode(event_id: u32, data1: u64, data2: u64) -> ::core::option::Option<Self> {
+                let _ = (data1, data2);
+                match event_id {
+                    #(#decodes,)*
+                    _ => ::core::option::Option::None,
+                }
+            }
+        }
+    })
+}
+
+/// Bindings for a variant's fields in a pattern, in order.
+fn bindings(fields: &Fields) -> Vec<Ident> {
+    (0..fields.len())
+        .map(|i| format_ident!("__f{}", i, span = Span::call_site()))
+        .collect()
+}
+
+fn encode_arm(v: &Variant) -> TokenStream2 {
+    let ident = v.ident;
+    let vars = bindings(v.fields);
+    let words = (0..MAX_FIELDS).map(|i| match vars.get(i) {
+        Some(var) => quote!(::rt::event::Payload::to_word(*#var)),
+        None => quote!(0),
+    });
+    let pattern = match v.fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|f| &f.ident);
+            quote!(Self::#ident { #(#names: #vars),* })
+        }
+        Fields::Unnamed(_) => quote!(Self::#ident(#(#vars),*)),
+        Fields::Unit => quote!(Self::#ident),
+    };
+    quote!(#pattern => (#(#words),*))
+}
+
+fn decode_arm(v: &Variant) -> TokenStream2 {
+    let (ident, id) = (v.ident, v.id);
+    let words = [quote!(data1), quote!(data2)];
+    let values = words
+        .iter()
+        .take(v.fields.len())
+        .map(|w| quote!(::rt::event::Payload::from_word(#w)));
+    let value = match v.fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|f| &f.ident);
+            quote!(Self::#ident { #(#names: #values),* })
+        }
+        Fields::Unnamed(_) => quote!(Self::#ident(#(#values),*)),
+        Fields::Unit => quote!(Self::#ident),
+    };
+    quote!(#id => ::core::option::Option::Some(#value))
+}
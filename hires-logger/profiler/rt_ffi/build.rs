@@ -1,36 +1,189 @@
 use std::env;
 use std::path::PathBuf;
+use std::process::Command;
 
 fn main() {
-    // assume the libhires_rt.so is already built at this stage.
-    let cpp_build_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
-        .join("../../build/rt");
-    println!("cargo:rustc-link-search=native={}", cpp_build_dir.display());
-    // should consider static library and static link???
-    println!("cargo:rustc-link-lib=dylib=hires_rt");
-
-    let header_path = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
-        .join("../../rt/include/rt_c.h");
+    // By default assume libhires_rt.so was already built by build.sh. With
+    // HIRES_VENDORED=1, configure the top-level CMake project into the same
+    // build/ directory and build just the runtime, so the library ends up
+    // where the profiler's rpath points either way.
+    println!("cargo:rerun-if-env-changed=HIRES_VENDORED");
+    println!("cargo:rerun-if-env-changed=HIRES_RT_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=HIRES_RT_INCLUDE_DIR");
+    // musl targets produce static executables, so they always take the
+    // static path.
+    let target = env::var("TARGET").unwrap();
+    let link_static = env::var("CARGO_FEATURE_STATIC").is_ok()
+        || env::var("CARGO_CFG_TARGET_ENV").is_ok_and(|e| e == "musl");
+    // dlopen is not available to static musl executables either.
+    let dynamic_load = env::var("CARGO_FEATURE_DYNAMIC_LOAD").is_ok();
+    if dynamic_load && link_static {
+        panic!(
+            "The dynamic-load feature needs libhires_rt.so and cannot be combined \
+             with static linking (the `static` feature or a musl target)"
+        );
+    }
+    let repo_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("../..");
+    let vendored = env::var("HIRES_VENDORED").is_ok_and(|v| v == "1");
+    if vendored {
+        println!("cargo:rerun-if-changed={}", repo_dir.join("rt/src").display());
+        println!("cargo:rerun-if-changed={}", repo_dir.join("rt/CMakeLists.txt").display());
+        cmake::Config::new(&repo_dir)
+            .out_dir(&repo_dir)
+            .define("BUILD_EXAMPLES", "OFF")
+            .build_target(if link_static { "hires_rt_static" } else { "hires_rt" })
+            .build();
+    }
+    // HIRES_RT_LIB_DIR points at an out-of-tree build or installed copy of
+    // the library instead; the vendored build always lands in build/rt.
+    // Without either, a system-wide install registered with pkg-config
+    // (hires_rt.pc) wins over the in-tree build.
+    let lib_dir_override = env::var_os("HIRES_RT_LIB_DIR").filter(|_| !vendored);
+    let pkg = if vendored || lib_dir_override.is_some() {
+        None
+    } else {
+        pkg_config::Config::new()
+            .cargo_metadata(false)
+            .env_metadata(true)
+            .probe("hires_rt")
+            .ok()
+    };
+    let lib_dirs = match (lib_dir_override, &pkg) {
+        (Some(dir), _) => vec![PathBuf::from(dir)],
+        (None, Some(lib)) => lib.link_paths.clone(),
+        (None, None) => vec![repo_dir.join("build/rt")],
+    };
+    if dynamic_load {
+        // Nothing is linked; src/dynamic.rs falls back to these directories
+        // when the dynamic linker's search path has no libhires_rt.
+        let dirs = env::join_paths(&lib_dirs).expect("Library directory contains ':'");
+        println!("cargo:rustc-env=HIRES_RT_BUILD_LIB_DIRS={}", dirs.to_string_lossy());
+    } else {
+        for dir in &lib_dirs {
+            println!("cargo:rustc-link-search=native={}", dir.display());
+        }
+        if link_static {
+            // libhires_rt.a plus the C++ runtime it needs, so the final binary
+            // does not depend on libhires_rt.so or libstdc++.so.
+            println!("cargo:rustc-link-lib=static=hires_rt");
+            // libstdc++.a must come from the target's toolchain (e.g. a musl or
+            // aarch64 cross g++), so ask the target C++ compiler.
+            let cxx = target_tool("CXX", &target, "c++");
+            let output = Command::new(&cxx)
+                .arg("-print-file-name=libstdc++.a")
+                .output()
+                .expect("Failed to run the C++ compiler to locate libstdc++.a");
+            let libstdcxx = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+            if let Some(dir) = libstdcxx.parent().filter(|_| libstdcxx.is_absolute()) {
+                println!("cargo:rustc-link-search=native={}", dir.display());
+            }
+            println!("cargo:rustc-link-lib=static=stdc++");
+        } else {
+            println!("cargo:rustc-link-lib=dylib=hires_rt");
+        }
+    }
+
+    // HIRES_RT_INCLUDE_DIR (or the Cflags of hires_rt.pc) holds an installed
+    // rt_c.h with common.h next to it; in-tree they live in rt/include and
+    // shared.
+    let include_dirs = match (env::var_os("HIRES_RT_INCLUDE_DIR"), &pkg) {
+        (Some(dir), _) => vec![PathBuf::from(dir)],
+        (None, Some(lib)) if !lib.include_paths.is_empty() => lib.include_paths.clone(),
+        _ => vec![repo_dir.join("rt/include"), repo_dir.join("shared")],
+    };
+    let find = |name: &str| {
+        include_dirs
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| include_dirs[0].join(name))
+    };
+    let header_path = find("rt_c.h");
     println!("cargo:rerun-if-changed={}", header_path.display());
-    let shared_header_path = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
-        .join("../../shared/common.h");
+    let shared_header_path = find("common.h");
     println!("cargo:rerun-if-changed={}", shared_header_path.display());
 
+    // Without the `bindgen` feature lib.rs includes the checked-in
+    // src/bindings.rs and libclang is not needed.
+    #[cfg(feature = "bindgen")]
+    generate_bindings(&header_path, &include_dirs, &target);
+}
+
+/// A C/C++ tool for `target`, looked up the way the cc crate does:
+/// `<VAR>_<target>`, `<VAR>_<target_with_underscores>`, `TARGET_<VAR>`,
+/// then `<VAR>`. Cross builds set e.g. `CXX_aarch64_unknown_linux_gnu`.
+fn target_tool(var: &str, target: &str, default: &str) -> String {
+    let keys = [
+        format!("{}_{}", var, target),
+        format!("{}_{}", var, target.replace('-', "_")),
+        format!("TARGET_{}", var),
+        var.to_string(),
+    ];
+    for key in &keys {
+        println!("cargo:rerun-if-env-changed={}", key);
+    }
+    keys.iter()
+        .find_map(|key| env::var(key).ok())
+        .unwrap_or_else(|| default.to_string())
+}
+
+/// Sysroot for bindgen's clang when cross-compiling: HIRES_SYSROOT, else
+/// the one the target C compiler reports (cross gcc toolchains know theirs).
+/// bindgen already passes `--target` itself.
+#[cfg(feature = "bindgen")]
+fn cross_sysroot(target: &str) -> Option<PathBuf> {
+    println!("cargo:rerun-if-env-changed=HIRES_SYSROOT");
+    if let Some(dir) = env::var_os("HIRES_SYSROOT") {
+        return Some(PathBuf::from(dir));
+    }
+    let cc = target_tool("CC", target, "cc");
+    let output = Command::new(&cc).arg("-print-sysroot").output().ok()?;
+    let dir = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    (output.status.success() && dir.is_absolute()).then_some(dir)
+}
+
+/// Flag macros that set bit 63. cexpr evaluates `1ULL << 63` as a signed
+/// 64-bit value, which bindgen would emit as a negative i64.
+#[cfg(feature = "bindgen")]
+#[derive(Debug)]
+struct U64Flags;
+
+#[cfg(feature = "bindgen")]
+impl bindgen::callbacks::ParseCallbacks for U64Flags {
+    fn int_macro(&self, name: &str, _value: i64) -> Option<bindgen::callbacks::IntKind> {
+        name.ends_with("_FAILED").then_some(bindgen::callbacks::IntKind::U64)
+    }
+}
+
+#[cfg(feature = "bindgen")]
+fn generate_bindings(header_path: &std::path::Path, include_dirs: &[PathBuf], target: &str) {
+    let mut clang_args: Vec<String> = include_dirs
+        .iter()
+        .map(|dir| format!("-I{}", dir.display()))
+        .collect();
+    if env::var("HOST").is_ok_and(|host| host != target)
+        && let Some(sysroot) = cross_sysroot(target)
+    {
+        clang_args.push(format!("--sysroot={}", sysroot.display()));
+    }
     let bindings = bindgen::Builder::default()
         .header(header_path.to_str().expect("Header path is not valid UTF-8"))
-        .clang_arg(format!(
-            "-I{}",
-            PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
-                .join("../../rt/include")
-                .display()
-        ))
-        .clang_arg(format!( 
-            "-I{}",
-            PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
-                .join("../../shared")
-                .display()
-        ))
+        .clang_args(clang_args)
+        // Only our own headers, not libc's, and no doc comments, so the
+        // output can be checked in as src/bindings.rs unchanged.
+        .allowlist_file(".*/rt_c\\.h")
+        .allowlist_file(".*/common\\.h")
+        .generate_comments(false)
+        // Size, alignment and field offset checks of every struct, compiled
+        // as const assertions along with the bindings.
+        .layout_tests(true)
         .derive_default(true)
+        // cexpr cannot expand _IO()/_IOR(), so without this the
+        // HIRES_IOCTL_* request codes would be missing and Rust code talking
+        // to the device would have to hard-code them. Clang evaluates them
+        // for the target, whose ioctl encoding may differ from the host's.
+        .clang_macro_fallback()
+        .parse_callbacks(Box::new(U64Flags))
         .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()))
         // Use core::ffi types instead of std::os::raw
         // .use_core()
@@ -42,4 +195,4 @@ fn main() {
     bindings
         .write_to_file(out_path.join("bindings.rs"))
         .expect("Couldn't write bindings!");
-}
\ No newline at end of file
+}
@@ -0,0 +1,448 @@
+/* automatically generated by rust-bindgen 0.71.1 */
+
+pub const PROF_CACHE_LINE_SIZE: u32 = 64;
+pub type prof_size_t = u64;
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct hires_rb_meta_t {
+    pub capacity: prof_size_t,
+    pub idx_mask: prof_size_t,
+    pub shm_size_bytes_unaligned: prof_size_t,
+}
+#[allow(clippy::unnecessary_operation, clippy::identity_op)]
+const _: () = {
+    ["Size of hires_rb_meta_t"][::std::mem::size_of::<hires_rb_meta_t>() - 24usize];
+    ["Alignment of hires_rb_meta_t"][::std::mem::align_of::<hires_rb_meta_t>() - 8usize];
+    ["Offset of field: hires_rb_meta_t::capacity"]
+        [::std::mem::offset_of!(hires_rb_meta_t, capacity) - 0usize];
+    ["Offset of field: hires_rb_meta_t::idx_mask"]
+        [::std::mem::offset_of!(hires_rb_meta_t, idx_mask) - 8usize];
+    ["Offset of field: hires_rb_meta_t::shm_size_bytes_unaligned"]
+        [::std::mem::offset_of!(hires_rb_meta_t, shm_size_bytes_unaligned) - 16usize];
+};
+pub const HIRES_TSC_SRC_CALIBRATED: u32 = 0;
+pub const HIRES_TSC_SRC_SECURE_TSC: u32 = 1;
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct hires_tsc_info_t {
+    pub tsc_hz: prof_size_t,
+    pub calibrated_hz: prof_size_t,
+    pub reported_hz: prof_size_t,
+    pub source: prof_size_t,
+}
+#[allow(clippy::unnecessary_operation, clippy::identity_op)]
+const _: () = {
+    ["Size of hires_tsc_info_t"][::std::mem::size_of::<hires_tsc_info_t>() - 32usize];
+    ["Alignment of hires_tsc_info_t"][::std::mem::align_of::<hires_tsc_info_t>() - 8usize];
+    ["Offset of field: hires_tsc_info_t::tsc_hz"]
+        [::std::mem::offset_of!(hires_tsc_info_t, tsc_hz) - 0usize];
+    ["Offset of field: hires_tsc_info_t::calibrated_hz"]
+        [::std::mem::offset_of!(hires_tsc_info_t, calibrated_hz) - 8usize];
+    ["Offset of field: hires_tsc_info_t::reported_hz"]
+        [::std::mem::offset_of!(hires_tsc_info_t, reported_hz) - 16usize];
+    ["Offset of field: hires_tsc_info_t::source"]
+        [::std::mem::offset_of!(hires_tsc_info_t, source) - 24usize];
+};
+pub const HIRES_IOCTL_MAGIC: u8 = 104u8;
+pub const HIRES_IOCTL_RESET_RB: u32 = 26625;
+pub const HIRES_IOCTL_GET_RB_META: u32 = 2149083138;
+pub const HIRES_IOCTL_GET_TSC_CYCLE_PER_US: u32 = 2148034563;
+pub const HIRES_IOCTL_GET_TSC_HZ: u32 = 2148034564;
+pub const HIRES_IOCTL_GET_TSC_INFO: u32 = 2149607429;
+pub const HIRES_IOCTL_SET_RB_CAPACITY: u32 = 1074292742;
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct hires_rb_stats_t {
+    pub head: u64,
+    pub tail: u64,
+    pub pending: u64,
+    pub capacity: u64,
+    pub dropped: u64,
+}
+#[allow(clippy::unnecessary_operation, clippy::identity_op)]
+const _: () = {
+    ["Size of hires_rb_stats_t"][::std::mem::size_of::<hires_rb_stats_t>() - 40usize];
+    ["Alignment of hires_rb_stats_t"][::std::mem::align_of::<hires_rb_stats_t>() - 8usize];
+    ["Offset of field: hires_rb_stats_t::head"]
+        [::std::mem::offset_of!(hires_rb_stats_t, head) - 0usize];
+    ["Offset of field: hires_rb_stats_t::tail"]
+        [::std::mem::offset_of!(hires_rb_stats_t, tail) - 8usize];
+    ["Offset of field: hires_rb_stats_t::pending"]
+        [::std::mem::offset_of!(hires_rb_stats_t, pending) - 16usize];
+    ["Offset of field: hires_rb_stats_t::capacity"]
+        [::std::mem::offset_of!(hires_rb_stats_t, capacity) - 24usize];
+    ["Offset of field: hires_rb_stats_t::dropped"]
+        [::std::mem::offset_of!(hires_rb_stats_t, dropped) - 32usize];
+};
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct hires_batch_entry_t {
+    pub event_id: u32,
+    pub reserved: u32,
+    pub data1: u64,
+    pub data2: u64,
+}
+#[allow(clippy::unnecessary_operation, clippy::identity_op)]
+const _: () = {
+    ["Size of hires_batch_entry_t"][::std::mem::size_of::<hires_batch_entry_t>() - 24usize];
+    ["Alignment of hires_batch_entry_t"][::std::mem::align_of::<hires_batch_entry_t>() - 8usize];
+    ["Offset of field: hires_batch_entry_t::event_id"]
+        [::std::mem::offset_of!(hires_batch_entry_t, event_id) - 0usize];
+    ["Offset of field: hires_batch_entry_t::reserved"]
+        [::std::mem::offset_of!(hires_batch_entry_t, reserved) - 4usize];
+    ["Offset of field: hires_batch_entry_t::data1"]
+        [::std::mem::offset_of!(hires_batch_entry_t, data1) - 8usize];
+    ["Offset of field: hires_batch_entry_t::data2"]
+        [::std::mem::offset_of!(hires_batch_entry_t, data2) - 16usize];
+};
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct log_entry_t {
+    pub timestamp: u64,
+    pub event_id: u32,
+    pub cpu_id: u32,
+    pub flags: u16,
+    pub data1: u64,
+    pub data2: u64,
+}
+#[allow(clippy::unnecessary_operation, clippy::identity_op)]
+const _: () = {
+    ["Size of log_entry_t"][::std::mem::size_of::<log_entry_t>() - 40usize];
+    ["Alignment of log_entry_t"][::std::mem::align_of::<log_entry_t>() - 8usize];
+    ["Offset of field: log_entry_t::timestamp"]
+        [::std::mem::offset_of!(log_entry_t, timestamp) - 0usize];
+    ["Offset of field: log_entry_t::event_id"]
+        [::std::mem::offset_of!(log_entry_t, event_id) - 8usize];
+    ["Offset of field: log_entry_t::cpu_id"][::std::mem::offset_of!(log_entry_t, cpu_id) - 12usize];
+    ["Offset of field: log_entry_t::flags"][::std::mem::offset_of!(log_entry_t, flags) - 16usize];
+    ["Offset of field: log_entry_t::data1"][::std::mem::offset_of!(log_entry_t, data1) - 24usize];
+    ["Offset of field: log_entry_t::data2"][::std::mem::offset_of!(log_entry_t, data2) - 32usize];
+};
+pub const HIRES_ENTRY_LAYOUT_STANDARD: u32 = 0;
+pub const HIRES_ENTRY_LAYOUT_COMPACT: u32 = 1;
+pub const HIRES_ENTRY_LAYOUT_EXTENDED: u32 = 2;
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct log_entry_compact_t {
+    pub timestamp: u64,
+    pub event_id: u32,
+    pub cpu_id: u16,
+    pub flags: u16,
+    pub data1: u64,
+    pub data2: u64,
+}
+#[allow(clippy::unnecessary_operation, clippy::identity_op)]
+const _: () = {
+    ["Size of log_entry_compact_t"][::std::mem::size_of::<log_entry_compact_t>() - 32usize];
+    ["Alignment of log_entry_compact_t"][::std::mem::align_of::<log_entry_compact_t>() - 8usize];
+    ["Offset of field: log_entry_compact_t::timestamp"]
+        [::std::mem::offset_of!(log_entry_compact_t, timestamp) - 0usize];
+    ["Offset of field: log_entry_compact_t::event_id"]
+        [::std::mem::offset_of!(log_entry_compact_t, event_id) - 8usize];
+    ["Offset of field: log_entry_compact_t::cpu_id"]
+        [::std::mem::offset_of!(log_entry_compact_t, cpu_id) - 12usize];
+    ["Offset of field: log_entry_compact_t::flags"]
+        [::std::mem::offset_of!(log_entry_compact_t, flags) - 14usize];
+    ["Offset of field: log_entry_compact_t::data1"]
+        [::std::mem::offset_of!(log_entry_compact_t, data1) - 16usize];
+    ["Offset of field: log_entry_compact_t::data2"]
+        [::std::mem::offset_of!(log_entry_compact_t, data2) - 24usize];
+};
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct log_entry_ext_t {
+    pub timestamp: u64,
+    pub event_id: u32,
+    pub cpu_id: u32,
+    pub flags: u16,
+    pub tid: u32,
+    pub data1: u64,
+    pub data2: u64,
+    pub seqno: u64,
+    pub reserved: [u64; 2usize],
+}
+#[allow(clippy::unnecessary_operation, clippy::identity_op)]
+const _: () = {
+    ["Size of log_entry_ext_t"][::std::mem::size_of::<log_entry_ext_t>() - 64usize];
+    ["Alignment of log_entry_ext_t"][::std::mem::align_of::<log_entry_ext_t>() - 8usize];
+    ["Offset of field: log_entry_ext_t::timestamp"]
+        [::std::mem::offset_of!(log_entry_ext_t, timestamp) - 0usize];
+    ["Offset of field: log_entry_ext_t::event_id"]
+        [::std::mem::offset_of!(log_entry_ext_t, event_id) - 8usize];
+    ["Offset of field: log_entry_ext_t::cpu_id"]
+        [::std::mem::offset_of!(log_entry_ext_t, cpu_id) - 12usize];
+    ["Offset of field: log_entry_ext_t::flags"]
+        [::std::mem::offset_of!(log_entry_ext_t, flags) - 16usize];
+    ["Offset of field: log_entry_ext_t::tid"][::std::mem::offset_of!(log_entry_ext_t, tid) - 20usize];
+    ["Offset of field: log_entry_ext_t::data1"]
+        [::std::mem::offset_of!(log_entry_ext_t, data1) - 24usize];
+    ["Offset of field: log_entry_ext_t::data2"]
+        [::std::mem::offset_of!(log_entry_ext_t, data2) - 32usize];
+    ["Offset of field: log_entry_ext_t::seqno"]
+        [::std::mem::offset_of!(log_entry_ext_t, seqno) - 40usize];
+    ["Offset of field: log_entry_ext_t::reserved"]
+        [::std::mem::offset_of!(log_entry_ext_t, reserved) - 48usize];
+};
+pub const HIRES_BYTE_ORDER_MARK: u32 = 16909060;
+pub const HIRES_FIELD_TIMESTAMP: u32 = 0;
+pub const HIRES_FIELD_EVENT_ID: u32 = 1;
+pub const HIRES_FIELD_CPU_ID: u32 = 2;
+pub const HIRES_FIELD_FLAGS: u32 = 3;
+pub const HIRES_FIELD_DATA1: u32 = 4;
+pub const HIRES_FIELD_DATA2: u32 = 5;
+pub const HIRES_FIELD_TID: u32 = 6;
+pub const HIRES_FIELD_SEQNO: u32 = 7;
+pub const HIRES_ENTRY_FIELDS: u32 = 8;
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct hires_entry_layout_t {
+    pub byte_order: u32,
+    pub entry_size: u16,
+    pub header_size: u16,
+    pub id: u16,
+    pub reserved: u16,
+    pub field_offset: [u8; 8usize],
+    pub field_size: [u8; 8usize],
+}
+#[allow(clippy::unnecessary_operation, clippy::identity_op)]
+const _: () = {
+    ["Size of hires_entry_layout_t"][::std::mem::size_of::<hires_entry_layout_t>() - 28usize];
+    ["Alignment of hires_entry_layout_t"][::std::mem::align_of::<hires_entry_layout_t>() - 4usize];
+    ["Offset of field: hires_entry_layout_t::byte_order"]
+        [::std::mem::offset_of!(hires_entry_layout_t, byte_order) - 0usize];
+    ["Offset of field: hires_entry_layout_t::entry_size"]
+        [::std::mem::offset_of!(hires_entry_layout_t, entry_size) - 4usize];
+    ["Offset of field: hires_entry_layout_t::header_size"]
+        [::std::mem::offset_of!(hires_entry_layout_t, header_size) - 6usize];
+    ["Offset of field: hires_entry_layout_t::id"]
+        [::std::mem::offset_of!(hires_entry_layout_t, id) - 8usize];
+    ["Offset of field: hires_entry_layout_t::reserved"]
+        [::std::mem::offset_of!(hires_entry_layout_t, reserved) - 10usize];
+    ["Offset of field: hires_entry_layout_t::field_offset"]
+        [::std::mem::offset_of!(hires_entry_layout_t, field_offset) - 12usize];
+    ["Offset of field: hires_entry_layout_t::field_size"]
+        [::std::mem::offset_of!(hires_entry_layout_t, field_size) - 20usize];
+};
+pub const LOG_FLAG_VALID: u32 = 1;
+pub const LOG_FLAG_KERNEL: u32 = 2;
+pub const LOG_FLAG_TSC: u32 = 4;
+pub const HIRES_TS_MONOTONIC: u32 = 0;
+pub const HIRES_TS_MONOTONIC_RAW: u32 = 1;
+pub const HIRES_TS_RDTSC: u32 = 2;
+pub const HIRES_TS_RDTSCP: u32 = 3;
+pub const HIRES_TS_KVMCLOCK: u32 = 4;
+pub const HIRES_TS_RDTSC_LFENCE: u32 = 5;
+pub const HIRES_EV_RESERVED_FIRST: u32 = 232;
+pub const HIRES_EV_CLOCK_CHECK: u32 = 237;
+pub const HIRES_EV_EVENT_NAME: u32 = 238;
+pub const HIRES_EV_THREAD_NAME: u32 = 239;
+pub const HIRES_THREAD_NAME_MAX: u32 = 32;
+pub const HIRES_CHANNEL_SHIFT: u32 = 16;
+pub const HIRES_CHANNEL_BITS: u32 = 13;
+pub const HIRES_CHANNEL_MASK: u32 = 8191;
+pub const HIRES_EV_CHANNEL_NAME: u32 = 236;
+pub const HIRES_EV_VNET_FIRST: u32 = 240;
+pub const HIRES_EV_VNET_KICK: u32 = 240;
+pub const HIRES_EV_VNET_INTERRUPT: u32 = 241;
+pub const HIRES_EV_VNET_NAPI_POLL: u32 = 242;
+pub const HIRES_EV_VNET_SKB_DELIVER: u32 = 243;
+pub const HIRES_EV_VNET_LAST: u32 = 247;
+pub const HIRES_EV_SWIOTLB_FIRST: u32 = 248;
+pub const HIRES_EV_SWIOTLB_MAP: u32 = 248;
+pub const HIRES_EV_SWIOTLB_UNMAP: u32 = 249;
+pub const HIRES_EV_SWIOTLB_LAST: u32 = 251;
+pub const HIRES_SWIOTLB_FAILED: u64 = 9223372036854775808;
+pub const HIRES_EV_IRQ: u32 = 252;
+pub const HIRES_EV_WAKEUP: u32 = 253;
+pub const HIRES_EV_VMEXIT: u32 = 254;
+pub const HIRES_VMEXIT_TDX_VE: u32 = 1;
+pub const HIRES_VMEXIT_SNP_VC: u32 = 2;
+pub const HIRES_EV_TLS: u32 = 255;
+pub const HIRES_TLS_ENCRYPT: u32 = 0;
+pub const HIRES_TLS_DECRYPT: u32 = 1;
+pub const HIRES_TLS_FAILED: u64 = 9223372036854775808;
+pub const RING_BUFFER_LOG2_SIZE: u32 = 16;
+pub const RING_BUFFER_SIZE: u32 = 65536;
+pub const RING_BUFFER_MASK: u32 = 65535;
+pub const RING_BUFFER_LOG2_MAX: u32 = 32;
+#[repr(C)]
+#[repr(align(64))]
+#[derive(Debug, Copy, Clone)]
+pub struct shared_ring_buffer_t {
+    pub head: prof_size_t,
+    pub pad0: [::std::os::raw::c_char; 56usize],
+    pub tail: prof_size_t,
+    pub pad1: [::std::os::raw::c_char; 56usize],
+    pub shm_size_bytes_unaligned: u64,
+    pub shm_size_bytes_aligned: u64,
+    pub capacity: u64,
+    pub idx_mask: u64,
+    pub dropped_count: u64,
+    pub magic: u64,
+    pub layout_checksum: u32,
+    pub generation: u32,
+    pub entry_layout: hires_entry_layout_t,
+    pub pad2: [::std::os::raw::c_char; 44usize],
+    pub buffer: [log_entry_t; 65536usize],
+}
+#[allow(clippy::unnecessary_operation, clippy::identity_op)]
+const _: () = {
+    ["Size of shared_ring_buffer_t"][::std::mem::size_of::<shared_ring_buffer_t>() - 2621696usize];
+    ["Alignment of shared_ring_buffer_t"][::std::mem::align_of::<shared_ring_buffer_t>() - 64usize];
+    ["Offset of field: shared_ring_buffer_t::head"]
+        [::std::mem::offset_of!(shared_ring_buffer_t, head) - 0usize];
+    ["Offset of field: shared_ring_buffer_t::pad0"]
+        [::std::mem::offset_of!(shared_ring_buffer_t, pad0) - 8usize];
+    ["Offset of field: shared_ring_buffer_t::tail"]
+        [::std::mem::offset_of!(shared_ring_buffer_t, tail) - 64usize];
+    ["Offset of field: shared_ring_buffer_t::pad1"]
+        [::std::mem::offset_of!(shared_ring_buffer_t, pad1) - 72usize];
+    ["Offset of field: shared_ring_buffer_t::shm_size_bytes_unaligned"]
+        [::std::mem::offset_of!(shared_ring_buffer_t, shm_size_bytes_unaligned) - 128usize];
+    ["Offset of field: shared_ring_buffer_t::shm_size_bytes_aligned"]
+        [::std::mem::offset_of!(shared_ring_buffer_t, shm_size_bytes_aligned) - 136usize];
+    ["Offset of field: shared_ring_buffer_t::capacity"]
+        [::std::mem::offset_of!(shared_ring_buffer_t, capacity) - 144usize];
+    ["Offset of field: shared_ring_buffer_t::idx_mask"]
+        [::std::mem::offset_of!(shared_ring_buffer_t, idx_mask) - 152usize];
+    ["Offset of field: shared_ring_buffer_t::dropped_count"]
+        [::std::mem::offset_of!(shared_ring_buffer_t, dropped_count) - 160usize];
+    ["Offset of field: shared_ring_buffer_t::magic"]
+        [::std::mem::offset_of!(shared_ring_buffer_t, magic) - 168usize];
+    ["Offset of field: shared_ring_buffer_t::layout_checksum"]
+        [::std::mem::offset_of!(shared_ring_buffer_t, layout_checksum) - 176usize];
+    ["Offset of field: shared_ring_buffer_t::generation"]
+        [::std::mem::offset_of!(shared_ring_buffer_t, generation) - 180usize];
+    ["Offset of field: shared_ring_buffer_t::entry_layout"]
+        [::std::mem::offset_of!(shared_ring_buffer_t, entry_layout) - 184usize];
+    ["Offset of field: shared_ring_buffer_t::pad2"]
+        [::std::mem::offset_of!(shared_ring_buffer_t, pad2) - 212usize];
+    ["Offset of field: shared_ring_buffer_t::buffer"]
+        [::std::mem::offset_of!(shared_ring_buffer_t, buffer) - 256usize];
+};
+impl Default for shared_ring_buffer_t {
+    fn default() -> Self {
+        let mut s = ::std::mem::MaybeUninit::<Self>::uninit();
+        unsafe {
+            ::std::ptr::write_bytes(s.as_mut_ptr(), 0, 1);
+            s.assume_init()
+        }
+    }
+}
+pub const HIRES_SHM_MAGIC: u64 = 3549489973920155976;
+pub const HIRES_API_VERSION_MAJOR: u32 = 2;
+pub const HIRES_API_VERSION_MINOR: u32 = 0;
+pub const HIRES_API_VERSION: u32 = 131072;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct HiResLoggerConnHandle {
+    _unused: [u8; 0],
+}
+unsafe extern "C" {
+    pub fn hires_get_api_version() -> u32;
+}
+unsafe extern "C" {
+    pub fn hires_connect(device_path: *const ::std::os::raw::c_char) -> *mut HiResLoggerConnHandle;
+}
+unsafe extern "C" {
+    pub fn hires_connect_with_capacity(
+        device_path: *const ::std::os::raw::c_char,
+        capacity: u64,
+    ) -> *mut HiResLoggerConnHandle;
+}
+unsafe extern "C" {
+    pub fn hires_disconnect(handle: *mut HiResLoggerConnHandle);
+}
+unsafe extern "C" {
+    pub fn hires_log(
+        handle: *mut HiResLoggerConnHandle,
+        event_id: u32,
+        data1: u64,
+        data2: u64,
+    ) -> bool;
+}
+unsafe extern "C" {
+    pub fn hires_log_with_source(
+        handle: *mut HiResLoggerConnHandle,
+        source: u32,
+        event_id: u32,
+        data1: u64,
+        data2: u64,
+    ) -> bool;
+}
+unsafe extern "C" {
+    pub fn hires_log_batch(
+        handle: *mut HiResLoggerConnHandle,
+        entries: *const hires_batch_entry_t,
+        count: usize,
+    ) -> bool;
+}
+unsafe extern "C" {
+    pub fn hires_pop(handle: *mut HiResLoggerConnHandle, entry: *mut log_entry_t) -> bool;
+}
+unsafe extern "C" {
+    pub fn hires_peek(handle: *mut HiResLoggerConnHandle, entry: *mut log_entry_t) -> bool;
+}
+unsafe extern "C" {
+    pub fn hires_pop_ext(handle: *mut HiResLoggerConnHandle, entry: *mut log_entry_ext_t) -> bool;
+}
+unsafe extern "C" {
+    pub fn hires_get_entry_layout(handle: *mut HiResLoggerConnHandle) -> u32;
+}
+unsafe extern "C" {
+    pub fn hires_flush(handle: *mut HiResLoggerConnHandle);
+}
+unsafe extern "C" {
+    pub fn hires_get_stats(handle: *mut HiResLoggerConnHandle, out: *mut hires_rb_stats_t) -> bool;
+}
+unsafe extern "C" {
+    pub fn hires_get_buffer(handle: *mut HiResLoggerConnHandle) -> *mut shared_ring_buffer_t;
+}
+unsafe extern "C" {
+    pub fn hires_get_shm_size(handle: *mut HiResLoggerConnHandle) -> u64;
+}
+unsafe extern "C" {
+    pub fn hires_get_rb_capacity(handle: *mut HiResLoggerConnHandle) -> u64;
+}
+unsafe extern "C" {
+    pub fn hires_get_rb_idx_mask(handle: *mut HiResLoggerConnHandle) -> u64;
+}
+unsafe extern "C" {
+    pub fn hires_get_cycles_per_us(handle: *mut HiResLoggerConnHandle) -> u64;
+}
+unsafe extern "C" {
+    pub fn hires_get_tsc_hz(handle: *mut HiResLoggerConnHandle) -> u64;
+}
+unsafe extern "C" {
+    pub fn hires_get_tsc_info(
+        handle: *mut HiResLoggerConnHandle,
+        out: *mut hires_tsc_info_t,
+    ) -> bool;
+}
+unsafe extern "C" {
+    pub fn hires_get_drop_num(handle: *mut HiResLoggerConnHandle) -> u64;
+}
+unsafe extern "C" {
+    pub fn hires_set_timestamp_source(handle: *mut HiResLoggerConnHandle, source: u32) -> bool;
+}
+unsafe extern "C" {
+    pub fn hires_get_timestamp_source(handle: *mut HiResLoggerConnHandle) -> u32;
+}
+unsafe extern "C" {
+    pub fn hires_rdtsc() -> u64;
+}
+unsafe extern "C" {
+    pub fn hires_rdtscp(auxp: *mut u32) -> u64;
+}
+unsafe extern "C" {
+    pub fn hires_sizeof_log_entry() -> usize;
+}
+unsafe extern "C" {
+    pub fn hires_sizeof_ring_buffer() -> usize;
+}
+unsafe extern "C" {
+    pub fn hires_offsetof_ring_entries() -> usize;
+}
+unsafe extern "C" {
+    pub fn hires_get_last_error() -> *const ::std::os::raw::c_char;
+}
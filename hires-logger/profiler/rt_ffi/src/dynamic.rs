@@ -0,0 +1,94 @@
+// With the `dynamic-load` feature nothing links against libhires_rt. The
+// `hires_*` functions are shims with the same signatures as the bindings'
+// extern declarations; the first call to one opens the library with
+// libloading and resolves its symbol, which is cached for later calls.
+
+use libloading::Library;
+use std::env;
+use std::sync::OnceLock;
+
+static LIBRARY: OnceLock<Result<Library, String>> = OnceLock::new();
+
+/// Names handed to dlopen, so LD_LIBRARY_PATH and ld.so.cache are searched.
+/// The versioned name is what an installed runtime package provides.
+const NAMES: [&str; 2] = ["libhires_rt.so.0", "libhires_rt.so"];
+
+fn open() -> Result<Library, String> {
+    // HIRES_RT_LIB names the library file itself and is the only place
+    // tried when set.
+    if let Some(path) = env::var_os("HIRES_RT_LIB") {
+        return unsafe { Library::new(&path) }.map_err(|e| {
+            format!(
+                "Failed to load libhires_rt from HIRES_RT_LIB={}: {}",
+                path.to_string_lossy(),
+                e
+            )
+        });
+    }
+    // Then the system search path, then the directories the build linked
+    // against (what the rpath points at without `dynamic-load`).
+    let build_dirs = env!("HIRES_RT_BUILD_LIB_DIRS");
+    let candidates = NAMES.iter().map(|name| name.to_string()).chain(
+        env::split_paths(build_dirs).map(|dir| dir.join("libhires_rt.so").display().to_string()),
+    );
+    let mut errors = Vec::new();
+    for candidate in candidates {
+        match unsafe { Library::new(&candidate) } {
+            Ok(lib) => return Ok(lib),
+            // dlerror() already names the file.
+            Err(e) => errors.push(format!("  {}", e)),
+        }
+    }
+    Err(format!(
+        "libhires_rt is not installed on this host. Tried:\n{}\n\
+         Build it with build.sh, install it, add its directory to LD_LIBRARY_PATH \
+         or set HIRES_RT_LIB to the library file.",
+        errors.join("\n")
+    ))
+}
+
+/// Opens libhires_rt if that has not been done yet. Calling this first
+/// turns a missing library into an error instead of a panic in the first
+/// `hires_*` call.
+pub fn load() -> Result<(), String> {
+    LIBRARY
+        .get_or_init(open)
+        .as_ref()
+        .map(|_| ())
+        .map_err(Clone::clone)
+}
+
+/// Resolves `name` (nul-terminated), panicking if the library or symbol is
+/// missing since the calling shim has no way to report an error.
+#[doc(hidden)]
+pub fn symbol<F: Copy>(name: &str) -> F {
+    let lib = match LIBRARY.get_or_init(open) {
+        Ok(lib) => lib,
+        Err(e) => panic!("{}", e),
+    };
+    match unsafe { lib.get::<F>(name.as_bytes()) } {
+        Ok(sym) => *sym,
+        Err(e) => panic!(
+            "libhires_rt has no {}, it is older than rt_ffi's bindings: {}",
+            name.trim_end_matches('\0'),
+            e
+        ),
+    }
+}
+
+/// Defines a shim for each listed function. Each shim's signature is
+/// checked against the declaration in the bindings, so this list only has
+/// to be extended when rt_c.h gains a function.
+macro_rules! shims {
+    ($(fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;)*) => {$(
+        /// Resolved from libhires_rt at runtime; see [`load`].
+        #[allow(clippy::missing_safety_doc)]
+        pub unsafe fn $name($($arg: $ty),*) $(-> $ret)? {
+            type F = unsafe extern "C" fn($($ty),*) $(-> $ret)?;
+            const _: F = $crate::bindings::$name;
+            static SYM: ::std::sync::OnceLock<F> = ::std::sync::OnceLock::new();
+            let f = *SYM.get_or_init(|| $crate::dynamic::symbol(concat!(stringify!($name), "\0")));
+            unsafe { f($($arg),*) }
+        }
+    )*};
+}
@@ -75,5 +75,64 @@ extern "C" {
 #![allow(non_snake_case)]
 #![allow(improper_ctypes)] // Allow bindgen's FFI types
 
-// Include the generated bindings file
-include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
\ No newline at end of file
+// With the default `bindgen` feature the bindings are generated from
+// rt/include/rt_c.h at build time, which needs libclang. Without it the
+// checked-in src/bindings.rs is used instead; refresh that file from
+// $OUT_DIR/bindings.rs of a `bindgen` build whenever the headers change.
+// The HIRES_IOCTL_* request codes in it use the asm-generic ioctl encoding
+// (x86_64, aarch64, riscv64); other targets need the `bindgen` feature.
+mod bindings {
+    #[cfg(feature = "bindgen")]
+    include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+    #[cfg(not(feature = "bindgen"))]
+    include!("bindings.rs");
+}
+pub use bindings::*;
+
+// With `dynamic-load` the functions above are shadowed by shims resolving
+// them from libhires_rt at runtime, so the crate's API stays the same.
+#[cfg(feature = "dynamic-load")]
+#[macro_use]
+mod dynamic;
+#[cfg(feature = "dynamic-load")]
+pub use dynamic::load;
+
+/// Makes sure libhires_rt is available before any `hires_*` call. Always
+/// true when it is linked at build time.
+#[cfg(not(feature = "dynamic-load"))]
+pub fn load() -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(feature = "dynamic-load")]
+shims! {
+    fn hires_get_api_version() -> u32;
+    fn hires_connect(device_path: *const ::std::os::raw::c_char) -> *mut HiResLoggerConnHandle;
+    fn hires_connect_with_capacity(device_path: *const ::std::os::raw::c_char, capacity: u64) -> *mut HiResLoggerConnHandle;
+    fn hires_disconnect(handle: *mut HiResLoggerConnHandle);
+    fn hires_log(handle: *mut HiResLoggerConnHandle, event_id: u32, data1: u64, data2: u64) -> bool;
+    fn hires_log_with_source(handle: *mut HiResLoggerConnHandle, source: u32, event_id: u32, data1: u64, data2: u64) -> bool;
+    fn hires_log_batch(handle: *mut HiResLoggerConnHandle, entries: *const hires_batch_entry_t, count: usize) -> bool;
+    fn hires_pop(handle: *mut HiResLoggerConnHandle, entry: *mut log_entry_t) -> bool;
+    fn hires_peek(handle: *mut HiResLoggerConnHandle, entry: *mut log_entry_t) -> bool;
+    fn hires_pop_ext(handle: *mut HiResLoggerConnHandle, entry: *mut log_entry_ext_t) -> bool;
+    fn hires_get_entry_layout(handle: *mut HiResLoggerConnHandle) -> u32;
+    fn hires_flush(handle: *mut HiResLoggerConnHandle);
+    fn hires_get_stats(handle: *mut HiResLoggerConnHandle, out: *mut hires_rb_stats_t) -> bool;
+    fn hires_get_buffer(handle: *mut HiResLoggerConnHandle) -> *mut shared_ring_buffer_t;
+    fn hires_get_shm_size(handle: *mut HiResLoggerConnHandle) -> u64;
+    fn hires_get_rb_capacity(handle: *mut HiResLoggerConnHandle) -> u64;
+    fn hires_get_rb_idx_mask(handle: *mut HiResLoggerConnHandle) -> u64;
+    fn hires_get_cycles_per_us(handle: *mut HiResLoggerConnHandle) -> u64;
+    fn hires_get_tsc_hz(handle: *mut HiResLoggerConnHandle) -> u64;
+    fn hires_get_tsc_info(handle: *mut HiResLoggerConnHandle, out: *mut hires_tsc_info_t) -> bool;
+    fn hires_get_drop_num(handle: *mut HiResLoggerConnHandle) -> u64;
+    fn hires_set_timestamp_source(handle: *mut HiResLoggerConnHandle, source: u32) -> bool;
+    fn hires_get_timestamp_source(handle: *mut HiResLoggerConnHandle) -> u32;
+    fn hires_rdtsc() -> u64;
+    fn hires_rdtscp(auxp: *mut u32) -> u64;
+    fn hires_sizeof_log_entry() -> usize;
+    fn hires_sizeof_ring_buffer() -> usize;
+    fn hires_offsetof_ring_entries() -> usize;
+    fn hires_get_last_error() -> *const ::std::os::raw::c_char;
+}
\ No newline at end of file
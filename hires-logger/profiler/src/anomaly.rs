@@ -0,0 +1,151 @@
+//! Spike detection over the per-bucket latency series.
+//!
+//! Each bucket's mean latency is scored against the median and MAD (median
+//! absolute deviation) of the trailing window of non-empty buckets. MAD is
+//! used instead of the standard deviation so a single huge spike does not
+//! inflate the baseline and hide the spikes that follow it. Consecutive
+//! flagged buckets are merged into one window.
+
+use crate::timeline::Timeline;
+use crate::units::Scale;
+use serde::Serialize;
+
+/// Scale factor making MAD a consistent estimator of the standard deviation.
+pub const MAD_SCALE: f64 = 1.4826;
+
+#[derive(Serialize)]
+pub struct Anomaly {
+    pub event_id: u32,
+    pub start_ms: u64,
+    pub end_ms: u64,
+    /// Highest bucket mean latency inside the window.
+    pub peak: f64,
+    /// Median of the trailing window when the anomaly started.
+    pub baseline: f64,
+    /// Highest robust z-score inside the window.
+    pub score: f64,
+}
+
+/// `window` is at least 1 (`--anomaly-window` rejects 0).
+pub fn detect(timeline: &Timeline, window: usize, threshold: f64, scale: Scale) -> Vec<Anomaly> {
+    let bucket_ms = timeline.bucket_ms();
+    let mut anomalies = Vec::new();
+
+    for (&event_id, buckets) in timeline.buckets() {
+        let mut history: Vec<f64> = Vec::with_capacity(window);
+        let mut current: Option<Anomaly> = None;
+
+        for (i, bucket) in buckets.iter().enumerate() {
+            if bucket.count == 0 {
+                continue;
+            }
+            let value = bucket.avg();
+
+            let flagged = if history.len() >= window {
+                let baseline = median(&history);
+                let deviations: Vec<f64> = history.iter().map(|v| (v - baseline).abs()).collect();
+                let spread = median(&deviations) * MAD_SCALE;
+                let score = if spread > 0.0 {
+                    (value - baseline) / spread
+                } else if value > baseline {
+                    f64::INFINITY
+                } else {
+                    0.0
+                };
+                (score >= threshold).then_some((baseline, score))
+            } else {
+                None
+            };
+
+            let start_ms = i as u64 * bucket_ms;
+            match (flagged, current.as_mut()) {
+                (Some((_, score)), Some(a)) => {
+                    a.end_ms = start_ms + bucket_ms;
+                    a.peak = a.peak.max(scale.cycles(value));
+                    a.score = a.score.max(score);
+                }
+                (Some((baseline, score)), None) => {
+                    current = Some(Anomaly {
+                        event_id,
+                        start_ms,
+                        end_ms: start_ms + bucket_ms,
+                        peak: scale.cycles(value),
+                        baseline: scale.cycles(baseline),
+                        score,
+                    });
+                }
+                (None, _) => anomalies.extend(current.take()),
+            }
+
+            // Flagged buckets stay out of the baseline so a long spike does
+            // not become the new normal.
+            if flagged.is_none() {
+                if history.len() == window {
+                    history.remove(0);
+                }
+                history.push(value);
+            }
+        }
+        anomalies.extend(current);
+    }
+    anomalies
+}
+
+pub fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Unit;
+    use clap::Parser;
+
+    /// One sample per 1 ms bucket of event 1.
+    fn timeline(values: &[u64]) -> Timeline {
+        let mut timeline = Timeline::new(1);
+        let times: Vec<u64> = (0..values.len() as u64).map(|i| i * 1_000_000).collect();
+        timeline.record_run(1, &times, values);
+        timeline
+    }
+
+    #[test]
+    fn window_must_be_positive() {
+        assert!(crate::Args::try_parse_from(["profiler", "--anomaly-window", "0"]).is_err());
+        let args = crate::Args::try_parse_from(["profiler", "--anomaly-window", "1"]).unwrap();
+        assert_eq!(args.anomaly_window, 1);
+    }
+
+    #[test]
+    fn window_of_one_flags_a_spike() {
+        let scale = Scale::new(Unit::Cycles, 0);
+        let found = detect(&timeline(&[10, 10, 100, 10]), 1, 3.5, scale);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].start_ms, found[0].end_ms), (2, 3));
+        assert_eq!(found[0].peak, 100.0);
+        assert_eq!(found[0].baseline, 10.0);
+    }
+
+    #[test]
+    fn no_score_before_the_window_fills() {
+        let scale = Scale::new(Unit::Cycles, 0);
+        assert!(detect(&timeline(&[10, 10, 100]), 3, 3.5, scale).is_empty());
+        assert_eq!(
+            detect(&timeline(&[10, 10, 10, 100]), 3, 3.5, scale).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn median_of_even_and_odd_lengths() {
+        assert_eq!(median(&[3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median(&[4.0, 1.0, 3.0, 2.0]), 2.5);
+    }
+}
@@ -0,0 +1,281 @@
+//! Threshold assertions on per-event statistics.
+//!
+//! An assertion has the form `<event_id>:<stat><op><value>`, e.g.
+//! `5:p99<=2000` or `3:count>0`. Stats are `count`, `avg`, `p50`, `p90`,
+//! `p99` and `max`; latency thresholds are in the `--units` of the report.
+//! Operators are `<`, `<=`, `>`, `>=`, `==` and `!=`. Without a TSC rate
+//! the report falls back to cycles, so latency assertions fail rather than
+//! compare cycles against the thresholds.
+
+use crate::stats::percentile;
+use crate::units::{Scale, Unit};
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stat {
+    Count,
+    Avg,
+    P50,
+    P90,
+    P99,
+    Max,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CmpOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+/// A parsed `--assert` specification.
+#[derive(Clone, Debug)]
+pub struct Assertion {
+    pub event_id: u32,
+    stat: Stat,
+    op: CmpOp,
+    threshold: f64,
+    /// The check without the event ID, e.g. `p99<=2000`.
+    check: String,
+}
+
+#[derive(Serialize)]
+pub struct AssertionResult {
+    pub event_id: u32,
+    pub check: String,
+    /// `None` if the event has no samples to compute the stat from.
+    pub actual: Option<f64>,
+    pub passed: bool,
+}
+
+/// Operators, longest first so `<=` is not read as `<`.
+const OPERATORS: &[(&str, CmpOp)] = &[
+    ("<=", CmpOp::Le),
+    (">=", CmpOp::Ge),
+    ("==", CmpOp::Eq),
+    ("!=", CmpOp::Ne),
+    ("<", CmpOp::Lt),
+    (">", CmpOp::Gt),
+];
+
+impl Assertion {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let (event, check) = input
+            .split_once(':')
+            .ok_or_else(|| format!("expected <event_id>:<stat><op><value>, got '{}'", input))?;
+        let event_id = event
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid event ID '{}'", event.trim()))?;
+
+        let check: String = check.chars().filter(|c| !c.is_whitespace()).collect();
+        let op_pos = check
+            .find(['<', '>', '=', '!'])
+            .ok_or_else(|| format!("missing comparison operator in '{}'", check))?;
+        let (name, rest) = check.split_at(op_pos);
+        let (op_str, op) = OPERATORS
+            .iter()
+            .find(|(s, _)| rest.starts_with(s))
+            .ok_or_else(|| format!("invalid comparison operator in '{}'", check))?;
+
+        let stat = match name {
+            "count" => Stat::Count,
+            "avg" => Stat::Avg,
+            "p50" => Stat::P50,
+            "p90" => Stat::P90,
+            "p99" => Stat::P99,
+            "max" => Stat::Max,
+            _ => return Err(format!("unknown stat '{}' (count, avg, p50, p90, p99, max)", name)),
+        };
+        let value = &rest[op_str.len()..];
+        let threshold = value
+            .parse()
+            .map_err(|_| format!("invalid threshold '{}'", value))?;
+
+        Ok(Assertion {
+            event_id,
+            stat,
+            op: *op,
+            threshold,
+            check,
+        })
+    }
+
+    /// Evaluates the assertion against the sorted raw samples (in cycles)
+    /// of its event. `calibrated` is false when `scale` fell back to cycles
+    /// from the requested unit.
+    fn check(&self, sorted: &[u64], scale: Scale, calibrated: bool) -> AssertionResult {
+        let actual = if let Stat::Count = self.stat {
+            Some(sorted.len() as f64)
+        } else if sorted.is_empty() {
+            None
+        } else {
+            let cycles = match self.stat {
+                Stat::Avg => {
+                    sorted.iter().map(|&v| v as u128).sum::<u128>() as f64 / sorted.len() as f64
+                }
+                Stat::P50 => percentile(sorted, 50.0) as f64,
+                Stat::P90 => percentile(sorted, 90.0) as f64,
+                Stat::P99 => percentile(sorted, 99.0) as f64,
+                Stat::Max => sorted[sorted.len() - 1] as f64,
+                Stat::Count => unreachable!(),
+            };
+            Some(scale.cycles(cycles))
+        };
+        let comparable = calibrated || self.stat == Stat::Count;
+        let passed = comparable
+            && actual.is_some_and(|v| match self.op {
+                CmpOp::Lt => v < self.threshold,
+                CmpOp::Le => v <= self.threshold,
+                CmpOp::Gt => v > self.threshold,
+                CmpOp::Ge => v >= self.threshold,
+                CmpOp::Eq => v == self.threshold,
+                CmpOp::Ne => v != self.threshold,
+            });
+        AssertionResult {
+            event_id: self.event_id,
+            check: self.check.clone(),
+            actual,
+            passed,
+        }
+    }
+}
+
+/// Evaluates every assertion, sorting each event's samples once.
+/// `samples` returns the raw samples of an event; `unit` is the one the
+/// thresholds were given in.
+pub fn check_all<'a>(
+    assertions: &[Assertion],
+    samples: impl Fn(u32) -> &'a [u64],
+    scale: Scale,
+    unit: Unit,
+) -> Vec<AssertionResult> {
+    let calibrated = scale.unit() == unit;
+    if !calibrated && assertions.iter().any(|a| a.stat != Stat::Count) {
+        eprintln!(
+            "Warning: TSC rate is not calibrated, failing latency assertions given in {}",
+            unit.label()
+        );
+    }
+    let mut sorted: HashMap<u32, Vec<u64>> = HashMap::new();
+    assertions
+        .iter()
+        .map(|a| {
+            let sorted = sorted.entry(a.event_id).or_insert_with(|| {
+                let mut s = samples(a.event_id).to_vec();
+                s.sort_unstable();
+                s
+            });
+            a.check(sorted, scale, calibrated)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_event_stat_operator_and_threshold() {
+        let a = Assertion::parse(" 5 : p99 <= 2000.5 ").unwrap();
+        assert_eq!(a.event_id, 5);
+        assert_eq!((a.stat, a.op, a.threshold), (Stat::P99, CmpOp::Le, 2000.5));
+        assert_eq!(a.check, "p99<=2000.5");
+        for (input, stat, op) in [
+            ("1:count>0", Stat::Count, CmpOp::Gt),
+            ("1:avg<10", Stat::Avg, CmpOp::Lt),
+            ("1:p50>=1", Stat::P50, CmpOp::Ge),
+            ("1:p90==1", Stat::P90, CmpOp::Eq),
+            ("1:max!=1", Stat::Max, CmpOp::Ne),
+        ] {
+            let a = Assertion::parse(input).unwrap();
+            assert_eq!((a.stat, a.op), (stat, op), "{}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_assertions() {
+        for input in [
+            "p99<=1", "x:p99<=1", "1:p99", "1:p99=<1", "1:p95<1", "1:p99<", "1:p99<x",
+        ] {
+            assert!(Assertion::parse(input).is_err(), "{}", input);
+        }
+    }
+
+    fn check(
+        specs: &[&str],
+        samples: &[u64],
+        scale: Scale,
+        unit: Unit,
+    ) -> Vec<(bool, Option<f64>)> {
+        let assertions: Vec<Assertion> =
+            specs.iter().map(|s| Assertion::parse(s).unwrap()).collect();
+        check_all(
+            &assertions,
+            |id| if id == 1 { samples } else { &[] },
+            scale,
+            unit,
+        )
+        .iter()
+        .map(|r| (r.passed, r.actual))
+        .collect()
+    }
+
+    #[test]
+    fn checks_stats_of_the_event() {
+        let samples = [40, 10, 30, 20];
+        let results = check(
+            &[
+                "1:count==4",
+                "1:avg<25",
+                "1:max>=40",
+                "1:p50<10",
+                "2:count==0",
+                "2:max<1",
+            ],
+            &samples,
+            Scale::new(Unit::Cycles, 0),
+            Unit::Cycles,
+        );
+        assert_eq!(
+            results,
+            [
+                (true, Some(4.0)),
+                (false, Some(25.0)),
+                (true, Some(40.0)),
+                (false, Some(20.0)),
+                (true, Some(0.0)),
+                // No samples to take the stat from.
+                (false, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn thresholds_are_in_the_report_unit() {
+        // 3000 cycles at 3 GHz.
+        let results = check(
+            &["1:max<=1"],
+            &[3000],
+            Scale::new(Unit::Us, 3_000_000_000),
+            Unit::Us,
+        );
+        assert_eq!(results, [(true, Some(1.0))]);
+    }
+
+    #[test]
+    fn latency_assertions_fail_without_calibration() {
+        // The report fell back to cycles, which the ns threshold would pass.
+        let results = check(
+            &["1:max<=5000", "1:count==1"],
+            &[3000],
+            Scale::new(Unit::Cycles, 0),
+            Unit::Ns,
+        );
+        assert_eq!(results, [(false, Some(3000.0)), (true, Some(1.0))]);
+    }
+}
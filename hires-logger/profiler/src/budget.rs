@@ -0,0 +1,145 @@
+//! Sample budgets that end a capture (`--max-events`, `--max-event-samples`).
+//!
+//! A run stops once it has recorded `--max-events` entries in total, or once
+//! every event given a `--max-event-samples <event_id>:<count>` budget has
+//! that many samples, whichever comes first. Entries of an event whose
+//! budget is full are not recorded, so every budgeted event ends the run
+//! with exactly its count. Only entries that pass `--filter` count.
+
+use std::collections::BTreeMap;
+
+/// A parsed `--max-event-samples` specification.
+#[derive(Clone, Copy, Debug)]
+pub struct EventBudget {
+    pub event_id: u32,
+    pub samples: u64,
+}
+
+impl EventBudget {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let (event, samples) = input
+            .split_once(':')
+            .ok_or_else(|| format!("expected <event_id>:<count>, got '{}'", input))?;
+        let event_id = event
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid event ID '{}'", event.trim()))?;
+        let samples = samples
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or_else(|| format!("invalid sample count '{}'", samples.trim()))?;
+        Ok(EventBudget { event_id, samples })
+    }
+}
+
+pub struct SampleBudget {
+    total: Option<u64>,
+    recorded: u64,
+    /// Remaining samples per budgeted event.
+    remaining: BTreeMap<u32, u64>,
+    /// Budgeted events still short of their count.
+    unmet: usize,
+}
+
+impl SampleBudget {
+    /// `None` if neither kind of budget is set.
+    pub fn new(total: Option<u64>, per_event: &[EventBudget]) -> Option<Self> {
+        if total.is_none() && per_event.is_empty() {
+            return None;
+        }
+        // A repeated event keeps its last budget.
+        let remaining: BTreeMap<u32, u64> =
+            per_event.iter().map(|b| (b.event_id, b.samples)).collect();
+        Some(SampleBudget {
+            total,
+            recorded: 0,
+            unmet: remaining.len(),
+            remaining,
+        })
+    }
+
+    /// Whether to record an entry of `event_id`; counts it if so.
+    pub fn admit(&mut self, event_id: u32) -> bool {
+        if let Some(left) = self.remaining.get_mut(&event_id) {
+            if *left == 0 {
+                return false;
+            }
+            *left -= 1;
+            if *left == 0 {
+                self.unmet -= 1;
+            }
+        }
+        self.recorded += 1;
+        true
+    }
+
+    pub fn is_met(&self) -> bool {
+        self.total.is_some_and(|total| self.recorded >= total)
+            || (!self.remaining.is_empty() && self.unmet == 0)
+    }
+
+    pub fn recorded(&self) -> u64 {
+        self.recorded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(event_id: u32, samples: u64) -> EventBudget {
+        EventBudget { event_id, samples }
+    }
+
+    #[test]
+    fn parses_event_budgets() {
+        let b = EventBudget::parse(" 5 : 100 ").unwrap();
+        assert_eq!((b.event_id, b.samples), (5, 100));
+        for bad in ["5", "x:1", "5:0", "5:-1", "5:", ":5"] {
+            assert!(EventBudget::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn no_budget_without_limits() {
+        assert!(SampleBudget::new(None, &[]).is_none());
+    }
+
+    #[test]
+    fn total_budget_counts_every_event() {
+        let mut b = SampleBudget::new(Some(3), &[]).unwrap();
+        for id in [1, 2] {
+            assert!(b.admit(id));
+            assert!(!b.is_met());
+        }
+        assert!(b.admit(3));
+        assert!(b.is_met());
+        assert_eq!(b.recorded(), 3);
+    }
+
+    #[test]
+    fn event_budgets_cap_each_event_and_end_when_all_are_full() {
+        // A repeated event keeps its last budget.
+        let mut b = SampleBudget::new(None, &[budget(1, 5), budget(1, 2), budget(2, 1)]).unwrap();
+        assert!(b.admit(1));
+        assert!(b.admit(1));
+        assert!(!b.admit(1));
+        // Unbudgeted events are recorded without limit.
+        assert!(b.admit(9));
+        assert!(!b.is_met());
+        assert!(b.admit(2));
+        assert!(b.is_met());
+        assert!(!b.admit(2));
+        assert_eq!(b.recorded(), 4);
+    }
+
+    #[test]
+    fn total_budget_ends_the_run_before_event_budgets() {
+        let mut b = SampleBudget::new(Some(2), &[budget(1, 10)]).unwrap();
+        assert!(b.admit(1));
+        assert!(b.admit(2));
+        assert!(b.is_met());
+    }
+}
@@ -0,0 +1,157 @@
+//! File output off the receive loop (`host-collector --output`).
+//!
+//! The collector's receive loop is also what drains the ivshmem ring, so a
+//! `write()` that stalls on a slow disk (writeback, a full page cache, an
+//! fsync elsewhere on the device) used to stop the drain, and the guest
+//! dropped records. [`CaptureWriter`] fills pre-allocated buffers instead and
+//! hands each full one through an [`crate::spsc`] queue to a thread that owns
+//! the file; emptied buffers come back through a second queue.
+//!
+//! If the writer thread falls behind by more than [`BUFFERS`], the current
+//! buffer keeps growing rather than waiting for room: the backlog moves to
+//! the heap and the receive loop never blocks on the disk.
+
+use crate::spsc::{self, Receiver, Sender};
+use std::fs::File;
+use std::io::{self, Write};
+use std::mem;
+use std::path::Path;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Bytes per buffer before it is handed to the writer thread.
+pub const BUFFER_BYTES: usize = 1 << 20;
+/// Buffers in flight between the threads (a power of two).
+pub const BUFFERS: usize = 16;
+
+/// How long the writer thread sleeps when it has nothing to write.
+const IDLE: Duration = Duration::from_millis(1);
+
+pub struct CaptureWriter {
+    current: Vec<u8>,
+    /// `None` once closed.
+    full: Option<Sender<Vec<u8>>>,
+    empty: Receiver<Vec<u8>>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl CaptureWriter {
+    /// Creates (or truncates) `path` and starts the writer thread.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = File::create(path)?;
+        let (full_tx, full_rx) = spsc::queue(BUFFERS);
+        let (mut empty_tx, empty_rx) = spsc::queue(BUFFERS);
+        for _ in 0..BUFFERS {
+            let _ = empty_tx.push(Vec::with_capacity(BUFFER_BYTES));
+        }
+        let thread = thread::Builder::new()
+            .name("hires-writer".to_string())
+            .spawn(move || write_out(file, full_rx, empty_tx))?;
+        Ok(CaptureWriter {
+            current: Vec::with_capacity(BUFFER_BYTES),
+            full: Some(full_tx),
+            empty: empty_rx,
+            thread: Some(thread),
+        })
+    }
+
+    /// Hands everything written so far to the writer thread, waits for it
+    /// to reach the file and returns the first error the thread hit.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.close()
+    }
+
+    /// Queues the current buffer and takes an empty one, or keeps the
+    /// current one to grow if the queue is full.
+    fn pass_on(&mut self) -> io::Result<()> {
+        let Some(full) = &mut self.full else {
+            return Err(io::Error::other("capture writer closed"));
+        };
+        // The writer thread only exits early on an error; `finish` has it.
+        if full.is_closed() {
+            return Err(io::Error::other("capture writer thread stopped"));
+        }
+        match full.push(mem::take(&mut self.current)) {
+            Ok(()) => {
+                self.current = self
+                    .empty
+                    .pop()
+                    .unwrap_or_else(|| Vec::with_capacity(BUFFER_BYTES));
+            }
+            Err(buf) => self.current = buf,
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        let Some(mut full) = self.full.take() else {
+            return Ok(());
+        };
+        // The last buffer waits for room; nothing else is being drained now.
+        let mut buf = mem::take(&mut self.current);
+        while !buf.is_empty() && !full.is_closed() {
+            match full.push(buf) {
+                Ok(()) => break,
+                Err(back) => {
+                    buf = back;
+                    thread::sleep(IDLE);
+                }
+            }
+        }
+        // Closing the queue lets the thread exit once it is empty.
+        drop(full);
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(io::Error::other("capture writer thread panicked")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for CaptureWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if !self.current.is_empty() && self.current.len() + data.len() > BUFFER_BYTES {
+            self.pass_on()?;
+        }
+        self.current.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    /// Queues the current buffer without waiting for the writer thread.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.current.is_empty() {
+            return Ok(());
+        }
+        self.pass_on()
+    }
+}
+
+/// Like `BufWriter`, writes out what it holds when dropped, ignoring errors.
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+/// The writer thread: writes buffers out in order until the sender is gone
+/// and the queue is empty.
+fn write_out(
+    mut file: File,
+    mut full: Receiver<Vec<u8>>,
+    mut empty: Sender<Vec<u8>>,
+) -> io::Result<()> {
+    loop {
+        match full.pop() {
+            Some(mut buf) => {
+                file.write_all(&buf)?;
+                buf.clear();
+                // Buffers grown while the thread was behind are not kept.
+                if buf.capacity() <= BUFFER_BYTES {
+                    let _ = empty.push(buf);
+                }
+            }
+            None if full.is_finished() => return file.flush(),
+            None => thread::sleep(IDLE),
+        }
+    }
+}
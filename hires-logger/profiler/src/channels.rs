@@ -0,0 +1,109 @@
+//! Channels (see `rt::channel`): events of workloads sharing the ring.
+//!
+//! A channel's events carry its ID in bits 16-28 of the event ID, so
+//! every statistic keyed by event ID keeps channels apart as is. The tables
+//! indexed by event ID get a block of [`MAX_EVENT_BUCKET_SIZE`] slots per
+//! channel seen ([`Slots`]). Channel names arrive as `HIRES_EV_CHANNEL_NAME`
+//! entries and label the channel's events in reports.
+
+use crate::MAX_EVENT_BUCKET_SIZE;
+use crate::names::Names;
+
+#[derive(Default)]
+pub struct Channels {
+    names: Names,
+}
+
+impl Channels {
+    /// Records one part of a channel name registration. Two names for one
+    /// channel mean their IDs collide and their events are mixed.
+    pub fn record(&mut self, data1: u64, data2: u64) {
+        let (channel, _, _) = rt::thread::unpack_name_tag(data1);
+        let before = self.names.get(channel).map(str::to_string);
+        self.names.record(data1, data2);
+        if let (Some(before), Some(now)) = (before, self.names.get(channel))
+            && before != now
+        {
+            eprintln!(
+                "Warning: channel {:#06x} registered as `{}` and as `{}`; their events are mixed",
+                channel, before, now
+            );
+        }
+    }
+
+    /// How reports label the channel `event_id` was logged on: its name, or
+    /// its ID in hex if none was registered. `None` on the default channel.
+    pub fn label(&self, event_id: u32) -> Option<String> {
+        let (channel, _) = rt::channel::split(event_id);
+        (channel != 0).then(|| {
+            self.names
+                .get(channel as u32)
+                .map_or_else(|| format!("{:#06x}", channel), str::to_string)
+        })
+    }
+}
+
+/// Maps event IDs to slots of a per-event table: the default channel's
+/// events at their IDs, and each other channel a block of
+/// `MAX_EVENT_BUCKET_SIZE` after those, in the order channels are first seen.
+pub struct Slots {
+    /// The channel of each block.
+    channels: Vec<u16>,
+    /// An ID past its channel's block was seen and reported.
+    warned: bool,
+}
+
+impl Default for Slots {
+    fn default() -> Self {
+        Slots {
+            channels: vec![0],
+            warned: false,
+        }
+    }
+}
+
+impl Slots {
+    /// The slot of `event_id`, adding a block for a new channel. `None`
+    /// (reported once) for an ID past the block within its channel.
+    pub fn slot(&mut self, event_id: u32) -> Option<usize> {
+        if let Some(slot) = self.find(event_id) {
+            return Some(slot);
+        }
+        let (channel, id) = rt::channel::split(event_id);
+        if id as usize >= MAX_EVENT_BUCKET_SIZE {
+            if !self.warned {
+                self.warned = true;
+                eprintln!(
+                    "Warning: entries with event IDs from {} up (first seen: {}) are not counted",
+                    MAX_EVENT_BUCKET_SIZE, event_id
+                );
+            }
+            return None;
+        }
+        self.channels.push(channel);
+        Some((self.channels.len() - 1) * MAX_EVENT_BUCKET_SIZE + id as usize)
+    }
+
+    /// The slot of `event_id` if its channel has a block.
+    pub fn find(&self, event_id: u32) -> Option<usize> {
+        let (channel, id) = rt::channel::split(event_id);
+        if id as usize >= MAX_EVENT_BUCKET_SIZE {
+            return None;
+        }
+        let block = self.channels.iter().position(|&c| c == channel)?;
+        Some(block * MAX_EVENT_BUCKET_SIZE + id as usize)
+    }
+
+    /// The event ID `slot` counts.
+    pub fn event_id(&self, slot: usize) -> u32 {
+        rt::channel::channel_event(
+            self.channels[slot / MAX_EVENT_BUCKET_SIZE],
+            (slot % MAX_EVENT_BUCKET_SIZE) as u32,
+        )
+    }
+
+    /// Slots in use, a multiple of `MAX_EVENT_BUCKET_SIZE`.
+    pub fn len(&self) -> usize {
+        self.channels.len() * MAX_EVENT_BUCKET_SIZE
+    }
+}
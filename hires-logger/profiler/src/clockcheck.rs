@@ -0,0 +1,54 @@
+//! TSC cross-check results (`HIRES_EV_CLOCK_CHECK`, see `rt::clockcheck`).
+//!
+//! Entries come from producers running an `rt::clockcheck::ClockCheck` and
+//! from the profiler's own with `--clock-check`.
+
+use crate::platform::TSC_MISMATCH_PPM;
+use rt::clockcheck::ClockSample;
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct ClockCheckReport {
+    pub samples: usize,
+    /// Total TSC time over total `CLOCK_MONOTONIC` time, as ppm.
+    pub mean_error_ppm: f64,
+    /// The sample furthest from agreement, signed.
+    pub worst_error_ppm: f64,
+    /// Whether the mean error exceeds `TSC_MISMATCH_PPM`.
+    pub mismatch: bool,
+}
+
+#[derive(Default)]
+pub struct ClockCheckTracker {
+    samples: Vec<ClockSample>,
+}
+
+impl ClockCheckTracker {
+    pub fn record(&mut self, data1: u64, data2: u64) {
+        self.samples.push(ClockSample::from_entry(data1, data2));
+    }
+
+    /// `None` if no checks were logged.
+    pub fn report(&self) -> Option<ClockCheckReport> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let total = ClockSample {
+            tsc_ns: self.samples.iter().map(|s| s.tsc_ns).sum(),
+            monotonic_ns: self.samples.iter().map(|s| s.monotonic_ns).sum(),
+        };
+        let mean_error_ppm = total.error_ppm();
+        let worst_error_ppm = self
+            .samples
+            .iter()
+            .map(ClockSample::error_ppm)
+            .max_by(|a, b| a.abs().total_cmp(&b.abs()))
+            .unwrap_or(0.0);
+        Some(ClockCheckReport {
+            samples: self.samples.len(),
+            mean_error_ppm,
+            worst_error_ppm,
+            mismatch: mean_error_ppm.abs() > TSC_MISMATCH_PPM,
+        })
+    }
+}
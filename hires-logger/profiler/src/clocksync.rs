@@ -0,0 +1,216 @@
+//! Guest-to-host TSC offset and drift estimation over vsock.
+//!
+//! The guest sends timestamped probes to a responder in the host collector
+//! (on the forwarding port + 1), which stamps their receipt and its reply
+//! with the host TSC. As in PTP, each exchange gives
+//!
+//! ```text
+//! offset = ((t2 - t1) + (t3 - t4)) / 2      delay = (t4 - t1) - (t3 - t2)
+//! ```
+//!
+//! with t1/t4 on the guest TSC and t2/t3 on the host's. A round keeps the
+//! exchange with the smallest delay, the least disturbed by queueing. One
+//! round at the start of a capture and one at the end give the offset and
+//! its drift, stored as a [`ClockModel`] in the report so guest and host
+//! captures can be merged:
+//!
+//! ```text
+//! host_tsc = guest_tsc + offset_cycles + (guest_tsc - guest_ref_tsc) * drift_ppm / 1e6
+//! ```
+
+use crate::hostlink::{vsock_accept, vsock_connect, vsock_listen};
+use crate::units::read_tsc;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::thread;
+
+/// Exchanges per round.
+const ROUND_PROBES: usize = 64;
+
+#[derive(Clone, Copy, Debug)]
+struct Exchange {
+    /// Guest TSC halfway between send and reply.
+    guest_tsc: u64,
+    offset: i128,
+    delay: u64,
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct ClockModel {
+    /// Guest TSC the offset refers to.
+    pub guest_ref_tsc: u64,
+    /// Host TSC minus guest TSC at `guest_ref_tsc`, in cycles.
+    pub offset_cycles: f64,
+    /// Change of the offset per guest cycle, in parts per million; 0 if
+    /// only one round was taken.
+    pub drift_ppm: f64,
+    /// Round trip of the best exchange in the last round, in cycles; the
+    /// offset is accurate to about half of it.
+    pub delay_cycles: u64,
+}
+
+/// The responder's port, one above the forwarding `port`.
+pub fn sync_port(port: u32) -> io::Result<u32> {
+    port.checked_add(1).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no clock sync port above vsock port {}", port),
+        )
+    })
+}
+
+/// Guest side: a connection to the host's responder.
+pub struct ClockSync {
+    stream: File,
+    seq: u64,
+    first: Exchange,
+    last: Exchange,
+}
+
+impl ClockSync {
+    /// Connects and runs the first round.
+    pub fn connect(cid: u32, port: u32) -> io::Result<Self> {
+        let mut stream = vsock_connect(cid, port)?;
+        let first = round(&mut stream, 0)?;
+        Ok(ClockSync {
+            stream,
+            seq: ROUND_PROBES as u64,
+            first,
+            last: first,
+        })
+    }
+
+    /// Runs another round, extending the drift baseline.
+    pub fn resync(&mut self) -> io::Result<()> {
+        self.last = round(&mut self.stream, self.seq)?;
+        self.seq += ROUND_PROBES as u64;
+        Ok(())
+    }
+
+    pub fn model(&self) -> ClockModel {
+        fit(&self.first, &self.last)
+    }
+}
+
+/// The offset and delay of one exchange, from its four timestamps.
+fn exchange(t1: u64, t2: u64, t3: u64, t4: u64) -> Exchange {
+    Exchange {
+        guest_tsc: t1 / 2 + t4 / 2,
+        offset: ((t2 as i128 - t1 as i128) + (t3 as i128 - t4 as i128)) / 2,
+        delay: t4.saturating_sub(t1).saturating_sub(t3.saturating_sub(t2)),
+    }
+}
+
+/// The clock model from the best exchanges of the first and last rounds.
+fn fit(first: &Exchange, last: &Exchange) -> ClockModel {
+    let span = last.guest_tsc.saturating_sub(first.guest_tsc);
+    let drift_ppm = if span > 0 {
+        (last.offset - first.offset) as f64 / span as f64 * 1e6
+    } else {
+        0.0
+    };
+    ClockModel {
+        guest_ref_tsc: first.guest_tsc,
+        offset_cycles: first.offset as f64,
+        drift_ppm,
+        delay_cycles: last.delay,
+    }
+}
+
+fn round(stream: &mut File, seq: u64) -> io::Result<Exchange> {
+    let mut best: Option<Exchange> = None;
+    for i in 0..ROUND_PROBES as u64 {
+        let mut probe = [0u8; 16];
+        probe[..8].copy_from_slice(&(seq + i).to_le_bytes());
+        let t1 = read_tsc();
+        probe[8..].copy_from_slice(&t1.to_le_bytes());
+        stream.write_all(&probe)?;
+        let mut reply = [0u8; 32];
+        stream.read_exact(&mut reply)?;
+        let t4 = read_tsc();
+        let word = |i: usize| u64::from_le_bytes(reply[i * 8..i * 8 + 8].try_into().unwrap());
+        if word(0) != seq + i || word(1) != t1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "clock sync reply out of sequence",
+            ));
+        }
+        let exchange = exchange(t1, word(2), word(3), t4);
+        if best.is_none_or(|b| exchange.delay < b.delay) {
+            best = Some(exchange);
+        }
+    }
+    Ok(best.unwrap())
+}
+
+/// Host side: answers probes from any number of guests, one thread each.
+pub fn spawn_responder(port: u32) -> io::Result<()> {
+    let listener = vsock_listen(port)?;
+    thread::spawn(move || {
+        while let Ok((stream, _)) = vsock_accept(&listener) {
+            thread::spawn(move || respond(stream));
+        }
+    });
+    Ok(())
+}
+
+fn respond(mut stream: File) {
+    let mut probe = [0u8; 16];
+    while stream.read_exact(&mut probe).is_ok() {
+        let t2 = read_tsc();
+        let mut reply = [0u8; 32];
+        reply[..16].copy_from_slice(&probe);
+        reply[16..24].copy_from_slice(&t2.to_le_bytes());
+        let t3 = read_tsc();
+        reply[24..].copy_from_slice(&t3.to_le_bytes());
+        if stream.write_all(&reply).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exchange_splits_the_round_trip() {
+        // Host 1000 cycles ahead, 100 cycles each way, 50 in the responder.
+        let e = exchange(10_000, 11_100, 11_150, 10_250);
+        assert_eq!((e.guest_tsc, e.offset, e.delay), (10_125, 1000, 200));
+        // Asymmetric paths shift the offset by half the difference.
+        let e = exchange(10_000, 11_300, 11_300, 10_400);
+        assert_eq!((e.offset, e.delay), (1100, 400));
+        // A host behind the guest.
+        assert_eq!(exchange(10_000, 9_100, 9_100, 10_200).offset, -1000);
+    }
+
+    #[test]
+    fn exchange_delay_saturates_on_bad_stamps() {
+        // The guest TSC went backwards, or the host's did.
+        assert_eq!(exchange(10_000, 5, 10, 9_000).delay, 0);
+        assert_eq!(exchange(10_000, 10, 5, 10_100).delay, 100);
+        assert_eq!(exchange(u64::MAX, 0, 0, 0).delay, 0);
+    }
+
+    #[test]
+    fn fit_measures_drift_between_rounds() {
+        let first = exchange(1_000_000, 1_001_100, 1_001_100, 1_000_200);
+        // 10 ppm over 1e9 cycles.
+        let last = exchange(1_001_000_000, 1_001_011_100, 1_001_011_100, 1_001_000_200);
+        let model = fit(&first, &last);
+        assert_eq!(model.guest_ref_tsc, 1_000_100);
+        assert_eq!(model.offset_cycles, 1000.0);
+        assert!((model.drift_ppm - 10.0).abs() < 1e-9, "{}", model.drift_ppm);
+        assert_eq!(model.delay_cycles, 200);
+        // One round: no drift.
+        assert_eq!(fit(&first, &first).drift_ppm, 0.0);
+    }
+
+    #[test]
+    fn sync_port_is_checked() {
+        assert_eq!(sync_port(5000).unwrap(), 5001);
+        assert!(sync_port(u32::MAX).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+//! ANSI colors for terminal output.
+//!
+//! Colors are on only when the stream is a terminal, and never with
+//! `--no-color` or a non-empty `NO_COLOR` (https://no-color.org), so
+//! redirected output and reports stay plain.
+
+use std::fmt;
+use std::io::IsTerminal;
+
+const RED: &str = "31";
+const GREEN: &str = "32";
+const MAGENTA: &str = "35";
+const CYAN: &str = "36";
+
+/// Whether to color one output stream.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Palette {
+    enabled: bool,
+}
+
+impl Palette {
+    pub fn detect(no_color: bool, stream: &impl IsTerminal) -> Self {
+        let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        Palette {
+            enabled: !no_color && !no_color_env && stream.is_terminal(),
+        }
+    }
+
+    fn paint<T: fmt::Display>(self, code: &'static str, text: T) -> Painted<T> {
+        Painted {
+            code: self.enabled.then_some(code),
+            text,
+        }
+    }
+
+    /// Events logged by khires (`LOG_FLAG_KERNEL`).
+    pub fn kernel<T: fmt::Display>(self, text: T) -> Painted<T> {
+        self.paint(MAGENTA, text)
+    }
+
+    /// Events logged from userspace.
+    pub fn user<T: fmt::Display>(self, text: T) -> Painted<T> {
+        self.paint(CYAN, text)
+    }
+
+    /// A violated threshold: a failed assertion, a clock mismatch.
+    pub fn fail<T: fmt::Display>(self, text: T) -> Painted<T> {
+        self.paint(RED, text)
+    }
+
+    pub fn pass<T: fmt::Display>(self, text: T) -> Painted<T> {
+        self.paint(GREEN, text)
+    }
+}
+
+/// `text`, wrapped in an escape sequence if the palette is enabled. Width
+/// and alignment apply to the text either way.
+pub struct Painted<T> {
+    code: Option<&'static str>,
+    text: T,
+}
+
+impl<T: fmt::Display> fmt::Display for Painted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => {
+                write!(f, "\x1b[{}m", code)?;
+                self.text.fmt(f)?;
+                write!(f, "\x1b[0m")
+            }
+            None => self.text.fmt(f),
+        }
+    }
+}
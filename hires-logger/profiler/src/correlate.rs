@@ -0,0 +1,139 @@
+//! Cross-event correlation over the time-bucketed series.
+//!
+//! Each event contributes two series: its rate (samples per bucket) and its
+//! p99 latency per bucket. Every pair of series is compared with a Pearson
+//! coefficient, which answers questions like "does event 7's p99 spike when
+//! event 3 gets busier". The timeline must keep samples (see
+//! `Timeline::keep_samples`) for the p99 series.
+
+use crate::info;
+use crate::timeline::Timeline;
+use serde::Serialize;
+
+/// Minimum number of shared buckets before a coefficient is reported.
+const MIN_POINTS: usize = 3;
+
+#[derive(Serialize)]
+pub struct CorrelationMatrix {
+    pub labels: Vec<String>,
+    /// Row-major coefficients; `None` where there is too little overlap or
+    /// one of the series is constant.
+    pub values: Vec<Vec<Option<f64>>>,
+}
+
+/// A series with gaps: p99 is undefined in buckets without samples.
+struct Series {
+    label: String,
+    points: Vec<Option<f64>>,
+}
+
+pub fn correlate(timeline: &Timeline) -> CorrelationMatrix {
+    let buckets = timeline.buckets();
+    let len = buckets.values().map(|b| b.len()).max().unwrap_or(0);
+
+    let mut series = Vec::with_capacity(buckets.len() * 2);
+    for (&event_id, b) in buckets {
+        let rate = (0..len)
+            .map(|i| Some(b.get(i).map_or(0, |b| b.count) as f64))
+            .collect();
+        let latency = (0..len)
+            .map(|i| timeline.percentile(event_id, i, 99.0).map(|v| v as f64))
+            .collect();
+        series.push(Series {
+            label: format!("e{}.rate", event_id),
+            points: rate,
+        });
+        series.push(Series {
+            label: format!("e{}.p99", event_id),
+            points: latency,
+        });
+    }
+
+    let values = series
+        .iter()
+        .map(|a| {
+            series
+                .iter()
+                .map(|b| pearson(&a.points, &b.points))
+                .collect()
+        })
+        .collect();
+
+    CorrelationMatrix {
+        labels: series.into_iter().map(|s| s.label).collect(),
+        values,
+    }
+}
+
+pub fn pearson(a: &[Option<f64>], b: &[Option<f64>]) -> Option<f64> {
+    let pairs: Vec<(f64, f64)> = a
+        .iter()
+        .zip(b)
+        .filter_map(|(x, y)| Some(((*x)?, (*y)?)))
+        .collect();
+    if pairs.len() < MIN_POINTS {
+        return None;
+    }
+
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+    let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (x, y) in &pairs {
+        let (dx, dy) = (x - mean_x, y - mean_y);
+        cov += dx * dy;
+        var_x += dx * dx;
+        var_y += dy * dy;
+    }
+    if var_x == 0.0 || var_y == 0.0 {
+        return None;
+    }
+    Some(cov / (var_x * var_y).sqrt())
+}
+
+pub fn print_matrix(matrix: &CorrelationMatrix) {
+    let width = matrix.labels.iter().map(|l| l.len()).max().unwrap_or(0).max(6);
+    let mut header = format!("{:width$}", "", width = width);
+    for label in &matrix.labels {
+        header += &format!(" {:>width$}", label, width = width);
+    }
+    info!("{}", header);
+    for (label, row) in matrix.labels.iter().zip(&matrix.values) {
+        let mut line = format!("{:width$}", label, width = width);
+        for v in row {
+            line += &match v {
+                Some(r) => format!(" {:>width$.2}", r, width = width),
+                None => format!(" {:>width$}", "-", width = width),
+            };
+        }
+        info!("{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_series_is_the_bucket_p99() {
+        let mut timeline = Timeline::new(1);
+        timeline.keep_samples();
+        timeline.anchor(0);
+        for i in 0..5u64 {
+            let start = i * 1_000_000;
+            // Event 7's mean stays at 100 while its tail grows with event 3's rate.
+            let mut values = vec![100 - i; 98];
+            values.extend([100 + 49 * i; 2]);
+            let times: Vec<u64> = (0..values.len() as u64).map(|j| start + j).collect();
+            timeline.record_run(7, &times, &values);
+            timeline.record_run(3, &times[..i as usize + 1], &values[..i as usize + 1]);
+        }
+
+        let matrix = correlate(&timeline);
+        assert_eq!(matrix.labels, ["e3.rate", "e3.p99", "e7.rate", "e7.p99"]);
+        let r = matrix.values[0][3].unwrap();
+        assert!((r - 1.0).abs() < 1e-9, "{}", r);
+        // Constant rate: no coefficient.
+        assert_eq!(matrix.values[2][3], None);
+    }
+}
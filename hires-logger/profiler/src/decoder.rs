@@ -0,0 +1,121 @@
+//! Payload decoder plugins.
+//!
+//! A decoder is a shared library implementing the ABI in
+//! `rt/include/hires_decoder.h`. It turns an entry's raw payload into named,
+//! rendered fields; the profiler counts the distinct renderings per event and
+//! shows the most frequent ones in the report.
+
+use libloading::Library;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{CStr, c_char};
+use std::path::Path;
+
+const NAME_LEN: usize = 32;
+const VALUE_LEN: usize = 64;
+const MAX_FIELDS: usize = 16;
+
+/// Distinct renderings tracked per event before new ones are ignored.
+const MAX_TRACKED_RENDERINGS: usize = 1 << 12;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawField {
+    name: [c_char; NAME_LEN],
+    value: [c_char; VALUE_LEN],
+}
+
+type DecodeFn = unsafe extern "C" fn(u32, u64, u64, *mut RawField, usize) -> usize;
+
+pub struct Decoder {
+    decode: DecodeFn,
+    // Keeps the library mapped for as long as `decode` may be called.
+    _lib: Library,
+}
+
+impl Decoder {
+    pub fn load(path: &Path) -> Result<Self, libloading::Error> {
+        let lib = unsafe { Library::new(path)? };
+        let decode = unsafe { *lib.get::<DecodeFn>(b"hires_decode\0")? };
+        Ok(Decoder { decode, _lib: lib })
+    }
+
+    /// Decodes a payload into `name=value` pairs, or `None` if the plugin
+    /// does not handle `event_id`.
+    pub fn decode(&self, event_id: u32, data1: u64, data2: u64) -> Option<Vec<(String, String)>> {
+        let mut fields = [RawField {
+            name: [0; NAME_LEN],
+            value: [0; VALUE_LEN],
+        }; MAX_FIELDS];
+        let n = unsafe { (self.decode)(event_id, data1, data2, fields.as_mut_ptr(), MAX_FIELDS) };
+        if n == 0 {
+            return None;
+        }
+        Some(
+            fields[..n.min(MAX_FIELDS)]
+                .iter()
+                .map(|f| (c_field(&f.name), c_field(&f.value)))
+                .collect(),
+        )
+    }
+}
+
+/// Reads a fixed-size C string field, tolerating a missing terminator.
+fn c_field(buf: &[c_char]) -> String {
+    let bytes: Vec<u8> = buf.iter().map(|&c| c as u8).collect();
+    match CStr::from_bytes_until_nul(&bytes) {
+        Ok(s) => s.to_string_lossy().into_owned(),
+        Err(_) => String::from_utf8_lossy(&bytes).into_owned(),
+    }
+}
+
+pub fn render(fields: &[(String, String)]) -> String {
+    fields
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Serialize)]
+pub struct DecodedHit {
+    pub event_id: u32,
+    pub fields: String,
+    pub count: u64,
+}
+
+/// Counts distinct decoded renderings per event.
+#[derive(Default)]
+pub struct DecodedCounts {
+    counts: BTreeMap<u32, HashMap<String, u64>>,
+}
+
+impl DecodedCounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, event_id: u32, rendered: String) {
+        let counts = self.counts.entry(event_id).or_default();
+        if let Some(count) = counts.get_mut(&rendered) {
+            *count += 1;
+        } else if counts.len() < MAX_TRACKED_RENDERINGS {
+            counts.insert(rendered, 1);
+        }
+    }
+
+    /// Hits ordered by event ID and then by descending count.
+    pub fn hits(&self) -> Vec<DecodedHit> {
+        let mut hits = Vec::new();
+        for (&event_id, counts) in &self.counts {
+            let mut per_event: Vec<(&String, u64)> = counts.iter().map(|(f, &c)| (f, c)).collect();
+            per_event.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+            hits.extend(per_event.into_iter().map(|(fields, count)| DecodedHit {
+                event_id,
+                fields: fields.clone(),
+                count,
+            }));
+        }
+        hits
+    }
+}
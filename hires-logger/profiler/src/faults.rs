@@ -0,0 +1,96 @@
+//! `--faults`: page faults taken during the run (see `rt::faults`).
+//!
+//! Two measures, from connecting (and `--mlock`, whose own faults are not
+//! counted) to the end of the run. The ring's own are the pages of the
+//! profiler's mapping that became resident meanwhile (`mincore`): each was
+//! faulted in by the drain thread. The process page faults are every fault
+//! the profiler took on any memory, aggregation and report buffers
+//! included; they bound the ring's from above. Producers map the ring on
+//! their own and fault on their own page tables; they lock their mapping
+//! with `HiResConn::lock_ring` (`rt::InitOptions::lock_ring`).
+
+use crate::info;
+use rt::HiResConn;
+use rt::faults::{FaultCounts, Residency};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct FaultReport {
+    /// The ring was locked with `--mlock`.
+    pub locked: bool,
+    /// Faults of the whole profiler process, not only on the ring.
+    pub process_minor_faults: u64,
+    pub process_major_faults: u64,
+    /// Pages of the ring; `None` on the perf fallback.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ring_pages: Option<u64>,
+    /// Ring pages mapped into the profiler at the start and end of the run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resident_at_start: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resident_at_end: Option<u64>,
+    /// Ring pages that became resident during the run: faults on the ring.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ring_pages_faulted_in: Option<u64>,
+}
+
+pub struct FaultTracker {
+    locked: bool,
+    start: FaultCounts,
+    residency: Option<Residency>,
+}
+
+impl FaultTracker {
+    /// Starts counting; call after locking the ring.
+    pub fn start(conn: &HiResConn, locked: bool) -> Self {
+        FaultTracker {
+            locked,
+            start: FaultCounts::process().unwrap_or_default(),
+            residency: conn.ring_residency(),
+        }
+    }
+
+    pub fn report(&self, conn: &HiResConn) -> FaultReport {
+        let faults = FaultCounts::process()
+            .unwrap_or_default()
+            .since(&self.start);
+        let end = conn.ring_residency();
+        FaultReport {
+            locked: self.locked,
+            process_minor_faults: faults.minor,
+            process_major_faults: faults.major,
+            ring_pages: self.residency.or(end).map(|r| r.pages),
+            resident_at_start: self.residency.map(|r| r.resident),
+            resident_at_end: end.map(|r| r.resident),
+            ring_pages_faulted_in: self
+                .residency
+                .zip(end)
+                .map(|(start, end)| end.resident.saturating_sub(start.resident)),
+        }
+    }
+}
+
+pub fn print_report(r: &FaultReport) {
+    info!("---- Page faults ----");
+    info!(
+        "Process page faults during run: {} minor, {} major (any memory, ring {})",
+        r.process_minor_faults,
+        r.process_major_faults,
+        if r.locked { "locked" } else { "not locked" }
+    );
+    if let (Some(pages), Some(start), Some(end), Some(faulted)) = (
+        r.ring_pages,
+        r.resident_at_start,
+        r.resident_at_end,
+        r.ring_pages_faulted_in,
+    ) {
+        info!(
+            "Ring pages resident: {} of {} at start, {} at end ({} faulted in during run)",
+            start, pages, end, faulted
+        );
+        if end < pages && !r.locked {
+            info!("Note: draining may still fault on the rest; --mlock maps it up front.");
+        }
+    }
+    info!();
+}
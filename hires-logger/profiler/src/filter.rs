@@ -0,0 +1,394 @@
+//! Entry filter expressions.
+//!
+//! A small boolean expression language over entry fields, e.g.
+//!
+//! ```text
+//! event_id == 5 && data1 > 1000 && cpu in [0, 1]
+//! !(kernel == 1) || event_id in [3, 4]
+//! ```
+//!
+//! Fields: `event_id`, `cpu`, `flags`, `kernel` (0/1), `timestamp`, `data1`,
+//! `data2`. Operators: `==`, `!=`, `<`, `<=`, `>`, `>=`, `in [..]`, `&&`,
+//! `||`, `!` and parentheses. Numbers may be decimal or `0x` hex.
+
+use rt::{LOG_FLAG_KERNEL, log_entry_t};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    EventId,
+    Cpu,
+    Flags,
+    Kernel,
+    Timestamp,
+    Data1,
+    Data2,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "event_id" | "event" | "id" => Field::EventId,
+            "cpu" | "cpu_id" => Field::Cpu,
+            "flags" => Field::Flags,
+            "kernel" => Field::Kernel,
+            "timestamp" | "ts" => Field::Timestamp,
+            "data1" => Field::Data1,
+            "data2" => Field::Data2,
+            _ => return None,
+        })
+    }
+
+    fn get(self, entry: &log_entry_t) -> u64 {
+        match self {
+            Field::EventId => entry.event_id as u64,
+            Field::Cpu => entry.cpu_id as u64,
+            Field::Flags => entry.flags as u64,
+            Field::Kernel => (entry.flags & (LOG_FLAG_KERNEL as u16) != 0) as u64,
+            Field::Timestamp => entry.timestamp,
+            Field::Data1 => entry.data1,
+            Field::Data2 => entry.data2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Cmp(Field, CmpOp, u64),
+    In(Field, Vec<u64>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, entry: &log_entry_t) -> bool {
+        match self {
+            Expr::Cmp(field, op, value) => {
+                let v = field.get(entry);
+                match op {
+                    CmpOp::Eq => v == *value,
+                    CmpOp::Ne => v != *value,
+                    CmpOp::Lt => v < *value,
+                    CmpOp::Le => v <= *value,
+                    CmpOp::Gt => v > *value,
+                    CmpOp::Ge => v >= *value,
+                }
+            }
+            Expr::In(field, values) => values.contains(&field.get(entry)),
+            Expr::Not(e) => !e.eval(entry),
+            Expr::And(a, b) => a.eval(entry) && b.eval(entry),
+            Expr::Or(a, b) => a.eval(entry) || b.eval(entry),
+        }
+    }
+}
+
+/// A parsed `--filter` expression.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        if let Some(tok) = parser.peek() {
+            return Err(format!("unexpected '{}' after expression", tok));
+        }
+        Ok(Filter { expr })
+    }
+
+    #[inline]
+    pub fn matches(&self, entry: &log_entry_t) -> bool {
+        self.expr.eval(entry)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(u64),
+    Op(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Ident(s) => write!(f, "{}", s),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+/// Operators, longest first so `<=` is not read as `<` followed by `=`.
+const OPERATORS: &[&str] = &[
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "(", ")", "[", "]", ",",
+];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+    while !rest.is_empty() {
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            if len == 0 {
+                return Err(format!("unexpected character '{}'", rest.chars().next().unwrap()));
+            }
+            let word = &rest[..len];
+            tokens.push(if word.starts_with(|c: char| c.is_ascii_digit()) {
+                Token::Number(parse_number(word)?)
+            } else {
+                Token::Ident(word.to_string())
+            });
+            rest = &rest[len..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn parse_number(word: &str) -> Result<u64, String> {
+    let parsed = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => word.parse(),
+    };
+    parsed.map_err(|_| format!("invalid number '{}'", word))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let tok = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        tok
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(o)) if *o == op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.eat(op) {
+            Ok(())
+        } else {
+            Err(match self.peek() {
+                Some(tok) => format!("expected '{}', found '{}'", op, tok),
+                None => format!("expected '{}' at end of expression", op),
+            })
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.and()?;
+        while self.eat("||") {
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while self.eat("&&") {
+            lhs = Expr::And(Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.or()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let field = match self.next() {
+            Some(Token::Ident(name)) => {
+                Field::from_name(&name).ok_or_else(|| format!("unknown field '{}'", name))?
+            }
+            Some(tok) => return Err(format!("expected a field name, found '{}'", tok)),
+            None => return Err("expected a field name at end of expression".to_string()),
+        };
+
+        if matches!(self.peek(), Some(Token::Ident(word)) if word == "in") {
+            self.pos += 1;
+            self.expect("[")?;
+            let mut values = Vec::new();
+            if !self.eat("]") {
+                loop {
+                    values.push(self.number()?);
+                    if self.eat("]") {
+                        break;
+                    }
+                    self.expect(",")?;
+                }
+            }
+            return Ok(Expr::In(field, values));
+        }
+
+        let op = match self.next() {
+            Some(Token::Op("==")) => CmpOp::Eq,
+            Some(Token::Op("!=")) => CmpOp::Ne,
+            Some(Token::Op("<")) => CmpOp::Lt,
+            Some(Token::Op("<=")) => CmpOp::Le,
+            Some(Token::Op(">")) => CmpOp::Gt,
+            Some(Token::Op(">=")) => CmpOp::Ge,
+            Some(tok) => return Err(format!("expected a comparison operator, found '{}'", tok)),
+            None => return Err("expected a comparison operator at end of expression".to_string()),
+        };
+        Ok(Expr::Cmp(field, op, self.number()?))
+    }
+
+    fn number(&mut self) -> Result<u64, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(n),
+            Some(tok) => Err(format!("expected a number, found '{}'", tok)),
+            None => Err("expected a number at end of expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(event_id: u32, cpu: u32, data1: u64) -> log_entry_t {
+        log_entry_t {
+            timestamp: 0,
+            event_id,
+            cpu_id: cpu,
+            flags: LOG_FLAG_KERNEL as u16,
+            data1,
+            data2: 0,
+        }
+    }
+
+    fn matches(expr: &str, e: &log_entry_t) -> bool {
+        Filter::parse(expr).unwrap().matches(e)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let e = entry(5, 0, 0);
+        // event_id == 5 || (event_id == 6 && data1 > 0)
+        assert!(matches("event_id == 5 || event_id == 6 && data1 > 0", &e));
+        // (event_id == 6 && data1 > 0) || event_id == 5
+        assert!(matches("event_id == 6 && data1 > 0 || event_id == 5", &e));
+        assert!(!matches(
+            "(event_id == 5 || event_id == 6) && data1 > 0",
+            &e
+        ));
+    }
+
+    #[test]
+    fn negation_binds_tighter_than_and() {
+        let e = entry(5, 0, 0);
+        assert!(!matches("!event_id == 5 && data1 == 0", &e));
+        assert!(matches("!(event_id == 5 && data1 == 1)", &e));
+        assert!(matches("!!event_id == 5", &e));
+        assert!(!matches("!(kernel == 1) || event_id in [3, 4]", &e));
+    }
+
+    #[test]
+    fn range_bounds() {
+        let expr = "data1 >= 10 && data1 < 20";
+        assert!(!matches(expr, &entry(0, 0, 9)));
+        assert!(matches(expr, &entry(0, 0, 10)));
+        assert!(matches(expr, &entry(0, 0, 19)));
+        assert!(!matches(expr, &entry(0, 0, 20)));
+        assert!(matches("data1 <= 0xff && data1 > 0", &entry(0, 0, 255)));
+        assert!(matches("data1 != 3", &entry(0, 0, 4)));
+        assert!(matches(
+            "ts == 0 && data1 == 18446744073709551615",
+            &entry(0, 0, u64::MAX)
+        ));
+    }
+
+    #[test]
+    fn in_lists() {
+        let expr = "cpu in [0, 1]";
+        assert!(matches(expr, &entry(0, 0, 0)));
+        assert!(matches(expr, &entry(0, 1, 0)));
+        assert!(!matches(expr, &entry(0, 2, 0)));
+        assert!(!matches("cpu in []", &entry(0, 0, 0)));
+        assert!(matches(
+            "event_id == 5 && data1 > 1000 && cpu in [0,1]",
+            &entry(5, 1, 1001)
+        ));
+    }
+
+    #[test]
+    fn malformed_input_is_an_error() {
+        for expr in [
+            "event_id ==",
+            "event_id 5",
+            "== 5",
+            "bogus == 5",
+            "event_id == 5 &&",
+            "event_id == 5 ||",
+            "(event_id == 5",
+            "event_id == 5)",
+            "event_id == 5 data1 == 1",
+            "cpu in [0, 1",
+            "cpu in [0 1]",
+            "cpu in [0,]",
+            "cpu in 0",
+            "data1 == 0xzz",
+            "data1 == 18446744073709551616",
+            "data1 == -1",
+            "data1 = 1",
+            "event_id == 5 & data1 == 1",
+            "!",
+            "()",
+        ] {
+            assert!(Filter::parse(expr).is_err(), "{:?} parsed", expr);
+        }
+    }
+
+    #[test]
+    fn empty_expressions_are_errors() {
+        for expr in ["", "   ", "\t\n"] {
+            assert_eq!(
+                Filter::parse(expr).err().as_deref(),
+                Some("expected a field name at end of expression"),
+                "{:?}",
+                expr
+            );
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Loss detection from producer sequence numbers.
+//!
+//! When producers put a per-event, monotonically increasing sequence number
+//! in one of the payload fields, any jump larger than one between two
+//! consecutive entries of the same event means entries were lost in between
+//! (dropped by the ring or never logged). The gap is recorded together with
+//! the timestamps of the entries bracketing it, so the report can say which
+//! intervals of the capture are untrustworthy.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+pub struct Gap {
+    pub event_id: u32,
+    /// Last sequence number seen before the gap.
+    pub after_seq: u64,
+    /// First sequence number seen after the gap.
+    pub before_seq: u64,
+    pub missing: u64,
+    pub start_ms: u64,
+    pub end_ms: u64,
+}
+
+#[derive(Serialize)]
+pub struct EventGaps {
+    pub event_id: u32,
+    pub gaps: u64,
+    pub missing: u64,
+    /// Entries whose sequence number did not advance (reordered or reset).
+    pub out_of_order: u64,
+}
+
+#[derive(Serialize)]
+pub struct GapReport {
+    pub total_missing: u64,
+    pub events: Vec<EventGaps>,
+    pub ranges: Vec<Gap>,
+}
+
+struct Stream {
+    last_seq: u64,
+    last_ts_ns: u64,
+    out_of_order: u64,
+}
+
+/// Raw gap, with absolute timestamps until the report is produced.
+struct RawGap {
+    event_id: u32,
+    after_seq: u64,
+    before_seq: u64,
+    start_ns: u64,
+    end_ns: u64,
+}
+
+#[derive(Default)]
+pub struct GapTracker {
+    streams: BTreeMap<u32, Stream>,
+    gaps: Vec<RawGap>,
+}
+
+impl GapTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, event_id: u32, seq: u64, ts_ns: u64) {
+        let Some(stream) = self.streams.get_mut(&event_id) else {
+            self.streams.insert(
+                event_id,
+                Stream {
+                    last_seq: seq,
+                    last_ts_ns: ts_ns,
+                    out_of_order: 0,
+                },
+            );
+            return;
+        };
+
+        // Distances are taken modulo 2^64 so a counter wrapping from u64::MAX
+        // to 0 is continuous; a step back of up to half the range is a
+        // reorder or reset rather than a gap.
+        let step = seq.wrapping_sub(stream.last_seq);
+        if step == 0 || step > u64::MAX / 2 {
+            stream.out_of_order += 1;
+            return;
+        }
+        if step > 1 {
+            self.gaps.push(RawGap {
+                event_id,
+                after_seq: stream.last_seq,
+                before_seq: seq,
+                start_ns: stream.last_ts_ns,
+                end_ns: ts_ns,
+            });
+        }
+        stream.last_seq = seq;
+        stream.last_ts_ns = ts_ns;
+    }
+
+    /// Builds the report, expressing times as offsets from `origin_ns`.
+    pub fn report(&self, origin_ns: u64) -> GapReport {
+        let to_ms = |ts: u64| ts.saturating_sub(origin_ns) / 1_000_000;
+
+        let ranges: Vec<Gap> = self
+            .gaps
+            .iter()
+            .map(|g| Gap {
+                event_id: g.event_id,
+                after_seq: g.after_seq,
+                before_seq: g.before_seq,
+                missing: g.before_seq.wrapping_sub(g.after_seq) - 1,
+                start_ms: to_ms(g.start_ns),
+                end_ms: to_ms(g.end_ns),
+            })
+            .collect();
+
+        let events: Vec<EventGaps> = self
+            .streams
+            .iter()
+            .map(|(&event_id, stream)| {
+                let mine = ranges.iter().filter(|g| g.event_id == event_id);
+                EventGaps {
+                    event_id,
+                    gaps: mine.clone().count() as u64,
+                    missing: mine.map(|g| g.missing).sum(),
+                    out_of_order: stream.out_of_order,
+                }
+            })
+            .collect();
+
+        GapReport {
+            total_missing: events.iter().map(|e| e.missing).sum(),
+            events,
+            ranges,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gap_is_recorded_with_its_bracketing_entries() {
+        let mut tracker = GapTracker::new();
+        tracker.record(3, 10, 1_000_000);
+        tracker.record(3, 11, 2_000_000);
+        tracker.record(3, 15, 5_000_000);
+        tracker.record(3, 16, 6_000_000);
+        let report = tracker.report(0);
+        assert_eq!(report.total_missing, 3);
+        assert_eq!(report.ranges.len(), 1);
+        let gap = &report.ranges[0];
+        assert_eq!((gap.after_seq, gap.before_seq, gap.missing), (11, 15, 3));
+        assert_eq!((gap.start_ms, gap.end_ms), (2, 5));
+        assert_eq!(report.events[0].gaps, 1);
+        assert_eq!(report.events[0].out_of_order, 0);
+    }
+
+    #[test]
+    fn wraparound_is_continuous() {
+        let mut tracker = GapTracker::new();
+        tracker.record(3, u64::MAX - 1, 0);
+        tracker.record(3, u64::MAX, 1);
+        tracker.record(3, 0, 2);
+        tracker.record(3, 1, 3);
+        let report = tracker.report(0);
+        assert_eq!(report.total_missing, 0);
+        assert!(report.ranges.is_empty());
+        assert_eq!(report.events[0].out_of_order, 0);
+
+        // A gap spanning the wrap counts the entries on both sides.
+        tracker.record(4, u64::MAX - 1, 0);
+        tracker.record(4, 2, 1);
+        let report = tracker.report(0);
+        assert_eq!(report.total_missing, 3);
+        assert_eq!(report.ranges[0].missing, 3);
+    }
+
+    #[test]
+    fn steps_back_are_out_of_order() {
+        let mut tracker = GapTracker::new();
+        tracker.record(3, 10, 0);
+        tracker.record(3, 10, 1);
+        tracker.record(3, 9, 2);
+        tracker.record(3, 11, 3);
+        let report = tracker.report(0);
+        assert_eq!(report.total_missing, 0);
+        assert_eq!(report.events[0].out_of_order, 2);
+    }
+}
@@ -0,0 +1,172 @@
+//! Event aliases and groups given on the command line.
+//!
+//! `--alias 5=tx_doorbell` names an event in every report, taking
+//! precedence over a name the workload registers (`HIRES_EV_EVENT_NAME`).
+//! `--group net_tx=5,6,7` adds a row rolling up the samples of its events,
+//! e.g. the stages of one path logged under separate IDs.
+
+use crate::names::Names;
+use crate::stats::percentile;
+use crate::units::Scale;
+use serde::Serialize;
+
+/// A parsed `--alias` specification.
+#[derive(Clone, Debug)]
+pub struct Alias {
+    event_id: u32,
+    name: String,
+}
+
+impl Alias {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let (event, name) = input
+            .split_once('=')
+            .ok_or_else(|| format!("expected <event_id>=<name>, got '{}'", input))?;
+        let event_id = parse_event_id(event)?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(format!("empty name for event {}", event_id));
+        }
+        Ok(Alias {
+            event_id,
+            name: name.to_string(),
+        })
+    }
+}
+
+/// Applies the aliases to the event name table.
+pub fn apply_aliases(aliases: &[Alias], names: &mut Names) {
+    for a in aliases {
+        names.alias(a.event_id, a.name.clone());
+    }
+}
+
+/// A parsed `--group` specification.
+#[derive(Clone, Debug)]
+pub struct Group {
+    name: String,
+    events: Vec<u32>,
+}
+
+impl Group {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let (name, events) = input
+            .split_once('=')
+            .ok_or_else(|| format!("expected <name>=<event_id>,..., got '{}'", input))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(format!("empty group name in '{}'", input));
+        }
+        let mut events = events
+            .split(',')
+            .map(parse_event_id)
+            .collect::<Result<Vec<u32>, String>>()?;
+        events.sort_unstable();
+        events.dedup();
+        Ok(Group {
+            name: name.to_string(),
+            events,
+        })
+    }
+}
+
+fn parse_event_id(s: &str) -> Result<u32, String> {
+    s.trim()
+        .parse()
+        .map_err(|_| format!("invalid event ID '{}'", s.trim()))
+}
+
+#[derive(Serialize)]
+pub struct GroupResult {
+    pub name: String,
+    pub events: Vec<u32>,
+    pub count: u64,
+    pub avg: f64,
+    pub p50: f64,
+    pub p99: f64,
+    pub max: f64,
+    /// Entries per second of session wall time.
+    pub rate_per_s: f64,
+}
+
+/// Statistics over the pooled samples of each group's events; `samples`
+/// returns an event's `data1` values.
+pub fn summarize<'a>(
+    groups: &[Group],
+    samples: impl Fn(u32) -> &'a [u64],
+    scale: Scale,
+    session_s: f64,
+) -> Vec<GroupResult> {
+    let session_s = session_s.max(f64::MIN_POSITIVE);
+    groups
+        .iter()
+        .map(|g| {
+            let mut pooled: Vec<u64> = g
+                .events
+                .iter()
+                .flat_map(|&id| samples(id).iter().copied())
+                .collect();
+            pooled.sort_unstable();
+            let sum: u128 = pooled.iter().map(|&v| v as u128).sum();
+            let count = pooled.len() as u64;
+            GroupResult {
+                name: g.name.clone(),
+                events: g.events.clone(),
+                count,
+                avg: scale.cycles(if count > 0 {
+                    sum as f64 / count as f64
+                } else {
+                    0.0
+                }),
+                p50: scale.cycles(percentile(&pooled, 50.0) as f64),
+                p99: scale.cycles(percentile(&pooled, 99.0) as f64),
+                max: scale.cycles(*pooled.last().unwrap_or(&0) as f64),
+                rate_per_s: count as f64 / session_s,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Unit;
+
+    #[test]
+    fn parses_groups() {
+        let g = Group::parse(" net_tx = 7, 5 ,6 ").unwrap();
+        assert_eq!(
+            (g.name.as_str(), g.events.as_slice()),
+            ("net_tx", &[5, 6, 7][..])
+        );
+        let g = Group::parse("one=3").unwrap();
+        assert_eq!(g.events, [3]);
+    }
+
+    #[test]
+    fn duplicate_group_events_count_once() {
+        let g = Group::parse("rx=5,6,5,6,5").unwrap();
+        assert_eq!(g.events, [5, 6]);
+        let samples = |id| -> &'static [u64] { if id == 5 { &[10, 20] } else { &[30] } };
+        let r = &summarize(&[g], samples, Scale::new(Unit::Cycles, 0), 1.0)[0];
+        assert_eq!((r.count, r.avg, r.max), (3, 20.0, 30.0));
+    }
+
+    #[test]
+    fn rejects_malformed_groups() {
+        for input in [
+            "net_tx", "=5,6", " =5", "g=", "g=5,", "g=5,,6", "g=x", "g=-1", "g=5;6",
+        ] {
+            assert!(Group::parse(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn parses_aliases() {
+        let a = Alias::parse(" 5 = tx doorbell ").unwrap();
+        assert_eq!((a.event_id, a.name.as_str()), (5, "tx doorbell"));
+        for input in ["5", "5=", "5= ", "x=name", "=name"] {
+            assert!(Alias::parse(input).is_err(), "{}", input);
+        }
+    }
+}
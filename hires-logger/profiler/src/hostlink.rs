@@ -0,0 +1,840 @@
+//! Guest-to-host forwarding of log entries (`--forward` and the
+//! `host-collector` subcommand).
+//!
+//! The guest-side consumer forwards every valid entry it pops to a collector
+//! on the host, which stamps each arrival with the host TSC and
+//! CLOCK_MONOTONIC. Two transports are supported:
+//!
+//! * `vsock:CID:PORT` (guest) / `vsock:PORT` (host): an AF_VSOCK stream. The
+//!   stream starts with a [`Hello`] followed by fixed-size records.
+//! * `ivshmem:PATH`: a single-producer ring in shared memory. On the host
+//!   PATH is the file backing QEMU's `memory-backend-file,share=on` object
+//!   (e.g. `/dev/shm/hires`); in the guest it is the ivshmem BAR, e.g.
+//!   `/sys/bus/pci/devices/0000:00:05.0/resource2`. The collector creates and
+//!   initializes the ring, so it must be started first.
+//!
+//! Over vsock the forwarder also synchronizes with the collector (see
+//! [`crate::clocksync`]) on the next port up, so the guest's report carries
+//! the offset/drift model for merging the two captures.
+//!
+//! Guest and host clocks only differ by an offset (and, with TSC scaling, a
+//! rate), so arrival delays are reported relative to the smallest one seen
+//! per event: the attributable part is the queueing and transport time on
+//! top of the fastest observed path.
+
+use crate::capture::CaptureWriter;
+use crate::clocksync::{self, ClockModel, ClockSync};
+use crate::stats::{Reservoir, percentile};
+use crate::units::{Scale, Unit, cycles_to_ns, read_tsc};
+use clap::Args;
+use rt::{LOG_FLAG_KERNEL, LOG_FLAG_TSC, log_entry_t};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"HIRESFWD");
+/// Size of one serialized entry on either transport.
+const RECORD_SIZE: usize = 40;
+
+/// Ring header offsets; head and tail sit on their own cache lines.
+const SHM_MAGIC: usize = 0;
+const SHM_TSC_HZ: usize = 8;
+const SHM_CAPACITY: usize = 16;
+const SHM_HEAD: usize = 64;
+const SHM_TAIL: usize = 128;
+const SHM_RECORDS: usize = 192;
+
+/// How long the guest waits for the collector to initialize the ring.
+const SHM_OPEN_TIMEOUT: Duration = Duration::from_secs(60);
+/// Delays kept per event for percentiles, 1 MiB each.
+const MAX_DELAY_SAMPLES: usize = 1 << 16;
+
+#[derive(Clone, Debug)]
+pub enum Transport {
+    /// Guest side: host CID and port. Host side: CID is ignored.
+    Vsock {
+        cid: u32,
+        port: u32,
+    },
+    Ivshmem(PathBuf),
+}
+
+impl Transport {
+    /// Parses `vsock:CID:PORT`, `vsock:PORT` (listening side) or
+    /// `ivshmem:PATH`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if let Some(path) = s.strip_prefix("ivshmem:") {
+            return Ok(Transport::Ivshmem(PathBuf::from(path)));
+        }
+        let Some(addr) = s.strip_prefix("vsock:") else {
+            return Err(format!(
+                "expected vsock:CID:PORT, vsock:PORT or ivshmem:PATH, got '{}'",
+                s
+            ));
+        };
+        let num = |v: &str| {
+            v.parse::<u32>()
+                .map_err(|e| format!("invalid vsock address '{}': {}", addr, e))
+        };
+        match addr.split_once(':') {
+            Some((cid, port)) => Ok(Transport::Vsock {
+                cid: num(cid)?,
+                port: num(port)?,
+            }),
+            None => Ok(Transport::Vsock {
+                cid: libc::VMADDR_CID_ANY,
+                port: num(addr)?,
+            }),
+        }
+    }
+}
+
+/// First bytes of a vsock stream.
+struct Hello {
+    tsc_hz: u64,
+}
+
+impl Hello {
+    fn encode(&self) -> [u8; 16] {
+        let mut buf = [0u8; 16];
+        buf[..8].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[8..].copy_from_slice(&self.tsc_hz.to_le_bytes());
+        buf
+    }
+
+    fn decode(buf: &[u8; 16]) -> io::Result<Self> {
+        if u64::from_le_bytes(buf[..8].try_into().unwrap()) != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a hires forwarding stream",
+            ));
+        }
+        Ok(Hello {
+            tsc_hz: u64::from_le_bytes(buf[8..].try_into().unwrap()),
+        })
+    }
+}
+
+fn encode(entry: &log_entry_t) -> [u8; RECORD_SIZE] {
+    let mut buf = [0u8; RECORD_SIZE];
+    buf[0..8].copy_from_slice(&entry.timestamp.to_le_bytes());
+    buf[8..12].copy_from_slice(&entry.event_id.to_le_bytes());
+    buf[12..16].copy_from_slice(&entry.cpu_id.to_le_bytes());
+    buf[16..18].copy_from_slice(&entry.flags.to_le_bytes());
+    buf[24..32].copy_from_slice(&entry.data1.to_le_bytes());
+    buf[32..40].copy_from_slice(&entry.data2.to_le_bytes());
+    buf
+}
+
+fn decode(buf: &[u8]) -> log_entry_t {
+    let u64_at = |i: usize| u64::from_le_bytes(buf[i..i + 8].try_into().unwrap());
+    let u32_at = |i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+    log_entry_t {
+        timestamp: u64_at(0),
+        event_id: u32_at(8),
+        cpu_id: u32_at(12),
+        flags: u16::from_le_bytes(buf[16..18].try_into().unwrap()),
+        data1: u64_at(24),
+        data2: u64_at(32),
+    }
+}
+
+fn vsock_addr(cid: u32, port: u32) -> libc::sockaddr_vm {
+    let mut addr: libc::sockaddr_vm = unsafe { mem::zeroed() };
+    addr.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+    addr.svm_cid = cid;
+    addr.svm_port = port;
+    addr
+}
+
+fn vsock_socket() -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::socket(libc::AF_VSOCK, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+pub fn vsock_connect(cid: u32, port: u32) -> io::Result<File> {
+    let sock = vsock_socket()?;
+    let addr = vsock_addr(cid, port);
+    let ret = unsafe {
+        libc::connect(
+            sock.as_raw_fd(),
+            &addr as *const _ as *const libc::sockaddr,
+            mem::size_of_val(&addr) as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(File::from(sock))
+}
+
+/// Binds a listening socket on `port` for any CID.
+pub fn vsock_listen(port: u32) -> io::Result<OwnedFd> {
+    let sock = vsock_socket()?;
+    let addr = vsock_addr(libc::VMADDR_CID_ANY, port);
+    let ret = unsafe {
+        libc::bind(
+            sock.as_raw_fd(),
+            &addr as *const _ as *const libc::sockaddr,
+            mem::size_of_val(&addr) as libc::socklen_t,
+        )
+    };
+    if ret < 0 || unsafe { libc::listen(sock.as_raw_fd(), 1) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(sock)
+}
+
+/// Waits for a guest to connect; returns the stream and the guest's CID.
+pub fn vsock_accept(listener: &OwnedFd) -> io::Result<(File, u32)> {
+    let mut peer: libc::sockaddr_vm = unsafe { mem::zeroed() };
+    let mut len = mem::size_of_val(&peer) as libc::socklen_t;
+    let fd = unsafe {
+        libc::accept4(
+            listener.as_raw_fd(),
+            &mut peer as *mut _ as *mut libc::sockaddr,
+            &mut len,
+            libc::SOCK_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((
+        File::from(unsafe { OwnedFd::from_raw_fd(fd) }),
+        peer.svm_cid,
+    ))
+}
+
+/// Shared-memory ring mapped from a file (host) or an ivshmem BAR (guest).
+pub struct ShmRing {
+    base: *mut u8,
+    len: usize,
+    capacity: u64,
+}
+
+impl ShmRing {
+    fn map(file: &File, len: usize) -> io::Result<Self> {
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ShmRing {
+            base: base as *mut u8,
+            len,
+            capacity: 0,
+        })
+    }
+
+    /// Rejects files that cannot hold the header and at least two records.
+    fn check_len(path: &Path, len: usize) -> io::Result<()> {
+        if len <= SHM_RECORDS + RECORD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is too small for a ring", path.display()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Records that fit in a mapping of `len` bytes.
+    fn max_capacity(len: usize) -> u64 {
+        ((len - SHM_RECORDS) / RECORD_SIZE) as u64
+    }
+
+    /// Host side: sizes the backing file and initializes an empty ring.
+    fn create(path: &Path, len: usize) -> io::Result<Self> {
+        Self::check_len(path, len)?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        file.set_len(len as u64)?;
+        let mut ring = Self::map(&file, len)?;
+        ring.capacity = Self::max_capacity(len);
+        ring.word(SHM_MAGIC).store(0, Ordering::SeqCst);
+        ring.word(SHM_TSC_HZ).store(0, Ordering::Relaxed);
+        ring.word(SHM_HEAD).store(0, Ordering::Relaxed);
+        ring.word(SHM_TAIL).store(0, Ordering::Relaxed);
+        ring.word(SHM_CAPACITY)
+            .store(ring.capacity, Ordering::Relaxed);
+        ring.word(SHM_MAGIC).store(MAGIC, Ordering::Release);
+        Ok(ring)
+    }
+
+    /// Guest side: maps the BAR and waits up to `timeout` for the collector
+    /// to initialize it.
+    ///
+    /// In a confidential VM the BAR is host memory, so nothing read from it
+    /// is trusted: the capacity must fit the mapping, and `push` checks the
+    /// indices on every call.
+    fn open(path: &Path, timeout: Duration) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let len = file.metadata()?.len() as usize;
+        Self::check_len(path, len)?;
+        let mut ring = Self::map(&file, len)?;
+        let deadline = Instant::now() + timeout;
+        while ring.word(SHM_MAGIC).load(Ordering::Acquire) != MAGIC {
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "no collector initialized {} within {:?}",
+                        path.display(),
+                        timeout
+                    ),
+                ));
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        let capacity = ring.word(SHM_CAPACITY).load(Ordering::Relaxed);
+        if !(1..=Self::max_capacity(len)).contains(&capacity) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "ring capacity {} in {} does not fit its {} bytes",
+                    capacity,
+                    path.display(),
+                    len
+                ),
+            ));
+        }
+        ring.capacity = capacity;
+        Ok(ring)
+    }
+
+    fn word(&self, offset: usize) -> &AtomicU64 {
+        unsafe { &*(self.base.add(offset) as *const AtomicU64) }
+    }
+
+    fn slot(&self, index: u64) -> *mut u8 {
+        unsafe {
+            self.base
+                .add(SHM_RECORDS + (index % self.capacity) as usize * RECORD_SIZE)
+        }
+    }
+
+    /// Returns `Ok(false)` if the ring is full, and an error if the indices
+    /// are inconsistent (the consumer's tail past the head, or more than a
+    /// ring's worth behind it).
+    fn push(&self, record: &[u8; RECORD_SIZE]) -> io::Result<bool> {
+        let head = self.word(SHM_HEAD).load(Ordering::Relaxed);
+        let tail = self.word(SHM_TAIL).load(Ordering::Acquire);
+        let used = head.wrapping_sub(tail);
+        if used > self.capacity {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("corrupt ring indices: head {}, tail {}", head, tail),
+            ));
+        }
+        if used == self.capacity {
+            return Ok(false);
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(record.as_ptr(), self.slot(head), RECORD_SIZE);
+        }
+        self.word(SHM_HEAD)
+            .store(head.wrapping_add(1), Ordering::Release);
+        Ok(true)
+    }
+
+    fn pop(&self) -> Option<[u8; RECORD_SIZE]> {
+        let tail = self.word(SHM_TAIL).load(Ordering::Relaxed);
+        if tail == self.word(SHM_HEAD).load(Ordering::Acquire) {
+            return None;
+        }
+        let mut record = [0u8; RECORD_SIZE];
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.slot(tail), record.as_mut_ptr(), RECORD_SIZE);
+        }
+        self.word(SHM_TAIL)
+            .store(tail.wrapping_add(1), Ordering::Release);
+        Some(record)
+    }
+}
+
+impl Drop for ShmRing {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.base as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// Guest side of the link, fed from the consumer loop.
+pub enum Forwarder {
+    Vsock {
+        stream: BufWriter<File>,
+        sync: Option<ClockSync>,
+    },
+    Ivshmem {
+        ring: ShmRing,
+        dropped: u64,
+    },
+}
+
+impl Forwarder {
+    pub fn connect(transport: &Transport, tsc_hz: u64) -> io::Result<Self> {
+        match transport {
+            Transport::Vsock { cid, port } => {
+                let mut stream = BufWriter::new(vsock_connect(*cid, *port)?);
+                stream.write_all(&Hello { tsc_hz }.encode())?;
+                let sync = clocksync::sync_port(*port)
+                    .and_then(|sync_port| ClockSync::connect(*cid, sync_port))
+                    .inspect_err(|e| eprintln!("Warning: clock sync with the host failed: {}", e))
+                    .ok();
+                Ok(Forwarder::Vsock { stream, sync })
+            }
+            Transport::Ivshmem(path) => {
+                let ring = ShmRing::open(path, SHM_OPEN_TIMEOUT)?;
+                ring.word(SHM_TSC_HZ).store(tsc_hz, Ordering::Release);
+                Ok(Forwarder::Ivshmem { ring, dropped: 0 })
+            }
+        }
+    }
+
+    pub fn send(&mut self, entry: &log_entry_t) -> io::Result<()> {
+        let record = encode(entry);
+        match self {
+            Forwarder::Vsock { stream, .. } => stream.write_all(&record),
+            Forwarder::Ivshmem { ring, dropped } => {
+                if !ring.push(&record)? {
+                    *dropped += 1;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Pushes out buffered records, e.g. when the consumer goes idle.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Forwarder::Vsock { stream, .. } => stream.flush(),
+            Forwarder::Ivshmem { .. } => Ok(()),
+        }
+    }
+
+    /// Records lost because the host fell behind (ivshmem only; vsock
+    /// applies backpressure instead).
+    pub fn dropped(&self) -> u64 {
+        match self {
+            Forwarder::Vsock { .. } => 0,
+            Forwarder::Ivshmem { dropped, .. } => *dropped,
+        }
+    }
+
+    /// Takes a closing sync round and returns the guest/host clock model,
+    /// if the host's responder was reachable.
+    pub fn finish_clock_sync(&mut self) -> Option<ClockModel> {
+        let Forwarder::Vsock {
+            sync: Some(sync), ..
+        } = self
+        else {
+            return None;
+        };
+        if let Err(e) = sync.resync() {
+            eprintln!("Warning: closing clock sync round failed: {}", e);
+        }
+        Some(sync.model())
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct CollectorArgs {
+    /// Where guests forward to: vsock:PORT or ivshmem:PATH (the host file backing the ivshmem device)
+    #[arg(short, long, value_parser = Transport::parse)]
+    listen: Transport,
+
+    /// Size of the shared-memory ring file for ivshmem:PATH, in MiB (a power of two, matching the QEMU object)
+    #[arg(long, default_value_t = 16)]
+    ivshmem_mib: usize,
+
+    /// Write every received entry with its host arrival timestamps to this CSV file
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Output units for arrival delays
+    #[arg(long, value_enum, default_value_t = Unit::Ns)]
+    units: Unit,
+}
+
+#[derive(Serialize)]
+pub struct ArrivalDelay {
+    pub event_id: u32,
+    pub count: u64,
+    pub avg: f64,
+    pub p50: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+/// One event's delays; the extremes and the sum are exact, percentiles come
+/// from a sample.
+struct EventDelays {
+    count: u64,
+    sum: i128,
+    min: i128,
+    max: i128,
+    sample: Reservoir<MAX_DELAY_SAMPLES, i128>,
+}
+
+/// Per-event guest-to-host delay above the minimum observed, in ns.
+#[derive(Default)]
+struct DelayTracker {
+    events: BTreeMap<u32, EventDelays>,
+}
+
+impl DelayTracker {
+    fn record(&mut self, event_id: u32, delay_ns: i128) {
+        let d = self.events.entry(event_id).or_insert(EventDelays {
+            count: 0,
+            sum: 0,
+            min: delay_ns,
+            max: delay_ns,
+            sample: Reservoir::default(),
+        });
+        d.count += 1;
+        d.sum += delay_ns;
+        d.min = d.min.min(delay_ns);
+        d.max = d.max.max(delay_ns);
+        d.sample.add(delay_ns);
+    }
+
+    fn report(&self, scale: Scale) -> Vec<ArrivalDelay> {
+        self.events
+            .iter()
+            .map(|(&event_id, d)| {
+                let sorted: Vec<u64> = d
+                    .sample
+                    .sorted()
+                    .iter()
+                    .map(|&v| (v - d.min) as u64)
+                    .collect();
+                let above_min = d.sum - d.min * d.count as i128;
+                ArrivalDelay {
+                    event_id,
+                    count: d.count,
+                    avg: scale.ns(above_min as f64 / d.count as f64),
+                    p50: scale.ns(percentile(&sorted, 50.0) as f64),
+                    p99: scale.ns(percentile(&sorted, 99.0) as f64),
+                    max: scale.ns((d.max - d.min) as f64),
+                }
+            })
+            .collect()
+    }
+}
+
+fn host_monotonic_ns() -> u64 {
+    let mut ts: libc::timespec = unsafe { mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// Receives forwarded entries until the guest disconnects or Ctrl-C.
+pub fn run_collector(args: &CollectorArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })?;
+
+    // Written from its own thread, so a slow disk cannot stall the receive
+    // loop (see capture).
+    let mut output = args
+        .output
+        .as_deref()
+        .map(CaptureWriter::create)
+        .transpose()?;
+    if let Some(out) = &mut output {
+        writeln!(
+            out,
+            "host_tsc,host_ns,timestamp,event_id,cpu_id,flags,data1,data2"
+        )?;
+    }
+
+    let mut delays = DelayTracker::default();
+    let mut received: u64 = 0;
+    let mut guest_tsc_hz = 0;
+    let mut handle = |record: &[u8], guest_tsc_hz: u64| -> io::Result<()> {
+        let host_tsc = read_tsc();
+        let host_ns = host_monotonic_ns();
+        let entry = decode(record);
+        received += 1;
+        if let Some(out) = &mut output {
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{},{}",
+                host_tsc,
+                host_ns,
+                entry.timestamp,
+                entry.event_id,
+                entry.cpu_id,
+                entry.flags,
+                entry.data1,
+                entry.data2
+            )?;
+        }
+        // Compare like with like: TSC-stamped entries against the host TSC
+        // (shared with the guest up to offset and scaling), ns entries
+        // against CLOCK_MONOTONIC.
+        let tsc_stamped = entry.flags & ((LOG_FLAG_KERNEL | LOG_FLAG_TSC) as u16) != 0;
+        let delay_ns = if tsc_stamped {
+            cycles_to_ns(host_tsc, guest_tsc_hz) as i128
+                - cycles_to_ns(entry.timestamp, guest_tsc_hz) as i128
+        } else {
+            host_ns as i128 - entry.timestamp as i128
+        };
+        delays.record(entry.event_id, delay_ns);
+        Ok(())
+    };
+
+    match &args.listen {
+        Transport::Vsock { port, .. } => {
+            let sync_port = clocksync::sync_port(*port)?;
+            clocksync::spawn_responder(sync_port)?;
+            println!(
+                "Waiting for a guest on vsock port {} (clock sync on {})",
+                port, sync_port
+            );
+            let (mut stream, cid) = vsock_accept(&vsock_listen(*port)?)?;
+            let mut hello = [0u8; 16];
+            stream.read_exact(&mut hello)?;
+            guest_tsc_hz = Hello::decode(&hello)?.tsc_hz;
+            println!("Guest CID {} connected, TSC rate {} Hz", cid, guest_tsc_hz);
+            let mut record = [0u8; RECORD_SIZE];
+            while running.load(Ordering::Relaxed) {
+                match stream.read_exact(&mut record) {
+                    Ok(()) => handle(&record, guest_tsc_hz)?,
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        Transport::Ivshmem(path) => {
+            let ring = ShmRing::create(path, args.ivshmem_mib << 20)?;
+            println!(
+                "Ring of {} entries ready in {}, waiting for the guest",
+                ring.capacity,
+                path.display()
+            );
+            while running.load(Ordering::Relaxed) {
+                match ring.pop() {
+                    Some(record) => {
+                        if guest_tsc_hz == 0 {
+                            guest_tsc_hz = ring.word(SHM_TSC_HZ).load(Ordering::Acquire);
+                        }
+                        handle(&record, guest_tsc_hz)?
+                    }
+                    None => thread::sleep(Duration::from_micros(50)),
+                }
+            }
+        }
+    }
+
+    if let Some(out) = output {
+        out.finish()?;
+    }
+
+    // Delays are already in ns; the scale only selects the output unit.
+    let scale = Scale::new(args.units, guest_tsc_hz);
+    println!("---- Guest-to-host arrival delay (above minimum) ----");
+    for d in delays.report(scale) {
+        println!(
+            "Event ID: {}, Count: {}, Average: {} {}, p50: {}, p99: {}, Max: {}",
+            d.event_id,
+            d.count,
+            d.avg,
+            scale.label(),
+            d.p50,
+            d.p99,
+            d.max
+        );
+    }
+    println!();
+    println!("Total entries received: {}", received);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("hires-shm-{}-{}", name, std::process::id()))
+    }
+
+    fn record(n: u8) -> [u8; RECORD_SIZE] {
+        [n; RECORD_SIZE]
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let entry = log_entry_t {
+            timestamp: 0x0102_0304_0506_0708,
+            event_id: 42,
+            cpu_id: 7,
+            flags: LOG_FLAG_TSC as u16,
+            data1: u64::MAX,
+            data2: 12345,
+        };
+        let back = decode(&encode(&entry));
+        assert_eq!(back.timestamp, entry.timestamp);
+        assert_eq!(back.event_id, entry.event_id);
+        assert_eq!(back.cpu_id, entry.cpu_id);
+        assert_eq!(back.flags, entry.flags);
+        assert_eq!(back.data1, entry.data1);
+        assert_eq!(back.data2, entry.data2);
+    }
+
+    #[test]
+    fn hello_checks_the_magic() {
+        let hello = Hello {
+            tsc_hz: 2_900_000_000,
+        }
+        .encode();
+        assert_eq!(Hello::decode(&hello).unwrap().tsc_hz, 2_900_000_000);
+        let mut bad = hello;
+        bad[0] ^= 1;
+        let err = Hello::decode(&bad).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn transports_parse() {
+        assert!(matches!(
+            Transport::parse("vsock:2:5000"),
+            Ok(Transport::Vsock { cid: 2, port: 5000 })
+        ));
+        assert!(matches!(
+            Transport::parse("vsock:5000"),
+            Ok(Transport::Vsock {
+                cid: libc::VMADDR_CID_ANY,
+                port: 5000
+            })
+        ));
+        match Transport::parse("ivshmem:/dev/shm/hires") {
+            Ok(Transport::Ivshmem(path)) => assert_eq!(path, Path::new("/dev/shm/hires")),
+            other => panic!("{:?}", other),
+        }
+        for bad in [
+            "",
+            "tcp:1",
+            "vsock:",
+            "vsock:x",
+            "vsock:1:x",
+            "vsock:1:2:3",
+            "vsock:-1",
+        ] {
+            assert!(Transport::parse(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn ring_passes_records_in_order_and_wraps() {
+        let path = ring_path("wrap");
+        let host = ShmRing::create(&path, SHM_RECORDS + 4 * RECORD_SIZE).unwrap();
+        let guest = ShmRing::open(&path, Duration::ZERO).unwrap();
+        assert_eq!((host.capacity, guest.capacity), (4, 4));
+        for round in 0..3u8 {
+            for n in 0..4 {
+                assert!(guest.push(&record(round * 4 + n)).unwrap());
+            }
+            assert!(
+                !guest.push(&record(0xff)).unwrap(),
+                "full ring accepted a record"
+            );
+            for n in 0..4 {
+                assert_eq!(host.pop(), Some(record(round * 4 + n)));
+            }
+            assert_eq!(host.pop(), None);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn guest_rejects_a_capacity_past_the_mapping() {
+        let path = ring_path("capacity");
+        let len = SHM_RECORDS + 4 * RECORD_SIZE;
+        let host = ShmRing::create(&path, len).unwrap();
+        for capacity in [0, 5, u64::MAX] {
+            host.word(SHM_CAPACITY).store(capacity, Ordering::Relaxed);
+            let err = ShmRing::open(&path, Duration::ZERO).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", capacity);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn guest_rejects_a_tail_past_the_head() {
+        let path = ring_path("indices");
+        let host = ShmRing::create(&path, SHM_RECORDS + 4 * RECORD_SIZE).unwrap();
+        let guest = ShmRing::open(&path, Duration::ZERO).unwrap();
+        host.word(SHM_TAIL).store(1, Ordering::Release);
+        let err = guest.push(&record(1)).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // Indices that wrapped together are fine.
+        host.word(SHM_HEAD).store(u64::MAX, Ordering::Relaxed);
+        host.word(SHM_TAIL).store(u64::MAX, Ordering::Release);
+        assert!(guest.push(&record(1)).unwrap());
+        assert_eq!(host.pop(), Some(record(1)));
+        assert_eq!(host.word(SHM_TAIL).load(Ordering::Relaxed), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn guest_gives_up_on_an_uninitialized_ring() {
+        let path = ring_path("timeout");
+        File::create(&path)
+            .unwrap()
+            .set_len((SHM_RECORDS + 4 * RECORD_SIZE) as u64)
+            .unwrap();
+        let err = ShmRing::open(&path, Duration::ZERO).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rings_need_room_for_two_records() {
+        let path = ring_path("small");
+        let err = ShmRing::create(&path, SHM_RECORDS + RECORD_SIZE)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn delays_are_reported_above_the_minimum() {
+        let mut delays = DelayTracker::default();
+        for d in [-100, -50, 150] {
+            delays.record(3, d);
+        }
+        let report = delays.report(Scale::new(Unit::Ns, 1));
+        assert_eq!(report.len(), 1);
+        let d = &report[0];
+        assert_eq!((d.count, d.avg, d.p50, d.max), (3, 100.0, 50.0, 250.0));
+    }
+}
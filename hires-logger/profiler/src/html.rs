@@ -0,0 +1,251 @@
+//! Self-contained HTML run report.
+//!
+//! The JSON report is embedded in the page and rendered client-side into
+//! SVG charts (per-event latency CDFs and latency over time) and the summary
+//! tables, so the file can be shared and opened without any other tooling or
+//! network access.
+
+use crate::report::{self, Report};
+use std::io::{self, Write};
+use std::path::Path;
+
+pub fn write_html(path: &Path, report: &Report) -> io::Result<()> {
+    // `</` inside the embedded JSON would otherwise close the script element.
+    let data = serde_json::to_string(report)?.replace("</", "<\\/");
+    let mut w = report::create(path)?;
+    w.write_all(HEAD.as_bytes())?;
+    writeln!(w, "<script>const REPORT = {};</script>", data)?;
+    w.write_all(BODY.as_bytes())?;
+    w.flush()
+}
+
+const HEAD: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Profiler report</title>
+<style>
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1, h2 { font-weight: 600; }
+table { border-collapse: collapse; margin-bottom: 1.5em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.7em; text-align: right; }
+th { background: #f3f3f3; }
+td.text { text-align: left; font-family: monospace; }
+.fail { color: #b00; font-weight: 600; }
+.chart { position: relative; margin-bottom: 2em; }
+.chart svg { border: 1px solid #ddd; background: #fff; }
+.legend span { cursor: pointer; margin-right: 1em; user-select: none; }
+.legend span.off { opacity: 0.3; }
+.tip { position: absolute; pointer-events: none; background: #222; color: #fff;
+       padding: 0.2em 0.5em; font-size: 0.85em; border-radius: 3px; display: none; }
+</style>
+</head>
+<body>
+<h1>Profiler report</h1>
+"#;
+
+const BODY: &str = r##"<div id="content"></div>
+<script>
+(function () {
+  const COLORS = ["#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd",
+                  "#8c564b", "#e377c2", "#7f7f7f", "#bcbd22", "#17becf"];
+  const root = document.getElementById("content");
+  const el = (tag, attrs, text) => {
+    const e = document.createElement(tag);
+    for (const k in attrs || {}) e.setAttribute(k, attrs[k]);
+    if (text !== undefined) e.textContent = text;
+    return e;
+  };
+  const svgEl = (tag, attrs) => {
+    const e = document.createElementNS("http://www.w3.org/2000/svg", tag);
+    for (const k in attrs) e.setAttribute(k, attrs[k]);
+    return e;
+  };
+  const unit = REPORT.units;
+
+  function table(title, headers, rows) {
+    root.appendChild(el("h2", {}, title));
+    const t = el("table");
+    const head = el("tr");
+    headers.forEach((h) => head.appendChild(el("th", {}, h)));
+    t.appendChild(head);
+    rows.forEach((r) => {
+      const tr = el("tr");
+      r.forEach((c) => {
+        const td = el("td", c && c.cls ? { class: c.cls } : {}, c && c.text !== undefined ? c.text : c);
+        tr.appendChild(td);
+      });
+      t.appendChild(tr);
+    });
+    root.appendChild(t);
+  }
+
+  // series: [{label, points: [[x, y], ...]}]
+  function chart(title, xLabel, yLabel, series, logX) {
+    root.appendChild(el("h2", {}, title));
+    const wrap = el("div", { class: "chart" });
+    const W = 900, H = 360, L = 70, R = 20, T = 20, B = 45;
+    const all = series.flatMap((s) => s.points);
+    if (all.length === 0) { root.appendChild(el("p", {}, "No data.")); return; }
+    const fx = logX ? (x) => Math.log10(Math.max(x, 1e-3)) : (x) => x;
+    let x0 = Math.min(...all.map((p) => fx(p[0]))), x1 = Math.max(...all.map((p) => fx(p[0])));
+    let y0 = 0, y1 = Math.max(...all.map((p) => p[1]));
+    if (x1 === x0) x1 = x0 + 1;
+    if (y1 === y0) y1 = y0 + 1;
+    const sx = (x) => L + (fx(x) - x0) / (x1 - x0) * (W - L - R);
+    const sy = (y) => H - B - (y - y0) / (y1 - y0) * (H - T - B);
+    const svg = svgEl("svg", { width: W, height: H });
+    for (let i = 0; i <= 5; i++) {
+      const gy = y0 + (y1 - y0) * i / 5, gx = x0 + (x1 - x0) * i / 5;
+      const py = sy(gy), px = L + (W - L - R) * i / 5;
+      svg.appendChild(svgEl("line", { x1: L, x2: W - R, y1: py, y2: py, stroke: "#eee" }));
+      const ty = svgEl("text", { x: L - 5, y: py + 4, "text-anchor": "end", "font-size": 11 });
+      ty.textContent = +gy.toPrecision(3);
+      svg.appendChild(ty);
+      const tx = svgEl("text", { x: px, y: H - B + 15, "text-anchor": "middle", "font-size": 11 });
+      tx.textContent = +(logX ? Math.pow(10, gx) : gx).toPrecision(3);
+      svg.appendChild(tx);
+    }
+    const xl = svgEl("text", { x: (W + L) / 2, y: H - 8, "text-anchor": "middle", "font-size": 12 });
+    xl.textContent = xLabel;
+    svg.appendChild(xl);
+    const yl = svgEl("text", { x: 14, y: (H - B) / 2, "font-size": 12,
+                               transform: `rotate(-90 14 ${(H - B) / 2})`, "text-anchor": "middle" });
+    yl.textContent = yLabel;
+    svg.appendChild(yl);
+
+    const legend = el("div", { class: "legend" });
+    const tip = el("div", { class: "tip" });
+    series.forEach((s, i) => {
+      const color = COLORS[i % COLORS.length];
+      const d = s.points.map((p, j) => (j ? "L" : "M") + sx(p[0]).toFixed(1) + "," + sy(p[1]).toFixed(1)).join("");
+      const path = svgEl("path", { d: d, fill: "none", stroke: color, "stroke-width": 1.5 });
+      svg.appendChild(path);
+      const item = el("span", { style: "color:" + color }, "■ " + s.label);
+      item.onclick = () => {
+        item.classList.toggle("off");
+        path.style.display = item.classList.contains("off") ? "none" : "";
+      };
+      legend.appendChild(item);
+      s.path = path;
+    });
+    svg.addEventListener("mousemove", (ev) => {
+      const box = svg.getBoundingClientRect();
+      const mx = ev.clientX - box.left, my = ev.clientY - box.top;
+      let best = null;
+      series.forEach((s) => {
+        if (s.path.style.display === "none") return;
+        s.points.forEach((p) => {
+          const dist = Math.hypot(sx(p[0]) - mx, sy(p[1]) - my);
+          if (!best || dist < best.dist) best = { dist, s, p };
+        });
+      });
+      if (best && best.dist < 30) {
+        tip.style.display = "block";
+        tip.style.left = (mx + 12) + "px";
+        tip.style.top = (my + 12) + "px";
+        tip.textContent = `${best.s.label}: ${xLabel} ${+best.p[0].toPrecision(6)}, ${yLabel} ${+best.p[1].toPrecision(6)}`;
+      } else {
+        tip.style.display = "none";
+      }
+    });
+    svg.addEventListener("mouseleave", () => { tip.style.display = "none"; });
+    wrap.appendChild(legend);
+    wrap.appendChild(svg);
+    wrap.appendChild(tip);
+    root.appendChild(wrap);
+  }
+
+  table("Run", ["Field", "Value"], [
+    ["Command", { text: REPORT.process.cmdline.join(" "), cls: "text" }],
+    ["PID", REPORT.process.pid],
+    ["Started", new Date(REPORT.process.started_unix_s * 1000).toISOString()],
+    ["Host", { text: ((u) => `${u.nodename} (${u.sysname} ${u.release} ${u.version} ${u.machine})`)(REPORT.process.uts),
+               cls: "text" }],
+    ["cgroup", { text: REPORT.process.cgroup ?? "-", cls: "text" }],
+    ["Versions", { text: `profiler ${REPORT.process.profiler_version}, rt ${REPORT.process.rt_version}`, cls: "text" }],
+    ["Device", { text: REPORT.device, cls: "text" }],
+    ["Platform", REPORT.environment.platform +
+      (REPORT.environment.hypervisor ? ` (${REPORT.environment.hypervisor})` : "")],
+    ["vCPUs", REPORT.environment.vcpus],
+    ["Kernel", { text: REPORT.environment.kernel, cls: "text" }],
+    ["Clocksource", REPORT.environment.clocksource || "unknown"],
+    ["TSC frequency (Hz)", `${REPORT.environment.tsc_hz} (${REPORT.environment.tsc_source})`],
+    ["TSC frequency (calibrated, Hz)", REPORT.environment.calibrated_tsc_hz ?? "-"],
+    ["TSC frequency (SecureTSC, Hz)", REPORT.environment.secure_tsc_hz ?? "-"],
+    ["TSC frequency (CPUID, Hz)", REPORT.environment.cpuid_tsc_hz ?? "-"],
+    ["TSC calibration error (ppm)", REPORT.environment.tsc_mismatch_ppm == null ? "-"
+      : REPORT.environment.tsc_mismatch_ppm.toFixed(0) +
+        (REPORT.environment.tsc_mismatch_ppm > 1000 ? " (MISMATCH)" : "")],
+    ["TSC flags", REPORT.environment.tsc_flags.join(" ") || "-"],
+    ["TSC vs CLOCK_MONOTONIC (ppm)", REPORT.clock_check == null ? "-"
+      : REPORT.clock_check.mean_error_ppm.toFixed(1) + " mean, " +
+        REPORT.clock_check.worst_error_ppm.toFixed(1) + " worst over " +
+        REPORT.clock_check.samples + " intervals" +
+        (REPORT.clock_check.mismatch ? " (MISMATCH)" : "")],
+    ["Duration since connect (s)", REPORT.duration_s.toFixed(1)],
+    ["Connected at", new Date(REPORT.session_start_unix_ns / 1e6).toISOString()],
+    ["Units", unit],
+    ["Bucket width (ms)", REPORT.bucket_ms],
+    ["Entries processed", REPORT.entries_processed],
+    ["Entries dropped", REPORT.entries_dropped],
+    ["Entries filtered out", REPORT.entries_filtered],
+    ...(REPORT.entries_torn === undefined ? [] : [["Entries torn (discarded)", REPORT.entries_torn]]),
+  ]);
+
+  const relative = REPORT.relative_to === undefined ? [] : [`vs event ${REPORT.relative_to}`];
+  table("Events", ["Event ID", "Name", "Count", `Average (${unit})`, "Rate (/s)", "Duty cycle", "Share",
+    ...relative],
+    REPORT.events.map((e) => [e.channel ? `${e.channel}/${e.id & 0xffff}` : e.id,
+      { text: e.name ?? "-", cls: "text" }, e.count,
+      +e.avg.toPrecision(6), e.rate_per_s.toFixed(1),
+      e.duty_cycle === null ? "-" : (e.duty_cycle * 100).toFixed(2) + "%",
+      (e.share * 100).toFixed(1) + "%",
+      ...(relative.length ? [e.relative === null ? "-" : e.relative.toFixed(2) + "x"] : [])]));
+
+  if (REPORT.cdfs) {
+    chart("Latency CDF", `latency (${unit})`, "fraction", REPORT.cdfs.map((c) => ({
+      label: "Event " + c.event_id,
+      points: c.points,
+    })), true);
+  }
+
+  const byEvent = {};
+  REPORT.series.forEach((p) => {
+    (byEvent[p.event_id] = byEvent[p.event_id] || []).push([p.bucket_start_ms, p.avg]);
+  });
+  chart("Latency over time", "time (ms)", `mean latency (${unit})`, Object.keys(byEvent).map((id) => ({
+    label: "Event " + id,
+    points: byEvent[id],
+  })), false);
+
+  if (REPORT.assertions) {
+    table("Assertions", ["Event ID", "Check", "Result", "Actual"], REPORT.assertions.map((r) => [
+      r.event_id,
+      { text: r.check, cls: "text" },
+      r.passed ? "PASS" : { text: "FAIL", cls: "fail" },
+      r.actual === null ? "-" : r.actual,
+    ]));
+  }
+  if (REPORT.threads) {
+    table(`Per-thread (${unit})`, ["Event ID", "TID", "Name", "Count", "Average", "p50", "p99", "Max"],
+      REPORT.threads.map((t) => [t.event_id, t.tid, { text: t.name ?? "-", cls: "text" }, t.count,
+        +t.avg.toPrecision(6), +t.p50.toPrecision(6), +t.p99.toPrecision(6), +t.max.toPrecision(6)]));
+  }
+  if (REPORT.anomalies) {
+    table("Anomalies", ["Event ID", "Window (ms)", `Peak (${unit})`, `Baseline (${unit})`, "Score"],
+      REPORT.anomalies.map((a) => [a.event_id, a.start_ms + "-" + a.end_ms, +a.peak.toPrecision(6),
+                                   +a.baseline.toPrecision(6), a.score.toFixed(1)]));
+  }
+  if (REPORT.critical_path) {
+    table("Critical path", ["Root event ID", "Stage", "Dominant", "Share", `Avg on path (${unit})`],
+      REPORT.critical_path.flatMap((r) => r.stages.map((s) => [
+        r.root_event, { text: s.path, cls: "text" }, s.dominant,
+        (s.dominant_share * 100).toFixed(1) + "%", +s.avg_on_path.toPrecision(6),
+      ])));
+  }
+})();
+</script>
+</body>
+</html>
+"##;
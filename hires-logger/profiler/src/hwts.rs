@@ -0,0 +1,311 @@
+//! Wire-to-host latency from NIC hardware timestamps.
+//!
+//! See `rt::hwts` for the producer-side convention. PHC sync samples are
+//! fitted to a line (PHC ns against TSC cycles) so every hardware timestamp
+//! can be moved onto the TSC timeline. RX latency is then the time from the
+//! packet hitting the wire to the application logging it; TX latency is the
+//! time from the submit marker to the packet leaving the NIC.
+
+use crate::stats::{Reservoir, percentile};
+use crate::timeline::entry_time_ns;
+use crate::units::{Scale, cycles_to_ns};
+use rt::hwts::{HW_TIMESTAMP_FLAG, HW_TX_BIT, PHC_SYNC_EVENT};
+use rt::{LOG_FLAG_KERNEL, LOG_FLAG_TSC, log_entry_t};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Observations kept per event and direction, 1 MiB each. Latencies are
+/// only known once every sync sample is in, so past this many the
+/// statistics are estimated from a sample.
+const MAX_HW_SAMPLES: usize = 1 << 16;
+
+/// Submit markers whose completion never arrives pile up; past this many
+/// the pending set is discarded.
+const MAX_PENDING: usize = 1 << 20;
+
+#[derive(Serialize)]
+pub struct HwLatency {
+    pub event_id: u32,
+    /// `rx` or `tx`.
+    pub direction: &'static str,
+    pub count: u64,
+    /// Samples with a negative latency, i.e. clock sync error, left out of
+    /// the statistics. Estimated from the sample past `MAX_HW_SAMPLES`.
+    pub negative: u64,
+    pub avg: f64,
+    pub p50: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+#[derive(Serialize)]
+pub struct HwReport {
+    pub sync_samples: usize,
+    /// PHC rate relative to the calibrated TSC frequency.
+    pub drift_ppm: Option<f64>,
+    /// Largest distance of a sync sample from the fitted line, in ns.
+    pub max_residual_ns: Option<f64>,
+    /// TX completions without a submit marker.
+    pub unmatched_tx: u64,
+    /// Submit markers discarded at `MAX_PENDING`.
+    pub evicted_tx: u64,
+    /// Entries not stamped in the TSC domain (see `TimestampSource`), whose
+    /// latencies mix clocks.
+    pub non_tsc: u64,
+    pub latencies: Vec<HwLatency>,
+}
+
+/// PHC ns as a linear function of TSC cycles, anchored at the first sample
+/// so the fit stays precise in f64.
+struct PhcFit {
+    tsc0: u64,
+    phc0: u64,
+    ns_per_cycle: f64,
+}
+
+impl PhcFit {
+    fn new(samples: &[(u64, u64)], tsc_hz: u64) -> Option<Self> {
+        let &(tsc0, phc0) = samples.first()?;
+        let nominal = 1e9 / tsc_hz as f64;
+        let deltas: Vec<(f64, f64)> = samples
+            .iter()
+            .map(|&(t, p)| {
+                (
+                    t.wrapping_sub(tsc0) as i64 as f64,
+                    p.wrapping_sub(phc0) as i64 as f64,
+                )
+            })
+            .collect();
+        // Least squares through the anchor; a single sample keeps the
+        // calibrated rate.
+        let sxx: f64 = deltas.iter().map(|(x, _)| x * x).sum();
+        let sxy: f64 = deltas.iter().map(|(x, y)| x * y).sum();
+        let ns_per_cycle = if sxx > 0.0 { sxy / sxx } else { nominal };
+        Some(PhcFit {
+            tsc0,
+            phc0,
+            ns_per_cycle,
+        })
+    }
+
+    fn residual(&self, tsc: u64, phc: u64) -> f64 {
+        let x = tsc.wrapping_sub(self.tsc0) as i64 as f64;
+        let y = phc.wrapping_sub(self.phc0) as i64 as f64;
+        (y - x * self.ns_per_cycle).abs()
+    }
+
+    /// Moves a PHC timestamp onto the TSC timeline, in ns.
+    fn to_ns(&self, phc: u64, tsc_hz: u64) -> u64 {
+        let dy = phc.wrapping_sub(self.phc0) as i64 as f64;
+        let tsc = (self.tsc0 as f64 + dy / self.ns_per_cycle).max(0.0) as u64;
+        cycles_to_ns(tsc, tsc_hz)
+    }
+}
+
+/// (entry ns, PHC ns) pairs of one event and direction; for TX the entry is
+/// the submit marker.
+#[derive(Default)]
+struct Observations {
+    count: u64,
+    sample: Reservoir<MAX_HW_SAMPLES, (u64, u64)>,
+}
+
+#[derive(Default)]
+pub struct HwTimestampTracker {
+    /// (TSC, PHC ns) sync samples.
+    sync: Vec<(u64, u64)>,
+    /// (event, direction) -> observations.
+    observations: BTreeMap<(u32, &'static str), Observations>,
+    /// Submit markers awaiting their completion: (event, key) -> entry ns.
+    tx_submits: HashMap<(u32, u64), u64>,
+    unmatched_tx: u64,
+    evicted_tx: u64,
+    non_tsc: u64,
+}
+
+impl HwTimestampTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sync.is_empty() && self.observations.is_empty()
+    }
+
+    /// Adds one entry; `event_id` still carries the hardware timestamp flag.
+    pub fn record(&mut self, entry: &log_entry_t, tsc_hz: u64) {
+        if entry.event_id == PHC_SYNC_EVENT {
+            self.sync.push((entry.data1, entry.data2));
+            return;
+        }
+        if entry.flags & ((LOG_FLAG_KERNEL | LOG_FLAG_TSC) as u16) == 0 {
+            self.non_tsc += 1;
+        }
+        let event_id = entry.event_id & !HW_TIMESTAMP_FLAG;
+        let ts_ns = entry_time_ns(entry, tsc_hz);
+        if entry.data2 & HW_TX_BIT == 0 {
+            self.observe(event_id, "rx", ts_ns, entry.data1);
+        } else if entry.data1 == 0 {
+            if self.tx_submits.len() >= MAX_PENDING {
+                self.evicted_tx += self.tx_submits.len() as u64;
+                self.tx_submits.clear();
+            }
+            self.tx_submits.insert((event_id, entry.data2), ts_ns);
+        } else if let Some(submit) = self.tx_submits.remove(&(event_id, entry.data2)) {
+            self.observe(event_id, "tx", submit, entry.data1);
+        } else {
+            self.unmatched_tx += 1;
+        }
+    }
+
+    fn observe(&mut self, event_id: u32, direction: &'static str, entry_ns: u64, phc: u64) {
+        let observations = self.observations.entry((event_id, direction)).or_default();
+        observations.count += 1;
+        observations.sample.add((entry_ns, phc));
+    }
+
+    pub fn report(&self, tsc_hz: u64, scale: Scale) -> HwReport {
+        let fit = (tsc_hz != 0)
+            .then(|| PhcFit::new(&self.sync, tsc_hz))
+            .flatten();
+        let mut latencies = Vec::new();
+        if let Some(fit) = &fit {
+            for (&(event_id, direction), observations) in &self.observations {
+                // Signed latencies in ns.
+                let values: Vec<i64> = observations
+                    .sample
+                    .sorted()
+                    .iter()
+                    .map(|&(entry_ns, phc)| {
+                        let wire_ns = fit.to_ns(phc, tsc_hz) as i64;
+                        if direction == "rx" {
+                            entry_ns as i64 - wire_ns
+                        } else {
+                            wire_ns - entry_ns as i64
+                        }
+                    })
+                    .collect();
+                let mut sorted: Vec<u64> = values
+                    .iter()
+                    .filter(|&&v| v >= 0)
+                    .map(|&v| v as u64)
+                    .collect();
+                sorted.sort_unstable();
+                let negative = ((values.len() - sorted.len()) as u128 * observations.count as u128
+                    / values.len() as u128) as u64;
+                let avg = if sorted.is_empty() {
+                    0.0
+                } else {
+                    sorted.iter().map(|&v| v as u128).sum::<u128>() as f64 / sorted.len() as f64
+                };
+                latencies.push(HwLatency {
+                    event_id,
+                    direction,
+                    count: observations.count - negative,
+                    negative,
+                    avg: scale.ns(avg),
+                    p50: scale.ns(percentile(&sorted, 50.0) as f64),
+                    p99: scale.ns(percentile(&sorted, 99.0) as f64),
+                    max: scale.ns(sorted.last().copied().unwrap_or(0) as f64),
+                });
+            }
+        }
+
+        HwReport {
+            sync_samples: self.sync.len(),
+            drift_ppm: fit
+                .as_ref()
+                .map(|f| (f.ns_per_cycle * tsc_hz as f64 / 1e9 - 1.0) * 1e6),
+            max_residual_ns: fit.as_ref().map(|f| {
+                self.sync
+                    .iter()
+                    .map(|&(t, p)| f.residual(t, p))
+                    .fold(0.0, f64::max)
+            }),
+            unmatched_tx: self.unmatched_tx,
+            evicted_tx: self.evicted_tx,
+            non_tsc: self.non_tsc,
+            latencies,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Unit;
+
+    const TSC_HZ: u64 = 1_000_000_000;
+
+    fn entry(event_id: u32, timestamp: u64, data1: u64, data2: u64) -> log_entry_t {
+        log_entry_t {
+            timestamp,
+            event_id,
+            cpu_id: 0,
+            flags: LOG_FLAG_TSC as u16,
+            data1,
+            data2,
+        }
+    }
+
+    fn tracker(entries: &[log_entry_t]) -> HwTimestampTracker {
+        let mut tracker = HwTimestampTracker::new();
+        // The PHC runs 1000 ns ahead of the TSC at 1 GHz.
+        for tsc in [0, 1_000_000, 2_000_000] {
+            tracker.record(&entry(PHC_SYNC_EVENT, 0, tsc, tsc + 1000), TSC_HZ);
+        }
+        for e in entries {
+            tracker.record(e, TSC_HZ);
+        }
+        tracker
+    }
+
+    #[test]
+    fn latencies_are_measured_on_the_fitted_timeline() {
+        let ev = 7 | HW_TIMESTAMP_FLAG;
+        let tracker = tracker(&[
+            // Hit the wire at TSC 5000, logged at 5300.
+            entry(ev, 5300, 6000, 1),
+            // Submitted at 8000, left the NIC at TSC 8200.
+            entry(ev, 8000, 0, 2 | HW_TX_BIT),
+            entry(ev, 9000, 9200, 2 | HW_TX_BIT),
+            // A completion without its submit marker.
+            entry(ev, 9500, 9700, 3 | HW_TX_BIT),
+        ]);
+        let report = tracker.report(TSC_HZ, Scale::new(Unit::Ns, 1));
+        assert_eq!(report.sync_samples, 3);
+        assert!(report.drift_ppm.unwrap().abs() < 1e-6);
+        let latencies: Vec<_> = report
+            .latencies
+            .iter()
+            .map(|l| (l.event_id, l.direction, l.count, l.max, l.negative))
+            .collect();
+        assert_eq!(latencies, [(7, "rx", 1, 300.0, 0), (7, "tx", 1, 200.0, 0)]);
+        assert_eq!(report.unmatched_tx, 1);
+    }
+
+    #[test]
+    fn negative_latencies_are_left_out() {
+        let ev = 7 | HW_TIMESTAMP_FLAG;
+        // Logged before the packet hit the wire.
+        let tracker = tracker(&[entry(ev, 4000, 6000, 1), entry(ev, 6000, 6000, 1)]);
+        let report = tracker.report(TSC_HZ, Scale::new(Unit::Ns, 1));
+        let rx = &report.latencies[0];
+        assert_eq!((rx.count, rx.negative, rx.max), (1, 1, 1000.0));
+    }
+
+    #[test]
+    fn pending_submits_are_bounded() {
+        let ev = 7 | HW_TIMESTAMP_FLAG;
+        let mut tracker = HwTimestampTracker::new();
+        for key in 0..=MAX_PENDING as u64 {
+            tracker.record(&entry(ev, 100, 0, key | HW_TX_BIT), TSC_HZ);
+        }
+        assert_eq!(tracker.tx_submits.len(), 1);
+        // The completion of a discarded submit no longer pairs.
+        tracker.record(&entry(ev, 200, 300, HW_TX_BIT), TSC_HZ);
+        let report = tracker.report(TSC_HZ, Scale::new(Unit::Ns, 1));
+        assert_eq!(report.evicted_tx, MAX_PENDING as u64);
+        assert_eq!(report.unmatched_tx, 1);
+    }
+}
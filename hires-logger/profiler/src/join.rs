@@ -0,0 +1,297 @@
+//! Cross-layer joins on the correlation key in `data2` (`--join-events`).
+//!
+//! Entries of the selected events are grouped by key (e.g. `rt::net`'s
+//! packet key) regardless of whether the kernel, a packet hook or userspace
+//! logged them. Each key's observations are ordered by time and every
+//! consecutive pair contributes one sample to its `from -> to` transition,
+//! so a packet's path through the layers is broken down hop by hop.
+//!
+//! A key is folded into the report as soon as every selected event has seen
+//! it, so only keys still in flight are kept. Keys that never complete
+//! (other traffic, drops) are folded in as they are once `MAX_PENDING` of
+//! them pile up.
+
+use crate::stats::{Reservoir, percentile};
+use crate::timeline::entry_time_ns;
+use crate::units::Scale;
+use rt::log_entry_t;
+use rt::packet::{HOOK_SOCKET, HOOK_TC_INGRESS, HOOK_XDP, PACKET_HOOK_FLAG, is_packet_hook};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Keys in flight before all of them are folded in.
+const MAX_PENDING: usize = 1 << 20;
+/// Observations of one key before it is folded in, for keys that recur.
+const MAX_OBSERVATIONS: usize = 64;
+/// Samples kept per transition for percentiles, 512 KiB each.
+const MAX_TRANSITION_SAMPLES: usize = 1 << 16;
+
+/// Parses one `--join-events` item: an event ID, or `xdp`, `tc` or `socket`
+/// for the packet hooks.
+pub fn parse_event(s: &str) -> Result<u32, String> {
+    match s {
+        "xdp" => Ok(PACKET_HOOK_FLAG | HOOK_XDP),
+        "tc" => Ok(PACKET_HOOK_FLAG | HOOK_TC_INGRESS),
+        "socket" => Ok(PACKET_HOOK_FLAG | HOOK_SOCKET),
+        _ => s
+            .parse()
+            .map_err(|e| format!("invalid event '{}': {}", s, e)),
+    }
+}
+
+/// Display name of a joined event.
+pub fn event_name(event_id: u32) -> String {
+    match event_id {
+        id if id == PACKET_HOOK_FLAG | HOOK_XDP => "xdp".to_string(),
+        id if id == PACKET_HOOK_FLAG | HOOK_TC_INGRESS => "tc".to_string(),
+        id if id == PACKET_HOOK_FLAG | HOOK_SOCKET => "socket".to_string(),
+        id => id.to_string(),
+    }
+}
+
+#[derive(Serialize)]
+pub struct Transition {
+    pub from: String,
+    pub to: String,
+    pub count: u64,
+    pub avg: f64,
+    pub p50: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+#[derive(Serialize)]
+pub struct JoinReport {
+    pub keys: u64,
+    /// Keys seen by only one of the selected events.
+    pub unjoined: u64,
+    /// Keys folded in before every selected event saw them, at `MAX_PENDING`.
+    pub evicted: u64,
+    pub transitions: Vec<Transition>,
+}
+
+#[derive(Clone, Default)]
+struct TransitionStats {
+    count: u64,
+    sum: u128,
+    max: u64,
+    sample: Reservoir<MAX_TRANSITION_SAMPLES>,
+}
+
+/// Keys folded into per-transition statistics.
+#[derive(Clone, Default)]
+struct Joined {
+    keys: u64,
+    unjoined: u64,
+    transitions: BTreeMap<(u32, u32), TransitionStats>,
+}
+
+impl Joined {
+    /// Folds in one key's observations, in time order.
+    fn add(&mut self, obs: &[(u64, u32)]) {
+        self.keys += 1;
+        if obs.iter().all(|&(_, e)| e == obs[0].1) {
+            self.unjoined += 1;
+            return;
+        }
+        for pair in obs.windows(2) {
+            let ((t0, from), (t1, to)) = (pair[0], pair[1]);
+            let delta = t1 - t0;
+            let t = self.transitions.entry((from, to)).or_default();
+            t.count += 1;
+            t.sum += delta as u128;
+            t.max = t.max.max(delta);
+            t.sample.add(delta);
+        }
+    }
+}
+
+pub struct KeyJoiner {
+    events: Vec<u32>,
+    /// Key -> (time in ns, event) in time order, until the key is folded in.
+    pending: HashMap<u64, Vec<(u64, u32)>>,
+    max_pending: usize,
+    joined: Joined,
+    evicted: u64,
+}
+
+impl KeyJoiner {
+    pub fn new(events: Vec<u32>) -> Self {
+        KeyJoiner {
+            events,
+            pending: HashMap::new(),
+            max_pending: MAX_PENDING,
+            joined: Joined::default(),
+            evicted: 0,
+        }
+    }
+
+    pub fn record(&mut self, entry: &log_entry_t, tsc_hz: u64) {
+        if entry.data2 == 0 || !self.events.contains(&entry.event_id) {
+            return;
+        }
+        // Hook entries carry the time the hook saw the packet in data1.
+        let ts_ns = if is_packet_hook(entry.event_id) && entry.data1 != 0 {
+            entry.data1
+        } else {
+            entry_time_ns(entry, tsc_hz)
+        };
+        if self.pending.len() >= self.max_pending && !self.pending.contains_key(&entry.data2) {
+            self.evicted += self.pending.len() as u64;
+            for (_, obs) in self.pending.drain() {
+                self.joined.add(&obs);
+            }
+        }
+        let obs = self.pending.entry(entry.data2).or_default();
+        // Entries mostly arrive in time order, so this is usually a push.
+        let at = obs.partition_point(|&o| o <= (ts_ns, entry.event_id));
+        obs.insert(at, (ts_ns, entry.event_id));
+
+        let complete = self
+            .events
+            .iter()
+            .all(|&e| obs.iter().any(|&(_, seen)| seen == e));
+        if complete || obs.len() >= MAX_OBSERVATIONS {
+            let obs = self.pending.remove(&entry.data2).unwrap_or_default();
+            self.joined.add(&obs);
+        }
+    }
+
+    pub fn report(&self, scale: Scale) -> JoinReport {
+        let mut joined = self.joined.clone();
+        for obs in self.pending.values() {
+            joined.add(obs);
+        }
+        let transitions = joined
+            .transitions
+            .iter()
+            .map(|(&(from, to), t)| {
+                let sorted = t.sample.sorted();
+                Transition {
+                    from: event_name(from),
+                    to: event_name(to),
+                    count: t.count,
+                    avg: scale.ns(t.sum as f64 / t.count as f64),
+                    p50: scale.ns(percentile(&sorted, 50.0) as f64),
+                    p99: scale.ns(percentile(&sorted, 99.0) as f64),
+                    max: scale.ns(t.max as f64),
+                }
+            })
+            .collect();
+        JoinReport {
+            keys: joined.keys,
+            unjoined: joined.unjoined,
+            evicted: self.evicted,
+            transitions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Unit;
+
+    const XDP: u32 = PACKET_HOOK_FLAG | HOOK_XDP;
+    const SOCKET: u32 = PACKET_HOOK_FLAG | HOOK_SOCKET;
+    const APP: u32 = 7;
+
+    fn joiner() -> KeyJoiner {
+        KeyJoiner::new(vec![XDP, SOCKET, APP])
+    }
+
+    /// An entry of `event_id` for `key` at `ts_ns`, with hooks carrying it
+    /// in data1 as they do.
+    fn observe(joiner: &mut KeyJoiner, event_id: u32, key: u64, ts_ns: u64) {
+        let hook = is_packet_hook(event_id);
+        let entry = log_entry_t {
+            timestamp: if hook { 0 } else { ts_ns },
+            event_id,
+            cpu_id: 0,
+            flags: 0,
+            data1: if hook { ts_ns } else { 0 },
+            data2: key,
+        };
+        joiner.record(&entry, 0);
+    }
+
+    fn transitions(report: &JoinReport) -> Vec<(&str, &str, u64, f64, f64)> {
+        report
+            .transitions
+            .iter()
+            .map(|t| (t.from.as_str(), t.to.as_str(), t.count, t.avg, t.max))
+            .collect()
+    }
+
+    fn scale() -> Scale {
+        Scale::new(Unit::Ns, 1)
+    }
+
+    #[test]
+    fn consecutive_observations_form_transitions() {
+        let mut j = joiner();
+        // Key 1 arrives out of order; key 2 is slower on every hop.
+        observe(&mut j, SOCKET, 1, 150);
+        observe(&mut j, XDP, 1, 100);
+        observe(&mut j, APP, 1, 400);
+        observe(&mut j, XDP, 2, 1000);
+        observe(&mut j, SOCKET, 2, 1250);
+        observe(&mut j, APP, 2, 1750);
+        let report = j.report(scale());
+        assert_eq!((report.keys, report.unjoined, report.evicted), (2, 0, 0));
+        assert_eq!(
+            transitions(&report),
+            [
+                ("xdp", "socket", 2, 150.0, 250.0),
+                ("socket", "7", 2, 375.0, 500.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn complete_keys_are_folded_in() {
+        let mut j = joiner();
+        observe(&mut j, XDP, 1, 100);
+        observe(&mut j, SOCKET, 1, 200);
+        assert_eq!(j.pending.len(), 1);
+        observe(&mut j, APP, 1, 300);
+        assert!(j.pending.is_empty());
+        assert_eq!(j.joined.keys, 1);
+    }
+
+    #[test]
+    fn keys_seen_by_one_event_are_unjoined() {
+        let mut j = joiner();
+        observe(&mut j, XDP, 1, 100);
+        observe(&mut j, XDP, 1, 200);
+        observe(&mut j, XDP, 2, 100);
+        observe(&mut j, SOCKET, 2, 300);
+        // Unselected events and key 0 are ignored.
+        observe(&mut j, 9, 2, 200);
+        observe(&mut j, APP, 0, 200);
+        let report = j.report(scale());
+        assert_eq!((report.keys, report.unjoined), (2, 1));
+        assert_eq!(transitions(&report), [("xdp", "socket", 1, 200.0, 200.0)]);
+    }
+
+    #[test]
+    fn pending_keys_are_bounded() {
+        let mut j = joiner();
+        j.max_pending = 2;
+        for key in 1..=5 {
+            observe(&mut j, XDP, key, 100);
+            observe(&mut j, SOCKET, key, 110);
+        }
+        assert!(j.pending.len() <= 2);
+        let report = j.report(scale());
+        assert_eq!((report.keys, report.evicted), (5, 4));
+        assert_eq!(transitions(&report), [("xdp", "socket", 5, 10.0, 10.0)]);
+
+        // A key that keeps recurring is folded in at `MAX_OBSERVATIONS`.
+        let mut j = joiner();
+        for i in 0..MAX_OBSERVATIONS as u64 {
+            observe(&mut j, XDP, 9, i);
+        }
+        assert!(j.pending.is_empty());
+    }
+}
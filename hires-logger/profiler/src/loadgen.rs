@@ -0,0 +1,426 @@
+//! Request-response load generator (`profiler loadgen`).
+//!
+//! Each connection runs a closed loop (or a fixed rate with `--rate`) of
+//! requests against a `loadgen --listen` server, over TCP or UDP. Every
+//! phase of a request is timed and logged through the hires ring with its
+//! duration in TSC cycles in `data1` and the request sequence number in
+//! `data2`:
+//!
+//! | event           | phase                                    |
+//! |-----------------|------------------------------------------|
+//! | `event_base`    | serialize the request                    |
+//! | `event_base + 1`| send it                                  |
+//! | `event_base + 2`| receive the whole response               |
+//! | `event_base + 3`| parse and verify the response            |
+//! | `event_base + 4`| whole request, serialize to parse        |
+//!
+//! The server logs `event_base + 5` (receive a request, including the wait
+//! for it) and `event_base + 6` (build and send the response).
+
+use crate::stats::percentile;
+use crate::units::{Scale, Unit, read_tsc};
+use clap::{Args, ValueEnum};
+use rt::HiResConn;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// seq (u64), response size (u32), payload length (u32).
+const HEADER_BYTES: usize = 16;
+/// Largest UDP payload.
+const MAX_DATAGRAM: usize = 65507;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+#[derive(Args, Debug)]
+pub struct LoadgenArgs {
+    /// Server to load, host:port
+    #[arg(long, conflicts_with = "listen")]
+    target: Option<SocketAddr>,
+
+    /// Run as the server on this address instead, e.g. 0.0.0.0:7878
+    #[arg(long)]
+    listen: Option<SocketAddr>,
+
+    #[arg(long, value_enum, default_value_t = Protocol::Tcp)]
+    protocol: Protocol,
+
+    /// Concurrent connections, one thread each
+    #[arg(short, long, default_value_t = 1)]
+    connections: usize,
+
+    /// Requests per second per connection (0 = closed loop, as fast as responses come back)
+    #[arg(long, default_value_t = 0)]
+    rate: u64,
+
+    /// How long to run, in seconds
+    #[arg(long, default_value_t = 10)]
+    duration: u64,
+
+    /// Request payload size in bytes
+    #[arg(long, default_value_t = 64)]
+    request_size: usize,
+
+    /// Response payload size in bytes
+    #[arg(long, default_value_t = 64)]
+    response_size: usize,
+
+    /// First of the seven event IDs logged (see the module docs)
+    #[arg(long, default_value_t = 100)]
+    event_base: u32,
+
+    /// Output units for the latency summary
+    #[arg(long, value_enum, default_value_t = Unit::Us)]
+    units: Unit,
+}
+
+/// Deterministic payload so the parse phase has something to verify.
+fn fill_payload(buf: &mut [u8], seq: u64) {
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = (seq as usize).wrapping_add(i) as u8;
+    }
+}
+
+fn serialize(buf: &mut Vec<u8>, seq: u64, response_size: usize, payload: usize) {
+    buf.clear();
+    buf.extend_from_slice(&seq.to_le_bytes());
+    buf.extend_from_slice(&(response_size as u32).to_le_bytes());
+    buf.extend_from_slice(&(payload as u32).to_le_bytes());
+    buf.resize(HEADER_BYTES + payload, 0);
+    fill_payload(&mut buf[HEADER_BYTES..], seq);
+}
+
+/// Returns (seq, response size, payload length).
+fn parse_header(buf: &[u8]) -> (u64, usize, usize) {
+    (
+        u64::from_le_bytes(buf[..8].try_into().unwrap()),
+        u32::from_le_bytes(buf[8..12].try_into().unwrap()) as usize,
+        u32::from_le_bytes(buf[12..16].try_into().unwrap()) as usize,
+    )
+}
+
+fn verify(buf: &[u8], seq: u64) -> bool {
+    let (got, _, len) = parse_header(buf);
+    got == seq
+        && buf.len() == HEADER_BYTES + len
+        && buf[HEADER_BYTES..]
+            .iter()
+            .enumerate()
+            .all(|(i, &b)| b == (seq as usize).wrapping_add(i) as u8)
+}
+
+/// Either transport, connected to the server.
+enum Channel {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+impl Channel {
+    fn open(protocol: Protocol, target: SocketAddr) -> io::Result<Self> {
+        match protocol {
+            Protocol::Tcp => {
+                let stream = TcpStream::connect(target)?;
+                stream.set_nodelay(true)?;
+                Ok(Channel::Tcp(stream))
+            }
+            Protocol::Udp => {
+                let socket = UdpSocket::bind(if target.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                })?;
+                socket.connect(target)?;
+                socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+                Ok(Channel::Udp(socket))
+            }
+        }
+    }
+
+    fn send(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Channel::Tcp(s) => s.write_all(buf),
+            Channel::Udp(s) => s.send(buf).map(|_| ()),
+        }
+    }
+
+    /// Reads one whole message into `buf`.
+    fn recv(&mut self, buf: &mut Vec<u8>) -> io::Result<()> {
+        match self {
+            Channel::Tcp(s) => {
+                buf.resize(HEADER_BYTES, 0);
+                s.read_exact(buf)?;
+                let (_, _, len) = parse_header(buf);
+                buf.resize(HEADER_BYTES + len, 0);
+                s.read_exact(&mut buf[HEADER_BYTES..])
+            }
+            Channel::Udp(s) => {
+                buf.resize(MAX_DATAGRAM, 0);
+                let n = s.recv(buf)?;
+                buf.truncate(n);
+                if n < HEADER_BYTES {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "short datagram"));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ClientStats {
+    latencies: Vec<u64>,
+    errors: u64,
+    invalid: u64,
+}
+
+#[inline]
+fn timed<T>(conn: &HiResConn, event_id: u32, seq: u64, f: impl FnOnce() -> T) -> T {
+    let start = read_tsc();
+    let r = f();
+    conn.log(event_id, read_tsc() - start, seq);
+    r
+}
+
+fn client(
+    conn: &HiResConn,
+    args: &LoadgenArgs,
+    target: SocketAddr,
+    id: usize,
+    running: &AtomicBool,
+) -> io::Result<ClientStats> {
+    let mut channel = Channel::open(args.protocol, target)?;
+    let mut stats = ClientStats::default();
+    let mut request = Vec::new();
+    let mut response = Vec::new();
+    let interval = (args.rate > 0).then(|| Duration::from_nanos(1_000_000_000 / args.rate));
+    let end = Instant::now() + Duration::from_secs(args.duration);
+    let mut next = Instant::now();
+    // Sequence numbers are unique across connections.
+    let mut seq = (id as u64) << 40;
+    let ev = args.event_base;
+
+    while running.load(Ordering::Relaxed) && Instant::now() < end {
+        if let Some(interval) = interval {
+            let now = Instant::now();
+            if now < next {
+                thread::sleep(next - now);
+            }
+            next += interval;
+        }
+        seq += 1;
+        let start = read_tsc();
+        timed(conn, ev, seq, || {
+            serialize(&mut request, seq, args.response_size, args.request_size)
+        });
+        let result = timed(conn, ev + 1, seq, || channel.send(&request))
+            .and_then(|()| timed(conn, ev + 2, seq, || channel.recv(&mut response)));
+        match result {
+            Ok(()) => {
+                if timed(conn, ev + 3, seq, || verify(&response, seq)) {
+                    let total = read_tsc() - start;
+                    conn.log(ev + 4, total, seq);
+                    stats.latencies.push(total);
+                } else {
+                    stats.invalid += 1;
+                }
+            }
+            // A lost datagram; TCP errors end the connection.
+            Err(e) if args.protocol == Protocol::Udp && is_timeout(&e) => stats.errors += 1,
+            Err(e) => {
+                eprintln!("Connection {}: {}", id, e);
+                stats.errors += 1;
+                break;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+pub fn run_loadgen(device: &str, args: &LoadgenArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })?;
+
+    if args.protocol == Protocol::Udp
+        && (args.request_size.max(args.response_size) + HEADER_BYTES > MAX_DATAGRAM)
+    {
+        return Err(format!(
+            "UDP messages are limited to {} payload bytes",
+            MAX_DATAGRAM - HEADER_BYTES
+        )
+        .into());
+    }
+
+    let connection = HiResConn::connect(Some(device.as_ref()))?;
+    if let Some(addr) = args.listen {
+        return serve(&connection, args, addr, &running);
+    }
+    let Some(target) = args.target else {
+        return Err("loadgen needs --target host:port or --listen addr".into());
+    };
+
+    println!(
+        "Load: {} connection(s) to {} over {:?}, {} B requests, {} B responses, {}",
+        args.connections,
+        target,
+        args.protocol,
+        args.request_size,
+        args.response_size,
+        if args.rate > 0 {
+            format!("{}/s per connection", args.rate)
+        } else {
+            "closed loop".to_string()
+        }
+    );
+    let started = Instant::now();
+    let results: Vec<io::Result<ClientStats>> = thread::scope(|s| {
+        let handles: Vec<_> = (0..args.connections)
+            .map(|id| {
+                let (conn, running) = (&connection, &*running);
+                s.spawn(move || client(conn, args, target, id, running))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("loadgen client panicked"))
+            .collect()
+    });
+    let elapsed = started.elapsed().as_secs_f64();
+
+    let mut all = ClientStats::default();
+    for r in results {
+        let s = r?;
+        all.latencies.extend(s.latencies);
+        all.errors += s.errors;
+        all.invalid += s.invalid;
+    }
+    all.latencies.sort_unstable();
+    let scale = Scale::new(args.units, connection.get_tsc_hz());
+    let lat = &all.latencies;
+    println!("---- Load generator ----");
+    println!(
+        "Requests: {}, Errors: {}, Invalid responses: {}, Throughput: {:.0} req/s",
+        lat.len(),
+        all.errors,
+        all.invalid,
+        lat.len() as f64 / elapsed
+    );
+    if !lat.is_empty() {
+        let sum: u128 = lat.iter().map(|&v| v as u128).sum();
+        println!(
+            "Latency ({}): Average: {}, p50: {}, p90: {}, p99: {}, p99.9: {}, Max: {}",
+            scale.label(),
+            scale.cycles(sum as f64 / lat.len() as f64),
+            scale.cycles(percentile(lat, 50.0) as f64),
+            scale.cycles(percentile(lat, 90.0) as f64),
+            scale.cycles(percentile(lat, 99.0) as f64),
+            scale.cycles(percentile(lat, 99.9) as f64),
+            scale.cycles(lat[lat.len() - 1] as f64)
+        );
+    }
+    println!();
+    Ok(())
+}
+
+/// Builds the response to a request held in `request`.
+fn respond(request: &[u8], response: &mut Vec<u8>) {
+    let (seq, response_size, _) = parse_header(request);
+    serialize(response, seq, 0, response_size);
+}
+
+fn serve(
+    conn: &HiResConn,
+    args: &LoadgenArgs,
+    addr: SocketAddr,
+    running: &AtomicBool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ev = args.event_base;
+    println!(
+        "Serving loadgen requests over {:?} on {}",
+        args.protocol, addr
+    );
+    match args.protocol {
+        Protocol::Udp => {
+            let socket = UdpSocket::bind(addr)?;
+            socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+            let mut request = vec![0u8; MAX_DATAGRAM];
+            let mut response = Vec::new();
+            while running.load(Ordering::Relaxed) {
+                let start = read_tsc();
+                let (n, peer) = match socket.recv_from(&mut request) {
+                    Ok(r) => r,
+                    Err(e) if is_timeout(&e) || e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                if n < HEADER_BYTES {
+                    continue;
+                }
+                let seq = parse_header(&request).0;
+                conn.log(ev + 5, read_tsc() - start, seq);
+                timed(conn, ev + 6, seq, || {
+                    respond(&request[..n], &mut response);
+                    socket.send_to(&response, peer)
+                })?;
+            }
+        }
+        Protocol::Tcp => {
+            let listener = TcpListener::bind(addr)?;
+            listener.set_nonblocking(true)?;
+            thread::scope(|s| -> io::Result<()> {
+                while running.load(Ordering::Relaxed) {
+                    let stream = match listener.accept() {
+                        Ok((stream, _)) => stream,
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(Duration::from_millis(10));
+                            continue;
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    stream.set_nonblocking(false)?;
+                    stream.set_nodelay(true)?;
+                    s.spawn(move || serve_tcp(conn, ev, stream));
+                }
+                Ok(())
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Serves one TCP connection until the client closes it.
+fn serve_tcp(conn: &HiResConn, ev: u32, stream: TcpStream) {
+    let mut channel = Channel::Tcp(stream);
+    let mut request = Vec::new();
+    let mut response = Vec::new();
+    loop {
+        let start = read_tsc();
+        if channel.recv(&mut request).is_err() {
+            break;
+        }
+        let seq = parse_header(&request).0;
+        conn.log(ev + 5, read_tsc() - start, seq);
+        let sent = timed(conn, ev + 6, seq, || {
+            respond(&request, &mut response);
+            channel.send(&response)
+        });
+        if sent.is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Loss events: ring-buffer drops vs. network drops vs. latency spikes.
+//!
+//! Once per bucket the consumer samples three counters: entries the ring
+//! dropped (measurement loss), the per-CPU softnet backlog drops from
+//! `/proc/net/softnet_stat`, and the NIC drop counters under
+//! `/sys/class/net/<iface>/statistics` (network loss). Consecutive buckets
+//! with any drops form one loss event, which is then matched against the
+//! latency spikes found by [`crate::anomaly::detect`]. An event with only
+//! ring drops means the capture itself is incomplete there; one with
+//! network drops is real loss the workload saw.
+
+use crate::anomaly::{self, Anomaly};
+use crate::info;
+use crate::timeline::Timeline;
+use crate::units::{Scale, cycles_to_ns, read_tsc};
+use rt::HiResConn;
+use serde::Serialize;
+use std::fs;
+use std::time::{Duration, Instant};
+
+/// NIC counters summed into the network drop count.
+const NIC_COUNTERS: [&str; 3] = ["rx_dropped", "tx_dropped", "rx_missed_errors"];
+
+#[derive(Clone, Copy, Default)]
+struct Counters {
+    ring: u64,
+    softnet: u64,
+    nic: u64,
+}
+
+#[derive(Serialize)]
+pub struct LossEvent {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    /// `measurement`, `network` or `both`.
+    pub kind: &'static str,
+    pub ring_drops: u64,
+    pub softnet_drops: u64,
+    pub nic_drops: u64,
+    /// Events with a latency spike overlapping the window.
+    pub spiking_events: Vec<u32>,
+}
+
+#[derive(Serialize)]
+pub struct LossReport {
+    pub interfaces: Vec<String>,
+    pub ring_drops: u64,
+    pub softnet_drops: u64,
+    pub nic_drops: u64,
+    pub events: Vec<LossEvent>,
+    /// Latency spikes that overlap no loss event.
+    pub unexplained_spikes: usize,
+}
+
+pub struct LossSampler {
+    interfaces: Vec<String>,
+    interval: Duration,
+    last_sample: Instant,
+    /// Previous sample of the system counters (`ring` unused: the
+    /// connection hands out its deltas itself).
+    last: Option<Counters>,
+    /// Sample time (ns) and counter increases since the previous sample.
+    deltas: Vec<(u64, Counters)>,
+}
+
+impl LossSampler {
+    /// Watches `interfaces`, or every interface but `lo` if empty.
+    pub fn new(interfaces: Vec<String>, bucket_ms: u64) -> Self {
+        let interfaces = if interfaces.is_empty() {
+            let mut all: Vec<String> = fs::read_dir("/sys/class/net")
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter_map(|e| e.file_name().into_string().ok())
+                .filter(|name| name != "lo")
+                .collect();
+            all.sort();
+            all
+        } else {
+            interfaces
+        };
+        LossSampler {
+            interfaces,
+            interval: Duration::from_millis(bucket_ms.max(1)),
+            last_sample: Instant::now(),
+            last: None,
+            deltas: Vec::new(),
+        }
+    }
+
+    /// Samples the counters if a bucket's worth of time has passed since the
+    /// last sample, or unconditionally with `force`. Takes `conn`'s drops
+    /// (see [`HiResConn::take_drops`]), so it should be their only taker.
+    pub fn poll(&mut self, conn: &HiResConn, tsc_hz: u64, force: bool) {
+        if !force && self.last.is_some() && self.last_sample.elapsed() < self.interval {
+            return;
+        }
+        self.last_sample = Instant::now();
+        let now = Counters {
+            ring: conn.take_drops(),
+            softnet: softnet_drops(),
+            nic: self.nic_drops(),
+        };
+        if let Some(last) = self.last {
+            let delta = Counters {
+                ring: now.ring,
+                softnet: now.softnet.saturating_sub(last.softnet),
+                nic: now.nic.saturating_sub(last.nic),
+            };
+            if delta.ring + delta.softnet + delta.nic > 0 {
+                self.deltas.push((cycles_to_ns(read_tsc(), tsc_hz), delta));
+            }
+        }
+        self.last = Some(now);
+    }
+
+    fn nic_drops(&self) -> u64 {
+        self.interfaces
+            .iter()
+            .flat_map(|iface| {
+                NIC_COUNTERS.iter().filter_map(move |c| {
+                    fs::read_to_string(format!("/sys/class/net/{}/statistics/{}", iface, c))
+                        .ok()?
+                        .trim()
+                        .parse::<u64>()
+                        .ok()
+                })
+            })
+            .sum()
+    }
+
+    pub fn report(
+        &self,
+        timeline: &Timeline,
+        window: usize,
+        threshold: f64,
+        scale: Scale,
+    ) -> LossReport {
+        let bucket_ms = timeline.bucket_ms();
+        let bucket_ns = bucket_ms.max(1) * 1_000_000;
+        let origin = timeline.origin_ns();
+
+        let mut events: Vec<LossEvent> = Vec::new();
+        let mut last_bucket: Option<u64> = None;
+        for (ts_ns, d) in &self.deltas {
+            let bucket = ts_ns.saturating_sub(origin) / bucket_ns;
+            let extends = last_bucket.is_some_and(|b| bucket <= b + 1);
+            if !extends {
+                events.push(LossEvent {
+                    start_ms: bucket * bucket_ms,
+                    end_ms: 0,
+                    kind: "",
+                    ring_drops: 0,
+                    softnet_drops: 0,
+                    nic_drops: 0,
+                    spiking_events: Vec::new(),
+                });
+            }
+            let e = events.last_mut().unwrap();
+            e.end_ms = (bucket + 1) * bucket_ms;
+            e.ring_drops += d.ring;
+            e.softnet_drops += d.softnet;
+            e.nic_drops += d.nic;
+            last_bucket = Some(bucket);
+        }
+
+        let spikes: Vec<Anomaly> = anomaly::detect(timeline, window, threshold, scale);
+        let mut explained = vec![false; spikes.len()];
+        for e in &mut events {
+            let network = e.softnet_drops + e.nic_drops > 0;
+            e.kind = match (e.ring_drops > 0, network) {
+                (true, true) => "both",
+                (true, false) => "measurement",
+                _ => "network",
+            };
+            for (a, explained) in spikes.iter().zip(&mut explained) {
+                if a.start_ms < e.end_ms && e.start_ms < a.end_ms {
+                    *explained = true;
+                    if !e.spiking_events.contains(&a.event_id) {
+                        e.spiking_events.push(a.event_id);
+                    }
+                }
+            }
+            e.spiking_events.sort_unstable();
+        }
+
+        LossReport {
+            interfaces: self.interfaces.clone(),
+            ring_drops: events.iter().map(|e| e.ring_drops).sum(),
+            softnet_drops: events.iter().map(|e| e.softnet_drops).sum(),
+            nic_drops: events.iter().map(|e| e.nic_drops).sum(),
+            unexplained_spikes: explained.iter().filter(|&&x| !x).count(),
+            events,
+        }
+    }
+}
+
+/// Sum of the `dropped` column (the second) over all CPUs.
+fn softnet_drops() -> u64 {
+    fs::read_to_string("/proc/net/softnet_stat")
+        .map(|s| {
+            s.lines()
+                .filter_map(|l| l.split_whitespace().nth(1))
+                .filter_map(|v| u64::from_str_radix(v, 16).ok())
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+pub fn print_report(r: &LossReport) {
+    info!("---- Loss events ----");
+    info!(
+        "Interfaces: {}, Ring drops: {}, Softnet drops: {}, NIC drops: {}",
+        if r.interfaces.is_empty() {
+            "none".to_string()
+        } else {
+            r.interfaces.join(", ")
+        },
+        r.ring_drops,
+        r.softnet_drops,
+        r.nic_drops
+    );
+    if r.events.is_empty() {
+        info!("No drops observed.");
+    }
+    for e in &r.events {
+        let spiking: Vec<String> = e.spiking_events.iter().map(|id| id.to_string()).collect();
+        info!(
+            "Window: {}-{} ms, Kind: {}, Ring: {}, Softnet: {}, NIC: {}, Spiking events: {}",
+            e.start_ms,
+            e.end_ms,
+            e.kind,
+            e.ring_drops,
+            e.softnet_drops,
+            e.nic_drops,
+            if spiking.is_empty() {
+                "none".to_string()
+            } else {
+                spiking.join(", ")
+            }
+        );
+    }
+    info!(
+        "Latency spikes outside any loss event: {}",
+        r.unexplained_spikes
+    );
+    info!();
+}
@@ -1,90 +1,479 @@
-use clap::Parser;
-use rt::{HiResConn, LOG_FLAG_VALID, log_entry_t};
+mod anomaly;
+mod assertions;
+mod batch;
+mod budget;
+mod capture;
+mod channels;
+mod clockcheck;
+mod clocksync;
+mod color;
+mod correlate;
+mod decoder;
+mod faults;
+mod filter;
+mod gaps;
+mod groups;
+mod hostlink;
+mod html;
+mod hwts;
+mod join;
+mod loadgen;
+mod loss;
+mod markdown;
+mod names;
+mod packets;
+mod pipeline;
+mod platform;
+mod probe;
+mod process;
+mod queues;
+mod report;
+mod slo;
+mod spans;
+mod spsc;
+mod stacks;
+mod stats;
+mod swiotlb;
+mod symbols;
+mod threads;
+mod timeline;
+mod tls;
+mod trace;
+mod units;
+mod virtio;
+mod vmexits;
+mod wakeup;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use report::Report;
+use rt::{HIRES_TSC_SRC_SECURE_TSC, HiResConn, LOG_FLAG_KERNEL, LOG_FLAG_VALID, log_entry_t};
+use serde::Serialize;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to the profiler device node
     #[arg(short, long, default_value = "/dev/khires")]
     device: String,
 
+    /// Ask khires for a ring of this many entries (a power of two) instead of the size it was loaded with; fails while another process has the ring mapped at a different size
+    #[arg(long)]
+    ring_capacity: Option<u64>,
+
     /// Polling interval in milliseconds when buffer is empty
     #[arg(short, long, default_value_t = 10)]
     poll_interval_ms: u64,
+
+    /// Width of the time buckets for the latency-over-time series, in milliseconds
+    #[arg(long, default_value_t = 100)]
+    bucket_ms: u64,
+
+    /// Unit for latency values in the summary and all report formats
+    #[arg(long, value_enum, default_value_t = units::Unit::Us)]
+    units: units::Unit,
+
+    /// Clear the terminal and reprint the running summary every this many seconds
+    #[arg(long)]
+    watch: Option<u64>,
+
+    /// Write the summary and time series as JSON to this file, or to stdout for '-'
+    #[arg(long)]
+    json: Option<PathBuf>,
+
+    /// Write the summary as CSV to this file (series goes to <stem>_series.csv), or to stdout for '-' (no series)
+    #[arg(long)]
+    csv: Option<PathBuf>,
+
+    /// Write a Markdown report (tables, histograms, run metadata) to this file, or to stdout for '-'
+    #[arg(long)]
+    markdown: Option<PathBuf>,
+
+    /// Write a self-contained HTML report with latency CDF and time series charts to this file, or to stdout for '-'
+    #[arg(long)]
+    html: Option<PathBuf>,
+
+    /// Print nothing to stdout but the reports written to '-' (no banner, progress or summary); warnings still go to stderr
+    #[arg(short, long, conflicts_with = "watch")]
+    quiet: bool,
+
+    /// Report the correlation matrix between per-bucket event rates and p99 latencies
+    #[arg(long)]
+    correlate: bool,
+
+    /// List time windows where an event's bucket latency spiked above its rolling baseline
+    #[arg(long)]
+    detect_anomalies: bool,
+
+    /// Number of trailing non-empty buckets forming the anomaly baseline
+    #[arg(
+        long,
+        default_value_t = 20,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    anomaly_window: usize,
+
+    /// Robust z-score (median/MAD) above which a bucket is flagged
+    #[arg(long, default_value_t = 3.5)]
+    anomaly_threshold: f64,
+
+    /// Payload field carrying a per-event sequence number; enables the gap/loss report
+    #[arg(long, value_enum)]
+    seq_field: Option<PayloadField>,
+
+    /// Payload field carrying the producer's thread ID; enables the per-thread breakdown
+    #[arg(long, value_enum)]
+    tid_field: Option<PayloadField>,
+
+    /// Symbolize instruction pointers in data1 of kernel events using this kallsyms file
+    #[arg(long, num_args = 0..=1, default_missing_value = "/proc/kallsyms")]
+    kallsyms: Option<PathBuf>,
+
+    /// Symbolize userspace addresses against this binary (with debug info) via addr2line
+    #[arg(long)]
+    symbols: Option<PathBuf>,
+
+    /// Payload field of userspace events holding the address to symbolize
+    #[arg(long, value_enum, default_value_t = PayloadField::Data2)]
+    symbols_field: PayloadField,
+
+    /// Load address of the binary passed to --symbols (hex), subtracted for PIE executables
+    #[arg(long, value_parser = parse_hex, default_value = "0")]
+    symbols_base: u64,
+
+    /// Reconstruct span trees from the span/parent IDs in data2 and print per-stage breakdowns
+    #[arg(long)]
+    spans: bool,
+
+    /// Rank the stages that most often dominate each request's critical path (implies span tracking)
+    #[arg(long)]
+    critical_path: bool,
+
+    /// Pair kernel interrupt events with userspace HIRES_EV_WAKEUP entries by key and report the latency
+    #[arg(long)]
+    wakeup_latency: bool,
+
+    /// Kernel event to pair wakeups with, e.g. 241 for the virtio-net interrupt (keyed by virtqueue)
+    #[arg(long, default_value_t = rt::HIRES_EV_IRQ)]
+    wakeup_irq_event: u32,
+
+    /// Join these events on the correlation key in data2 (e.g. rt::net::packet_key) and break the path down hop by hop; IDs or xdp/tc/socket, comma-separated
+    #[arg(long, value_delimiter = ',', value_parser = join::parse_event)]
+    join_events: Vec<u32>,
+
+    /// Break virtio-net kicks/interrupts and --queue-events down per queue and CPU, to expose RSS/IRQ imbalance
+    #[arg(long)]
+    queues: bool,
+
+    /// Events carrying a queue index in bits 32-47 of data2 (rt::net::queue_data2, DPDK bursts), comma-separated; implies --queues
+    #[arg(long, value_delimiter = ',')]
+    queue_events: Vec<u32>,
+
+    /// Pin the consumer thread and its memory to this NUMA node; load khires with the same numa_node= so the ring lives there too
+    #[arg(long)]
+    numa_node: Option<u32>,
+
+    /// Lock the ring's mapping in memory (mlock) so draining it takes no page faults; needs `ulimit -l` of at least the ring's size or CAP_IPC_LOCK
+    #[arg(long)]
+    mlock: bool,
+
+    /// Report the ring pages faulted in during the run and the page faults of the whole profiler process
+    #[arg(long)]
+    faults: bool,
+
+    /// Re-check each entry's slot after copying it and discard (and count) entries a producer rewrote mid-copy
+    #[arg(long)]
+    torn_checks: bool,
+
+    /// Count VM exits per bucket (khires exit_probes=1 events, or --exit-counter) and correlate exit bursts with latency spikes
+    #[arg(long)]
+    vm_exits: bool,
+
+    /// Sample ring, softnet and NIC drop counters per bucket and report loss windows against latency spikes
+    #[arg(long)]
+    loss_events: bool,
+
+    /// Interfaces whose drop counters --loss-events samples, comma-separated (default: all but lo)
+    #[arg(long, value_delimiter = ',', requires = "loss_events")]
+    loss_ifaces: Vec<String>,
+
+    /// Sample this cumulative exit counter once per bucket instead of using khires events, e.g. a KVM stat under /sys/kernel/debug/kvm
+    #[arg(long, requires = "vm_exits")]
+    exit_counter: Option<PathBuf>,
+
+    /// Forward every entry to a host-side collector: vsock:CID:PORT or ivshmem:PATH (the ivshmem BAR, e.g. /sys/bus/pci/devices/.../resource2)
+    #[arg(long, value_parser = hostlink::Transport::parse)]
+    forward: Option<hostlink::Transport>,
+
+    /// Shared library implementing hires_decode() (see rt/include/hires_decoder.h) to render payloads
+    #[arg(long)]
+    decoder: Option<PathBuf>,
+
+    /// Only process entries matching this expression, e.g. 'event_id==5 && data1 > 1000 && cpu in [0,1]'; name registrations always pass
+    #[arg(long, value_parser = filter::Filter::parse)]
+    filter: Option<filter::Filter>,
+
+    /// Name an event in all reports, overriding a registered name, e.g. '5=tx_doorbell'; repeatable
+    #[arg(long = "alias", value_parser = groups::Alias::parse)]
+    aliases: Vec<groups::Alias>,
+
+    /// Roll events up into a named group reported alongside them, e.g. 'net_tx=5,6,7'; repeatable
+    #[arg(long = "group", value_parser = groups::Group::parse)]
+    groups: Vec<groups::Group>,
+
+    /// Report each event's average latency relative to this event's (e.g. 2.0x)
+    #[arg(long)]
+    relative_to: Option<u32>,
+
+    /// Count the samples of an event violating a latency objective, e.g. 'event=12 < 50us'; repeatable
+    #[arg(long = "slo", value_parser = slo::Slo::parse)]
+    slos: Vec<slo::Slo>,
+
+    /// Stop after recording this many entries (those passing --filter), for captures of the same size on any machine
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_events: Option<u64>,
+
+    /// Record at most this many samples of an event and stop once every such event has them, e.g. '5:100000'; repeatable
+    #[arg(long, value_parser = budget::EventBudget::parse)]
+    max_event_samples: Vec<budget::EventBudget>,
+
+    /// Check a per-event stat against a threshold, e.g. '5:p99<=20' (in --units); repeatable
+    #[arg(long = "assert", value_parser = assertions::Assertion::parse)]
+    assertions: Vec<assertions::Assertion>,
+
+    /// Write the assertion results as JUnit XML test cases to this file
+    #[arg(long)]
+    junit: Option<PathBuf>,
+
+    /// Measure the TSC against CLOCK_MONOTONIC over every interval of this many seconds and report the disagreement
+    #[arg(long)]
+    clock_check: Option<u64>,
+
+    /// Never color terminal output (also: NO_COLOR=1); by default colors are used when writing to a terminal
+    #[arg(long)]
+    no_color: bool,
+
+    /// Log a synthetic stream into the in-memory ring, e.g. '7@20000:data1=exp(1500):then=8/exp(500)'; repeatable
+    #[cfg(feature = "stub")]
+    #[arg(long, value_parser = hires_testkit::Stream::parse)]
+    synthetic: Vec<hires_testkit::Stream>,
+
+    /// Seed for --synthetic; the same seed reproduces the same events
+    #[cfg(feature = "stub")]
+    #[arg(long, default_value_t = 0)]
+    synthetic_seed: u64,
+
+    /// Stop after this many seconds of --synthetic events, once the ring is drained
+    #[cfg(feature = "stub")]
+    #[arg(long, requires = "synthetic")]
+    synthetic_secs: Option<f64>,
 }
 
-const MAX_EVENT_BUCKET_SIZE: usize = 256;
-const DEFAULT_DATA_CAPACITY: usize = 1 << 25; // 32MB
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run on the host: receive entries forwarded by a guest (--forward) and timestamp their arrival
+    HostCollector(hostlink::CollectorArgs),
+    /// Measure UDP round trips against an echo service (or run one with --listen)
+    Probe(probe::ProbeArgs),
+    /// Instrumented TCP/UDP request-response load generator (or its server with --listen)
+    Loadgen(loadgen::LoadgenArgs),
+    /// Print every entry as it is consumed, as text or JSON Lines (--format jsonl)
+    Trace(trace::TraceArgs),
+}
 
-#[repr(align(64))]
-#[derive(Default)]
-struct Event {
-    id: u64,
-    count: u64,
-    data: Vec<u64>,
+fn parse_hex(s: &str) -> Result<u64, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    u64::from_str_radix(digits, 16).map_err(|e| format!("invalid hex value '{}': {}", s, e))
+}
+
+/// Names one of the two payload fields of a log entry.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum PayloadField {
+    Data1,
+    Data2,
 }
 
-impl Event {
-    fn new(id: u64) -> Self {
-        Event {
-            id,
-            count: 0,
-            data: Vec::with_capacity(DEFAULT_DATA_CAPACITY),
+impl PayloadField {
+    fn get(self, entry: &log_entry_t) -> u64 {
+        match self {
+            PayloadField::Data1 => entry.data1,
+            PayloadField::Data2 => entry.data2,
         }
     }
+}
 
-    fn add_data(&mut self, data: u64) {
-        if self.data.len() < DEFAULT_DATA_CAPACITY {
-            self.count += 1;
-            self.data.push(data);
-        } else {
-            eprintln!("Warning: Data capacity exceeded for event ID {}", self.id);
+/// Set by --quiet.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// `println!` for the banner, progress and human-readable summary, which
+/// --quiet suppresses so stdout carries only reports written to `-`.
+/// Warnings and errors go to stderr regardless. Report modules print
+/// their sections through it too, via `use crate::info`.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if !$crate::QUIET.load(::std::sync::atomic::Ordering::Relaxed) {
+            println!($($arg)*);
         }
-    }
+    };
+}
+pub(crate) use info;
+
+const MAX_EVENT_BUCKET_SIZE: usize = 256;
+const DEFAULT_DATA_CAPACITY: usize = 1 << 25; // 32MB
+
+/// One event's running aggregates. Each sits alone on a cache line, so the
+/// aggregator touches one line per event it updates and the table is 16 KiB
+/// per channel.
+#[repr(align(64))]
+#[derive(Clone, Copy, Default)]
+struct EventStats {
+    count: u64,
+    // Running sum; u128 so billions of large cycle values cannot overflow it.
+    sum: u128,
+    /// Logged by khires (`LOG_FLAG_KERNEL`) rather than from userspace.
+    kernel: bool,
+}
 
-    fn avg(&self) -> f32 {
+impl EventStats {
+    fn avg(&self) -> f64 {
         if self.count > 0 {
-            let sum: u64 = self.data.iter().sum();
-            let avg = (sum as f32) / (self.count as f32);
-            return avg;
+            return (self.sum as f64) / (self.count as f64);
         }
-        return 0.0;
+        0.0
     }
 
-    fn summary(&self) -> EventResult {
+    /// `session_s` is the wall time the entries were collected over, from
+    /// the connection's session anchor; `total_sum` is the sum over all
+    /// events.
+    fn summary(
+        &self,
+        id: u32,
+        scale: units::Scale,
+        session_s: f64,
+        names: &names::Names,
+        total_sum: u128,
+    ) -> EventResult {
+        let session_s = session_s.max(f64::MIN_POSITIVE);
         EventResult {
-            id: self.id,
+            id: id as u64,
+            name: names.get(id).map(str::to_string),
+            kernel: self.kernel,
             count: self.count,
-            avg: self.avg(),
+            avg: scale.cycles(self.avg()),
+            rate_per_s: self.count as f64 / session_s,
+            duty_cycle: scale.seconds(self.sum as f64).map(|busy| busy / session_s),
+            share: if total_sum > 0 {
+                self.sum as f64 / total_sum as f64
+            } else {
+                0.0
+            },
+            channel: None,
+            relative: None,
         }
     }
 }
 
+/// Per-event state as parallel arrays indexed by slot (one per event ID, see
+/// `channels::Slots`): the aggregates the hot path updates in one compact
+/// table, and the samples kept for percentiles apart from it.
 struct Benchmarks {
-    event_bucket: [Event; MAX_EVENT_BUCKET_SIZE],
+    stats: Vec<EventStats>,
+    /// `data1` of each sample, grown as samples arrive (up to
+    /// `DEFAULT_DATA_CAPACITY`) rather than reserved up front.
+    samples: Vec<Vec<u64>>,
+    slots: channels::Slots,
 }
 
 impl Benchmarks {
     fn new() -> Self {
-        let event_bucket = std::array::from_fn(|i| Event {
-            id: i as u64,
-            count: 0,
-            data: Vec::with_capacity(DEFAULT_DATA_CAPACITY),
-        });
-        Benchmarks { event_bucket }
+        Benchmarks {
+            stats: vec![EventStats::default(); MAX_EVENT_BUCKET_SIZE],
+            samples: vec![Vec::new(); MAX_EVENT_BUCKET_SIZE],
+            slots: channels::Slots::default(),
+        }
+    }
+
+    /// The slot counting `event_id`, growing the tables for a new channel.
+    fn slot(&mut self, event_id: u32) -> Option<usize> {
+        let slot = self.slots.slot(event_id)?;
+        if self.stats.len() < self.slots.len() {
+            self.stats.resize(self.slots.len(), EventStats::default());
+            self.samples.resize(self.slots.len(), Vec::new());
+        }
+        Some(slot)
+    }
+
+    fn event_id(&self, slot: usize) -> u32 {
+        self.slots.event_id(slot)
     }
 
-    fn summary(&self) -> Vec<EventResult> {
-        self
-            .event_bucket
+    fn add_run(&mut self, slot: usize, data: &[u64], kernel: bool) {
+        let stats = &mut self.stats[slot];
+        let samples = &mut self.samples[slot];
+        let room = DEFAULT_DATA_CAPACITY - samples.len();
+        let (kept, dropped) = data.split_at(data.len().min(room));
+        stats.count += kept.len() as u64;
+        stats.sum += batch::sum(kept);
+        stats.kernel |= kernel;
+        samples.extend_from_slice(kept);
+        if !dropped.is_empty() {
+            eprintln!(
+                "Warning: Data capacity exceeded for event ID {} ({} samples not kept)",
+                self.slots.event_id(slot),
+                dropped.len()
+            );
+        }
+    }
+
+    /// The kept samples of `event_id`; empty for an unknown event.
+    fn samples(&self, event_id: u32) -> &[u64] {
+        self.slots
+            .find(event_id)
+            .and_then(|slot| self.samples.get(slot))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// With a `reference` event, each result's `relative` is its average
+    /// over the reference's.
+    fn summary(
+        &self,
+        scale: units::Scale,
+        session_s: f64,
+        names: &names::Names,
+        channels: &channels::Channels,
+        reference: Option<u32>,
+    ) -> Vec<EventResult> {
+        let total_sum: u128 = self.stats.iter().map(|e| e.sum).sum();
+        let mut result = self
+            .stats
             .iter()
-            .map(|e| e.summary())
-            .filter(|e| e.count > 0)
-            .collect::<Vec<EventResult>>()
+            .enumerate()
+            .filter(|(_, e)| e.count > 0)
+            .map(|(slot, e)| {
+                let id = self.slots.event_id(slot);
+                EventResult {
+                    channel: channels.label(id),
+                    ..e.summary(id, scale, session_s, names, total_sum)
+                }
+            })
+            .collect::<Vec<EventResult>>();
+        let reference_avg = reference
+            .and_then(|id| result.iter().find(|e| e.id == id as u64))
+            .map(|e| e.avg)
+            .filter(|&avg| avg > 0.0);
+        if let Some(reference_avg) = reference_avg {
+            for e in &mut result {
+                e.relative = Some(e.avg / reference_avg);
+            }
+        }
+        result
         // for entry in result.iter() {
         //     println!(
         //         "Event ID: {}, Count: {}, Average: {}",
@@ -94,24 +483,243 @@ impl Benchmarks {
     }
 }
 
+fn print_events(result: &[EventResult], scale: units::Scale, color: color::Palette) {
+    for entry in result.iter() {
+        let event = match &entry.name {
+            Some(name) => format!("Event ID: {} ({})", entry.label(), name),
+            None => format!("Event ID: {}", entry.label()),
+        };
+        let relative = entry
+            .relative
+            .map(|r| format!(", Relative: {:.2}x", r))
+            .unwrap_or_default();
+        info!(
+            "{}, Count: {}, Average: {} {}, Rate: {:.1}/s, Duty cycle: {}, Share: {:.1}%{}",
+            if entry.kernel {
+                color.kernel(event)
+            } else {
+                color.user(event)
+            },
+            entry.count,
+            entry.avg,
+            scale.label(),
+            entry.rate_per_s,
+            entry
+                .duty_cycle
+                .map_or("-".to_string(), |d| format!("{:.2}%", d * 100.0)),
+            entry.share * 100.0,
+            relative
+        );
+    }
+}
+
+fn print_slos(results: &[slo::SloResult], color: color::Palette) {
+    info!("---- SLOs ----");
+    for r in results {
+        let violations = format!(
+            "{} of {} samples violate ({:.3}%)",
+            r.violations,
+            r.samples,
+            r.violation_fraction * 100.0
+        );
+        info!(
+            "Event ID: {}, {}: {}",
+            r.event_id,
+            r.objective,
+            if r.violations > 0 {
+                color.fail(violations)
+            } else {
+                color.pass(violations)
+            }
+        );
+    }
+    info!();
+}
+
+#[derive(Serialize)]
 struct EventResult {
     id: u64,
+    /// Registered with `HIRES_EV_EVENT_NAME` (see `rt::event`).
+    name: Option<String>,
+    /// The channel the event was logged on (see `channels`), by name or ID;
+    /// `None` for the default channel.
+    channel: Option<String>,
+    /// Logged by khires rather than from userspace.
+    kernel: bool,
     count: u64,
-    avg: f32,
+    avg: f64,
+    /// Entries per second of session wall time.
+    rate_per_s: f64,
+    /// Fraction of the session spent in the event, taking `data1` as its
+    /// duration as `avg` does. Above 1 when events overlap (several
+    /// threads); `None` without TSC calibration.
+    duty_cycle: Option<f64>,
+    /// Fraction of the `data1` time logged by all events that is this
+    /// event's: where the time goes.
+    share: f64,
+    /// Average over the `--relative-to` event's; `None` without one or if
+    /// it has no samples.
+    relative: Option<f64>,
+}
+
+impl EventResult {
+    /// The event ID as reports show it: `channel/id` off the default
+    /// channel.
+    fn label(&self) -> String {
+        match &self.channel {
+            Some(channel) => format!("{}/{}", channel, rt::channel::split(self.id as u32).1),
+            None => self.id.to_string(),
+        }
+    }
+}
+
+/// Logs the --synthetic streams into the in-memory ring from another
+/// thread, paced in real time. With --synthetic-secs the run ends once they
+/// are done and the consumer has drained the ring.
+#[cfg(feature = "stub")]
+fn spawn_synthetic(
+    args: &Args,
+    running: Arc<AtomicBool>,
+) -> Option<thread::JoinHandle<hires_testkit::DriveStats>> {
+    if args.synthetic.is_empty() {
+        return None;
+    }
+    let generator = hires_testkit::Generator::new(args.synthetic_seed, args.synthetic.clone());
+    let end_ns = args.synthetic_secs.map_or(u64::MAX, |secs| (secs * 1e9) as u64);
+    Some(thread::spawn(move || {
+        // The stub ring is shared by every connection in the process.
+        let producer = match HiResConn::connect(None) {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("Error: synthetic producer failed to connect: {}", e);
+                return hires_testkit::DriveStats::default();
+            }
+        };
+        let stats = hires_testkit::drive(
+            &producer,
+            generator.until(end_ns),
+            hires_testkit::Pace::Realtime,
+            &running,
+        );
+        if end_ns != u64::MAX {
+            while running.load(Ordering::SeqCst) && producer.peek().is_some() {
+                thread::sleep(Duration::from_millis(10));
+            }
+            running.store(false, Ordering::SeqCst);
+        }
+        stats
+    }))
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    QUIET.store(args.quiet, Ordering::Relaxed);
+    match &args.command {
+        Some(Command::HostCollector(collector)) => return hostlink::run_collector(collector),
+        Some(Command::Probe(probe)) => return probe::run_probe(&args.device, probe),
+        Some(Command::Loadgen(load)) => return loadgen::run_loadgen(&args.device, load),
+        Some(Command::Trace(trace)) => {
+            let symbols = trace::TraceSymbols::new(
+                args.kallsyms
+                    .as_deref()
+                    .map(symbols::KernelSymbols::load)
+                    .transpose()?,
+                args.symbols
+                    .clone()
+                    .map(|bin| symbols::UserSymbols::new(bin, args.symbols_base)),
+                args.symbols_field,
+            );
+            return trace::run_trace(
+                &args.device,
+                args.poll_interval_ms,
+                args.no_color,
+                symbols,
+                trace,
+            );
+        }
+        None => {}
+    }
+
+    let kernel_symbols = args
+        .kallsyms
+        .as_deref()
+        .map(symbols::KernelSymbols::load)
+        .transpose()?;
+    let mut kernel_counts = symbols::SymbolCounts::new();
+    let user_symbols = args
+        .symbols
+        .clone()
+        .map(|bin| symbols::UserSymbols::new(bin, args.symbols_base));
+    let mut user_counts = symbols::SymbolCounts::new();
+
+    let payload_decoder = args
+        .decoder
+        .as_deref()
+        .map(decoder::Decoder::load)
+        .transpose()?;
+    let mut decoded_counts = decoder::DecodedCounts::new();
+
+    let color = color::Palette::detect(args.no_color, &std::io::stdout());
+    let err_color = color::Palette::detect(args.no_color, &std::io::stderr());
 
     let mut bench = Benchmarks::new();
+    let mut timeline = timeline::Timeline::new(args.bucket_ms);
+    if args.correlate {
+        timeline.keep_samples();
+    }
+    let mut gap_tracker = gaps::GapTracker::new();
+    let mut thread_breakdown = threads::ThreadBreakdown::new();
+    let mut clock_tracker = clockcheck::ClockCheckTracker::default();
+    let mut event_names = names::Names::default();
+    let mut channels = channels::Channels::default();
+    groups::apply_aliases(&args.aliases, &mut event_names);
+    let mut queue_breakdown = (args.queues || !args.queue_events.is_empty())
+        .then(|| queues::QueueBreakdown::new(args.queue_events.clone()));
+    let mut stack_assembler = stacks::StackAssembler::new();
+    let mut span_store = spans::SpanStore::new();
+    let mut hw_tracker = hwts::HwTimestampTracker::new();
+    let mut packet_tracker = packets::PacketTracker::new();
+    let mut virtio_tracker = virtio::VirtioTracker::new();
+    let mut swiotlb_tracker = swiotlb::SwiotlbTracker::new();
+    let mut tls_tracker = tls::TlsTracker::new();
+    let mut wakeup_pairer = args
+        .wakeup_latency
+        .then(|| wakeup::WakeupPairer::new(args.wakeup_irq_event));
+    let mut loss_sampler = args
+        .loss_events
+        .then(|| loss::LossSampler::new(args.loss_ifaces.clone(), args.bucket_ms));
+    let mut key_joiner =
+        (!args.join_events.is_empty()).then(|| join::KeyJoiner::new(args.join_events.clone()));
+    let mut exit_tracker = args
+        .vm_exits
+        .then(|| vmexits::VmExitTracker::new(args.exit_counter.clone(), args.bucket_ms));
 
-    println!("Profiler Consumer starting...");
-    println!("Connecting to device: {}", args.device);
-    println!("Polling interval: {} ms", args.poll_interval_ms);
+    info!("Profiler Consumer starting...");
+    info!("Connecting to device: {}", args.device);
+    info!("Polling interval: {} ms", args.poll_interval_ms);
+
+    if let Some(node) = args.numa_node {
+        rt::numa::bind_current_thread(node)?;
+        match rt::numa::ring_node() {
+            Some(ring) if ring == node => info!("Consumer bound to NUMA node {} (ring is local)", node),
+            Some(ring) => eprintln!(
+                "Warning: consumer bound to NUMA node {} but the ring is on node {}",
+                node, ring
+            ),
+            None => eprintln!(
+                "Warning: consumer bound to NUMA node {}, but the ring is not pinned (load khires with numa_node={})",
+                node, node
+            ),
+        }
+    }
 
     // Connect using the safe wrapper
-    let connection = HiResConn::connect(Some(args.device.as_ref()))?;
-    println!("Connected successfully.");
+    let connection = match args.ring_capacity {
+        Some(capacity) => HiResConn::connect_with_capacity(Some(args.device.as_ref()), capacity)?,
+        None => HiResConn::connect(Some(args.device.as_ref()))?,
+    };
+    let session = connection.session_anchor();
+    info!("Connected successfully ({:?} backend).", connection.backend());
 
     // Get the raw buffer pointer (requires unsafe block to use)
     // let buffer_ptr = unsafe { connection.get_raw_buffer() };
@@ -123,77 +731,943 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let size = connection.get_rb_capacity();
     let mask = connection.get_rb_idx_mask();
 
-    println!("Buffer Size: {}, Mask: 0x{:x}", size, mask);
+    info!("Buffer Size: {}, Mask: 0x{:x}", size, mask);
     if size == 0 || (size & mask) != 0 {
         eprintln!("Error: Invalid buffer size/mask read from shared memory.");
         return Ok(());
     }
+    if args.mlock {
+        connection.lock_ring()?;
+        info!("Ring locked in memory ({} bytes).", connection.get_shm_size());
+    }
+    connection.set_torn_checks(args.torn_checks);
+    let cycle_rate = connection.get_cycles_per_us();
+    let tsc_hz = connection.get_tsc_hz();
+    let scale = units::Scale::new(args.units, tsc_hz);
+    let mut slo_tracker = slo::SloTracker::new(&args.slos, tsc_hz)?;
+    let tsc_info = connection.get_tsc_info();
+    let secure_tsc = tsc_info.is_some_and(|i| i.source == HIRES_TSC_SRC_SECURE_TSC as u64);
+    let tsc_source = if tsc_hz == 0 {
+        "uncalibrated"
+    } else if secure_tsc {
+        "SNP SecureTSC"
+    } else if tsc_hz == cycle_rate * 1_000_000 {
+        "khires calibration (rounded to cycles/us)"
+    } else {
+        "khires calibration"
+    };
+    let process = process::ProcessInfo::current();
+    let environment = platform::Environment::detect(
+        tsc_hz,
+        tsc_source,
+        tsc_info.map_or((tsc_hz != 0).then_some(tsc_hz), |i| Some(i.calibrated_hz)),
+        tsc_info.map(|i| i.reported_hz).filter(|&hz| hz != 0),
+    );
+    info!(
+        "Platform: {}, vCPUs: {}, Kernel: {}, Clocksource: {}, TSC: {} Hz ({})",
+        environment.platform,
+        environment.vcpus,
+        environment.kernel,
+        environment.clocksource.as_deref().unwrap_or("unknown"),
+        environment.tsc_hz,
+        environment.tsc_source
+    );
+    if environment.tsc_mismatch() {
+        eprintln!(
+            "{} TSC calibration ({} Hz) disagrees with the platform-reported frequency by {:.0} ppm; latencies may be mis-scaled.",
+            err_color.fail("Warning:"),
+            environment.calibrated_tsc_hz.unwrap_or(0),
+            environment.tsc_mismatch_ppm.unwrap_or(0.0)
+        );
+    }
+
+    let mut forwarder = args
+        .forward
+        .as_ref()
+        .map(|t| hostlink::Forwarder::connect(t, tsc_hz))
+        .transpose()?;
+    if let Some(t) = &args.forward {
+        info!("Forwarding entries to {:?}", t);
+    }
 
     // --- Setup Ctrl+C Handler ---
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
 
     ctrlc::set_handler(move || {
-        println!("\nCtrl+C received, shutting down...");
+        info!("\nCtrl+C received, shutting down...");
         r.store(false, Ordering::SeqCst);
     })?;
 
-    println!("Ctrl+C handler set. Press Ctrl+C to stop.");
+    info!("Ctrl+C handler set. Press Ctrl+C to stop.");
+
+    #[cfg(feature = "stub")]
+    let synthetic = spawn_synthetic(&args, running.clone());
 
     // --- Consumer Loop ---
     let mut entries_processed: u64 = 0;
+    let mut entries_filtered: u64 = 0;
     let mut last_dropped_count: u64 = 0;
 
-    println!("Starting consumer loop...");
+    info!("Starting consumer loop...");
+    // Setup above is not counted; the summary below runs after the count.
+    let fault_tracker = args
+        .faults
+        .then(|| faults::FaultTracker::start(&connection, args.mlock));
+    let watch_interval = args.watch.map(Duration::from_secs);
+    let mut last_refresh = Instant::now();
+    // Logs HIRES_EV_CLOCK_CHECK entries this loop then consumes like any
+    // producer's.
+    let clock_check_interval = args.clock_check.map(Duration::from_secs);
+    let mut clock_check =
+        clock_check_interval.map(|_| rt::clockcheck::ClockCheck::new(&connection));
+    let mut last_clock_check = Instant::now();
+    let mut sample_budget = budget::SampleBudget::new(args.max_events, &args.max_event_samples);
+    let poll_interval = Duration::from_millis(args.poll_interval_ms);
+    let mut runs = batch::EventRuns::default();
 
-    while running.load(Ordering::SeqCst) {
-        let entry = connection.pop();
+    // A second thread drains the ring (see pipeline); this one aggregates
+    // the batches it passes on. Leaving the scope, early or not, drops
+    // `batches`, which ends the drain thread.
+    let (drainer, batches) = pipeline::pipeline();
+    thread::scope(|scope| -> Result<(), Box<dyn std::error::Error>> {
+        let mut batches = batches;
+        thread::Builder::new()
+            .name("hires-drain".to_string())
+            .spawn_scoped(scope, || drainer.run(&connection, poll_interval))?;
 
-        if let Some(entry) = entry {
-            if entry.flags & (LOG_FLAG_VALID as u16) != 0 {
-                // println!("Entry: {:?}", entry);
-                entries_processed += 1;
-                let e_id = entry.event_id;
-                let b_entry = &mut bench.event_bucket[e_id as usize];
-                b_entry.add_data(entry.data1);
-            } else {
-                println!("Invalid entry received.");
+        loop {
+            if let Some(budget) = &sample_budget
+                && budget.is_met()
+            {
+                info!("Sample budget met after {} entries, stopping.", budget.recorded());
+                // Also ends --synthetic.
+                running.store(false, Ordering::SeqCst);
+                break;
             }
-        } else {
-            if args.poll_interval_ms > 0 {
-                if running.load(Ordering::SeqCst) {
-                    thread::sleep(Duration::from_millis(args.poll_interval_ms));
+            // Stopping: consume what the drain thread already took from the
+            // ring (--synthetic-secs runs end once the ring is empty).
+            if !running.load(Ordering::SeqCst) {
+                batches.stop();
+            }
+            if let Some(interval) = watch_interval
+                && last_refresh.elapsed() >= interval
+            {
+                last_refresh = Instant::now();
+                // Clear the screen and move the cursor home.
+                print!("\x1b[2J\x1b[H");
+                let session_s = connection.session_elapsed().as_secs_f64();
+                info!("---- Summary (live, {:.0} s) ----", session_s);
+                print_events(
+                    &bench.summary(scale, session_s, &event_names, &channels, args.relative_to),
+                    scale,
+                    color,
+                );
+                info!();
+                if !slo_tracker.is_empty() {
+                    print_slos(&slo_tracker.results(), color);
                 }
-            } else {
-                // we want to burn the CPU to get the fastest possible consume rate.
-                // thread::yield_now();
+                info!(
+                    "Total entries processed: {}, Total entries dropped: {}",
+                    entries_processed,
+                    connection.get_drop_num()
+                );
+            }
+
+            if let Some(tracker) = &mut exit_tracker {
+                tracker.poll(tsc_hz, false);
+            }
+            if let Some(sampler) = &mut loss_sampler {
+                sampler.poll(&connection, tsc_hz, false);
+            }
+            if let (Some(check), Some(interval)) = (&mut clock_check, clock_check_interval)
+                && last_clock_check.elapsed() >= interval
+            {
+                last_clock_check = Instant::now();
+                check.check(&connection);
+            }
+            // A ring reset or a khires unload under us leaves nothing valid
+            // behind the indexes being followed; report what was collected.
+            if let Err(e) = connection.check_seal() {
+                eprintln!("Stopping: {}", e);
+                break;
             }
+
+            let Some(mut batch) = batches.next() else {
+                if batches.is_finished() {
+                    break;
+                }
+                if let Some(fwd) = &mut forwarder {
+                    fwd.flush()?;
+                }
+                if args.poll_interval_ms > 0 {
+                    if running.load(Ordering::SeqCst) {
+                        thread::sleep(poll_interval);
+                    }
+                } else {
+                    // we want to burn the CPU to get the fastest possible consume rate.
+                    // thread::yield_now();
+                }
+                continue;
+            };
+
+            // Reported once the batch is back in the pool.
+            let mut forward_error = None;
+            for entry in batch.drain(..) {
+                // The rest of the batch is over budget.
+                if sample_budget.as_ref().is_some_and(budget::SampleBudget::is_met) {
+                    break;
+                }
+                if entry.flags & (LOG_FLAG_VALID as u16) != 0 {
+                    // println!("Entry: {:?}", entry);
+                    entries_processed += 1;
+                    if let Some(fwd) = &mut forwarder
+                        && let Err(e) = fwd.send(&entry)
+                    {
+                        forward_error = Some(e);
+                        break;
+                    }
+                    let e_id = entry.event_id;
+                    if e_id == rt::HIRES_EV_THREAD_NAME {
+                        thread_breakdown.record_name(entry.data1, entry.data2);
+                        continue;
+                    }
+                    if e_id == rt::HIRES_EV_EVENT_NAME {
+                        event_names.record(entry.data1, entry.data2);
+                        continue;
+                    }
+                    if e_id == rt::HIRES_EV_CHANNEL_NAME {
+                        channels.record(entry.data1, entry.data2);
+                        continue;
+                    }
+                    if e_id == rt::HIRES_EV_CLOCK_CHECK {
+                        clock_tracker.record(entry.data1, entry.data2);
+                        continue;
+                    }
+                    // Registrations above always pass; every report below only
+                    // sees entries matching --filter.
+                    if let Some(filter) = &args.filter
+                        && !filter.matches(&entry)
+                    {
+                        entries_filtered += 1;
+                        continue;
+                    }
+                    if rt::stack::is_stack_frame(e_id) {
+                        let kernel = entry.flags & (LOG_FLAG_KERNEL as u16) != 0;
+                        stack_assembler.record(e_id, kernel, entry.data1, entry.data2);
+                        continue;
+                    }
+                    if rt::hwts::is_hw_timestamp(e_id) {
+                        hw_tracker.record(&entry, tsc_hz);
+                        continue;
+                    }
+                    if let Some(joiner) = &mut key_joiner {
+                        joiner.record(&entry, tsc_hz);
+                    }
+                    if rt::packet::is_packet_hook(e_id) {
+                        packet_tracker.record(&entry);
+                        continue;
+                    }
+                    if let Some(budget) = &mut sample_budget
+                        && !budget.admit(e_id)
+                    {
+                        continue;
+                    }
+                    if virtio::is_virtio_event(e_id) {
+                        virtio_tracker.record(e_id, entry.data1, entry.data2);
+                    } else if swiotlb::is_swiotlb_event(e_id) {
+                        swiotlb_tracker.record(e_id, entry.data1, entry.data2);
+                    } else if e_id == rt::HIRES_EV_TLS {
+                        tls_tracker.record(&entry);
+                    } else if e_id == rt::HIRES_EV_VMEXIT
+                        && let Some(tracker) = &mut exit_tracker
+                    {
+                        tracker.record(entry.data1, entry.data2);
+                    }
+                    if let Some(pairer) = &mut wakeup_pairer {
+                        pairer.record(&entry);
+                    }
+                    let ts_ns = timeline::entry_time_ns(&entry, tsc_hz);
+                    let kernel = entry.flags & (LOG_FLAG_KERNEL as u16) != 0;
+                    if let Some(slot) = bench.slot(e_id) {
+                        runs.push(slot, entry.data1, ts_ns, kernel);
+                    }
+                    if let Some(field) = args.seq_field {
+                        gap_tracker.record(e_id, field.get(&entry), ts_ns);
+                    }
+                    if let Some(field) = args.tid_field {
+                        thread_breakdown.record(e_id, field.get(&entry), entry.data1);
+                    }
+                    if let Some(breakdown) = &mut queue_breakdown {
+                        breakdown.record(&entry);
+                    }
+                    if let Some(dec) = &payload_decoder
+                        && let Some(fields) = dec.decode(e_id, entry.data1, entry.data2)
+                    {
+                        decoded_counts.record(e_id, decoder::render(&fields));
+                    }
+                    if args.spans || args.critical_path {
+                        span_store.record(e_id, entry.data1, entry.data2, ts_ns);
+                    }
+                    if kernel {
+                        if let Some(symbols) = &kernel_symbols
+                            && symbols.contains(entry.data1)
+                        {
+                            kernel_counts.record(e_id, entry.data1);
+                        }
+                    } else if user_symbols.is_some() {
+                        user_counts.record(e_id, args.symbols_field.get(&entry));
+                    }
+                } else {
+                    info!("Invalid entry received.");
+                }
+            }
+            // Per-event statistics are applied a run at a time (see batch).
+            if let Some(first_ns) = runs.first_ns() {
+                timeline.anchor(first_ns);
+            }
+            runs.drain(|slot, run| {
+                let e_id = bench.event_id(slot);
+                bench.add_run(slot, &run.values, run.kernel);
+                slo_tracker.record_run(e_id, &run.values);
+                timeline.record_run(e_id, &run.times_ns, &run.values);
+            });
+            batches.recycle(batch);
+            if let Some(e) = forward_error {
+                return Err(e.into());
+            }
+            // Optional: Check for dropped count if needed
+            // let current_dropped = connection.get_dropped_count();
+            // if current_dropped > last_dropped_count {
+            //     println!("Warning: {} entries dropped.", current_dropped - last_dropped_count);
+            //     last_dropped_count = current_dropped;
+            // }
         }
-        // Optional: Check for dropped count if needed
-        // let current_dropped = connection.get_dropped_count();
-        // if current_dropped > last_dropped_count {
-        //     println!("Warning: {} entries dropped.", current_dropped - last_dropped_count);
-        //     last_dropped_count = current_dropped;
-        // }
-    }
+        Ok(())
+    })?;
     
+    // Measured from the connection's session anchor, so rates and duty
+    // cycles cover the whole time entries could have been logged.
+    let run_duration = connection.session_elapsed();
+    let fault_report = fault_tracker.as_ref().map(|t| t.report(&connection));
+    #[cfg(feature = "stub")]
+    if let Some(handle) = synthetic {
+        let stats = handle.join().unwrap_or_default();
+        info!(
+            "Synthetic: {} logged, {} dropped by injection, {} rejected by a full ring",
+            stats.logged, stats.injected, stats.rejected
+        );
+    }
+    if let Some(tracker) = &mut exit_tracker {
+        tracker.poll(tsc_hz, true);
+    }
+    if let Some(sampler) = &mut loss_sampler {
+        sampler.poll(&connection, tsc_hz, true);
+    }
+    // The last partial interval too, so short runs get a sample; the loop
+    // no longer consumes its entry.
+    if let Some(sample) = clock_check.as_mut().and_then(|c| c.check(&connection)) {
+        clock_tracker.record(sample.tsc_ns, sample.monotonic_ns);
+    }
+
     // --- Summary ---
-    println!("---- Summary ----");
-    let cycle_rate = connection.get_cycles_per_us();
-    let result = bench.summary();
-    for entry in result.iter() {
-        println!(
-            "Event ID: {}, Count: {}, Average: {}, Duration: {} us",
-            entry.id, entry.count, entry.avg, entry.avg / (cycle_rate as f32)
+    info!("---- Summary ({:.3} s since connect) ----", run_duration.as_secs_f64());
+    let result = bench.summary(
+        scale,
+        run_duration.as_secs_f64(),
+        &event_names,
+        &channels,
+        args.relative_to,
+    );
+    print_events(&result, scale, color);
+    info!();
+
+    let group_results = groups::summarize(
+        &args.groups,
+        |id| bench.samples(id),
+        scale,
+        run_duration.as_secs_f64(),
+    );
+    if !group_results.is_empty() {
+        info!("---- Groups ({}) ----", scale.label());
+        for g in &group_results {
+            let events: Vec<String> = g.events.iter().map(u32::to_string).collect();
+            info!(
+                "Group: {} [{}], Count: {}, Average: {}, p50: {}, p99: {}, Max: {}, Rate: {:.1}/s",
+                g.name,
+                events.join(","),
+                g.count,
+                g.avg,
+                g.p50,
+                g.p99,
+                g.max,
+                g.rate_per_s
+            );
+        }
+        info!();
+    }
+
+    let correlation = args.correlate.then(|| correlate::correlate(&timeline));
+    if let Some(matrix) = &correlation {
+        info!(
+            "---- Correlation ({} ms buckets, rate = samples/bucket, p99 = p99 latency) ----",
+            timeline.bucket_ms()
         );
+        correlate::print_matrix(matrix);
+        info!();
     }
-    println!();
-    
+
+    let anomalies = args
+        .detect_anomalies
+        .then(|| anomaly::detect(&timeline, args.anomaly_window, args.anomaly_threshold, scale));
+    if let Some(anomalies) = &anomalies {
+        info!(
+            "---- Anomalies (window {} buckets, threshold {}, {}) ----",
+            args.anomaly_window,
+            args.anomaly_threshold,
+            scale.label()
+        );
+        if anomalies.is_empty() {
+            info!("No anomalies detected.");
+        }
+        for a in anomalies {
+            info!(
+                "Event ID: {}, Window: {}-{} ms, Peak: {}, Baseline: {}, Score: {:.1}",
+                a.event_id, a.start_ms, a.end_ms, a.peak, a.baseline, a.score
+            );
+        }
+        info!();
+    }
+
+    let gap_report = args
+        .seq_field
+        .map(|_| gap_tracker.report(timeline.origin_ns()));
+    if let Some(report) = &gap_report {
+        info!("---- Gaps (sequence numbers in {:?}) ----", args.seq_field.unwrap());
+        for e in &report.events {
+            info!(
+                "Event ID: {}, Gaps: {}, Missing: {}, Out of order: {}",
+                e.event_id, e.gaps, e.missing, e.out_of_order
+            );
+        }
+        const MAX_PRINTED_GAPS: usize = 20;
+        for g in report.ranges.iter().take(MAX_PRINTED_GAPS) {
+            info!(
+                "  Event ID: {}, {}-{} ms, seq {} -> {} ({} missing)",
+                g.event_id, g.start_ms, g.end_ms, g.after_seq, g.before_seq, g.missing
+            );
+        }
+        if report.ranges.len() > MAX_PRINTED_GAPS {
+            info!("  ... {} more gaps", report.ranges.len() - MAX_PRINTED_GAPS);
+        }
+        info!("Total missing entries: {}", report.total_missing);
+        info!();
+    }
+
+    let thread_results = args.tid_field.map(|_| thread_breakdown.summary(scale));
+    if let Some(results) = &thread_results {
+        info!("---- Per-thread ({}) ----", scale.label());
+        for r in results {
+            let name = r.name.as_deref().map(|n| format!(" ({})", n)).unwrap_or_default();
+            info!(
+                "Event ID: {}, TID: {}{}, Count: {}, Average: {}, p50: {}, p99: {}, Max: {}",
+                r.event_id, r.tid, name, r.count, r.avg, r.p50, r.p99, r.max
+            );
+        }
+        info!();
+    }
+
+    let queue_report = queue_breakdown
+        .as_ref()
+        .filter(|q| !q.is_empty())
+        .map(|q| q.report(scale));
+    if let Some(r) = &queue_report {
+        queues::print_report(r, scale);
+    }
+
+    let kernel_hits = kernel_symbols
+        .as_ref()
+        .map(|syms| kernel_counts.hits(|addr| syms.resolve(addr)));
+    if let Some(hits) = &kernel_hits {
+        symbols::print_hits("Kernel symbols (data1 of kernel events)", hits);
+    }
+    let user_hits = match &user_symbols {
+        Some(syms) => {
+            let resolved = syms.resolve_all(&user_counts.addresses())?;
+            Some(user_counts.hits(|addr| resolved.get(&addr).cloned()))
+        }
+        None => None,
+    };
+    if let Some(hits) = &user_hits {
+        let title = format!("User symbols ({:?} of userspace events)", args.symbols_field);
+        symbols::print_hits(&title, hits);
+    }
+
+    let stack_summary = {
+        let user_resolved = match &user_symbols {
+            Some(syms) => syms.resolve_all(&stack_assembler.user_addresses())?,
+            None => Default::default(),
+        };
+        stack_assembler.summary(|kernel, addr| {
+            if kernel {
+                kernel_symbols.as_ref()?.resolve(addr)
+            } else {
+                user_resolved.get(&addr).cloned()
+            }
+        })
+    };
+    if !stack_summary.is_empty() || stack_assembler.incomplete() > 0 {
+        info!("---- Stack traces ----");
+        const MAX_PRINTED_STACKS: usize = 5;
+        for s in report::top_per_event(&stack_summary, MAX_PRINTED_STACKS, |s| s.event_id) {
+            info!("Event ID: {}, Count: {}", s.event_id, s.count);
+            for frame in &s.frames {
+                info!("    {}", frame);
+            }
+        }
+        info!("Incomplete stacks: {}", stack_assembler.incomplete());
+        info!();
+    }
+
+    let clock_check_report = clock_tracker.report();
+    if let Some(check) = &clock_check_report {
+        info!(
+            "TSC cross-check: {} intervals, mean error {:.1} ppm, worst {:.1} ppm against CLOCK_MONOTONIC",
+            check.samples, check.mean_error_ppm, check.worst_error_ppm
+        );
+        if check.mismatch {
+            eprintln!(
+                "{} the TSC disagrees with CLOCK_MONOTONIC by {:.0} ppm during the run; latencies may be mis-scaled.",
+                err_color.fail("Warning:"),
+                check.mean_error_ppm
+            );
+        }
+        info!();
+    }
+
+    let hw_report = (!hw_tracker.is_empty()).then(|| hw_tracker.report(tsc_hz, scale));
+    if let Some(hw) = &hw_report {
+        info!("---- Hardware timestamps ----");
+        match (hw.drift_ppm, hw.max_residual_ns) {
+            (Some(drift), Some(residual)) => info!(
+                "PHC sync samples: {}, Drift: {:.2} ppm, Max residual: {:.0} ns",
+                hw.sync_samples, drift, residual
+            ),
+            _ => info!("No PHC sync samples; hardware timestamps cannot be placed on the timeline."),
+        }
+        for l in &hw.latencies {
+            info!(
+                "Event ID: {}, Direction: {}, Count: {}, Average: {} {}, p50: {}, p99: {}, Max: {}, Negative: {}",
+                l.event_id,
+                l.direction,
+                l.count,
+                l.avg,
+                scale.label(),
+                l.p50,
+                l.p99,
+                l.max,
+                l.negative
+            );
+        }
+        if hw.unmatched_tx > 0 {
+            info!("Unmatched TX completions: {}", hw.unmatched_tx);
+        }
+        if hw.evicted_tx > 0 {
+            info!("TX submit markers discarded: {}", hw.evicted_tx);
+        }
+        if hw.non_tsc > 0 {
+            info!(
+                "Note: {} entries were not stamped from the TSC; use an rdtsc timestamp source for wire-to-host latency.",
+                hw.non_tsc
+            );
+        }
+        info!();
+    }
+
+    let virtio_report = (!virtio_tracker.is_empty()).then(|| virtio_tracker.report(scale));
+    if let Some(v) = &virtio_report {
+        info!("---- virtio-net datapath ----");
+        let pipeline: Vec<&str> = v.stages.iter().map(|s| s.name).collect();
+        info!("  {}", pipeline.join(" -> "));
+        for s in &v.stages {
+            info!(
+                "Stage {} (e{}): Count: {}, Average: {} {}, p99: {}",
+                s.name,
+                s.event_id,
+                s.count,
+                s.avg,
+                scale.label(),
+                s.p99
+            );
+        }
+        if let Some(ppp) = v.packets_per_poll {
+            info!("Packets per NAPI poll: {:.2}", ppp);
+        }
+        if v.bytes_delivered > 0 {
+            info!("Bytes delivered: {}", v.bytes_delivered);
+        }
+        if !v.kicks_per_queue.is_empty() {
+            let kicks: Vec<String> = v
+                .kicks_per_queue
+                .iter()
+                .map(|(vq, n)| format!("vq{}: {}", vq, n))
+                .collect();
+            info!("Kicks per virtqueue: {}", kicks.join(", "));
+        }
+        info!();
+    }
+
+    let wakeup_report = wakeup_pairer.as_ref().map(|p| p.report(scale));
+    if let Some(w) = &wakeup_report {
+        info!("---- Interrupt-to-wakeup latency ----");
+        info!("Interrupt event ID: {}", w.irq_event);
+        for k in &w.keys {
+            info!(
+                "Key: {}, Count: {}, Average: {} {}, p50: {}, p90: {}, p99: {}, Max: {}",
+                k.key,
+                k.count,
+                k.avg,
+                scale.label(),
+                k.p50,
+                k.p90,
+                k.p99,
+                k.max
+            );
+        }
+        info!("Unpaired wakeups: {}", w.unpaired);
+        info!();
+    }
+
+    let join_report = key_joiner.as_ref().map(|j| j.report(scale));
+    if let Some(j) = &join_report {
+        info!("---- Key join ----");
+        for t in &j.transitions {
+            info!(
+                "Hop: {} -> {}, Count: {}, Average: {} {}, p50: {}, p99: {}, Max: {}",
+                t.from,
+                t.to,
+                t.count,
+                t.avg,
+                scale.label(),
+                t.p50,
+                t.p99,
+                t.max
+            );
+        }
+        info!("Keys: {}, seen by a single event: {}", j.keys, j.unjoined);
+        if j.evicted > 0 {
+            info!("Keys folded in before every event saw them: {}", j.evicted);
+        }
+        info!();
+    }
+
+    let swiotlb_report = (!swiotlb_tracker.is_empty()).then(|| swiotlb_tracker.report(scale));
+    if let Some(ops) = &swiotlb_report {
+        swiotlb::print_report(ops, run_duration.as_secs_f64(), scale);
+    }
+
+    let loss_report = loss_sampler.as_ref().map(|s| {
+        s.report(&timeline, args.anomaly_window, args.anomaly_threshold, scale)
+    });
+    if let Some(r) = &loss_report {
+        loss::print_report(r);
+    }
+
+    if let Some(r) = &fault_report {
+        faults::print_report(r);
+    }
+
+    let tls_report = (!tls_tracker.is_empty())
+        .then(|| tls_tracker.report(run_duration.as_secs_f64(), tsc_hz, scale));
+    if let Some(ops) = &tls_report {
+        tls::print_report(ops, scale);
+    }
+
+    let vm_exit_report = exit_tracker
+        .as_ref()
+        .map(|t| t.report(&timeline, args.anomaly_threshold, scale));
+    if let Some(r) = &vm_exit_report {
+        vmexits::print_report(r, timeline.bucket_ms(), scale);
+    }
+
+    let packet_report = (!packet_tracker.is_empty()).then(|| packet_tracker.report(scale));
+    if let Some(p) = &packet_report {
+        info!("---- Packet datapath ----");
+        info!(
+            "Packets: XDP: {}, tc ingress: {}, Socket: {}, Matched: {}",
+            p.xdp, p.tc_ingress, p.socket, p.matched
+        );
+        for s in &p.stages {
+            info!(
+                "Stage {}: Count: {}, Average: {} {}, p50: {}, p99: {}, Max: {}",
+                s.stage,
+                s.count,
+                s.avg,
+                scale.label(),
+                s.p50,
+                s.p99,
+                s.max
+            );
+        }
+        if p.evicted > 0 {
+            info!("Unmatched packets discarded: {}", p.evicted);
+        }
+        if p.non_monotonic > 0 {
+            info!(
+                "Note: {} socket entries were stamped from the TSC; keep the default timestamp source to compare against the eBPF hooks.",
+                p.non_monotonic
+            );
+        }
+        info!();
+    }
+
+    if span_store.dropped() > 0 {
+        eprintln!(
+            "Warning: span store full; {} spans were ignored and trees may be incomplete",
+            span_store.dropped()
+        );
+    }
+    if span_store.reused() > 0 {
+        info!(
+            "Note: {} spans reused an earlier span ID; children were linked to the parent ending first after them.",
+            span_store.reused()
+        );
+    }
+    let span_breakdown = args.spans.then(|| span_store.forest().breakdown(scale));
+    if let Some(breakdown) = &span_breakdown {
+        info!("---- Span trees ----");
+        for b in breakdown {
+            info!(
+                "Root event ID: {}, Trees: {}, Average: {} {}",
+                b.root_event,
+                b.trees,
+                b.root_avg,
+                scale.label()
+            );
+            let pipeline: Vec<String> = b.stages.iter().map(|s| format!("e{}", s.path)).collect();
+            info!("  {}", pipeline.join(" -> "));
+            for s in &b.stages {
+                info!(
+                    "  Stage e{}: Count: {}, Average: {} {}, Share: {:.1}%",
+                    s.path,
+                    s.count,
+                    s.avg,
+                    scale.label(),
+                    s.share * 100.0
+                );
+            }
+        }
+        info!();
+    }
+
+    let critical_paths = args
+        .critical_path
+        .then(|| span_store.forest().critical_paths(scale));
+    if let Some(reports) = &critical_paths {
+        info!("---- Critical path ----");
+        for r in reports {
+            info!("Root event ID: {}, Trees: {}", r.root_event, r.trees);
+            for s in &r.stages {
+                info!(
+                    "  Stage {}: Dominant in {} ({:.1}%), Avg on path: {:.3} {}",
+                    s.path,
+                    s.dominant,
+                    s.dominant_share * 100.0,
+                    s.avg_on_path,
+                    scale.label()
+                );
+            }
+        }
+        info!();
+    }
+
+    let decoded_hits = payload_decoder.as_ref().map(|_| decoded_counts.hits());
+    if let Some(hits) = &decoded_hits {
+        info!("---- Decoded payloads ----");
+        const MAX_PRINTED_DECODED: usize = 10;
+        for h in report::top_per_event(hits, MAX_PRINTED_DECODED, |h| h.event_id) {
+            info!("Event ID: {}, {}: {}", h.event_id, h.fields, h.count);
+        }
+        info!();
+    }
+
+    let assertion_results =
+        assertions::check_all(&args.assertions, |id| bench.samples(id), scale, args.units);
+    if !assertion_results.is_empty() {
+        info!("---- Assertions ----");
+        for r in &assertion_results {
+            let actual = r
+                .actual
+                .map_or_else(|| "no samples".to_string(), |v| v.to_string());
+            info!(
+                "Event ID: {}, {}: {} (actual: {})",
+                r.event_id,
+                r.check,
+                if r.passed {
+                    color.pass("PASS")
+                } else {
+                    color.fail("FAIL")
+                },
+                actual
+            );
+        }
+        info!();
+    }
+
+    let slo_results = slo_tracker.results();
+    if !slo_results.is_empty() {
+        print_slos(&slo_results, color);
+    }
+
     let drop_num = connection.get_drop_num();
-    println!(
+    info!(
         "Total entries processed: {}, Total entries dropped: {}",
         entries_processed, drop_num
     );
+    if args.filter.is_some() {
+        info!("Entries filtered out: {}", entries_filtered);
+    }
+    let torn_reads = args.torn_checks.then(|| connection.torn_reads());
+    if let Some(torn) = torn_reads {
+        info!("Torn entries discarded: {}", torn);
+        if torn > 0 {
+            eprintln!(
+                "Warning: {} entries were rewritten while being read; a producer is not following the ring protocol",
+                torn
+            );
+        }
+    }
+    if timeline.out_of_range() > 0 {
+        eprintln!(
+            "Warning: {} samples were timestamped past the last {} ms bucket and left out of the series",
+            timeline.out_of_range(),
+            timeline.bucket_ms()
+        );
+    }
+    let mut clock_model = None;
+    if let Some(fwd) = &mut forwarder {
+        fwd.flush()?;
+        info!("Entries not forwarded (host ring full): {}", fwd.dropped());
+        clock_model = fwd.finish_clock_sync();
+    }
+    if let Some(m) = &clock_model {
+        info!(
+            "Guest-to-host clock: offset {:.0} cycles at guest TSC {}, drift {:.3} ppm, sync delay {} cycles",
+            m.offset_cycles, m.guest_ref_tsc, m.drift_ppm, m.delay_cycles
+        );
+    }
+
+    if args.json.is_some() || args.csv.is_some() || args.markdown.is_some() || args.html.is_some() {
+        let series = timeline.points(scale);
+        let histograms: Vec<stats::Histogram> = if args.markdown.is_some() {
+            result
+                .iter()
+                .map(|e| {
+                    stats::log2_histogram(e.id as u32, bench.samples(e.id as u32), scale)
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        const CDF_POINTS: usize = 200;
+        let cdfs: Vec<stats::Cdf> = if args.html.is_some() {
+            result
+                .iter()
+                .map(|e| {
+                    stats::cdf(e.id as u32, bench.samples(e.id as u32), CDF_POINTS, scale)
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let report = Report {
+            device: &args.device,
+            environment: &environment,
+            process: &process,
+            duration_s: run_duration.as_secs_f64(),
+            session_start_tsc: session.tsc,
+            session_start_unix_ns: session
+                .wall
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64),
+            cycles_per_us: cycle_rate,
+            units: scale.unit(),
+            entries_processed,
+            entries_dropped: drop_num,
+            entries_filtered,
+            entries_torn: torn_reads,
+            bucket_ms: timeline.bucket_ms(),
+            events: &result,
+            relative_to: args.relative_to,
+            groups: &group_results,
+            series: &series,
+            histograms: &histograms,
+            cdfs: &cdfs,
+            correlation: correlation.as_ref(),
+            anomalies: anomalies.as_deref(),
+            gaps: gap_report.as_ref(),
+            threads: thread_results.as_deref(),
+            queues: queue_report.as_ref(),
+            kernel_symbols: kernel_hits.as_deref(),
+            user_symbols: user_hits.as_deref(),
+            stacks: &stack_summary,
+            hw_timestamps: hw_report.as_ref(),
+            packets: packet_report.as_ref(),
+            virtio: virtio_report.as_ref(),
+            swiotlb: swiotlb_report.as_deref(),
+            tls: tls_report.as_deref(),
+            vm_exits: vm_exit_report.as_ref(),
+            loss: loss_report.as_ref(),
+            faults: fault_report.as_ref(),
+            wakeup: wakeup_report.as_ref(),
+            join: join_report.as_ref(),
+            clock_sync: clock_model.as_ref(),
+            clock_check: clock_check_report.as_ref(),
+            spans: span_breakdown.as_deref(),
+            critical_path: critical_paths.as_deref(),
+            decoded: decoded_hits.as_deref(),
+            assertions: &assertion_results,
+            slos: &slo_results,
+        };
+        // Reports written to stdout ('-') are not announced there.
+        if let Some(path) = &args.json {
+            report::write_json(path, &report)?;
+            if !report::is_stdout(path) {
+                info!("JSON report written to {}", path.display());
+            }
+        }
+        if let Some(path) = &args.csv
+            && let Some(series_path) = report::write_csv(path, &report)?
+        {
+            info!(
+                "CSV report written to {} (series: {})",
+                path.display(),
+                series_path.display()
+            );
+        }
+        if let Some(path) = &args.markdown {
+            markdown::write_markdown(path, &report)?;
+            if !report::is_stdout(path) {
+                info!("Markdown report written to {}", path.display());
+            }
+        }
+        if let Some(path) = &args.html {
+            html::write_html(path, &report)?;
+            if !report::is_stdout(path) {
+                info!("HTML report written to {}", path.display());
+            }
+        }
+    }
+
+    if let Some(path) = &args.junit {
+        report::write_junit(path, &assertion_results)?;
+        info!("JUnit report written to {}", path.display());
+    }
+
+    let failed = assertion_results.iter().filter(|r| !r.passed).count();
+    if failed > 0 {
+        return Err(format!("{} of {} assertions failed", failed, assertion_results.len()).into());
+    }
 
     Ok(())
 }
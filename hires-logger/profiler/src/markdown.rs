@@ -0,0 +1,313 @@
+//! Markdown run report, for pasting into issues and experiment logs.
+
+use crate::report::{self, Report};
+use crate::stats::Histogram;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Width of the longest bar in the histogram code blocks.
+const HISTOGRAM_WIDTH: u64 = 40;
+
+pub fn write_markdown(path: &Path, report: &Report) -> io::Result<()> {
+    let mut w = report::create(path)?;
+    let unit = report.units.label();
+
+    writeln!(w, "# Profiler report")?;
+    writeln!(w)?;
+    writeln!(w, "## Run")?;
+    writeln!(w)?;
+    writeln!(w, "| | |")?;
+    writeln!(w, "|---|---|")?;
+    let process = report.process;
+    writeln!(w, "| Command | `{}` |", process.cmdline.join(" "))?;
+    writeln!(w, "| PID | {} |", process.pid)?;
+    writeln!(w, "| Started (Unix time) | {} |", process.started_unix_s)?;
+    let uts = &process.uts;
+    writeln!(
+        w,
+        "| Host | {} ({} {} {} {}) |",
+        uts.nodename, uts.sysname, uts.release, uts.version, uts.machine
+    )?;
+    if let Some(cgroup) = &process.cgroup {
+        writeln!(w, "| cgroup | `{}` |", cgroup)?;
+    }
+    writeln!(
+        w,
+        "| Versions | profiler {}, rt {} |",
+        process.profiler_version, process.rt_version
+    )?;
+    writeln!(w, "| Device | `{}` |", report.device)?;
+    let env = report.environment;
+    match &env.hypervisor {
+        Some(hv) => writeln!(w, "| Platform | {} ({}) |", env.platform, hv)?,
+        None => writeln!(w, "| Platform | {} |", env.platform)?,
+    }
+    writeln!(w, "| vCPUs | {} |", env.vcpus)?;
+    writeln!(w, "| Kernel | {} |", env.kernel)?;
+    if let Some(clocksource) = &env.clocksource {
+        writeln!(w, "| Clocksource | {} |", clocksource)?;
+    }
+    writeln!(w, "| TSC frequency | {} Hz ({}) |", env.tsc_hz, env.tsc_source)?;
+    if let Some(hz) = env.calibrated_tsc_hz
+        && hz != env.tsc_hz
+    {
+        writeln!(w, "| TSC frequency (calibrated) | {} Hz |", hz)?;
+    }
+    if let Some(hz) = env.secure_tsc_hz {
+        writeln!(w, "| TSC frequency (SecureTSC) | {} Hz |", hz)?;
+    }
+    if let Some(hz) = env.cpuid_tsc_hz {
+        writeln!(w, "| TSC frequency (CPUID) | {} Hz |", hz)?;
+    }
+    if let Some(ppm) = env.tsc_mismatch_ppm {
+        let flag = if env.tsc_mismatch() { " **MISMATCH**" } else { "" };
+        writeln!(w, "| TSC calibration error | {:.0} ppm{} |", ppm, flag)?;
+    }
+    if !env.tsc_flags.is_empty() {
+        writeln!(w, "| TSC flags | {} |", env.tsc_flags.join(" "))?;
+    }
+    if let Some(check) = report.clock_check {
+        let flag = if check.mismatch { " **MISMATCH**" } else { "" };
+        writeln!(
+            w,
+            "| TSC vs CLOCK_MONOTONIC | {:.1} ppm mean, {:.1} ppm worst over {} intervals{} |",
+            check.mean_error_ppm, check.worst_error_ppm, check.samples, flag
+        )?;
+    }
+    writeln!(w, "| Duration (since connect) | {:.1} s |", report.duration_s)?;
+    writeln!(w, "| Units | {} |", unit)?;
+    writeln!(w, "| Bucket width | {} ms |", report.bucket_ms)?;
+    writeln!(w, "| Entries processed | {} |", report.entries_processed)?;
+    writeln!(w, "| Entries dropped | {} |", report.entries_dropped)?;
+    writeln!(w, "| Entries filtered out | {} |", report.entries_filtered)?;
+    if let Some(torn) = report.entries_torn {
+        writeln!(w, "| Entries torn (discarded) | {} |", torn)?;
+    }
+    writeln!(w)?;
+
+    writeln!(w, "## Events")?;
+    writeln!(w)?;
+    let relative_header = report
+        .relative_to
+        .map(|id| format!(" vs event {} |", id))
+        .unwrap_or_default();
+    writeln!(
+        w,
+        "| Event ID | Name | Count | Average ({}) | Rate (/s) | Duty cycle | Share |{}",
+        unit, relative_header
+    )?;
+    writeln!(
+        w,
+        "|---:|---|---:|---:|---:|---:|---:|{}",
+        if report.relative_to.is_some() { "---:|" } else { "" }
+    )?;
+    for e in report.events {
+        let duty = e
+            .duty_cycle
+            .map_or("-".to_string(), |d| format!("{:.2}%", d * 100.0));
+        let relative = match (report.relative_to, e.relative) {
+            (Some(_), Some(r)) => format!(" {:.2}x |", r),
+            (Some(_), None) => " - |".to_string(),
+            (None, _) => String::new(),
+        };
+        writeln!(
+            w,
+            "| {} | {} | {} | {} | {:.1} | {} | {:.1}% |{}",
+            e.label(),
+            e.name.as_deref().unwrap_or("-"),
+            e.count,
+            e.avg,
+            e.rate_per_s,
+            duty,
+            e.share * 100.0,
+            relative
+        )?;
+    }
+    writeln!(w)?;
+
+    if !report.groups.is_empty() {
+        writeln!(w, "## Groups")?;
+        writeln!(w)?;
+        writeln!(
+            w,
+            "| Group | Events | Count | Average ({0}) | p50 ({0}) | p99 ({0}) | Max ({0}) | Rate (/s) |",
+            unit
+        )?;
+        writeln!(w, "|---|---|---:|---:|---:|---:|---:|---:|")?;
+        for g in report.groups {
+            let events: Vec<String> = g.events.iter().map(u32::to_string).collect();
+            writeln!(
+                w,
+                "| {} | {} | {} | {} | {} | {} | {} | {:.1} |",
+                g.name,
+                events.join(", "),
+                g.count,
+                g.avg,
+                g.p50,
+                g.p99,
+                g.max,
+                g.rate_per_s
+            )?;
+        }
+        writeln!(w)?;
+    }
+
+    if !report.histograms.is_empty() {
+        writeln!(w, "## Latency histograms ({})", unit)?;
+        writeln!(w)?;
+        for h in report.histograms {
+            write_histogram(&mut w, h)?;
+        }
+    }
+
+    if !report.assertions.is_empty() {
+        writeln!(w, "## Assertions")?;
+        writeln!(w)?;
+        writeln!(w, "| Event ID | Check | Result | Actual |")?;
+        writeln!(w, "|---:|---|---|---:|")?;
+        for r in report.assertions {
+            let actual = r.actual.map_or_else(|| "-".to_string(), |v| v.to_string());
+            let result = if r.passed { "PASS" } else { "**FAIL**" };
+            writeln!(
+                w,
+                "| {} | `{}` | {} | {} |",
+                r.event_id, r.check, result, actual
+            )?;
+        }
+        writeln!(w)?;
+    }
+
+    if !report.slos.is_empty() {
+        writeln!(w, "## SLOs")?;
+        writeln!(w)?;
+        writeln!(w, "| Event ID | Objective | Samples | Violations | Violating |")?;
+        writeln!(w, "|---:|---|---:|---:|---:|")?;
+        for r in report.slos {
+            writeln!(
+                w,
+                "| {} | `{}` | {} | {} | {:.3}% |",
+                r.event_id,
+                r.objective,
+                r.samples,
+                r.violations,
+                r.violation_fraction * 100.0
+            )?;
+        }
+        writeln!(w)?;
+    }
+
+    if let Some(threads) = report.threads {
+        writeln!(w, "## Per-thread")?;
+        writeln!(w)?;
+        writeln!(w, "Latencies in {}.", unit)?;
+        writeln!(w)?;
+        writeln!(w, "| Event ID | TID | Name | Count | Average | p50 | p99 | Max |")?;
+        writeln!(w, "|---:|---:|---|---:|---:|---:|---:|---:|")?;
+        for t in threads {
+            writeln!(
+                w,
+                "| {} | {} | {} | {} | {} | {} | {} | {} |",
+                t.event_id,
+                t.tid,
+                t.name.as_deref().unwrap_or("-"),
+                t.count,
+                t.avg,
+                t.p50,
+                t.p99,
+                t.max
+            )?;
+        }
+        writeln!(w)?;
+    }
+
+    if let Some(anomalies) = report.anomalies {
+        writeln!(w, "## Anomalies")?;
+        writeln!(w)?;
+        if anomalies.is_empty() {
+            writeln!(w, "No anomalies detected.")?;
+        } else {
+            writeln!(w, "| Event ID | Window (ms) | Peak ({0}) | Baseline ({0}) | Score |", unit)?;
+            writeln!(w, "|---:|---|---:|---:|---:|")?;
+            for a in anomalies {
+                writeln!(
+                    w,
+                    "| {} | {}-{} | {} | {} | {:.1} |",
+                    a.event_id, a.start_ms, a.end_ms, a.peak, a.baseline, a.score
+                )?;
+            }
+        }
+        writeln!(w)?;
+    }
+
+    if let Some(gaps) = report.gaps {
+        writeln!(w, "## Gaps")?;
+        writeln!(w)?;
+        writeln!(w, "| Event ID | Gaps | Missing | Out of order |")?;
+        writeln!(w, "|---:|---:|---:|---:|")?;
+        for e in &gaps.events {
+            writeln!(
+                w,
+                "| {} | {} | {} | {} |",
+                e.event_id, e.gaps, e.missing, e.out_of_order
+            )?;
+        }
+        writeln!(w)?;
+    }
+
+    if let Some(paths) = report.critical_path {
+        writeln!(w, "## Critical path")?;
+        writeln!(w)?;
+        writeln!(
+            w,
+            "| Root event ID | Stage | Dominant | Share | Avg on path ({}) |",
+            unit
+        )?;
+        writeln!(w, "|---:|---|---:|---:|---:|")?;
+        for r in paths {
+            for s in &r.stages {
+                writeln!(
+                    w,
+                    "| {} | {} | {} | {:.1}% | {:.3} |",
+                    r.root_event,
+                    s.path,
+                    s.dominant,
+                    s.dominant_share * 100.0,
+                    s.avg_on_path
+                )?;
+            }
+        }
+        writeln!(w)?;
+    }
+
+    w.flush()
+}
+
+fn write_histogram(w: &mut impl Write, h: &Histogram) -> io::Result<()> {
+    let peak = h.buckets.iter().map(|b| b.count).max().unwrap_or(0).max(1);
+    writeln!(w, "### Event {}", h.event_id)?;
+    writeln!(w)?;
+    writeln!(w, "```")?;
+    for b in &h.buckets {
+        let bar = "#".repeat((b.count * HISTOGRAM_WIDTH).div_ceil(peak) as usize);
+        writeln!(
+            w,
+            "[{:>12}, {:>12})  {:<width$}  {}",
+            format_bound(b.lower),
+            format_bound(b.upper),
+            bar,
+            b.count,
+            width = HISTOGRAM_WIDTH as usize
+        )?;
+    }
+    writeln!(w, "```")?;
+    writeln!(w)
+}
+
+/// Bucket bounds are exact in cycles but fractional in other units.
+fn format_bound(v: f64) -> String {
+    if v.fract() == 0.0 {
+        format!("{}", v)
+    } else {
+        format!("{:.3}", v)
+    }
+}
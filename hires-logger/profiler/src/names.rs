@@ -0,0 +1,48 @@
+//! Names registered in-band: `HIRES_EV_THREAD_NAME` (see `rt::thread`) and
+//! `HIRES_EV_EVENT_NAME` (see `rt::event`), which share an encoding.
+
+use std::collections::HashMap;
+
+/// Names by key (a TID or an event ID), assembled from their parts.
+#[derive(Default)]
+pub struct Names {
+    names: HashMap<u32, String>,
+    /// Parts of names still being received, by key.
+    partial: HashMap<u32, Vec<u64>>,
+    /// Names given on the command line, which registrations do not replace.
+    aliases: HashMap<u32, String>,
+}
+
+impl Names {
+    /// Records one part of a registration. A producer logs the parts in
+    /// order, so a part out of sequence means one was dropped; that name is
+    /// discarded rather than misspelled.
+    pub fn record(&mut self, data1: u64, data2: u64) {
+        let (key, part, parts) = rt::thread::unpack_name_tag(data1);
+        let pending = self.partial.entry(key).or_default();
+        if part == 0 {
+            pending.clear();
+        }
+        if pending.len() != part as usize {
+            self.partial.remove(&key);
+            return;
+        }
+        pending.push(data2);
+        if pending.len() >= parts as usize {
+            let name = rt::thread::name_from_parts(pending);
+            self.partial.remove(&key);
+            self.names.insert(key, name);
+        }
+    }
+
+    pub fn alias(&mut self, key: u32, name: String) {
+        self.aliases.insert(key, name);
+    }
+
+    pub fn get(&self, key: u32) -> Option<&str> {
+        self.aliases
+            .get(&key)
+            .or_else(|| self.names.get(&key))
+            .map(String::as_str)
+    }
+}
@@ -0,0 +1,143 @@
+//! NIC-to-socket latency breakdown from XDP/tc packet hooks.
+//!
+//! See `rt::packet` for the convention. Observations of the same packet are
+//! joined by hash; a packet is complete once the socket entry arrives, and
+//! its hook-to-hook intervals are then attributed to the datapath stages.
+
+use crate::stats::percentile;
+use crate::units::Scale;
+use rt::packet::{HOOK_SOCKET, HOOK_TC_INGRESS, HOOK_XDP, PACKET_HOOK_FLAG};
+use rt::{LOG_FLAG_KERNEL, LOG_FLAG_TSC, log_entry_t};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Packets only seen by the hooks (other traffic, or dropped before the
+/// socket) pile up; past this many the pending set is discarded.
+const MAX_PENDING: usize = 1 << 20;
+
+/// Stages in datapath order: (name, from hook, to hook).
+const STAGES: &[(&str, u32, u32)] = &[
+    ("xdp->tc", HOOK_XDP, HOOK_TC_INGRESS),
+    ("tc->socket", HOOK_TC_INGRESS, HOOK_SOCKET),
+    ("xdp->socket", HOOK_XDP, HOOK_SOCKET),
+];
+
+#[derive(Serialize)]
+pub struct PacketStage {
+    pub stage: &'static str,
+    pub count: u64,
+    pub avg: f64,
+    pub p50: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+#[derive(Serialize)]
+pub struct PacketReport {
+    pub xdp: u64,
+    pub tc_ingress: u64,
+    pub socket: u64,
+    /// Socket entries with a matching XDP or tc observation.
+    pub matched: u64,
+    /// Pending packets discarded at `MAX_PENDING`.
+    pub evicted: u64,
+    /// Socket entries stamped from the TSC rather than CLOCK_MONOTONIC.
+    pub non_monotonic: u64,
+    pub stages: Vec<PacketStage>,
+}
+
+/// First time each hook saw a packet, indexed by `HOOK_* - 1`.
+type HookTimes = [Option<u64>; 3];
+
+#[derive(Default)]
+pub struct PacketTracker {
+    pending: HashMap<u64, HookTimes>,
+    seen: [u64; 3],
+    matched: u64,
+    evicted: u64,
+    non_monotonic: u64,
+    /// Per-stage intervals in ns, parallel to `STAGES`.
+    samples: Vec<Vec<u64>>,
+}
+
+impl PacketTracker {
+    pub fn new() -> Self {
+        PacketTracker {
+            samples: vec![Vec::new(); STAGES.len()],
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.iter().all(|&n| n == 0)
+    }
+
+    pub fn record(&mut self, entry: &log_entry_t) {
+        let hook = entry.event_id & !PACKET_HOOK_FLAG;
+        if !(HOOK_XDP..=HOOK_SOCKET).contains(&hook) {
+            return;
+        }
+        self.seen[hook as usize - 1] += 1;
+        let ts = if entry.data1 != 0 {
+            entry.data1
+        } else {
+            if entry.flags & ((LOG_FLAG_KERNEL | LOG_FLAG_TSC) as u16) != 0 {
+                self.non_monotonic += 1;
+            }
+            entry.timestamp
+        };
+
+        if hook != HOOK_SOCKET {
+            if self.pending.len() >= MAX_PENDING {
+                self.evicted += self.pending.len() as u64;
+                self.pending.clear();
+            }
+            let times = self.pending.entry(entry.data2).or_default();
+            times[hook as usize - 1].get_or_insert(ts);
+            return;
+        }
+
+        let Some(mut times) = self.pending.remove(&entry.data2) else {
+            return;
+        };
+        times[HOOK_SOCKET as usize - 1] = Some(ts);
+        self.matched += 1;
+        for (i, &(_, from, to)) in STAGES.iter().enumerate() {
+            if let (Some(a), Some(b)) = (times[from as usize - 1], times[to as usize - 1])
+                && b >= a
+            {
+                self.samples[i].push(b - a);
+            }
+        }
+    }
+
+    pub fn report(&self, scale: Scale) -> PacketReport {
+        let stages = STAGES
+            .iter()
+            .zip(&self.samples)
+            .filter(|(_, s)| !s.is_empty())
+            .map(|(&(stage, _, _), samples)| {
+                let mut sorted = samples.clone();
+                sorted.sort_unstable();
+                let sum: u128 = sorted.iter().map(|&v| v as u128).sum();
+                PacketStage {
+                    stage,
+                    count: sorted.len() as u64,
+                    avg: scale.ns(sum as f64 / sorted.len() as f64),
+                    p50: scale.ns(percentile(&sorted, 50.0) as f64),
+                    p99: scale.ns(percentile(&sorted, 99.0) as f64),
+                    max: scale.ns(sorted[sorted.len() - 1] as f64),
+                }
+            })
+            .collect();
+        PacketReport {
+            xdp: self.seen[HOOK_XDP as usize - 1],
+            tc_ingress: self.seen[HOOK_TC_INGRESS as usize - 1],
+            socket: self.seen[HOOK_SOCKET as usize - 1],
+            matched: self.matched,
+            evicted: self.evicted,
+            non_monotonic: self.non_monotonic,
+            stages,
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Two-stage consumer: a drain thread empties the ring, the main thread
+//! aggregates.
+//!
+//! With one thread doing both, every aggregation stall (a sample vector
+//! growing, a symbol lookup, a forward that blocks, a `--watch` refresh)
+//! stops the ring from being drained, and at high event rates that is when
+//! it overflows. The drain thread does nothing but pop entries into batches
+//! and pass them on through a lock-free single-producer single-consumer
+//! queue ([`crate::spsc`]); the aggregator hands emptied batches back
+//! through a second one, so in steady state nothing is allocated.
+//!
+//! If the aggregator falls behind by more than [`QUEUE_BATCHES`], the drain
+//! thread keeps growing its current batch rather than waiting for room: the
+//! backlog moves to the heap instead of overflowing the ring.
+
+use crate::spsc::{self, Receiver, Sender};
+use rt::{HiResConn, log_entry_t};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Entries per batch before it is passed on.
+pub const BATCH_ENTRIES: usize = 4096;
+/// Batches in flight between the threads (a power of two).
+pub const QUEUE_BATCHES: usize = 64;
+
+pub type Batch = Vec<log_entry_t>;
+
+/// The drain thread's side.
+pub struct Drainer {
+    full: Sender<Batch>,
+    empty: Receiver<Batch>,
+    stop: Arc<AtomicBool>,
+}
+
+/// The aggregator's side. Dropping it ends the drain thread.
+pub struct Batches {
+    full: Receiver<Batch>,
+    empty: Sender<Batch>,
+    stop: Arc<AtomicBool>,
+}
+
+pub fn pipeline() -> (Drainer, Batches) {
+    let (full_tx, full_rx) = spsc::queue(QUEUE_BATCHES);
+    // Batches beyond what fits here are freed rather than recycled.
+    let (empty_tx, empty_rx) = spsc::queue(QUEUE_BATCHES * 2);
+    let stop = Arc::new(AtomicBool::new(false));
+    (
+        Drainer {
+            full: full_tx,
+            empty: empty_rx,
+            stop: stop.clone(),
+        },
+        Batches {
+            full: full_rx,
+            empty: empty_tx,
+            stop,
+        },
+    )
+}
+
+impl Drainer {
+    /// Pops entries into batches until the aggregator is gone, or until
+    /// [`Batches::stop`] and the ring is empty (then passes on what it has).
+    /// Sleeps `idle` whenever the ring is empty; 0 spins.
+    pub fn run(mut self, conn: &HiResConn, idle: Duration) {
+        let mut batch = self.fresh_batch();
+        // Entries still to take after the stop: what the ring can hold, so
+        // producers that keep logging cannot hold the drain thread forever.
+        let mut after_stop: Option<u64> = None;
+        loop {
+            if self.full.is_closed() {
+                return;
+            }
+            if after_stop.is_none() && self.stop.load(Ordering::Acquire) {
+                after_stop = Some(conn.get_rb_capacity());
+            }
+            if after_stop == Some(0) {
+                break;
+            }
+            match conn.pop() {
+                Some(entry) => {
+                    batch.push(entry);
+                    if let Some(left) = &mut after_stop {
+                        *left -= 1;
+                    }
+                    // Past a full batch, retry once per batch's worth.
+                    if batch.len().is_multiple_of(BATCH_ENTRIES) {
+                        batch = self.pass_on(batch);
+                    }
+                }
+                None if after_stop.is_some() => break,
+                None => {
+                    if !batch.is_empty() {
+                        batch = self.pass_on(batch);
+                    }
+                    if idle.is_zero() {
+                        std::hint::spin_loop();
+                    } else {
+                        thread::sleep(idle);
+                    }
+                }
+            }
+        }
+        // The aggregator is still consuming until `full` closes.
+        while !batch.is_empty() && !self.full.is_closed() {
+            batch = self.pass_on(batch);
+            thread::yield_now();
+        }
+    }
+
+    /// Queues `batch` and returns a fresh one, or returns `batch` to keep
+    /// filling if the queue is full.
+    fn pass_on(&mut self, batch: Batch) -> Batch {
+        match self.full.push(batch) {
+            Ok(()) => self.fresh_batch(),
+            Err(batch) => batch,
+        }
+    }
+
+    fn fresh_batch(&mut self) -> Batch {
+        self.empty
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(BATCH_ENTRIES))
+    }
+}
+
+impl Batches {
+    /// The next batch, if the drain thread has passed one on.
+    pub fn next(&mut self) -> Option<Batch> {
+        self.full.pop()
+    }
+
+    /// Hands an emptied batch back for reuse.
+    pub fn recycle(&mut self, mut batch: Batch) {
+        batch.clear();
+        // Batches grown past the usual size are not worth keeping.
+        if batch.capacity() <= BATCH_ENTRIES {
+            let _ = self.empty.push(batch);
+        }
+    }
+
+    /// Asks the drain thread to empty the ring, pass on what it has taken
+    /// and exit.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Release);
+    }
+
+    /// Whether the drain thread has exited and every batch was consumed.
+    pub fn is_finished(&self) -> bool {
+        self.full.is_finished()
+    }
+}
+
+#[cfg(all(test, feature = "stub"))]
+mod tests {
+    use super::*;
+
+    /// The stub ring is shared by the whole process; this is the only test
+    /// that uses it.
+    #[test]
+    fn stop_drains_what_is_left_in_the_ring() {
+        const N: u64 = 3 * BATCH_ENTRIES as u64 + 5;
+        let conn = HiResConn::connect(None).unwrap();
+        while conn.pop().is_some() {}
+        for i in 0..N {
+            assert!(conn.log(1, i, 0));
+        }
+        let (drainer, mut batches) = pipeline();
+        // Stopped before it starts: it still takes all N.
+        batches.stop();
+        thread::scope(|scope| {
+            scope.spawn(|| drainer.run(&conn, Duration::ZERO));
+            let mut seen = 0;
+            while !batches.is_finished() {
+                let Some(batch) = batches.next() else {
+                    thread::yield_now();
+                    continue;
+                };
+                for entry in &batch {
+                    assert_eq!(entry.data1, seen);
+                    seen += 1;
+                }
+                batches.recycle(batch);
+            }
+            assert_eq!(seen, N);
+        });
+        assert!(conn.pop().is_none());
+    }
+}
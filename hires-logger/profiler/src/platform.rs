@@ -0,0 +1,186 @@
+//! Detection of the (confidential) VM environment a capture is taken in.
+//!
+//! Timing behaviour differs a lot between bare metal, a plain VM and TDX or
+//! SEV-SNP guests (TSC virtualization, #VE/#VC exits on some instructions),
+//! so every report records where it came from.
+
+use serde::Serialize;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{__cpuid, __cpuid_count};
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize)]
+pub struct Environment {
+    /// `Intel TDX`, `AMD SEV-SNP`, `AMD SEV-ES`, `AMD SEV`, `Arm CCA`, `VM` or
+    /// `bare metal`.
+    pub platform: String,
+    /// Hypervisor vendor signature from CPUID leaf 0x40000000, if any.
+    pub hypervisor: Option<String>,
+    pub vcpus: usize,
+    pub kernel: String,
+    /// Current kernel clocksource (e.g. `tsc`, `kvm-clock`).
+    pub clocksource: Option<String>,
+    /// Calibrated TSC frequency used for all conversions.
+    pub tsc_hz: u64,
+    /// How `tsc_hz` was obtained.
+    pub tsc_source: String,
+    /// khires timing-loop measurement, even when `tsc_hz` came from elsewhere.
+    pub calibrated_tsc_hz: Option<u64>,
+    /// Guest-visible frequency on SEV-SNP SecureTSC guests.
+    pub secure_tsc_hz: Option<u64>,
+    /// Nominal TSC frequency reported by CPUID leaf 0x15, if enumerated.
+    pub cpuid_tsc_hz: Option<u64>,
+    /// Disagreement between the timing loop and the platform-reported
+    /// frequency (SecureTSC, else CPUID), in parts per million.
+    pub tsc_mismatch_ppm: Option<f64>,
+    /// TSC-related CPU flags from /proc/cpuinfo.
+    pub tsc_flags: Vec<String>,
+}
+
+const TSC_FLAGS: &[&str] = &["constant_tsc", "nonstop_tsc", "tsc_known_freq", "tsc_reliable"];
+
+/// Calibration disagreement above which a capture is flagged. Every latency
+/// is scaled by the TSC frequency, so this is also the error bound on them.
+pub const TSC_MISMATCH_PPM: f64 = 1000.0;
+
+impl Environment {
+    pub fn detect(
+        tsc_hz: u64,
+        tsc_source: &str,
+        calibrated_tsc_hz: Option<u64>,
+        secure_tsc_hz: Option<u64>,
+    ) -> Self {
+        let cpu_flags = cpuinfo_flags();
+        let has_flag = |f: &str| cpu_flags.iter().any(|c| c == f);
+        let hypervisor = hypervisor_vendor();
+
+        let platform = if is_tdx_guest() || has_flag("tdx_guest") || Path::new("/dev/tdx_guest").exists()
+        {
+            "Intel TDX"
+        } else if has_flag("sev_snp") || Path::new("/dev/sev-guest").exists() {
+            "AMD SEV-SNP"
+        } else if has_flag("sev_es") {
+            "AMD SEV-ES"
+        } else if has_flag("sev") {
+            "AMD SEV"
+        } else if is_cca_realm() {
+            "Arm CCA"
+        } else if hypervisor.is_some() {
+            "VM"
+        } else {
+            "bare metal"
+        };
+
+        let cpuid_tsc_hz = cpuid_tsc_hz();
+        let tsc_mismatch_ppm = calibrated_tsc_hz
+            .zip(secure_tsc_hz.or(cpuid_tsc_hz))
+            .filter(|&(_, reported)| reported != 0)
+            .map(|(calibrated, reported)| {
+                (calibrated as f64 - reported as f64).abs() / reported as f64 * 1e6
+            });
+
+        Environment {
+            platform: platform.to_string(),
+            hypervisor,
+            vcpus: std::thread::available_parallelism().map_or(0, |n| n.get()),
+            kernel: read_trimmed("/proc/sys/kernel/osrelease").unwrap_or_default(),
+            clocksource: read_trimmed("/sys/devices/system/clocksource/clocksource0/current_clocksource"),
+            tsc_hz,
+            tsc_source: tsc_source.to_string(),
+            calibrated_tsc_hz,
+            secure_tsc_hz,
+            cpuid_tsc_hz,
+            tsc_mismatch_ppm,
+            tsc_flags: TSC_FLAGS
+                .iter()
+                .filter(|f| has_flag(f))
+                .map(|f| f.to_string())
+                .collect(),
+        }
+    }
+
+    /// Whether the TSC calibration disagrees with the platform-reported
+    /// frequency by more than `TSC_MISMATCH_PPM`.
+    pub fn tsc_mismatch(&self) -> bool {
+        self.tsc_mismatch_ppm.is_some_and(|ppm| ppm > TSC_MISMATCH_PPM)
+    }
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+fn cpuinfo_flags() -> Vec<String> {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+    cpuinfo
+        .lines()
+        .find(|l| l.starts_with("flags"))
+        .and_then(|l| l.split_once(':'))
+        .map(|(_, flags)| flags.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+fn signature(regs: [u32; 3]) -> String {
+    let bytes: Vec<u8> = regs.iter().flat_map(|r| r.to_le_bytes()).collect();
+    String::from_utf8_lossy(&bytes)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string()
+}
+
+#[cfg(target_arch = "x86_64")]
+fn hypervisor_vendor() -> Option<String> {
+    // CPUID.1:ECX bit 31 is set by every hypervisor.
+    if __cpuid(1).ecx & (1 << 31) == 0 {
+        return None;
+    }
+    let leaf = __cpuid(0x4000_0000);
+    Some(signature([leaf.ebx, leaf.ecx, leaf.edx]))
+}
+
+/// TD guests see the `IntelTDX    ` signature in CPUID leaf 0x21.
+#[cfg(target_arch = "x86_64")]
+fn is_tdx_guest() -> bool {
+    const TDX_LEAF: u32 = 0x21;
+    if __cpuid(0).eax < TDX_LEAF {
+        return false;
+    }
+    let leaf = __cpuid_count(TDX_LEAF, 0);
+    signature([leaf.ebx, leaf.edx, leaf.ecx]) == "IntelTDX"
+}
+
+#[cfg(target_arch = "x86_64")]
+fn cpuid_tsc_hz() -> Option<u64> {
+    if __cpuid(0).eax < 0x15 {
+        return None;
+    }
+    // TSC = crystal (ecx) * ebx / eax; any of them may be 0 if not enumerated.
+    let leaf = __cpuid(0x15);
+    if leaf.eax == 0 || leaf.ebx == 0 || leaf.ecx == 0 {
+        return None;
+    }
+    Some(leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64)
+}
+
+// Without CPUID there is no hypervisor signature or enumerated TSC rate;
+// the flags and device nodes checked in `detect` still apply.
+#[cfg(not(target_arch = "x86_64"))]
+fn hypervisor_vendor() -> Option<String> {
+    None
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn is_tdx_guest() -> bool {
+    false
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn cpuid_tsc_hz() -> Option<u64> {
+    None
+}
+
+/// Realm guests register the `arm-cca-dev` platform device for attestation.
+fn is_cca_realm() -> bool {
+    cfg!(target_arch = "aarch64") && Path::new("/sys/bus/platform/devices/arm-cca-dev").exists()
+}
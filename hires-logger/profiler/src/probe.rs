@@ -0,0 +1,205 @@
+//! UDP round-trip probe (`profiler probe`).
+//!
+//! Sends fixed-rate probes to a UDP echo service and measures each round
+//! trip on the TSC, exercising the full guest network stack. Every send and
+//! echo is also logged through the hires ring (`send_event` with the
+//! sequence number in `data2`, `recv_event` with the RTT in cycles in
+//! `data1`), so a profiler consumer running alongside sees the probes on
+//! the same timeline as kernel and virtio events. `--listen` runs the echo
+//! side.
+
+use crate::stats::percentile;
+use crate::units::{Scale, Unit, read_tsc};
+use clap::Args;
+use rt::HiResConn;
+use std::collections::HashSet;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Sequence number and send TSC.
+const HEADER_BYTES: usize = 16;
+
+#[derive(Args, Debug)]
+pub struct ProbeArgs {
+    /// Echo service to probe, host:port
+    #[arg(long, conflicts_with = "listen")]
+    udp: Option<SocketAddr>,
+
+    /// Run as the echo service on this address instead, e.g. 0.0.0.0:7777
+    #[arg(long)]
+    listen: Option<SocketAddr>,
+
+    /// Probes per second
+    #[arg(long, default_value_t = 1000)]
+    rate: u64,
+
+    /// Number of probes to send (0 = until Ctrl-C)
+    #[arg(long, default_value_t = 10_000)]
+    count: u64,
+
+    /// Probe payload size in bytes (at least 16)
+    #[arg(long, default_value_t = 64)]
+    size: usize,
+
+    /// How long to wait for outstanding echoes after the last probe, in ms
+    #[arg(long, default_value_t = 1000)]
+    timeout_ms: u64,
+
+    /// Event logged when a probe is sent
+    #[arg(long, default_value_t = 1)]
+    send_event: u32,
+
+    /// Event logged when its echo arrives
+    #[arg(long, default_value_t = 2)]
+    recv_event: u32,
+
+    /// Output units for the RTT distribution
+    #[arg(long, value_enum, default_value_t = Unit::Us)]
+    units: Unit,
+}
+
+pub fn run_probe(device: &str, args: &ProbeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })?;
+
+    if let Some(addr) = args.listen {
+        return echo(addr, &running);
+    }
+    let Some(target) = args.udp else {
+        return Err("probe needs --udp host:port or --listen addr".into());
+    };
+    if args.rate == 0 {
+        return Err("--rate must be positive".into());
+    }
+
+    let connection = HiResConn::connect(Some(device.as_ref()))?;
+    let scale = Scale::new(args.units, connection.get_tsc_hz());
+    let socket = UdpSocket::bind(if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })?;
+    socket.connect(target)?;
+    socket.set_nonblocking(true)?;
+
+    let interval = Duration::from_nanos(1_000_000_000 / args.rate);
+    let mut packet = vec![0u8; args.size.max(HEADER_BYTES)];
+    let mut buf = vec![0u8; packet.len() + 64];
+    let mut rtts: Vec<u64> = Vec::new();
+    let mut outstanding: HashSet<u64> = HashSet::new();
+    let mut sent: u64 = 0;
+    let mut duplicates: u64 = 0;
+
+    println!(
+        "Probing {} at {}/s with {} B payloads",
+        target,
+        args.rate,
+        packet.len()
+    );
+    let started = Instant::now();
+    let mut next_send = started;
+    let mut deadline: Option<Instant> = None;
+    loop {
+        let now = Instant::now();
+        let sending = running.load(Ordering::Relaxed) && (args.count == 0 || sent < args.count);
+        if sending && now >= next_send {
+            let t1 = read_tsc();
+            packet[..8].copy_from_slice(&sent.to_le_bytes());
+            packet[8..16].copy_from_slice(&t1.to_le_bytes());
+            match socket.send(&packet) {
+                Ok(_) => {
+                    connection.log(args.send_event, 0, sent);
+                    outstanding.insert(sent);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.into()),
+            }
+            sent += 1;
+            next_send += interval;
+        } else if !sending {
+            let d = *deadline.get_or_insert(now + Duration::from_millis(args.timeout_ms));
+            if outstanding.is_empty() || now >= d {
+                break;
+            }
+        }
+
+        match socket.recv(&mut buf) {
+            Ok(n) if n >= HEADER_BYTES => {
+                let t4 = read_tsc();
+                let seq = u64::from_le_bytes(buf[..8].try_into().unwrap());
+                let t1 = u64::from_le_bytes(buf[8..16].try_into().unwrap());
+                if outstanding.remove(&seq) {
+                    let rtt = t4.saturating_sub(t1);
+                    connection.log(args.recv_event, rtt, seq);
+                    rtts.push(rtt);
+                } else {
+                    duplicates += 1;
+                }
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::hint::spin_loop(),
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    rtts.sort_unstable();
+    println!("---- RTT probe ----");
+    println!(
+        "Sent: {}, Received: {}, Lost: {}, Duplicates: {}, Duration: {:.2} s",
+        sent,
+        rtts.len(),
+        outstanding.len(),
+        duplicates,
+        started.elapsed().as_secs_f64()
+    );
+    if !rtts.is_empty() {
+        let sum: u128 = rtts.iter().map(|&v| v as u128).sum();
+        println!(
+            "RTT ({}): Min: {}, Average: {}, p50: {}, p90: {}, p99: {}, p99.9: {}, Max: {}",
+            scale.label(),
+            scale.cycles(rtts[0] as f64),
+            scale.cycles(sum as f64 / rtts.len() as f64),
+            scale.cycles(percentile(&rtts, 50.0) as f64),
+            scale.cycles(percentile(&rtts, 90.0) as f64),
+            scale.cycles(percentile(&rtts, 99.0) as f64),
+            scale.cycles(percentile(&rtts, 99.9) as f64),
+            scale.cycles(rtts[rtts.len() - 1] as f64)
+        );
+    }
+    println!();
+    Ok(())
+}
+
+/// Echoes every datagram back to its sender until Ctrl-C.
+fn echo(addr: SocketAddr, running: &AtomicBool) -> Result<(), Box<dyn std::error::Error>> {
+    let socket = UdpSocket::bind(addr)?;
+    socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+    println!("Echoing UDP on {}", addr);
+    let mut buf = vec![0u8; 65536];
+    let mut echoed: u64 = 0;
+    while running.load(Ordering::Relaxed) {
+        match socket.recv_from(&mut buf) {
+            Ok((n, peer)) => {
+                socket.send_to(&buf[..n], peer)?;
+                echoed += 1;
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    println!("Datagrams echoed: {}", echoed);
+    Ok(())
+}
@@ -0,0 +1,80 @@
+//! Which invocation produced a report.
+//!
+//! Recorded automatically next to [`crate::platform::Environment`], so a
+//! results file found long after the run still names the command line,
+//! host, container and tool versions behind it.
+
+use serde::Serialize;
+use std::ffi::CStr;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub cmdline: Vec<String>,
+    /// Wall-clock start of the run, in seconds since the Unix epoch.
+    pub started_unix_s: u64,
+    pub uts: Uts,
+    /// The cgroup v2 path (`0::` line of /proc/self/cgroup), else the first
+    /// v1 hierarchy listed; identifies the container or systemd unit.
+    pub cgroup: Option<String>,
+    /// Version of the `rt` crate this profiler was built against.
+    pub rt_version: &'static str,
+    pub profiler_version: &'static str,
+}
+
+/// `uname(2)`.
+#[derive(Serialize, Default)]
+pub struct Uts {
+    pub sysname: String,
+    pub nodename: String,
+    pub release: String,
+    pub version: String,
+    pub machine: String,
+}
+
+impl ProcessInfo {
+    pub fn current() -> Self {
+        ProcessInfo {
+            pid: std::process::id(),
+            cmdline: std::env::args().collect(),
+            started_unix_s: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            uts: uname(),
+            cgroup: cgroup(),
+            rt_version: rt::VERSION,
+            profiler_version: env!("CARGO_PKG_VERSION"),
+        }
+    }
+}
+
+fn uname() -> Uts {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return Uts::default();
+    }
+    let field = |f: &[libc::c_char]| {
+        unsafe { CStr::from_ptr(f.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    Uts {
+        sysname: field(&uts.sysname),
+        nodename: field(&uts.nodename),
+        release: field(&uts.release),
+        version: field(&uts.version),
+        machine: field(&uts.machine),
+    }
+}
+
+fn cgroup() -> Option<String> {
+    let content = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let path = |line: &str| line.splitn(3, ':').nth(2).map(str::to_string);
+    content
+        .lines()
+        .find(|l| l.starts_with("0::"))
+        .or_else(|| content.lines().next())
+        .and_then(path)
+}
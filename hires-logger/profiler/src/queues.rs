@@ -0,0 +1,162 @@
+//! Per-queue (RSS) breakdown of queue-tagged events.
+//!
+//! The virtio-net kick and interrupt events carry their virtqueue index,
+//! decoded into RX/TX queue pairs (virtio-net uses vq 2i for RX and 2i + 1
+//! for TX); events named with `--queue-events` carry a queue index in bits
+//! 32-47 of `data2` (`rt::net::queue_data2`, the DPDK burst layout). Each
+//! queue's latency and the CPUs that handled it are reported, along with a
+//! per-event imbalance ratio, so uneven RSS spreading or IRQ affinity inside
+//! the guest shows up directly.
+
+use crate::info;
+use crate::stats::{Sampled, percentile};
+use crate::units::Scale;
+use rt::{HIRES_EV_VNET_INTERRUPT, HIRES_EV_VNET_KICK, log_entry_t};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Latencies kept per queue for percentiles, 512 KiB each.
+const MAX_QUEUE_SAMPLES: usize = 1 << 16;
+
+#[derive(Serialize)]
+pub struct QueueResult {
+    pub event_id: u32,
+    /// `rx<N>`/`tx<N>` for virtqueues, `q<N>` otherwise.
+    pub queue: String,
+    pub count: u64,
+    /// Fraction of the event's samples on this queue.
+    pub share: f64,
+    pub avg: f64,
+    pub p99: f64,
+    pub max: f64,
+    /// Samples per CPU that logged them.
+    pub cpus: BTreeMap<u32, u64>,
+}
+
+#[derive(Serialize)]
+pub struct QueueImbalance {
+    pub event_id: u32,
+    pub queues: usize,
+    /// Busiest queue's count over the mean count; 1.0 is perfectly even.
+    pub ratio: f64,
+}
+
+#[derive(Serialize)]
+pub struct QueueReport {
+    pub queues: Vec<QueueResult>,
+    pub imbalance: Vec<QueueImbalance>,
+}
+
+#[derive(Default)]
+struct QueueStats {
+    samples: Sampled<MAX_QUEUE_SAMPLES>,
+    cpus: BTreeMap<u32, u64>,
+}
+
+/// (event, label prefix, queue index); the prefix is `rx`, `tx` or `q`.
+type QueueKey = (u32, &'static str, u64);
+
+pub struct QueueBreakdown {
+    events: Vec<u32>,
+    stats: BTreeMap<QueueKey, QueueStats>,
+}
+
+impl QueueBreakdown {
+    pub fn new(events: Vec<u32>) -> Self {
+        QueueBreakdown {
+            events,
+            stats: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stats.is_empty()
+    }
+
+    pub fn record(&mut self, entry: &log_entry_t) {
+        let (prefix, queue) = match entry.event_id {
+            HIRES_EV_VNET_KICK | HIRES_EV_VNET_INTERRUPT => {
+                let vq = entry.data2;
+                (if vq.is_multiple_of(2) { "rx" } else { "tx" }, vq / 2)
+            }
+            id if self.events.contains(&id) => ("q", rt::net::queue_of(entry.data2) as u64),
+            _ => return,
+        };
+        let stats = self
+            .stats
+            .entry((entry.event_id, prefix, queue))
+            .or_default();
+        stats.samples.add(entry.data1);
+        *stats.cpus.entry(entry.cpu_id).or_default() += 1;
+    }
+
+    pub fn report(&self, scale: Scale) -> QueueReport {
+        let mut totals: BTreeMap<u32, (u64, u64, usize)> = BTreeMap::new();
+        for ((event_id, _, _), s) in &self.stats {
+            let t = totals.entry(*event_id).or_default();
+            let n = s.samples.count;
+            t.0 += n;
+            t.1 = t.1.max(n);
+            t.2 += 1;
+        }
+
+        let queues = self
+            .stats
+            .iter()
+            .map(|((event_id, prefix, queue), s)| {
+                let sorted = s.samples.sorted();
+                let count = s.samples.count;
+                QueueResult {
+                    event_id: *event_id,
+                    queue: format!("{}{}", prefix, queue),
+                    count,
+                    share: count as f64 / totals[event_id].0 as f64,
+                    avg: scale.cycles(s.samples.mean()),
+                    p99: scale.cycles(percentile(&sorted, 99.0) as f64),
+                    max: scale.cycles(s.samples.max as f64),
+                    cpus: s.cpus.clone(),
+                }
+            })
+            .collect();
+
+        let imbalance = totals
+            .into_iter()
+            .map(|(event_id, (total, busiest, queues))| QueueImbalance {
+                event_id,
+                queues,
+                ratio: busiest as f64 / (total as f64 / queues as f64),
+            })
+            .collect();
+
+        QueueReport { queues, imbalance }
+    }
+}
+
+pub fn print_report(r: &QueueReport, scale: Scale) {
+    info!("---- Per-queue ({}) ----", scale.label());
+    for q in &r.queues {
+        let cpus: Vec<String> = q
+            .cpus
+            .iter()
+            .map(|(cpu, n)| format!("{}: {}", cpu, n))
+            .collect();
+        info!(
+            "Event ID: {}, Queue: {}, Count: {} ({:.1}%), Average: {}, p99: {}, Max: {}, CPUs: {}",
+            q.event_id,
+            q.queue,
+            q.count,
+            q.share * 100.0,
+            q.avg,
+            q.p99,
+            q.max,
+            cpus.join(", ")
+        );
+    }
+    for i in &r.imbalance {
+        info!(
+            "Event ID: {}, Queues: {}, Imbalance (busiest/mean): {:.2}",
+            i.event_id, i.queues, i.ratio
+        );
+    }
+    info!();
+}
@@ -0,0 +1,266 @@
+//! Machine-readable run reports (JSON, CSV and JUnit XML).
+
+use crate::EventResult;
+use crate::anomaly::Anomaly;
+use crate::assertions::AssertionResult;
+use crate::clockcheck::ClockCheckReport;
+use crate::clocksync::ClockModel;
+use crate::correlate::CorrelationMatrix;
+use crate::decoder::DecodedHit;
+use crate::faults::FaultReport;
+use crate::gaps::GapReport;
+use crate::groups::GroupResult;
+use crate::hwts::HwReport;
+use crate::join::JoinReport;
+use crate::loss::LossReport;
+use crate::packets::PacketReport;
+use crate::platform::Environment;
+use crate::process::ProcessInfo;
+use crate::queues::QueueReport;
+use crate::slo::SloResult;
+use crate::stats::{Cdf, Histogram};
+use crate::spans::{CriticalPathReport, StageBreakdown};
+use crate::stacks::StackSummary;
+use crate::swiotlb::SwiotlbOp;
+use crate::symbols::SymbolHit;
+use crate::threads::ThreadResult;
+use crate::timeline::SeriesPoint;
+use crate::tls::TlsOp;
+use crate::units::Unit;
+use crate::virtio::VirtioReport;
+use crate::vmexits::VmExitReport;
+use crate::wakeup::WakeupReport;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize)]
+pub struct Report<'a> {
+    pub device: &'a str,
+    pub environment: &'a Environment,
+    pub process: &'a ProcessInfo,
+    /// Wall-clock length of the capture, in seconds, from the connection's
+    /// session anchor to the end of the run.
+    pub duration_s: f64,
+    /// TSC and wall clock (ns since the Unix epoch) read together at
+    /// connect, for placing TSC timestamps in wall time.
+    pub session_start_tsc: u64,
+    pub session_start_unix_ns: u64,
+    pub cycles_per_us: u64,
+    /// Unit of every latency value in the report.
+    pub units: Unit,
+    pub entries_processed: u64,
+    pub entries_dropped: u64,
+    pub entries_filtered: u64,
+    /// Entries discarded by `--torn-checks`; `None` without it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entries_torn: Option<u64>,
+    pub bucket_ms: u64,
+    pub events: &'a [EventResult],
+    /// The `--relative-to` event that `relative` in `events` compares with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relative_to: Option<u32>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub groups: &'a [GroupResult],
+    pub series: &'a [SeriesPoint],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub histograms: &'a [Histogram],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub cdfs: &'a [Cdf],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation: Option<&'a CorrelationMatrix>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anomalies: Option<&'a [Anomaly]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gaps: Option<&'a GapReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threads: Option<&'a [ThreadResult]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queues: Option<&'a QueueReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kernel_symbols: Option<&'a [SymbolHit]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_symbols: Option<&'a [SymbolHit]>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub stacks: &'a [StackSummary],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hw_timestamps: Option<&'a HwReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packets: Option<&'a PacketReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub virtio: Option<&'a VirtioReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swiotlb: Option<&'a [SwiotlbOp]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<&'a [TlsOp]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vm_exits: Option<&'a VmExitReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loss: Option<&'a LossReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub faults: Option<&'a FaultReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wakeup: Option<&'a WakeupReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub join: Option<&'a JoinReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_sync: Option<&'a ClockModel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_check: Option<&'a ClockCheckReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spans: Option<&'a [StageBreakdown]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub critical_path: Option<&'a [CriticalPathReport]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decoded: Option<&'a [DecodedHit]>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub assertions: &'a [AssertionResult],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub slos: &'a [SloResult],
+}
+
+/// The first `n` items of each event in a slice already grouped by event ID.
+pub fn top_per_event<T>(items: &[T], n: usize, event_id: impl Fn(&T) -> u32) -> Vec<&T> {
+    let mut taken = 0;
+    let mut last_event = None;
+    items
+        .iter()
+        .filter(|item| {
+            let id = event_id(item);
+            if last_event != Some(id) {
+                last_event = Some(id);
+                taken = 0;
+            }
+            taken += 1;
+            taken <= n
+        })
+        .collect()
+}
+
+/// `-` in place of a report path: write the report to stdout.
+pub const STDOUT_PATH: &str = "-";
+
+pub fn is_stdout(path: &Path) -> bool {
+    path.as_os_str() == STDOUT_PATH
+}
+
+/// Opens a report destination: the file at `path`, or stdout for `-`.
+pub fn create(path: &Path) -> std::io::Result<BufWriter<Box<dyn Write>>> {
+    let out: Box<dyn Write> = if is_stdout(path) {
+        Box::new(std::io::stdout().lock())
+    } else {
+        Box::new(File::create(path)?)
+    };
+    Ok(BufWriter::new(out))
+}
+
+pub fn write_json(path: &Path, report: &Report) -> std::io::Result<()> {
+    let mut writer = create(path)?;
+    serde_json::to_writer_pretty(&mut writer, report)?;
+    writeln!(writer)?;
+    writer.flush()
+}
+
+/// Writes the per-event summary to `path` and the time series next to it as
+/// `<stem>_series.csv`. Returns the path of the series file, `None` for
+/// `-`: on stdout only the summary is written.
+pub fn write_csv(path: &Path, report: &Report) -> std::io::Result<Option<PathBuf>> {
+    let mut writer = create(path)?;
+    let unit = report.units.label();
+    writeln!(
+        writer,
+        "event_id,count,avg_{},rate_per_s,duty_cycle,name,share,relative",
+        unit
+    )?;
+    for e in report.events {
+        let duty = e.duty_cycle.map_or(String::new(), |d| d.to_string());
+        let relative = e.relative.map_or(String::new(), |r| r.to_string());
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{}",
+            e.id,
+            e.count,
+            e.avg,
+            e.rate_per_s,
+            duty,
+            e.name.as_deref().unwrap_or(""),
+            e.share,
+            relative
+        )?;
+    }
+    writer.flush()?;
+    if is_stdout(path) {
+        return Ok(None);
+    }
+
+    let series_path = series_csv_path(path);
+    let mut writer = BufWriter::new(File::create(&series_path)?);
+    writeln!(
+        writer,
+        "event_id,bucket_start_ms,count,avg_{0},min_{0},max_{0}",
+        unit
+    )?;
+    for p in report.series {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{}",
+            p.event_id, p.bucket_start_ms, p.count, p.avg, p.min, p.max
+        )?;
+    }
+    writer.flush()?;
+    Ok(Some(series_path))
+}
+
+/// Writes the assertion results as a JUnit test suite, one test case per
+/// assertion, so CI systems show them as ordinary pass/fail tests.
+pub fn write_junit(path: &Path, results: &[AssertionResult]) -> std::io::Result<()> {
+    let failures = results.iter().filter(|r| !r.passed).count();
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<testsuites tests="{}" failures="{}">"#,
+        results.len(),
+        failures
+    )?;
+    writeln!(
+        writer,
+        r#"  <testsuite name="hires-profiler" tests="{}" failures="{}">"#,
+        results.len(),
+        failures
+    )?;
+    for r in results {
+        let classname = format!("event.{}", r.event_id);
+        let name = xml_escape(&r.check);
+        if r.passed {
+            writeln!(writer, r#"    <testcase classname="{}" name="{}"/>"#, classname, name)?;
+            continue;
+        }
+        let message = match r.actual {
+            Some(actual) => format!("actual value {}", actual),
+            None => "no samples recorded".to_string(),
+        };
+        writeln!(writer, r#"    <testcase classname="{}" name="{}">"#, classname, name)?;
+        writeln!(writer, r#"      <failure message="{}"/>"#, xml_escape(&message))?;
+        writeln!(writer, "    </testcase>")?;
+    }
+    writeln!(writer, "  </testsuite>")?;
+    writeln!(writer, "</testsuites>")?;
+    writer.flush()
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn series_csv_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "report".to_string());
+    path.with_file_name(format!("{}_series.csv", stem))
+}
@@ -0,0 +1,265 @@
+//! Per-event latency objectives (`--slo`).
+//!
+//! An objective has the form `event=<event_id> <op> <value><unit>`, e.g.
+//! `event=12 < 50us`: samples of event 12 (`data1`) should stay below 50us.
+//! Operators are `<` and `<=`; units are `cycles`, `ns`, `us`, `ms` and `s`.
+//! Unlike `--assert`, which checks one statistic at the end, every sample is
+//! counted against the objective, so the share of samples violating it is
+//! known live (with `--watch`) as well as in the final summary.
+
+use serde::Serialize;
+
+/// A parsed `--slo` specification.
+#[derive(Clone, Debug)]
+pub struct Slo {
+    event_id: u32,
+    /// `<=` rather than `<`.
+    inclusive: bool,
+    value: f64,
+    unit: ThresholdUnit,
+    /// The objective without the event, e.g. `< 50us`.
+    objective: String,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ThresholdUnit {
+    Cycles,
+    /// Nanoseconds per unit.
+    Time(f64),
+}
+
+/// Units, longest suffix first so `ms` is not read as `s`.
+const UNITS: &[(&str, ThresholdUnit)] = &[
+    ("cycles", ThresholdUnit::Cycles),
+    ("ns", ThresholdUnit::Time(1.0)),
+    ("us", ThresholdUnit::Time(1e3)),
+    ("ms", ThresholdUnit::Time(1e6)),
+    ("s", ThresholdUnit::Time(1e9)),
+];
+
+impl Slo {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let spec: String = input.chars().filter(|c| !c.is_whitespace()).collect();
+        let rest = spec.strip_prefix("event=").ok_or_else(|| {
+            format!(
+                "expected event=<event_id> <op> <value><unit>, got '{}'",
+                input
+            )
+        })?;
+        let op_pos = rest
+            .find('<')
+            .ok_or_else(|| format!("expected '<' or '<=' in '{}'", input))?;
+        let (event, rest) = rest.split_at(op_pos);
+        let event_id = event
+            .parse()
+            .map_err(|_| format!("invalid event ID '{}'", event))?;
+        let (inclusive, threshold) = match rest.strip_prefix("<=") {
+            Some(threshold) => (true, threshold),
+            None => (false, &rest[1..]),
+        };
+        let (suffix, unit) = UNITS
+            .iter()
+            .find(|(suffix, _)| threshold.ends_with(suffix))
+            .ok_or_else(|| {
+                format!(
+                    "threshold '{}' needs a unit (cycles, ns, us, ms or s)",
+                    threshold
+                )
+            })?;
+        let number = &threshold[..threshold.len() - suffix.len()];
+        let value: f64 = number
+            .parse()
+            .map_err(|_| format!("invalid threshold '{}'", number))?;
+        if !value.is_finite() || value < 0.0 {
+            return Err(format!(
+                "threshold '{}' must be a non-negative number",
+                number
+            ));
+        }
+        Ok(Slo {
+            event_id,
+            inclusive,
+            value,
+            unit: *unit,
+            objective: format!(
+                "{} {}{}",
+                if inclusive { "<=" } else { "<" },
+                number,
+                suffix
+            ),
+        })
+    }
+}
+
+/// The largest whole cycle count meeting `< threshold` (`<= threshold` if
+/// `inclusive`), or `None` if not even 0 does. Samples are whole cycles:
+/// `< 2.5` admits up to 2, and so does `<= 2.5`, but `< 3` only up to 2 and
+/// `<= 3` up to 3.
+fn limit(threshold: f64, inclusive: bool) -> Option<u64> {
+    if inclusive {
+        (threshold >= 0.0).then(|| threshold.floor() as u64)
+    } else {
+        (threshold > 0.0).then(|| (threshold.ceil() - 1.0) as u64)
+    }
+}
+
+#[derive(Serialize)]
+pub struct SloResult {
+    pub event_id: u32,
+    pub objective: String,
+    pub samples: u64,
+    pub violations: u64,
+    /// `violations / samples`; 0 without samples.
+    pub violation_fraction: f64,
+}
+
+struct Tracked {
+    slo: Slo,
+    /// Largest sample, in TSC cycles, that meets the objective; `None` if
+    /// none can.
+    limit: Option<u64>,
+    samples: u64,
+    violations: u64,
+}
+
+pub struct SloTracker {
+    tracked: Vec<Tracked>,
+}
+
+impl SloTracker {
+    /// Fails if an objective is in time units and the TSC is not calibrated.
+    pub fn new(slos: &[Slo], tsc_hz: u64) -> Result<Self, String> {
+        let tracked = slos
+            .iter()
+            .map(|slo| {
+                let threshold = match slo.unit {
+                    ThresholdUnit::Cycles => slo.value,
+                    ThresholdUnit::Time(_) if tsc_hz == 0 => {
+                        return Err(format!(
+                            "--slo 'event={} {}' needs a calibrated TSC; give the threshold in cycles",
+                            slo.event_id, slo.objective
+                        ));
+                    }
+                    ThresholdUnit::Time(ns_per_unit) => {
+                        slo.value * ns_per_unit * tsc_hz as f64 / 1e9
+                    }
+                };
+                Ok(Tracked {
+                    slo: slo.clone(),
+                    limit: limit(threshold, slo.inclusive),
+                    samples: 0,
+                    violations: 0,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(SloTracker { tracked })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracked.is_empty()
+    }
+
+    /// Counts samples (`data1`, in cycles) of one event against its
+    /// objectives.
+    pub fn record_run(&mut self, event_id: u32, cycles: &[u64]) {
+        for t in self
+            .tracked
+            .iter_mut()
+            .filter(|t| t.slo.event_id == event_id)
+        {
+            t.samples += cycles.len() as u64;
+            t.violations += match t.limit {
+                Some(limit) => cycles.iter().map(|&v| (v > limit) as u64).sum::<u64>(),
+                None => cycles.len() as u64,
+            };
+        }
+    }
+
+    pub fn results(&self) -> Vec<SloResult> {
+        self.tracked
+            .iter()
+            .map(|t| SloResult {
+                event_id: t.slo.event_id,
+                objective: t.slo.objective.clone(),
+                samples: t.samples,
+                violations: t.violations,
+                violation_fraction: if t.samples > 0 {
+                    t.violations as f64 / t.samples as f64
+                } else {
+                    0.0
+                },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_at_integer_thresholds() {
+        assert_eq!(limit(3.0, false), Some(2));
+        assert_eq!(limit(3.0, true), Some(3));
+        assert_eq!(limit(1.0, false), Some(0));
+    }
+
+    #[test]
+    fn limits_at_fractional_thresholds() {
+        assert_eq!(limit(2.5, false), Some(2));
+        assert_eq!(limit(2.5, true), Some(2));
+        assert_eq!(limit(0.5, false), Some(0));
+        assert_eq!(limit(0.5, true), Some(0));
+    }
+
+    #[test]
+    fn limits_at_zero() {
+        assert_eq!(limit(0.0, false), None);
+        assert_eq!(limit(0.0, true), Some(0));
+    }
+
+    #[test]
+    fn nothing_meets_less_than_zero() {
+        let slo = Slo::parse("event=1 < 0cycles").unwrap();
+        let mut tracker = SloTracker::new(&[slo], 0).unwrap();
+        tracker.record_run(1, &[0, 0, 5]);
+        let result = &tracker.results()[0];
+        assert_eq!((result.samples, result.violations), (3, 3));
+    }
+
+    #[test]
+    fn counts_violations_in_time_units() {
+        // 1 GHz: 1 cycle per ns, so `< 50ns` admits up to 49 cycles.
+        let slo = Slo::parse("event=12 < 50ns").unwrap();
+        let mut tracker = SloTracker::new(&[slo], 1_000_000_000).unwrap();
+        tracker.record_run(12, &[10, 49, 50, 51]);
+        tracker.record_run(13, &[1_000]);
+        let result = &tracker.results()[0];
+        assert_eq!(result.objective, "< 50ns");
+        assert_eq!((result.samples, result.violations), (4, 2));
+        assert_eq!(result.violation_fraction, 0.5);
+    }
+
+    #[test]
+    fn parse_rejects_bad_thresholds() {
+        for spec in [
+            "event=1 < -5us",
+            "event=1 < NaNus",
+            "event=1 < infus",
+            "event=1 < us",
+            "event=1 < 5",
+            "event=1 > 5us",
+            "event=x < 5us",
+            "1 < 5us",
+        ] {
+            assert!(Slo::parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn time_thresholds_need_a_calibrated_tsc() {
+        let slo = Slo::parse("event=1 <= 2.5us").unwrap();
+        assert!(SloTracker::new(std::slice::from_ref(&slo), 0).is_err());
+        assert!(SloTracker::new(&[slo], 2_000_000_000).is_ok());
+    }
+}
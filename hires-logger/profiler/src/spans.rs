@@ -0,0 +1,440 @@
+//! Span tree reconstruction from the correlation IDs in `data2`.
+//!
+//! See `rt::span` for the producer-side convention. Spans are kept until the
+//! end of the run and then linked into trees; each tree is one request, and
+//! its children are the stages that request went through. Stages are
+//! identified by their event ID path from the root (e.g. `3/5`), so the same
+//! stage nested under different parents is kept apart.
+//!
+//! Span IDs are only 32 bits, so a long run may see one again. Each sighting
+//! starts a new generation of that ID, and a child is linked to the
+//! generation of its parent that ends first at or after it.
+
+use crate::units::Scale;
+use rt::span::unpack_span_ids;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+pub struct Span {
+    pub event_id: u32,
+    pub parent_id: u32,
+    /// Duration from `data1`, in cycles.
+    pub duration: u64,
+    /// Normalized log timestamp, which producers take when the span ends.
+    pub end_ns: u64,
+}
+
+#[derive(Serialize)]
+pub struct StageStat {
+    pub path: String,
+    pub count: u64,
+    pub avg: f64,
+    /// Fraction of the summed root duration spent in this stage.
+    pub share: f64,
+}
+
+#[derive(Serialize)]
+pub struct StageBreakdown {
+    pub root_event: u32,
+    pub trees: u64,
+    pub root_avg: f64,
+    /// Stages ordered by their mean end time relative to the root.
+    pub stages: Vec<StageStat>,
+}
+
+#[derive(Serialize)]
+pub struct CriticalStage {
+    /// Stage path as in the breakdown; `self` is time the root spent outside
+    /// any child on the critical path.
+    pub path: String,
+    /// Requests in which this stage was the largest contributor.
+    pub dominant: u64,
+    pub dominant_share: f64,
+    /// Mean time this stage spent on the critical path per request.
+    pub avg_on_path: f64,
+}
+
+#[derive(Serialize)]
+pub struct CriticalPathReport {
+    pub root_event: u32,
+    pub trees: u64,
+    /// Stages ranked by how often they dominate the critical path.
+    pub stages: Vec<CriticalStage>,
+}
+
+/// Spans kept before new ones are ignored; each takes about 40 bytes.
+const MAX_SPANS: usize = 1 << 21;
+
+/// A span ID and its generation.
+pub type SpanKey = (u32, u32);
+
+#[derive(Default)]
+pub struct SpanStore {
+    spans: HashMap<SpanKey, Span>,
+    /// Generations seen per span ID.
+    generations: HashMap<u32, u32>,
+    reused: u64,
+    dropped: u64,
+}
+
+/// Linked view over a `SpanStore`.
+pub struct SpanForest<'a> {
+    pub spans: &'a HashMap<SpanKey, Span>,
+    pub children: HashMap<SpanKey, Vec<SpanKey>>,
+    /// Spans without a parent, or whose parent was never seen.
+    pub roots: Vec<SpanKey>,
+}
+
+impl SpanStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, event_id: u32, duration: u64, data2: u64, end_ns: u64) {
+        let (span_id, parent_id) = unpack_span_ids(data2);
+        if span_id == 0 {
+            return;
+        }
+        if self.spans.len() >= MAX_SPANS {
+            self.dropped += 1;
+            return;
+        }
+        let generation = self.generations.entry(span_id).or_default();
+        if *generation > 0 {
+            self.reused += 1;
+        }
+        self.spans.insert(
+            (span_id, *generation),
+            Span {
+                event_id,
+                parent_id,
+                duration,
+                end_ns,
+            },
+        );
+        *generation += 1;
+    }
+
+    /// Spans whose ID had already been seen.
+    pub fn reused(&self) -> u64 {
+        self.reused
+    }
+
+    /// Spans ignored because the store was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn forest(&self) -> SpanForest<'_> {
+        // (end_ns, generation) of every reused ID, ordered by end time.
+        let mut ends: HashMap<u32, Vec<(u64, u32)>> = HashMap::new();
+        for (&(id, generation), span) in &self.spans {
+            if self.generations[&id] > 1 {
+                ends.entry(id).or_default().push((span.end_ns, generation));
+            }
+        }
+        for gens in ends.values_mut() {
+            gens.sort_unstable();
+        }
+
+        let mut children: HashMap<SpanKey, Vec<SpanKey>> = HashMap::new();
+        let mut roots = Vec::new();
+        for (&key, span) in &self.spans {
+            let parent = match ends.get(&span.parent_id) {
+                _ if span.parent_id == 0 => None,
+                // The first generation ending at or after the child, else the last.
+                Some(gens) => {
+                    let i = gens.partition_point(|&(end, _)| end < span.end_ns);
+                    Some((span.parent_id, gens[i.min(gens.len() - 1)].1))
+                }
+                None => Some((span.parent_id, 0)).filter(|p| self.spans.contains_key(p)),
+            };
+            match parent {
+                Some(parent) => children.entry(parent).or_default().push(key),
+                None => roots.push(key),
+            }
+        }
+        roots.sort_unstable();
+        SpanForest {
+            spans: &self.spans,
+            children,
+            roots,
+        }
+    }
+}
+
+impl SpanForest<'_> {
+    /// Roots that have at least one child, i.e. actual request trees.
+    pub fn trees(&self) -> impl Iterator<Item = SpanKey> + '_ {
+        self.roots
+            .iter()
+            .copied()
+            .filter(|id| self.children.contains_key(id))
+    }
+
+    /// Aggregates the stages of every tree, grouped by root event.
+    pub fn breakdown(&self, scale: Scale) -> Vec<StageBreakdown> {
+        #[derive(Default)]
+        struct StageAcc {
+            count: u64,
+            sum: u128,
+            end_offset_sum: i128,
+        }
+        #[derive(Default)]
+        struct RootAcc {
+            trees: u64,
+            sum: u128,
+            stages: BTreeMap<String, StageAcc>,
+        }
+
+        let mut per_root: BTreeMap<u32, RootAcc> = BTreeMap::new();
+        for root_id in self.trees() {
+            let root = &self.spans[&root_id];
+            let acc = per_root.entry(root.event_id).or_default();
+            acc.trees += 1;
+            acc.sum += root.duration as u128;
+
+            let mut stack: Vec<(SpanKey, String)> = vec![(root_id, String::new())];
+            while let Some((id, prefix)) = stack.pop() {
+                for &child_id in self.children.get(&id).into_iter().flatten() {
+                    let child = &self.spans[&child_id];
+                    let path = if prefix.is_empty() {
+                        child.event_id.to_string()
+                    } else {
+                        format!("{}/{}", prefix, child.event_id)
+                    };
+                    let stage = acc.stages.entry(path.clone()).or_default();
+                    stage.count += 1;
+                    stage.sum += child.duration as u128;
+                    stage.end_offset_sum += child.end_ns as i128 - root.end_ns as i128;
+                    stack.push((child_id, path));
+                }
+            }
+        }
+
+        per_root
+            .into_iter()
+            .map(|(root_event, acc)| {
+                let mut stages: Vec<(f64, StageStat)> = acc
+                    .stages
+                    .into_iter()
+                    .map(|(path, s)| {
+                        let mean_end = s.end_offset_sum as f64 / s.count as f64;
+                        let stat = StageStat {
+                            path,
+                            count: s.count,
+                            avg: scale.cycles(s.sum as f64 / s.count as f64),
+                            share: if acc.sum > 0 {
+                                s.sum as f64 / acc.sum as f64
+                            } else {
+                                0.0
+                            },
+                        };
+                        (mean_end, stat)
+                    })
+                    .collect();
+                stages.sort_by(|a, b| a.0.total_cmp(&b.0));
+                StageBreakdown {
+                    root_event,
+                    trees: acc.trees,
+                    root_avg: scale.cycles(acc.sum as f64 / acc.trees as f64),
+                    stages: stages.into_iter().map(|(_, s)| s).collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// Computes each request's critical path and ranks, per root event, the
+    /// stages by how often they contribute most to it.
+    ///
+    /// A span covers `[end - duration, end]`. Walking back from a span's end,
+    /// the child finishing last is on the critical path; the walk then
+    /// continues from that child's start, so children overlapping it (i.e.
+    /// running in parallel) are skipped. Time not covered by a chosen child
+    /// is the span's own contribution.
+    pub fn critical_paths(&self, scale: Scale) -> Vec<CriticalPathReport> {
+        #[derive(Default)]
+        struct RootAcc {
+            trees: u64,
+            dominant: HashMap<String, u64>,
+            on_path: HashMap<String, u128>,
+        }
+
+        let to_ns = |cycles: u64| scale.cycles_to_ns(cycles);
+
+        let mut per_root: BTreeMap<u32, RootAcc> = BTreeMap::new();
+        for root_id in self.trees() {
+            let mut contrib: HashMap<String, u64> = HashMap::new();
+            self.walk_critical(root_id, "self".to_string(), String::new(), &to_ns, &mut contrib);
+
+            let acc = per_root.entry(self.spans[&root_id].event_id).or_default();
+            acc.trees += 1;
+            if let Some((path, _)) = contrib.iter().max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0))) {
+                *acc.dominant.entry(path.clone()).or_default() += 1;
+            }
+            for (path, ns) in contrib {
+                *acc.on_path.entry(path).or_default() += ns as u128;
+            }
+        }
+
+        per_root
+            .into_iter()
+            .map(|(root_event, acc)| {
+                let mut stages: Vec<CriticalStage> = acc
+                    .on_path
+                    .iter()
+                    .map(|(path, &ns)| {
+                        let dominant = acc.dominant.get(path).copied().unwrap_or(0);
+                        CriticalStage {
+                            path: path.clone(),
+                            dominant,
+                            dominant_share: dominant as f64 / acc.trees as f64,
+                            avg_on_path: scale.ns(ns as f64 / acc.trees as f64),
+                        }
+                    })
+                    .collect();
+                stages.sort_by(|a, b| {
+                    b.dominant
+                        .cmp(&a.dominant)
+                        .then(b.avg_on_path.total_cmp(&a.avg_on_path))
+                });
+                CriticalPathReport {
+                    root_event,
+                    trees: acc.trees,
+                    stages,
+                }
+            })
+            .collect()
+    }
+
+    /// Adds the critical-path contribution of span `id` (labelled `label`)
+    /// and of its critical descendants, whose paths extend `prefix`.
+    fn walk_critical(
+        &self,
+        id: SpanKey,
+        label: String,
+        prefix: String,
+        to_ns: &impl Fn(u64) -> u64,
+        contrib: &mut HashMap<String, u64>,
+    ) {
+        let span = &self.spans[&id];
+        let start = span.end_ns.saturating_sub(to_ns(span.duration));
+
+        let mut children: Vec<SpanKey> = self.children.get(&id).cloned().unwrap_or_default();
+        children.sort_by_key(|c| std::cmp::Reverse(self.spans[c].end_ns));
+
+        let mut cursor = span.end_ns;
+        let mut own = 0;
+        for child_id in children {
+            let child = &self.spans[&child_id];
+            if child.end_ns > cursor || child.end_ns <= start {
+                continue;
+            }
+            own += cursor - child.end_ns;
+            let path = if prefix.is_empty() {
+                child.event_id.to_string()
+            } else {
+                format!("{}/{}", prefix, child.event_id)
+            };
+            self.walk_critical(child_id, path.clone(), path, to_ns, contrib);
+            cursor = child.end_ns.saturating_sub(to_ns(child.duration)).max(start);
+        }
+        own += cursor.saturating_sub(start);
+        *contrib.entry(label).or_default() += own;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Unit;
+    use rt::span::pack_span_ids;
+
+    fn scale() -> Scale {
+        Scale::new(Unit::Cycles, 0)
+    }
+
+    /// Records `(event_id, span_id, parent_id, duration, end_ns)` spans.
+    fn store(spans: &[(u32, u32, u32, u64, u64)]) -> SpanStore {
+        let mut store = SpanStore::new();
+        for &(event_id, id, parent, duration, end_ns) in spans {
+            store.record(event_id, duration, pack_span_ids(id, parent), end_ns);
+        }
+        store
+    }
+
+    fn stages(b: &StageBreakdown) -> Vec<(&str, u64, f64)> {
+        b.stages
+            .iter()
+            .map(|s| (s.path.as_str(), s.count, s.avg))
+            .collect()
+    }
+
+    #[test]
+    fn nested_stages_are_keyed_by_path_and_ordered_by_end() {
+        let spans = store(&[
+            (3, 3, 2, 20, 1040),
+            (2, 2, 1, 60, 1060),
+            (4, 4, 1, 30, 1090),
+            (1, 1, 0, 100, 1100),
+            (3, 13, 12, 60, 2040),
+            (2, 12, 11, 180, 2060),
+            (4, 14, 11, 90, 2090),
+            (1, 11, 0, 300, 2100),
+        ]);
+        let breakdown = spans.forest().breakdown(scale());
+        assert_eq!(breakdown.len(), 1);
+        let b = &breakdown[0];
+        assert_eq!((b.root_event, b.trees, b.root_avg), (1, 2, 200.0));
+        let want = vec![("2/3", 2, 40.0), ("2", 2, 120.0), ("4", 2, 60.0)];
+        assert_eq!(stages(b), want);
+    }
+
+    #[test]
+    fn share_is_of_the_summed_root_duration() {
+        let spans = store(&[
+            (2, 2, 1, 25, 50),
+            (1, 1, 0, 100, 100),
+            (2, 12, 11, 75, 250),
+            (1, 11, 0, 300, 300),
+        ]);
+        assert_eq!(spans.forest().breakdown(scale())[0].stages[0].share, 0.25);
+
+        // Zero-length roots report no share rather than dividing by zero.
+        let spans = store(&[(2, 2, 1, 5, 50), (1, 1, 0, 0, 100)]);
+        assert_eq!(spans.forest().breakdown(scale())[0].stages[0].share, 0.0);
+    }
+
+    #[test]
+    fn orphans_root_their_own_trees() {
+        // Parents 98 and 99 are never seen: 10 roots a tree, 20 stays alone.
+        let spans = store(&[(6, 11, 10, 5, 50), (5, 10, 99, 10, 60), (7, 20, 98, 10, 70)]);
+        let forest = spans.forest();
+        assert_eq!(forest.roots, vec![(10, 0), (20, 0)]);
+        assert_eq!(forest.trees().collect::<Vec<_>>(), vec![(10, 0)]);
+        let breakdown = forest.breakdown(scale());
+        assert_eq!(breakdown.len(), 1);
+        assert_eq!(breakdown[0].root_event, 5);
+        assert_eq!(stages(&breakdown[0]), vec![("6", 1, 5.0)]);
+    }
+
+    #[test]
+    fn reused_ids_start_a_new_generation() {
+        // Spans 1 and 2 are reused by the second request.
+        let spans = store(&[
+            (2, 2, 1, 10, 50),
+            (5, 3, 1, 10, 60),
+            (1, 1, 0, 100, 100),
+            (2, 2, 1, 30, 250),
+            (1, 1, 0, 100, 300),
+        ]);
+        assert_eq!(spans.reused(), 2);
+
+        let forest = spans.forest();
+        assert_eq!(forest.children[&(1, 0)].len(), 2);
+        assert_eq!(forest.children[&(1, 1)], vec![(2, 1)]);
+        let b = &forest.breakdown(scale())[0];
+        assert_eq!(b.trees, 2);
+        assert_eq!(stages(b), vec![("2", 2, 20.0), ("5", 1, 10.0)]);
+    }
+}
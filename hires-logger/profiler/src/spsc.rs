@@ -0,0 +1,200 @@
+//! Bounded lock-free single-producer single-consumer queue.
+//!
+//! Used to hand work between two threads without either one ever blocking
+//! on the other: [`Sender::push`] gives the value back when the queue is
+//! full and [`Receiver::pop`] returns `None` when it is empty, so each side
+//! decides for itself whether to retry, wait or do something else.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// An index on its own cache line, so producer and consumer do not
+/// false-share.
+#[repr(align(64))]
+struct Index(AtomicUsize);
+
+/// Bounded SPSC ring. `head` and `tail` only grow; a slot is
+/// `index & mask`.
+struct Queue<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    mask: usize,
+    /// Next slot to read, written by the consumer only.
+    head: Index,
+    /// Next slot to write, written by the producer only.
+    tail: Index,
+    /// Set when either side is dropped.
+    closed: AtomicBool,
+}
+
+// Slots are only touched by the side that owns them per `head`/`tail`.
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        let tail = *self.tail.0.get_mut();
+        for i in *self.head.0.get_mut()..tail {
+            unsafe { self.slots[i & self.mask].get_mut().assume_init_drop() };
+        }
+    }
+}
+
+pub struct Sender<T>(Arc<Queue<T>>);
+pub struct Receiver<T>(Arc<Queue<T>>);
+
+/// A queue of `capacity` slots, a power of two.
+pub fn queue<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity.is_power_of_two());
+    let q = Arc::new(Queue {
+        slots: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        mask: capacity - 1,
+        head: Index(AtomicUsize::new(0)),
+        tail: Index(AtomicUsize::new(0)),
+        closed: AtomicBool::new(false),
+    });
+    (Sender(q.clone()), Receiver(q))
+}
+
+impl<T> Sender<T> {
+    /// Gives `value` back if the queue is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let q = &*self.0;
+        let tail = q.tail.0.load(Ordering::Relaxed);
+        if tail - q.head.0.load(Ordering::Acquire) > q.mask {
+            return Err(value);
+        }
+        unsafe { (*q.slots[tail & q.mask].get()).write(value) };
+        q.tail.0.store(tail + 1, Ordering::Release);
+        Ok(())
+    }
+
+    /// Whether the receiver is gone.
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Receiver<T> {
+    pub fn pop(&mut self) -> Option<T> {
+        let q = &*self.0;
+        let head = q.head.0.load(Ordering::Relaxed);
+        if head == q.tail.0.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = unsafe { (*q.slots[head & q.mask].get()).assume_init_read() };
+        q.head.0.store(head + 1, Ordering::Release);
+        Some(value)
+    }
+
+    /// Whether the sender is gone and everything it sent has been popped.
+    pub fn is_finished(&self) -> bool {
+        let q = &*self.0;
+        q.closed.load(Ordering::Acquire)
+            && q.head.0.load(Ordering::Relaxed) == q.tail.0.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::Release);
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn full_and_empty_across_wraparound() {
+        let (mut tx, mut rx) = queue(4);
+        let mut next = 0;
+        // Indexes run many times around the four slots.
+        for _ in 0..10 {
+            assert_eq!(rx.pop(), None);
+            for _ in 0..4 {
+                tx.push(next).unwrap();
+                next += 1;
+            }
+            assert_eq!(tx.push(-1), Err(-1));
+            assert_eq!(rx.pop(), Some(next - 4));
+            tx.push(next).unwrap();
+            next += 1;
+            assert_eq!(tx.push(-1), Err(-1));
+            for expected in next - 4..next {
+                assert_eq!(rx.pop(), Some(expected));
+            }
+        }
+        assert_eq!(rx.pop(), None);
+    }
+
+    #[test]
+    fn dropping_the_sender_finishes_the_receiver() {
+        let (mut tx, mut rx) = queue(2);
+        tx.push(1).unwrap();
+        assert!(!rx.is_finished());
+        drop(tx);
+        // Not until what was sent has been popped.
+        assert!(!rx.is_finished());
+        assert_eq!(rx.pop(), Some(1));
+        assert!(rx.is_finished());
+    }
+
+    #[test]
+    fn dropping_the_receiver_closes_the_sender() {
+        let (tx, rx) = queue::<u32>(2);
+        assert!(!tx.is_closed());
+        drop(rx);
+        assert!(tx.is_closed());
+    }
+
+    #[test]
+    fn queued_values_are_dropped_with_the_queue() {
+        let value = Arc::new(());
+        let (mut tx, mut rx) = queue(4);
+        for _ in 0..3 {
+            tx.push(value.clone()).unwrap();
+        }
+        drop(rx.pop());
+        assert_eq!(Arc::strong_count(&value), 3);
+        drop(tx);
+        drop(rx);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn values_cross_threads_once_and_in_order() {
+        const N: u64 = 200_000;
+        let (mut tx, mut rx) = queue(8);
+        let producer = thread::spawn(move || {
+            for i in 0..N {
+                let mut value = i;
+                while let Err(v) = tx.push(value) {
+                    value = v;
+                    thread::yield_now();
+                }
+            }
+        });
+        let mut expected = 0;
+        while !rx.is_finished() {
+            match rx.pop() {
+                Some(value) => {
+                    assert_eq!(value, expected);
+                    expected += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!(expected, N);
+    }
+}
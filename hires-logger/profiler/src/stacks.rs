@@ -0,0 +1,100 @@
+//! Reassembly of stack traces logged across several entries.
+//!
+//! See `rt::stack` for the producer-side convention. Frames are collected per
+//! (event, stack ID) until all of them have arrived; identical stacks are then
+//! counted together so the report shows the most common call paths behind an
+//! event's outliers.
+
+use rt::stack::{STACK_FRAME_FLAG, unpack_frame_tag};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Serialize)]
+pub struct StackSummary {
+    pub event_id: u32,
+    pub kernel: bool,
+    pub count: u64,
+    pub frames: Vec<String>,
+}
+
+struct Partial {
+    frames: Vec<Option<u64>>,
+    received: usize,
+}
+
+#[derive(Default)]
+pub struct StackAssembler {
+    partial: HashMap<(u32, u32), Partial>,
+    /// Completed stacks: (event, kernel, frames) -> occurrences.
+    complete: BTreeMap<(u32, bool, Vec<u64>), u64>,
+}
+
+impl StackAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds one frame entry; `event_id` still carries the frame flag.
+    pub fn record(&mut self, event_id: u32, kernel: bool, ip: u64, tag: u64) {
+        let event_id = event_id & !STACK_FRAME_FLAG;
+        let (stack_id, index, total) = unpack_frame_tag(tag);
+        if total == 0 || index >= total {
+            return;
+        }
+
+        let key = (event_id, stack_id);
+        let partial = self.partial.entry(key).or_insert_with(|| Partial {
+            frames: vec![None; total as usize],
+            received: 0,
+        });
+        let Some(slot) = partial.frames.get_mut(index as usize) else {
+            return;
+        };
+        if slot.replace(ip).is_none() {
+            partial.received += 1;
+        }
+        if partial.received == partial.frames.len() {
+            let partial = self.partial.remove(&key).expect("partial stack present");
+            let frames = partial.frames.into_iter().flatten().collect();
+            *self.complete.entry((event_id, kernel, frames)).or_default() += 1;
+        }
+    }
+
+    /// Stacks still missing frames (dropped or not yet drained).
+    pub fn incomplete(&self) -> usize {
+        self.partial.len()
+    }
+
+    /// Every distinct frame address of completed userspace stacks.
+    pub fn user_addresses(&self) -> Vec<u64> {
+        let mut addrs: Vec<u64> = self
+            .complete
+            .keys()
+            .filter(|(_, kernel, _)| !kernel)
+            .flat_map(|(_, _, frames)| frames.iter().copied())
+            .collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs
+    }
+
+    /// Distinct stacks ordered by event ID and then by descending count, with
+    /// frames rendered by `resolve(kernel, addr)` where possible.
+    pub fn summary(&self, resolve: impl Fn(bool, u64) -> Option<String>) -> Vec<StackSummary> {
+        let mut result: Vec<StackSummary> = self
+            .complete
+            .iter()
+            .map(|((event_id, kernel, frames), &count)| StackSummary {
+                event_id: *event_id,
+                kernel: *kernel,
+                count,
+                frames: frames
+                    .iter()
+                    .map(|&ip| resolve(*kernel, ip).unwrap_or_else(|| format!("0x{:x}", ip)))
+                    .collect(),
+            })
+            .collect();
+        result.sort_by(|a, b| a.event_id.cmp(&b.event_id).then(b.count.cmp(&a.count)));
+        result
+    }
+}
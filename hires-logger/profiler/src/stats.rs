@@ -0,0 +1,196 @@
+//! Order statistics and histograms shared by the report sections.
+
+use crate::units::Scale;
+use serde::Serialize;
+
+/// Nearest-rank percentile of an already sorted slice.
+pub fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Uniform sample of at most `CAP` values out of a stream (reservoir
+/// sampling), for percentiles over more values than are worth keeping.
+#[derive(Clone, Default)]
+pub struct Reservoir<const CAP: usize, T = u64> {
+    seen: u64,
+    values: Vec<T>,
+    /// SplitMix64 state; a fixed seed keeps reports reproducible.
+    rng: u64,
+}
+
+impl<const CAP: usize, T: Copy + Ord> Reservoir<CAP, T> {
+    pub fn add(&mut self, value: T) {
+        self.seen += 1;
+        if self.values.len() < CAP {
+            self.values.push(value);
+            return;
+        }
+        let slot = self.next_random() % self.seen;
+        if let Some(kept) = self.values.get_mut(slot as usize) {
+            *kept = value;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The kept values, sorted for `percentile`.
+    pub fn sorted(&self) -> Vec<T> {
+        let mut sorted = self.values.clone();
+        sorted.sort_unstable();
+        sorted
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Exact count, sum and maximum of a stream of values, plus a `Reservoir`
+/// of at most `CAP` of them for percentiles.
+#[derive(Clone, Default)]
+pub struct Sampled<const CAP: usize> {
+    pub count: u64,
+    pub sum: u128,
+    pub max: u64,
+    sample: Reservoir<CAP>,
+}
+
+impl<const CAP: usize> Sampled<CAP> {
+    pub fn add(&mut self, value: u64) {
+        self.count += 1;
+        self.sum += value as u128;
+        self.max = self.max.max(value);
+        self.sample.add(value);
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.sum as f64 / self.count as f64
+    }
+
+    /// The sampled values, sorted for `percentile`.
+    pub fn sorted(&self) -> Vec<u64> {
+        self.sample.sorted()
+    }
+}
+
+#[derive(Serialize)]
+pub struct HistogramBucket {
+    /// Inclusive lower bound.
+    pub lower: f64,
+    /// Exclusive upper bound.
+    pub upper: f64,
+    pub count: u64,
+}
+
+#[derive(Serialize)]
+pub struct Histogram {
+    pub event_id: u32,
+    /// Power-of-two buckets from the smallest to the largest non-empty one.
+    pub buckets: Vec<HistogramBucket>,
+}
+
+/// Builds a histogram with power-of-two bucket bounds, which keeps both the
+/// fast path and the tail of a latency distribution readable. Buckets are
+/// formed over cycles; only the reported bounds are converted by `scale`.
+pub fn log2_histogram(event_id: u32, samples: &[u64], scale: Scale) -> Histogram {
+    // Bucket 0 holds zeros; bucket i > 0 holds [2^(i-1), 2^i).
+    let mut counts = [0u64; 65];
+    for &v in samples {
+        counts[(u64::BITS - v.leading_zeros()) as usize] += 1;
+    }
+    let bounds = |i: usize| match i {
+        0 => (0, 1),
+        64 => (1 << 63, u64::MAX),
+        _ => (1u64 << (i - 1), 1u64 << i),
+    };
+    let first = counts.iter().position(|&c| c > 0);
+    let last = counts.iter().rposition(|&c| c > 0);
+    let buckets = match (first, last) {
+        (Some(first), Some(last)) => (first..=last)
+            .map(|i| {
+                let (lower, upper) = bounds(i);
+                HistogramBucket {
+                    lower: scale.cycles(lower as f64),
+                    upper: scale.cycles(upper as f64),
+                    count: counts[i],
+                }
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    Histogram { event_id, buckets }
+}
+
+#[derive(Serialize)]
+pub struct Cdf {
+    pub event_id: u32,
+    /// `(value, fraction of samples <= value)` pairs in increasing order.
+    pub points: Vec<(f64, f64)>,
+}
+
+/// Samples the empirical CDF at up to `max_points` evenly spaced ranks, plus
+/// the tail percentiles that even spacing would skip over.
+pub fn cdf(event_id: u32, samples: &[u64], max_points: usize, scale: Scale) -> Cdf {
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+    let n = sorted.len();
+    let mut ranks: Vec<usize> = (1..=max_points.max(1))
+        .map(|i| (i * n).div_ceil(max_points.max(1)))
+        .collect();
+    ranks.extend(
+        [99.0, 99.9, 99.99]
+            .iter()
+            .map(|p: &f64| ((p / 100.0) * n as f64).ceil() as usize),
+    );
+    ranks.retain(|&r| r >= 1 && r <= n);
+    ranks.sort_unstable();
+    ranks.dedup();
+    let points = ranks
+        .into_iter()
+        .map(|r| (scale.cycles(sorted[r - 1] as f64), r as f64 / n as f64))
+        .collect();
+    Cdf { event_id, points }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reservoir_keeps_everything_up_to_its_capacity() {
+        let mut reservoir = Reservoir::<4>::default();
+        for v in [5, 1, 4] {
+            reservoir.add(v);
+        }
+        assert_eq!(reservoir.sorted(), [1, 4, 5]);
+        for v in 10..1000 {
+            reservoir.add(v);
+        }
+        assert_eq!(reservoir.values.len(), 4);
+    }
+
+    #[test]
+    fn sampled_statistics_are_exact_past_the_sample() {
+        let mut sampled = Sampled::<4>::default();
+        assert_eq!(sampled.mean(), 0.0);
+        for v in 1..=100 {
+            sampled.add(v);
+        }
+        assert_eq!((sampled.count, sampled.sum, sampled.max), (100, 5050, 100));
+        assert_eq!(sampled.mean(), 50.5);
+        assert_eq!(sampled.sorted().len(), 4);
+    }
+}
@@ -0,0 +1,144 @@
+//! swiotlb bounce-buffer accounting from the reserved `HIRES_EV_SWIOTLB_*`
+//! events.
+//!
+//! In a CVM every DMA buffer is bounced through shared (decrypted) memory,
+//! so each map and unmap copies the payload once. khires (loaded with
+//! `swiotlb_probes=1`) logs the time spent per call in `data1` and the DMA
+//! direction and size in `data2`.
+
+use crate::info;
+use crate::stats::{Sampled, percentile};
+use crate::units::Scale;
+use rt::{
+    HIRES_EV_SWIOTLB_FIRST, HIRES_EV_SWIOTLB_LAST, HIRES_EV_SWIOTLB_MAP, HIRES_EV_SWIOTLB_UNMAP,
+    HIRES_SWIOTLB_FAILED,
+};
+use serde::Serialize;
+
+/// Call durations kept per op for percentiles, 512 KiB each.
+const MAX_OP_SAMPLES: usize = 1 << 16;
+
+/// `enum dma_data_direction` names, by value.
+const DIRECTIONS: [&str; 4] = ["bidirectional", "to_device", "from_device", "none"];
+
+#[inline]
+pub fn is_swiotlb_event(event_id: u32) -> bool {
+    (HIRES_EV_SWIOTLB_FIRST..=HIRES_EV_SWIOTLB_LAST).contains(&event_id)
+}
+
+#[derive(Serialize)]
+pub struct SwiotlbOp {
+    /// `map` or `unmap`.
+    pub op: &'static str,
+    pub count: u64,
+    /// Maps that found no free slot in the pool.
+    pub failures: u64,
+    pub bytes: u64,
+    pub avg_size: f64,
+    pub avg: f64,
+    pub p99: f64,
+    pub max: f64,
+    /// Calls per DMA direction, in `DIRECTIONS` order.
+    pub by_direction: [u64; 4],
+}
+
+#[derive(Default)]
+struct OpStats {
+    samples: Sampled<MAX_OP_SAMPLES>,
+    failures: u64,
+    bytes: u64,
+    by_direction: [u64; 4],
+}
+
+#[derive(Default)]
+pub struct SwiotlbTracker {
+    map: OpStats,
+    unmap: OpStats,
+}
+
+impl SwiotlbTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.samples.count == 0 && self.unmap.samples.count == 0
+    }
+
+    pub fn record(&mut self, event_id: u32, data1: u64, data2: u64) {
+        let op = match event_id {
+            HIRES_EV_SWIOTLB_MAP => &mut self.map,
+            HIRES_EV_SWIOTLB_UNMAP => &mut self.unmap,
+            _ => return,
+        };
+        op.samples.add(data1);
+        if data2 & HIRES_SWIOTLB_FAILED != 0 {
+            op.failures += 1;
+        } else {
+            op.bytes += data2 & 0xffff_ffff;
+        }
+        let dir = ((data2 >> 32) & 0x7fff_ffff) as usize;
+        if let Some(n) = op.by_direction.get_mut(dir) {
+            *n += 1;
+        }
+    }
+
+    pub fn report(&self, scale: Scale) -> Vec<SwiotlbOp> {
+        [("map", &self.map), ("unmap", &self.unmap)]
+            .into_iter()
+            .filter(|(_, s)| s.samples.count > 0)
+            .map(|(op, s)| {
+                let sorted = s.samples.sorted();
+                let count = s.samples.count;
+                let succeeded = count - s.failures;
+                SwiotlbOp {
+                    op,
+                    count,
+                    failures: s.failures,
+                    bytes: s.bytes,
+                    avg_size: if succeeded > 0 {
+                        s.bytes as f64 / succeeded as f64
+                    } else {
+                        0.0
+                    },
+                    avg: scale.cycles(s.samples.mean()),
+                    p99: scale.cycles(percentile(&sorted, 99.0) as f64),
+                    max: scale.cycles(s.samples.max as f64),
+                    by_direction: s.by_direction,
+                }
+            })
+            .collect()
+    }
+}
+
+pub fn print_report(ops: &[SwiotlbOp], duration_s: f64, scale: Scale) {
+    info!("---- swiotlb bounce buffering ----");
+    for o in ops {
+        info!(
+            "Op: {}, Count: {}, Failures: {}, Bytes: {}, Average size: {:.0} B, Average: {} {}, p99: {}, Max: {}",
+            o.op,
+            o.count,
+            o.failures,
+            o.bytes,
+            o.avg_size,
+            o.avg,
+            scale.label(),
+            o.p99,
+            o.max
+        );
+        let dirs: Vec<String> = DIRECTIONS
+            .iter()
+            .zip(o.by_direction)
+            .filter(|&(_, n)| n > 0)
+            .map(|(d, n)| format!("{}: {}", d, n))
+            .collect();
+        info!("  Directions: {}", dirs.join(", "));
+        if duration_s > 0.0 {
+            info!(
+                "  Bounced: {:.2} MiB/s",
+                o.bytes as f64 / duration_s / (1024.0 * 1024.0)
+            );
+        }
+    }
+    info!();
+}
@@ -0,0 +1,202 @@
+//! Symbolization of pointer-valued payloads.
+//!
+//! Kernel-side producers may log an instruction pointer in `data1` (e.g. the
+//! caller of `hires_log`). Such values are resolved against `/proc/kallsyms`
+//! or a copy of it saved from the machine the capture was taken on.
+//!
+//! Userspace addresses are resolved against a binary with debug info through
+//! binutils' `addr2line`, batched into a single invocation at report time.
+
+use crate::info;
+use crate::report::top_per_event;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Addresses further than this past the last symbol are not attributed to it.
+const MAX_SYMBOL_SPAN: u64 = 1 << 20;
+
+/// Distinct addresses tracked per event before new ones are ignored, so a
+/// field that turns out not to hold pointers cannot grow without bound.
+const MAX_TRACKED_ADDRESSES: usize = 1 << 16;
+
+pub struct KernelSymbols {
+    /// Text symbols sorted by address.
+    symbols: Vec<(u64, String)>,
+}
+
+impl KernelSymbols {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let mut symbols: Vec<(u64, String)> = content
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let addr = u64::from_str_radix(parts.next()?, 16).ok()?;
+                let kind = parts.next()?;
+                let name = parts.next()?;
+                // Only text symbols can be instruction pointers.
+                matches!(kind, "t" | "T" | "w" | "W").then(|| (addr, name.to_string()))
+            })
+            .collect();
+
+        if symbols.iter().all(|(addr, _)| *addr == 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} has no usable addresses (hidden by kptr_restrict?); run as root or pass a saved copy",
+                    path.display()
+                ),
+            ));
+        }
+        symbols.sort_unstable_by_key(|(addr, _)| *addr);
+        Ok(KernelSymbols { symbols })
+    }
+
+    /// Index of the symbol containing `addr` and the offset into it.
+    fn lookup(&self, addr: u64) -> Option<(usize, u64)> {
+        let idx = self.symbols.partition_point(|(a, _)| *a <= addr).checked_sub(1)?;
+        let offset = addr - self.symbols[idx].0;
+        let end = self
+            .symbols
+            .get(idx + 1)
+            .map_or(self.symbols[idx].0 + MAX_SYMBOL_SPAN, |(a, _)| *a);
+        (addr < end).then_some((idx, offset))
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.lookup(addr).is_some()
+    }
+
+    /// Formats `addr` as `symbol+0xoffset`, or `None` if it is not kernel text.
+    pub fn resolve(&self, addr: u64) -> Option<String> {
+        self.lookup(addr)
+            .map(|(idx, offset)| format!("{}+0x{:x}", self.symbols[idx].1, offset))
+    }
+}
+
+pub struct UserSymbols {
+    binary: PathBuf,
+    /// Load address of the binary, subtracted before lookup (for PIE).
+    base: u64,
+}
+
+impl UserSymbols {
+    pub fn new(binary: PathBuf, base: u64) -> Self {
+        UserSymbols { binary, base }
+    }
+
+    /// Resolves all `addrs` with one `addr2line` run. Addresses that do not
+    /// map to a function in the binary are left out of the result.
+    pub fn resolve_all(&self, addrs: &[u64]) -> io::Result<HashMap<u64, String>> {
+        if addrs.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let mut child = Command::new("addr2line")
+            .arg("-f")
+            .arg("-C")
+            .arg("-e")
+            .arg(&self.binary)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        // Feed stdin from a separate thread so a large batch cannot deadlock
+        // against addr2line filling its stdout pipe.
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let input: String = addrs
+            .iter()
+            .map(|a| format!("0x{:x}\n", a.wrapping_sub(self.base)))
+            .collect();
+        let feeder = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+        let output = child.wait_with_output()?;
+        feeder.join().expect("addr2line feeder panicked")?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "addr2line failed on {}",
+                self.binary.display()
+            )));
+        }
+
+        // Two lines per address: function name, then file:line.
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut lines = stdout.lines();
+        let mut resolved = HashMap::new();
+        for &addr in addrs {
+            let (Some(function), Some(location)) = (lines.next(), lines.next()) else {
+                break;
+            };
+            if function != "??" {
+                resolved.insert(addr, format!("{} ({})", function, location));
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+#[derive(Serialize)]
+pub struct SymbolHit {
+    pub event_id: u32,
+    pub symbol: String,
+    pub count: u64,
+}
+
+/// Counts how often each address appears in each event's payload.
+#[derive(Default)]
+pub struct SymbolCounts {
+    counts: BTreeMap<u32, HashMap<u64, u64>>,
+}
+
+impl SymbolCounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, event_id: u32, addr: u64) {
+        let counts = self.counts.entry(event_id).or_default();
+        if let Some(count) = counts.get_mut(&addr) {
+            *count += 1;
+        } else if counts.len() < MAX_TRACKED_ADDRESSES {
+            counts.insert(addr, 1);
+        }
+    }
+
+    /// Every distinct address recorded, across all events.
+    pub fn addresses(&self) -> Vec<u64> {
+        let mut addrs: Vec<u64> = self.counts.values().flat_map(|c| c.keys().copied()).collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs
+    }
+
+    /// Hits ordered by event ID and then by descending count. Addresses for
+    /// which `resolve` returns `None` are skipped.
+    pub fn hits(&self, resolve: impl Fn(u64) -> Option<String>) -> Vec<SymbolHit> {
+        let mut hits = Vec::new();
+        for (&event_id, counts) in &self.counts {
+            let mut per_event: Vec<(u64, u64)> = counts.iter().map(|(&a, &c)| (a, c)).collect();
+            per_event.sort_by_key(|&(addr, count)| (std::cmp::Reverse(count), addr));
+            hits.extend(per_event.into_iter().filter_map(|(addr, count)| {
+                Some(SymbolHit {
+                    event_id,
+                    symbol: resolve(addr)?,
+                    count,
+                })
+            }));
+        }
+        hits
+    }
+}
+
+/// Prints the most frequent hits of each event under `title`.
+pub fn print_hits(title: &str, hits: &[SymbolHit]) {
+    const MAX_PRINTED_SYMBOLS: usize = 10;
+    info!("---- {} ----", title);
+    for h in top_per_event(hits, MAX_PRINTED_SYMBOLS, |h| h.event_id) {
+        info!("Event ID: {}, {}: {}", h.event_id, h.symbol, h.count);
+    }
+    info!();
+}
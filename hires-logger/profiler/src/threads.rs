@@ -0,0 +1,129 @@
+//! Per-thread breakdown of event latencies.
+//!
+//! Samples are grouped by (event ID, thread ID) so a multi-threaded server
+//! can see which of its workers contribute the tail of an event's
+//! distribution.
+//!
+//! Counts, means and maxima are exact. Percentiles come from a uniform
+//! sample of at most `MAX_THREAD_SAMPLES` values per (event, thread), so
+//! memory stays bounded however long the run.
+//!
+//! Threads that register their names (`HIRES_EV_THREAD_NAME`, see
+//! `rt::thread`) are reported by name next to their TID.
+
+use crate::names::Names;
+use crate::stats::{Reservoir, percentile};
+use crate::units::Scale;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+pub struct ThreadResult {
+    pub event_id: u32,
+    pub tid: u64,
+    pub name: Option<String>,
+    pub count: u64,
+    pub avg: f64,
+    pub p50: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+/// Samples kept per (event, thread) for percentiles, 512 KiB each.
+const MAX_THREAD_SAMPLES: usize = 1 << 16;
+
+#[derive(Default)]
+struct ThreadSamples {
+    count: u64,
+    sum: u128,
+    max: u64,
+    reservoir: Reservoir<MAX_THREAD_SAMPLES>,
+}
+
+#[derive(Default)]
+pub struct ThreadBreakdown {
+    samples: BTreeMap<(u32, u64), ThreadSamples>,
+    /// Registered thread names by TID.
+    names: Names,
+}
+
+impl ThreadBreakdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, event_id: u32, tid: u64, value: u64) {
+        let samples = self.samples.entry((event_id, tid)).or_default();
+        samples.count += 1;
+        samples.sum += value as u128;
+        samples.max = samples.max.max(value);
+        samples.reservoir.add(value);
+    }
+
+    /// Records one part of a `HIRES_EV_THREAD_NAME` registration.
+    pub fn record_name(&mut self, data1: u64, data2: u64) {
+        self.names.record(data1, data2);
+    }
+
+    /// Per-thread statistics, ordered by event ID and then by descending p99
+    /// so the worst thread of each event comes first.
+    pub fn summary(&self, scale: Scale) -> Vec<ThreadResult> {
+        let mut result: Vec<ThreadResult> = self
+            .samples
+            .iter()
+            .map(|(&(event_id, tid), data)| {
+                let sorted = data.reservoir.sorted();
+                ThreadResult {
+                    event_id,
+                    tid,
+                    name: u32::try_from(tid)
+                        .ok()
+                        .and_then(|tid| self.names.get(tid))
+                        .map(str::to_string),
+                    count: data.count,
+                    avg: scale.cycles(data.sum as f64 / data.count as f64),
+                    p50: scale.cycles(percentile(&sorted, 50.0) as f64),
+                    p99: scale.cycles(percentile(&sorted, 99.0) as f64),
+                    max: scale.cycles(data.max as f64),
+                }
+            })
+            .collect();
+        result.sort_by(|a, b| a.event_id.cmp(&b.event_id).then(b.p99.total_cmp(&a.p99)));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Unit;
+
+    #[test]
+    fn long_runs_keep_a_bounded_sample() {
+        let mut threads = ThreadBreakdown::new();
+        let n = 4 * MAX_THREAD_SAMPLES as u64;
+        for v in 1..=n {
+            threads.record(3, 100, v);
+        }
+        threads.record(3, 101, 7);
+        assert_eq!(
+            threads.samples[&(3, 100)].reservoir.sorted().len(),
+            MAX_THREAD_SAMPLES
+        );
+
+        let result = threads.summary(Scale::new(Unit::Cycles, 0));
+        assert_eq!(result.len(), 2);
+        let worst = &result[0];
+        assert_eq!((worst.tid, worst.count), (100, n));
+        assert_eq!(worst.avg, (n + 1) as f64 / 2.0);
+        assert_eq!(worst.max, n as f64);
+        // The sample is uniform over the whole run, not its first values.
+        let expected = 0.99 * n as f64;
+        assert!(
+            (worst.p99 - expected).abs() < 0.005 * n as f64,
+            "{}",
+            worst.p99
+        );
+        assert_eq!((result[1].count, result[1].p99), (1, 7.0));
+    }
+}
@@ -0,0 +1,212 @@
+//! Fixed-width time bucketing of event samples.
+//!
+//! Every sample is folded into a per-event bucket keyed by its offset from the
+//! first observed timestamp, so "latency over time" can be reported without
+//! keeping the raw samples around. Per-bucket percentiles need samples, so
+//! they are only kept (up to `MAX_BUCKET_SAMPLES` per bucket) on request.
+
+use crate::stats::{Reservoir, percentile};
+use crate::units::{Scale, cycles_to_ns};
+use rt::{LOG_FLAG_KERNEL, LOG_FLAG_TSC, log_entry_t};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Samples kept per (event, bucket) for percentiles, 8 KiB each.
+const MAX_BUCKET_SAMPLES: usize = 1 << 10;
+/// Buckets per event; samples past the last one (corrupt or misread
+/// timestamps, or a run longer than ~17 minutes of 1 ms buckets) are
+/// counted instead of growing the series.
+const MAX_BUCKETS: usize = 1 << 20;
+
+#[derive(Clone, Copy, Default)]
+pub struct Bucket {
+    pub count: u64,
+    pub sum: u128,
+    pub min: u64,
+    pub max: u64,
+}
+
+impl Bucket {
+    fn add(&mut self, value: u64) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.count += 1;
+        self.sum += value as u128;
+    }
+
+    pub fn avg(&self) -> f64 {
+        if self.count > 0 {
+            return self.sum as f64 / self.count as f64;
+        }
+        0.0
+    }
+}
+
+/// One (event, bucket) row of the latency-over-time series.
+#[derive(Serialize)]
+pub struct SeriesPoint {
+    pub event_id: u32,
+    pub bucket_start_ms: u64,
+    pub count: u64,
+    pub avg: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+pub struct Timeline {
+    bucket_ns: u64,
+    origin_ns: Option<u64>,
+    series: BTreeMap<u32, Vec<Bucket>>,
+    /// Per-bucket samples, parallel to `series`, once `keep_samples` is set.
+    samples: Option<BTreeMap<u32, Vec<Reservoir<MAX_BUCKET_SAMPLES>>>>,
+    out_of_range: u64,
+}
+
+impl Timeline {
+    pub fn new(bucket_ms: u64) -> Self {
+        Timeline {
+            bucket_ns: bucket_ms.max(1) * 1_000_000,
+            origin_ns: None,
+            series: BTreeMap::new(),
+            samples: None,
+            out_of_range: 0,
+        }
+    }
+
+    /// Keeps a sample of each bucket's values for `percentile`.
+    pub fn keep_samples(&mut self) {
+        self.samples.get_or_insert_default();
+    }
+
+    pub fn bucket_ms(&self) -> u64 {
+        self.bucket_ns / 1_000_000
+    }
+
+    /// Timestamp (ns) the series is anchored at, or 0 before any sample.
+    pub fn origin_ns(&self) -> u64 {
+        self.origin_ns.unwrap_or(0)
+    }
+
+    /// Anchors the series at `ts_ns` unless it already has an origin.
+    ///
+    /// The first recorded timestamp becomes the origin of the series. Entries
+    /// from different producers are not strictly ordered, so anything that
+    /// lands before the origin is folded into the first bucket.
+    pub fn anchor(&mut self, ts_ns: u64) {
+        self.origin_ns.get_or_insert(ts_ns);
+    }
+
+    /// Records each of `values` for `event_id` in the bucket covering the
+    /// matching timestamp in `times_ns`, looking the event up once.
+    pub fn record_run(&mut self, event_id: u32, times_ns: &[u64], values: &[u64]) {
+        let Some(&first) = times_ns.first() else {
+            return;
+        };
+        let origin = *self.origin_ns.get_or_insert(first);
+        let buckets = self.series.entry(event_id).or_default();
+        let mut reservoirs = self
+            .samples
+            .as_mut()
+            .map(|samples| samples.entry(event_id).or_default());
+        for (&ts_ns, &value) in times_ns.iter().zip(values) {
+            let idx = ts_ns.saturating_sub(origin) / self.bucket_ns;
+            if idx >= MAX_BUCKETS as u64 {
+                self.out_of_range += 1;
+                continue;
+            }
+            let idx = idx as usize;
+            if buckets.len() <= idx {
+                buckets.resize(idx + 1, Bucket::default());
+            }
+            buckets[idx].add(value);
+            if let Some(reservoirs) = &mut reservoirs {
+                if reservoirs.len() <= idx {
+                    reservoirs.resize_with(idx + 1, Reservoir::default);
+                }
+                reservoirs[idx].add(value);
+            }
+        }
+    }
+
+    /// Samples whose timestamp fell past the last bucket.
+    pub fn out_of_range(&self) -> u64 {
+        self.out_of_range
+    }
+
+    /// The `p`th percentile of bucket `idx` of `event_id`, or `None` if the
+    /// bucket is empty or samples are not kept.
+    pub fn percentile(&self, event_id: u32, idx: usize, p: f64) -> Option<u64> {
+        let reservoir = self.samples.as_ref()?.get(&event_id)?.get(idx)?;
+        (!reservoir.is_empty()).then(|| percentile(&reservoir.sorted(), p))
+    }
+
+    /// Per-event bucket vectors, all indexed from the same origin.
+    pub fn buckets(&self) -> &BTreeMap<u32, Vec<Bucket>> {
+        &self.series
+    }
+
+    /// Flattens the non-empty buckets into rows ordered by event, then time,
+    /// with latencies converted by `scale`.
+    pub fn points(&self, scale: Scale) -> Vec<SeriesPoint> {
+        let bucket_ms = self.bucket_ms();
+        self.series
+            .iter()
+            .flat_map(|(&event_id, buckets)| {
+                buckets
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, b)| b.count > 0)
+                    .map(move |(i, b)| SeriesPoint {
+                        event_id,
+                        bucket_start_ms: i as u64 * bucket_ms,
+                        count: b.count,
+                        avg: scale.cycles(b.avg()),
+                        min: scale.cycles(b.min as f64),
+                        max: scale.cycles(b.max as f64),
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Normalizes an entry's timestamp to nanoseconds.
+///
+/// Userspace producers stamp nanoseconds by default while the kernel module
+/// (and producers using an rdtsc timestamp source) stamp raw TSC cycles, so
+/// those entries are scaled by the calibrated frequency.
+pub fn entry_time_ns(entry: &log_entry_t, tsc_hz: u64) -> u64 {
+    if entry.flags & ((LOG_FLAG_KERNEL | LOG_FLAG_TSC) as u16) != 0 {
+        cycles_to_ns(entry.timestamp, tsc_hz)
+    } else {
+        entry.timestamp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn far_future_timestamps_are_counted_not_bucketed() {
+        let mut timeline = Timeline::new(1);
+        timeline.keep_samples();
+        let last = (MAX_BUCKETS as u64 - 1) * 1_000_000;
+        timeline.record_run(
+            3,
+            &[0, 1_500_000, u64::MAX, last + 1_000_000],
+            &[1, 2, 3, 4],
+        );
+        assert_eq!(timeline.out_of_range(), 2);
+        assert_eq!(timeline.buckets()[&3].len(), 2);
+        assert_eq!(timeline.percentile(3, 1, 50.0), Some(2));
+
+        timeline.record_run(3, &[last], &[5]);
+        assert_eq!(timeline.buckets()[&3].len(), MAX_BUCKETS);
+        assert_eq!(timeline.out_of_range(), 2);
+    }
+}
@@ -0,0 +1,125 @@
+//! TLS record crypto accounting from the reserved `HIRES_EV_TLS` event.
+//!
+//! Entries come from khires' kTLS probes (`tls_probes=1`, flagged
+//! `LOG_FLAG_KERNEL`) and from userspace stacks via `rt::tls`. Reporting
+//! them by side and direction, with the share of a CPU they used, separates
+//! the crypto cost inside the CVM from the transport latency around it.
+
+use crate::info;
+use crate::stats::{Sampled, percentile};
+use crate::units::Scale;
+use rt::{HIRES_TLS_DECRYPT, HIRES_TLS_FAILED, LOG_FLAG_KERNEL, log_entry_t};
+use serde::Serialize;
+
+/// Record durations kept per side and op for percentiles, 512 KiB each.
+const MAX_OP_SAMPLES: usize = 1 << 16;
+
+#[derive(Serialize)]
+pub struct TlsOp {
+    /// `kernel` (kTLS) or `user`.
+    pub side: &'static str,
+    /// `encrypt` or `decrypt`.
+    pub op: &'static str,
+    pub count: u64,
+    pub failures: u64,
+    pub bytes: u64,
+    pub avg: f64,
+    pub p99: f64,
+    pub max: f64,
+    /// Crypto time per KiB of payload.
+    pub per_kib: f64,
+    /// Total crypto time as a fraction of one CPU over the run.
+    pub cpu_share: f64,
+}
+
+#[derive(Default)]
+struct OpStats {
+    samples: Sampled<MAX_OP_SAMPLES>,
+    failures: u64,
+    bytes: u64,
+}
+
+#[derive(Default)]
+pub struct TlsTracker {
+    /// Indexed by `[kernel][decrypt]`.
+    ops: [[OpStats; 2]; 2],
+}
+
+impl TlsTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.iter().flatten().all(|s| s.samples.count == 0)
+    }
+
+    pub fn record(&mut self, entry: &log_entry_t) {
+        let kernel = entry.flags & (LOG_FLAG_KERNEL as u16) != 0;
+        let decrypt = ((entry.data2 >> 32) & 0x7fff_ffff) as u32 == HIRES_TLS_DECRYPT;
+        let op = &mut self.ops[kernel as usize][decrypt as usize];
+        op.samples.add(entry.data1);
+        if entry.data2 & HIRES_TLS_FAILED != 0 {
+            op.failures += 1;
+        } else {
+            op.bytes += entry.data2 & 0xffff_ffff;
+        }
+    }
+
+    pub fn report(&self, duration_s: f64, tsc_hz: u64, scale: Scale) -> Vec<TlsOp> {
+        let mut ops = Vec::new();
+        for (side, kernel) in [("kernel", 1), ("user", 0)] {
+            for (op, decrypt) in [("encrypt", 0), ("decrypt", 1)] {
+                let s = &self.ops[kernel][decrypt];
+                if s.samples.count == 0 {
+                    continue;
+                }
+                let sorted = s.samples.sorted();
+                let sum = s.samples.sum;
+                let count = s.samples.count;
+                ops.push(TlsOp {
+                    side,
+                    op,
+                    count,
+                    failures: s.failures,
+                    bytes: s.bytes,
+                    avg: scale.cycles(sum as f64 / count as f64),
+                    p99: scale.cycles(percentile(&sorted, 99.0) as f64),
+                    max: scale.cycles(s.samples.max as f64),
+                    per_kib: if s.bytes > 0 {
+                        scale.cycles(sum as f64 * 1024.0 / s.bytes as f64)
+                    } else {
+                        0.0
+                    },
+                    cpu_share: if duration_s > 0.0 && tsc_hz > 0 {
+                        sum as f64 / tsc_hz as f64 / duration_s
+                    } else {
+                        0.0
+                    },
+                });
+            }
+        }
+        ops
+    }
+}
+
+pub fn print_report(ops: &[TlsOp], scale: Scale) {
+    info!("---- TLS record crypto ----");
+    for o in ops {
+        info!(
+            "Side: {}, Op: {}, Count: {}, Failures: {}, Bytes: {}, Average: {} {}, p99: {}, Max: {}, Per KiB: {}, CPU: {:.2}%",
+            o.side,
+            o.op,
+            o.count,
+            o.failures,
+            o.bytes,
+            o.avg,
+            scale.label(),
+            o.p99,
+            o.max,
+            o.per_kib,
+            o.cpu_share * 100.0
+        );
+    }
+    info!();
+}
@@ -0,0 +1,365 @@
+//! Live entry stream (`profiler trace`).
+//!
+//! Prints every entry as it is consumed instead of aggregating. With
+//! `--format jsonl` each entry is one JSON object on its own line of stdout,
+//! for piping into jq, vector or a log shipper:
+//!
+//! ```text
+//! {"ts_ns":1718000000123,"event_id":101,"name":"send","cpu":3,"kernel":false,"flags":1,"data1":4210,"data2":1448}
+//! ```
+//!
+//! Name registrations (`HIRES_EV_EVENT_NAME`, `HIRES_EV_THREAD_NAME`) are
+//! consumed rather than printed; entries logged after them carry the names.
+//! Output is flushed whenever the ring runs empty, so lines arrive in real
+//! time without a write per entry. Status messages go to stderr.
+//!
+//! With the top-level `--kallsyms` or `--symbols` (e.g. `profiler --kallsyms
+//! trace`), the address each entry carries is resolved through
+//! [`crate::symbols`] and printed as `symbol+0xoffset` (`"symbol"` in JSON):
+//! `data1` of kernel events against kallsyms, `--symbols-field` of userspace
+//! events against the binary.
+//!
+//! Text output colors kernel and userspace events differently when stdout is
+//! a terminal (see [`crate::color`]); JSON Lines are never colored.
+
+use crate::PayloadField;
+use crate::color::Palette;
+use crate::decoder::Decoder;
+use crate::filter::Filter;
+use crate::names::Names;
+use crate::symbols::{KernelSymbols, UserSymbols};
+use crate::timeline::entry_time_ns;
+use clap::{Args, ValueEnum};
+use rt::{HiResConn, LOG_FLAG_KERNEL, LOG_FLAG_VALID, log_entry_t};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum TraceFormat {
+    /// One aligned line per entry
+    Text,
+    /// One JSON object per entry (JSON Lines)
+    Jsonl,
+}
+
+#[derive(Args, Debug)]
+pub struct TraceArgs {
+    /// Output format
+    #[arg(long, value_enum, default_value_t = TraceFormat::Text)]
+    format: TraceFormat,
+
+    /// Only print entries matching this expression, e.g. 'event_id==5 && data1 > 1000'
+    #[arg(long, value_parser = Filter::parse)]
+    filter: Option<Filter>,
+
+    /// Payload field carrying the producer's thread ID; adds the thread's registered name
+    #[arg(long, value_enum)]
+    tid_field: Option<PayloadField>,
+
+    /// Shared library implementing hires_decode() to add the decoded payload fields
+    #[arg(long)]
+    decoder: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct TraceEntry<'a> {
+    ts_ns: u64,
+    event_id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    cpu: u32,
+    kernel: bool,
+    flags: u16,
+    data1: u64,
+    data2: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    symbol: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<BTreeMap<String, String>>,
+}
+
+/// Userspace addresses looked up before new ones are printed unresolved,
+/// so a field that turns out not to hold pointers cannot run `addr2line`
+/// for every entry.
+const MAX_USER_ADDRESSES: usize = 1 << 12;
+
+/// The symbol tables the trace resolves addresses with.
+pub struct TraceSymbols {
+    kernel: Option<KernelSymbols>,
+    user: Option<UserSymbols>,
+    user_field: PayloadField,
+    /// Userspace lookups so far; each new address is one `addr2line` run.
+    user_cache: HashMap<u64, Option<String>>,
+}
+
+impl TraceSymbols {
+    pub fn new(
+        kernel: Option<KernelSymbols>,
+        user: Option<UserSymbols>,
+        user_field: PayloadField,
+    ) -> Self {
+        TraceSymbols {
+            kernel,
+            user,
+            user_field,
+            user_cache: HashMap::new(),
+        }
+    }
+
+    /// The symbol of the address `entry` carries, if a table resolves it.
+    fn resolve(&mut self, entry: &log_entry_t) -> Option<String> {
+        if entry.flags & (LOG_FLAG_KERNEL as u16) != 0 {
+            return self.kernel.as_ref()?.resolve(entry.data1);
+        }
+        let user = self.user.as_ref()?;
+        let addr = self.user_field.get(entry);
+        if let Some(symbol) = self.user_cache.get(&addr) {
+            return symbol.clone();
+        }
+        if self.user_cache.len() >= MAX_USER_ADDRESSES {
+            return None;
+        }
+        let symbol = match user.resolve_all(&[addr]) {
+            Ok(mut resolved) => resolved.remove(&addr),
+            Err(e) => {
+                eprintln!("Warning: userspace symbols disabled: {}", e);
+                self.user = None;
+                None
+            }
+        };
+        self.user_cache.insert(addr, symbol.clone());
+        symbol
+    }
+}
+
+pub fn run_trace(
+    device: &str,
+    poll_interval_ms: u64,
+    no_color: bool,
+    mut symbols: TraceSymbols,
+    args: &TraceArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })?;
+
+    let decoder = args.decoder.as_deref().map(Decoder::load).transpose()?;
+    let connection = HiResConn::connect(Some(device.as_ref()))?;
+    let tsc_hz = connection.get_tsc_hz();
+    eprintln!(
+        "Tracing {} ({:?} backend), Ctrl+C to stop",
+        device,
+        connection.backend()
+    );
+
+    let color = Palette::detect(no_color, &io::stdout());
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut event_names = Names::default();
+    let mut thread_names = Names::default();
+    let mut printed: u64 = 0;
+
+    let result = (|| -> io::Result<()> {
+        while running.load(Ordering::SeqCst) {
+            let Some(entry) = connection.pop() else {
+                out.flush()?;
+                if let Err(e) = connection.check_seal() {
+                    eprintln!("Stopping: {}", e);
+                    return Ok(());
+                }
+                thread::sleep(Duration::from_millis(poll_interval_ms.max(1)));
+                continue;
+            };
+            if entry.flags & (LOG_FLAG_VALID as u16) == 0 {
+                continue;
+            }
+            match entry.event_id {
+                rt::HIRES_EV_EVENT_NAME => event_names.record(entry.data1, entry.data2),
+                rt::HIRES_EV_THREAD_NAME => thread_names.record(entry.data1, entry.data2),
+                _ => {
+                    if args.filter.as_ref().is_some_and(|f| !f.matches(&entry)) {
+                        continue;
+                    }
+                    let thread = args
+                        .tid_field
+                        .and_then(|field| u32::try_from(field.get(&entry)).ok())
+                        .and_then(|tid| thread_names.get(tid));
+                    let fields = decoder
+                        .as_ref()
+                        .and_then(|d| d.decode(entry.event_id, entry.data1, entry.data2))
+                        .map(|f| f.into_iter().collect());
+                    let symbol = symbols.resolve(&entry);
+                    let line = TraceEntry {
+                        ts_ns: entry_time_ns(&entry, tsc_hz),
+                        event_id: entry.event_id,
+                        name: event_names.get(entry.event_id),
+                        cpu: entry.cpu_id,
+                        kernel: entry.flags & (LOG_FLAG_KERNEL as u16) != 0,
+                        flags: entry.flags,
+                        data1: entry.data1,
+                        data2: entry.data2,
+                        thread,
+                        symbol: symbol.as_deref(),
+                        fields,
+                    };
+                    write_entry(&mut out, args.format, color, &line)?;
+                    printed += 1;
+                }
+            }
+        }
+        out.flush()
+    })();
+
+    match result {
+        // The reader (e.g. `| head`) went away; that ends the trace.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        other => other?,
+    }
+    eprintln!(
+        "Traced {} entries, {} dropped by producers",
+        printed,
+        connection.get_drop_num()
+    );
+    Ok(())
+}
+
+fn write_entry(
+    out: &mut impl Write,
+    format: TraceFormat,
+    color: Palette,
+    entry: &TraceEntry,
+) -> io::Result<()> {
+    match format {
+        TraceFormat::Jsonl => {
+            serde_json::to_writer(&mut *out, entry)?;
+            writeln!(out)
+        }
+        TraceFormat::Text => {
+            let event = match entry.name {
+                Some(name) => format!("{} ({})", entry.event_id, name),
+                None => entry.event_id.to_string(),
+            };
+            write!(
+                out,
+                "{:>20} cpu {:>3} {} data1={} data2={}",
+                entry.ts_ns,
+                entry.cpu,
+                if entry.kernel {
+                    color.kernel(format!("K {:<24}", event))
+                } else {
+                    color.user(format!("U {:<24}", event))
+                },
+                entry.data1,
+                entry.data2
+            )?;
+            if let Some(thread) = entry.thread {
+                write!(out, " thread={}", thread)?;
+            }
+            if let Some(symbol) = entry.symbol {
+                write!(out, " symbol={}", symbol)?;
+            }
+            if let Some(fields) = &entry.fields {
+                for (name, value) in fields {
+                    write!(out, " {}={}", name, value)?;
+                }
+            }
+            writeln!(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A kallsyms file for the test `name`, loaded.
+    fn kernel_symbols(name: &str) -> KernelSymbols {
+        let file = format!("hires-kallsyms-{}-{}", name, std::process::id());
+        let path = std::env::temp_dir().join(file);
+        std::fs::write(
+            &path,
+            "ffffffff81000000 T _stext\nffffffff81001000 t tcp_sendmsg\nffffffff81002000 T tcp_recvmsg\n",
+        )
+        .unwrap();
+        let symbols = KernelSymbols::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        symbols
+    }
+
+    fn entry(kernel: bool, data1: u64) -> log_entry_t {
+        let mut flags = LOG_FLAG_VALID as u16;
+        if kernel {
+            flags |= LOG_FLAG_KERNEL as u16;
+        }
+        log_entry_t {
+            timestamp: 0,
+            event_id: 7,
+            cpu_id: 2,
+            flags,
+            data1,
+            data2: 0,
+        }
+    }
+
+    fn line<'a>(e: &log_entry_t, symbol: Option<&'a str>) -> TraceEntry<'a> {
+        TraceEntry {
+            ts_ns: 1000,
+            event_id: e.event_id,
+            name: None,
+            cpu: e.cpu_id,
+            kernel: e.flags & (LOG_FLAG_KERNEL as u16) != 0,
+            flags: e.flags,
+            data1: e.data1,
+            data2: e.data2,
+            thread: None,
+            symbol,
+            fields: None,
+        }
+    }
+
+    fn format(format: TraceFormat, line: &TraceEntry) -> String {
+        let mut out = Vec::new();
+        let color = Palette::detect(true, &io::stdout());
+        write_entry(&mut out, format, color, line).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn kernel_addresses_resolve_to_symbol_and_offset() {
+        let mut symbols =
+            TraceSymbols::new(Some(kernel_symbols("resolve")), None, PayloadField::Data2);
+        let e = entry(true, 0xffff_ffff_8100_1234);
+        let symbol = symbols.resolve(&e);
+        assert_eq!(symbol.as_deref(), Some("tcp_sendmsg+0x234"));
+
+        let text = format(TraceFormat::Text, &line(&e, symbol.as_deref()));
+        assert!(text.ends_with(" symbol=tcp_sendmsg+0x234\n"), "{}", text);
+        let json = format(TraceFormat::Jsonl, &line(&e, symbol.as_deref()));
+        assert!(json.contains(r#""symbol":"tcp_sendmsg+0x234""#), "{}", json);
+    }
+
+    #[test]
+    fn unresolved_entries_have_no_symbol() {
+        let mut symbols = TraceSymbols::new(
+            Some(kernel_symbols("unresolved")),
+            None,
+            PayloadField::Data2,
+        );
+        // Userspace events are not looked up in kallsyms, and 0 is no text.
+        assert_eq!(symbols.resolve(&entry(false, 0xffff_ffff_8100_1234)), None);
+        assert_eq!(symbols.resolve(&entry(true, 0)), None);
+
+        let e = entry(true, 0);
+        assert!(!format(TraceFormat::Text, &line(&e, None)).contains("symbol="));
+        assert!(!format(TraceFormat::Jsonl, &line(&e, None)).contains("symbol"));
+    }
+}
@@ -0,0 +1,183 @@
+//! Output units for latency values.
+//!
+//! Durations are collected in TSC cycles and only converted when results are
+//! produced, so every report section and output format uses the same unit.
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// Shared with `HiResConn::cycles_to_ns`, so producers and the profiler
+/// convert alike.
+pub use rt::cycles_to_ns;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Unit {
+    Cycles,
+    Ns,
+    Us,
+    Ms,
+}
+
+impl Unit {
+    pub fn label(self) -> &'static str {
+        match self {
+            Unit::Cycles => "cycles",
+            Unit::Ns => "ns",
+            Unit::Us => "us",
+            Unit::Ms => "ms",
+        }
+    }
+}
+
+/// Converts cycle counts to the selected unit using the connection's
+/// calibrated TSC frequency.
+#[derive(Clone, Copy, Debug)]
+pub struct Scale {
+    unit: Unit,
+    tsc_hz: u64,
+}
+
+impl Scale {
+    /// Falls back to cycles if the TSC rate is not calibrated.
+    pub fn new(unit: Unit, tsc_hz: u64) -> Self {
+        if tsc_hz == 0 && unit != Unit::Cycles {
+            eprintln!(
+                "Warning: TSC rate is not calibrated, reporting {} as cycles",
+                unit.label()
+            );
+            return Scale {
+                unit: Unit::Cycles,
+                tsc_hz,
+            };
+        }
+        Scale { unit, tsc_hz }
+    }
+
+    pub fn unit(&self) -> Unit {
+        self.unit
+    }
+
+    pub fn label(&self) -> &'static str {
+        self.unit.label()
+    }
+
+    /// Exact integer conversion of a cycle count to nanoseconds. Without
+    /// calibration the count is passed through unchanged.
+    pub fn cycles_to_ns(&self, cycles: u64) -> u64 {
+        cycles_to_ns(cycles, self.tsc_hz)
+    }
+
+    /// A cycle count in seconds, or `None` without calibration.
+    pub fn seconds(&self, cycles: f64) -> Option<f64> {
+        (self.tsc_hz != 0).then(|| cycles / self.tsc_hz as f64)
+    }
+
+    pub fn cycles(&self, cycles: f64) -> f64 {
+        match self.unit {
+            Unit::Cycles => cycles,
+            _ => self.ns(cycles * 1e9 / self.tsc_hz as f64),
+        }
+    }
+
+    /// Converts a nanosecond value. Without calibration, "nanosecond" values
+    /// derived from cycles are still cycles and are passed through.
+    pub fn ns(&self, ns: f64) -> f64 {
+        match self.unit {
+            Unit::Cycles if self.tsc_hz == 0 => ns,
+            Unit::Cycles => ns * self.tsc_hz as f64 / 1e9,
+            Unit::Ns => ns,
+            Unit::Us => ns / 1e3,
+            Unit::Ms => ns / 1e6,
+        }
+    }
+}
+
+/// Raw TSC of the CPU we run on, for timing done by the profiler itself
+/// (probes, clock sync, host-side arrival stamps). On Arm this is the
+/// generic timer's virtual count, which is what libhires_rt stamps there.
+pub fn read_tsc() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        core::arch::x86_64::_rdtsc()
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        let count: u64;
+        core::arch::asm!("mrs {}, cntvct_el0", out(reg) count, options(nomem, nostack));
+        count
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GHZ_3: u64 = 3_000_000_000;
+
+    #[test]
+    fn cycles_to_ns_is_exact() {
+        assert_eq!(cycles_to_ns(0, GHZ_3), 0);
+        assert_eq!(cycles_to_ns(3, GHZ_3), 1);
+        assert_eq!(cycles_to_ns(2, GHZ_3), 0);
+        assert_eq!(cycles_to_ns(3_000_000_000, GHZ_3), 1_000_000_000);
+        // A non-round rate is not rounded to whole cycles per microsecond.
+        assert_eq!(cycles_to_ns(2_499_999_999, 2_499_999_999), 1_000_000_000);
+    }
+
+    #[test]
+    fn cycles_to_ns_at_extreme_rates() {
+        // Uncalibrated: passed through.
+        assert_eq!(cycles_to_ns(12345, 0), 12345);
+        assert_eq!(cycles_to_ns(u64::MAX, GHZ_3), u64::MAX / 3);
+        assert_eq!(cycles_to_ns(u64::MAX, u64::MAX), 1_000_000_000);
+        assert_eq!(cycles_to_ns(1, u64::MAX), 0);
+        // Below 1 GHz a large count no longer fits in nanoseconds.
+        assert_eq!(cycles_to_ns(u64::MAX, 1), u64::MAX);
+        assert_eq!(cycles_to_ns(u64::MAX, 999_999_999), u64::MAX);
+    }
+
+    #[test]
+    fn scale_converts_to_each_unit() {
+        let cycles = 4_500_000.0;
+        assert_eq!(Scale::new(Unit::Cycles, GHZ_3).cycles(cycles), cycles);
+        assert_eq!(Scale::new(Unit::Ns, GHZ_3).cycles(cycles), 1_500_000.0);
+        assert_eq!(Scale::new(Unit::Us, GHZ_3).cycles(cycles), 1_500.0);
+        assert_eq!(Scale::new(Unit::Ms, GHZ_3).cycles(cycles), 1.5);
+        assert_eq!(Scale::new(Unit::Us, GHZ_3).ns(2_000.0), 2.0);
+        assert_eq!(Scale::new(Unit::Cycles, GHZ_3).ns(1.0), 3.0);
+        assert_eq!(Scale::new(Unit::Us, GHZ_3).seconds(cycles), Some(0.0015));
+        assert_eq!(Scale::new(Unit::Us, GHZ_3).cycles_to_ns(6), 2);
+
+        let fast = Scale::new(Unit::Ns, u64::MAX);
+        assert_eq!(fast.cycles(u64::MAX as f64), 1e9);
+        assert!(fast.cycles(1.0) > 0.0);
+    }
+
+    #[test]
+    fn uncalibrated_scale_reports_cycles() {
+        for unit in [Unit::Cycles, Unit::Ns, Unit::Us, Unit::Ms] {
+            let scale = Scale::new(unit, 0);
+            assert_eq!(scale.unit(), Unit::Cycles);
+            assert_eq!(scale.label(), "cycles");
+            assert_eq!(scale.cycles(1234.0), 1234.0);
+            assert_eq!(scale.ns(1234.0), 1234.0);
+            assert_eq!(scale.seconds(1234.0), None);
+            assert_eq!(scale.cycles_to_ns(1234), 1234);
+        }
+    }
+
+    #[test]
+    fn units_parse_from_their_labels() {
+        for unit in [Unit::Cycles, Unit::Ns, Unit::Us, Unit::Ms] {
+            assert_eq!(Unit::from_str(unit.label(), false), Ok(unit));
+        }
+        assert_eq!(Unit::from_str("US", true), Ok(Unit::Us));
+        assert!(Unit::from_str("US", false).is_err());
+        for bad in ["", "s", "µs", "nanoseconds"] {
+            assert!(Unit::from_str(bad, true).is_err(), "{:?}", bad);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Named virtio-net datapath from the reserved `HIRES_EV_VNET_*` events.
+//!
+//! khires (loaded with `virtio_probes=1`) logs one event per milestone with
+//! the time spent in the hooked function in `data1`. The stages are decoded
+//! into a pipeline in datapath order, with the per-event payloads (virtqueue
+//! index, NAPI work done, skb length) aggregated alongside.
+
+use crate::stats::{Sampled, percentile};
+use crate::units::Scale;
+use rt::{
+    HIRES_EV_VNET_FIRST, HIRES_EV_VNET_INTERRUPT, HIRES_EV_VNET_KICK, HIRES_EV_VNET_LAST,
+    HIRES_EV_VNET_NAPI_POLL, HIRES_EV_VNET_SKB_DELIVER,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Durations kept per stage for percentiles, 512 KiB each.
+const MAX_STAGE_SAMPLES: usize = 1 << 16;
+
+/// Stages in datapath order: (event, name).
+const STAGES: &[(u32, &str)] = &[
+    (HIRES_EV_VNET_KICK, "kick"),
+    (HIRES_EV_VNET_INTERRUPT, "interrupt"),
+    (HIRES_EV_VNET_NAPI_POLL, "napi_poll"),
+    (HIRES_EV_VNET_SKB_DELIVER, "skb_deliver"),
+];
+
+#[inline]
+pub fn is_virtio_event(event_id: u32) -> bool {
+    (HIRES_EV_VNET_FIRST..=HIRES_EV_VNET_LAST).contains(&event_id)
+}
+
+#[derive(Serialize)]
+pub struct VirtioStage {
+    pub event_id: u32,
+    pub name: &'static str,
+    pub count: u64,
+    pub avg: f64,
+    pub p99: f64,
+}
+
+#[derive(Serialize)]
+pub struct VirtioReport {
+    pub stages: Vec<VirtioStage>,
+    /// Average NAPI work done (packets) per poll.
+    pub packets_per_poll: Option<f64>,
+    pub bytes_delivered: u64,
+    /// Kicks (VM exits) per virtqueue index.
+    pub kicks_per_queue: BTreeMap<u64, u64>,
+}
+
+#[derive(Default)]
+pub struct VirtioTracker {
+    /// Durations in cycles per stage, parallel to `STAGES`.
+    samples: Vec<Sampled<MAX_STAGE_SAMPLES>>,
+    polls: u64,
+    poll_work: u64,
+    bytes_delivered: u64,
+    kicks_per_queue: BTreeMap<u64, u64>,
+}
+
+impl VirtioTracker {
+    pub fn new() -> Self {
+        VirtioTracker {
+            samples: vec![Sampled::default(); STAGES.len()],
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.iter().all(|s| s.count == 0)
+    }
+
+    pub fn record(&mut self, event_id: u32, data1: u64, data2: u64) {
+        let Some(stage) = STAGES.iter().position(|&(id, _)| id == event_id) else {
+            return;
+        };
+        self.samples[stage].add(data1);
+        match event_id {
+            HIRES_EV_VNET_KICK => *self.kicks_per_queue.entry(data2).or_default() += 1,
+            HIRES_EV_VNET_NAPI_POLL => {
+                self.polls += 1;
+                self.poll_work += data2 & 0xffff_ffff;
+            }
+            HIRES_EV_VNET_SKB_DELIVER => self.bytes_delivered += data2,
+            _ => {}
+        }
+    }
+
+    pub fn report(&self, scale: Scale) -> VirtioReport {
+        let stages = STAGES
+            .iter()
+            .zip(&self.samples)
+            .filter(|(_, s)| s.count > 0)
+            .map(|(&(event_id, name), samples)| {
+                let sorted = samples.sorted();
+                VirtioStage {
+                    event_id,
+                    name,
+                    count: samples.count,
+                    avg: scale.cycles(samples.mean()),
+                    p99: scale.cycles(percentile(&sorted, 99.0) as f64),
+                }
+            })
+            .collect();
+        VirtioReport {
+            stages,
+            packets_per_poll: (self.polls > 0).then(|| self.poll_work as f64 / self.polls as f64),
+            bytes_delivered: self.bytes_delivered,
+            kicks_per_queue: self.kicks_per_queue.clone(),
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! VM-exit accounting and correlation with latency spikes.
+//!
+//! Exits are the dominant unpredictable cost in CVM networking, so this pass
+//! counts them per timeline bucket and lines the counts up with each event's
+//! latency. Counts come from one of two sources:
+//!
+//! * khires loaded with `exit_probes=1` logs `HIRES_EV_VMEXIT` for every
+//!   #VE/#VC the guest handles, with the exit reason and handler time.
+//! * `--exit-counter PATH` samples a file holding a cumulative exit count
+//!   (e.g. a per-VM KVM stat under `/sys/kernel/debug/kvm` when running on
+//!   the host) once per bucket. Samples are stamped on the TSC, like kernel
+//!   entries. If given, the counter takes precedence over the events.
+//!
+//! A bucket is an exit burst when its count sits more than `threshold`
+//! MADs above the median over the whole run, and a latency spike when the
+//! event's mean latency does, scored the same way as in [`crate::anomaly`].
+
+use crate::anomaly::{MAD_SCALE, median};
+use crate::correlate::pearson;
+use crate::info;
+use crate::stats::{Sampled, percentile};
+use crate::timeline::Timeline;
+use crate::units::{Scale, cycles_to_ns, read_tsc};
+use rt::{HIRES_EV_VMEXIT, HIRES_VMEXIT_SNP_VC, HIRES_VMEXIT_TDX_VE};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Handler times kept per exit reason for percentiles, 512 KiB each.
+const MAX_REASON_SAMPLES: usize = 1 << 16;
+
+/// VMX basic exit reasons seen in #VE.
+const TDX_REASONS: [(u32, &str); 7] = [
+    (10, "cpuid"),
+    (12, "hlt"),
+    (18, "vmcall"),
+    (30, "io"),
+    (31, "rdmsr"),
+    (32, "wrmsr"),
+    (48, "ept_violation"),
+];
+
+/// SVM exit codes seen in #VC.
+const SNP_REASONS: [(u32, &str); 8] = [
+    (0x6e, "rdtsc"),
+    (0x72, "cpuid"),
+    (0x78, "hlt"),
+    (0x7b, "ioio"),
+    (0x7c, "msr"),
+    (0x81, "vmmcall"),
+    (0x87, "rdtscp"),
+    (0x400, "npf"),
+];
+
+#[derive(Serialize)]
+pub struct ExitReason {
+    /// `tdx_ve`, `snp_vc` or `unknown`.
+    pub kind: &'static str,
+    pub reason: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'static str>,
+    pub count: u64,
+    /// Guest handler time, excluding the exit itself.
+    pub avg: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+#[derive(Serialize)]
+pub struct ExitCorrelation {
+    pub event_id: u32,
+    /// Pearson coefficient between exits and mean latency per bucket.
+    pub coefficient: Option<f64>,
+    pub spikes: u64,
+    /// Spike buckets that are also exit bursts.
+    pub spikes_in_bursts: u64,
+    pub avg_in_bursts: Option<f64>,
+    pub avg_outside_bursts: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct VmExitReport {
+    /// `exit_counter` or `exit_probes`.
+    pub source: &'static str,
+    pub total_exits: u64,
+    pub buckets: usize,
+    pub bursts: usize,
+    pub peak_per_bucket: u64,
+    pub reasons: Vec<ExitReason>,
+    pub events: Vec<ExitCorrelation>,
+}
+
+struct ExitCounter {
+    path: PathBuf,
+    interval: Duration,
+    last_sample: Instant,
+    last_value: Option<u64>,
+    /// Sample time (ns) and exits since the previous sample.
+    deltas: Vec<(u64, u64)>,
+}
+
+pub struct VmExitTracker {
+    counter: Option<ExitCounter>,
+    /// data2 (kind and reason) -> handler cycles.
+    reasons: BTreeMap<u64, Sampled<MAX_REASON_SAMPLES>>,
+}
+
+impl VmExitTracker {
+    pub fn new(counter: Option<PathBuf>, bucket_ms: u64) -> Self {
+        VmExitTracker {
+            counter: counter.map(|path| ExitCounter {
+                path,
+                interval: Duration::from_millis(bucket_ms.max(1)),
+                last_sample: Instant::now(),
+                last_value: None,
+                deltas: Vec::new(),
+            }),
+            reasons: BTreeMap::new(),
+        }
+    }
+
+    /// Records a `HIRES_EV_VMEXIT` entry.
+    pub fn record(&mut self, data1: u64, data2: u64) {
+        self.reasons.entry(data2).or_default().add(data1);
+    }
+
+    /// Samples the exit counter if a bucket's worth of time has passed since
+    /// the last sample, or unconditionally with `force`.
+    pub fn poll(&mut self, tsc_hz: u64, force: bool) {
+        let Some(c) = &mut self.counter else {
+            return;
+        };
+        if !force && c.last_value.is_some() && c.last_sample.elapsed() < c.interval {
+            return;
+        }
+        c.last_sample = Instant::now();
+        let Some(value) = fs::read_to_string(&c.path)
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+        else {
+            return;
+        };
+        if let Some(last) = c.last_value {
+            let ts_ns = cycles_to_ns(read_tsc(), tsc_hz);
+            c.deltas.push((ts_ns, value.saturating_sub(last)));
+        }
+        c.last_value = Some(value);
+    }
+
+    fn per_bucket(&self, timeline: &Timeline, len: usize) -> Vec<u64> {
+        let mut exits = vec![0u64; len];
+        match &self.counter {
+            Some(c) => {
+                let bucket_ns = timeline.bucket_ms().max(1) * 1_000_000;
+                for &(ts_ns, n) in &c.deltas {
+                    let idx = (ts_ns.saturating_sub(timeline.origin_ns()) / bucket_ns) as usize;
+                    if let Some(e) = exits.get_mut(idx) {
+                        *e += n;
+                    }
+                }
+            }
+            None => {
+                if let Some(b) = timeline.buckets().get(&HIRES_EV_VMEXIT) {
+                    for (e, b) in exits.iter_mut().zip(b) {
+                        *e = b.count;
+                    }
+                }
+            }
+        }
+        exits
+    }
+
+    pub fn report(&self, timeline: &Timeline, threshold: f64, scale: Scale) -> VmExitReport {
+        let len = timeline
+            .buckets()
+            .values()
+            .map(|b| b.len())
+            .max()
+            .unwrap_or(0);
+        let exits = self.per_bucket(timeline, len);
+        let exit_values: Vec<f64> = exits.iter().map(|&n| n as f64).collect();
+        let bursts: Vec<bool> = flag(&exit_values, threshold);
+        let exit_points: Vec<Option<f64>> = exit_values.iter().map(|&v| Some(v)).collect();
+
+        let events = timeline
+            .buckets()
+            .iter()
+            .filter(|&(&id, _)| id != HIRES_EV_VMEXIT)
+            .map(|(&event_id, buckets)| {
+                let latency: Vec<Option<f64>> = (0..len)
+                    .map(|i| buckets.get(i).filter(|b| b.count > 0).map(|b| b.avg()))
+                    .collect();
+                let present: Vec<f64> = latency.iter().flatten().copied().collect();
+                let spikes = flag(&present, threshold);
+
+                let (mut in_sum, mut in_n, mut out_sum, mut out_n) = (0.0, 0u64, 0.0, 0u64);
+                let (mut spike_count, mut spikes_in_bursts) = (0, 0);
+                let mut spike = spikes.iter();
+                for (i, v) in latency.iter().enumerate() {
+                    let Some(v) = *v else {
+                        continue;
+                    };
+                    let is_spike = *spike.next().unwrap();
+                    if bursts[i] {
+                        in_sum += v;
+                        in_n += 1;
+                    } else {
+                        out_sum += v;
+                        out_n += 1;
+                    }
+                    if is_spike {
+                        spike_count += 1;
+                        if bursts[i] {
+                            spikes_in_bursts += 1;
+                        }
+                    }
+                }
+                ExitCorrelation {
+                    event_id,
+                    coefficient: pearson(&exit_points, &latency),
+                    spikes: spike_count,
+                    spikes_in_bursts,
+                    avg_in_bursts: (in_n > 0).then(|| scale.cycles(in_sum / in_n as f64)),
+                    avg_outside_bursts: (out_n > 0).then(|| scale.cycles(out_sum / out_n as f64)),
+                }
+            })
+            .collect();
+
+        let reasons = self
+            .reasons
+            .iter()
+            .map(|(&data2, samples)| {
+                let sorted = samples.sorted();
+                let reason = data2 as u32;
+                let (kind, names): (_, &[(u32, &str)]) = match (data2 >> 32) as u32 {
+                    HIRES_VMEXIT_TDX_VE => ("tdx_ve", &TDX_REASONS),
+                    HIRES_VMEXIT_SNP_VC => ("snp_vc", &SNP_REASONS),
+                    _ => ("unknown", &[]),
+                };
+                ExitReason {
+                    kind,
+                    reason,
+                    name: names.iter().find(|(r, _)| *r == reason).map(|(_, n)| *n),
+                    count: samples.count,
+                    avg: scale.cycles(samples.mean()),
+                    p99: scale.cycles(percentile(&sorted, 99.0) as f64),
+                    max: scale.cycles(samples.max as f64),
+                }
+            })
+            .collect();
+
+        VmExitReport {
+            source: if self.counter.is_some() {
+                "exit_counter"
+            } else {
+                "exit_probes"
+            },
+            total_exits: exits.iter().sum(),
+            buckets: len,
+            bursts: bursts.iter().filter(|&&b| b).count(),
+            peak_per_bucket: exits.iter().copied().max().unwrap_or(0),
+            reasons,
+            events,
+        }
+    }
+}
+
+/// Flags values whose robust z-score against the whole series reaches
+/// `threshold`.
+fn flag(values: &[f64], threshold: f64) -> Vec<bool> {
+    if values.is_empty() {
+        return Vec::new();
+    }
+    let baseline = median(values);
+    let deviations: Vec<f64> = values.iter().map(|v| (v - baseline).abs()).collect();
+    let spread = median(&deviations) * MAD_SCALE;
+    values
+        .iter()
+        .map(|&v| {
+            if spread > 0.0 {
+                (v - baseline) / spread >= threshold
+            } else {
+                v > baseline
+            }
+        })
+        .collect()
+}
+
+pub fn print_report(r: &VmExitReport, bucket_ms: u64, scale: Scale) {
+    info!("---- VM exits ----");
+    info!(
+        "Source: {}, Exits: {}, Bursts: {} of {} buckets, Peak: {} per {} ms",
+        r.source, r.total_exits, r.bursts, r.buckets, r.peak_per_bucket, bucket_ms
+    );
+    for x in &r.reasons {
+        info!(
+            "Kind: {}, Reason: {:#x} ({}), Count: {}, Handler average: {} {}, p99: {}, Max: {}",
+            x.kind,
+            x.reason,
+            x.name.unwrap_or("?"),
+            x.count,
+            x.avg,
+            scale.label(),
+            x.p99,
+            x.max
+        );
+    }
+    let fmt = |v: Option<f64>| v.map_or("-".to_string(), |v| format!("{:.3}", v));
+    for e in &r.events {
+        info!(
+            "Event: {}, Exit/latency correlation: {}, Spikes: {} ({} during bursts), Average in bursts: {}, outside: {} {}",
+            e.event_id,
+            e.coefficient
+                .map_or("-".to_string(), |c| format!("{:.2}", c)),
+            e.spikes,
+            e.spikes_in_bursts,
+            fmt(e.avg_in_bursts),
+            fmt(e.avg_outside_bursts),
+            scale.label()
+        );
+    }
+    info!();
+}
@@ -0,0 +1,172 @@
+//! Interrupt-to-wakeup latency (`--wakeup-latency`).
+//!
+//! Pairs kernel interrupt events (`HIRES_EV_IRQ` by default, or any event
+//! with the same layout such as the virtio-net interrupt) with userspace
+//! `HIRES_EV_WAKEUP` entries carrying the same key in `data2`. The interval
+//! runs from the first interrupt since the previous wakeup on that key, when
+//! the data became available, to the wakeup itself.
+
+use crate::stats::{Sampled, percentile};
+use crate::units::Scale;
+use rt::{HIRES_EV_WAKEUP, LOG_FLAG_KERNEL, log_entry_t};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Latencies kept per key for percentiles, 512 KiB each.
+const MAX_WAKEUP_SAMPLES: usize = 1 << 16;
+
+#[derive(Serialize)]
+pub struct WakeupLatency {
+    pub key: u64,
+    pub count: u64,
+    pub avg: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+#[derive(Serialize)]
+pub struct WakeupReport {
+    pub irq_event: u32,
+    pub keys: Vec<WakeupLatency>,
+    /// Wakeups without a preceding interrupt on their key.
+    pub unpaired: u64,
+}
+
+pub struct WakeupPairer {
+    irq_event: u32,
+    /// Key -> TSC at entry of the first interrupt since the last wakeup.
+    pending: HashMap<u64, u64>,
+    /// Key -> latencies in cycles.
+    samples: BTreeMap<u64, Sampled<MAX_WAKEUP_SAMPLES>>,
+    unpaired: u64,
+}
+
+impl WakeupPairer {
+    pub fn new(irq_event: u32) -> Self {
+        WakeupPairer {
+            irq_event,
+            pending: HashMap::new(),
+            samples: BTreeMap::new(),
+            unpaired: 0,
+        }
+    }
+
+    pub fn record(&mut self, entry: &log_entry_t) {
+        let key = entry.data2;
+        if entry.event_id == self.irq_event && entry.flags & (LOG_FLAG_KERNEL as u16) != 0 {
+            // Logged on handler return; data1 is the time spent in it.
+            let irq_start = entry.timestamp.saturating_sub(entry.data1);
+            self.pending.entry(key).or_insert(irq_start);
+        } else if entry.event_id == HIRES_EV_WAKEUP {
+            match self.pending.remove(&key) {
+                Some(irq_start) if entry.data1 >= irq_start => {
+                    self.samples
+                        .entry(key)
+                        .or_default()
+                        .add(entry.data1 - irq_start);
+                }
+                _ => self.unpaired += 1,
+            }
+        }
+    }
+
+    pub fn report(&self, scale: Scale) -> WakeupReport {
+        let keys = self
+            .samples
+            .iter()
+            .map(|(&key, samples)| {
+                let sorted = samples.sorted();
+                WakeupLatency {
+                    key,
+                    count: samples.count,
+                    avg: scale.cycles(samples.mean()),
+                    p50: scale.cycles(percentile(&sorted, 50.0) as f64),
+                    p90: scale.cycles(percentile(&sorted, 90.0) as f64),
+                    p99: scale.cycles(percentile(&sorted, 99.0) as f64),
+                    max: scale.cycles(samples.max as f64),
+                }
+            })
+            .collect();
+        WakeupReport {
+            irq_event: self.irq_event,
+            keys,
+            unpaired: self.unpaired,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Unit;
+    use rt::HIRES_EV_IRQ;
+
+    fn irq(pairer: &mut WakeupPairer, key: u64, end: u64, duration: u64, kernel: bool) {
+        pairer.record(&log_entry_t {
+            timestamp: end,
+            event_id: HIRES_EV_IRQ,
+            cpu_id: 0,
+            flags: if kernel { LOG_FLAG_KERNEL as u16 } else { 0 },
+            data1: duration,
+            data2: key,
+        });
+    }
+
+    fn wakeup(pairer: &mut WakeupPairer, key: u64, tsc: u64) {
+        pairer.record(&log_entry_t {
+            timestamp: 0,
+            event_id: HIRES_EV_WAKEUP,
+            cpu_id: 0,
+            flags: 0,
+            data1: tsc,
+            data2: key,
+        });
+    }
+
+    fn latencies(report: &WakeupReport) -> Vec<(u64, u64, f64, f64)> {
+        report
+            .keys
+            .iter()
+            .map(|k| (k.key, k.count, k.avg, k.max))
+            .collect()
+    }
+
+    #[test]
+    fn wakeups_pair_with_the_first_interrupt_since_the_last_one() {
+        let mut pairer = WakeupPairer::new(HIRES_EV_IRQ);
+        // The interval starts where the first handler was entered.
+        irq(&mut pairer, 1, 1000, 100, true);
+        irq(&mut pairer, 1, 1200, 50, true);
+        wakeup(&mut pairer, 1, 1500);
+        irq(&mut pairer, 1, 2000, 0, true);
+        wakeup(&mut pairer, 1, 2200);
+        // Keys are paired independently.
+        irq(&mut pairer, 2, 1100, 0, true);
+        wakeup(&mut pairer, 2, 1400);
+        let report = pairer.report(Scale::new(Unit::Cycles, 0));
+        assert_eq!(
+            latencies(&report),
+            [(1, 2, 400.0, 600.0), (2, 1, 300.0, 300.0)]
+        );
+        assert_eq!(report.unpaired, 0);
+    }
+
+    #[test]
+    fn wakeups_without_an_interrupt_are_unpaired() {
+        let mut pairer = WakeupPairer::new(HIRES_EV_IRQ);
+        wakeup(&mut pairer, 1, 1500);
+        // Userspace entries with the interrupt's ID do not count.
+        irq(&mut pairer, 1, 1000, 0, false);
+        wakeup(&mut pairer, 1, 1600);
+        // Nor does an interrupt stamped after the wakeup.
+        irq(&mut pairer, 1, 3000, 0, true);
+        wakeup(&mut pairer, 1, 2000);
+        // The interrupt was consumed by the failed pairing.
+        wakeup(&mut pairer, 1, 4000);
+        let report = pairer.report(Scale::new(Unit::Cycles, 0));
+        assert!(report.keys.is_empty());
+        assert_eq!(report.unpaired, 4);
+    }
+}
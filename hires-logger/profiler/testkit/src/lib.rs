@@ -0,0 +1,401 @@
+//! Reproducible synthetic event streams.
+//!
+//! A [`Generator`] merges any number of [`Stream`]s (an event ID, an arrival
+//! rate and process, payload distributions, an optional follow-up event and
+//! an injected drop probability) into one time-ordered sequence of
+//! [`Event`]s. The same seed and streams always produce the same events, so
+//! the profiler's aggregation, pairing and export code can be exercised and
+//! checked without khires or real hardware:
+//!
+//! - [`drive`] logs the events through any [`rt::ring::Producer`]: a
+//!   `HiResConn` (the `stub` backend for in-process runs), or
+//!   `rt::mock::MockRing`, optionally paced in real time;
+//! - [`Event::to_entry`] turns them straight into `log_entry_t`s stamped
+//!   with their synthetic time, for code that takes entries directly.
+//!
+//! Events marked [`Event::dropped`] are skipped by `drive`, as if the ring
+//! had lost them, and stay in the sequence so a test knows what was lost.
+//!
+//! Streams can also be written as `EVENT@RATE[:key=value...]` (see
+//! [`Stream::parse`]), which is what the profiler's `--synthetic` takes.
+//! The profiler's tests/synthetic.rs runs it that way and checks its
+//! reports against the events a [`Generator`] yields for the same seed.
+
+mod dist;
+
+pub use dist::{Dist, Rng};
+
+use rt::ring::Producer;
+use rt::{LOG_FLAG_VALID, log_entry_t};
+use std::cmp::{Ordering as CmpOrdering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// How a stream's events are spaced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arrivals {
+    /// Exponential gaps: a Poisson process at the stream's rate.
+    Poisson,
+    /// Fixed gaps of 1/rate.
+    Periodic,
+}
+
+/// What a stream puts in `data2`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Payload {
+    /// The stream's sequence number, from 0; `--seq-field data2` then sees
+    /// injected drops as gaps.
+    Seq,
+    Sample(Dist),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stream {
+    pub event_id: u32,
+    /// Mean events per second.
+    pub rate_hz: f64,
+    pub arrivals: Arrivals,
+    /// `data1`, typically the latency the default summary aggregates.
+    pub data1: Dist,
+    pub data2: Payload,
+    /// A second event logged this many ns after each one, with the same
+    /// `data2` (so it joins on it) and the delay as `data1`.
+    pub then: Option<(u32, Dist)>,
+    /// Probability that any one event is dropped.
+    pub drop: f64,
+}
+
+impl Stream {
+    /// A Poisson stream of `event_id` with zero `data1` and sequence numbers
+    /// in `data2`.
+    pub fn new(event_id: u32, rate_hz: f64) -> Self {
+        Stream {
+            event_id,
+            rate_hz,
+            arrivals: Arrivals::Poisson,
+            data1: Dist::Const(0.0),
+            data2: Payload::Seq,
+            then: None,
+            drop: 0.0,
+        }
+    }
+
+    /// Parses `EVENT@RATE` followed by any of `:gap=poisson|periodic`,
+    /// `:data1=DIST`, `:data2=seq|DIST`, `:then=EVENT/DIST` and `:drop=P`,
+    /// where DIST is `N`, `uniform(A,B)`, `exp(MEAN)` or `normal(MEAN,SD)`.
+    /// For example `7@20000:data1=exp(1500):then=8/uniform(100,200):drop=0.001`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut parts = s.split(':');
+        let head = parts.next().unwrap_or_default();
+        let (event, rate) = head
+            .split_once('@')
+            .ok_or_else(|| format!("invalid stream '{}': expected EVENT@RATE", s))?;
+        let event_id = parse_event(event)?;
+        let rate_hz = match rate.trim().parse::<f64>() {
+            Ok(r) if r.is_finite() && r > 0.0 => r,
+            _ => {
+                return Err(format!(
+                    "invalid rate '{}': expected events per second",
+                    rate
+                ));
+            }
+        };
+        let mut stream = Stream::new(event_id, rate_hz);
+        for part in parts {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("invalid option '{}': expected key=value", part))?;
+            match key.trim() {
+                "gap" => {
+                    stream.arrivals = match value.trim() {
+                        "poisson" => Arrivals::Poisson,
+                        "periodic" => Arrivals::Periodic,
+                        _ => {
+                            return Err(format!(
+                                "invalid gap '{}': expected poisson or periodic",
+                                value
+                            ));
+                        }
+                    }
+                }
+                "data1" => stream.data1 = Dist::parse(value)?,
+                "data2" => {
+                    stream.data2 = match value.trim() {
+                        "seq" => Payload::Seq,
+                        _ => Payload::Sample(Dist::parse(value)?),
+                    }
+                }
+                "then" => {
+                    let (event, delay) = value
+                        .split_once('/')
+                        .ok_or_else(|| format!("invalid then '{}': expected EVENT/DIST", value))?;
+                    stream.then = Some((parse_event(event)?, Dist::parse(delay)?));
+                }
+                "drop" => {
+                    stream.drop = match value.trim().parse::<f64>() {
+                        Ok(p) if (0.0..=1.0).contains(&p) => p,
+                        _ => {
+                            return Err(format!(
+                                "invalid drop '{}': expected a probability",
+                                value
+                            ));
+                        }
+                    }
+                }
+                other => return Err(format!("unknown stream option '{}'", other)),
+            }
+        }
+        Ok(stream)
+    }
+}
+
+fn parse_event(s: &str) -> Result<u32, String> {
+    s.trim()
+        .parse()
+        .map_err(|e| format!("invalid event '{}': {}", s, e))
+}
+
+/// One generated event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// Nanoseconds since the start of the run.
+    pub at_ns: u64,
+    pub event_id: u32,
+    pub data1: u64,
+    pub data2: u64,
+    /// Injected drop: not logged by [`drive`].
+    pub dropped: bool,
+}
+
+impl Event {
+    /// The entry a ring would hold for this event, stamped with `at_ns`.
+    pub fn to_entry(&self) -> log_entry_t {
+        log_entry_t {
+            timestamp: self.at_ns,
+            event_id: self.event_id,
+            cpu_id: 0,
+            flags: LOG_FLAG_VALID as u16,
+            data1: self.data1,
+            data2: self.data2,
+        }
+    }
+}
+
+enum Queued {
+    /// The next arrival of this stream, drawn when it comes out.
+    Arrival(usize),
+    FollowUp(Event),
+}
+
+/// Queue entries are ordered by time and then by when they were queued, so
+/// equal timestamps come out in a fixed order.
+struct Pending {
+    at_ns: u64,
+    order: u64,
+    queued: Queued,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (self.at_ns, self.order).cmp(&(other.at_ns, other.order))
+    }
+}
+
+struct StreamState {
+    stream: Stream,
+    /// Each stream draws from its own generator, so adding a stream leaves
+    /// the others' events unchanged.
+    rng: Rng,
+    clock_ns: f64,
+    seq: u64,
+}
+
+impl StreamState {
+    fn advance(&mut self) -> u64 {
+        let mean_gap = 1e9 / self.stream.rate_hz;
+        self.clock_ns += match self.stream.arrivals {
+            Arrivals::Poisson => Dist::Exp(mean_gap).sample(&mut self.rng),
+            Arrivals::Periodic => mean_gap,
+        };
+        self.clock_ns as u64
+    }
+}
+
+/// An endless, time-ordered merge of the streams' events.
+pub struct Generator {
+    streams: Vec<StreamState>,
+    queue: BinaryHeap<Reverse<Pending>>,
+    queued: u64,
+}
+
+impl Generator {
+    pub fn new(seed: u64, streams: Vec<Stream>) -> Self {
+        let mut seeds = Rng::new(seed);
+        let mut generator = Generator {
+            streams: streams
+                .into_iter()
+                .map(|stream| StreamState {
+                    stream,
+                    rng: Rng::new(seeds.next_u64()),
+                    clock_ns: 0.0,
+                    seq: 0,
+                })
+                .collect(),
+            queue: BinaryHeap::new(),
+            queued: 0,
+        };
+        for index in 0..generator.streams.len() {
+            generator.schedule_arrival(index);
+        }
+        generator
+    }
+
+    /// The events up to `end_ns` into the run.
+    pub fn until(self, end_ns: u64) -> impl Iterator<Item = Event> {
+        self.take_while(move |e| e.at_ns < end_ns)
+    }
+
+    fn push(&mut self, at_ns: u64, queued: Queued) {
+        self.queued += 1;
+        self.queue.push(Reverse(Pending {
+            at_ns,
+            order: self.queued,
+            queued,
+        }));
+    }
+
+    fn schedule_arrival(&mut self, index: usize) {
+        let at_ns = self.streams[index].advance();
+        self.push(at_ns, Queued::Arrival(index));
+    }
+
+    /// Draws the payload of stream `index`'s arrival at `at_ns` and queues
+    /// its follow-up, if any.
+    fn arrive(&mut self, index: usize, at_ns: u64) -> Event {
+        let state = &mut self.streams[index];
+        let seq = state.seq;
+        state.seq += 1;
+        let data1 = state.stream.data1.sample(&mut state.rng) as u64;
+        let data2 = match &state.stream.data2 {
+            Payload::Seq => seq,
+            Payload::Sample(dist) => dist.sample(&mut state.rng) as u64,
+        };
+        let dropped = state.rng.chance(state.stream.drop);
+        let follow_up = state.stream.then.clone().map(|(event_id, delay)| {
+            let delay = delay.sample(&mut state.rng) as u64;
+            Event {
+                at_ns: at_ns + delay,
+                event_id,
+                data1: delay,
+                data2,
+                dropped: state.rng.chance(state.stream.drop),
+            }
+        });
+        if let Some(event) = follow_up {
+            self.push(event.at_ns, Queued::FollowUp(event));
+        }
+        Event {
+            at_ns,
+            event_id: self.streams[index].stream.event_id,
+            data1,
+            data2,
+            dropped,
+        }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        let Reverse(pending) = self.queue.pop()?;
+        match pending.queued {
+            Queued::Arrival(index) => {
+                let event = self.arrive(index, pending.at_ns);
+                self.schedule_arrival(index);
+                Some(event)
+            }
+            Queued::FollowUp(event) => Some(event),
+        }
+    }
+}
+
+/// How [`drive`] spaces its `log()` calls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pace {
+    /// As fast as possible; only the order is reproduced.
+    Flat,
+    /// Each event is logged once its `at_ns` has passed since the start, so
+    /// the producer's own timestamps approximate the synthetic ones.
+    Realtime,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DriveStats {
+    pub logged: u64,
+    /// `log()` returned false: the ring was full.
+    pub rejected: u64,
+    /// Skipped as injected drops.
+    pub injected: u64,
+}
+
+/// Logs `events` through `producer` until they run out or `running` is
+/// cleared.
+pub fn drive<P: Producer + ?Sized>(
+    producer: &P,
+    events: impl IntoIterator<Item = Event>,
+    pace: Pace,
+    running: &AtomicBool,
+) -> DriveStats {
+    let mut stats = DriveStats::default();
+    let started = Instant::now();
+    for event in events {
+        if !running.load(Ordering::Relaxed) {
+            break;
+        }
+        if pace == Pace::Realtime {
+            wait_until(started + Duration::from_nanos(event.at_ns), running);
+        }
+        if event.dropped {
+            stats.injected += 1;
+        } else if producer.log(event.event_id, event.data1, event.data2) {
+            stats.logged += 1;
+        } else {
+            stats.rejected += 1;
+        }
+    }
+    stats
+}
+
+/// Sleeps until shortly before `deadline`, then spins, since sleeps are far
+/// too coarse for the gaps of a high-rate stream.
+fn wait_until(deadline: Instant, running: &AtomicBool) {
+    const SPIN: Duration = Duration::from_millis(1);
+    loop {
+        let now = Instant::now();
+        if now >= deadline || !running.load(Ordering::Relaxed) {
+            return;
+        }
+        let left = deadline - now;
+        if left > SPIN {
+            std::thread::sleep(left - SPIN);
+        } else {
+            std::hint::spin_loop();
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! End-to-end runs of the profiler on the stub ring: `--synthetic` streams
+//! from hires-testkit are logged in-process, aggregated, and exported, and
+//! the summary and the JSON/CSV reports are checked against the events the
+//! same seed generates here. Built without the `stub` feature this file is
+//! empty.
+//!
+//! ```text
+//! cargo test --no-default-features --features stub --test synthetic
+//! ```
+#![cfg(feature = "stub")]
+
+use hires_testkit::{Generator, Stream};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::Command;
+
+const SEED: u64 = 7;
+const SECS: f64 = 0.2;
+const STREAM: &str = "7@20000:data1=exp(1500):then=8/uniform(100,200):drop=0.01";
+
+/// Count and mean `data1` of the events `drive` logs, per event ID.
+fn expected() -> BTreeMap<u32, (u64, f64)> {
+    let stream = Stream::parse(STREAM).unwrap();
+    let mut sums: BTreeMap<u32, (u64, u128)> = BTreeMap::new();
+    for event in Generator::new(SEED, vec![stream]).until((SECS * 1e9) as u64) {
+        if event.dropped {
+            continue;
+        }
+        let (count, sum) = sums.entry(event.event_id).or_default();
+        *count += 1;
+        *sum += event.data1 as u128;
+    }
+    sums.into_iter()
+        .map(|(id, (count, sum))| (id, (count, sum as f64 / count as f64)))
+        .collect()
+}
+
+fn assert_avg(event_id: u32, got: f64, want: f64) {
+    assert!(
+        (got - want).abs() <= want * 1e-9,
+        "event {}: avg {} != {}",
+        event_id,
+        got,
+        want
+    );
+}
+
+fn out_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hires-synthetic-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn synthetic_run_matches_generated_events() {
+    let dir = out_dir();
+    let json = dir.join("report.json");
+    let csv = dir.join("report.csv");
+    let output = Command::new(env!("CARGO_BIN_EXE_profiler"))
+        .args(["--synthetic", STREAM])
+        .args(["--synthetic-seed", &SEED.to_string()])
+        .args(["--synthetic-secs", &SECS.to_string()])
+        .args(["--units", "cycles", "--no-color"])
+        .arg("--json")
+        .arg(&json)
+        .arg("--csv")
+        .arg(&csv)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "profiler failed:\n{}", stdout);
+
+    let expected = expected();
+    let logged: u64 = expected.values().map(|&(count, _)| count).sum();
+    assert_eq!(expected.len(), 2);
+
+    // Summary
+    assert!(stdout.contains("0 rejected by a full ring"), "{}", stdout);
+    for (id, (count, _)) in &expected {
+        let line = format!("Event ID: {}, Count: {},", id, count);
+        assert!(stdout.contains(&line), "missing `{}` in:\n{}", line, stdout);
+    }
+    let total = format!("Total entries processed: {},", logged);
+    assert!(
+        stdout.contains(&total),
+        "missing `{}` in:\n{}",
+        total,
+        stdout
+    );
+
+    // JSON
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(report["units"], "cycles");
+    assert_eq!(report["entries_processed"], logged);
+    let events = report["events"].as_array().unwrap();
+    assert_eq!(events.len(), expected.len());
+    for event in events {
+        let id = event["id"].as_u64().unwrap() as u32;
+        let (count, avg) = expected[&id];
+        assert_eq!(event["count"], count, "event {}", id);
+        assert_avg(id, event["avg"].as_f64().unwrap(), avg);
+    }
+
+    // CSV
+    let csv = std::fs::read_to_string(&csv).unwrap();
+    let mut lines = csv.lines();
+    let header: Vec<&str> = lines.next().unwrap().split(',').collect();
+    let column = |name: &str| header.iter().position(|&h| h == name).unwrap();
+    let (id_col, count_col, avg_col) = (column("event_id"), column("count"), column("avg_cycles"));
+    let rows: Vec<Vec<&str>> = lines.map(|l| l.split(',').collect()).collect();
+    assert_eq!(rows.len(), expected.len());
+    for row in rows {
+        let id: u32 = row[id_col].parse().unwrap();
+        let (count, avg) = expected[&id];
+        assert_eq!(
+            row[count_col].parse::<u64>().unwrap(),
+            count,
+            "event {}",
+            id
+        );
+        assert_avg(id, row[avg_col].parse().unwrap(), avg);
+    }
+    assert!(dir.join("report_series.csv").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn filtered_entries_reach_no_report() {
+    let output = Command::new(env!("CARGO_BIN_EXE_profiler"))
+        .args(["--synthetic", STREAM])
+        .args(["--synthetic-seed", &SEED.to_string()])
+        .args(["--synthetic-secs", &SECS.to_string()])
+        .args(["--units", "cycles", "--no-color"])
+        .args(["--filter", "event_id == 8"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "profiler failed:\n{}", stdout);
+
+    let expected = expected();
+    let line = format!("Event ID: 8, Count: {},", expected[&8].0);
+    assert!(stdout.contains(&line), "missing `{}` in:\n{}", line, stdout);
+    assert!(!stdout.contains("Event ID: 7,"), "{}", stdout);
+    let filtered = format!("Entries filtered out: {}", expected[&7].0);
+    assert!(
+        stdout.contains(&filtered),
+        "missing `{}` in:\n{}",
+        filtered,
+        stdout
+    );
+}
+
+#[test]
+fn max_events_stops_the_capture() {
+    let run = |max_events: &str| {
+        Command::new(env!("CARGO_BIN_EXE_profiler"))
+            .args(["--synthetic", STREAM])
+            .args(["--synthetic-seed", &SEED.to_string()])
+            .args(["--synthetic-secs", &SECS.to_string()])
+            .args(["--no-color", "--max-events", max_events])
+            .output()
+            .unwrap()
+    };
+    let output = run("100");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "profiler failed:\n{}", stdout);
+    assert!(
+        stdout.contains("Sample budget met after 100 entries"),
+        "{}",
+        stdout
+    );
+
+    let output = run("0");
+    assert!(!output.status.success());
+}
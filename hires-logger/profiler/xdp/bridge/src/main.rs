@@ -0,0 +1,107 @@
+//! Attaches the hires XDP/tc programs to an interface and forwards their
+//! per-packet timestamps into the hires ring buffer (see `rt::packet`), so
+//! the profiler can break NIC-to-socket latency down per hook.
+
+use aya::Ebpf;
+use aya::maps::RingBuf;
+use aya::programs::{SchedClassifier, TcAttachType, Xdp, XdpFlags, tc};
+use clap::Parser;
+use hires_xdp_common::PacketRecord;
+use rt::HiResConn;
+use rt::packet::PACKET_HOOK_FLAG;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Interface to attach to
+    #[arg(short, long)]
+    iface: String,
+
+    /// Compiled eBPF object (built from xdp/ebpf)
+    #[arg(
+        short,
+        long,
+        default_value = "xdp/ebpf/target/bpfel-unknown-none/release/hires-xdp-ebpf"
+    )]
+    object: PathBuf,
+
+    /// Path to the profiler device node
+    #[arg(short, long, default_value = "/dev/khires")]
+    device: String,
+
+    /// Use generic (skb) XDP mode, for drivers without native XDP such as some virtio-net setups
+    #[arg(long)]
+    skb_mode: bool,
+
+    /// Only attach the XDP program, not the tc ingress classifier
+    #[arg(long)]
+    no_tc: bool,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let connection = HiResConn::connect(Some(args.device.as_ref()))?;
+    let mut bpf = Ebpf::load_file(&args.object)?;
+
+    let flags = if args.skb_mode {
+        XdpFlags::SKB_MODE
+    } else {
+        XdpFlags::default()
+    };
+    let xdp: &mut Xdp = bpf
+        .program_mut("hires_xdp")
+        .ok_or("missing hires_xdp")?
+        .try_into()?;
+    xdp.load()?;
+    xdp.attach(&args.iface, flags)?;
+    println!("Attached XDP program to {}", args.iface);
+
+    if !args.no_tc {
+        // Fails harmlessly if the clsact qdisc already exists.
+        let _ = tc::qdisc_add_clsact(&args.iface);
+        let classifier: &mut SchedClassifier = bpf
+            .program_mut("hires_tc")
+            .ok_or("missing hires_tc")?
+            .try_into()?;
+        classifier.load()?;
+        classifier.attach(&args.iface, TcAttachType::Ingress)?;
+        println!("Attached tc ingress classifier to {}", args.iface);
+    }
+
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })?;
+
+    let mut ring = RingBuf::try_from(bpf.map_mut("PACKETS").ok_or("missing PACKETS map")?)?;
+    let mut forwarded: u64 = 0;
+    let mut dropped: u64 = 0;
+    while running.load(Ordering::Relaxed) {
+        let mut idle = true;
+        while let Some(item) = ring.next() {
+            idle = false;
+            let record: PacketRecord =
+                unsafe { std::ptr::read_unaligned(item.as_ptr() as *const PacketRecord) };
+            if connection.log(PACKET_HOOK_FLAG | record.hook, record.ts_ns, record.hash) {
+                forwarded += 1;
+            } else {
+                dropped += 1;
+            }
+        }
+        if idle {
+            std::thread::sleep(Duration::from_micros(100));
+        }
+    }
+
+    println!(
+        "Packets forwarded: {}, dropped (ring full): {}",
+        forwarded, dropped
+    );
+    Ok(())
+}
@@ -0,0 +1,62 @@
+//! Definitions shared by the XDP/tc eBPF program, the ring bridge and
+//! producers that log socket-level packet events.
+//!
+//! Every hook identifies a packet by [`packet_hash`], computed over its L4
+//! ports and the start of its payload. The socket side sees exactly the same
+//! bytes, so it can compute the same key without access to the headers.
+
+#![no_std]
+
+/// Packet seen by the XDP program (driver RX, before skb allocation).
+pub const HOOK_XDP: u32 = 1;
+/// Packet seen by the tc ingress classifier (after skb allocation, GRO).
+pub const HOOK_TC_INGRESS: u32 = 2;
+/// Packet delivered to the application socket.
+pub const HOOK_SOCKET: u32 = 3;
+
+/// Payload bytes that go into [`packet_hash`].
+pub const HASH_PAYLOAD_BYTES: usize = 32;
+
+/// One packet observation, as written to the `PACKETS` BPF ring buffer.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PacketRecord {
+    /// `bpf_ktime_get_ns()`, i.e. CLOCK_MONOTONIC.
+    pub ts_ns: u64,
+    pub hash: u64,
+    pub ifindex: u32,
+    /// `HOOK_*`.
+    pub hook: u32,
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+#[inline(always)]
+pub fn fnv1a_step(hash: u64, byte: u8) -> u64 {
+    (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+}
+
+/// FNV-1a over the ports (network byte order) followed by the first
+/// [`HASH_PAYLOAD_BYTES`] of payload. `src_port` is the sender's port, i.e.
+/// the peer's port when hashing a received packet.
+pub fn packet_hash(src_port: u16, dst_port: u16, payload: &[u8]) -> u64 {
+    let mut hash = FNV_OFFSET;
+    for b in src_port
+        .to_be_bytes()
+        .into_iter()
+        .chain(dst_port.to_be_bytes())
+    {
+        hash = fnv1a_step(hash, b);
+    }
+    for &b in payload.iter().take(HASH_PAYLOAD_BYTES) {
+        hash = fnv1a_step(hash, b);
+    }
+    hash
+}
+
+/// Starting value for hashing incrementally with [`fnv1a_step`], as the eBPF
+/// side must to satisfy the verifier.
+pub const fn hash_seed() -> u64 {
+    FNV_OFFSET
+}
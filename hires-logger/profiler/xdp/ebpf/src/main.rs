@@ -0,0 +1,112 @@
+//! XDP and tc ingress programs stamping every IPv4/IPv6 TCP/UDP packet into
+//! the `PACKETS` ring buffer, for `hires-xdp` to merge into the hires stream.
+//!
+//! Build with a nightly toolchain and bpf-linker:
+//! `cargo build --release` from this directory.
+
+#![no_std]
+#![no_main]
+
+use aya_ebpf::bindings::{TC_ACT_OK, xdp_action};
+use aya_ebpf::helpers::bpf_ktime_get_ns;
+use aya_ebpf::macros::{classifier, map, xdp};
+use aya_ebpf::maps::RingBuf;
+use aya_ebpf::programs::{TcContext, XdpContext};
+use hires_xdp_common::{
+    HASH_PAYLOAD_BYTES, HOOK_TC_INGRESS, HOOK_XDP, PacketRecord, fnv1a_step, hash_seed,
+};
+
+const ETH_HDR_LEN: usize = 14;
+const ETH_P_IP: u16 = 0x0800;
+const ETH_P_IPV6: u16 = 0x86DD;
+const IPV6_HDR_LEN: usize = 40;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+const UDP_HDR_LEN: usize = 8;
+
+#[map]
+static PACKETS: RingBuf = RingBuf::with_byte_size(1 << 22, 0);
+
+#[xdp]
+pub fn hires_xdp(ctx: XdpContext) -> u32 {
+    let ifindex = unsafe { (*ctx.ctx).ingress_ifindex };
+    record(ctx.data(), ctx.data_end(), ifindex, HOOK_XDP);
+    xdp_action::XDP_PASS
+}
+
+#[classifier]
+pub fn hires_tc(ctx: TcContext) -> i32 {
+    let ifindex = unsafe { (*ctx.skb.skb).ifindex };
+    record(ctx.data(), ctx.data_end(), ifindex, HOOK_TC_INGRESS);
+    TC_ACT_OK
+}
+
+/// Reads `N` bytes at `offset`, or `None` past the end of the packet.
+#[inline(always)]
+fn load<const N: usize>(start: usize, end: usize, offset: usize) -> Option<[u8; N]> {
+    if start + offset + N > end {
+        return None;
+    }
+    Some(unsafe { *((start + offset) as *const [u8; N]) })
+}
+
+#[inline(always)]
+fn record(start: usize, end: usize, ifindex: u32, hook: u32) {
+    let Some(hash) = hash_packet(start, end) else {
+        return;
+    };
+    if let Some(mut slot) = PACKETS.reserve::<PacketRecord>(0) {
+        slot.write(PacketRecord {
+            ts_ns: unsafe { bpf_ktime_get_ns() },
+            hash,
+            ifindex,
+            hook,
+        });
+        slot.submit(0);
+    }
+}
+
+/// Same key as `hires_xdp_common::packet_hash`, over the packet headers.
+#[inline(always)]
+fn hash_packet(start: usize, end: usize) -> Option<u64> {
+    let ethertype = u16::from_be_bytes(load::<2>(start, end, 12)?);
+    let (proto, l4) = match ethertype {
+        ETH_P_IP => {
+            let [ver_ihl] = load::<1>(start, end, ETH_HDR_LEN)?;
+            let [proto] = load::<1>(start, end, ETH_HDR_LEN + 9)?;
+            (proto, ETH_HDR_LEN + (ver_ihl & 0x0f) as usize * 4)
+        }
+        ETH_P_IPV6 => {
+            let [next] = load::<1>(start, end, ETH_HDR_LEN + 6)?;
+            (next, ETH_HDR_LEN + IPV6_HDR_LEN)
+        }
+        _ => return None,
+    };
+    let ports = load::<4>(start, end, l4)?;
+    let payload = match proto {
+        IPPROTO_TCP => {
+            let [doff] = load::<1>(start, end, l4 + 12)?;
+            l4 + (doff >> 4) as usize * 4
+        }
+        IPPROTO_UDP => l4 + UDP_HDR_LEN,
+        _ => return None,
+    };
+
+    let mut hash = hash_seed();
+    for b in ports {
+        hash = fnv1a_step(hash, b);
+    }
+    for i in 0..HASH_PAYLOAD_BYTES {
+        match load::<1>(start, end, payload + i) {
+            Some([b]) => hash = fnv1a_step(hash, b),
+            None => break,
+        }
+    }
+    Some(hash)
+}
+
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
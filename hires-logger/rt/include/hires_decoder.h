@@ -0,0 +1,44 @@
+#ifndef HIRES_DECODER_H
+#define HIRES_DECODER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+/*
+ * Payload decoder plugin ABI.
+ *
+ * A decoder is a shared library exporting `hires_decode`. The profiler loads
+ * it with `--decoder path/to/libdecoder.so` and calls it for every entry, so
+ * domain-specific packed payloads can be rendered as named fields without
+ * forking the profiler.
+ */
+
+#define HIRES_DECODER_NAME_LEN 32
+#define HIRES_DECODER_VALUE_LEN 64
+
+typedef struct {
+    char name[HIRES_DECODER_NAME_LEN];   // NUL-terminated field name
+    char value[HIRES_DECODER_VALUE_LEN]; // NUL-terminated rendered value
+} hires_field_t;
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/**
+ * @brief Decodes one entry's payload into named fields.
+ * @param event_id Event ID of the entry.
+ * @param data1 Payload 1 of the entry.
+ * @param data2 Payload 2 of the entry.
+ * @param out Array of `max_fields` fields to fill.
+ * @param max_fields Capacity of `out`.
+ * @return Number of fields written, or 0 if the decoder does not handle this event.
+ */
+size_t hires_decode(uint32_t event_id, uint64_t data1, uint64_t data2,
+                    hires_field_t* out, size_t max_fields);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // HIRES_DECODER_H
@@ -0,0 +1,49 @@
+#ifndef HIRES_DPDK_H
+#define HIRES_DPDK_H
+
+#include <stdint.h>
+
+#include <rte_cycles.h>
+#include <rte_ethdev.h>
+
+#include "rt_c.h"
+
+/*
+ * DPDK poll-mode driver shim.
+ *
+ * Drop-in replacements for rte_eth_rx_burst/rte_eth_tx_burst that log each
+ * burst into the hires ring:
+ *
+ *   data1 = TSC cycles spent in the burst call
+ *   data2 = HIRES_DPDK_DATA2(port, queue, packets)
+ *
+ * The event IDs are chosen by the application, so kernel-bypass bursts sit
+ * next to the kernel-path events of the same run. Empty RX polls are not
+ * logged, they would flood the ring; TX bursts are, since sending fewer
+ * packets than offered means the TX ring was full.
+ */
+
+#define HIRES_DPDK_DATA2(port, queue, n) \
+    (((uint64_t)(port) << 48) | ((uint64_t)(queue) << 32) | (uint64_t)(n))
+
+static inline uint16_t hires_dpdk_rx_burst(HiResLoggerConnHandle* handle, uint32_t event_id,
+                                           uint16_t port, uint16_t queue,
+                                           struct rte_mbuf** pkts, uint16_t nb_pkts) {
+    uint64_t start = rte_rdtsc();
+    uint16_t n = rte_eth_rx_burst(port, queue, pkts, nb_pkts);
+    if (n > 0) {
+        hires_log(handle, event_id, rte_rdtsc() - start, HIRES_DPDK_DATA2(port, queue, n));
+    }
+    return n;
+}
+
+static inline uint16_t hires_dpdk_tx_burst(HiResLoggerConnHandle* handle, uint32_t event_id,
+                                           uint16_t port, uint16_t queue,
+                                           struct rte_mbuf** pkts, uint16_t nb_pkts) {
+    uint64_t start = rte_rdtsc();
+    uint16_t n = rte_eth_tx_burst(port, queue, pkts, nb_pkts);
+    hires_log(handle, event_id, rte_rdtsc() - start, HIRES_DPDK_DATA2(port, queue, n));
+    return n;
+}
+
+#endif // HIRES_DPDK_H
@@ -0,0 +1,43 @@
+#ifndef HIRES_PRELOAD_H
+#define HIRES_PRELOAD_H
+
+#include <stdint.h>
+
+/*
+ * Socket syscall shim for unmodified binaries (libhires_preload.so).
+ *
+ *   LD_PRELOAD=libhires_preload.so ./server
+ *
+ * Interposes the send and receive families and logs each call on return:
+ *
+ *   data1 = TSC cycles spent in the call
+ *   data2 = HIRES_PRELOAD_DATA2(fd, ret)
+ *
+ * ret is the call's return value (bytes, or messages for the *mmsg calls),
+ * or -errno if it failed. EAGAIN returns from non-blocking sockets are not
+ * logged, an event loop polling empty sockets would flood the ring.
+ *
+ * Environment:
+ *   HIRES_DEVICE               device node (default /dev/khires)
+ *   HIRES_PRELOAD_EVENT_BASE   first event ID (default 200); the calls are
+ *                              logged as base + HIRES_PRELOAD_*
+ *
+ * If the device cannot be opened the shim stays out of the way and only
+ * forwards the calls.
+ */
+
+#define HIRES_PRELOAD_SEND      0
+#define HIRES_PRELOAD_SENDTO    1
+#define HIRES_PRELOAD_SENDMSG   2
+#define HIRES_PRELOAD_SENDMMSG  3
+#define HIRES_PRELOAD_RECV      4
+#define HIRES_PRELOAD_RECVFROM  5
+#define HIRES_PRELOAD_RECVMSG   6
+#define HIRES_PRELOAD_RECVMMSG  7
+#define HIRES_PRELOAD_NUM_EVENTS 8
+
+#define HIRES_PRELOAD_DEFAULT_BASE 200
+
+#define HIRES_PRELOAD_DATA2(fd, ret) (((uint64_t)(uint32_t)(fd) << 32) | (uint32_t)(int32_t)(ret))
+
+#endif // HIRES_PRELOAD_H
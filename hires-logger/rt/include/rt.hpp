@@ -1,5 +1,6 @@
 #pragma once
 
+#include <atomic>
 #include <optional>
 #include <stdexcept>
 #include <string>
@@ -14,6 +15,29 @@ public:
   using std::runtime_error::runtime_error;
 };
 
+/**
+ * @brief Where userspace producers take entry timestamps from.
+ *
+ * Raw TSC reads are cheapest, but on some CVM configurations the TSC is
+ * intercepted or scaled and a paravirt clock gives more trustworthy
+ * intervals. TSC-stamped entries carry LOG_FLAG_TSC so consumers can convert
+ * them.
+ *
+ * Among the TSC sources, Rdtsc may execute before earlier instructions have
+ * completed, so a short operation can appear shorter than it was. Rdtscp
+ * waits for them and also reads the CPU the timestamp was taken on, which
+ * then goes in the entry instead of a getcpu() call. RdtscLfence waits like
+ * Rdtscp without reading the CPU, for VMs that do not expose rdtscp.
+ */
+enum class TimestampSource : uint32_t {
+  Monotonic = HIRES_TS_MONOTONIC,
+  MonotonicRaw = HIRES_TS_MONOTONIC_RAW,
+  Rdtsc = HIRES_TS_RDTSC,
+  Rdtscp = HIRES_TS_RDTSCP,
+  Kvmclock = HIRES_TS_KVMCLOCK,
+  RdtscLfence = HIRES_TS_RDTSC_LFENCE,
+};
+
 class HiResConn {
 private:
   int fd_ = -1;
@@ -25,10 +49,46 @@ private:
   uint64_t rb_runtime_shm_size_ = 0;
   // TSC cycles per microsecond
   PROF_CACHE_LINE_ALIGNED uint64_t cycles_per_us_ = 0;
+  // TSC frequency in Hz, for precise cycle-to-time conversion
+  uint64_t tsc_hz_ = 0;
+  std::optional<hires_tsc_info_t> tsc_info_;
+  // Ring generation when this connection mapped it, see check_seal()
+  uint32_t generation_ = 0;
+  // Entry layout khires created the ring with (HIRES_ENTRY_LAYOUT_*)
+  hires_entry_layout_t entry_layout_{};
+  std::atomic<TimestampSource> ts_source_{TimestampSource::Monotonic};
+
+  // Helper to get CLOCK_MONOTONIC_RAW time
+  static uint64_t get_monotonic_raw_ns();
 
   // Helper to get monotonic time
   static uint64_t get_monotonic_ns();
 
+  // Entry `idx` of the ring and its flags, for the ring's entry layout
+  inline __attribute__((always_inline)) void *
+  entry_at(uint64_t idx) const noexcept {
+    return hires_ring_entry(shm_buf_, idx, entry_layout_.entry_size);
+  }
+  inline __attribute__((always_inline)) uint16_t *
+  flags_of(void *entry) const noexcept {
+    return reinterpret_cast<uint16_t *>(
+        static_cast<char *>(entry) +
+        entry_layout_.field_offset[HIRES_FIELD_FLAGS]);
+  }
+
+  // Copies a valid entry of any layout out of the ring
+  log_entry_ext_t read_entry(const void *entry, uint64_t seqno) const noexcept;
+
+  // Producer helpers shared by log() and log_batch(): the timestamp from
+  // source (adding LOG_FLAG_TSC to flags for TSC stamps) with the CPU the
+  // caller runs on, that CPU alone, and every field of the entry at ring
+  // position pos but its flags.
+  static uint64_t read_timestamp(TimestampSource source, uint16_t &flags,
+                                 unsigned &cpu) noexcept;
+  static unsigned current_cpu() noexcept;
+  void fill_entry(void *entry, uint64_t pos, uint64_t timestamp, unsigned cpu,
+                  uint32_t event_id, uint64_t data1, uint64_t data2) noexcept;
+
   inline __attribute__((always_inline)) void
   set_runtime_rb_meta(const hires_rb_meta_t &meta) noexcept {
     this->rb_runtime_capacity_ = static_cast<uint64_t>(meta.capacity);
@@ -46,9 +106,15 @@ public:
   /**
    * @brief Constructs a connection, opening and mmapping the device.
    * @param device_path Path to the HiResLogger character device.
-   * @throws HiResError if opening or mmapping fails.
+   * @param capacity Ring capacity in entries to ask khires for before
+   * mapping (HIRES_IOCTL_SET_RB_CAPACITY), a power of two in
+   * 2..2^RING_BUFFER_LOG2_MAX; 0 maps the ring as it is. khires refuses to
+   * resize a ring another process has mapped.
+   * @throws HiResError if opening or mmapping fails or the capacity is out
+   * of range, std::system_error if khires refuses the capacity.
    */
-  explicit HiResConn(const std::string &device_path = "/dev/khires");
+  explicit HiResConn(const std::string &device_path = "/dev/khires",
+                     uint64_t capacity = 0);
 
   /**
    * @brief Destructor, automatically unmaps and closes the device.
@@ -62,6 +128,20 @@ public:
 
   std::optional<hires_rb_meta_t> get_rb_meta() const noexcept;
   uint64_t get_kmod_cycles_per_us() const noexcept;
+  uint64_t get_kmod_tsc_hz() const noexcept;
+  std::optional<hires_tsc_info_t> get_kmod_tsc_info() const noexcept;
+
+  /**
+   * @brief Selects the timestamp source for subsequent log() calls.
+   * @return False (leaving the source unchanged) if the source is not usable
+   * here, e.g. Kvmclock while kvm-clock is not the active clocksource.
+   */
+  bool set_timestamp_source(TimestampSource source);
+
+  inline __attribute__((always_inline)) TimestampSource
+  get_timestamp_source() const noexcept {
+    return ts_source_.load(std::memory_order_relaxed);
+  }
 
   /**
    * @brief Logs an event to the shared ring buffer (Userspace Producer Logic).
@@ -73,6 +153,37 @@ public:
    */
   bool log(uint32_t event_id, uint64_t data1 = 0, uint64_t data2 = 0);
 
+  /**
+   * @brief log() with the timestamp taken from source instead of the
+   * connection's timestamp source, e.g. RdtscLfence around one short
+   * operation on a connection that otherwise uses the cheaper Rdtsc.
+   * Kvmclock is not checked against the active clocksource here.
+   */
+  bool log_with_source(TimestampSource source, uint32_t event_id,
+                       uint64_t data1 = 0, uint64_t data2 = 0);
+
+  /**
+   * @brief Logs several events as one contiguous run of entries.
+   * The slots are reserved with a single compare-and-swap on head (retried
+   * only if another producer moved it) and published after a single release
+   * fence, instead of a fetch_add and a release store per entry. The
+   * entries share one timestamp and CPU ID.
+   * @param entries The events, in order.
+   * @param count Number of events.
+   * @return True if all were logged, false if the ring had no room for the
+   * whole batch, which is then dropped whole (count drops).
+   */
+  bool log_batch(const hires_batch_entry_t *entries, size_t count);
+
+  /**
+   * @brief Logs a HIRES_EV_WAKEUP right after a blocking call (epoll_wait,
+   * recv) returns, to be paired with the interrupt identified by key.
+   * @param key Usually the IRQ number serving the socket's queue.
+   */
+  inline bool log_wakeup(uint64_t key) {
+    return log(HIRES_EV_WAKEUP, Ops::__rdtsc(), key);
+  }
+
   /**
    * @brief Attempts to pop one log entry from the buffer (Consumer Logic).
    * This implements the single-consumer side of the MPSC queue.
@@ -83,6 +194,61 @@ public:
    */
   std::optional<log_entry_t> pop();
 
+  /**
+   * @brief Returns the entry pop() would return next without consuming it.
+   * Waits for the VALID flag the same way pop() does.
+   * @return The entry, or std::nullopt if the buffer is empty or the entry
+   * wasn't ready.
+   */
+  std::optional<log_entry_t> peek() const;
+
+  /**
+   * @brief pop() returning every field the ring's entry layout has.
+   * tid is 0 unless the layout is HIRES_ENTRY_LAYOUT_EXTENDED; seqno is the
+   * entry's position in the ring for every layout.
+   */
+  std::optional<log_entry_ext_t> pop_ext();
+
+  /**
+   * @brief peek() returning every field the ring's entry layout has, see
+   * pop_ext().
+   */
+  std::optional<log_entry_ext_t> peek_ext() const;
+
+  /**
+   * @brief The entry layout khires created the ring with.
+   * @return One of HIRES_ENTRY_LAYOUT_*.
+   */
+  inline __attribute__((always_inline)) uint16_t
+  get_entry_layout() const noexcept {
+    return entry_layout_.id;
+  }
+
+  /**
+   * @brief Full fence after this thread's log() calls.
+   * Each entry is published on its own; this additionally orders all of
+   * them before any later memory operation of the caller, e.g. a flag or
+   * doorbell telling another process to drain the ring now.
+   */
+  inline __attribute__((always_inline)) void flush() const noexcept {
+    std::atomic_thread_fence(std::memory_order_seq_cst);
+  }
+
+  /**
+   * @brief Snapshot of the ring's indexes and counters.
+   */
+  hires_rb_stats_t stats() const noexcept;
+
+  /**
+   * @brief Checks the seal khires keeps in the ring header: the magic, the
+   * layout checksum of the geometry this connection mapped, and the
+   * generation seen at connect. Consumers call it now and then to notice a
+   * ring that was reset or a module that was unloaded under them.
+   * @return Why the mapping can no longer be trusted, or std::nullopt if the
+   * seal is intact.
+   */
+  std::optional<std::string> check_seal() const;
+
   /**
    * @brief Gets a raw pointer to the underlying shared memory buffer structure.
    * Use with caution. Primarily intended for the consumer or advanced usage.
@@ -110,17 +276,17 @@ public:
     return fd_;
   }
 
-  inline __attribute__((always_inline)) size_t
+  inline __attribute__((always_inline)) uint64_t
   get_rb_capacity() const noexcept {
     return rb_runtime_capacity_;
   }
 
-  inline __attribute__((always_inline)) size_t
+  inline __attribute__((always_inline)) uint64_t
   get_rb_idx_mask() const noexcept {
     return rb_runtime_idx_mask_;
   }
 
-  inline __attribute__((always_inline)) size_t
+  inline __attribute__((always_inline)) uint64_t
   get_rb_shm_size() const noexcept {
     return rb_runtime_shm_size_;
   }
@@ -130,6 +296,19 @@ public:
     return cycles_per_us_;
   }
   
+  inline __attribute__((always_inline)) uint64_t
+  get_tsc_hz() const noexcept {
+    return tsc_hz_;
+  }
+
+  /**
+   * @brief How the module obtained its TSC frequency (timing loop vs
+   * SecureTSC). Empty with modules that predate HIRES_IOCTL_GET_TSC_INFO.
+   */
+  inline const std::optional<hires_tsc_info_t> &get_tsc_info() const noexcept {
+    return tsc_info_;
+  }
+
   inline __attribute__((always_inline)) uint64_t
   get_drop_num() const noexcept {
     return shm_buf_->dropped_count;
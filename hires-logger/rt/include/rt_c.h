@@ -5,7 +5,25 @@
 #include <stdint.h>
 #include <stdbool.h>
 
+// In-tree, common.h lives in ../../shared. An installed copy of the headers
+// (e.g. for HIRES_RT_INCLUDE_DIR in the Rust build) keeps it next to this file.
+#if defined(__has_include)
+#if __has_include("../../shared/common.h")
 #include "../../shared/common.h"
+#else
+#include "common.h"
+#endif
+#else
+#include "../../shared/common.h"
+#endif
+
+// Version of this C API. The major number changes when existing functions or
+// shared structs change incompatibly, the minor number when functions are
+// added. Bindings built against MAJOR.MINOR work with any library reporting
+// the same major and at least that minor.
+#define HIRES_API_VERSION_MAJOR 2
+#define HIRES_API_VERSION_MINOR 0
+#define HIRES_API_VERSION ((HIRES_API_VERSION_MAJOR << 16) | HIRES_API_VERSION_MINOR)
 
 typedef struct HiResLoggerConnHandle HiResLoggerConnHandle;
 
@@ -13,6 +31,12 @@ typedef struct HiResLoggerConnHandle HiResLoggerConnHandle;
 extern "C" {
 #endif
 
+/**
+ * @brief Gets the HIRES_API_VERSION this library was built with, for
+ * bindings to check before calling anything else.
+ */
+uint32_t hires_get_api_version(void);
+
 /**
  * @brief Creates a profiler connection object.
  * Opens and mmaps the profiler device.
@@ -22,6 +46,16 @@ extern "C" {
  */
 HiResLoggerConnHandle* hires_connect(const char* device_path);
 
+/**
+ * @brief hires_connect() asking khires for a ring of capacity entries
+ * first (HIRES_IOCTL_SET_RB_CAPACITY). Since API 1.4.
+ * @param capacity A power of two in 2..2^RING_BUFFER_LOG2_MAX; 0 connects
+ * to the ring as it is. Fails if another process has the ring mapped with a
+ * different capacity, or the module cannot allocate it.
+ * @return A handle to the connection object, or NULL on failure.
+ */
+HiResLoggerConnHandle* hires_connect_with_capacity(const char* device_path, uint64_t capacity);
+
 /**
  * @brief Destroys a profiler connection object.
  * Unmaps the shared memory and closes the device file descriptor.
@@ -40,6 +74,34 @@ void hires_disconnect(HiResLoggerConnHandle* handle);
  */
 bool hires_log(HiResLoggerConnHandle* handle, uint32_t event_id, uint64_t data1, uint64_t data2);
 
+/**
+ * @brief hires_log() with the timestamp taken from source instead of the
+ * connection's timestamp source (see hires_set_timestamp_source), to pay
+ * for an ordered read only around the operations that need one. Since
+ * API 1.3.
+ * @param handle The handle returned by hires_connect. Must not be NULL.
+ * @param source One of the HIRES_TS_* constants from common.h.
+ * HIRES_TS_KVMCLOCK is not checked against the active clocksource.
+ * @return True on success, false if the buffer was full, the source is
+ * unknown or the handle is invalid.
+ */
+bool hires_log_with_source(HiResLoggerConnHandle* handle, uint32_t source, uint32_t event_id,
+                           uint64_t data1, uint64_t data2);
+
+/**
+ * @brief Logs several events as one contiguous run of entries, reserving
+ * the slots with a single compare-and-swap and publishing them after a
+ * single release fence. The entries share one timestamp and CPU ID. The
+ * batch is logged whole or, if the ring has no room for all of it, dropped
+ * whole (counting count drops). Since API 1.2.
+ * @param handle The handle returned by hires_connect. Must not be NULL.
+ * @param entries The events, in order. May be NULL if count is 0.
+ * @param count Number of events; at most the ring's capacity.
+ * @return True if every event was logged (or count is 0), false if the
+ * batch was dropped or the handle is invalid.
+ */
+bool hires_log_batch(HiResLoggerConnHandle* handle, const hires_batch_entry_t* entries, size_t count);
+
 /**
  * @brief Attempts to pop one log entry from the buffer using the provided handle.
  * @param handle The handle returned by hires_connect. Must not be NULL.
@@ -50,6 +112,48 @@ bool hires_log(HiResLoggerConnHandle* handle, uint32_t event_id, uint64_t data1,
  */
 bool hires_pop(HiResLoggerConnHandle* handle, log_entry_t* entry);
 
+/**
+ * @brief Copies the next entry without consuming it.
+ * @param handle The handle returned by hires_connect. Must not be NULL.
+ * @param entry Where the entry is copied. Must not be NULL.
+ * @return True if an entry was copied, false if the buffer was empty, the
+ * entry wasn't ready, or if the handle/entry pointer is invalid.
+ */
+bool hires_peek(HiResLoggerConnHandle* handle, log_entry_t* entry);
+
+/**
+ * @brief hires_pop() copying every field of the ring's entry layout.
+ * tid is 0 unless the ring uses HIRES_ENTRY_LAYOUT_EXTENDED; seqno is the
+ * entry's position in the ring for every layout. Since API 1.1.
+ * @param handle The handle returned by hires_connect. Must not be NULL.
+ * @param entry Where the entry is copied. Must not be NULL.
+ * @return Same as hires_pop().
+ */
+bool hires_pop_ext(HiResLoggerConnHandle* handle, log_entry_ext_t* entry);
+
+/**
+ * @brief Gets the entry layout khires created the ring with. Since API 1.1.
+ * @return One of HIRES_ENTRY_LAYOUT_*, HIRES_ENTRY_LAYOUT_STANDARD if the
+ * handle is invalid.
+ */
+uint32_t hires_get_entry_layout(HiResLoggerConnHandle* handle);
+
+/**
+ * @brief Full memory fence after the calling thread's hires_log() calls.
+ * Orders every entry logged so far before the caller's later memory
+ * operations (e.g. a doorbell telling the consumer to drain the ring).
+ * @param handle The handle returned by hires_connect. Must not be NULL.
+ */
+void hires_flush(HiResLoggerConnHandle* handle);
+
+/**
+ * @brief Takes a snapshot of the ring's indexes and counters.
+ * @param handle The handle returned by hires_connect. Must not be NULL.
+ * @param out Filled on success. Must not be NULL.
+ * @return False if the handle or out pointer is invalid.
+ */
+bool hires_get_stats(HiResLoggerConnHandle* handle, hires_rb_stats_t* out);
+
 /**
  * @brief Gets a raw pointer to the shared ring buffer structure.
  * Use with extreme caution. Allows direct manipulation/reading of the buffer.
@@ -63,16 +167,47 @@ shared_ring_buffer_t* hires_get_buffer(HiResLoggerConnHandle* handle);
  * @param handle The handle returned by profiler_connect. Must not be NULL.
  * @return Size in bytes, or 0 if handle is invalid.
  */
-size_t hires_get_shm_size(HiResLoggerConnHandle* handle);
+uint64_t hires_get_shm_size(HiResLoggerConnHandle* handle);
 
-size_t hires_get_rb_capacity(HiResLoggerConnHandle* handle);
-size_t hires_get_rb_idx_mask(HiResLoggerConnHandle* handle);
+// The ring geometry is 64-bit in the shared header whatever the size of
+// size_t, so rings past 4 GB are reported in full to 32-bit callers too.
+// These and hires_get_shm_size() returned size_t before API 2.0.
+uint64_t hires_get_rb_capacity(HiResLoggerConnHandle* handle);
+uint64_t hires_get_rb_idx_mask(HiResLoggerConnHandle* handle);
 uint64_t hires_get_cycles_per_us(HiResLoggerConnHandle* handle);
+/**
+ * @brief Gets the calibrated TSC frequency in Hz (not rounded to whole cycles/us).
+ */
+uint64_t hires_get_tsc_hz(HiResLoggerConnHandle* handle);
+/**
+ * @brief Gets how the kernel module obtained its TSC frequency.
+ * @param out Filled on success. Must not be NULL.
+ * @return False if the module does not report it (older module).
+ */
+bool hires_get_tsc_info(HiResLoggerConnHandle* handle, hires_tsc_info_t* out);
 uint64_t hires_get_drop_num(HiResLoggerConnHandle* handle);
 
+/**
+ * @brief Selects where hires_log() takes timestamps from.
+ * @param handle The handle returned by hires_connect. Must not be NULL.
+ * @param source One of the HIRES_TS_* constants from common.h.
+ * @return True on success, false if the source is unknown or unavailable
+ * (e.g. HIRES_TS_KVMCLOCK while kvm-clock is not the active clocksource).
+ */
+bool hires_set_timestamp_source(HiResLoggerConnHandle* handle, uint32_t source);
+uint32_t hires_get_timestamp_source(HiResLoggerConnHandle* handle);
+
 uint64_t hires_rdtsc(void);
 uint64_t hires_rdtscp(uint32_t* auxp);
 
+/**
+ * @brief Layout of the shared structs as compiled into this library, for
+ * language bindings to check their own definitions against at runtime.
+ */
+size_t hires_sizeof_log_entry(void);
+size_t hires_sizeof_ring_buffer(void);
+size_t hires_offsetof_ring_entries(void);
+
 /**
  * @brief Gets the last error message encountered by the API functions for the current thread.
  * Note: This is a simple thread-local error reporting mechanism. Not robust for
@@ -0,0 +1,114 @@
+// LD_PRELOAD shim logging socket syscall durations, see hires_preload.h.
+
+#include <dlfcn.h>
+#include <errno.h>
+#include <pthread.h>
+#include <stdlib.h>
+#include <sys/socket.h>
+
+#include "../include/hires_preload.h"
+#include "../include/rt_c.h"
+
+namespace {
+
+HiResLoggerConnHandle* conn = nullptr;
+uint32_t event_base = HIRES_PRELOAD_DEFAULT_BASE;
+pthread_once_t init_once = PTHREAD_ONCE_INIT;
+
+// Set while connecting, so the runtime's own calls are not logged.
+thread_local bool in_shim = false;
+
+void init() {
+    const char* base = getenv("HIRES_PRELOAD_EVENT_BASE");
+    if (base != nullptr) {
+        char* end = nullptr;
+        unsigned long value = strtoul(base, &end, 0);
+        if (end != base && *end == '\0' && value + HIRES_PRELOAD_NUM_EVENTS <= 256) {
+            event_base = static_cast<uint32_t>(value);
+        }
+    }
+    in_shim = true;
+    conn = hires_connect(getenv("HIRES_DEVICE"));
+    in_shim = false;
+}
+
+template <typename Fn>
+Fn* next(Fn*, const char* name) {
+    return reinterpret_cast<Fn*>(dlsym(RTLD_NEXT, name));
+}
+
+// Runs the real call and logs it; errno is preserved for the caller.
+template <typename Ret, typename Call>
+Ret timed(uint32_t event, int fd, Call call) {
+    if (in_shim) {
+        return call();
+    }
+    pthread_once(&init_once, init);
+    if (conn == nullptr) {
+        return call();
+    }
+    uint64_t start = hires_rdtsc();
+    Ret ret = call();
+    uint64_t elapsed = hires_rdtsc() - start;
+    int saved_errno = errno;
+    if (ret >= 0 || (saved_errno != EAGAIN && saved_errno != EWOULDBLOCK)) {
+        int64_t result = ret >= 0 ? static_cast<int64_t>(ret) : -saved_errno;
+        in_shim = true;
+        hires_log(conn, event_base + event, elapsed, HIRES_PRELOAD_DATA2(fd, result));
+        in_shim = false;
+    }
+    errno = saved_errno;
+    return ret;
+}
+
+} // namespace
+
+extern "C" {
+
+ssize_t send(int fd, const void* buf, size_t len, int flags) {
+    static auto real = next(&send, "send");
+    return timed<ssize_t>(HIRES_PRELOAD_SEND, fd, [&] { return real(fd, buf, len, flags); });
+}
+
+ssize_t sendto(int fd, const void* buf, size_t len, int flags, const struct sockaddr* addr,
+               socklen_t addrlen) {
+    static auto real = next(&sendto, "sendto");
+    return timed<ssize_t>(HIRES_PRELOAD_SENDTO, fd,
+                          [&] { return real(fd, buf, len, flags, addr, addrlen); });
+}
+
+ssize_t sendmsg(int fd, const struct msghdr* msg, int flags) {
+    static auto real = next(&sendmsg, "sendmsg");
+    return timed<ssize_t>(HIRES_PRELOAD_SENDMSG, fd, [&] { return real(fd, msg, flags); });
+}
+
+int sendmmsg(int fd, struct mmsghdr* msgvec, unsigned int vlen, int flags) {
+    static auto real = next(&sendmmsg, "sendmmsg");
+    return timed<int>(HIRES_PRELOAD_SENDMMSG, fd, [&] { return real(fd, msgvec, vlen, flags); });
+}
+
+ssize_t recv(int fd, void* buf, size_t len, int flags) {
+    static auto real = next(&recv, "recv");
+    return timed<ssize_t>(HIRES_PRELOAD_RECV, fd, [&] { return real(fd, buf, len, flags); });
+}
+
+ssize_t recvfrom(int fd, void* buf, size_t len, int flags, struct sockaddr* addr,
+                 socklen_t* addrlen) {
+    static auto real = next(&recvfrom, "recvfrom");
+    return timed<ssize_t>(HIRES_PRELOAD_RECVFROM, fd,
+                          [&] { return real(fd, buf, len, flags, addr, addrlen); });
+}
+
+ssize_t recvmsg(int fd, struct msghdr* msg, int flags) {
+    static auto real = next(&recvmsg, "recvmsg");
+    return timed<ssize_t>(HIRES_PRELOAD_RECVMSG, fd, [&] { return real(fd, msg, flags); });
+}
+
+int recvmmsg(int fd, struct mmsghdr* msgvec, unsigned int vlen, int flags,
+             struct timespec* timeout) {
+    static auto real = next(&recvmmsg, "recvmmsg");
+    return timed<int>(HIRES_PRELOAD_RECVMMSG, fd,
+                      [&] { return real(fd, msgvec, vlen, flags, timeout); });
+}
+
+} // extern "C"
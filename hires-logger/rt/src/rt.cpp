@@ -1,8 +1,10 @@
+#include <algorithm>
 #include <atomic>
 #include <cerrno>
 #include <cstdint>
 #include <cstring>
 #include <fcntl.h>
+#include <fstream>
 #include <iostream>
 #include <optional>
 #include <sys/mman.h>    // For mmap(), munmap()
@@ -35,7 +37,25 @@ uint64_t HiResConn::get_monotonic_ns() {
          static_cast<uint64_t>(ts.tv_nsec);
 }
 
-HiResConn::HiResConn(const std::string &device_path) {
+uint64_t HiResConn::get_monotonic_raw_ns() {
+  struct timespec ts;
+  if (clock_gettime(CLOCK_MONOTONIC_RAW, &ts) == -1) {
+    throw_system_error("clock_gettime(CLOCK_MONOTONIC_RAW) failed");
+  }
+  return static_cast<uint64_t>(ts.tv_sec) * 1000000000ULL +
+         static_cast<uint64_t>(ts.tv_nsec);
+}
+
+HiResConn::HiResConn(const std::string &device_path, uint64_t capacity) {
+  // khires's bounds, so a bad capacity fails here rather than in the ioctl
+  // with a bare EINVAL.
+  if (capacity != 0 &&
+      (capacity < 2 || (capacity & (capacity - 1)) != 0 ||
+       capacity > (1ULL << RING_BUFFER_LOG2_MAX))) {
+    throw HiResError("Requested ring capacity " + std::to_string(capacity) +
+                     " is not a power of two in 2..2^" +
+                     std::to_string(RING_BUFFER_LOG2_MAX));
+  }
   // use the default size first, then use ioctl to get the real size.
   this->rb_runtime_shm_size_ = SHARED_RING_BUFFER_TOTAL_SIZE;
   if (this->rb_runtime_shm_size_ < SHARED_RING_BUFFER_CTRL_SIZE) {
@@ -48,6 +68,20 @@ HiResConn::HiResConn(const std::string &device_path) {
     throw_system_error("Failed to open device '" + device_path + "'");
   }
 
+  // Size the ring before anything is mapped; the metadata below then
+  // describes the ring as requested.
+  if (capacity != 0) {
+    prof_size_t requested = capacity;
+    if (ioctl(fd_, HIRES_IOCTL_SET_RB_CAPACITY, &requested) == -1) {
+      int saved_errno = errno;
+      close(fd_);
+      fd_ = -1;
+      errno = saved_errno;
+      throw_system_error("Failed to set the ring capacity of device '" +
+                         device_path + "' to " + std::to_string(capacity));
+    }
+  }
+
   // ioctl for reading the runtime rb size and mask.
   auto rb_meta = this->get_rb_meta();
   if (!rb_meta.has_value()) {
@@ -59,6 +93,24 @@ HiResConn::HiResConn(const std::string &device_path) {
             << ", shm size: " << rb_meta->shm_size_bytes_unaligned << std::endl;
   this->set_runtime_rb_meta(*rb_meta);
   this->set_runtime_cycle_per_us(this->get_kmod_cycles_per_us());
+  this->tsc_info_ = this->get_kmod_tsc_info();
+  this->tsc_hz_ = this->tsc_info_.has_value() ? this->tsc_info_->tsc_hz
+                                              : this->get_kmod_tsc_hz();
+  if (this->tsc_hz_ == 0) {
+    // Older modules only report the rounded cycles/us rate.
+    this->tsc_hz_ = this->get_cycle_per_us() * 1000000ULL;
+  }
+
+  // The ring's size is 64-bit in the header; refuse one this process cannot
+  // map rather than mapping a truncated length.
+  if (static_cast<uint64_t>(static_cast<size_t>(get_rb_shm_size())) !=
+      get_rb_shm_size()) {
+    close(fd_);
+    fd_ = -1;
+    throw HiResError("Ring buffer of device '" + device_path + "' (" +
+                     std::to_string(get_rb_shm_size()) +
+                     " bytes) does not fit in this process's address space");
+  }
 
   // 3. Map the device memory
   void *mapped_ptr =
@@ -80,27 +132,35 @@ HiResConn::HiResConn(const std::string &device_path) {
 
   shm_buf_ = static_cast<shared_ring_buffer_t *>(mapped_ptr);
 
-  // 4. Optional but Recommended: Sanity check mapped buffer metadata
-  //    Read buffer_size and size_mask ONCE after mapping.
-  //    These are written by the kernel during init and don't change,
-  //    so no atomicity needed here.
-  // TODO: use ioctl to get the size, as the header size won't match the runtime
-  // size_t expected_entries = (1UL << RING_BUFFER_LOG2_SIZE);
-  // if (shm_buf_->buffer_size != expected_entries ||
-  //     shm_buf_->size_mask != (expected_entries - 1)) {
-  //   // Handle mismatch - either throw, log warning, or adapt dynamically
-  //   munmap(shm_buf_, shm_size_); // Clean up map
-  //   close(fd_);                  // Clean up fd
-  //   fd_ = -1;
-  //   shm_buf_ = nullptr;
-  //   throw HiResError(
-  //       "Mapped buffer metadata mismatch. Expected size=" +
-  //       std::to_string(expected_entries) +
-  //       ", Mapped size=" + std::to_string(shm_buf_->buffer_size));
-  //   // Note: Using shm_buf_->buffer_size read *before* unmap! Better to store
-  //   // first.
-  // }
-  // Store the validated size/mask for later use if needed
+  // 4. Check the seal khires wrote after initializing the header, so a
+  //    half-initialized ring or one from a module built with a different
+  //    common.h is refused here rather than consumed with the wrong layout.
+  this->generation_ = std::atomic_ref<uint32_t>(shm_buf_->generation)
+                          .load(std::memory_order_acquire);
+  // The checksum covers the entry size, so the layout is read before it.
+  this->entry_layout_ = shm_buf_->entry_layout;
+  if (auto broken = this->check_seal()) {
+    munmap(shm_buf_, get_rb_shm_size());
+    shm_buf_ = nullptr;
+    close(fd_);
+    fd_ = -1;
+    throw HiResError("Ring buffer of device '" + device_path +
+                     "' failed its integrity check: " + *broken);
+  }
+  // Any layout this build knows, as long as it is laid out the way this
+  // build would lay it out.
+  hires_entry_layout_t layout{};
+  if (!hires_entry_layout_init(&layout, entry_layout_.id) ||
+      memcmp(&layout, &entry_layout_, sizeof(layout)) != 0) {
+    munmap(shm_buf_, get_rb_shm_size());
+    shm_buf_ = nullptr;
+    close(fd_);
+    fd_ = -1;
+    throw HiResError(
+        "Ring buffer of device '" + device_path +
+        "' uses a different log_entry_t layout or byte order; rebuild "
+        "libhires_rt and khires from the same shared/common.h");
+  }
 }
 
 HiResConn::~HiResConn() {
@@ -143,7 +203,152 @@ uint64_t HiResConn::get_kmod_cycles_per_us() const noexcept {
   return cycles_per_us;
 }
 
+uint64_t HiResConn::get_kmod_tsc_hz() const noexcept {
+  long ioctl_ret = 0;
+  uint64_t tsc_hz = 0;
+  ioctl_ret = ioctl(this->get_fd(), HIRES_IOCTL_GET_TSC_HZ, &tsc_hz);
+  if (ioctl_ret < 0) {
+    std::cerr << "WARNING: HIRES_IOCTL_GET_TSC_HZ failed. Error " << errno
+              << ": " << strerror(errno) << std::endl;
+    return 0;
+  }
+  return tsc_hz;
+}
+
+std::optional<hires_tsc_info_t> HiResConn::get_kmod_tsc_info() const noexcept {
+  hires_tsc_info_t info = {};
+  long ioctl_ret = ioctl(this->get_fd(), HIRES_IOCTL_GET_TSC_INFO, &info);
+  if (ioctl_ret < 0) {
+    std::cerr << "WARNING: HIRES_IOCTL_GET_TSC_INFO failed. Error " << errno
+              << ": " << strerror(errno) << std::endl;
+    return std::nullopt;
+  }
+  return info;
+}
+
+bool HiResConn::set_timestamp_source(TimestampSource source) {
+  switch (source) {
+  case TimestampSource::Monotonic:
+  case TimestampSource::MonotonicRaw:
+  case TimestampSource::Rdtsc:
+  case TimestampSource::Rdtscp:
+  case TimestampSource::RdtscLfence:
+    break;
+  case TimestampSource::Kvmclock: {
+    // CLOCK_MONOTONIC_RAW is only backed by the paravirt clock while
+    // kvm-clock is the kernel's clocksource.
+    std::ifstream cs(
+        "/sys/devices/system/clocksource/clocksource0/current_clocksource");
+    std::string current;
+    if (!(cs >> current) || current != "kvm-clock") {
+      std::cerr << "ERROR: kvmclock timestamp source requested, but the "
+                   "current clocksource is '"
+                << current << "'" << std::endl;
+      return false;
+    }
+    break;
+  }
+  default:
+    return false;
+  }
+  ts_source_.store(source, std::memory_order_relaxed);
+  return true;
+}
+
+uint64_t HiResConn::read_timestamp(TimestampSource source, uint16_t &flags,
+                                   unsigned &cpu) noexcept {
+  uint64_t timestamp = 0;
+  switch (source) {
+  case TimestampSource::Monotonic:
+    timestamp = get_monotonic_ns();
+    break;
+  case TimestampSource::MonotonicRaw:
+  case TimestampSource::Kvmclock:
+    timestamp = get_monotonic_raw_ns();
+    break;
+  case TimestampSource::Rdtsc:
+    flags |= LOG_FLAG_TSC;
+    timestamp = Ops::__rdtsc();
+    break;
+  case TimestampSource::Rdtscp: {
+    // The CPU comes with the timestamp, so it is the one the TSC was read on
+    // and costs no getcpu().
+    uint32_t aux = 0;
+    flags |= LOG_FLAG_TSC;
+    timestamp = Ops::__rdtscp(&aux);
+    cpu = Ops::tsc_aux_cpu(aux);
+    return timestamp;
+  }
+  case TimestampSource::RdtscLfence:
+    flags |= LOG_FLAG_TSC;
+    timestamp = Ops::__rdtsc_lfence();
+    break;
+  }
+  cpu = current_cpu();
+  return timestamp;
+}
+
+unsigned HiResConn::current_cpu() noexcept {
+  // Get CPU ID using syscall (more portable than sched_getcpu glibc wrapper)
+  unsigned cpu = 0, node = 0; // Cache cpu/node info if needed for performance
+#ifdef SYS_getcpu
+  if (syscall(SYS_getcpu, &cpu, &node, NULL) == -1) {
+    cpu = 0xFFFF; // error
+  }
+#else
+  cpu = sched_getcpu();
+  if (cpu < 0) {
+    cpu = 0xFFFF;
+  }
+#endif
+  return cpu;
+}
+
+void HiResConn::fill_entry(void *entry, uint64_t pos, uint64_t timestamp,
+                           unsigned cpu, uint32_t event_id, uint64_t data1,
+                           uint64_t data2) noexcept {
+  switch (entry_layout_.id) {
+  case HIRES_ENTRY_LAYOUT_COMPACT: {
+    auto *e = static_cast<log_entry_compact_t *>(entry);
+    e->timestamp = timestamp;
+    e->event_id = event_id;
+    e->cpu_id = static_cast<uint16_t>(cpu);
+    e->data1 = data1;
+    e->data2 = data2;
+    break;
+  }
+  case HIRES_ENTRY_LAYOUT_EXTENDED: {
+    // gettid() once per thread; it never changes.
+    static thread_local uint32_t tid =
+        static_cast<uint32_t>(syscall(SYS_gettid));
+    auto *e = static_cast<log_entry_ext_t *>(entry);
+    e->timestamp = timestamp;
+    e->event_id = event_id;
+    e->cpu_id = static_cast<uint16_t>(cpu);
+    e->tid = tid;
+    e->data1 = data1;
+    e->data2 = data2;
+    e->seqno = pos;
+    break;
+  }
+  default: {
+    auto *e = static_cast<log_entry_t *>(entry);
+    e->timestamp = timestamp;
+    e->event_id = event_id;
+    e->cpu_id = static_cast<uint16_t>(cpu);
+    e->data1 = data1;
+    e->data2 = data2;
+    break;
+  }
+  }
+}
+
 bool HiResConn::log(uint32_t event_id, uint64_t data1, uint64_t data2) {
+  return log_with_source(get_timestamp_source(), event_id, data1, data2);
+}
+
+bool HiResConn::log_with_source(TimestampSource source, uint32_t event_id,
+                                uint64_t data1, uint64_t data2) {
   if (shm_buf_ == nullptr) {
     return false; // Not initialized
   }
@@ -157,9 +362,9 @@ bool HiResConn::log(uint32_t event_id, uint64_t data1, uint64_t data2) {
   // Atomically reserve a slot (acquire needed for fetch, release not strictly
   // needed but common)
   //    fetch_add returns the value BEFORE the addition.
-  size_t head = atomic_head.fetch_add(1, std::memory_order_acq_rel);
+  uint64_t head = atomic_head.fetch_add(1, std::memory_order_acq_rel);
 
-  size_t tail = atomic_tail.load(std::memory_order_acquire);
+  uint64_t tail = atomic_tail.load(std::memory_order_acquire);
   if ((head - tail) >= get_rb_capacity()) [[unlikely]] {
     atomic_dropped.fetch_add(1, std::memory_order_relaxed);
     // Note: Head was already incremented. No explicit rollback needed for this
@@ -167,29 +372,15 @@ bool HiResConn::log(uint32_t event_id, uint64_t data1, uint64_t data2) {
     return false;
   }
 
-  size_t current_idx = head & get_rb_idx_mask();
-  log_entry_t *entry = &shm_buf_->buffer[current_idx];
+  uint64_t current_idx = head & get_rb_idx_mask();
+  void *entry = entry_at(current_idx);
 
   // Fill data (flags are handled atomically below)
   //    Direct writes to plain members are fine before the release operation.
-  entry->timestamp = get_monotonic_ns();
-  entry->event_id = event_id;
-
-  // Get CPU ID using syscall (more portable than sched_getcpu glibc wrapper)
-  unsigned cpu = 0, node = 0; // Cache cpu/node info if needed for performance
-#ifdef SYS_getcpu
-  if (syscall(SYS_getcpu, &cpu, &node, NULL) == -1) {
-    cpu = 0xFFFF; // error
-  }
-#else
-  cpu = sched_getcpu();
-  if (cpu < 0) {
-    cpu = 0xFFFF;
-  }
-#endif
-  entry->cpu_id = static_cast<uint16_t>(cpu);
-  entry->data1 = data1;
-  entry->data2 = data2;
+  uint16_t initial_flags = 0; // Userspace origin, VALID bit added by the store
+  unsigned cpu = 0;
+  uint64_t timestamp = read_timestamp(source, initial_flags, cpu);
+  fill_entry(entry, head, timestamp, cpu, event_id, data1, data2);
 
   // Release Operations: Ensure prior writes are visible before VALID flag
   //    Option A: Use atomic_thread_fence (explicit fence)
@@ -198,15 +389,123 @@ bool HiResConn::log(uint32_t event_id, uint64_t data1, uint64_t data2) {
 
   // Atomically set the flags including the VALID bit (Release semantics)
   //    This makes the entry visible to the consumer.
-  std::atomic_ref<uint16_t> atomic_flags(entry->flags);
-  uint16_t initial_flags =
-      0; // Userspace origin, VALID bit will be added by store
+  std::atomic_ref<uint16_t> atomic_flags(*flags_of(entry));
   atomic_flags.store(initial_flags | LOG_FLAG_VALID, std::memory_order_release);
 
   return true; // Success
 }
 
+bool HiResConn::log_batch(const hires_batch_entry_t *entries, size_t count) {
+  if (shm_buf_ == nullptr) {
+    return false; // Not initialized
+  }
+  if (count == 0) {
+    return true;
+  }
+
+  std::atomic_ref<uint64_t> atomic_head(shm_buf_->head);
+  std::atomic_ref<uint64_t> atomic_tail(shm_buf_->tail);
+  std::atomic_ref<uint64_t> atomic_dropped(shm_buf_->dropped_count);
+
+  // Reserve [head, head + count) only if all of it is free, so unlike log()
+  // a full ring leaves head alone. Acquire on tail as in log(): the slots
+  // are not written before the consumer's copy out of them is done.
+  uint64_t head = atomic_head.load(std::memory_order_relaxed);
+  do {
+    uint64_t tail = atomic_tail.load(std::memory_order_acquire);
+    if (head - tail > get_rb_capacity() ||
+        count > get_rb_capacity() - (head - tail)) [[unlikely]] {
+      atomic_dropped.fetch_add(count, std::memory_order_relaxed);
+      return false;
+    }
+  } while (!atomic_head.compare_exchange_weak(head, head + count,
+                                              std::memory_order_acq_rel,
+                                              std::memory_order_relaxed));
+
+  uint16_t initial_flags = 0;
+  unsigned cpu = 0;
+  uint64_t timestamp =
+      read_timestamp(get_timestamp_source(), initial_flags, cpu);
+  for (size_t i = 0; i < count; i++) {
+    fill_entry(entry_at((head + i) & get_rb_idx_mask()), head + i, timestamp,
+               cpu, entries[i].event_id, entries[i].data1, entries[i].data2);
+  }
+
+  // One release fence orders every entry above before all of the relaxed
+  // VALID stores below; a consumer whose acquire load sees any of them sees
+  // that entry whole.
+  std::atomic_thread_fence(std::memory_order_release);
+  for (size_t i = 0; i < count; i++) {
+    std::atomic_ref<uint16_t> atomic_flags(
+        *flags_of(entry_at((head + i) & get_rb_idx_mask())));
+    atomic_flags.store(initial_flags | LOG_FLAG_VALID,
+                       std::memory_order_relaxed);
+  }
+  return true;
+}
+
+log_entry_ext_t HiResConn::read_entry(const void *entry,
+                                      uint64_t seqno) const noexcept {
+  log_entry_ext_t out{};
+  switch (entry_layout_.id) {
+  case HIRES_ENTRY_LAYOUT_COMPACT: {
+    const auto *e = static_cast<const log_entry_compact_t *>(entry);
+    out.timestamp = e->timestamp;
+    out.event_id = e->event_id;
+    out.cpu_id = e->cpu_id;
+    out.flags = e->flags;
+    out.data1 = e->data1;
+    out.data2 = e->data2;
+    out.seqno = seqno;
+    break;
+  }
+  case HIRES_ENTRY_LAYOUT_EXTENDED:
+    out = *static_cast<const log_entry_ext_t *>(entry);
+    break;
+  default: {
+    const auto *e = static_cast<const log_entry_t *>(entry);
+    out.timestamp = e->timestamp;
+    out.event_id = e->event_id;
+    out.cpu_id = e->cpu_id;
+    out.flags = e->flags;
+    out.data1 = e->data1;
+    out.data2 = e->data2;
+    out.seqno = seqno;
+    break;
+  }
+  }
+  return out;
+}
+
+// The fields log_entry_t has.
+static log_entry_t to_log_entry(const log_entry_ext_t &e) noexcept {
+  log_entry_t out{};
+  out.timestamp = e.timestamp;
+  out.event_id = e.event_id;
+  out.cpu_id = e.cpu_id;
+  out.flags = e.flags;
+  out.data1 = e.data1;
+  out.data2 = e.data2;
+  return out;
+}
+
 std::optional<log_entry_t> HiResConn::pop() {
+  auto entry = pop_ext();
+  if (!entry.has_value()) {
+    return std::nullopt;
+  }
+  return to_log_entry(*entry);
+}
+
+std::optional<log_entry_t> HiResConn::peek() const {
+  auto entry = peek_ext();
+  if (!entry.has_value()) {
+    return std::nullopt;
+  }
+  return to_log_entry(*entry);
+}
+
+std::optional<log_entry_ext_t> HiResConn::pop_ext() {
   if (shm_buf_ == nullptr) {
     return std::nullopt; // Not initialized
   }
@@ -215,19 +514,19 @@ std::optional<log_entry_t> HiResConn::pop() {
   std::atomic_ref<uint64_t> atomic_tail(shm_buf_->tail);
 
   // 1. Read current tail (Relaxed is okay, only consumer modifies tail)
-  size_t tail = atomic_tail.load(std::memory_order_relaxed);
+  uint64_t tail = atomic_tail.load(std::memory_order_relaxed);
 
   // 2. Check if buffer is empty (use Acquire on head load)
   //    Ensures we see producer writes that happened *before* head was updated.
-  size_t head = atomic_head.load(std::memory_order_acquire);
+  uint64_t head = atomic_head.load(std::memory_order_acquire);
   if (tail == head) {
     return std::nullopt; // Buffer is empty
   }
 
   // 3. Calculate index and get entry pointer
-  size_t current_idx = tail & get_rb_idx_mask();
-  log_entry_t *entry = &shm_buf_->buffer[current_idx];
-  std::atomic_ref<uint16_t> atomic_flags(entry->flags);
+  uint64_t current_idx = tail & get_rb_idx_mask();
+  void *entry = entry_at(current_idx);
+  std::atomic_ref<uint16_t> atomic_flags(*flags_of(entry));
 
   // 4. Wait for the VALID flag (use Acquire load)
   //    Ensures we see the data writes that happened *before* the flag was set.
@@ -247,7 +546,7 @@ std::optional<log_entry_t> HiResConn::pop() {
   // 5. Read data (Entry is valid and ready)
   //    Perform a simple copy. Volatile isn't strictly needed due to
   //    atomics/fences.
-  log_entry_t result_entry = *entry; // Direct struct copy
+  log_entry_ext_t result_entry = read_entry(entry, tail);
 
   // 6. Optional: Clear the VALID flag (Relaxed store is sufficient)
   //    This helps debugging and potentially some producer logic variants.
@@ -264,4 +563,84 @@ std::optional<log_entry_t> HiResConn::pop() {
   // 8. Return the copied data
   return result_entry;
 }
+
+std::optional<log_entry_ext_t> HiResConn::peek_ext() const {
+  if (shm_buf_ == nullptr) {
+    return std::nullopt; // Not initialized
+  }
+
+  std::atomic_ref<uint64_t> atomic_head(shm_buf_->head);
+  std::atomic_ref<uint64_t> atomic_tail(shm_buf_->tail);
+
+  uint64_t tail = atomic_tail.load(std::memory_order_relaxed);
+  uint64_t head = atomic_head.load(std::memory_order_acquire);
+  if (tail == head) {
+    return std::nullopt; // Buffer is empty
+  }
+
+  void *entry = entry_at(tail & get_rb_idx_mask());
+  std::atomic_ref<uint16_t> atomic_flags(*flags_of(entry));
+
+  // Same bounded wait as pop(), but the entry and tail are left untouched.
+  constexpr int max_spins = 100;
+  int spin_count = 0;
+  while ((atomic_flags.load(std::memory_order_acquire) & LOG_FLAG_VALID) == 0) {
+    if (++spin_count > max_spins) {
+      return std::nullopt;
+    }
+    std::this_thread::yield();
+  }
+  return read_entry(entry, tail);
+}
+
+hires_rb_stats_t HiResConn::stats() const noexcept {
+  hires_rb_stats_t stats{};
+  if (shm_buf_ == nullptr) {
+    return stats;
+  }
+  std::atomic_ref<uint64_t> atomic_head(shm_buf_->head);
+  std::atomic_ref<uint64_t> atomic_tail(shm_buf_->tail);
+  std::atomic_ref<uint64_t> atomic_dropped(shm_buf_->dropped_count);
+
+  stats.tail = atomic_tail.load(std::memory_order_acquire);
+  stats.head = atomic_head.load(std::memory_order_acquire);
+  stats.capacity = get_rb_capacity();
+  // Producers bump head before noticing the ring is full, so head - tail can
+  // exceed the capacity while drops are happening.
+  stats.pending = std::min<uint64_t>(stats.head - stats.tail, stats.capacity);
+  stats.dropped = atomic_dropped.load(std::memory_order_relaxed);
+  return stats;
+}
+std::optional<std::string> HiResConn::check_seal() const {
+  if (shm_buf_ == nullptr) {
+    return "not connected";
+  }
+  uint64_t magic = std::atomic_ref<uint64_t>(shm_buf_->magic)
+                       .load(std::memory_order_acquire);
+  if (magic == 0) {
+    return "khires has not initialized the ring or has been unloaded";
+  }
+  if (magic != HIRES_SHM_MAGIC) {
+    return "bad magic " + std::to_string(magic);
+  }
+  // Check the geometry this connection uses, not just what the header says
+  // now.
+  uint32_t expected =
+      hires_layout_checksum(entry_layout_.entry_size, get_rb_capacity(),
+                            get_rb_idx_mask(), get_rb_shm_size());
+  if (shm_buf_->layout_checksum != expected ||
+      shm_buf_->capacity != get_rb_capacity() ||
+      shm_buf_->idx_mask != get_rb_idx_mask()) {
+    return "layout checksum mismatch (header " +
+           std::to_string(shm_buf_->layout_checksum) + ", expected " +
+           std::to_string(expected) + ")";
+  }
+  uint32_t generation = std::atomic_ref<uint32_t>(shm_buf_->generation)
+                            .load(std::memory_order_relaxed);
+  if (generation != generation_) {
+    return "ring was reset (generation " + std::to_string(generation_) +
+           " -> " + std::to_string(generation) + ")";
+  }
+  return std::nullopt;
+}
 } // namespace HiResLogger
@@ -1,5 +1,6 @@
 #include <cstddef>
 #include <string>
+#include <system_error>
 
 #include "../include/rt_c.h"
 #include "../include/rt.hpp"
@@ -32,16 +33,27 @@ static void set_last_error(const std::string& msg) {
 
 extern "C" {
 
+uint32_t hires_get_api_version(void) {
+    return HIRES_API_VERSION;
+}
+
 HiResLoggerConnHandle* hires_connect(const char* device_path) {
+    return hires_connect_with_capacity(device_path, 0);
+}
+
+HiResLoggerConnHandle* hires_connect_with_capacity(const char* device_path, uint64_t capacity) {
     set_last_error(""); // Clear last error
     try {
         std::string path = (device_path != nullptr) ? device_path : "/dev/khires";
-        HiResLogger::HiResConn* conn = new HiResLogger::HiResConn(path);
+        HiResLogger::HiResConn* conn = new HiResLogger::HiResConn(path, capacity);
         // Cast to opaque handle type
         return reinterpret_cast<HiResLoggerConnHandle*>(conn);
     } catch (const HiResLogger::HiResError& e) {
         set_last_error(e.what());
         return nullptr;
+    } catch (const std::system_error& e) {
+        set_last_error(e.what());
+        return nullptr;
     } catch (const std::bad_alloc&) {
         set_last_error("Memory allocation failed during connect");
         return nullptr;
@@ -78,6 +90,52 @@ bool hires_log(HiResLoggerConnHandle* handle, uint32_t event_id, uint64_t data1,
     }
 }
 
+bool hires_log_with_source(HiResLoggerConnHandle* handle, uint32_t source, uint32_t event_id,
+                           uint64_t data1, uint64_t data2) {
+    set_last_error(""); // Clear last error
+    if (handle == nullptr) {
+        set_last_error("Invalid handle passed to hires_log_with_source");
+        return false;
+    }
+    if (source > HIRES_TS_RDTSC_LFENCE) {
+        set_last_error("Unknown timestamp source passed to hires_log_with_source");
+        return false;
+    }
+    HiResLogger::HiResConn* conn = reinterpret_cast<HiResLogger::HiResConn*>(handle);
+    try {
+        return conn->log_with_source(static_cast<HiResLogger::TimestampSource>(source), event_id,
+                                     data1, data2);
+    } catch (const std::exception& e) {
+        set_last_error(std::string("Exception during log: ") + e.what());
+        return false;
+    } catch (...) {
+         set_last_error("Unknown exception during log");
+        return false;
+    }
+}
+
+bool hires_log_batch(HiResLoggerConnHandle* handle, const hires_batch_entry_t* entries, size_t count) {
+    set_last_error(""); // Clear last error
+    if (handle == nullptr) {
+        set_last_error("Invalid handle passed to hires_log_batch");
+        return false;
+    }
+    if (entries == nullptr && count != 0) {
+        set_last_error("NULL entries pointer passed to hires_log_batch");
+        return false;
+    }
+    HiResLogger::HiResConn* conn = reinterpret_cast<HiResLogger::HiResConn*>(handle);
+    try {
+        return conn->log_batch(entries, count);
+    } catch (const std::exception& e) {
+        set_last_error(std::string("Exception during log: ") + e.what());
+        return false;
+    } catch (...) {
+        set_last_error("Unknown exception during log");
+        return false;
+    }
+}
+
 bool hires_pop(HiResLoggerConnHandle* handle, log_entry_t* entry) {
     set_last_error(""); // Clear last error
     if (handle == nullptr) {
@@ -109,6 +167,93 @@ bool hires_pop(HiResLoggerConnHandle* handle, log_entry_t* entry) {
     }
 }
 
+bool hires_peek(HiResLoggerConnHandle* handle, log_entry_t* entry) {
+    set_last_error(""); // Clear last error
+    if (handle == nullptr) {
+        set_last_error("Invalid handle passed to hires_peek");
+        return false;
+    }
+    if (entry == nullptr) {
+        set_last_error("NULL entry pointer passed to hires_peek");
+        return false;
+    }
+
+    HiResLogger::HiResConn* conn = reinterpret_cast<HiResLogger::HiResConn*>(handle);
+    try {
+        std::optional<log_entry_t> result = conn->peek();
+        if (result.has_value()) {
+            *entry = result.value();
+            return true;
+        }
+        return false;
+    } catch (const std::exception& e) {
+        set_last_error(std::string("Exception during peek: ") + e.what());
+        return false;
+    } catch (...) {
+        set_last_error("Unknown exception during peek");
+        return false;
+    }
+}
+
+bool hires_pop_ext(HiResLoggerConnHandle* handle, log_entry_ext_t* entry) {
+    set_last_error(""); // Clear last error
+    if (handle == nullptr) {
+        set_last_error("Invalid handle passed to hires_pop_ext");
+        return false;
+    }
+    if (entry == nullptr) {
+        set_last_error("NULL entry pointer passed to hires_pop_ext");
+        return false;
+    }
+
+    HiResLogger::HiResConn* conn = reinterpret_cast<HiResLogger::HiResConn*>(handle);
+    try {
+        std::optional<log_entry_ext_t> result = conn->pop_ext();
+        if (result.has_value()) {
+            *entry = result.value();
+            return true;
+        }
+        return false;
+    } catch (const std::exception& e) {
+        set_last_error(std::string("Exception during pop: ") + e.what());
+        return false;
+    } catch (...) {
+        set_last_error("Unknown exception during pop");
+        return false;
+    }
+}
+
+uint32_t hires_get_entry_layout(HiResLoggerConnHandle* handle) {
+    set_last_error(""); // Clear last error
+    if (handle == nullptr) {
+        set_last_error("Invalid handle passed to hires_get_entry_layout");
+        return HIRES_ENTRY_LAYOUT_STANDARD;
+    }
+    HiResLogger::HiResConn* conn = reinterpret_cast<HiResLogger::HiResConn*>(handle);
+    return conn->get_entry_layout();
+}
+
+void hires_flush(HiResLoggerConnHandle* handle) {
+    set_last_error(""); // Clear last error
+    if (handle == nullptr) {
+        set_last_error("Invalid handle passed to hires_flush");
+        return;
+    }
+    HiResLogger::HiResConn* conn = reinterpret_cast<HiResLogger::HiResConn*>(handle);
+    conn->flush();
+}
+
+bool hires_get_stats(HiResLoggerConnHandle* handle, hires_rb_stats_t* out) {
+    set_last_error(""); // Clear last error
+    if (handle == nullptr || out == nullptr) {
+        set_last_error("Invalid handle or NULL out pointer passed to hires_get_stats");
+        return false;
+    }
+    HiResLogger::HiResConn* conn = reinterpret_cast<HiResLogger::HiResConn*>(handle);
+    *out = conn->stats();
+    return true;
+}
+
 shared_ring_buffer_t* hires_get_buffer(HiResLoggerConnHandle* handle) {
     set_last_error(""); // Clear last error
     if (handle == nullptr) {
@@ -119,17 +264,17 @@ shared_ring_buffer_t* hires_get_buffer(HiResLoggerConnHandle* handle) {
     return conn->get_raw_buf();
 }
 
-size_t hires_get_shm_size(HiResLoggerConnHandle* handle) {
+uint64_t hires_get_shm_size(HiResLoggerConnHandle* handle) {
     set_last_error(""); // Clear last error
      if (handle == nullptr) {
         set_last_error("Invalid handle passed to profiler_get_buffer_size");
         return 0;
     }
     HiResLogger::HiResConn* conn = reinterpret_cast<HiResLogger::HiResConn*>(handle);
-    return conn->get_mapped_size();
+    return conn->get_rb_shm_size();
 }
 
-size_t hires_get_rb_capacity(HiResLoggerConnHandle* handle) {
+uint64_t hires_get_rb_capacity(HiResLoggerConnHandle* handle) {
     set_last_error(""); // Clear last error
     if (handle == nullptr) {
         set_last_error("Invalid handle passed to profiler_get_rb_size");
@@ -139,7 +284,7 @@ size_t hires_get_rb_capacity(HiResLoggerConnHandle* handle) {
     return conn->get_rb_capacity();
 }
 
-size_t hires_get_rb_idx_mask(HiResLoggerConnHandle* handle) {
+uint64_t hires_get_rb_idx_mask(HiResLoggerConnHandle* handle) {
     set_last_error(""); // Clear last error
     if (handle == nullptr) {
         set_last_error("Invalid handle passed to profiler_get_rb_mask");
@@ -159,6 +304,55 @@ uint64_t hires_get_cycles_per_us(HiResLoggerConnHandle* handle) {
     return conn->get_cycle_per_us();
 }
 
+uint64_t hires_get_tsc_hz(HiResLoggerConnHandle* handle) {
+    set_last_error(""); // Clear last error
+    if (handle == nullptr) {
+        set_last_error("Invalid handle passed to hires_get_tsc_hz");
+        return 0;
+    }
+    HiResLogger::HiResConn* conn = reinterpret_cast<HiResLogger::HiResConn*>(handle);
+    return conn->get_tsc_hz();
+}
+
+bool hires_get_tsc_info(HiResLoggerConnHandle* handle, hires_tsc_info_t* out) {
+    set_last_error(""); // Clear last error
+    if (handle == nullptr || out == nullptr) {
+        set_last_error("Invalid arguments passed to hires_get_tsc_info");
+        return false;
+    }
+    HiResLogger::HiResConn* conn = reinterpret_cast<HiResLogger::HiResConn*>(handle);
+    const auto& info = conn->get_tsc_info();
+    if (!info.has_value()) {
+        return false;
+    }
+    *out = *info;
+    return true;
+}
+
+bool hires_set_timestamp_source(HiResLoggerConnHandle* handle, uint32_t source) {
+    set_last_error(""); // Clear last error
+    if (handle == nullptr) {
+        set_last_error("Invalid handle passed to hires_set_timestamp_source");
+        return false;
+    }
+    HiResLogger::HiResConn* conn = reinterpret_cast<HiResLogger::HiResConn*>(handle);
+    if (!conn->set_timestamp_source(static_cast<HiResLogger::TimestampSource>(source))) {
+        set_last_error("Timestamp source " + std::to_string(source) + " is not available");
+        return false;
+    }
+    return true;
+}
+
+uint32_t hires_get_timestamp_source(HiResLoggerConnHandle* handle) {
+    set_last_error(""); // Clear last error
+    if (handle == nullptr) {
+        set_last_error("Invalid handle passed to hires_get_timestamp_source");
+        return HIRES_TS_MONOTONIC;
+    }
+    HiResLogger::HiResConn* conn = reinterpret_cast<HiResLogger::HiResConn*>(handle);
+    return static_cast<uint32_t>(conn->get_timestamp_source());
+}
+
 uint64_t hires_get_drop_num(HiResLoggerConnHandle* handle) {
     set_last_error(""); // Clear last error
     if (handle == nullptr) {
@@ -177,4 +371,16 @@ uint64_t hires_rdtscp(uint32_t* auxp) {
     return HiResLogger::Ops::__rdtscp(auxp);
 }
 
+size_t hires_sizeof_log_entry(void) {
+    return sizeof(log_entry_t);
+}
+
+size_t hires_sizeof_ring_buffer(void) {
+    return sizeof(shared_ring_buffer_t);
+}
+
+size_t hires_offsetof_ring_entries(void) {
+    return offsetof(shared_ring_buffer_t, buffer);
+}
+
 } // extern "C"
\ No newline at end of file
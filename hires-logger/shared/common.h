@@ -39,12 +39,47 @@ typedef struct {
     prof_size_t shm_size_bytes_unaligned;   // Size of the shared memory region in bytes (unaligned)
 } hires_rb_meta_t;
 
+// Where the module's TSC frequency came from
+#define HIRES_TSC_SRC_CALIBRATED 0 // Timing loop against ktime
+#define HIRES_TSC_SRC_SECURE_TSC 1 // SEV-SNP SecureTSC guest frequency
+
+typedef struct {
+    prof_size_t tsc_hz;        // Frequency used for conversions
+    prof_size_t calibrated_hz; // Timing-loop measurement (always taken)
+    prof_size_t reported_hz;   // Guest-visible frequency, 0 if not available
+    prof_size_t source;        // HIRES_TSC_SRC_*
+} hires_tsc_info_t;
+
 #define HIRES_IOCTL_MAGIC 'h'
 #define HIRES_IOCTL_RESET_RB                _IO(HIRES_IOCTL_MAGIC, 1)
 #define HIRES_IOCTL_GET_RB_META             _IOR(HIRES_IOCTL_MAGIC, 2, hires_rb_meta_t)
 #define HIRES_IOCTL_GET_TSC_CYCLE_PER_US    _IOR(HIRES_IOCTL_MAGIC, 3, prof_size_t)
+#define HIRES_IOCTL_GET_TSC_HZ              _IOR(HIRES_IOCTL_MAGIC, 4, prof_size_t)
+#define HIRES_IOCTL_GET_TSC_INFO            _IOR(HIRES_IOCTL_MAGIC, 5, hires_tsc_info_t)
+// Reallocates the ring with the given capacity in entries, a power of two
+// up to 1 << RING_BUFFER_LOG2_MAX, before the caller maps it. EBUSY if the
+// ring is mapped and has another capacity; its entries are discarded.
+#define HIRES_IOCTL_SET_RB_CAPACITY         _IOW(HIRES_IOCTL_MAGIC, 6, prof_size_t)
 // --- End IOCTL Definitions ---
 
+// Snapshot of the ring's indexes and counters (hires_get_stats)
+typedef struct {
+    uint64_t head;     // Slots reserved by producers so far
+    uint64_t tail;     // Slots consumed so far
+    uint64_t pending;  // Entries waiting to be popped, at most capacity
+    uint64_t capacity;
+    uint64_t dropped;  // Entries dropped because the ring was full
+} hires_rb_stats_t;
+
+// One event of a batch logged with hires_log_batch(): the caller's part of
+// an entry. The batch shares one timestamp and CPU ID.
+typedef struct {
+    uint32_t event_id;
+    uint32_t reserved; // 0
+    uint64_t data1;
+    uint64_t data2;
+} hires_batch_entry_t;
+
 typedef struct {
     uint64_t timestamp;
     uint32_t event_id;
@@ -54,14 +89,189 @@ typedef struct {
     uint64_t data2;
 } log_entry_t;
 
+// Entry layouts a ring can be created with (khires entry_layout=), recorded
+// in hires_entry_layout_t::id. All carry log_entry_t's fields; compact packs
+// them into 32 bytes (cpu_id truncated to 16 bits) to fit more entries in the
+// same memory, extended adds the producer's TID and the slot's sequence number.
+#define HIRES_ENTRY_LAYOUT_STANDARD 0 // log_entry_t, 40 bytes
+#define HIRES_ENTRY_LAYOUT_COMPACT  1 // log_entry_compact_t, 32 bytes
+#define HIRES_ENTRY_LAYOUT_EXTENDED 2 // log_entry_ext_t, 64 bytes
+
+typedef struct {
+    uint64_t timestamp;
+    uint32_t event_id;
+    uint16_t cpu_id;
+    uint16_t flags;
+    uint64_t data1;
+    uint64_t data2;
+} log_entry_compact_t;
+
+// log_entry_t with the same offsets, plus the fields below.
+typedef struct {
+    uint64_t timestamp;
+    uint32_t event_id;
+    uint32_t cpu_id;
+    uint16_t flags;
+    uint32_t tid;      // Producer's thread ID, 0 for kernel entries outside a task
+    uint64_t data1;
+    uint64_t data2;
+    uint64_t seqno;    // Head index the slot was reserved at
+    uint64_t reserved[2];
+} log_entry_ext_t;
+
+// log_entry_t as the writer of a ring laid it out, so a reader built for
+// another architecture or an older/newer common.h refuses the ring instead of
+// misreading it. Fields are indexed by HIRES_FIELD_*.
+#define HIRES_BYTE_ORDER_MARK 0x01020304 // Reads as 0x04030201 across endianness
+#define HIRES_FIELD_TIMESTAMP 0
+#define HIRES_FIELD_EVENT_ID  1
+#define HIRES_FIELD_CPU_ID    2
+#define HIRES_FIELD_FLAGS     3
+#define HIRES_FIELD_DATA1     4
+#define HIRES_FIELD_DATA2     5
+#define HIRES_FIELD_TID       6 // Extended layout only
+#define HIRES_FIELD_SEQNO     7 // Extended layout only
+#define HIRES_ENTRY_FIELDS    8
+
+typedef struct {
+    uint32_t byte_order;                       // HIRES_BYTE_ORDER_MARK, native order
+    uint16_t entry_size;                       // Size of one entry of this layout
+    uint16_t header_size;                      // Offset of the first entry in the ring
+    uint16_t id;                               // HIRES_ENTRY_LAYOUT_*
+    uint16_t reserved;
+    // Fields a layout doesn't have are 0 bytes at offset 0.
+    uint8_t field_offset[HIRES_ENTRY_FIELDS];
+    uint8_t field_size[HIRES_ENTRY_FIELDS];
+} hires_entry_layout_t;
+
 // Flag definitions
 #define LOG_FLAG_VALID (1 << 0)
 #define LOG_FLAG_KERNEL (1 << 1)
+#define LOG_FLAG_TSC (1 << 2) // Userspace entry stamped in TSC cycles, not ns
+
+// Userspace timestamp sources (see HiResConn::set_timestamp_source)
+#define HIRES_TS_MONOTONIC 0     // clock_gettime(CLOCK_MONOTONIC), ns (default)
+#define HIRES_TS_MONOTONIC_RAW 1 // clock_gettime(CLOCK_MONOTONIC_RAW), ns
+#define HIRES_TS_RDTSC 2         // raw rdtsc, cycles
+#define HIRES_TS_RDTSCP 3        // rdtscp (waits for prior instructions, reads the
+                                 // CPU ID with the TSC), cycles
+#define HIRES_TS_KVMCLOCK 4      // CLOCK_MONOTONIC_RAW via the vDSO, only while the
+                                 // kvm-clock clocksource is active, ns
+#define HIRES_TS_RDTSC_LFENCE 5  // lfence; rdtsc (waits for prior instructions
+                                 // without rdtscp's TSC_AUX read), cycles
+
+// Event IDs from HIRES_EV_RESERVED_FIRST up are reserved for the runtime
+// (through 239) and khires (240 and up); application events use lower IDs.
+#define HIRES_EV_RESERVED_FIRST   232
+
+// --- Reserved Event ID: TSC cross-check ---
+// Logged by userspace (rt::clockcheck) after measuring one interval with
+// both the TSC and CLOCK_MONOTONIC: data1 is its length in ns per the TSC at
+// the calibrated frequency, data2 its length in ns per CLOCK_MONOTONIC. A
+// steady disagreement means a mis-calibrated or virtualized TSC.
+#define HIRES_EV_CLOCK_CHECK      237
+
+// --- Reserved Event ID: event names ---
+// Logged by userspace (rt::event) to register the name of an application
+// event ID, so reports can show it next to the ID. Encoded like
+// HIRES_EV_THREAD_NAME below, with the event ID in place of the TID: data1 is
+// HIRES_THREAD_NAME_DATA1(event_id, part, parts), data2 holds 8 bytes of the
+// name, and names are cut at HIRES_THREAD_NAME_MAX bytes.
+#define HIRES_EV_EVENT_NAME       238
+
+// --- Reserved Event ID: thread names ---
+// Logged by userspace (rt::thread) to register a thread's name, so per-thread
+// reports can show it next to the TID. A name is split into 8-byte parts, one
+// entry each: data1 packs the TID, the part index and the part count (see
+// HIRES_THREAD_NAME_DATA1), data2 holds the part's bytes, first byte in the
+// lowest byte and zero-padded. Names are cut at HIRES_THREAD_NAME_MAX bytes.
+#define HIRES_EV_THREAD_NAME      239
+
+#define HIRES_THREAD_NAME_MAX     32
+#define HIRES_THREAD_NAME_DATA1(tid, part, parts) \
+    (((uint64_t)(parts) << 48) | ((uint64_t)(part) << 32) | (uint32_t)(tid))
+
+// --- Channels ---
+// Workloads sharing the ring keep their event IDs apart by logging on a
+// channel: bits 16-28 of an event ID select it, the lower bits hold an
+// application event ID (below HIRES_EV_RESERVED_FIRST). Bits 29-31 stay clear
+// for the flags rt sets on packet hook, hardware timestamp and stack frame
+// entries. Channel 0 is the default; khires and the reserved events always
+// log on it. A channel's ID is hires_channel_id() of its name, so producers
+// agree on it without coordinating and keep it across runs.
+#define HIRES_CHANNEL_SHIFT       16
+#define HIRES_CHANNEL_BITS        13
+#define HIRES_CHANNEL_MASK        ((1u << HIRES_CHANNEL_BITS) - 1)
+#define HIRES_CHANNEL_EVENT(channel, event_id) \
+    (((uint32_t)(channel) << HIRES_CHANNEL_SHIFT) | (uint32_t)(event_id))
+
+// --- Reserved Event ID: channel names ---
+// Logged by userspace (rt::channel) to register a channel's name, encoded like
+// HIRES_EV_THREAD_NAME with the channel ID in place of the TID.
+#define HIRES_EV_CHANNEL_NAME     236
+
+// --- Reserved Event IDs: virtio-net datapath ---
+// Logged by khires when loaded with virtio_probes=1. data1 is always the time
+// spent in the hooked function in TSC cycles. virtio-net uses even virtqueue
+// indexes for RX and odd ones for TX (queue pair i = 2i, 2i + 1).
+#define HIRES_EV_VNET_FIRST       240
+#define HIRES_EV_VNET_KICK        240 // virtqueue_notify (VM exit): data2 = vq index
+#define HIRES_EV_VNET_INTERRUPT   241 // vring_interrupt: data2 = vq index
+#define HIRES_EV_VNET_NAPI_POLL   242 // virtnet_poll: data2 = napi_id << 32 | work done
+#define HIRES_EV_VNET_SKB_DELIVER 243 // GRO receive within virtnet_poll: data2 = skb->len
+#define HIRES_EV_VNET_LAST        247 // Reserved through here
+
+// --- Reserved Event IDs: swiotlb bounce buffering ---
+// Logged by khires when loaded with swiotlb_probes=1. data1 is the time spent
+// in the call (including the bounce copy) in TSC cycles; data2 packs the DMA
+// direction and the mapping size in bytes, see HIRES_SWIOTLB_DATA2.
+#define HIRES_EV_SWIOTLB_FIRST    248
+#define HIRES_EV_SWIOTLB_MAP      248 // swiotlb_tbl_map_single
+#define HIRES_EV_SWIOTLB_UNMAP    249 // swiotlb_tbl_unmap_single
+#define HIRES_EV_SWIOTLB_LAST     251 // Reserved through here
+
+#define HIRES_SWIOTLB_FAILED      (1ULL << 63) // Map failed (pool exhausted)
+#define HIRES_SWIOTLB_DATA2(dir, size) (((uint64_t)(dir) << 32) | (uint32_t)(size))
+
+// --- Reserved Event IDs: interrupt-to-wakeup ---
+// HIRES_EV_IRQ is logged by khires when loaded with irq_probes=1 (data1 =
+// handler time in TSC cycles, data2 = IRQ number). HIRES_EV_WAKEUP is logged by
+// userspace when a blocking call returns (data1 = TSC at wakeup, data2 = the
+// IRQ or other key the wakeup should be paired with).
+#define HIRES_EV_IRQ              252 // handle_irq_event
+#define HIRES_EV_WAKEUP           253 // epoll_wait/recv return in userspace
+
+// --- Reserved Event ID: guest-visible VM exits ---
+// Logged by khires when loaded with exit_probes=1, once per exit the guest
+// kernel has to handle itself: a TDX #VE or an SEV-ES/SNP #VC. data1 is the
+// handler time in TSC cycles (excluding the exit itself); data2 packs the
+// exception kind and the architectural exit reason, see HIRES_VMEXIT_DATA2.
+#define HIRES_EV_VMEXIT           254 // tdx_handle_virt_exception / vc_handle_exitcode
+
+#define HIRES_VMEXIT_TDX_VE       1 // TDX #VE: reason is the VMX exit reason
+#define HIRES_VMEXIT_SNP_VC       2 // SEV-ES/SNP #VC: reason is the SVM exit code
+#define HIRES_VMEXIT_DATA2(kind, reason) (((uint64_t)(kind) << 32) | (uint32_t)(reason))
+
+// --- Reserved Event ID: TLS record crypto ---
+// One TLS record encrypted or decrypted. Logged by khires for kTLS when loaded
+// with tls_probes=1 (flagged LOG_FLAG_KERNEL), and by userspace TLS stacks
+// through rt::tls. data1 is the time spent in TSC cycles; data2 packs the
+// direction and the record payload length, see HIRES_TLS_DATA2.
+#define HIRES_EV_TLS              255 // tls_do_encryption / tls_do_decryption
+
+#define HIRES_TLS_ENCRYPT         0
+#define HIRES_TLS_DECRYPT         1
+#define HIRES_TLS_FAILED          (1ULL << 63) // Crypto error (e.g. bad record MAC)
+#define HIRES_TLS_DATA2(dir, len) (((uint64_t)(dir) << 32) | (uint32_t)(len))
 
 // Ring buffer constants
 #define RING_BUFFER_LOG2_SIZE 16
 #define RING_BUFFER_SIZE (1UL << RING_BUFFER_LOG2_SIZE)
 #define RING_BUFFER_MASK (RING_BUFFER_SIZE - 1)
+// Largest ring khires allocates (rb_size_log2, HIRES_IOCTL_SET_RB_CAPACITY):
+// 2^32 entries is a 160 GB ring; beyond that the shift and the page count
+// are the only limits left, and no guest has the memory anyway.
+#define RING_BUFFER_LOG2_MAX 32
 
 // Shared structure using PLAIN types for atomic fields
 typedef struct {
@@ -79,7 +289,13 @@ typedef struct {
     uint64_t capacity;
     uint64_t idx_mask;
     uint64_t dropped_count;
-    char pad2[PROF_CACHE_LINE_SIZE > (sizeof(uint64_t) * 3) ? PROF_CACHE_LINE_SIZE - (sizeof(uint64_t) * 3) : 1]; // Adjusted padding size calculation
+
+    // Seal, written by khires last at init (see hires_layout_checksum()).
+    uint64_t magic;            // HIRES_SHM_MAGIC once initialized, 0 after unload
+    uint32_t layout_checksum;  // hires_layout_checksum() of the fields above
+    uint32_t generation;       // Changes on every module load and ring reset
+    hires_entry_layout_t entry_layout; // Filled by hires_entry_layout_init()
+    char pad2[PROF_CACHE_LINE_SIZE * 2 - sizeof(uint64_t) * 7 - sizeof(hires_entry_layout_t)]; // Entries stay at offset 256
 
     // The Actual Buffer
     PROF_CACHE_LINE_ALIGNED log_entry_t buffer[RING_BUFFER_SIZE];
@@ -91,4 +307,102 @@ typedef struct {
 #define SHARED_RING_BUFFER_CTRL_SIZE (offsetof(shared_ring_buffer_t, buffer))
 #define SHARED_RING_BUFFER_TOTAL_SIZE (SHARED_RING_BUFFER_CTRL_SIZE + (RING_BUFFER_SIZE * sizeof(log_entry_t)))
 
+// "HIRESRB1" in memory on little-endian machines.
+#define HIRES_SHM_MAGIC 0x3142525345524948ULL
+
+#define HIRES_ENTRY_FIELD(layout, type, idx, field)                        \
+    do {                                                                   \
+        (layout)->field_offset[idx] = offsetof(type, field);               \
+        (layout)->field_size[idx] = sizeof(((type *)0)->field);            \
+    } while (0)
+
+#define HIRES_ENTRY_COMMON_FIELDS(layout, type)                            \
+    do {                                                                   \
+        (layout)->entry_size = sizeof(type);                               \
+        HIRES_ENTRY_FIELD(layout, type, HIRES_FIELD_TIMESTAMP, timestamp); \
+        HIRES_ENTRY_FIELD(layout, type, HIRES_FIELD_EVENT_ID, event_id);   \
+        HIRES_ENTRY_FIELD(layout, type, HIRES_FIELD_CPU_ID, cpu_id);       \
+        HIRES_ENTRY_FIELD(layout, type, HIRES_FIELD_FLAGS, flags);         \
+        HIRES_ENTRY_FIELD(layout, type, HIRES_FIELD_DATA1, data1);         \
+        HIRES_ENTRY_FIELD(layout, type, HIRES_FIELD_DATA2, data2);         \
+    } while (0)
+
+// Describes entry layout `id` and this build's ring header. Returns 0 for an
+// unknown id.
+static inline int hires_entry_layout_init(hires_entry_layout_t *layout, unsigned int id)
+{
+    unsigned int i;
+
+    for (i = 0; i < HIRES_ENTRY_FIELDS; ++i) {
+        layout->field_offset[i] = 0;
+        layout->field_size[i] = 0;
+    }
+    layout->reserved = 0;
+    layout->byte_order = HIRES_BYTE_ORDER_MARK;
+    layout->header_size = SHARED_RING_BUFFER_CTRL_SIZE;
+    layout->id = id;
+    switch (id) {
+    case HIRES_ENTRY_LAYOUT_STANDARD:
+        HIRES_ENTRY_COMMON_FIELDS(layout, log_entry_t);
+        return 1;
+    case HIRES_ENTRY_LAYOUT_COMPACT:
+        HIRES_ENTRY_COMMON_FIELDS(layout, log_entry_compact_t);
+        return 1;
+    case HIRES_ENTRY_LAYOUT_EXTENDED:
+        HIRES_ENTRY_COMMON_FIELDS(layout, log_entry_ext_t);
+        HIRES_ENTRY_FIELD(layout, log_entry_ext_t, HIRES_FIELD_TID, tid);
+        HIRES_ENTRY_FIELD(layout, log_entry_ext_t, HIRES_FIELD_SEQNO, seqno);
+        return 1;
+    default:
+        return 0;
+    }
+}
+
+// Entry `idx` of a ring whose entries are `entry_size` bytes; buffer[] only
+// indexes standard-layout rings.
+static inline void *hires_ring_entry(shared_ring_buffer_t *rb, uint64_t idx,
+                                     uint16_t entry_size)
+{
+    return (char *)rb->buffer + idx * entry_size;
+}
+
+// FNV-1a over the entry size, header size and the ring geometry khires
+// reports, so a mapping made by a module built from a different common.h, or
+// read before khires finished writing the header, fails the check instead of
+// being consumed with the wrong layout.
+static inline uint32_t hires_layout_checksum(uint16_t entry_size, uint64_t capacity,
+                                             uint64_t idx_mask,
+                                             uint64_t shm_size_bytes_unaligned)
+{
+    const uint64_t words[5] = {
+        entry_size, SHARED_RING_BUFFER_CTRL_SIZE,
+        capacity, idx_mask, shm_size_bytes_unaligned,
+    };
+    uint32_t hash = 2166136261u;
+    unsigned int i, byte;
+
+    for (i = 0; i < 5; ++i) {
+        for (byte = 0; byte < 8; ++byte) {
+            hash ^= (uint8_t)(words[i] >> (byte * 8));
+            hash *= 16777619u;
+        }
+    }
+    return hash;
+}
+
+// FNV-1a over a channel's name, folded to HIRES_CHANNEL_BITS. Never 0, the
+// default channel; distinct names can collide, which the profiler reports.
+static inline uint16_t hires_channel_id(const char *name)
+{
+    uint32_t hash = 2166136261u;
+    uint16_t id;
+
+    for (; *name; ++name) {
+        hash ^= (uint8_t)*name;
+        hash *= 16777619u;
+    }
+    id = (uint16_t)(((hash >> HIRES_CHANNEL_BITS) ^ hash) & HIRES_CHANNEL_MASK);
+    return id ? id : 1;
+}
+
 #endif // SHARED_COMMON_H
\ No newline at end of file
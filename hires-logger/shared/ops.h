@@ -8,6 +8,9 @@ typedef u64 uint64_t;
 #else
 #include <stdint.h>
 #include <time.h>
+#if defined(__aarch64__)
+#include <sched.h>
+#endif
 #endif
 
 #ifdef __cplusplus
@@ -15,6 +18,53 @@ namespace HiResLogger {
 namespace Ops {
 #endif
 
+#if defined(__aarch64__)
+/*
+ * Arm has no TSC. The generic timer's virtual count (CNTVCT_EL0) is the
+ * equivalent: a constant-rate counter readable from EL0, and the rest of the
+ * code treats its ticks as "cycles". ISB stands in for the serializing cpuid
+ * and for rdtscp's wait on prior instructions.
+ */
+static inline __attribute__((always_inline)) void cpu_serialize(void)
+{
+	asm volatile("isb" : : : "memory");
+}
+
+static inline __attribute__((always_inline)) uint64_t __rdtsc(void)
+{
+	uint64_t v;
+	asm volatile("mrs %0, cntvct_el0" : "=r" (v));
+	return v;
+}
+
+static inline __attribute__((always_inline)) uint64_t __rdtscp(uint32_t *auxp)
+{
+	uint64_t v;
+	asm volatile("isb\n\t"
+		"mrs %0, cntvct_el0" : "=r" (v) : : "memory");
+	if (auxp)
+#ifdef __KERNEL__
+		*auxp = raw_smp_processor_id();
+#else
+		*auxp = (uint32_t)sched_getcpu();
+#endif
+	return v;
+}
+
+static inline __attribute__((always_inline)) uint64_t __rdtsc_lfence(void)
+{
+	uint64_t v;
+	asm volatile("isb\n\t"
+		"mrs %0, cntvct_el0" : "=r" (v) : : "memory");
+	return v;
+}
+
+/* __rdtscp() already stores the CPU number itself. */
+static inline __attribute__((always_inline)) uint32_t tsc_aux_cpu(uint32_t aux)
+{
+	return aux;
+}
+#else
 static inline __attribute__((always_inline)) void cpu_serialize(void)
 {
     asm volatile("xorl %%eax, %%eax\n\t"
@@ -37,6 +87,27 @@ static inline __attribute__((always_inline)) uint64_t __rdtscp(uint32_t *auxp)
 	return ((uint64_t)a) | (((uint64_t)d) << 32);
 }
 
+/*
+ * lfence keeps rdtsc from executing before prior instructions complete, like
+ * rdtscp but without the TSC_AUX read, so it also works on VMs that do not
+ * expose rdtscp.
+ */
+static inline __attribute__((always_inline)) uint64_t __rdtsc_lfence(void)
+{
+	uint32_t a, d;
+	asm volatile("lfence\n\t"
+		"rdtsc" : "=a" (a), "=d" (d) : : "memory");
+	return ((uint64_t)a) | (((uint64_t)d) << 32);
+}
+
+/* Linux sets TSC_AUX to (node << 12) | cpu. */
+static inline __attribute__((always_inline)) uint32_t tsc_aux_cpu(uint32_t aux)
+{
+	return aux & 0xfff;
+}
+
+#endif // __aarch64__
+
 #ifndef __KERNEL__
 /* derived from DPDK (only for userspace program to use) */
 static uint64_t __time_calibrate_tsc(void)